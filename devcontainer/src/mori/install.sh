@@ -0,0 +1,43 @@
+#!/usr/bin/env bash
+# Install mori from the GitHub release matching the container architecture
+set -euo pipefail
+
+VERSION="${VERSION:-latest}"
+REPO="skanehira/mori"
+
+case "$(uname -m)" in
+  x86_64 | amd64) ARCH="x86_64" ;;
+  aarch64 | arm64) ARCH="aarch64" ;;
+  *)
+    echo "mori: unsupported architecture $(uname -m)" >&2
+    exit 1
+    ;;
+esac
+
+if ! command -v curl >/dev/null 2>&1; then
+  apt-get update
+  apt-get install -y --no-install-recommends curl ca-certificates
+fi
+
+if [ "$VERSION" = "latest" ]; then
+  RELEASE_URL="https://api.github.com/repos/${REPO}/releases/latest"
+else
+  RELEASE_URL="https://api.github.com/repos/${REPO}/releases/tags/v${VERSION#v}"
+fi
+
+# Linux assets are built per kernel version: mori-linux-<arch>-kernel-<version>.tar.gz
+ASSET_URL="$(curl -fsSL "$RELEASE_URL" |
+  grep -o "\"browser_download_url\": *\"[^\"]*mori-linux-${ARCH}-kernel-[^\"]*\.tar\.gz\"" |
+  head -n 1 |
+  sed 's/.*"\(https[^"]*\)"$/\1/')"
+if [ -z "$ASSET_URL" ]; then
+  echo "mori: no Linux ${ARCH} asset found in ${RELEASE_URL}" >&2
+  exit 1
+fi
+
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+curl -fsSL "$ASSET_URL" | tar xz -C "$TMP_DIR"
+install -m 0755 "$TMP_DIR"/mori-linux-"${ARCH}"-kernel-* /usr/local/bin/mori
+
+echo "mori installed from ${ASSET_URL}"
@@ -5,9 +5,28 @@ fn main() {
     let vmlinux_rs = out_dir.join("vmlinux.rs");
 
     // Generate vmlinux.rs using aya-tool
-    // Specify the types we need: file and path
+    // Specify the types we need: file and path, inode, super_block and
+    // dentry for the inode-based fallback, iattr for metadata changes,
+    // linux_binprm for program execution, msghdr for unix socket datagrams,
+    // and proc_inode through kernfs_node to find the cgroup of a /proc/<pid> entry
     let status = Command::new("aya-tool")
-        .args(["generate", "file", "path"])
+        .args([
+            "generate",
+            "file",
+            "path",
+            "inode",
+            "super_block",
+            "dentry",
+            "iattr",
+            "linux_binprm",
+            "msghdr",
+            "proc_inode",
+            "pid",
+            "task_struct",
+            "css_set",
+            "cgroup",
+            "kernfs_node",
+        ])
         .output()
         .expect(
             "Failed to execute aya-tool. Make sure aya-tool is installed (cargo install aya-tool)",
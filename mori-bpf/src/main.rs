@@ -10,26 +10,158 @@ mod vmlinux {
 }
 
 use aya_ebpf::{
-    helpers::{bpf_d_path, bpf_get_current_cgroup_id},
-    macros::{cgroup_sock_addr, lsm, map},
+    helpers::{
+        bpf_d_path, bpf_get_current_cgroup_id, bpf_get_current_comm, bpf_get_current_pid_tgid,
+        bpf_ktime_get_boot_ns, bpf_probe_read_kernel, bpf_probe_read_kernel_buf,
+        bpf_probe_read_kernel_str_bytes, bpf_probe_read_user, bpf_probe_write_user,
+        generated::bpf_set_retval,
+    },
+    macros::{cgroup_skb, cgroup_sock_addr, lsm, map, tracepoint},
     maps::{
-        HashMap, PerCpuArray,
+        Array, HashMap, PerCpuArray, RingBuf,
         lpm_trie::{Key, LpmTrie},
     },
-    programs::{LsmContext, SockAddrContext},
+    programs::{LsmContext, SkBuffContext, SockAddrContext, TracePointContext},
 };
 use aya_log_ebpf::info;
-use vmlinux::{file, path};
+use vmlinux::{
+    cgroup, css_set, dentry, file, hlist_node, iattr, inode, kernfs_node, linux_binprm, msghdr,
+    path, pid, proc_inode, super_block, task_struct,
+};
 
 const ALLOW: i32 = 1;
 const DENY: i32 = 0;
 
-const PATH_MAX: usize = 512;
+// Longest path bpf_d_path resolves, null terminator included (the kernel's PATH_MAX)
+const PATH_MAX: usize = 4096;
+// FNV-1a parameters of the DENY_PATHS keys (matching path_hash in userspace)
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+// Longest denied directory (with its trailing slash) matched by DENY_SUBTREES;
+// LPM trie keys hold at most 256 bytes of data
+const SUBTREE_PATH_MAX: usize = 256;
+// Parent directories mori_inode_open looks up in DENY_INODES
+const INODE_PARENT_DEPTH: usize = 32;
+
+// Plugin verdicts (matching userspace constants in ebpf.rs)
+const VERDICT_PENDING: u8 = 0;
+const VERDICT_ALLOW: u8 = 1;
+
+// Why a connection got its verdict (matching userspace constants in flow.rs)
+const REASON_ALLOW_LIST: u8 = 0;
+const REASON_PLUGIN: u8 = 1;
+const REASON_PLUGIN_PENDING: u8 = 2;
+const REASON_NO_RULE: u8 = 3;
+const REASON_DENY_LIST: u8 = 4;
+const REASON_DNS_ENFORCED: u8 = 5;
+
+// Size of a flow record (layout documented at FLOW_EVENTS)
+const FLOW_RECORD_LEN: usize = 68;
+
+// Size of a file denial record and of its fields before the path (layout
+// documented at DENIAL_EVENTS)
+const FILE_RECORD_HEADER_LEN: usize = 24;
+const FILE_RECORD_LEN: usize = FILE_RECORD_HEADER_LEN + PATH_MAX;
+// Size of the detail of the DENIAL_EVENTS records that carry no file; the
+// longest is a server name
+const DENIAL_DETAIL_LEN: usize = SNI_NAME_MAX;
+
+// What a DENIAL_EVENTS record reports (matching userspace constants in
+// file_events.rs)
+const DENIAL_PATH: u8 = 0;
+const DENIAL_NAME: u8 = 1;
+const DENIAL_BIND: u8 = 2;
+const DENIAL_SOCKET: u8 = 3;
+const DENIAL_SNI: u8 = 4;
+const DENIAL_INBOUND: u8 = 5;
+const DENIAL_UNIX: u8 = 6;
+
+// IPv4, IPv6 and TCP header fields read by mori_inbound
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const TCP_FLAG_SYN: u8 = 0x02;
+const TCP_FLAG_ACK: u8 = 0x10;
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_AUTH: u8 = 51;
+const IPV6_DEST_OPTS: u8 = 60;
+const IPV6_LOOPBACK: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+// Address families and protocols of ICMP (ping and raw) sockets
+const AF_INET: i32 = 2;
+const AF_INET6: i32 = 10;
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_ICMPV6: u8 = 58;
+
+// Abstract unix socket addresses: sun_family, a NUL byte, then the name
+// (matching UNIX_NAME_MAX in userspace)
+const AF_UNIX: u16 = 1;
+const UNIX_NAME_OFFSET: usize = 3;
+const UNIX_NAME_MAX: usize = 107;
+const UNIX_ALLOW: u8 = 1;
+const UNIX_DENY: u8 = 2;
+
+// DNS answers parsed by mori_dns_snoop (matching userspace constants in ebpf.rs)
+const DNS_PORT: u16 = 53;
+const DNS_NAME_MAX: usize = 128;
+const DNS_MAX_ANSWERS: usize = 16;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_CLASS_IN: u16 = 1;
+// DNS_ENDPOINTS_V4/V6 values and the ports of DNS-over-HTTPS and
+// DNS-over-TLS (matching userspace constants in ebpf.rs)
+const DNS_NAMESERVER: u8 = 1;
+const DNS_BYPASS: u8 = 2;
+const HTTPS_PORT: u16 = 443;
+const DOT_PORT: u16 = 853;
+// TLS ClientHellos parsed by mori_sni (SNI_NAME_MAX matches userspace in ebpf.rs)
+const IPV6_HEADER_LEN: usize = 40;
+const TLS_HANDSHAKE: u8 = 0x16;
+const TLS_CLIENT_HELLO: u8 = 1;
+const TLS_EXT_SERVER_NAME: u16 = 0;
+const TLS_MAX_EXTENSIONS: usize = 32;
+const SNI_NAME_MAX: usize = 128;
+// Seconds a snooped address stays allowed after its TTL (KERNEL_EXPIRY_GRACE in userspace)
+const DNS_EXPIRY_GRACE_SECS: u64 = 30;
+
+// BPF_NOEXIST: only insert the entry if the key does not exist yet
+const BPF_NOEXIST: u64 = 1;
 
 // Access mode flags (matching userspace AccessMode enum)
 const ACCESS_MODE_READ: u8 = 1;
 const ACCESS_MODE_WRITE: u8 = 2;
 const ACCESS_MODE_READWRITE: u8 = 3;
+const ACCESS_MODE_EXECUTE: u8 = 4;
+
+// Device kinds in DENY_DEVICES keys and DENY_DEVICE_TYPES (bit flags)
+const DEVICE_BLOCK: u8 = 1;
+const DEVICE_CHAR: u8 = 2;
+
+// inode.i_mode file type bits (include/uapi/linux/stat.h)
+const S_IFMT: u16 = 0o170000;
+const S_IFBLK: u16 = 0o060000;
+const S_IFCHR: u16 = 0o020000;
+
+// procfs superblock magic (include/uapi/linux/magic.h)
+const PROC_SUPER_MAGIC: u64 = 0x9fa0;
+
+// errno of opening or stat'ing a file hidden by FILE_HIDE_DENIED
+const ENOENT: i32 = 2;
+
+// Offset of d_reclen in struct linux_dirent64 (d_ino comes first), and the
+// most entries of one getdents64 buffer checked for hidden files
+const DIRENT_RECLEN_OFFSET: u64 = 16;
+const HIDE_MAX_DIRENTS: usize = 256;
+
+// mmap(2) protection flag for executable mappings (include/uapi/asm-generic/mman-common.h)
+const PROT_EXEC: u64 = 0x4;
+
+// iattr.ia_valid bits of the metadata changes denied on write-protected files
+const ATTR_MODE: u32 = 1 << 0;
+const ATTR_UID: u32 = 1 << 1;
+const ATTR_GID: u32 = 1 << 2;
+const ATTR_SIZE: u32 = 1 << 3;
 
 // File open flags from Linux kernel (include/uapi/asm-generic/fcntl.h)
 const O_ACCMODE: u32 = 0x0003; // Mask to extract access mode from flags
@@ -39,9 +171,96 @@ const O_RDWR: u32 = 0x0002; // Open for reading and writing
 
 // Allow list for IPv4 addresses using LPM Trie for efficient CIDR matching
 // Key: Key<[u8; 4]> where prefix_len is the number of significant bits and data is the IPv4 address
-// Value: u8 (1 = allowed)
+// Value: u64 deadline in CLOCK_BOOTTIME nanoseconds (0 = never expires); entries
+// learned from DNS get one so they stop matching if userspace stops refreshing them
+// Capacity must match ALLOW_V4_CAPACITY in userspace, which evicts DNS-learned entries near it
+#[map]
+static ALLOW_V4_LPM: LpmTrie<[u8; 4], u64> = LpmTrie::with_max_entries(1024, 0);
+
+// Allow list for IPv6 addresses, checked by mori_connect6
+// Same layout as ALLOW_V4_LPM with 16-byte addresses; IPv4-mapped addresses
+// (::ffff:a.b.c.d) are looked up in ALLOW_V4_LPM instead
+#[map]
+static ALLOW_V6_LPM: LpmTrie<[u8; 16], u64> = LpmTrie::with_max_entries(1024, 0);
+
+// Allow lists for addresses only allowed on some destination ports
+// Key data is the IP protocol number (IPPROTO_TCP or IPPROTO_UDP), the address
+// and the port, the latter two in network byte order; the prefix length always
+// covers the protocol and address bits, so they match exactly while the port
+// matches by prefix (a port range is one entry per aligned block of ports).
+// Values are deadlines as in ALLOW_V4_LPM
+#[map]
+static ALLOW_V4_PORT_LPM: LpmTrie<[u8; 7], u64> = LpmTrie::with_max_entries(8192, 0);
+
+#[map]
+static ALLOW_V6_PORT_LPM: LpmTrie<[u8; 19], u64> = LpmTrie::with_max_entries(8192, 0);
+
+// Destinations denied before the allow lists are consulted (--deny-network)
+// Same key layout as ALLOW_V4_LPM and ALLOW_V6_LPM; entries never expire, and
+// IPv4-mapped IPv6 addresses are looked up in DENY_V4_LPM
+#[map]
+static DENY_V4_LPM: LpmTrie<[u8; 4], u8> = LpmTrie::with_max_entries(1024, 0);
+
+#[map]
+static DENY_V6_LPM: LpmTrie<[u8; 16], u8> = LpmTrie::with_max_entries(1024, 0);
+
+// Nameservers (DNS_NAMESERVER) and known DNS-over-HTTPS/TLS resolvers
+// (DNS_BYPASS), keyed by address in network byte order; only consulted while
+// DNS_ENFORCED is set
+#[map]
+static DNS_ENDPOINTS_V4: HashMap<[u8; 4], u8> = HashMap::with_max_entries(256, 0);
+
+#[map]
+static DNS_ENDPOINTS_V6: HashMap<[u8; 16], u8> = HashMap::with_max_entries(256, 0);
+
+// DNS switch; index 0 is set to 1 by userspace to deny port 53 to anything
+// but the nameservers, and ports 443 and 853 to the DNS_BYPASS resolvers
+#[map]
+static DNS_ENFORCED: Array<u8> = Array::with_max_entries(1, 0);
+
+// Allowed domains whose DNS answers mori_dns_snoop adds to the allow lists
+// Key: the name in DNS wire format (length-prefixed lowercase labels and the
+// terminating zero), zero-padded to DNS_NAME_MAX bytes
+#[map]
+static SNOOP_DOMAINS: HashMap<[u8; DNS_NAME_MAX], u8> = HashMap::with_max_entries(1024, 0);
+
+// Allowed domain suffixes (`.corp.internal`), checked by mori_dns_snoop and
+// mori_sni for names missing from SNOOP_DOMAINS and SNI_DOMAINS
+// Key: the lowercase name with a leading dot, reversed ("lanretni.proc."), so
+// the suffixes of a name are prefixes of its reversed form; zero-padded to
+// DNS_NAME_MAX bytes with the prefix length covering the suffix
+#[map]
+static DOMAIN_SUFFIXES: LpmTrie<[u8; DNS_NAME_MAX], u8> = LpmTrie::with_max_entries(256, 0);
+
+// IPv4 addresses of the nameservers whose answers are trusted
+#[map]
+static SNOOP_SERVERS: HashMap<[u8; 4], u8> = HashMap::with_max_entries(64, 0);
+
+// Allowed domains a TLS ClientHello may name, checked by mori_sni
+// Key: the lowercase server name, zero-padded to SNI_NAME_MAX bytes
+#[map]
+static SNI_DOMAINS: HashMap<[u8; SNI_NAME_MAX], u8> = HashMap::with_max_entries(1024, 0);
+
+// Destinations allowed by address, whose TLS connections mori_sni does not check
+// Same key layout as ALLOW_V4_LPM and ALLOW_V6_LPM
+#[map]
+static SNI_EXEMPT_V4: LpmTrie<[u8; 4], u8> = LpmTrie::with_max_entries(1024, 0);
+
+#[map]
+static SNI_EXEMPT_V6: LpmTrie<[u8; 16], u8> = LpmTrie::with_max_entries(1024, 0);
+
+// Peers allowed to open TCP connections to the command's listening sockets
+// Same layout as ALLOW_V4_LPM and ALLOW_V6_LPM; only consulted when
+// mori_inbound is attached
+#[map]
+static INBOUND_V4_LPM: LpmTrie<[u8; 4], u8> = LpmTrie::with_max_entries(1024, 0);
+
+#[map]
+static INBOUND_V6_LPM: LpmTrie<[u8; 16], u8> = LpmTrie::with_max_entries(1024, 0);
+
+// Ports the command may listen on; only consulted when mori_bind4/6 are attached
 #[map]
-static ALLOW_V4_LPM: LpmTrie<[u8; 4], u8> = LpmTrie::with_max_entries(1024, 0);
+static LISTEN_PORTS: HashMap<u16, u8> = HashMap::with_max_entries(256, 0);
 
 // Target cgroup ID for file access control
 // Note: BPF_LSM_CGROUP attach type cannot be used for file_open hook because:
@@ -51,143 +270,1936 @@ static ALLOW_V4_LPM: LpmTrie<[u8; 4], u8> = LpmTrie::with_max_entries(1024, 0);
 #[map]
 static TARGET_CGROUP: HashMap<u64, u8> = HashMap::with_max_entries(1, 0);
 
-// Deny list for file paths; value is access mode (1=READ, 2=WRITE, 3=READ|WRITE)
+// Socket address families the cgroup may not create (AF_PACKET, AF_NETLINK, ...)
+#[map]
+static DENY_FAMILIES: HashMap<u32, u8> = HashMap::with_max_entries(64, 0);
+
+// ICMP switches; index 0 is set to 1 by userspace. DENY_ICMP refuses ICMP
+// sockets in mori_socket_create, ICMP_ALLOWED lets connected ping sockets
+// reach any destination the deny lists do not cover
+#[map]
+static DENY_ICMP: Array<u8> = Array::with_max_entries(1, 0);
+
+#[map]
+static ICMP_ALLOWED: Array<u8> = Array::with_max_entries(1, 0);
+
+// Abstract unix socket names (without the leading NUL, zero-padded) and
+// whether they are allowed (UNIX_ALLOW) or denied (UNIX_DENY)
+#[map]
+static UNIX_ABSTRACT: HashMap<[u8; UNIX_NAME_MAX], u8> = HashMap::with_max_entries(256, 0);
+
+// Abstract name switch; index 0 is set to 1 by userspace to deny names
+// missing from UNIX_ABSTRACT
+#[map]
+static UNIX_DENY_ABSTRACT: Array<u8> = Array::with_max_entries(1, 0);
+
+// Deny list for file paths; value is access mode (1=READ, 2=WRITE, 3=READ|WRITE, 4=EXECUTE)
+// Key: 64-bit FNV-1a hash of the path without its null terminator, so that
+// paths of any length fit in a fixed-size key
+#[map]
+static DENY_PATHS: HashMap<u64, u8> = HashMap::with_max_entries(1024, 0);
+
+// Denied directories covering every file below them
+// Key: the directory path with a trailing slash, zero-padded, with the prefix
+// length covering it; value is access mode as in DENY_PATHS
+#[map]
+static DENY_SUBTREES: LpmTrie<[u8; SUBTREE_PATH_MAX], u8> = LpmTrie::with_max_entries(1024, 0);
+
+// Allow list for file paths, consulted when FILE_ALLOW_ONLY is set
+// Key: an allowed path with a null terminator (the path itself) or a trailing
+// slash (everything below it), zero-padded, with the prefix length covering
+// it; value is the access mode allowed as in DENY_PATHS
+#[map]
+static ALLOW_SUBTREES: LpmTrie<[u8; SUBTREE_PATH_MAX], u8> = LpmTrie::with_max_entries(1024, 0);
+
+// File allow-list switch; index 0 is set to 1 by userspace to deny files
+// missing from ALLOW_SUBTREES (or ALLOW_INODES)
+#[map]
+static FILE_ALLOW_ONLY: Array<u8> = Array::with_max_entries(1, 0);
+
+// Hiding switch; index 0 is set to 1 by userspace to make files denied for
+// reading by DENY_PATHS or DENY_SUBTREES look missing
+#[map]
+static FILE_HIDE_DENIED: Array<u8> = Array::with_max_entries(1, 0);
+
+// getdents64(2) calls of the cgroup in flight, by thread ID: the user buffer
+// being filled and the device of the listed directory (0 until
+// mori_file_permission sees it); only used when FILE_HIDE_DENIED is set
+#[map]
+static HIDE_LISTINGS: HashMap<u32, [u64; 2]> = HashMap::with_max_entries(1024, 0);
+
+// /proc switch; index 0 is set to 1 by userspace to deny opening the
+// /proc/<pid> entries of processes outside TARGET_CGROUP
+#[map]
+static FILE_PRIVATE_PROC: Array<u8> = Array::with_max_entries(1, 0);
+
+// Denial event switch; index 0 is set to 1 by userspace when it consumes DENIAL_EVENTS
+#[map]
+static DENIAL_EVENTS_ENABLED: Array<u8> = Array::with_max_entries(1, 0);
+
+// Accesses the programs denied, or would have denied in audit mode. Record
+// layout: TGID (native endian u32), the denied access (ACCESS_MODE_* bits,
+// 0 for sockets), the kind (DENIAL_*), padding, the task's comm (16 bytes,
+// NUL-padded) and a detail depending on the kind:
+// - DENIAL_PATH: the path from bpf_d_path (NUL-terminated, empty if it could
+//   not be resolved), the record being FILE_RECORD_LEN bytes
+// - DENIAL_NAME: the last component of the path, NUL-terminated, from the
+//   hooks that cannot call bpf_d_path; also FILE_RECORD_LEN bytes
+// - DENIAL_BIND: the port (network byte order)
+// - DENIAL_SOCKET: the family and protocol (native endian i32)
+// - DENIAL_SNI: the server name, empty if none could be read
+// - DENIAL_INBOUND: the peer's IPv6 address (IPv4 in its IPv4-mapped form)
+//   and the local port (network byte order, 0 if not read)
+// - DENIAL_UNIX: the abstract name without its leading NUL, zero-padded
+// The other records are DENIAL_DETAIL_LEN bytes past the header. Packet
+// programs do not run in the task that sent or receives the packet, so
+// DENIAL_SNI and DENIAL_INBOUND records have TGID 0 and an empty comm.
+#[map]
+static DENIAL_EVENTS: RingBuf = RingBuf::with_byte_size(256 * 1024, 0);
+
+// Every open of the target cgroup, published by mori_file_observe for the
+// file inventory; records are laid out as DENIAL_PATH records, with the
+// requested access in place of the denied one
+#[map]
+static OPEN_EVENTS: RingBuf = RingBuf::with_byte_size(4 * 1024 * 1024, 0);
+
+// Rule counter switch; index 0 is set to 1 by userspace to count the opens
+// of the paths each deny rule covers in FILE_RULE_HITS
+#[map]
+static FILE_RULE_STATS: Array<u8> = Array::with_max_entries(1, 0);
+
+// Index of the deny rule of a path in FILE_RULE_HITS, keyed like DENY_PATHS
+#[map]
+static DENY_PATH_RULES: HashMap<u64, u32> = HashMap::with_max_entries(1024, 0);
+
+// Index of the deny rule of a directory in FILE_RULE_HITS, keyed like DENY_SUBTREES
+#[map]
+static DENY_SUBTREE_RULES: LpmTrie<[u8; SUBTREE_PATH_MAX], u32> =
+    LpmTrie::with_max_entries(1024, 0);
+
+// Opens of the paths each deny rule covers, by rule index: the number
+// allowed (the rule denies other access) and the number denied
+#[map]
+static FILE_RULE_HITS: PerCpuArray<[u64; 2]> = PerCpuArray::with_max_entries(1024, 0);
+
+// Deny list by inode, used by the hooks where bpf_d_path is unavailable, and
+// by the path programs so that a hard link to a denied file is denied too
+// Key: device number (kernel encoding) and inode number; value is access mode as in DENY_PATHS
+#[map]
+static DENY_INODES: HashMap<[u64; 2], u8> = HashMap::with_max_entries(1024, 0);
+
+// Allow list by inode, used by mori_inode_open when FILE_ALLOW_ONLY is set
+#[map]
+static ALLOW_INODES: HashMap<[u64; 2], u8> = HashMap::with_max_entries(1024, 0);
+
+// Programs (or directories of programs) that may not be executed, from the
+// [process] exec rules; key as in DENY_INODES, value unused
+#[map]
+static EXEC_DENY_INODES: HashMap<[u64; 2], u8> = HashMap::with_max_entries(1024, 0);
+
+// Programs (or directories of programs) that may be executed when EXEC_ALLOW_ONLY is set
+#[map]
+static EXEC_ALLOW_INODES: HashMap<[u64; 2], u8> = HashMap::with_max_entries(1024, 0);
+
+// Exec allow-list switch; index 0 is set to 1 by userspace to deny executing
+// programs missing from EXEC_ALLOW_INODES
+#[map]
+static EXEC_ALLOW_ONLY: Array<u8> = Array::with_max_entries(1, 0);
+
+// Device kinds of which every node is denied; index 0 holds DEVICE_* flags
+#[map]
+static DENY_DEVICE_TYPES: Array<u8> = Array::with_max_entries(1, 0);
+
+// Denied devices, whatever node they are opened through
+// Key: DEVICE_* kind and device number (kernel encoding, inode.i_rdev)
 #[map]
-static DENY_PATHS: HashMap<[u8; PATH_MAX], u8> = HashMap::with_max_entries(1024, 0);
+static DENY_DEVICES: HashMap<[u64; 2], u8> = HashMap::with_max_entries(1024, 0);
 
 // Scratch buffer for path resolution. Using a per-CPU array avoids allocating
 // large buffers on the BPF stack (limited to 512 bytes).
 #[map]
 static PATH_SCRATCH: PerCpuArray<[u8; PATH_MAX]> = PerCpuArray::with_max_entries(1, 0);
 
+// Hash of the path path_denied_mode resolved last, for count_rule_hit
+#[map]
+static PATH_HASH_SCRATCH: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+// Scratch DENY_SUBTREES key, too large for the BPF stack as well
+#[map]
+static SUBTREE_SCRATCH: PerCpuArray<Key<[u8; SUBTREE_PATH_MAX]>> =
+    PerCpuArray::with_max_entries(1, 0);
+
+// Plugin switch; index 0 is set to 1 by userspace when a policy plugin is loaded
+#[map]
+static PLUGIN_ENABLED: Array<u8> = Array::with_max_entries(1, 0);
+
+// Decision requests for connections matching no allow rule
+// Each record is a connection key: IPv4 address and port, both in network byte order
+#[map]
+static PLUGIN_REQUESTS: RingBuf = RingBuf::with_byte_size(64 * 1024, 0);
+
+// Plugin verdicts per connection key; value is VERDICT_PENDING until the plugin replies
+#[map]
+static PLUGIN_VERDICTS: HashMap<[u8; 6], u8> = HashMap::with_max_entries(4096, 0);
+
+// Connection event switch; index 0 is set to 1 by userspace when it consumes FLOW_EVENTS
+#[map]
+static FLOW_LOG_ENABLED: Array<u8> = Array::with_max_entries(1, 0);
+
+// Audit switch; index 0 is set to 1 by userspace for --audit. The programs
+// then let through what they would deny, still reporting it in FLOW_EVENTS
+// and DENIAL_EVENTS. mori's own protections (kernel, bpf and ptrace) stay on.
+#[map]
+static AUDIT: Array<u8> = Array::with_max_entries(1, 0);
+
+// Every connection attempt while events are enabled. Each record is:
+// boot time in ns (u64, native endian), PID (u32, native endian),
+// destination and source IPv6 addresses (IPv4 addresses in their IPv4-mapped
+// form), destination and source ports (network byte order), IP protocol,
+// verdict (1 = allow), reason (REASON_*), padding and the task's comm
+// (16 bytes, NUL-padded)
+#[map]
+static FLOW_EVENTS: RingBuf = RingBuf::with_byte_size(256 * 1024, 0);
+
+// errno denied connections fail with in the *_errno programs; index 0 is set by userspace
+#[map]
+static DENY_ERRNO: Array<u32> = Array::with_max_entries(1, 0);
+
 #[cgroup_sock_addr(connect4)]
 pub fn mori_connect4(ctx: SockAddrContext) -> i32 {
+    connect4(&ctx)
+}
+
+// Same as mori_connect4, but denied connections fail with DENY_ERRNO instead of
+// EPERM. bpf_set_retval needs Linux 5.18, so userspace only loads this program
+// when another errno was asked for.
+#[cgroup_sock_addr(connect4)]
+pub fn mori_connect4_errno(ctx: SockAddrContext) -> i32 {
+    let verdict = connect4(&ctx);
+    if verdict == DENY
+        && let Some(&errno) = DENY_ERRNO.get(0)
+        && errno != 0
+    {
+        unsafe { bpf_set_retval(-(errno as i32)) };
+    }
+    verdict
+}
+
+// UDP datagrams sent with an explicit destination (sendto/sendmsg) skip
+// connect(), so they are checked against the same rules here. recvmsg hooks
+// cannot deny (the kernel requires them to return 1); replies only arrive
+// from peers the command could send to.
+#[cgroup_sock_addr(sendmsg4)]
+pub fn mori_sendmsg4(ctx: SockAddrContext) -> i32 {
+    connect4(&ctx)
+}
+
+#[cgroup_sock_addr(sendmsg4)]
+pub fn mori_sendmsg4_errno(ctx: SockAddrContext) -> i32 {
+    let verdict = connect4(&ctx);
+    if verdict == DENY
+        && let Some(&errno) = DENY_ERRNO.get(0)
+        && errno != 0
+    {
+        unsafe { bpf_set_retval(-(errno as i32)) };
+    }
+    verdict
+}
+
+#[cgroup_sock_addr(connect6)]
+pub fn mori_connect6(ctx: SockAddrContext) -> i32 {
+    connect6(&ctx)
+}
+
+// Same as mori_connect6, denying with DENY_ERRNO like mori_connect4_errno
+#[cgroup_sock_addr(connect6)]
+pub fn mori_connect6_errno(ctx: SockAddrContext) -> i32 {
+    let verdict = connect6(&ctx);
+    if verdict == DENY
+        && let Some(&errno) = DENY_ERRNO.get(0)
+        && errno != 0
+    {
+        unsafe { bpf_set_retval(-(errno as i32)) };
+    }
+    verdict
+}
+
+#[cgroup_sock_addr(sendmsg6)]
+pub fn mori_sendmsg6(ctx: SockAddrContext) -> i32 {
+    connect6(&ctx)
+}
+
+#[cgroup_sock_addr(sendmsg6)]
+pub fn mori_sendmsg6_errno(ctx: SockAddrContext) -> i32 {
+    let verdict = connect6(&ctx);
+    if verdict == DENY
+        && let Some(&errno) = DENY_ERRNO.get(0)
+        && errno != 0
+    {
+        unsafe { bpf_set_retval(-(errno as i32)) };
+    }
+    verdict
+}
+
+// Sockets bound to a port the policy does not list cannot listen for
+// connections. Port 0 (an ephemeral port chosen by the kernel) is let
+// through, as clients bind it before connecting.
+#[cgroup_sock_addr(bind4)]
+pub fn mori_bind4(ctx: SockAddrContext) -> i32 {
+    decide_bind(&ctx)
+}
+
+#[cgroup_sock_addr(bind6)]
+pub fn mori_bind6(ctx: SockAddrContext) -> i32 {
+    decide_bind(&ctx)
+}
+
+fn decide_bind(ctx: &SockAddrContext) -> i32 {
+    let port = u16::from_be(unsafe { (*ctx.sock_addr).user_port } as u16);
+    if port == 0 || unsafe { LISTEN_PORTS.get(&port) }.is_some() {
+        ALLOW
+    } else {
+        info!(ctx, "deny bind: port {}", port);
+        report_task_denial(DENIAL_BIND, &port.to_be_bytes());
+        sock_deny()
+    }
+}
+
+fn connect4(ctx: &SockAddrContext) -> i32 {
     let addr = unsafe { (*ctx.sock_addr).user_ip4 };
     // When a 32-bit value is loaded in BPF it lands in CPU-endian order (little-endian on x86/arm64).
     // Convert back to big-endian so it matches the network-ordered keys stored in the map.
-    let addr_be = u32::from_be(addr);
+    let ip_bytes = u32::from_be(addr).to_be_bytes();
+    let port = u16::from_be(unsafe { (*ctx.sock_addr).user_port } as u16);
+    // IPPROTO_TCP or IPPROTO_UDP; port rules only match the protocols they name
+    let protocol = unsafe { (*ctx.sock_addr).protocol } as u8;
+
+    let (verdict, reason) = decide_v4(ip_bytes, port, protocol);
+    finish_connect(ctx, ipv4_mapped(ip_bytes), verdict, reason)
+}
+
+fn connect6(ctx: &SockAddrContext) -> i32 {
+    let words = unsafe { (*ctx.sock_addr).user_ip6 };
+    // Each 32-bit word is in network byte order, like user_ip4
+    let mut ip_bytes = [0u8; 16];
+    for (chunk, word) in ip_bytes.chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&u32::from_be(word).to_be_bytes());
+    }
+    let port = u16::from_be(unsafe { (*ctx.sock_addr).user_port } as u16);
+    let protocol = unsafe { (*ctx.sock_addr).protocol } as u8;
+
+    // IPv4-mapped addresses reach IPv4 hosts through a dual-stack socket, so
+    // they are decided like IPv4 connections, plugin included
+    let (verdict, reason) = if ip_bytes[..10] == [0; 10] && ip_bytes[10..12] == [0xff, 0xff] {
+        decide_v4(
+            [ip_bytes[12], ip_bytes[13], ip_bytes[14], ip_bytes[15]],
+            port,
+            protocol,
+        )
+    } else {
+        decide_v6(ip_bytes, port, protocol)
+    };
+    finish_connect(ctx, ip_bytes, verdict, reason)
+}
+
+/// Record the verdict on a connection to `dst` and return it, letting the
+/// connection through in audit mode
+#[inline(always)]
+fn finish_connect(ctx: &SockAddrContext, dst: [u8; 16], verdict: i32, reason: u8) -> i32 {
+    if flow_log_enabled() {
+        record_flow(ctx, dst, verdict, reason);
+    }
+    if verdict == DENY {
+        sock_deny()
+    } else {
+        verdict
+    }
+}
+
+fn decide_v4(ip_bytes: [u8; 4], port: u16, protocol: u8) -> (i32, u8) {
+    // Verdicts are reported through FLOW_EVENTS rather than logged here
+    if DENY_V4_LPM.get(&Key::new(32, ip_bytes)).is_some() {
+        (DENY, REASON_DENY_LIST)
+    } else if dns_denied(unsafe { DNS_ENDPOINTS_V4.get(&ip_bytes) }, port) {
+        (DENY, REASON_DNS_ENFORCED)
+    } else if is_allowed_v4(ip_bytes, port, protocol) || icmp_allowed(protocol) {
+        (ALLOW, REASON_ALLOW_LIST)
+    } else if plugin_enabled() {
+        let port_bytes = port.to_be_bytes();
+        let conn_key = [
+            ip_bytes[0],
+            ip_bytes[1],
+            ip_bytes[2],
+            ip_bytes[3],
+            port_bytes[0],
+            port_bytes[1],
+        ];
+        ask_plugin(&conn_key)
+    } else {
+        (DENY, REASON_NO_RULE)
+    }
+}
+
+// Policy plugins are asked about IPv4 destinations only, so other IPv6
+// connections are decided by the deny and allow lists alone
+fn decide_v6(ip_bytes: [u8; 16], port: u16, protocol: u8) -> (i32, u8) {
+    let mut port_key = [0u8; 19];
+    port_key[0] = protocol;
+    port_key[1..17].copy_from_slice(&ip_bytes);
+    port_key[17..].copy_from_slice(&port.to_be_bytes());
+    if DENY_V6_LPM.get(&Key::new(128, ip_bytes)).is_some() {
+        (DENY, REASON_DENY_LIST)
+    } else if dns_denied(unsafe { DNS_ENDPOINTS_V6.get(&ip_bytes) }, port) {
+        (DENY, REASON_DNS_ENFORCED)
+    } else if is_allowed(&ALLOW_V6_LPM, &Key::new(128, ip_bytes))
+        || is_allowed(&ALLOW_V6_PORT_LPM, &Key::new(152, port_key))
+        || icmp_allowed(protocol)
+    {
+        (ALLOW, REASON_ALLOW_LIST)
+    } else {
+        (DENY, REASON_NO_RULE)
+    }
+}
+
+/// `ip_bytes` as an IPv4-mapped IPv6 address (::ffff:a.b.c.d)
+#[inline(always)]
+fn ipv4_mapped(ip_bytes: [u8; 4]) -> [u8; 16] {
+    let mut mapped = [0u8; 16];
+    mapped[10] = 0xff;
+    mapped[11] = 0xff;
+    mapped[12..].copy_from_slice(&ip_bytes);
+    mapped
+}
 
-    // For LPM Trie lookup, always use prefix_len=32 (full IPv4 address).
+/// Whether an IPv4 destination is allowed on every port or on `port`
+fn is_allowed_v4(ip_bytes: [u8; 4], port: u16, protocol: u8) -> bool {
+    let port_bytes = port.to_be_bytes();
+    let port_key = [
+        protocol,
+        ip_bytes[0],
+        ip_bytes[1],
+        ip_bytes[2],
+        ip_bytes[3],
+        port_bytes[0],
+        port_bytes[1],
+    ];
+    // For LPM Trie lookup, always use the full key length (32 bits for the
+    // address, 56 bits for the protocol, address and port).
     // The LPM Trie will find the longest matching prefix automatically.
     // For example, if searching for 104.16.30.34:
     // - First tries to match 104.16.30.34/32 (exact match)
     // - If not found, tries shorter prefixes like 104.16.0.0/13
     // - Returns the longest matching prefix entry
-    let ip_bytes = addr_be.to_be_bytes();
-    let key = Key::new(32, ip_bytes);
+    is_allowed(&ALLOW_V4_LPM, &Key::new(32, ip_bytes))
+        || is_allowed(&ALLOW_V4_PORT_LPM, &Key::new(56, port_key))
+}
 
-    match ALLOW_V4_LPM.get(&key) {
-        Some(_) => {
-            info!(
-                &ctx,
-                "connect: {}.{}.{}.{}", ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3]
-            );
-            ALLOW
-        }
-        None => {
-            info!(
-                &ctx,
-                "deny: {}.{}.{}.{}", ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3]
-            );
-            DENY
+/// Look up the address in an allow list, deleting a matching entry past its deadline
+fn is_allowed<const N: usize>(map: &LpmTrie<[u8; N], u64>, key: &Key<[u8; N]>) -> bool {
+    // An expired single address may hide a shorter prefix that still matches,
+    // so look again once it is gone
+    for _ in 0..2 {
+        let deadline = match map.get(key) {
+            Some(&deadline) => deadline,
+            None => return false,
+        };
+        if deadline == 0 || unsafe { bpf_ktime_get_boot_ns() } < deadline {
+            return true;
         }
+        // Only single addresses learned from DNS expire, so the match is the full-length key
+        let _ = map.remove(key);
     }
+    false
 }
 
-#[lsm(hook = "file_open")]
-pub fn mori_path_open(ctx: LsmContext) -> i32 {
-    match try_path_open(&ctx) {
-        Ok(()) => 0,
-        Err(ret) => ret,
-    }
+fn plugin_enabled() -> bool {
+    matches!(PLUGIN_ENABLED.get(0), Some(&1))
 }
 
-fn try_path_open(ctx: &LsmContext) -> Result<(), i32> {
-    // Check if current process is in target cgroup
-    // This filters events to only processes within the monitored cgroup
-    let cgroup_id = unsafe { bpf_get_current_cgroup_id() };
-    if unsafe { TARGET_CGROUP.get(&cgroup_id).is_none() } {
-        return Ok(()); // Not in target cgroup, allow
-    }
+fn flow_log_enabled() -> bool {
+    matches!(FLOW_LOG_ENABLED.get(0), Some(&1))
+}
 
-    // Get file pointer from LSM context (file_open hook receives struct file *)
-    let file_ptr = unsafe { ctx.arg::<*const file>(0) };
-    if file_ptr.is_null() {
-        return Ok(());
+// Verdict of a socket or skb program for a connection or packet it denies;
+// ALLOW in audit mode
+#[inline(always)]
+fn sock_deny() -> i32 {
+    if matches!(AUDIT.get(0), Some(&1)) {
+        ALLOW
+    } else {
+        DENY
     }
+}
 
-    // Get the address of f_path field from struct file
-    // This works because LSM programs have trusted pointers with BTF type information
-    // Cast vmlinux::path to aya_ebpf::bindings::path (same memory layout)
-    let path_ptr = unsafe {
-        &(*file_ptr).f_path as *const path as *const aya_ebpf::bindings::path
-            as *mut aya_ebpf::bindings::path
-    };
+// Return value of an LSM hook for an access it denies with `ret`; 0 (let
+// through) in audit mode
+#[inline(always)]
+fn lsm_deny(ret: i32) -> i32 {
+    if matches!(AUDIT.get(0), Some(&1)) {
+        0
+    } else {
+        ret
+    }
+}
 
-    // Use per-CPU scratch buffer to avoid exceeding the 512-byte BPF stack limit
-    let path_buf = match PATH_SCRATCH.get_ptr_mut(0) {
-        Some(ptr) => unsafe { &mut *ptr },
-        None => return Ok(()),
-    };
+/// Whether DNS enforcement denies `port` on a destination whose
+/// DNS_ENDPOINTS_V4/V6 value is `endpoint`
+fn dns_denied(endpoint: Option<&u8>, port: u16) -> bool {
+    if !matches!(DNS_ENFORCED.get(0), Some(&1)) {
+        return false;
+    }
+    match port {
+        DNS_PORT => endpoint != Some(&DNS_NAMESERVER),
+        HTTPS_PORT | DOT_PORT => endpoint == Some(&DNS_BYPASS),
+        _ => false,
+    }
+}
 
-    let ret = unsafe {
-        bpf_d_path(
-            path_ptr,
-            path_buf.as_mut_ptr() as *mut aya_ebpf::cty::c_char,
-            PATH_MAX as u32,
-        )
-    };
+/// Whether `protocol` is ICMP and ICMP may reach any host
+fn icmp_allowed(protocol: u8) -> bool {
+    (protocol == IPPROTO_ICMP || protocol == IPPROTO_ICMPV6)
+        && matches!(ICMP_ALLOWED.get(0), Some(&1))
+}
 
-    if ret < 0 {
-        return Ok(());
-    }
+/// Publish a flow record for the connection attempt to `dst_addr`
+fn record_flow(ctx: &SockAddrContext, dst_addr: [u8; 16], verdict: i32, reason: u8) {
+    let sock_addr = unsafe { &*ctx.sock_addr };
+    let dst_port = u16::from_be(sock_addr.user_port as u16).to_be_bytes();
 
-    // Ensure bytes after the path string are zeroed
-    // bpf_d_path only writes the path string + null terminator,
-    // but may leave garbage after that in the buffer.
-    // We need to zero everything after the actual path to ensure HashMap lookups work.
-    // eBPF verifier doesn't allow variable-offset array writes,
-    // so we check each index against path_len within a fixed-range loop.
-    let path_len = ret as usize;
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..PATH_MAX {
-        if i >= path_len {
-            path_buf[i] = 0;
+    // The local address is only known if the socket was bound before connect()
+    let sk = unsafe { sock_addr.__bindgen_anon_1.sk };
+    let ipv4 = sock_addr.user_family == AF_INET as u32;
+    let mut src_addr = if ipv4 { ipv4_mapped([0; 4]) } else { [0; 16] };
+    let mut src_port = [0u8; 2];
+    if !sk.is_null() {
+        let sk = unsafe { &*sk };
+        if ipv4 {
+            src_addr = ipv4_mapped(u32::from_be(sk.src_ip4).to_be_bytes());
+        } else {
+            for (chunk, word) in src_addr.chunks_exact_mut(4).zip(sk.src_ip6) {
+                chunk.copy_from_slice(&u32::from_be(word).to_be_bytes());
+            }
         }
+        src_port = (sk.src_port as u16).to_be_bytes();
     }
 
-    // Get file open flags from struct file
-    let f_flags = unsafe { (*file_ptr).f_flags };
-    let access_mode = f_flags & O_ACCMODE;
+    let boot_ns = unsafe { bpf_ktime_get_boot_ns() }.to_ne_bytes();
+    let pid = ((bpf_get_current_pid_tgid() >> 32) as u32).to_ne_bytes();
+    let comm = bpf_get_current_comm().unwrap_or_default();
 
-    // Determine if this is a read or write operation
-    let is_read = access_mode == O_RDONLY || access_mode == O_RDWR;
-    let is_write = access_mode == O_WRONLY || access_mode == O_RDWR;
+    let mut record = [0u8; FLOW_RECORD_LEN];
+    record[0..8].copy_from_slice(&boot_ns);
+    record[8..12].copy_from_slice(&pid);
+    record[12..28].copy_from_slice(&dst_addr);
+    record[28..44].copy_from_slice(&src_addr);
+    record[44..46].copy_from_slice(&dst_port);
+    record[46..48].copy_from_slice(&src_port);
+    record[48] = sock_addr.protocol as u8;
+    record[49] = (verdict == ALLOW) as u8;
+    record[50] = reason;
+    record[52..68].copy_from_slice(&comm);
+    let _ = FLOW_EVENTS.output(&record, 0);
+}
 
-    // Check if this path is in the deny list
-    match unsafe { DENY_PATHS.get(&*path_buf) } {
-        Some(denied_mode) => {
-            // Check if the current access mode matches the denied mode
-            let should_deny = match *denied_mode {
-                ACCESS_MODE_READ => is_read,
-                ACCESS_MODE_WRITE => is_write,
-                ACCESS_MODE_READWRITE => is_read || is_write,
-                _ => false,
-            };
+/// Look up the plugin verdict for a connection, requesting one if there is none yet
+///
+/// A BPF program cannot wait for userspace, so the connection that triggers a
+/// request is denied; retries see the verdict once the plugin has replied.
+fn ask_plugin(conn_key: &[u8; 6]) -> (i32, u8) {
+    match unsafe { PLUGIN_VERDICTS.get(conn_key) } {
+        Some(&VERDICT_ALLOW) => (ALLOW, REASON_PLUGIN),
+        Some(&VERDICT_PENDING) => (DENY, REASON_PLUGIN_PENDING),
+        Some(_) => (DENY, REASON_PLUGIN),
+        None => {
+            // Mark the request as pending first so concurrent connects send it only once
+            if PLUGIN_VERDICTS
+                .insert(conn_key, &VERDICT_PENDING, BPF_NOEXIST)
+                .is_ok()
+                // A request lost to a full ring buffer would leave the
+                // connection pending forever, so let the next attempt ask again
+                && PLUGIN_REQUESTS.output(conn_key, 0).is_err()
+            {
+                let _ = PLUGIN_VERDICTS.remove(conn_key);
+            }
+            (DENY, REASON_PLUGIN_PENDING)
+        }
+    }
+}
 
-            if should_deny {
-                // Access mode matches deny policy, block access
-                return Err(-1);
-            } else {
-                // Access mode doesn't match deny policy, allow access
-                return Ok(());
+// Drop TCP connection requests to the cgroup's sockets from peers outside
+// INBOUND_V4_LPM and INBOUND_V6_LPM. Only SYNs without ACK are checked, so
+// replies to the command's own outbound connections are never affected.
+// IPv6 packets with extension headers, whose TCP header is not read, are
+// dropped from every peer that is not allowed.
+#[cgroup_skb(ingress)]
+pub fn mori_inbound(ctx: SkBuffContext) -> i32 {
+    match denied_syn(&ctx) {
+        Some((peer, port)) => {
+            let mut detail = [0u8; 18];
+            detail[..16].copy_from_slice(&peer);
+            detail[16..].copy_from_slice(&port.to_be_bytes());
+            report_packet_denial(DENIAL_INBOUND, &detail);
+            sock_deny()
+        }
+        None => ALLOW,
+    }
+}
+
+/// The peer (IPv4 addresses in their IPv4-mapped form) and local port of a
+/// connection request to drop; the port is 0 when the TCP header was not read
+fn denied_syn(ctx: &SkBuffContext) -> Option<([u8; 16], u16)> {
+    // cgroup skb programs see the packet from the network header on
+    let version_ihl: u8 = ctx.load(0).ok()?;
+    match version_ihl >> 4 {
+        4 => {
+            let protocol: u8 = ctx.load(9).ok()?;
+            if protocol != IPPROTO_TCP {
+                return None;
+            }
+            let header_len = ((version_ihl & 0x0f) as usize) * 4;
+            if !is_syn(ctx, header_len)? {
+                return None;
             }
+            let source: [u8; 4] = ctx.load(12).ok()?;
+            if source[0] == 127 || INBOUND_V4_LPM.get(&Key::new(32, source)).is_some() {
+                return None;
+            }
+            let port = u16::from_be(ctx.load(header_len + 2).ok()?);
+            Some((ipv4_mapped(source), port))
         }
-        None => {
-            // Path not in deny list, allow access
-            return Ok(());
+        6 => {
+            let next_header: u8 = ctx.load(6).ok()?;
+            let extension = matches!(
+                next_header,
+                IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_FRAGMENT | IPV6_DEST_OPTS | IPV6_AUTH
+            );
+            if !extension && (next_header != IPPROTO_TCP || !is_syn(ctx, IPV6_HEADER_LEN)?) {
+                return None;
+            }
+            let source: [u8; 16] = ctx.load(8).ok()?;
+            if source == IPV6_LOOPBACK || INBOUND_V6_LPM.get(&Key::new(128, source)).is_some() {
+                return None;
+            }
+            let port = if extension {
+                0
+            } else {
+                u16::from_be(ctx.load(IPV6_HEADER_LEN + 2).ok()?)
+            };
+            Some((source, port))
         }
+        _ => None,
+    }
+}
+
+/// Check if the TCP header at `tcp` opens a connection (SYN without ACK)
+fn is_syn(ctx: &SkBuffContext, tcp: usize) -> Option<bool> {
+    let flags: u8 = ctx.load(tcp + 13).ok()?;
+    Some(flags & TCP_FLAG_SYN != 0 && flags & TCP_FLAG_ACK == 0)
+}
+
+// Read the DNS answers the command receives and allow the A and AAAA records
+// of allowed domains right away, before the command connects to them. Only
+// UDP responses from SNOOP_SERVERS whose single question is in SNOOP_DOMAINS
+// or under DOMAIN_SUFFIXES are used; every packet is let through.
+#[cgroup_skb(ingress)]
+pub fn mori_dns_snoop(ctx: SkBuffContext) -> i32 {
+    let _ = snoop_dns_answer(&ctx);
+    ALLOW
+}
+
+fn snoop_dns_answer(ctx: &SkBuffContext) -> Option<()> {
+    let version_ihl: u8 = ctx.load(0).ok()?;
+    if version_ihl >> 4 != 4 {
+        return None;
+    }
+    let protocol: u8 = ctx.load(9).ok()?;
+    if protocol != IPPROTO_UDP {
+        return None;
+    }
+    let source: [u8; 4] = ctx.load(12).ok()?;
+    SNOOP_SERVERS.get(&source)?;
+    let header_len = ((version_ihl & 0x0f) as usize) * 4;
+    let source_port = u16::from_be(ctx.load(header_len).ok()?);
+    if source_port != DNS_PORT {
+        return None;
+    }
+
+    // DNS header: id, flags, question/answer/authority/additional counts
+    let dns = header_len + 8;
+    let flags = u16::from_be(ctx.load(dns + 2).ok()?);
+    // A response (QR) without error (RCODE 0)
+    if flags & 0x8000 == 0 || flags & 0x000f != 0 {
+        return None;
     }
+    let questions = u16::from_be(ctx.load(dns + 4).ok()?);
+    let answers = u16::from_be(ctx.load(dns + 6).ok()?) as usize;
+    if questions != 1 {
+        return None;
+    }
+
+    // Copy the question name up to its terminating zero, lowercased; a name
+    // with a zero byte inside a label never matches a wire-format key
+    let mut name = [0u8; DNS_NAME_MAX];
+    let mut name_len = 0;
+    for i in 0..DNS_NAME_MAX {
+        let byte: u8 = ctx.load(dns + 12 + i).ok()?;
+        if byte == 0 {
+            name_len = i + 1;
+            break;
+        }
+        name[i] = byte.to_ascii_lowercase();
+    }
+    if name_len == 0 {
+        return None;
+    }
+    if SNOOP_DOMAINS.get(&name).is_none() && !wire_name_under_suffix(&name, name_len - 1) {
+        return None;
+    }
+
+    // Every address in the answer section belongs to the queried name or to
+    // the CNAME chain it leads to
+    let now = unsafe { bpf_ktime_get_boot_ns() };
+    // Skip the name, type and class of the question
+    let mut offset = dns + 12 + name_len + 4;
+    for _ in 0..DNS_MAX_ANSWERS.min(answers) {
+        // Answer names are compression pointers in practice; stop at anything else
+        let label: u8 = ctx.load(offset).ok()?;
+        if label & 0xc0 != 0xc0 {
+            return None;
+        }
+        let record_type = u16::from_be(ctx.load(offset + 2).ok()?);
+        let class = u16::from_be(ctx.load(offset + 4).ok()?);
+        let ttl = u32::from_be(ctx.load(offset + 6).ok()?);
+        let data_len = u16::from_be(ctx.load(offset + 10).ok()?) as usize;
+        let deadline = now + (ttl as u64 + DNS_EXPIRY_GRACE_SECS) * 1_000_000_000;
+        if class == DNS_CLASS_IN && record_type == DNS_TYPE_A && data_len == 4 {
+            let addr: [u8; 4] = ctx.load(offset + 12).ok()?;
+            allow_snooped(&ALLOW_V4_LPM, &Key::new(32, addr), deadline);
+        } else if class == DNS_CLASS_IN && record_type == DNS_TYPE_AAAA && data_len == 16 {
+            let addr: [u8; 16] = ctx.load(offset + 12).ok()?;
+            allow_snooped(&ALLOW_V6_LPM, &Key::new(128, addr), deadline);
+        }
+        offset += 12 + data_len;
+    }
+    Some(())
+}
+
+/// Check if the wire-format `name` (`len` bytes before the terminating zero)
+/// is under one of DOMAIN_SUFFIXES
+///
+/// Each length byte becomes a dot, so the name reads as text with a leading
+/// dot. Names with bytes other than letters, digits, '-' and '_' are not
+/// matched, since a dot inside a label would pass for a label boundary.
+fn wire_name_under_suffix(name: &[u8; DNS_NAME_MAX], len: usize) -> bool {
+    let mut key = Key::new((DNS_NAME_MAX * 8) as u32, [0u8; DNS_NAME_MAX]);
+    let mut next_label = 0;
+    for i in 0..DNS_NAME_MAX {
+        if i >= len {
+            break;
+        }
+        let byte = if i == next_label {
+            next_label += 1 + name[i] as usize;
+            b'.'
+        } else if name[i].is_ascii_alphanumeric() || name[i] == b'-' || name[i] == b'_' {
+            name[i]
+        } else {
+            return false;
+        };
+        key.data[(len - 1 - i) & (DNS_NAME_MAX - 1)] = byte;
+    }
+    DOMAIN_SUFFIXES.get(&key).is_some()
+}
+
+/// Allow a snooped address until `deadline`, keeping entries that never expire
+/// or already live longer
+fn allow_snooped<const N: usize>(map: &LpmTrie<[u8; N], u64>, key: &Key<[u8; N]>, deadline: u64) {
+    // The lookup may match a covering range, whose entries never expire
+    match map.get(key) {
+        Some(&0) => {}
+        Some(&current) if current >= deadline => {}
+        _ => {
+            let _ = map.insert(key, &deadline, 0);
+        }
+    }
+}
+
+// Drop TLS ClientHellos whose server name is not in SNI_DOMAINS, so an allowed
+// domain does not allow every site behind the same addresses. Only TCP
+// segments starting a handshake record are checked, and destinations in
+// SNI_EXEMPT_V4/V6 not at all. A ClientHello whose server name cannot be read
+// (none sent, or not within the segment) is dropped too. IPv6 packets with
+// extension headers are not inspected.
+#[cgroup_skb(egress)]
+pub fn mori_sni(ctx: SkBuffContext) -> i32 {
+    match sni_denied(&ctx) {
+        Some(name) => {
+            info!(&ctx, "deny tls: server name not allowed");
+            report_packet_denial(DENIAL_SNI, &name);
+            sock_deny()
+        }
+        None => ALLOW,
+    }
+}
+
+/// The zero-padded server name of a ClientHello to drop, all zeros when it
+/// cannot be read
+fn sni_denied(ctx: &SkBuffContext) -> Option<[u8; SNI_NAME_MAX]> {
+    let record = tls_record_offset(ctx)?;
+    // Record type and version, handshake type and length, client version
+    let header: [u8; 11] = match ctx.load(record) {
+        Ok(header) => header,
+        // A handshake record cut short before the client version
+        Err(_) => return Some([0; SNI_NAME_MAX]),
+    };
+    if header[1] != 3 || header[5] != TLS_CLIENT_HELLO || header[9] != 3 {
+        return None;
+    }
+    match client_hello_server_name(ctx, record) {
+        Some(name) if SNI_DOMAINS.get(&name).is_some() || server_name_under_suffix(&name) => None,
+        Some(name) => Some(name),
+        None => Some([0; SNI_NAME_MAX]),
+    }
+}
+
+/// Check if the zero-padded server name is under one of DOMAIN_SUFFIXES
+fn server_name_under_suffix(name: &[u8; SNI_NAME_MAX]) -> bool {
+    // The name reversed, then the leading dot
+    let mut key = Key::new((DNS_NAME_MAX * 8) as u32, [0u8; DNS_NAME_MAX]);
+    let mut len = SNI_NAME_MAX;
+    for i in 0..SNI_NAME_MAX {
+        if name[i] == 0 {
+            len = i;
+            break;
+        }
+    }
+    // client_hello_server_name leaves at least one zero byte
+    if len == 0 || len >= DNS_NAME_MAX {
+        return false;
+    }
+    for i in 0..SNI_NAME_MAX {
+        if i >= len {
+            break;
+        }
+        key.data[(len - 1 - i) & (DNS_NAME_MAX - 1)] = name[i];
+    }
+    key.data[len & (DNS_NAME_MAX - 1)] = b'.';
+    DOMAIN_SUFFIXES.get(&key).is_some()
+}
+
+/// Offset of the TCP payload if it starts a TLS handshake record to a
+/// destination that is not exempt
+fn tls_record_offset(ctx: &SkBuffContext) -> Option<usize> {
+    // cgroup skb programs see the packet from the network header on
+    let version_ihl: u8 = ctx.load(0).ok()?;
+    let tcp = match version_ihl >> 4 {
+        4 => {
+            let protocol: u8 = ctx.load(9).ok()?;
+            let destination: [u8; 4] = ctx.load(16).ok()?;
+            if protocol != IPPROTO_TCP || SNI_EXEMPT_V4.get(&Key::new(32, destination)).is_some() {
+                return None;
+            }
+            ((version_ihl & 0x0f) as usize) * 4
+        }
+        6 => {
+            let next_header: u8 = ctx.load(6).ok()?;
+            let destination: [u8; 16] = ctx.load(24).ok()?;
+            if next_header != IPPROTO_TCP
+                || SNI_EXEMPT_V6.get(&Key::new(128, destination)).is_some()
+            {
+                return None;
+            }
+            IPV6_HEADER_LEN
+        }
+        _ => return None,
+    };
+    let data_offset: u8 = ctx.load(tcp + 12).ok()?;
+    let payload = tcp + ((data_offset >> 4) as usize) * 4;
+    // Segments without payload fail to load
+    let content_type: u8 = ctx.load(payload).ok()?;
+    (content_type == TLS_HANDSHAKE).then_some(payload)
+}
+
+/// The server name extension of the ClientHello in the record at `record`,
+/// lowercased and zero-padded to an SNI_DOMAINS key
+fn client_hello_server_name(ctx: &SkBuffContext, record: usize) -> Option<[u8; SNI_NAME_MAX]> {
+    // Skip the record header (5), handshake header (4), client version (2)
+    // and random (32)
+    let mut offset = record + 43;
+    let session_id_len: u8 = ctx.load(offset).ok()?;
+    offset += 1 + session_id_len as usize;
+    let cipher_suites_len = u16::from_be(ctx.load(offset).ok()?) as usize;
+    offset += 2 + cipher_suites_len;
+    let compression_methods_len: u8 = ctx.load(offset).ok()?;
+    offset += 1 + compression_methods_len as usize;
+    let extensions_len = u16::from_be(ctx.load(offset).ok()?) as usize;
+    offset += 2;
+    let end = offset + extensions_len;
+
+    for _ in 0..TLS_MAX_EXTENSIONS {
+        if offset + 4 > end {
+            return None;
+        }
+        let extension_type = u16::from_be(ctx.load(offset).ok()?);
+        let extension_len = u16::from_be(ctx.load(offset + 2).ok()?) as usize;
+        if extension_type == TLS_EXT_SERVER_NAME {
+            // Server name list length, then the first entry: its type
+            // (0 = host name) and length
+            let name_type: u8 = ctx.load(offset + 6).ok()?;
+            let name_len = u16::from_be(ctx.load(offset + 7).ok()?) as usize;
+            if name_type != 0 || name_len == 0 || name_len >= SNI_NAME_MAX {
+                return None;
+            }
+            let mut name = [0u8; SNI_NAME_MAX];
+            for i in 0..SNI_NAME_MAX {
+                if i == name_len {
+                    break;
+                }
+                let byte: u8 = ctx.load(offset + 9 + i).ok()?;
+                name[i] = byte.to_ascii_lowercase();
+            }
+            return Some(name);
+        }
+        offset += 4 + extension_len;
+    }
+    None
+}
+
+#[lsm(hook = "file_open")]
+pub fn mori_path_open(ctx: LsmContext) -> i32 {
+    match try_path_open(&ctx) {
+        Ok(()) => 0,
+        Err(ret) => lsm_deny(ret),
+    }
+}
+
+fn try_path_open(ctx: &LsmContext) -> Result<(), i32> {
+    // Check if current process is in target cgroup
+    // This filters events to only processes within the monitored cgroup
+    if !in_target_cgroup() {
+        return Ok(()); // Not in target cgroup, allow
+    }
+
+    // Get file pointer from LSM context (file_open hook receives struct file *)
+    let file_ptr = unsafe { ctx.arg::<*const file>(0) };
+    if file_ptr.is_null() {
+        return Ok(());
+    }
+
+    // Get the address of f_path field from struct file
+    // This works because LSM programs have trusted pointers with BTF type information
+    // Cast vmlinux::path to aya_ebpf::bindings::path (same memory layout)
+    let path_ptr = unsafe {
+        &(*file_ptr).f_path as *const path as *const aya_ebpf::bindings::path
+            as *mut aya_ebpf::bindings::path
+    };
+    let requested = requested_mode(unsafe { (*file_ptr).f_flags });
+
+    if device_denied(file_ptr) || proc_denied(file_ptr) {
+        report_denial(path_ptr, requested);
+        return Err(-1);
+    }
+
+    match path_denied_mode(path_ptr, true) {
+        Some(denied_mode) if denied_mode & requested != 0 => {
+            count_rule_hit(true);
+            report_denial(path_ptr, denied_mode & requested);
+            Err(if hidden(path_ptr) { -ENOENT } else { -1 })
+        }
+        Some(_) => {
+            count_rule_hit(false);
+            Ok(())
+        }
+        // Path not resolved; bpf_d_path failed
+        None => Ok(()),
+    }
+}
+
+// Count an open, `denied` or not, against the deny rule covering the path
+// path_denied_mode left in PATH_SCRATCH and SUBTREE_SCRATCH: the path itself
+// when it is listed, otherwise the closest listed directory above it
+#[inline(always)]
+fn count_rule_hit(denied: bool) {
+    if !matches!(FILE_RULE_STATS.get(0), Some(&1)) {
+        return;
+    }
+    let Some(hash) = PATH_HASH_SCRATCH.get(0) else {
+        return;
+    };
+    let rule = match unsafe { DENY_PATH_RULES.get(hash) } {
+        Some(rule) => Some(*rule),
+        None => SUBTREE_SCRATCH
+            .get(0)
+            .and_then(|key| DENY_SUBTREE_RULES.get(key))
+            .copied(),
+    };
+    if let Some(hits) = rule.and_then(|rule| FILE_RULE_HITS.get_ptr_mut(rule)) {
+        // Per-CPU, so nothing else updates it concurrently
+        unsafe { (*hits)[denied as usize] += 1 };
+    }
+}
+
+// Publish a DENIAL_EVENTS record of the denied access `mode` to the file at
+// `path_ptr`, when userspace consumes them
+#[inline(always)]
+fn report_denial(path_ptr: *mut aya_ebpf::bindings::path, mode: u8) {
+    if denial_events_enabled() {
+        publish_file_event(&DENIAL_EVENTS, path_ptr, mode);
+    }
+}
+
+// Publish a DENIAL_NAME record of the denied access `mode` to the file at
+// `dentry_ptr`, when userspace consumes them
+#[inline(always)]
+fn report_name(dentry_ptr: *const dentry, mode: u8) {
+    if !denial_events_enabled() || dentry_ptr.is_null() {
+        return;
+    }
+    let Some(mut entry) = DENIAL_EVENTS.reserve::<[u8; FILE_RECORD_LEN]>(0) else {
+        return;
+    };
+    let record = unsafe { &mut *entry.as_mut_ptr() };
+    write_task_header(record, DENIAL_NAME, mode);
+    let name = unsafe { (*dentry_ptr).d_name.name };
+    let read =
+        unsafe { bpf_probe_read_kernel_str_bytes(name, &mut record[FILE_RECORD_HEADER_LEN..]) };
+    if read.is_err() {
+        record[FILE_RECORD_HEADER_LEN] = 0;
+    }
+    entry.submit(0);
+}
+
+// Publish a DENIAL_EVENTS record of `kind` with `detail` for the current
+// task, when userspace consumes them
+#[inline(always)]
+fn report_task_denial<const N: usize>(kind: u8, detail: &[u8; N]) {
+    if !denial_events_enabled() {
+        return;
+    }
+    let mut record = [0u8; FILE_RECORD_HEADER_LEN + DENIAL_DETAIL_LEN];
+    write_task_header(&mut record, kind, 0);
+    record[FILE_RECORD_HEADER_LEN..FILE_RECORD_HEADER_LEN + N].copy_from_slice(detail);
+    let _ = DENIAL_EVENTS.output(&record, 0);
+}
+
+// Publish a DENIAL_EVENTS record of `kind` with `detail` from a packet
+// program, which has no task to name
+#[inline(always)]
+fn report_packet_denial<const N: usize>(kind: u8, detail: &[u8; N]) {
+    if !denial_events_enabled() {
+        return;
+    }
+    let mut record = [0u8; FILE_RECORD_HEADER_LEN + DENIAL_DETAIL_LEN];
+    record[5] = kind;
+    record[FILE_RECORD_HEADER_LEN..FILE_RECORD_HEADER_LEN + N].copy_from_slice(detail);
+    let _ = DENIAL_EVENTS.output(&record, 0);
+}
+
+#[inline(always)]
+fn denial_events_enabled() -> bool {
+    matches!(DENIAL_EVENTS_ENABLED.get(0), Some(&1))
+}
+
+// Fill in the TGID, access `mode`, `kind` and comm of a record (layout
+// documented at DENIAL_EVENTS)
+#[inline(always)]
+fn write_task_header(record: &mut [u8], kind: u8, mode: u8) {
+    let pid = ((bpf_get_current_pid_tgid() >> 32) as u32).to_ne_bytes();
+    record[0..4].copy_from_slice(&pid);
+    record[4..8].copy_from_slice(&[mode, kind, 0, 0]);
+    record[8..FILE_RECORD_HEADER_LEN].copy_from_slice(&bpf_get_current_comm().unwrap_or_default());
+}
+
+// file_open hook recording every open for the file inventory; it never
+// denies anything and is attached only when the inventory is requested
+#[lsm(hook = "file_open")]
+pub fn mori_file_observe(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let file_ptr = unsafe { ctx.arg::<*const file>(0) };
+    if file_ptr.is_null() {
+        return 0;
+    }
+    let path_ptr = unsafe {
+        &(*file_ptr).f_path as *const path as *const aya_ebpf::bindings::path
+            as *mut aya_ebpf::bindings::path
+    };
+    publish_file_event(
+        &OPEN_EVENTS,
+        path_ptr,
+        requested_mode(unsafe { (*file_ptr).f_flags }),
+    );
+    0
+}
+
+// Publish a record of `mode` access to the file at `path_ptr` on `events`
+// (layout documented at DENIAL_EVENTS); dropped when the ring buffer is full
+#[inline(always)]
+fn publish_file_event(events: &RingBuf, path_ptr: *mut aya_ebpf::bindings::path, mode: u8) {
+    let Some(mut entry) = events.reserve::<[u8; FILE_RECORD_LEN]>(0) else {
+        return;
+    };
+    // Reserved memory is not zeroed
+    let record = unsafe { &mut *entry.as_mut_ptr() };
+    write_task_header(record, DENIAL_PATH, mode);
+    let ret = unsafe {
+        bpf_d_path(
+            path_ptr,
+            record[FILE_RECORD_HEADER_LEN..].as_mut_ptr() as *mut aya_ebpf::cty::c_char,
+            PATH_MAX as u32,
+        )
+    };
+    if ret < 0 {
+        record[FILE_RECORD_HEADER_LEN] = 0;
+    }
+    entry.submit(0);
+}
+
+// stat(2) of hidden files, so that they look missing rather than denied;
+// attached only when FILE_HIDE_DENIED is set
+#[lsm(hook = "inode_getattr")]
+pub fn mori_path_getattr(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let path_ptr = unsafe { ctx.arg::<*mut aya_ebpf::bindings::path>(0) };
+    if !path_ptr.is_null() && hidden(path_ptr) {
+        lsm_deny(-ENOENT)
+    } else {
+        0
+    }
+}
+
+// Whether FILE_HIDE_DENIED makes the file at `path_ptr` look missing: a deny
+// rule denies reading it. Files only missing from the allow list stay
+// visible, since programs stat the directories above their paths.
+#[inline(always)]
+fn hidden(path_ptr: *mut aya_ebpf::bindings::path) -> bool {
+    matches!(FILE_HIDE_DENIED.get(0), Some(&1))
+        && path_denied_mode(path_ptr, false).is_some_and(|mode| mode & ACCESS_MODE_READ != 0)
+}
+
+// Directory listings of the cgroup, so that mori_getdents_exit can drop
+// hidden files from them; the three listing programs are attached only when
+// FILE_HIDE_DENIED is set
+#[tracepoint(category = "syscalls", name = "sys_enter_getdents64")]
+pub fn mori_getdents_enter(ctx: TracePointContext) -> u32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    // The arguments (fd, dirent, count) follow the common fields and the
+    // syscall number
+    let Ok(buffer) = (unsafe { ctx.read_at::<u64>(24) }) else {
+        return 0;
+    };
+    let tid = bpf_get_current_pid_tgid() as u32;
+    let _ = HIDE_LISTINGS.insert(&tid, &[buffer, 0], 0);
+    0
+}
+
+// iterate_dir() checks that the directory a getdents64 call lists may be
+// read; record its device, which the inode numbers in the listing belong to
+#[lsm(hook = "file_permission")]
+pub fn mori_file_permission(ctx: LsmContext) -> i32 {
+    let tid = bpf_get_current_pid_tgid() as u32;
+    let Some(listing) = HIDE_LISTINGS.get_ptr_mut(&tid) else {
+        return 0;
+    };
+    let file_ptr = unsafe { ctx.arg::<*const file>(0) };
+    if file_ptr.is_null() {
+        return 0;
+    }
+    if let Some(key) = inode_key(unsafe { (*file_ptr).f_path.dentry }) {
+        unsafe { (*listing)[1] = key[0] };
+    }
+    0
+}
+
+// Drop the entries of hidden files from the buffer getdents64 filled by
+// zeroing their inode numbers, which readdir(3) and Go skip like the entries
+// of deleted files. Files are matched by DENY_INODES, so only the denied
+// paths that existed when mori started are dropped.
+#[tracepoint(category = "syscalls", name = "sys_exit_getdents64")]
+pub fn mori_getdents_exit(ctx: TracePointContext) -> u32 {
+    let tid = bpf_get_current_pid_tgid() as u32;
+    let Some(&[buffer, dev]) = (unsafe { HIDE_LISTINGS.get(&tid) }) else {
+        return 0;
+    };
+    let _ = HIDE_LISTINGS.remove(&tid);
+    // The return value follows the common fields and the syscall number
+    let Ok(len) = (unsafe { ctx.read_at::<i64>(16) }) else {
+        return 0;
+    };
+    if dev == 0 || len <= 0 || matches!(AUDIT.get(0), Some(&1)) {
+        return 0;
+    }
+
+    let mut offset = 0;
+    for _ in 0..HIDE_MAX_DIRENTS {
+        if offset >= len as u64 {
+            break;
+        }
+        let entry = buffer + offset;
+        let Ok(ino) = (unsafe { bpf_probe_read_user(entry as *const u64) }) else {
+            break;
+        };
+        let Ok(reclen) =
+            (unsafe { bpf_probe_read_user((entry + DIRENT_RECLEN_OFFSET) as *const u16) })
+        else {
+            break;
+        };
+        if reclen == 0 {
+            break;
+        }
+        let denied = unsafe { DENY_INODES.get(&[dev, ino]) }
+            .copied()
+            .unwrap_or(0);
+        if denied & ACCESS_MODE_READ != 0 {
+            let _ = unsafe { bpf_probe_write_user(entry as *mut u64, &0u64) };
+        }
+        offset += reclen as u64;
+    }
+    0
+}
+
+// truncate(2) changes a file without opening it, so it is denied like
+// opening the file for writing; attached together with mori_path_open
+#[lsm(hook = "path_truncate")]
+pub fn mori_path_truncate(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let path_ptr = unsafe { ctx.arg::<*mut aya_ebpf::bindings::path>(0) };
+    if path_ptr.is_null() {
+        return 0;
+    }
+    match path_denied_mode(path_ptr, true) {
+        Some(denied_mode) if denied_mode & ACCESS_MODE_WRITE != 0 => {
+            report_denial(path_ptr, ACCESS_MODE_WRITE);
+            lsm_deny(-1)
+        }
+        _ => 0,
+    }
+}
+
+// Access modes denied on the file at `path_ptr` by DENY_PATHS, DENY_SUBTREES
+// and, with `allow_list`, the file allow list; None when bpf_d_path fails
+#[inline(always)]
+fn path_denied_mode(path_ptr: *mut aya_ebpf::bindings::path, allow_list: bool) -> Option<u8> {
+    // Use per-CPU scratch buffer to avoid exceeding the 512-byte BPF stack limit
+    let path_buf = unsafe { &mut *PATH_SCRATCH.get_ptr_mut(0)? };
+
+    let ret = unsafe {
+        bpf_d_path(
+            path_ptr,
+            path_buf.as_mut_ptr() as *mut aya_ebpf::cty::c_char,
+            PATH_MAX as u32,
+        )
+    };
+
+    if ret < 0 {
+        return None;
+    }
+
+    // A hard link reaches a denied file under another path; the file itself
+    // is found by its inode
+    let dentry_ptr = unsafe { (*path_ptr).dentry } as *const dentry;
+    let inode_mode = inode_key(dentry_ptr)
+        .and_then(|key| unsafe { DENY_INODES.get(&key) }.copied())
+        .unwrap_or(0);
+
+    // bpf_d_path returns the length including the null terminator; the
+    // bytes after it are left over from earlier calls
+    let path_len = ret as usize;
+
+    // Check if this path is in the deny list. The verifier needs a constant
+    // loop bound, so the loop stops at the end of the path by itself.
+    let mut hash = FNV_OFFSET;
+    #[allow(clippy::needless_range_loop)]
+    for i in 0..PATH_MAX {
+        if i + 1 >= path_len {
+            break;
+        }
+        hash ^= path_buf[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    let mut denied_mode = inode_mode | unsafe { DENY_PATHS.get(&hash) }.copied().unwrap_or(0);
+    if let Some(slot) = PATH_HASH_SCRATCH.get_ptr_mut(0) {
+        unsafe { *slot = hash };
+    }
+
+    // Then whether a denied directory holds it; the longest match carries
+    // the modes of the denied directories above it too
+    if let Some(key) = SUBTREE_SCRATCH.get_ptr_mut(0) {
+        let key = unsafe { &mut *key };
+        key.prefix_len = (SUBTREE_PATH_MAX * 8) as u32;
+        // Copy the path zero-padded; the verifier doesn't allow
+        // variable-offset writes, so each index is checked against path_len
+        for i in 0..SUBTREE_PATH_MAX {
+            key.data[i] = if i < path_len { path_buf[i] } else { 0 };
+        }
+        if let Some(mode) = DENY_SUBTREES.get(key) {
+            denied_mode |= *mode;
+        }
+        // In allow-list mode, whatever the closest allowed path does not
+        // allow is denied
+        if allow_list && matches!(FILE_ALLOW_ONLY.get(0), Some(&1)) {
+            let allowed_mode = ALLOW_SUBTREES.get(key).copied().unwrap_or(0);
+            denied_mode |= !allowed_mode & ACCESS_MODE_READWRITE;
+        }
+    }
+    Some(denied_mode)
+}
+
+// file_open hook matching denied files by inode instead of path
+// bpf_d_path is only allowed from some hooks and missing on older kernels;
+// userspace loads this program instead of mori_path_open when that one is rejected.
+#[lsm(hook = "file_open")]
+pub fn mori_inode_open(ctx: LsmContext) -> i32 {
+    match try_inode_open(&ctx) {
+        Ok(()) => 0,
+        Err(ret) => lsm_deny(ret),
+    }
+}
+
+fn try_inode_open(ctx: &LsmContext) -> Result<(), i32> {
+    if !in_target_cgroup() {
+        return Ok(());
+    }
+
+    let file_ptr = unsafe { ctx.arg::<*const file>(0) };
+    if file_ptr.is_null() {
+        return Ok(());
+    }
+
+    let dentry_ptr = unsafe { (*file_ptr).f_path.dentry };
+    let requested = requested_mode(unsafe { (*file_ptr).f_flags });
+    if device_denied(file_ptr) || proc_denied(file_ptr) {
+        report_name(dentry_ptr, requested);
+        return Err(-1);
+    }
+
+    match dentry_denied_mode(dentry_ptr) {
+        Some(denied_mode) if denies(denied_mode, unsafe { (*file_ptr).f_flags }) => {
+            report_name(dentry_ptr, denied_mode & requested);
+            Err(-1)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Whether the file being opened is a device node denied by
+// DENY_DEVICE_TYPES or DENY_DEVICES; the path it is opened by doesn't matter
+#[inline(always)]
+fn device_denied(file_ptr: *const file) -> bool {
+    let inode_ptr: *const inode = unsafe { (*file_ptr).f_inode };
+    if inode_ptr.is_null() {
+        return false;
+    }
+    let kind = match unsafe { (*inode_ptr).i_mode } & S_IFMT {
+        S_IFBLK => DEVICE_BLOCK,
+        S_IFCHR => DEVICE_CHAR,
+        _ => return false,
+    };
+    if DENY_DEVICE_TYPES
+        .get(0)
+        .is_some_and(|types| types & kind != 0)
+    {
+        return true;
+    }
+    let key = [kind as u64, unsafe { (*inode_ptr).i_rdev } as u64];
+    unsafe { DENY_DEVICES.get(&key) }.is_some()
+}
+
+// Whether the file being opened belongs to the /proc/<pid> directory of a
+// process outside TARGET_CGROUP, when FILE_PRIVATE_PROC is set
+#[inline(always)]
+fn proc_denied(file_ptr: *const file) -> bool {
+    if !matches!(FILE_PRIVATE_PROC.get(0), Some(&1)) {
+        return false;
+    }
+    let inode_ptr: *const inode = unsafe { (*file_ptr).f_inode };
+    if inode_ptr.is_null() {
+        return false;
+    }
+    let sb_ptr: *const super_block = unsafe { (*inode_ptr).i_sb };
+    if sb_ptr.is_null() || unsafe { (*sb_ptr).s_magic } as u64 != PROC_SUPER_MAGIC {
+        return false;
+    }
+    match proc_cgroup_id(inode_ptr) {
+        Some(cgroup_id) => unsafe { TARGET_CGROUP.get(&cgroup_id) }.is_none(),
+        None => false,
+    }
+}
+
+// cgroup ID of the process a procfs inode belongs to, read through the
+// proc_inode embedding it; None for entries that belong to no process, such
+// as /proc/meminfo, and for processes that already exited
+#[inline(always)]
+fn proc_cgroup_id(inode_ptr: *const inode) -> Option<u64> {
+    let ei =
+        (inode_ptr as usize - core::mem::offset_of!(proc_inode, vfs_inode)) as *const proc_inode;
+    let pid_ptr: *const pid =
+        unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*ei).pid)) }.ok()?;
+    if pid_ptr.is_null() {
+        return None;
+    }
+    // The task is linked into pid.tasks[PIDTYPE_PID] by task_struct.pid_links[PIDTYPE_PID]
+    let link: *const hlist_node =
+        unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*pid_ptr).tasks[0].first)) }.ok()?;
+    if link.is_null() {
+        return None;
+    }
+    let task =
+        (link as usize - core::mem::offset_of!(task_struct, pid_links)) as *const task_struct;
+    task_cgroup_id(task)
+}
+
+// cgroup v2 ID of a task, read through its css_set
+#[inline(always)]
+fn task_cgroup_id(task: *const task_struct) -> Option<u64> {
+    if task.is_null() {
+        return None;
+    }
+    let cgroups: *const css_set =
+        unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*task).cgroups)) }.ok()?;
+    let cgroup_ptr: *const cgroup =
+        unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*cgroups).dfl_cgrp)) }.ok()?;
+    let kn: *const kernfs_node =
+        unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*cgroup_ptr).kn)) }.ok()?;
+    unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*kn).id)) }.ok()
+}
+
+// Loading kernel modules, by name (request_module) or from a file or buffer
+// (finit_module, init_module), and reading kexec images and security
+// policies into the kernel, from TARGET_CGROUP
+#[lsm(hook = "kernel_module_request")]
+pub fn mori_kernel_module_request(_ctx: LsmContext) -> i32 {
+    deny_target_cgroup()
+}
+
+#[lsm(hook = "kernel_read_file")]
+pub fn mori_kernel_read_file(_ctx: LsmContext) -> i32 {
+    deny_target_cgroup()
+}
+
+#[lsm(hook = "kernel_load_data")]
+pub fn mori_kernel_load_data(_ctx: LsmContext) -> i32 {
+    deny_target_cgroup()
+}
+
+// Every bpf() command from TARGET_CGROUP, which could otherwise load
+// programs or rewrite maps, mori's own included
+#[lsm(hook = "bpf")]
+pub fn mori_bpf(_ctx: LsmContext) -> i32 {
+    deny_target_cgroup()
+}
+
+#[inline(always)]
+fn deny_target_cgroup() -> i32 {
+    if in_target_cgroup() { -1 } else { 0 }
+}
+
+// Attaching to (ptrace) or reading the memory of (process_vm_readv,
+// /proc/<pid>/mem) a process outside TARGET_CGROUP from inside it. Processes
+// whose cgroup cannot be read are treated as outside.
+#[lsm(hook = "ptrace_access_check")]
+pub fn mori_ptrace_access_check(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let child: *const task_struct = unsafe { ctx.arg(0) };
+    match task_cgroup_id(child) {
+        Some(cgroup_id) if unsafe { TARGET_CGROUP.get(&cgroup_id) }.is_some() => 0,
+        _ => -1,
+    }
+}
+
+// Mode, owner and size changes (chmod, chown, truncate) of files that may
+// not be written. bpf_d_path is not available from this hook, so files are
+// matched by inode in both modes.
+#[lsm(hook = "inode_setattr")]
+pub fn mori_inode_setattr(ctx: LsmContext) -> i32 {
+    // inode_setattr(struct mnt_idmap *, struct dentry *, struct iattr *)
+    decide_setattr(unsafe { ctx.arg(1) }, unsafe { ctx.arg(2) })
+}
+
+// The same for kernels before 6.9, whose hook has no idmap argument
+#[lsm(hook = "inode_setattr")]
+pub fn mori_inode_setattr_legacy(ctx: LsmContext) -> i32 {
+    // inode_setattr(struct dentry *, struct iattr *)
+    decide_setattr(unsafe { ctx.arg(0) }, unsafe { ctx.arg(1) })
+}
+
+#[inline(always)]
+fn decide_setattr(dentry_ptr: *const dentry, attr_ptr: *const iattr) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    if dentry_ptr.is_null() || attr_ptr.is_null() {
+        return 0;
+    }
+    let valid = unsafe { (*attr_ptr).ia_valid };
+    if valid & (ATTR_MODE | ATTR_UID | ATTR_GID | ATTR_SIZE) == 0 {
+        return 0;
+    }
+    if write_denied(dentry_ptr) {
+        report_name(dentry_ptr, ACCESS_MODE_WRITE);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+// Extended attribute changes (setfattr, setcap, chcon) and POSIX ACL changes
+// of files that may not be written, matched by inode like mori_inode_setattr.
+// Linux 6.2 moved ACLs from the xattr hooks to hooks of their own.
+#[lsm(hook = "inode_setxattr")]
+pub fn mori_inode_setxattr(ctx: LsmContext) -> i32 {
+    decide_xattr(&ctx)
+}
+
+#[lsm(hook = "inode_removexattr")]
+pub fn mori_inode_removexattr(ctx: LsmContext) -> i32 {
+    decide_xattr(&ctx)
+}
+
+#[lsm(hook = "inode_set_acl")]
+pub fn mori_inode_set_acl(ctx: LsmContext) -> i32 {
+    decide_xattr(&ctx)
+}
+
+#[lsm(hook = "inode_remove_acl")]
+pub fn mori_inode_remove_acl(ctx: LsmContext) -> i32 {
+    decide_xattr(&ctx)
+}
+
+// Deny an attribute change of the dentry after the mnt_idmap argument of
+// the xattr and ACL hooks when it may not be written
+#[inline(always)]
+fn decide_xattr(ctx: &LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let dentry_ptr = unsafe { ctx.arg::<*const dentry>(1) };
+    if write_denied(dentry_ptr) {
+        report_name(dentry_ptr, ACCESS_MODE_WRITE);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+// Creating a file, directory, device node or link adds a name to a
+// directory, so it is denied where the directory may not be written; the parent walk gives this the same subtree semantics as
+// opening. bpf_d_path is not available from these hooks, so directories are
+// matched by inode.
+#[lsm(hook = "path_mknod")]
+pub fn mori_path_mknod(ctx: LsmContext) -> i32 {
+    // path_mknod(const struct path *dir, struct dentry *, umode_t, unsigned int dev)
+    decide_create(unsafe { ctx.arg(0) })
+}
+
+#[lsm(hook = "path_mkdir")]
+pub fn mori_path_mkdir(ctx: LsmContext) -> i32 {
+    // path_mkdir(const struct path *dir, struct dentry *, umode_t)
+    decide_create(unsafe { ctx.arg(0) })
+}
+
+#[lsm(hook = "path_symlink")]
+pub fn mori_path_symlink(ctx: LsmContext) -> i32 {
+    // path_symlink(const struct path *dir, struct dentry *, const char *old_name)
+    decide_create(unsafe { ctx.arg(0) })
+}
+
+#[lsm(hook = "path_link")]
+pub fn mori_path_link(ctx: LsmContext) -> i32 {
+    // path_link(struct dentry *old_dentry, const struct path *new_dir, struct dentry *)
+    decide_create(unsafe { ctx.arg(1) })
+}
+
+// A rename also removes the name from the old directory and replaces the
+// file the new name pointed to, so the file moved, the file replaced and
+// the new directory must all be writable; the parent walk from the moved
+// file covers the old directory
+#[lsm(hook = "path_rename")]
+pub fn mori_path_rename(ctx: LsmContext) -> i32 {
+    // path_rename(const struct path *old_dir, struct dentry *old_dentry,
+    //             const struct path *new_dir, struct dentry *new_dentry, unsigned int flags)
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let new_dir: *const path = unsafe { ctx.arg(2) };
+    let new_dir_dentry = if new_dir.is_null() {
+        core::ptr::null()
+    } else {
+        unsafe { (*new_dir).dentry }
+    };
+    for dentry_ptr in [unsafe { ctx.arg(1) }, new_dir_dentry, unsafe { ctx.arg(3) }] {
+        if write_denied(dentry_ptr) {
+            report_name(dentry_ptr, ACCESS_MODE_WRITE);
+            return lsm_deny(-1);
+        }
+    }
+    0
+}
+
+// Deleting a file or directory that may not be written, or one in a
+// directory that may not be written, which the parent walk covers
+#[lsm(hook = "path_unlink")]
+pub fn mori_path_unlink(ctx: LsmContext) -> i32 {
+    // path_unlink(const struct path *dir, struct dentry *)
+    decide_remove(unsafe { ctx.arg(1) })
+}
+
+#[lsm(hook = "path_rmdir")]
+pub fn mori_path_rmdir(ctx: LsmContext) -> i32 {
+    // path_rmdir(const struct path *dir, struct dentry *)
+    decide_remove(unsafe { ctx.arg(1) })
+}
+
+/// Check adding a name to the directory at `dir_ptr`
+#[inline(always)]
+fn decide_create(dir_ptr: *const path) -> i32 {
+    if !in_target_cgroup() || dir_ptr.is_null() {
+        return 0;
+    }
+    let dentry_ptr = unsafe { (*dir_ptr).dentry };
+    if write_denied(dentry_ptr) {
+        report_name(dentry_ptr, ACCESS_MODE_WRITE);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+/// Check removing the file at `dentry_ptr`
+#[inline(always)]
+fn decide_remove(dentry_ptr: *const dentry) -> i32 {
+    if in_target_cgroup() && write_denied(dentry_ptr) {
+        report_name(dentry_ptr, ACCESS_MODE_WRITE);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+/// Whether the file at `dentry_ptr`, or a directory above it, may not be
+/// written; false for a name without a file (a rename's new name before it
+/// exists)
+#[inline(always)]
+fn write_denied(dentry_ptr: *const dentry) -> bool {
+    !dentry_ptr.is_null()
+        && dentry_denied_mode(dentry_ptr).is_some_and(|mode| mode & ACCESS_MODE_WRITE != 0)
+}
+
+// Executing a program. Like the metadata hook this has no bpf_d_path, so
+// files are matched by inode; a denied directory still covers programs
+// created below it later through the parent walk.
+#[lsm(hook = "bprm_check_security")]
+pub fn mori_bprm_check(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let bprm_ptr = unsafe { ctx.arg::<*const linux_binprm>(0) };
+    if bprm_ptr.is_null() {
+        return 0;
+    }
+    let file_ptr: *const file = unsafe { (*bprm_ptr).file };
+    if file_ptr.is_null() {
+        return 0;
+    }
+    exec_denied_by_file_rules(unsafe { (*file_ptr).f_path.dentry })
+}
+
+// Mapping a file as executable code, which is how the dynamic loader runs
+// shared libraries and how a program could bypass mori_bprm_check
+#[lsm(hook = "mmap_file")]
+pub fn mori_mmap_file(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    // mmap_file(struct file *, unsigned long reqprot, unsigned long prot, unsigned long flags)
+    let file_ptr = unsafe { ctx.arg::<*const file>(0) };
+    let prot: u64 = unsafe { ctx.arg(2) };
+    // Anonymous mappings have no file
+    if file_ptr.is_null() || prot & PROT_EXEC == 0 {
+        return 0;
+    }
+    exec_denied_by_file_rules(unsafe { (*file_ptr).f_path.dentry })
+}
+
+// Deny executing the file at `dentry_ptr` when the file rules deny executing it
+#[inline(always)]
+fn exec_denied_by_file_rules(dentry_ptr: *const dentry) -> i32 {
+    match dentry_denied_mode(dentry_ptr) {
+        Some(denied_mode) if denied_mode & ACCESS_MODE_EXECUTE != 0 => {
+            report_name(dentry_ptr, ACCESS_MODE_EXECUTE);
+            lsm_deny(-1)
+        }
+        _ => 0,
+    }
+}
+
+// Executing a program under the [process] exec rules. For a script this runs
+// for the script and then for its interpreter, so both must be allowed.
+#[lsm(hook = "bprm_check_security")]
+pub fn mori_exec_check(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let bprm_ptr = unsafe { ctx.arg::<*const linux_binprm>(0) };
+    if bprm_ptr.is_null() {
+        return 0;
+    }
+    let file_ptr: *const file = unsafe { (*bprm_ptr).file };
+    if file_ptr.is_null() {
+        return 0;
+    }
+    let dentry_ptr = unsafe { (*file_ptr).f_path.dentry };
+    let denied = exec_listed(&EXEC_DENY_INODES, dentry_ptr)
+        || (matches!(EXEC_ALLOW_ONLY.get(0), Some(&1))
+            && !exec_listed(&EXEC_ALLOW_INODES, dentry_ptr));
+    if denied {
+        report_name(dentry_ptr, ACCESS_MODE_EXECUTE);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+// Mapping a denied program as executable code, which is how running it
+// through the dynamic loader (ld.so /usr/bin/curl) would bypass
+// mori_exec_check. The allow list is left to the exec hook, since shared
+// libraries are mapped the same way.
+#[lsm(hook = "mmap_file")]
+pub fn mori_exec_mmap(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let file_ptr = unsafe { ctx.arg::<*const file>(0) };
+    let prot: u64 = unsafe { ctx.arg(2) };
+    if file_ptr.is_null() || prot & PROT_EXEC == 0 {
+        return 0;
+    }
+    let dentry_ptr = unsafe { (*file_ptr).f_path.dentry };
+    if exec_listed(&EXEC_DENY_INODES, dentry_ptr) {
+        report_name(dentry_ptr, ACCESS_MODE_EXECUTE);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+// Whether the inode of `dentry_ptr`, or a directory above it within the
+// mount, is in `inodes`
+#[inline(always)]
+fn exec_listed(inodes: &HashMap<[u64; 2], u8>, dentry_ptr: *const dentry) -> bool {
+    let Some(key) = inode_key(dentry_ptr) else {
+        return false;
+    };
+    if unsafe { inodes.get(&key) }.is_some() {
+        return true;
+    }
+    let mut dentry_ptr = dentry_ptr;
+    for _ in 0..INODE_PARENT_DEPTH {
+        let parent: *const dentry = unsafe { (*dentry_ptr).d_parent };
+        if parent.is_null() || parent == dentry_ptr {
+            break;
+        }
+        let parent_inode: *const inode = unsafe { (*parent).d_inode };
+        if parent_inode.is_null() {
+            break;
+        }
+        if unsafe { inodes.get(&[key[0], (*parent_inode).i_ino as u64]) }.is_some() {
+            return true;
+        }
+        dentry_ptr = parent;
+    }
+    false
+}
+
+// Key of the inode of `dentry_ptr` in DENY_INODES and ALLOW_INODES
+#[inline(always)]
+fn inode_key(dentry_ptr: *const dentry) -> Option<[u64; 2]> {
+    if dentry_ptr.is_null() {
+        return None;
+    }
+    let inode_ptr: *const inode = unsafe { (*dentry_ptr).d_inode };
+    if inode_ptr.is_null() {
+        return None;
+    }
+    let sb_ptr: *const super_block = unsafe { (*inode_ptr).i_sb };
+    if sb_ptr.is_null() {
+        return None;
+    }
+    Some([
+        unsafe { (*sb_ptr).s_dev } as u64,
+        unsafe { (*inode_ptr).i_ino } as u64,
+    ])
+}
+
+// Access modes denied on the inode of `dentry_ptr` by DENY_INODES and
+// ALLOW_INODES; listed directories cover the files below them, so the
+// parent dentries are walked up to the root of the mount
+#[inline(always)]
+fn dentry_denied_mode(dentry_ptr: *const dentry) -> Option<u8> {
+    let key = inode_key(dentry_ptr)?;
+
+    let mut denied_mode = unsafe { DENY_INODES.get(&key) }.copied().unwrap_or(0);
+    let allow_only = matches!(FILE_ALLOW_ONLY.get(0), Some(&1));
+    let mut allowed_mode = if allow_only {
+        unsafe { ALLOW_INODES.get(&key) }.copied().unwrap_or(0)
+    } else {
+        ACCESS_MODE_READWRITE
+    };
+
+    let mut dentry_ptr = dentry_ptr;
+    for _ in 0..INODE_PARENT_DEPTH {
+        let parent: *const dentry = unsafe { (*dentry_ptr).d_parent };
+        if parent.is_null() || parent == dentry_ptr {
+            break;
+        }
+        let parent_inode: *const inode = unsafe { (*parent).d_inode };
+        if parent_inode.is_null() {
+            break;
+        }
+        let parent_key = [key[0], unsafe { (*parent_inode).i_ino } as u64];
+        if let Some(mode) = unsafe { DENY_INODES.get(&parent_key) } {
+            denied_mode |= *mode;
+        }
+        if allow_only && let Some(mode) = unsafe { ALLOW_INODES.get(&parent_key) } {
+            allowed_mode |= *mode;
+        }
+        dentry_ptr = parent;
+    }
+    Some(denied_mode | (!allowed_mode & ACCESS_MODE_READWRITE))
+}
+
+// Raw packet and netlink sockets never reach the connect hooks, and ICMP
+// sockets usually send without connecting, so they are refused when created.
+// Sockets the kernel creates for itself are left alone.
+#[lsm(hook = "socket_create")]
+pub fn mori_socket_create(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let family: i32 = unsafe { ctx.arg(0) };
+    let protocol: i32 = unsafe { ctx.arg(2) };
+    let kern: i32 = unsafe { ctx.arg(3) };
+    if kern != 0 {
+        return 0;
+    }
+    let icmp = (family == AF_INET && protocol == i32::from(IPPROTO_ICMP))
+        || (family == AF_INET6 && protocol == i32::from(IPPROTO_ICMPV6));
+    let denied = unsafe { DENY_FAMILIES.get(&(family as u32)) }.is_some()
+        || (icmp && matches!(DENY_ICMP.get(0), Some(&1)));
+    if denied {
+        info!(&ctx, "deny socket: family {} protocol {}", family, protocol);
+        let mut detail = [0u8; 8];
+        detail[..4].copy_from_slice(&family.to_ne_bytes());
+        detail[4..].copy_from_slice(&protocol.to_ne_bytes());
+        report_task_denial(DENIAL_SOCKET, &detail);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+// Abstract unix sockets have no inode, so file rules never see them; their
+// names are checked when connecting and when sending a datagram to an address.
+#[lsm(hook = "socket_connect")]
+pub fn mori_unix_connect(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let addr: *const u8 = unsafe { ctx.arg(1) };
+    let addrlen: i32 = unsafe { ctx.arg(2) };
+    decide_unix(&ctx, addr, addrlen)
+}
+
+#[lsm(hook = "socket_sendmsg")]
+pub fn mori_unix_sendmsg(ctx: LsmContext) -> i32 {
+    if !in_target_cgroup() {
+        return 0;
+    }
+    let msg: *const msghdr = unsafe { ctx.arg(1) };
+    let Ok(addr) = (unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*msg).msg_name)) }) else {
+        return 0;
+    };
+    let Ok(addrlen) = (unsafe { bpf_probe_read_kernel(core::ptr::addr_of!((*msg).msg_namelen)) })
+    else {
+        return 0;
+    };
+    // Sends on connected sockets were checked by mori_unix_connect
+    if addr.is_null() {
+        return 0;
+    }
+    decide_unix(&ctx, addr as *const u8, addrlen)
+}
+
+/// Check a connection or datagram to the socket address at `addr`; only
+/// abstract unix addresses can be denied
+fn decide_unix(ctx: &LsmContext, addr: *const u8, addrlen: i32) -> i32 {
+    if addrlen <= UNIX_NAME_OFFSET as i32 {
+        return 0;
+    }
+    let Ok(family) = (unsafe { bpf_probe_read_kernel(addr as *const u16) }) else {
+        return 0;
+    };
+    let Ok(first) = (unsafe { bpf_probe_read_kernel(addr.add(2)) }) else {
+        return 0;
+    };
+    // Pathname sockets start with their path instead of a NUL byte
+    if family != AF_UNIX || first != 0 {
+        return 0;
+    }
+
+    let mut name = [0u8; UNIX_NAME_MAX];
+    let len = (addrlen as usize - UNIX_NAME_OFFSET).min(UNIX_NAME_MAX);
+    // A name that cannot be read is denied, and reported without a name
+    let denied =
+        if unsafe { bpf_probe_read_kernel_buf(addr.add(UNIX_NAME_OFFSET), &mut name[..len]) }
+            .is_err()
+        {
+            name = [0; UNIX_NAME_MAX];
+            true
+        } else {
+            match unsafe { UNIX_ABSTRACT.get(&name) } {
+                Some(&UNIX_ALLOW) => false,
+                Some(&UNIX_DENY) => true,
+                _ => matches!(UNIX_DENY_ABSTRACT.get(0), Some(&1)),
+            }
+        };
+    if denied {
+        info!(ctx, "deny abstract unix socket");
+        report_task_denial(DENIAL_UNIX, &name);
+        lsm_deny(-1)
+    } else {
+        0
+    }
+}
+
+fn in_target_cgroup() -> bool {
+    let cgroup_id = unsafe { bpf_get_current_cgroup_id() };
+    unsafe { TARGET_CGROUP.get(&cgroup_id).is_some() }
+}
+
+/// Check if opening a file with `f_flags` is denied by `denied_mode` (ACCESS_MODE_*)
+fn denies(denied_mode: u8, f_flags: u32) -> bool {
+    // Execute bits are left to mori_bprm_check and mori_mmap_file
+    denied_mode & requested_mode(f_flags) != 0
+}
+
+/// Access opening a file with `f_flags` asks for, as ACCESS_MODE_READ and ACCESS_MODE_WRITE bits
+fn requested_mode(f_flags: u32) -> u8 {
+    let access_mode = f_flags & O_ACCMODE;
+
+    // Determine if this is a read or write operation
+    let is_read = access_mode == O_RDONLY || access_mode == O_RDWR;
+    let is_write = access_mode == O_WRONLY || access_mode == O_RDWR;
+
+    (if is_read { ACCESS_MODE_READ } else { 0 }) | (if is_write { ACCESS_MODE_WRITE } else { 0 })
 }
 
 #[panic_handler]
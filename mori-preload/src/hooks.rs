@@ -0,0 +1,424 @@
+use std::{
+    cell::Cell,
+    collections::BTreeSet,
+    ffi::{CStr, OsStr, c_char, c_int},
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use libc::{FILE, addrinfo, mode_t, sockaddr, socklen_t};
+
+use crate::policy::{ShimPolicy, absolute_path, access_from_flags, access_from_mode};
+
+/// Environment variable carrying the policy from mori
+const POLICY_ENV: &str = "MORI_PRELOAD_POLICY";
+
+/// Error returned for denied operations (matches the eBPF programs); denied
+/// connections use the policy's `connect-errno` instead when it is set
+const DENIED_ERRNO: c_int = libc::EPERM;
+
+static POLICY: OnceLock<Option<ShimPolicy>> = OnceLock::new();
+
+/// Addresses returned for allowed host names
+static RESOLVED: Mutex<BTreeSet<IpAddr>> = Mutex::new(BTreeSet::new());
+
+thread_local! {
+    /// Set while a hook runs so calls made by the hook itself are passed through
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+fn policy() -> Option<&'static ShimPolicy> {
+    POLICY
+        .get_or_init(|| {
+            std::env::var(POLICY_ENV)
+                .ok()
+                .map(|encoded| ShimPolicy::parse(&encoded))
+        })
+        .as_ref()
+}
+
+struct Guard;
+
+impl Guard {
+    fn enter() -> Option<Self> {
+        ACTIVE
+            .try_with(|active| (!active.replace(true)).then_some(Guard))
+            .ok()
+            .flatten()
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let _ = ACTIVE.try_with(|active| active.set(false));
+    }
+}
+
+/// Look up the next definition of a libc function, cached per call site
+macro_rules! real {
+    ($name:literal, $ty:ty) => {{
+        static REAL: OnceLock<usize> = OnceLock::new();
+        let ptr = *REAL.get_or_init(|| unsafe {
+            libc::dlsym(libc::RTLD_NEXT, concat!($name, "\0").as_ptr().cast()) as usize
+        });
+        // A null pointer becomes None
+        unsafe { std::mem::transmute::<usize, Option<$ty>>(ptr) }
+    }};
+}
+
+fn set_errno(code: c_int) {
+    unsafe { *libc::__errno_location() = code };
+}
+
+/// Check if opening `path` relative to `dirfd` with `access` bits is denied
+///
+/// # Safety
+/// `path` must be null or point to a NUL-terminated string.
+unsafe fn path_denied(dirfd: c_int, path: *const c_char, access: u8) -> bool {
+    let Some(policy) = policy() else {
+        return false;
+    };
+    if (policy.denied_paths.is_empty() && policy.allowed_paths.is_empty()) || path.is_null() {
+        return false;
+    }
+    let Some(_guard) = Guard::enter() else {
+        return false;
+    };
+
+    let path = Path::new(OsStr::from_bytes(
+        unsafe { CStr::from_ptr(path) }.to_bytes(),
+    ));
+    let base = if path.is_absolute() {
+        Some(PathBuf::from("/"))
+    } else if dirfd == libc::AT_FDCWD {
+        std::env::current_dir().ok()
+    } else {
+        fs::read_link(format!("/proc/self/fd/{}", dirfd)).ok()
+    };
+    let Some(base) = base else {
+        return false;
+    };
+
+    // Match the resolved path like bpf_d_path does; fall back to the lexical
+    // path for files that do not exist yet
+    let absolute = absolute_path(&base, path);
+    let resolved = fs::canonicalize(&absolute).unwrap_or(absolute);
+    policy.denies_path(&resolved, access)
+}
+
+/// Check if connecting to `addr` is denied
+///
+/// # Safety
+/// `addr` must be null or point to a socket address of `len` bytes.
+unsafe fn connect_denied(addr: *const sockaddr, len: socklen_t) -> bool {
+    let Some(policy) = policy() else {
+        return false;
+    };
+    if addr.is_null() {
+        return false;
+    }
+
+    let len = len as usize;
+    if c_int::from(unsafe { (*addr).sa_family }) == libc::AF_UNIX {
+        // Abstract names start with a NUL byte; pathname sockets are not restricted
+        let path = size_of::<libc::sa_family_t>();
+        if len <= path || unsafe { *addr.cast::<u8>().add(path) } != 0 {
+            return false;
+        }
+        let name =
+            unsafe { std::slice::from_raw_parts(addr.cast::<u8>().add(path + 1), len - path - 1) };
+        return !policy.allows_abstract(name);
+    }
+    if !policy.restrict_network {
+        return false;
+    }
+
+    let ip = match c_int::from(unsafe { (*addr).sa_family }) {
+        libc::AF_INET if len >= size_of::<libc::sockaddr_in>() => {
+            let addr = unsafe { &*addr.cast::<libc::sockaddr_in>() };
+            IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)))
+        }
+        libc::AF_INET6 if len >= size_of::<libc::sockaddr_in6>() => {
+            let addr = Ipv6Addr::from(
+                unsafe { &*addr.cast::<libc::sockaddr_in6>() }
+                    .sin6_addr
+                    .s6_addr,
+            );
+            addr.to_ipv4_mapped().map_or(IpAddr::V6(addr), IpAddr::V4)
+        }
+        // AF_UNSPEC (disconnect) and others are not restricted
+        _ => return false,
+    };
+
+    !policy.allows_addr(ip) && !RESOLVED.lock().is_ok_and(|resolved| resolved.contains(&ip))
+}
+
+/// Check if binding to `addr` is denied
+///
+/// # Safety
+/// `addr` must be null or point to a socket address of `len` bytes.
+unsafe fn bind_denied(addr: *const sockaddr, len: socklen_t) -> bool {
+    let Some(policy) = policy() else {
+        return false;
+    };
+    if !policy.restrict_listen || addr.is_null() {
+        return false;
+    }
+
+    let len = len as usize;
+    let port = match c_int::from(unsafe { (*addr).sa_family }) {
+        libc::AF_INET if len >= size_of::<libc::sockaddr_in>() => {
+            u16::from_be(unsafe { &*addr.cast::<libc::sockaddr_in>() }.sin_port)
+        }
+        libc::AF_INET6 if len >= size_of::<libc::sockaddr_in6>() => {
+            u16::from_be(unsafe { &*addr.cast::<libc::sockaddr_in6>() }.sin6_port)
+        }
+        // Unix sockets and others have no port
+        _ => return false,
+    };
+
+    !policy.allows_bind(port)
+}
+
+/// Remember the addresses of an allowed host so they can be connected to
+///
+/// # Safety
+/// `res` must be a list returned by getaddrinfo.
+unsafe fn record_addresses(mut res: *const addrinfo) {
+    let Ok(mut resolved) = RESOLVED.lock() else {
+        return;
+    };
+    while !res.is_null() {
+        let info = unsafe { &*res };
+        if !info.ai_addr.is_null() {
+            match info.ai_family {
+                libc::AF_INET => {
+                    let addr = unsafe { &*info.ai_addr.cast::<libc::sockaddr_in>() };
+                    resolved.insert(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                        addr.sin_addr.s_addr,
+                    ))));
+                }
+                libc::AF_INET6 => {
+                    let addr = unsafe { &*info.ai_addr.cast::<libc::sockaddr_in6>() };
+                    resolved.insert(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+                }
+                _ => {}
+            }
+        }
+        res = info.ai_next;
+    }
+}
+
+type ConnectFn = unsafe extern "C" fn(c_int, *const sockaddr, socklen_t) -> c_int;
+type SocketFn = unsafe extern "C" fn(c_int, c_int, c_int) -> c_int;
+type BindFn = unsafe extern "C" fn(c_int, *const sockaddr, socklen_t) -> c_int;
+type GetaddrinfoFn = unsafe extern "C" fn(
+    *const c_char,
+    *const c_char,
+    *const addrinfo,
+    *mut *mut addrinfo,
+) -> c_int;
+type OpenFn = unsafe extern "C" fn(*const c_char, c_int, mode_t) -> c_int;
+type OpenatFn = unsafe extern "C" fn(c_int, *const c_char, c_int, mode_t) -> c_int;
+type FopenFn = unsafe extern "C" fn(*const c_char, *const c_char) -> *mut FILE;
+
+/// # Safety
+/// Same contract as connect(2).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn connect(fd: c_int, addr: *const sockaddr, len: socklen_t) -> c_int {
+    if unsafe { connect_denied(addr, len) } {
+        let errno = policy().and_then(|policy| policy.connect_errno);
+        set_errno(errno.unwrap_or(DENIED_ERRNO));
+        return -1;
+    }
+    match real!("connect", ConnectFn) {
+        Some(real) => unsafe { real(fd, addr, len) },
+        None => {
+            set_errno(libc::ENOSYS);
+            -1
+        }
+    }
+}
+
+/// # Safety
+/// Same contract as socket(2).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int {
+    if policy().is_some_and(|policy| !policy.allows_socket(domain, protocol)) {
+        set_errno(DENIED_ERRNO);
+        return -1;
+    }
+    match real!("socket", SocketFn) {
+        Some(real) => unsafe { real(domain, ty, protocol) },
+        None => {
+            set_errno(libc::ENOSYS);
+            -1
+        }
+    }
+}
+
+/// # Safety
+/// Same contract as bind(2).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bind(fd: c_int, addr: *const sockaddr, len: socklen_t) -> c_int {
+    if unsafe { bind_denied(addr, len) } {
+        set_errno(DENIED_ERRNO);
+        return -1;
+    }
+    match real!("bind", BindFn) {
+        Some(real) => unsafe { real(fd, addr, len) },
+        None => {
+            set_errno(libc::ENOSYS);
+            -1
+        }
+    }
+}
+
+/// # Safety
+/// Same contract as getaddrinfo(3).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn getaddrinfo(
+    node: *const c_char,
+    service: *const c_char,
+    hints: *const addrinfo,
+    res: *mut *mut addrinfo,
+) -> c_int {
+    let Some(real) = real!("getaddrinfo", GetaddrinfoFn) else {
+        return libc::EAI_SYSTEM;
+    };
+    let Some(policy) = policy().filter(|policy| policy.restrict_network) else {
+        return unsafe { real(node, service, hints, res) };
+    };
+    if node.is_null() {
+        return unsafe { real(node, service, hints, res) };
+    }
+
+    let host = unsafe { CStr::from_ptr(node) }.to_string_lossy();
+    if !policy.allows_host(&host) {
+        return libc::EAI_NONAME;
+    }
+    let ret = unsafe { real(node, service, hints, res) };
+    if ret == 0 && !res.is_null() {
+        unsafe { record_addresses(*res) };
+    }
+    ret
+}
+
+/// # Safety
+/// Same contract as open(2).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn open(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    let real = real!("open", OpenFn);
+    unsafe { open_with(real, path, flags, mode) }
+}
+
+/// # Safety
+/// Same contract as open(2).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn open64(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    let real = real!("open64", OpenFn);
+    unsafe { open_with(real, path, flags, mode) }
+}
+
+/// # Safety
+/// Same contract as openat(2).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn openat(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    let real = real!("openat", OpenatFn);
+    unsafe { openat_with(real, dirfd, path, flags, mode) }
+}
+
+/// # Safety
+/// Same contract as openat(2).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn openat64(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    let real = real!("openat64", OpenatFn);
+    unsafe { openat_with(real, dirfd, path, flags, mode) }
+}
+
+/// # Safety
+/// Same contract as fopen(3).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fopen(path: *const c_char, mode: *const c_char) -> *mut FILE {
+    let real = real!("fopen", FopenFn);
+    unsafe { fopen_with(real, path, mode) }
+}
+
+/// # Safety
+/// Same contract as fopen(3).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fopen64(path: *const c_char, mode: *const c_char) -> *mut FILE {
+    let real = real!("fopen64", FopenFn);
+    unsafe { fopen_with(real, path, mode) }
+}
+
+unsafe fn open_with(
+    real: Option<OpenFn>,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    if unsafe { path_denied(libc::AT_FDCWD, path, access_from_flags(flags)) } {
+        set_errno(DENIED_ERRNO);
+        return -1;
+    }
+    match real {
+        Some(real) => unsafe { real(path, flags, mode) },
+        None => {
+            set_errno(libc::ENOSYS);
+            -1
+        }
+    }
+}
+
+unsafe fn openat_with(
+    real: Option<OpenatFn>,
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    if unsafe { path_denied(dirfd, path, access_from_flags(flags)) } {
+        set_errno(DENIED_ERRNO);
+        return -1;
+    }
+    match real {
+        Some(real) => unsafe { real(dirfd, path, flags, mode) },
+        None => {
+            set_errno(libc::ENOSYS);
+            -1
+        }
+    }
+}
+
+unsafe fn fopen_with(real: Option<FopenFn>, path: *const c_char, mode: *const c_char) -> *mut FILE {
+    let access = if mode.is_null() {
+        0
+    } else {
+        access_from_mode(&unsafe { CStr::from_ptr(mode) }.to_string_lossy())
+    };
+    if unsafe { path_denied(libc::AT_FDCWD, path, access) } {
+        set_errno(DENIED_ERRNO);
+        return std::ptr::null_mut();
+    }
+    match real {
+        Some(real) => unsafe { real(path, mode) },
+        None => {
+            set_errno(libc::ENOSYS);
+            std::ptr::null_mut()
+        }
+    }
+}
@@ -0,0 +1,13 @@
+//! LD_PRELOAD shim enforcing a mori policy in userspace
+//!
+//! mori loads this library into the command when the kernel supports neither
+//! cgroup sock_addr programs nor the BPF LSM. It intercepts `getaddrinfo`,
+//! `connect` and the `open`/`fopen` family and applies the policy passed in
+//! `MORI_PRELOAD_POLICY`. This is much weaker than the eBPF backend: statically
+//! linked programs, direct system calls and other libc entry points bypass it.
+
+#[cfg(target_os = "linux")]
+mod policy;
+
+#[cfg(all(target_os = "linux", not(test)))]
+mod hooks;
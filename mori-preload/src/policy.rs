@@ -0,0 +1,419 @@
+use std::{
+    net::IpAddr,
+    path::{Component, Path, PathBuf},
+};
+
+pub const ACCESS_READ: u8 = 1;
+pub const ACCESS_WRITE: u8 = 2;
+
+/// Policy enforced by the shim
+///
+/// mori encodes it one rule per line:
+///
+/// ```text
+/// restrict-network
+/// connect-errno 111
+/// deny-localhost
+/// host example.com
+/// host-suffix .corp.internal
+/// net 192.0.2.0/24
+/// deny-family 17
+/// deny-icmp
+/// deny-abstract
+/// allow-abstract /tmp/.X11-unix/X0
+/// deny-abstract-name dbus
+/// restrict-listen
+/// listen 8080
+/// deny-read /home/user/.ssh/id_ed25519
+/// deny-write /etc/hosts
+/// deny-read-write /home/user/.aws/credentials
+/// allow-read /usr
+/// allow-read-write /work/project
+/// ```
+///
+/// Without `restrict-network` all connections are allowed. Denied connections
+/// fail with the `connect-errno` value, EPERM by default. Loopback addresses
+/// are connectable unless `deny-localhost` is given. Without
+/// `restrict-listen` sockets may be bound to any port. Abstract unix socket
+/// names are given without their leading NUL byte. With any `allow-*` path,
+/// only those paths and everything below them may be opened.
+#[derive(Debug, Default, PartialEq)]
+pub struct ShimPolicy {
+    pub restrict_network: bool,
+    /// errno denied connections fail with (EPERM if unset)
+    pub connect_errno: Option<i32>,
+    /// Whether loopback addresses need a `net` rule like any other
+    pub deny_localhost: bool,
+    /// Host names that may be resolved; their addresses become connectable
+    pub hosts: Vec<String>,
+    /// Suffixes (`.corp.internal`) of further host names that may be resolved
+    pub host_suffixes: Vec<String>,
+    /// Networks that may be connected to
+    pub networks: Vec<(IpAddr, u8)>,
+    /// Address families socket(2) refuses
+    pub denied_families: Vec<i32>,
+    /// Whether socket(2) refuses ICMP sockets
+    pub deny_icmp: bool,
+    /// Whether abstract unix socket names missing from `allowed_abstract` are denied
+    pub deny_abstract: bool,
+    pub allowed_abstract: Vec<Vec<u8>>,
+    /// Abstract unix socket names that are always denied
+    pub denied_abstract: Vec<Vec<u8>>,
+    pub restrict_listen: bool,
+    /// Ports sockets may be bound to
+    pub listen_ports: Vec<u16>,
+    /// Denied paths with ACCESS_* bits
+    pub denied_paths: Vec<(PathBuf, u8)>,
+    /// Paths allowed in allow-list mode with ACCESS_* bits
+    pub allowed_paths: Vec<(PathBuf, u8)>,
+}
+
+impl ShimPolicy {
+    /// Decode the policy, ignoring lines it does not understand
+    pub fn parse(encoded: &str) -> Self {
+        let mut policy = Self::default();
+        for line in encoded.lines() {
+            let (keyword, value) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "restrict-network" => policy.restrict_network = true,
+                "connect-errno" => policy.connect_errno = value.parse().ok(),
+                "deny-localhost" => policy.deny_localhost = true,
+                "host" => policy.hosts.push(normalize_host(value)),
+                "host-suffix" => policy.host_suffixes.push(normalize_host(value)),
+                "net" => {
+                    if let Some(network) = parse_network(value) {
+                        policy.networks.push(network);
+                    }
+                }
+                "deny-family" => {
+                    if let Ok(family) = value.parse() {
+                        policy.denied_families.push(family);
+                    }
+                }
+                "deny-icmp" => policy.deny_icmp = true,
+                "deny-abstract" => policy.deny_abstract = true,
+                "allow-abstract" => policy.allowed_abstract.push(value.as_bytes().to_vec()),
+                "deny-abstract-name" => policy.denied_abstract.push(value.as_bytes().to_vec()),
+                "restrict-listen" => policy.restrict_listen = true,
+                "listen" => {
+                    if let Ok(port) = value.parse() {
+                        policy.listen_ports.push(port);
+                    }
+                }
+                "deny-read" => policy.deny(value, ACCESS_READ),
+                "deny-write" => policy.deny(value, ACCESS_WRITE),
+                "deny-read-write" => policy.deny(value, ACCESS_READ | ACCESS_WRITE),
+                "allow-read" => policy.allow(value, ACCESS_READ),
+                "allow-write" => policy.allow(value, ACCESS_WRITE),
+                "allow-read-write" => policy.allow(value, ACCESS_READ | ACCESS_WRITE),
+                _ => {}
+            }
+        }
+        policy
+    }
+
+    fn deny(&mut self, path: &str, access: u8) {
+        if !path.is_empty() {
+            self.denied_paths.push((PathBuf::from(path), access));
+        }
+    }
+
+    fn allow(&mut self, path: &str, access: u8) {
+        if !path.is_empty() {
+            self.allowed_paths.push((PathBuf::from(path), access));
+        }
+    }
+
+    /// Check if `host` may be resolved
+    pub fn allows_host(&self, host: &str) -> bool {
+        if !self.restrict_network || host.parse::<IpAddr>().is_ok() {
+            return true;
+        }
+        let host = normalize_host(host);
+        host == "localhost"
+            || self.hosts.contains(&host)
+            || self.host_suffixes.iter().any(|suffix| {
+                host.ends_with(suffix.as_str()) || suffix.strip_prefix('.') == Some(host.as_str())
+            })
+    }
+
+    /// Check if `addr` is allowed by the static rules (loopback or a listed network)
+    pub fn allows_addr(&self, addr: IpAddr) -> bool {
+        !self.restrict_network
+            || (addr.is_loopback() && !self.deny_localhost)
+            || self
+                .networks
+                .iter()
+                .any(|&(network, prefix_len)| in_network(addr, network, prefix_len))
+    }
+
+    /// Check if a socket may be bound to `port`; port 0 asks the kernel for an
+    /// ephemeral port, which clients do before connecting
+    pub fn allows_bind(&self, port: u16) -> bool {
+        !self.restrict_listen || port == 0 || self.listen_ports.contains(&port)
+    }
+
+    /// Check if connecting to the abstract unix socket `name` is allowed
+    pub fn allows_abstract(&self, name: &[u8]) -> bool {
+        if self.denied_abstract.iter().any(|denied| denied == name) {
+            return false;
+        }
+        !self.deny_abstract || self.allowed_abstract.iter().any(|allowed| allowed == name)
+    }
+
+    /// Check if a socket of `domain` for `protocol` may be created
+    pub fn allows_socket(&self, domain: i32, protocol: i32) -> bool {
+        let icmp = (domain == libc::AF_INET && protocol == libc::IPPROTO_ICMP)
+            || (domain == libc::AF_INET6 && protocol == libc::IPPROTO_ICMPV6);
+        let denied_icmp = icmp && self.deny_icmp;
+        !denied_icmp && !self.denied_families.contains(&domain)
+    }
+
+    /// Check if opening `path` (absolute, normalized) with `access` bits is denied,
+    /// by its own rule or the rule of a directory above it, or by missing from
+    /// the allowed paths
+    pub fn denies_path(&self, path: &Path, access: u8) -> bool {
+        if !self.allowed_paths.is_empty() {
+            let allowed = self
+                .allowed_paths
+                .iter()
+                .filter(|(allowed, _)| path.starts_with(allowed))
+                .fold(0, |bits, (_, allowed_access)| bits | allowed_access);
+            if access & !allowed != 0 {
+                return true;
+            }
+        }
+        self.denied_paths
+            .iter()
+            .any(|(denied, denied_access)| path.starts_with(denied) && denied_access & access != 0)
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Parse "addr" or "addr/prefix"
+fn parse_network(value: &str) -> Option<(IpAddr, u8)> {
+    match value.split_once('/') {
+        Some((addr, prefix_len)) => Some((addr.parse().ok()?, prefix_len.parse().ok()?)),
+        None => {
+            let addr: IpAddr = value.parse().ok()?;
+            let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+            Some((addr, prefix_len))
+        }
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len.min(32)));
+            let mask = mask.unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix_len.min(128)));
+            let mask = mask.unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Access bits requested by open(2) flags
+pub fn access_from_flags(flags: i32) -> u8 {
+    let mut access = match flags & libc::O_ACCMODE {
+        libc::O_WRONLY => ACCESS_WRITE,
+        libc::O_RDWR => ACCESS_READ | ACCESS_WRITE,
+        _ => ACCESS_READ,
+    };
+    if flags & (libc::O_CREAT | libc::O_TRUNC | libc::O_APPEND) != 0 {
+        access |= ACCESS_WRITE;
+    }
+    access
+}
+
+/// Access bits requested by an fopen(3) mode string
+pub fn access_from_mode(mode: &str) -> u8 {
+    let access = match mode.as_bytes().first() {
+        Some(b'r') => ACCESS_READ,
+        _ => ACCESS_WRITE,
+    };
+    if mode.contains('+') {
+        ACCESS_READ | ACCESS_WRITE
+    } else {
+        access
+    }
+}
+
+/// Join `path` onto `base` and resolve `.` and `..` lexically
+pub fn absolute_path(base: &Path, path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in base.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::CurDir => {}
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODED: &str = "\
+restrict-network
+connect-errno 111
+host Example.COM.
+host-suffix .Corp.Internal
+net 192.0.2.0/24
+net 2001:db8::1
+deny-family 17
+deny-icmp
+deny-abstract
+allow-abstract /tmp/.X11-unix/X0
+deny-abstract-name dbus
+restrict-listen
+listen 8080
+deny-read /home/user/.ssh/id_ed25519
+deny-read-write /home/user/.aws/credentials
+unknown rule
+";
+
+    #[test]
+    fn parse_reads_all_rules() {
+        let policy = ShimPolicy::parse(ENCODED);
+        assert!(policy.restrict_network);
+        assert_eq!(policy.connect_errno, Some(111));
+        assert_eq!(policy.hosts, vec!["example.com"]);
+        assert_eq!(policy.host_suffixes, vec![".corp.internal"]);
+        assert_eq!(
+            policy.networks,
+            vec![
+                ("192.0.2.0".parse().unwrap(), 24),
+                ("2001:db8::1".parse().unwrap(), 128),
+            ]
+        );
+        assert_eq!(policy.denied_families, vec![17]);
+        assert!(policy.deny_icmp);
+        assert_eq!(policy.listen_ports, vec![8080]);
+        assert_eq!(policy.denied_paths.len(), 2);
+    }
+
+    #[test]
+    fn allows_socket_refuses_denied_families_and_icmp() {
+        let policy = ShimPolicy::parse(ENCODED);
+        assert!(policy.allows_socket(libc::AF_INET, libc::IPPROTO_TCP));
+        assert!(!policy.allows_socket(libc::AF_PACKET, 0));
+        assert!(!policy.allows_socket(libc::AF_INET, libc::IPPROTO_ICMP));
+        assert!(!policy.allows_socket(libc::AF_INET6, libc::IPPROTO_ICMPV6));
+    }
+
+    #[test]
+    fn allows_abstract_follows_name_lists() {
+        let policy = ShimPolicy::parse(ENCODED);
+        assert!(policy.allows_abstract(b"/tmp/.X11-unix/X0"));
+        assert!(!policy.allows_abstract(b"dbus"));
+        assert!(!policy.allows_abstract(b"other"));
+        assert!(ShimPolicy::default().allows_abstract(b"other"));
+    }
+
+    #[test]
+    fn allows_bind_follows_listen_ports() {
+        let policy = ShimPolicy::parse(ENCODED);
+        assert!(policy.allows_bind(8080));
+        assert!(policy.allows_bind(0));
+        assert!(!policy.allows_bind(22));
+        assert!(ShimPolicy::parse("restrict-network\n").allows_bind(22));
+    }
+
+    #[test]
+    fn allows_host_and_addr_follow_rules() {
+        let policy = ShimPolicy::parse(ENCODED);
+        assert!(policy.allows_host("example.com"));
+        assert!(policy.allows_host("localhost"));
+        assert!(policy.allows_host("198.51.100.1"));
+        assert!(!policy.allows_host("evil.example"));
+        assert!(policy.allows_host("git.corp.internal"));
+        assert!(policy.allows_host("Corp.Internal."));
+        assert!(!policy.allows_host("notcorp.internal"));
+
+        assert!(policy.allows_addr("192.0.2.200".parse().unwrap()));
+        assert!(policy.allows_addr("127.0.0.1".parse().unwrap()));
+        assert!(policy.allows_addr("::1".parse().unwrap()));
+        assert!(!policy.allows_addr("192.0.3.1".parse().unwrap()));
+        assert!(!policy.allows_addr("2001:db8::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn deny_localhost_needs_loopback_rules() {
+        let policy = ShimPolicy::parse("restrict-network\ndeny-localhost\nnet 127.0.0.1/32\n");
+        assert!(policy.deny_localhost);
+        assert!(policy.allows_addr("127.0.0.1".parse().unwrap()));
+        assert!(!policy.allows_addr("127.0.0.2".parse().unwrap()));
+        assert!(!policy.allows_addr("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn unrestricted_network_allows_everything() {
+        let policy = ShimPolicy::parse("deny-write /etc/hosts\n");
+        assert!(policy.allows_host("evil.example"));
+        assert!(policy.allows_addr("203.0.113.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn denies_path_matches_access() {
+        let policy = ShimPolicy::parse(ENCODED);
+        let key = Path::new("/home/user/.ssh/id_ed25519");
+        assert!(policy.denies_path(key, ACCESS_READ));
+        assert!(!policy.denies_path(key, ACCESS_WRITE));
+        assert!(policy.denies_path(Path::new("/home/user/.aws/credentials"), ACCESS_WRITE));
+        assert!(!policy.denies_path(Path::new("/home/user/.ssh"), ACCESS_READ));
+        assert!(policy.denies_path(Path::new("/home/user/.aws/credentials/extra"), ACCESS_READ));
+        assert!(!policy.denies_path(Path::new("/home/user/.aws/credentials2"), ACCESS_READ));
+    }
+
+    #[test]
+    fn denies_path_outside_allowed_paths() {
+        let policy =
+            ShimPolicy::parse("allow-read /usr\nallow-read-write /work\ndeny-read /work/.env\n");
+        assert!(!policy.denies_path(Path::new("/usr/lib/libc.so.6"), ACCESS_READ));
+        assert!(policy.denies_path(Path::new("/usr/lib/libc.so.6"), ACCESS_WRITE));
+        assert!(!policy.denies_path(Path::new("/work/src/main.rs"), ACCESS_READ | ACCESS_WRITE));
+        assert!(policy.denies_path(Path::new("/work/.env"), ACCESS_READ));
+        assert!(policy.denies_path(Path::new("/home/user/.bashrc"), ACCESS_READ));
+    }
+
+    #[test]
+    fn access_from_flags_and_mode() {
+        assert_eq!(access_from_flags(libc::O_RDONLY), ACCESS_READ);
+        assert_eq!(
+            access_from_flags(libc::O_WRONLY | libc::O_CREAT),
+            ACCESS_WRITE
+        );
+        assert_eq!(
+            access_from_flags(libc::O_RDONLY | libc::O_TRUNC),
+            ACCESS_READ | ACCESS_WRITE
+        );
+        assert_eq!(access_from_mode("r"), ACCESS_READ);
+        assert_eq!(access_from_mode("wb"), ACCESS_WRITE);
+        assert_eq!(access_from_mode("r+"), ACCESS_READ | ACCESS_WRITE);
+    }
+
+    #[test]
+    fn absolute_path_resolves_relative_components() {
+        assert_eq!(
+            absolute_path(Path::new("/home/user/project"), Path::new("../.ssh/./id")),
+            PathBuf::from("/home/user/.ssh/id")
+        );
+        assert_eq!(
+            absolute_path(Path::new("/tmp"), Path::new("/etc/hosts")),
+            PathBuf::from("/etc/hosts")
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::{fmt::Write as _, fs::OpenOptions, io::Write as _};
+
+use crate::error::MoriError;
+
+use super::report::AuditReport;
+
+/// CI systems a run can be reported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CiProvider {
+    /// GitHub Actions: annotations for violations and a job step summary
+    Github,
+}
+
+/// Details of a finished run shown in CI
+#[derive(Debug)]
+pub struct RunSummary<'a> {
+    /// Command line that was run
+    pub command: &'a [String],
+    pub exit_code: i32,
+    /// Whether violations were only reported instead of blocked
+    pub audit: bool,
+    /// Human readable policy rules (see `Policy::restrictions`)
+    pub restrictions: &'a [String],
+    /// Violations, if they were collected
+    pub report: Option<&'a AuditReport>,
+}
+
+/// Report a finished run to the CI system
+pub fn publish(provider: CiProvider, summary: &RunSummary) -> Result<(), MoriError> {
+    match provider {
+        CiProvider::Github => {
+            // Workflow commands are read from stdout
+            print!("{}", github_annotations(summary));
+            if let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                file.write_all(github_step_summary(summary).as_bytes())?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Render one `::warning` (audit) or `::error` (enforced) workflow command per violation
+fn github_annotations(summary: &RunSummary) -> String {
+    let Some(report) = summary.report else {
+        return String::new();
+    };
+    let (level, title, verb) = if summary.audit {
+        ("warning", "mori audit", "Would deny")
+    } else {
+        ("error", "mori", "Denied")
+    };
+
+    let mut out = String::new();
+    for (operation, target, entry) in report.iter() {
+        let comms: Vec<&str> = entry.comms.iter().map(String::as_str).collect();
+        let message = format!(
+            "{} {} {} ({}, {} time(s))",
+            verb,
+            operation,
+            target,
+            comms.join(", "),
+            entry.count
+        );
+        let _ = writeln!(
+            out,
+            "::{} title={}::{}",
+            level,
+            escape_property(title),
+            escape_data(&message)
+        );
+    }
+    out
+}
+
+/// Render the run report as Markdown for the job summary page
+fn github_step_summary(summary: &RunSummary) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "### mori: {}\n", code_span(&summary.command.join(" ")));
+    let _ = writeln!(
+        out,
+        "- Mode: {}",
+        if summary.audit { "audit" } else { "enforce" }
+    );
+    let _ = writeln!(out, "- Exit code: {}\n", summary.exit_code);
+
+    out.push_str("#### Policy\n\n");
+    if summary.restrictions.is_empty() {
+        out.push_str("- no restrictions\n");
+    }
+    for rule in summary.restrictions {
+        let _ = writeln!(out, "- {}", rule);
+    }
+
+    out.push_str("\n#### Violations\n\n");
+    match summary.report {
+        None => out.push_str("Violations are only collected with `--audit`.\n"),
+        Some(report) if report.is_empty() => out.push_str("No violations.\n"),
+        Some(report) => {
+            out.push_str("| Operation | Target | Processes | Count |\n");
+            out.push_str("| --- | --- | --- | --- |\n");
+            for (operation, target, entry) in report.iter() {
+                let comms: Vec<&str> = entry.comms.iter().map(String::as_str).collect();
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} |",
+                    operation,
+                    escape_cell(target),
+                    escape_cell(&comms.join(", ")),
+                    entry.count
+                );
+            }
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// Escape workflow command data
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a workflow command property value
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn escape_cell(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+/// Inline code that may itself contain backticks
+fn code_span(value: &str) -> String {
+    if value.contains('`') {
+        format!("`` {} ``", value)
+    } else {
+        format!("`{}`", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{Operation, ViolationEvent};
+    use rstest::rstest;
+
+    fn report() -> AuditReport {
+        let mut report = AuditReport::default();
+        for (operation, target, comm) in [
+            (Operation::Connect, "192.0.2.1:443", "curl"),
+            (Operation::Connect, "192.0.2.1:443", "curl"),
+            (Operation::FileRead, "/tmp/a|b", "cat"),
+        ] {
+            report.record(&ViolationEvent {
+                operation,
+                target: target.to_string(),
+                pid: 1,
+                comm: comm.to_string(),
+            });
+        }
+        report
+    }
+
+    fn summary<'a>(report: Option<&'a AuditReport>, audit: bool) -> RunSummary<'a> {
+        RunSummary {
+            command: &[],
+            exit_code: 0,
+            audit,
+            restrictions: &[],
+            report,
+        }
+    }
+
+    #[test]
+    fn github_annotations_warn_in_audit_mode() {
+        let report = report();
+        let annotations = github_annotations(&summary(Some(&report), true));
+        let lines: Vec<&str> = annotations.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "::warning title=mori audit::Would deny connect 192.0.2.1:443 (curl, 2 time(s))",
+                "::warning title=mori audit::Would deny file-read /tmp/a|b (cat, 1 time(s))",
+            ]
+        );
+    }
+
+    #[test]
+    fn github_annotations_error_when_enforcing() {
+        let report = report();
+        let annotations = github_annotations(&summary(Some(&report), false));
+        assert!(annotations.starts_with("::error title=mori::Denied connect"));
+        assert!(github_annotations(&summary(None, false)).is_empty());
+    }
+
+    #[test]
+    fn github_step_summary_renders_report() {
+        let report = report();
+        let command = vec!["cargo".to_string(), "build".to_string()];
+        let restrictions = vec!["network: deny all outbound connections".to_string()];
+        let markdown = github_step_summary(&RunSummary {
+            command: &command,
+            exit_code: 101,
+            audit: true,
+            restrictions: &restrictions,
+            report: Some(&report),
+        });
+
+        assert!(markdown.starts_with("### mori: `cargo build`\n"));
+        assert!(markdown.contains("- Mode: audit\n- Exit code: 101\n"));
+        assert!(markdown.contains("- network: deny all outbound connections\n"));
+        assert!(markdown.contains("| connect | 192.0.2.1:443 | curl | 2 |\n"));
+        assert!(markdown.contains("| file-read | /tmp/a\\|b | cat | 1 |\n"));
+    }
+
+    #[test]
+    fn github_step_summary_without_report() {
+        let markdown = github_step_summary(&summary(None, false));
+        assert!(markdown.contains("- no restrictions\n"));
+        assert!(markdown.contains("Violations are only collected with `--audit`."));
+    }
+
+    #[rstest]
+    #[case("plain", "plain")]
+    #[case("100%\nnext", "100%25%0Anext")]
+    fn escape_data_encodes_special_characters(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(escape_data(value), expected);
+    }
+
+    #[test]
+    fn escape_property_encodes_separators() {
+        assert_eq!(escape_property("a:b,c"), "a%3Ab%2Cc");
+    }
+}
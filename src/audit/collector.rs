@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::{
+    event::ViolationEvent,
+    explain::{Explainer, stderr_color},
+    report::AuditReport,
+};
+
+/// Channel used by enforcement backends to publish violation events
+pub type ViolationSender = mpsc::UnboundedSender<ViolationEvent>;
+
+/// Background task that receives violation events and aggregates them into a report
+pub struct ViolationCollector {
+    sender: ViolationSender,
+    handle: JoinHandle<AuditReport>,
+}
+
+impl ViolationCollector {
+    /// Spawn the collector task
+    pub fn spawn() -> Self {
+        Self::spawn_inner(None)
+    }
+
+    /// Spawn the collector task, printing an explanation of each distinct
+    /// violation to stderr as it arrives
+    pub fn spawn_explaining(explainer: Explainer) -> Self {
+        Self::spawn_inner(Some(explainer))
+    }
+
+    fn spawn_inner(explainer: Option<Explainer>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<ViolationEvent>();
+        let handle = tokio::spawn(async move {
+            let color = stderr_color();
+            let mut report = AuditReport::default();
+            let mut explained = HashSet::new();
+            while let Some(event) = receiver.recv().await {
+                log::warn!("Policy violation: {}", event);
+                if let Some(explainer) = &explainer
+                    && explained.insert((event.operation, event.target.clone()))
+                {
+                    eprintln!("{}", explainer.explain(&event).render(color));
+                }
+                report.record(&event);
+            }
+            report
+        });
+        Self { sender, handle }
+    }
+
+    /// Get a sender for publishing events to this collector
+    pub fn sender(&self) -> ViolationSender {
+        self.sender.clone()
+    }
+
+    /// Stop collecting and return the aggregated report
+    ///
+    /// Waits until every sender obtained via [`ViolationCollector::sender`] has been dropped.
+    pub async fn finish(self) -> AuditReport {
+        drop(self.sender);
+        self.handle.await.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Operation;
+
+    #[tokio::test]
+    async fn finish_returns_all_published_events() {
+        let collector = ViolationCollector::spawn();
+        let sender = collector.sender();
+        for pid in [10, 11] {
+            sender
+                .send(ViolationEvent {
+                    operation: Operation::Exec,
+                    target: "/usr/bin/curl".to_string(),
+                    pid,
+                    comm: "sh".to_string(),
+                })
+                .unwrap();
+        }
+        drop(sender);
+
+        let report = collector.finish().await;
+        assert_eq!(report.total(), 2);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// Kind of access that was (or would have been) denied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Operation {
+    /// Outbound network connection
+    Connect,
+    /// File opened for reading
+    FileRead,
+    /// File opened for writing
+    FileWrite,
+    /// Program execution
+    Exec,
+    /// Socket bound to a port the command may not listen on
+    Bind,
+    /// Socket of a denied family, or an ICMP socket
+    Socket,
+    /// TLS connection to a server name that is not allowed
+    Tls,
+    /// Connection from a peer that is not allowed
+    Accept,
+    /// Connection to an abstract unix socket
+    Unix,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::Connect => "connect",
+            Operation::FileRead => "file-read",
+            Operation::FileWrite => "file-write",
+            Operation::Exec => "exec",
+            Operation::Bind => "bind",
+            Operation::Socket => "socket",
+            Operation::Tls => "tls",
+            Operation::Accept => "accept",
+            Operation::Unix => "unix",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A single access that violated the policy
+///
+/// In enforcing mode the access was blocked; in audit mode it was allowed but
+/// would have been blocked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViolationEvent {
+    pub operation: Operation,
+    /// Destination of the access: `ip:port` for connections, the port for
+    /// binds, the family for sockets, the server name for TLS, the peer and
+    /// port for accepted connections, `@name` for unix sockets and a path
+    /// (or only the file name, from hooks that cannot resolve paths) otherwise
+    pub target: String,
+    /// PID of the process that attempted the access
+    pub pid: u32,
+    /// Command name of the process that attempted the access
+    pub comm: String,
+}
+
+impl fmt::Display for ViolationEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} by {}({})",
+            self.operation, self.target, self.comm, self.pid
+        )
+    }
+}
@@ -0,0 +1,559 @@
+use std::{
+    collections::BTreeMap,
+    io::IsTerminal,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+};
+
+use super::event::{Operation, ViolationEvent};
+use crate::policy::{AccessMode, AllowPolicy, Policy};
+
+const RED_BOLD: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Minimum number of leading bits an address must share with a domain's
+/// addresses for the domain to be named as the nearest rule
+const NEAREST_DOMAIN_MIN_PREFIX: u32 = 16;
+
+/// Why an access was denied, in terms of the policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// What was denied (`connect to 192.0.2.1:443`)
+    pub access: String,
+    /// The rule, or missing rule, responsible
+    pub reason: String,
+    /// Change to the policy that would allow the access
+    pub suggestion: Option<String>,
+}
+
+impl Explanation {
+    /// Render the explanation for stderr, with ANSI colors if `color` is set
+    pub fn render(&self, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+        let mut out = format!(
+            "mori: {} {} \u{2014} {}",
+            self.access,
+            paint(RED_BOLD, "denied"),
+            paint(YELLOW, &self.reason)
+        );
+        if let Some(suggestion) = &self.suggestion {
+            out.push_str(&format!("\n      suggestion: {}", paint(GREEN, suggestion)));
+        }
+        out
+    }
+}
+
+/// Whether explanations printed to stderr should be colored
+///
+/// Colors are used when stderr is a terminal, unless `NO_COLOR` is set.
+pub fn stderr_color() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Maps violation events back to the policy rules that caused them
+#[derive(Debug, Clone)]
+pub struct Explainer {
+    policy: Policy,
+    /// Addresses the allowed domains resolved to
+    resolved: BTreeMap<String, Vec<Ipv4Addr>>,
+}
+
+impl Explainer {
+    /// Explain events against `policy`
+    pub fn new(policy: &Policy) -> Self {
+        Self {
+            policy: policy.clone(),
+            resolved: BTreeMap::new(),
+        }
+    }
+
+    /// Record the addresses an allowed domain resolved to
+    pub fn set_resolved(&mut self, domain: &str, addrs: Vec<Ipv4Addr>) {
+        self.resolved.insert(domain.to_string(), addrs);
+    }
+
+    /// Record the addresses of every allowed and denied domain, as returned by `lookup`
+    pub fn resolve_with(&mut self, lookup: impl Fn(&str) -> Vec<Ipv4Addr>) {
+        if let AllowPolicy::Entries {
+            allowed_domains, ..
+        } = &self.policy.network.policy
+        {
+            for domain in allowed_domains {
+                self.resolved.insert(domain.clone(), lookup(domain));
+            }
+        }
+        for domain in &self.policy.network.denied.denied_domains {
+            self.resolved.insert(domain.clone(), lookup(domain));
+        }
+    }
+
+    /// Explain a denied access
+    pub fn explain(&self, event: &ViolationEvent) -> Explanation {
+        let target = &event.target;
+        match event.operation {
+            // Hooks that cannot resolve paths report the file name alone
+            Operation::FileRead | Operation::FileWrite | Operation::Exec
+                if Path::new(target).is_relative() =>
+            {
+                let verb = match event.operation {
+                    Operation::FileRead => "read",
+                    Operation::FileWrite => "write",
+                    _ => "exec",
+                };
+                Explanation {
+                    access: format!("{} of {}", verb, target),
+                    reason: "a file or exec rule covers it; the full path is unknown".to_string(),
+                    suggestion: None,
+                }
+            }
+            Operation::Connect => self.explain_connect(target),
+            Operation::FileRead => self.explain_file(Path::new(target), AccessMode::Read),
+            Operation::FileWrite => self.explain_file(Path::new(target), AccessMode::Write),
+            Operation::Exec => self.explain_exec(Path::new(target)),
+            Operation::Bind => Explanation {
+                access: format!("listening on port {}", target),
+                reason: "not an allowed listening port".to_string(),
+                suggestion: Some(format!("--allow-listen {}", target)),
+            },
+            Operation::Socket => self.explain_socket(target),
+            Operation::Tls => Explanation {
+                access: format!("TLS connection to {}", target),
+                reason: "server name is not an allowed domain (enforce_sni)".to_string(),
+                suggestion: (!target.starts_with('(')).then(|| allow_network_suggestion(target)),
+            },
+            Operation::Accept => {
+                let peer = target.split(' ').next().unwrap_or(target);
+                Explanation {
+                    access: format!("connection from {}", target),
+                    reason: "peer is not in [network.inbound] allow".to_string(),
+                    suggestion: Some(format!("add {} to [network.inbound] allow", peer)),
+                }
+            }
+            Operation::Unix => self.explain_unix(target),
+        }
+    }
+
+    fn explain_socket(&self, family: &str) -> Explanation {
+        if family == "icmp" {
+            return Explanation {
+                access: "ICMP socket".to_string(),
+                reason: "ICMP is denied while outbound connections are restricted".to_string(),
+                suggestion: Some("allow_icmp = true in [network]".to_string()),
+            };
+        }
+        let reason = match &self.policy.network.denied_families {
+            Some(_) => "matches [network] deny_families".to_string(),
+            None => "denied by default while outbound connections are restricted".to_string(),
+        };
+        Explanation {
+            access: format!("{} socket", family),
+            reason,
+            suggestion: Some(format!("leave {} out of [network] deny_families", family)),
+        }
+    }
+
+    fn explain_unix(&self, target: &str) -> Explanation {
+        let access = format!("connect to unix socket {}", target);
+        let name = target.strip_prefix('@').unwrap_or(target);
+        if self
+            .policy
+            .network
+            .unix
+            .denied_abstract
+            .iter()
+            .any(|denied| denied == name)
+        {
+            return Explanation {
+                access,
+                reason: "matches [network.unix] deny".to_string(),
+                suggestion: Some(format!("remove {} from [network.unix] deny", target)),
+            };
+        }
+        Explanation {
+            access,
+            reason: "not in [network.unix] allow (deny_abstract)".to_string(),
+            suggestion: Some(format!("add {} to [network.unix] allow", target)),
+        }
+    }
+
+    fn explain_connect(&self, target: &str) -> Explanation {
+        let access = format!("connect to {}", target);
+        let host = target
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|_| {
+                target
+                    .rsplit_once(':')
+                    .map_or(target, |(host, _)| host)
+                    .to_string()
+            });
+        let suggestion = Some(allow_network_suggestion(&host));
+
+        if let Some(denied) = self.denied_rule(&host) {
+            return Explanation {
+                access,
+                reason: format!("matches --deny-network {}", denied),
+                suggestion: Some(format!("remove {} from --deny-network", denied)),
+            };
+        }
+        let AllowPolicy::Entries {
+            allowed_domains, ..
+        } = &self.policy.network.policy
+        else {
+            return Explanation {
+                access,
+                reason: "outside the sandbox's network rules".to_string(),
+                suggestion: None,
+            };
+        };
+        if self.policy.network.is_deny_all() {
+            return Explanation {
+                access,
+                reason: "all network access is denied".to_string(),
+                suggestion,
+            };
+        }
+
+        let mut reason = "no rule matches".to_string();
+        if let Ok(ip) = host.parse::<Ipv4Addr>()
+            && let Some((domain, addrs)) = self.nearest_domain(ip, allowed_domains)
+        {
+            let addrs: Vec<String> = addrs.iter().map(Ipv4Addr::to_string).collect();
+            reason.push_str(&format!(
+                "; nearest domain rule: {} resolved to different IPs ({})",
+                domain,
+                addrs.join(", ")
+            ));
+        }
+        Explanation {
+            access,
+            reason,
+            suggestion,
+        }
+    }
+
+    /// The deny list entry matching `host`, if any
+    fn denied_rule(&self, host: &str) -> Option<String> {
+        let denied = &self.policy.network.denied;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match host.parse::<IpAddr>().ok()? {
+            IpAddr::V4(ip) => denied
+                .denied_cidr
+                .iter()
+                .find(|(network, prefix_len)| {
+                    in_network(
+                        ip.to_bits().into(),
+                        network.to_bits().into(),
+                        *prefix_len,
+                        32,
+                    )
+                })
+                .map(|(network, prefix_len)| format!("{}/{}", network, prefix_len))
+                .or_else(|| {
+                    denied
+                        .denied_domains
+                        .iter()
+                        .find(|domain| {
+                            self.resolved
+                                .get(*domain)
+                                .is_some_and(|addrs| addrs.contains(&ip))
+                        })
+                        .cloned()
+                }),
+            IpAddr::V6(ip) => denied
+                .denied_cidr_v6
+                .iter()
+                .find(|(network, prefix_len)| {
+                    in_network(ip.to_bits(), network.to_bits(), *prefix_len, 128)
+                })
+                .map(|(network, prefix_len)| format!("{}/{}", network, prefix_len)),
+        }
+    }
+
+    /// The allowed domain whose addresses share the longest prefix with `ip`
+    fn nearest_domain(
+        &self,
+        ip: Ipv4Addr,
+        allowed_domains: &[String],
+    ) -> Option<(&str, &[Ipv4Addr])> {
+        allowed_domains
+            .iter()
+            .filter_map(|domain| {
+                let (domain, addrs) = self.resolved.get_key_value(domain)?;
+                let prefix = addrs
+                    .iter()
+                    .map(|addr| (addr.to_bits() ^ ip.to_bits()).leading_zeros())
+                    .max()?;
+                Some((prefix, domain.as_str(), addrs.as_slice()))
+            })
+            .filter(|(prefix, _, _)| *prefix >= NEAREST_DOMAIN_MIN_PREFIX)
+            .max_by_key(|(prefix, _, _)| *prefix)
+            .map(|(_, domain, addrs)| (domain, addrs))
+    }
+
+    fn explain_file(&self, path: &Path, access: AccessMode) -> Explanation {
+        let verb = match access {
+            AccessMode::Read => "read",
+            AccessMode::Write | AccessMode::ReadWrite => "write",
+            AccessMode::Execute => "execute",
+        };
+        let rule =
+            self.policy.file.denied_paths.iter().find(|(denied, mode)| {
+                path.starts_with(denied) && (*mode as u8) & (access as u8) != 0
+            });
+        let Some((denied, mode)) = rule else {
+            if !self.policy.file.allows(path, access) {
+                return Explanation {
+                    access: format!("{} of {}", verb, path.display()),
+                    reason: "outside the [file] allow list".to_string(),
+                    suggestion: Some(format!("add {} to [file] allow", path.display())),
+                };
+            }
+            return Explanation {
+                access: format!("{} of {}", verb, path.display()),
+                reason: "outside the sandbox's file rules".to_string(),
+                suggestion: None,
+            };
+        };
+        let flag = match mode {
+            AccessMode::Read => "--deny-file-read",
+            AccessMode::Write => "--deny-file-write",
+            AccessMode::ReadWrite => "--deny-file",
+            AccessMode::Execute => "[file] deny_execute",
+        };
+        Explanation {
+            access: format!("{} of {}", verb, path.display()),
+            reason: format!("matches {} {}", flag, denied.display()),
+            suggestion: Some(format!("remove {} from {}", denied.display(), flag)),
+        }
+    }
+
+    fn explain_exec(&self, program: &Path) -> Explanation {
+        let access = format!("exec of {}", program.display());
+        let process = &self.policy.process;
+        if let Some(denied) = process
+            .denied_exec
+            .iter()
+            .find(|denied| program.starts_with(denied))
+        {
+            return Explanation {
+                access,
+                reason: format!("matches --deny-exec {}", denied.display()),
+                suggestion: Some(format!("remove {} from --deny-exec", denied.display())),
+            };
+        }
+        if let Some((denied, _)) = self
+            .policy
+            .file
+            .denied_paths
+            .iter()
+            .find(|(denied, mode)| *mode == AccessMode::Execute && program.starts_with(denied))
+        {
+            return Explanation {
+                access,
+                reason: format!("matches [file] deny_execute {}", denied.display()),
+                suggestion: Some(format!(
+                    "remove {} from [file] deny_execute",
+                    denied.display()
+                )),
+            };
+        }
+        if !process.allowed_exec.is_empty() {
+            return Explanation {
+                access,
+                reason: "not in the --allow-exec-only list".to_string(),
+                suggestion: Some(format!("--allow-exec-only {}", program.display())),
+            };
+        }
+        Explanation {
+            access,
+            reason: "outside the sandbox's exec rules".to_string(),
+            suggestion: None,
+        }
+    }
+}
+
+/// Whether the `bits`-wide address `addr` is in `network`/`prefix_len`
+fn in_network(addr: u128, network: u128, prefix_len: u8, bits: u32) -> bool {
+    let host_bits = bits - u32::from(prefix_len);
+    (addr ^ network).checked_shr(host_bits).unwrap_or(0) == 0
+}
+
+/// Command line option that would allow connecting to `host`
+#[cfg(not(target_os = "macos"))]
+fn allow_network_suggestion(host: &str) -> String {
+    format!("--allow-network {}", host)
+}
+
+/// Command line option that would allow connecting to `host`
+///
+/// sandbox-exec cannot filter by destination, so only allowing everything helps.
+#[cfg(target_os = "macos")]
+fn allow_network_suggestion(_host: &str) -> String {
+    "--allow-network-all".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{DenyList, NetworkPolicy, UnixPolicy};
+
+    fn event(operation: Operation, target: &str) -> ViolationEvent {
+        ViolationEvent {
+            operation,
+            target: target.to_string(),
+            pid: 100,
+            comm: "curl".to_string(),
+        }
+    }
+
+    fn explainer() -> Explainer {
+        let mut policy = Policy::with_network(
+            NetworkPolicy::from_entries(&["github.com".to_string(), "example.com".to_string()])
+                .unwrap(),
+        );
+        policy.file.deny_read("/home/user/.ssh");
+        policy.process.deny_exec("/usr/bin/wget");
+        let mut explainer = Explainer::new(&policy);
+        explainer.set_resolved("github.com", vec![Ipv4Addr::new(140, 82, 112, 4)]);
+        explainer.set_resolved("example.com", vec![Ipv4Addr::new(93, 184, 216, 34)]);
+        explainer
+    }
+
+    #[test]
+    fn explain_connect_names_nearest_domain() {
+        let explanation = explainer().explain(&event(Operation::Connect, "140.82.121.4:443"));
+        assert_eq!(
+            explanation.render(false).lines().next().unwrap(),
+            "mori: connect to 140.82.121.4:443 denied \u{2014} no rule matches; nearest domain rule: github.com resolved to different IPs (140.82.112.4)"
+        );
+        #[cfg(not(target_os = "macos"))]
+        assert_eq!(
+            explanation.suggestion.as_deref(),
+            Some("--allow-network 140.82.121.4")
+        );
+
+        let unrelated = explainer().explain(&event(Operation::Connect, "198.51.100.1:80"));
+        assert_eq!(unrelated.reason, "no rule matches");
+    }
+
+    #[test]
+    fn explain_connect_with_network_denied() {
+        let explanation =
+            Explainer::new(&Policy::new()).explain(&event(Operation::Connect, "192.0.2.1:443"));
+        assert_eq!(explanation.reason, "all network access is denied");
+        #[cfg(not(target_os = "macos"))]
+        let expected = "--allow-network 192.0.2.1";
+        #[cfg(target_os = "macos")]
+        let expected = "--allow-network-all";
+        assert_eq!(explanation.suggestion.as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn explain_connect_names_deny_list_entry() {
+        let mut network = NetworkPolicy::from_allow_all(true);
+        network.denied =
+            DenyList::from_entries(&["10.0.0.0/8".to_string(), "metadata.internal".to_string()])
+                .unwrap();
+        let mut explainer = Explainer::new(&Policy::with_network(network));
+        explainer.resolve_with(|_| vec![Ipv4Addr::new(169, 254, 169, 254)]);
+
+        let explanation = explainer.explain(&event(Operation::Connect, "10.1.2.3:443"));
+        assert_eq!(explanation.reason, "matches --deny-network 10.0.0.0/8");
+        assert_eq!(
+            explanation.suggestion.as_deref(),
+            Some("remove 10.0.0.0/8 from --deny-network")
+        );
+
+        let explanation = explainer.explain(&event(Operation::Connect, "169.254.169.254:80"));
+        assert_eq!(
+            explanation.reason,
+            "matches --deny-network metadata.internal"
+        );
+    }
+
+    #[test]
+    fn explain_file_and_exec_name_the_rule() {
+        let explainer = explainer();
+
+        let file = explainer.explain(&event(Operation::FileRead, "/home/user/.ssh/id_ed25519"));
+        assert_eq!(file.access, "read of /home/user/.ssh/id_ed25519");
+        assert_eq!(file.reason, "matches --deny-file-read /home/user/.ssh");
+
+        let exec = explainer.explain(&event(Operation::Exec, "/usr/bin/wget"));
+        assert_eq!(exec.reason, "matches --deny-exec /usr/bin/wget");
+
+        let mut policy = Policy::new();
+        policy.file.deny_execute("/tmp");
+        let exec = Explainer::new(&policy).explain(&event(Operation::Exec, "/tmp/a.out"));
+        assert_eq!(exec.reason, "matches [file] deny_execute /tmp");
+
+        let mut policy = Policy::new();
+        policy.process.allow_exec("/usr/bin/make");
+        let exec = Explainer::new(&policy).explain(&event(Operation::Exec, "/usr/bin/cc"));
+        assert_eq!(
+            exec.suggestion.as_deref(),
+            Some("--allow-exec-only /usr/bin/cc")
+        );
+
+        let mut policy = Policy::new();
+        policy.file.allow("/work");
+        let file = Explainer::new(&policy).explain(&event(Operation::FileRead, "/data/input.csv"));
+        assert_eq!(file.reason, "outside the [file] allow list");
+        assert_eq!(
+            file.suggestion.as_deref(),
+            Some("add /data/input.csv to [file] allow")
+        );
+
+        // Hooks without bpf_d_path only report the file name
+        let file = explainer.explain(&event(Operation::FileWrite, "config.toml"));
+        assert_eq!(file.access, "write of config.toml");
+        assert_eq!(file.suggestion, None);
+    }
+
+    #[test]
+    fn explain_socket_denials_name_the_setting() {
+        let bind = explainer().explain(&event(Operation::Bind, "8080"));
+        assert_eq!(bind.suggestion.as_deref(), Some("--allow-listen 8080"));
+
+        let icmp = explainer().explain(&event(Operation::Socket, "icmp"));
+        assert_eq!(
+            icmp.suggestion.as_deref(),
+            Some("allow_icmp = true in [network]")
+        );
+
+        let mut policy = Policy::new();
+        policy.network.unix =
+            UnixPolicy::from_entries(true, &[], &["@/tmp/.X11-unix/X0".to_string()]).unwrap();
+        let explainer = Explainer::new(&policy);
+        let denied = explainer.explain(&event(Operation::Unix, "@/tmp/.X11-unix/X0"));
+        assert_eq!(denied.reason, "matches [network.unix] deny");
+        let unlisted = explainer.explain(&event(Operation::Unix, "@dbus"));
+        assert_eq!(
+            unlisted.suggestion.as_deref(),
+            Some("add @dbus to [network.unix] allow")
+        );
+
+        let accept = explainer.explain(&event(Operation::Accept, "198.51.100.7 to port 22"));
+        assert_eq!(
+            accept.suggestion.as_deref(),
+            Some("add 198.51.100.7 to [network.inbound] allow")
+        );
+    }
+
+    #[test]
+    fn render_colors_verdict_and_suggestion() {
+        let explanation = explainer().explain(&event(Operation::Exec, "/usr/bin/wget"));
+        let rendered = explanation.render(true);
+        assert!(rendered.contains("\x1b[1;31mdenied\x1b[0m"));
+        assert!(rendered.contains("\x1b[32mremove /usr/bin/wget from --deny-exec\x1b[0m"));
+    }
+}
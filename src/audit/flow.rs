@@ -0,0 +1,237 @@
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Output formats of the flow log
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FlowLogFormat {
+    /// One JSON object per connection attempt
+    #[default]
+    Jsonl,
+    /// Fixed-width columns in the style of NetFlow collectors (nfdump)
+    Netflow,
+}
+
+/// Whether a connection attempt was let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowVerdict {
+    Allowed,
+    Denied,
+}
+
+impl FlowVerdict {
+    fn as_str(self) -> &'static str {
+        match self {
+            FlowVerdict::Allowed => "allowed",
+            FlowVerdict::Denied => "denied",
+        }
+    }
+}
+
+/// A single connection attempt made by the sandboxed command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowRecord {
+    pub timestamp: SystemTime,
+    /// PID (thread group ID) of the process that connected
+    pub pid: u32,
+    /// Command name of the process that connected
+    pub comm: String,
+    /// IP protocol number (6 = TCP, 17 = UDP)
+    pub protocol: u8,
+    /// Local address; the unspecified address and port 0 when the socket
+    /// was not bound yet
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub verdict: FlowVerdict,
+    /// Policy rule that decided the verdict (e.g. `domain example.com`)
+    pub rule: String,
+    /// PTR name of the destination, looked up for denied connections with
+    /// `--reverse-dns`
+    pub ptr: Option<String>,
+}
+
+impl FlowRecord {
+    fn protocol_name(&self) -> String {
+        match self.protocol {
+            6 => "TCP".to_string(),
+            17 => "UDP".to_string(),
+            other => other.to_string(),
+        }
+    }
+
+    /// Render the record as a single line of JSON
+    pub fn to_json(&self) -> String {
+        let mut json = serde_json::json!({
+            "timestamp": format_timestamp(self.timestamp),
+            "pid": self.pid,
+            "comm": self.comm,
+            "protocol": self.protocol_name(),
+            "src_addr": self.source.ip().to_string(),
+            "src_port": self.source.port(),
+            "dst_addr": self.destination.ip().to_string(),
+            "dst_port": self.destination.port(),
+            "verdict": self.verdict.as_str(),
+            "rule": self.rule,
+        });
+        if let Some(ptr) = &self.ptr {
+            json["ptr"] = ptr.as_str().into();
+        }
+        json.to_string()
+    }
+
+    /// The rule, followed by the PTR name of the destination if known
+    fn annotated_rule(&self) -> String {
+        match &self.ptr {
+            Some(ptr) => format!("{} (ptr {})", self.rule, ptr),
+            None => self.rule.clone(),
+        }
+    }
+}
+
+/// Render the flow log in `format`
+pub fn render_flow_log(records: &[FlowRecord], format: FlowLogFormat) -> String {
+    let mut out = String::new();
+    match format {
+        FlowLogFormat::Jsonl => {
+            for record in records {
+                let _ = writeln!(out, "{}", record.to_json());
+            }
+        }
+        FlowLogFormat::Netflow => {
+            let _ = writeln!(
+                out,
+                "{:<24} {:<5} {:<21}    {:<21} {:>7} {:<7} Rule",
+                "Date first seen",
+                "Proto",
+                "Src IP Addr:Port",
+                "Dst IP Addr:Port",
+                "PID",
+                "Verdict"
+            );
+            for record in records {
+                let _ = writeln!(
+                    out,
+                    "{:<24} {:<5} {:<21} -> {:<21} {:>7} {:<7} {}",
+                    format_timestamp(record.timestamp),
+                    record.protocol_name(),
+                    record.source.to_string(),
+                    record.destination.to_string(),
+                    record.pid,
+                    record.verdict.as_str(),
+                    record.annotated_rule()
+                );
+            }
+        }
+    }
+    out
+}
+
+/// Format a time as RFC 3339 in UTC with millisecond precision
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Convert days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn record() -> FlowRecord {
+        FlowRecord {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_760_615_445_123),
+            pid: 4242,
+            comm: "curl".to_string(),
+            protocol: 6,
+            source: "0.0.0.0:0".parse().unwrap(),
+            destination: "93.184.216.34:443".parse().unwrap(),
+            verdict: FlowVerdict::Allowed,
+            rule: "domain example.com".to_string(),
+            ptr: None,
+        }
+    }
+
+    #[test]
+    fn format_timestamp_is_rfc3339_utc() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_timestamp(record().timestamp),
+            "2025-10-16T11:50:45.123Z"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn render_flow_log_as_jsonl() {
+        let mut denied = record();
+        denied.verdict = FlowVerdict::Denied;
+        denied.rule = "no matching rule".to_string();
+
+        let log = render_flow_log(&[record(), denied], FlowLogFormat::Jsonl);
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"comm":"curl","dst_addr":"93.184.216.34","dst_port":443,"pid":4242,"protocol":"TCP","rule":"domain example.com","src_addr":"0.0.0.0","src_port":0,"timestamp":"2025-10-16T11:50:45.123Z","verdict":"allowed"}"#
+        );
+        assert!(lines[1].contains(r#""verdict":"denied""#));
+        assert!(!lines[1].contains("ptr"));
+    }
+
+    #[test]
+    fn render_flow_log_with_ptr_name() {
+        let mut denied = record();
+        denied.verdict = FlowVerdict::Denied;
+        denied.rule = "no matching rule".to_string();
+        denied.ptr = Some("lb-93-184-216-34.example.net".to_string());
+
+        let jsonl = render_flow_log(std::slice::from_ref(&denied), FlowLogFormat::Jsonl);
+        assert!(jsonl.contains(r#""ptr":"lb-93-184-216-34.example.net""#));
+        let netflow = render_flow_log(&[denied], FlowLogFormat::Netflow);
+        assert!(
+            netflow
+                .lines()
+                .nth(1)
+                .unwrap()
+                .ends_with("denied  no matching rule (ptr lb-93-184-216-34.example.net)")
+        );
+    }
+
+    #[test]
+    fn render_flow_log_as_netflow() {
+        let log = render_flow_log(&[record()], FlowLogFormat::Netflow);
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(lines[0].starts_with("Date first seen"));
+        assert_eq!(
+            lines[1],
+            "2025-10-16T11:50:45.123Z TCP   0.0.0.0:0             -> 93.184.216.34:443        4242 allowed domain example.com"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use crate::policy::AccessMode;
+
+/// How the sandboxed command used one file over a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileAccess {
+    pub path: PathBuf,
+    /// Every access the file was opened for, combined
+    pub access: AccessMode,
+    /// Number of times the file was opened
+    pub opens: u64,
+    /// Command names of the processes that opened it
+    pub comms: BTreeSet<String>,
+}
+
+impl FileAccess {
+    /// Render the entry as a single line of JSON
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "path": self.path,
+            "access": self.access,
+            "opens": self.opens,
+            "comms": self.comms,
+        })
+        .to_string()
+    }
+}
+
+/// Files opened by the sandboxed command, aggregated per path
+#[derive(Debug, Default)]
+pub struct FileInventory {
+    entries: BTreeMap<PathBuf, FileAccess>,
+}
+
+impl FileInventory {
+    /// Record that `comm` opened `path` for `access`
+    pub fn record(&mut self, path: &Path, access: AccessMode, comm: &str) {
+        let entry = self
+            .entries
+            .entry(path.to_path_buf())
+            .or_insert_with(|| FileAccess {
+                path: path.to_path_buf(),
+                access,
+                opens: 0,
+                comms: BTreeSet::new(),
+            });
+        entry.access = combine(entry.access, access);
+        entry.opens += 1;
+        entry.comms.insert(comm.to_string());
+    }
+
+    /// The entries in path order
+    pub fn into_entries(self) -> Vec<FileAccess> {
+        self.entries.into_values().collect()
+    }
+}
+
+/// How often the opens of the paths one deny rule covers were let through
+/// and denied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRuleHits {
+    /// The denied file or directory
+    pub path: PathBuf,
+    /// Opens allowed because the rule denies another access
+    pub allowed: u64,
+    /// Opens the rule denied
+    pub denied: u64,
+}
+
+/// Access mode covering both `a` and `b`
+fn combine(a: AccessMode, b: AccessMode) -> AccessMode {
+    match a as u8 | b as u8 {
+        1 => AccessMode::Read,
+        2 => AccessMode::Write,
+        _ => AccessMode::ReadWrite,
+    }
+}
+
+/// Render the file inventory as JSON lines, one per file
+pub fn render_file_inventory(entries: &[FileAccess]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let _ = writeln!(out, "{}", entry.to_json());
+    }
+    out
+}
+
+/// Render the rule counters as a table, rules that never matched included
+pub fn render_file_rule_hits(rules: &[FileRuleHits]) -> String {
+    if rules.is_empty() {
+        return "mori file rule hits: no deny rules\n".to_string();
+    }
+    let mut out = String::from("mori file rule hits:\n");
+    let _ = writeln!(out, "  {:>8} {:>8}  path", "allowed", "denied");
+    for rule in rules {
+        let _ = writeln!(
+            out,
+            "  {:>8} {:>8}  {}",
+            rule.allowed,
+            rule.denied,
+            rule.path.display()
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_combines_opens_of_the_same_path() {
+        let mut inventory = FileInventory::default();
+        inventory.record(Path::new("/etc/hosts"), AccessMode::Read, "curl");
+        inventory.record(Path::new("/tmp/out"), AccessMode::Write, "sh");
+        inventory.record(Path::new("/etc/hosts"), AccessMode::Read, "wget");
+        inventory.record(Path::new("/tmp/out"), AccessMode::Read, "sh");
+
+        let log = render_file_inventory(&inventory.into_entries());
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"access":"read","comms":["curl","wget"],"opens":2,"path":"/etc/hosts"}"#,
+                r#"{"access":"read-write","comms":["sh"],"opens":2,"path":"/tmp/out"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn render_file_rule_hits_lists_every_rule() {
+        let rules = [
+            FileRuleHits {
+                path: PathBuf::from("/etc/passwd"),
+                allowed: 12,
+                denied: 0,
+            },
+            FileRuleHits {
+                path: PathBuf::from("/home/user/.ssh"),
+                allowed: 0,
+                denied: 3,
+            },
+        ];
+        assert_eq!(
+            render_file_rule_hits(&rules),
+            "mori file rule hits:\n   allowed   denied  path\n        12        0  /etc/passwd\n         0        3  /home/user/.ssh\n"
+        );
+        assert_eq!(
+            render_file_rule_hits(&[]),
+            "mori file rule hits: no deny rules\n"
+        );
+    }
+}
@@ -0,0 +1,18 @@
+pub mod ci;
+pub mod collector;
+pub mod event;
+pub mod explain;
+pub mod flow;
+pub mod inventory;
+pub mod report;
+pub mod synthesize;
+
+// Re-export main types
+pub use ci::CiProvider;
+pub use collector::{ViolationCollector, ViolationSender};
+pub use event::{Operation, ViolationEvent};
+pub use explain::{Explainer, Explanation};
+pub use flow::{FlowLogFormat, FlowRecord, FlowVerdict};
+pub use inventory::{FileAccess, FileInventory, FileRuleHits};
+pub use report::{AuditReport, ReportEntry, ReportFormat};
+pub use synthesize::Observations;
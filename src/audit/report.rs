@@ -0,0 +1,161 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use super::event::{Operation, ViolationEvent};
+
+/// How the violation report is printed at the end of a run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// Human-readable summary
+    #[default]
+    Text,
+    /// A single line of JSON (see [`AuditReport::to_json`]) for agents and other tools
+    Json,
+}
+
+/// Aggregated violations of one operation on one target
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReportEntry {
+    /// Number of times the access was attempted
+    pub count: usize,
+    /// Command names of the processes that attempted it
+    pub comms: BTreeSet<String>,
+}
+
+/// Aggregated view of all violations observed during a run
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    entries: BTreeMap<(Operation, String), ReportEntry>,
+}
+
+impl AuditReport {
+    /// Record a violation event
+    pub fn record(&mut self, event: &ViolationEvent) {
+        let entry = self
+            .entries
+            .entry((event.operation, event.target.clone()))
+            .or_default();
+        entry.count += 1;
+        // Packet programs cannot tell which process a packet belongs to
+        if !event.comm.is_empty() {
+            entry.comms.insert(event.comm.clone());
+        }
+    }
+
+    /// Total number of recorded violations (including repeats)
+    pub fn total(&self) -> usize {
+        self.entries.values().map(|entry| entry.count).sum()
+    }
+
+    /// Check if no violations were recorded
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over the aggregated violations, ordered by operation and target
+    pub fn iter(&self) -> impl Iterator<Item = (Operation, &str, &ReportEntry)> {
+        self.entries
+            .iter()
+            .map(|((operation, target), entry)| (*operation, target.as_str(), entry))
+    }
+
+    /// Render the report as JSON for consumption by other tools
+    pub fn to_json(&self) -> String {
+        let violations: Vec<serde_json::Value> = self
+            .iter()
+            .map(|(operation, target, entry)| {
+                serde_json::json!({
+                    "operation": operation.to_string(),
+                    "target": target,
+                    "processes": entry.comms,
+                    "count": entry.count,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "total": self.total(),
+            "violations": violations,
+        })
+        .to_string()
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "mori audit summary: {} violation(s), {} unique",
+            self.total(),
+            self.entries.len()
+        )?;
+        for (operation, target, entry) in self.iter() {
+            let comms: Vec<&str> = entry.comms.iter().map(String::as_str).collect();
+            writeln!(
+                f,
+                "  {:<10} {} ({}, {} time(s))",
+                operation.to_string(),
+                target,
+                comms.join(", "),
+                entry.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(operation: Operation, target: &str, comm: &str) -> ViolationEvent {
+        ViolationEvent {
+            operation,
+            target: target.to_string(),
+            pid: 100,
+            comm: comm.to_string(),
+        }
+    }
+
+    #[test]
+    fn record_aggregates_repeated_targets() {
+        let mut report = AuditReport::default();
+        report.record(&event(Operation::Connect, "192.0.2.1:443", "curl"));
+        report.record(&event(Operation::Connect, "192.0.2.1:443", "wget"));
+        report.record(&event(Operation::FileRead, "/etc/passwd", "cat"));
+
+        assert_eq!(report.total(), 3);
+        assert_eq!(report.entries.len(), 2);
+    }
+
+    #[test]
+    fn display_lists_entries_in_stable_order() {
+        let mut report = AuditReport::default();
+        report.record(&event(Operation::FileRead, "/etc/passwd", "cat"));
+        report.record(&event(Operation::Connect, "192.0.2.1:443", "curl"));
+        report.record(&event(Operation::Connect, "192.0.2.1:443", "curl"));
+
+        let rendered = report.to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "mori audit summary: 3 violation(s), 2 unique");
+        assert_eq!(lines[1], "  connect    192.0.2.1:443 (curl, 2 time(s))");
+        assert_eq!(lines[2], "  file-read  /etc/passwd (cat, 1 time(s))");
+    }
+
+    #[test]
+    fn to_json_lists_violations() {
+        let mut report = AuditReport::default();
+        report.record(&event(Operation::Connect, "192.0.2.1:443", "curl"));
+        report.record(&event(Operation::Connect, "192.0.2.1:443", "curl"));
+
+        assert_eq!(
+            report.to_json(),
+            r#"{"total":2,"violations":[{"count":2,"operation":"connect","processes":["curl"],"target":"192.0.2.1:443"}]}"#
+        );
+        assert_eq!(
+            AuditReport::default().to_json(),
+            r#"{"total":0,"violations":[]}"#
+        );
+    }
+}
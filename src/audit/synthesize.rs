@@ -0,0 +1,405 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{
+    cli::{ConfigFile, config::AllowConfig},
+    error::MoriError,
+    policy::{AccessMode, file::SYSTEM_PATHS},
+};
+
+/// Addresses of one /24 network allowed individually before they are
+/// replaced by the network
+const MIN_NETWORK_ADDRESSES: usize = 4;
+
+/// Paths directly below one directory allowed individually before they are
+/// replaced by the directory
+const MIN_SUBTREE_ENTRIES: usize = 3;
+
+/// Fewest components below `/` a directory replacing the paths below it
+/// has, so that neither `/home` nor a home directory is allowed whole
+const MIN_SUBTREE_DEPTH: usize = 3;
+
+/// The parts of a JSONL flow log record needed to rebuild a policy
+#[derive(Debug, Deserialize)]
+struct RecordedFlow {
+    dst_addr: IpAddr,
+    verdict: String,
+    rule: String,
+}
+
+/// The parts of a JSONL file inventory record needed to rebuild a policy
+#[derive(Debug, Deserialize)]
+struct RecordedFile {
+    path: PathBuf,
+    access: AccessMode,
+}
+
+/// Connection attempts aggregated over any number of recorded runs
+#[derive(Debug, Default)]
+pub struct Observations {
+    /// Every destination the commands connected to, allowed or not
+    destinations: BTreeSet<IpAddr>,
+    /// Domains each address was allowed through
+    domains: BTreeMap<IpAddr, BTreeSet<String>>,
+    /// Files the commands opened outside of the system paths
+    files: BTreeSet<PathBuf>,
+}
+
+impl Observations {
+    /// Add the records of a JSONL flow log (`--flow-log PATH`) read from `path`
+    pub fn add_flow_log(&mut self, path: &Path) -> Result<(), MoriError> {
+        let content = fs::read_to_string(path).map_err(|source| MoriError::FlowLogRead {
+            path: PathBuf::from(path),
+            source,
+        })?;
+        self.add_jsonl(&content)
+            .map_err(|(line, reason)| MoriError::FlowLogParse {
+                path: PathBuf::from(path),
+                line,
+                reason,
+            })
+    }
+
+    /// Add the records of a JSONL file inventory (`--file-inventory PATH`)
+    /// read from `path`
+    pub fn add_file_inventory(&mut self, path: &Path) -> Result<(), MoriError> {
+        let content = fs::read_to_string(path).map_err(|source| MoriError::FileInventoryRead {
+            path: PathBuf::from(path),
+            source,
+        })?;
+        self.add_inventory_jsonl(&content)
+            .map_err(|(line, reason)| MoriError::FileInventoryParse {
+                path: PathBuf::from(path),
+                line,
+                reason,
+            })
+    }
+
+    /// Add JSONL flow records; fails with the line number and reason on malformed input
+    fn add_jsonl(&mut self, content: &str) -> Result<(), (usize, String)> {
+        for flow in parse_jsonl(content)? {
+            self.record(flow);
+        }
+        Ok(())
+    }
+
+    /// Add JSONL file inventory records; fails like `add_jsonl`
+    fn add_inventory_jsonl(&mut self, content: &str) -> Result<(), (usize, String)> {
+        for file in parse_jsonl::<RecordedFile>(content)? {
+            // Paths every command may open need no rule, nor do pipes and
+            // sockets, nor the root directory, which would allow everything
+            if file.path.is_absolute()
+                && file.path.parent().is_some()
+                && !is_system_path(&file.path, file.access)
+            {
+                self.files.insert(file.path);
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, flow: RecordedFlow) {
+        let ip = flow.dst_addr;
+        // Localhost and the nameservers are always reachable
+        if ip.is_loopback() || flow.rule.starts_with("dns server ") || flow.rule == "localhost" {
+            return;
+        }
+        // Destinations denied on purpose are not worth allowing
+        if flow.rule == "deny list" {
+            return;
+        }
+        self.destinations.insert(ip);
+        if flow.verdict == "allowed"
+            && let Some(domains) = flow.rule.strip_prefix("domain ")
+        {
+            self.domains
+                .entry(ip)
+                .or_default()
+                .extend(domains.split(',').map(str::to_string));
+        }
+    }
+
+    /// The smallest configuration allowing every observed destination and
+    /// file
+    ///
+    /// Addresses a domain rule was seen allowing, in any run, are replaced by
+    /// domains, picking the domain that covers the most remaining addresses
+    /// first. The other addresses are allowed individually, or as a /24
+    /// network when at least `MIN_NETWORK_ADDRESSES` of it were seen; IPv6
+    /// addresses are always allowed individually. Files
+    /// become the allow list of the `[file]` table, see `collapse_paths`.
+    pub fn to_config(&self) -> ConfigFile {
+        let mut uncovered: BTreeSet<IpAddr> = self
+            .destinations
+            .iter()
+            .filter(|ip| self.domains.contains_key(ip))
+            .copied()
+            .collect();
+        let mut domains = BTreeSet::new();
+        while !uncovered.is_empty() {
+            let mut coverage: BTreeMap<&str, usize> = BTreeMap::new();
+            for ip in &uncovered {
+                for domain in &self.domains[ip] {
+                    *coverage.entry(domain).or_default() += 1;
+                }
+            }
+            // Ties go to the first domain by name
+            let Some((best, _)) = coverage
+                .into_iter()
+                .rev()
+                .max_by_key(|(_, covered)| *covered)
+            else {
+                break;
+            };
+            uncovered.retain(|ip| !self.domains[ip].contains(best));
+            domains.insert(best.to_string());
+        }
+        let mut networks: BTreeMap<[u8; 3], Vec<IpAddr>> = BTreeMap::new();
+        let mut ipv6 = Vec::new();
+        for ip in self
+            .destinations
+            .iter()
+            .filter(|ip| !self.domains.contains_key(ip))
+        {
+            match ip {
+                IpAddr::V4(v4) => {
+                    let [a, b, c, _] = v4.octets();
+                    networks.entry([a, b, c]).or_default().push(*ip);
+                }
+                IpAddr::V6(_) => ipv6.push(ip.to_string()),
+            }
+        }
+        let addresses = networks
+            .into_iter()
+            .flat_map(|([a, b, c], ips)| {
+                if ips.len() >= MIN_NETWORK_ADDRESSES {
+                    vec![format!("{}.{}.{}.0/24", a, b, c)]
+                } else {
+                    ips.iter().map(IpAddr::to_string).collect()
+                }
+            })
+            .chain(ipv6);
+
+        let mut config = ConfigFile::default();
+        let entries: Vec<String> = domains.into_iter().chain(addresses).collect();
+        if !entries.is_empty() {
+            config.network.allow = AllowConfig::Entries(entries);
+        }
+        config.file.allow = collapse_paths(&self.files).into_iter().collect();
+        config
+    }
+}
+
+/// Parse JSON lines into records; fails with the line number and reason on
+/// malformed input
+fn parse_jsonl<T: DeserializeOwned>(content: &str) -> Result<Vec<T>, (usize, String)> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| serde_json::from_str(line).map_err(|err| (index + 1, err.to_string())))
+        .collect()
+}
+
+/// Check if allow-list mode lets every command open `path` for `access`
+fn is_system_path(path: &Path, access: AccessMode) -> bool {
+    SYSTEM_PATHS
+        .iter()
+        .any(|&(system, mode)| path.starts_with(system) && (access as u8) & !(mode as u8) == 0)
+}
+
+/// Replace paths by the directories holding at least `MIN_SUBTREE_ENTRIES`
+/// of them, until no directory does, and drop paths below others
+///
+/// Only directories at least `MIN_SUBTREE_DEPTH` components below `/` may
+/// replace their entries.
+fn collapse_paths(paths: &BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+    let mut paths = paths.clone();
+    loop {
+        let mut entries: BTreeMap<&Path, usize> = BTreeMap::new();
+        for parent in paths.iter().filter_map(|path| path.parent()) {
+            // Components of a path count the root directory too
+            if parent.components().count() > MIN_SUBTREE_DEPTH {
+                *entries.entry(parent).or_default() += 1;
+            }
+        }
+        let subtrees: Vec<PathBuf> = entries
+            .into_iter()
+            .filter(|(_, count)| *count >= MIN_SUBTREE_ENTRIES)
+            .map(|(dir, _)| dir.to_path_buf())
+            .collect();
+        if subtrees.is_empty() {
+            break;
+        }
+        paths.retain(|path| !subtrees.iter().any(|dir| path.starts_with(dir)));
+        paths.extend(subtrees);
+    }
+    let nested: Vec<PathBuf> = paths
+        .iter()
+        .filter(|path| {
+            paths
+                .iter()
+                .any(|other| other != *path && path.starts_with(other))
+        })
+        .cloned()
+        .collect();
+    for path in nested {
+        paths.remove(&path);
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(dst: &str, verdict: &str, rule: &str) -> String {
+        serde_json::json!({
+            "timestamp": "2026-01-01T00:00:00.000Z",
+            "pid": 1,
+            "protocol": "TCP",
+            "src_addr": "0.0.0.0",
+            "src_port": 0,
+            "dst_addr": dst,
+            "dst_port": 443,
+            "verdict": verdict,
+            "rule": rule,
+        })
+        .to_string()
+    }
+
+    fn file_line(path: &str, access: &str) -> String {
+        serde_json::json!({
+            "path": path,
+            "access": access,
+            "opens": 1,
+            "comms": ["cargo"],
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn to_config_collapses_addresses_into_domains_across_runs() {
+        let first_run = [
+            line("140.82.112.4", "allowed", "domain github.com"),
+            line("127.0.0.1", "allowed", "localhost"),
+            line("10.0.0.53", "allowed", "dns server 10.0.0.53"),
+            line("192.0.2.1", "allowed", "ip 192.0.2.1"),
+        ]
+        .join("\n");
+        // The address github.com resolved to in the first run, denied in a later one
+        let second_run = [
+            line("140.82.112.4", "denied", "no matching rule"),
+            line(
+                "93.184.216.34",
+                "allowed",
+                "domain example.com,www.example.com",
+            ),
+            line("93.184.216.35", "allowed", "domain www.example.com"),
+            line("198.51.100.7", "denied", "no matching rule"),
+            line("169.254.169.254", "denied", "deny list"),
+        ]
+        .join("\n");
+
+        let mut observations = Observations::default();
+        observations.add_jsonl(&first_run).unwrap();
+        observations.add_jsonl(&second_run).unwrap();
+
+        let AllowConfig::Entries(entries) = observations.to_config().network.allow else {
+            panic!("expected allow entries");
+        };
+        assert_eq!(
+            entries,
+            vec!["github.com", "www.example.com", "192.0.2.1", "198.51.100.7"]
+        );
+    }
+
+    #[test]
+    fn to_config_aggregates_addresses_into_networks() {
+        let flows: Vec<String> = ["203.0.113.1", "203.0.113.9", "203.0.113.20", "203.0.113.77"]
+            .into_iter()
+            .chain(["198.51.100.1", "198.51.100.2", "198.51.100.3"])
+            .chain(["2001:db8::1", "2001:db8::2", "2001:db8::3", "2001:db8::4"])
+            .map(|dst| line(dst, "allowed", "allow all"))
+            .collect();
+        let mut observations = Observations::default();
+        observations.add_jsonl(&flows.join("\n")).unwrap();
+
+        let AllowConfig::Entries(entries) = observations.to_config().network.allow else {
+            panic!("expected allow entries");
+        };
+        assert_eq!(
+            entries,
+            vec![
+                "198.51.100.1",
+                "198.51.100.2",
+                "198.51.100.3",
+                "203.0.113.0/24",
+                "2001:db8::1",
+                "2001:db8::2",
+                "2001:db8::3",
+                "2001:db8::4"
+            ]
+        );
+    }
+
+    #[test]
+    fn to_config_allows_opened_files_as_subtrees() {
+        let inventory = [
+            file_line("/usr/lib/libc.so.6", "read"),
+            file_line("/etc/hosts", "read"),
+            file_line("/etc/passwd", "write"),
+            file_line("/dev/null", "read-write"),
+            file_line("/", "read"),
+            file_line("pipe:[1234]", "write"),
+            file_line("/home/me/.gitconfig", "read"),
+            file_line("/home/me/.bashrc", "read"),
+            file_line("/home/me/.profile", "read"),
+            file_line("/home/me/project/Cargo.toml", "read"),
+            file_line("/home/me/project/Cargo.lock", "read-write"),
+            file_line("/home/me/project/build.rs", "read"),
+            file_line("/home/me/project/target/debug/a", "write"),
+            file_line("/home/me/project/target/debug/b", "write"),
+            file_line("/home/me/project/target/debug/c", "write"),
+        ]
+        .join("\n");
+        let mut observations = Observations::default();
+        observations.add_inventory_jsonl(&inventory).unwrap();
+
+        let config = observations.to_config();
+        assert_eq!(
+            config.file.allow,
+            [
+                "/etc/passwd",
+                "/home/me/.bashrc",
+                "/home/me/.gitconfig",
+                "/home/me/.profile",
+                "/home/me/project",
+            ]
+            .map(PathBuf::from)
+        );
+        assert!(matches!(config.network.allow, AllowConfig::Boolean(false)));
+    }
+
+    #[test]
+    fn to_config_denies_network_without_observations() {
+        let config = Observations::default().to_config();
+        assert!(matches!(config.network.allow, AllowConfig::Boolean(false)));
+    }
+
+    #[test]
+    fn add_jsonl_reports_malformed_line() {
+        let content = format!(
+            "{}\nnot json\n",
+            line("192.0.2.1", "allowed", "ip 192.0.2.1")
+        );
+        let (line, _) = Observations::default().add_jsonl(&content).unwrap_err();
+        assert_eq!(line, 2);
+    }
+}
@@ -0,0 +1,63 @@
+use std::{ffi::OsString, path::PathBuf};
+
+use clap::Parser;
+use mori::{
+    cli::cargo::{cargo_policy, locate_workspace},
+    error::MoriError,
+    runtime::{RunOptions, execute_with_policy},
+};
+
+/// Invoked by cargo as `cargo-mori mori <args>`
+#[derive(Parser, Debug)]
+#[command(name = "cargo", bin_name = "cargo")]
+enum Cli {
+    Mori(MoriArgs),
+}
+
+/// Run a cargo command in a mori sandbox with a policy suited to Rust builds
+#[derive(clap::Args, Debug)]
+#[command(version)]
+struct MoriArgs {
+    /// Additional mori configuration merged into the Cargo policy
+    /// (default: mori.toml in the workspace root, if present)
+    #[arg(long = "config", value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Cargo command and its arguments, e.g. `build --release`
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required = true,
+        value_name = "COMMAND"
+    )]
+    cargo_args: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), MoriError> {
+    env_logger::init();
+
+    let Cli::Mori(args) = Cli::parse();
+
+    // cargo passes its own path to subcommands so the same toolchain is used
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let workspace_root = locate_workspace(&cargo)?;
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+
+    let policy = cargo_policy(&workspace_root, home.as_deref(), args.config.as_deref())?;
+
+    // sandbox-exec cannot filter by domain, so the registry allow list would deny everything
+    #[cfg(target_os = "macos")]
+    let policy = {
+        eprintln!("mori: network access is not restricted on macOS");
+        mori::policy::Policy {
+            network: mori::policy::NetworkPolicy::from_allow_all(true),
+            ..policy
+        }
+    };
+
+    let cargo = cargo.to_string_lossy();
+    let cargo_args: Vec<&str> = args.cargo_args.iter().map(String::as_str).collect();
+    let outcome = execute_with_policy(&cargo, &cargo_args, &policy, &RunOptions::default()).await?;
+    std::process::exit(outcome.exit_code);
+}
@@ -1,15 +1,62 @@
 use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::{
+    audit::{CiProvider, ReportFormat},
+    convert::SourceFormat,
+    export::ExportFormat,
+};
+
+#[cfg(target_os = "linux")]
+use crate::{
+    audit::FlowLogFormat,
+    net::cache::RefreshMargin,
+    runtime::{Backend, DenyErrno},
+};
+
+use super::preset::PolicyPreset;
+
+/// Files `mori trace` records to, and `mori policy-from` reads, by default
+pub(crate) const DEFAULT_FLOW_LOG: &str = "mori-flows.jsonl";
+pub(crate) const DEFAULT_FILE_INVENTORY: &str = "mori-files.jsonl";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub subcommand: Option<Commands>,
+
+    /// The flat form `mori [OPTIONS] -- COMMAND`, the same as `mori run`
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Options building the policy, shared by `mori run` and `mori policy show`
+#[derive(clap::Args, Debug)]
+pub struct PolicyArgs {
     /// Path to configuration file (TOML)
     #[arg(long = "config", value_name = "PATH")]
     pub config: Option<PathBuf>,
 
-    /// Allow outbound connections to the specified host[:port] (FQDN/IP)
+    /// Path to a policy in mori's JSON schema (as produced by `Policy::to_json`)
+    #[arg(long = "policy", value_name = "PATH")]
+    pub policy: Option<PathBuf>,
+
+    /// Start from a built-in policy; the config file and other options are merged on top
+    #[arg(long = "preset", value_name = "PRESET")]
+    pub preset: Option<PolicyPreset>,
+
+    /// Allow outbound connections to the specified host[:port] (FQDN/IP, or the
+    /// address groups private, linklocal and metadata)
     #[cfg(not(target_os = "macos"))]
     #[arg(long = "allow-network", value_delimiter = ',')]
     pub allow_network: Vec<String>,
@@ -18,6 +65,29 @@ pub struct Args {
     #[arg(long = "allow-network-all")]
     pub allow_network_all: bool,
 
+    /// Deny outbound connections to the specified hosts (FQDN/IP/CIDR, or the
+    /// address groups private, linklocal and metadata), even if allowed
+    #[cfg(not(target_os = "macos"))]
+    #[arg(long = "deny-network", value_delimiter = ',')]
+    pub deny_network: Vec<String>,
+
+    /// Abort when an allowed or denied domain resolves to no address at
+    /// startup, after retrying its lookup for up to 10 seconds
+    #[cfg(not(target_os = "macos"))]
+    #[arg(long = "strict-dns")]
+    pub strict_dns: bool,
+
+    /// Stop allowing localhost (127.0.0.1 and ::1) implicitly; local services
+    /// are then only reachable through --allow-network entries
+    #[cfg(not(target_os = "macos"))]
+    #[arg(long = "no-allow-localhost")]
+    pub no_allow_localhost: bool,
+
+    /// Allow listening only on the specified ports (listening is denied by
+    /// default whenever outbound connections are restricted)
+    #[arg(long = "allow-listen", value_name = "PORT", value_delimiter = ',')]
+    pub allow_listen: Vec<u16>,
+
     /// Deny file read/write access to the specified paths (all other paths are allowed)
     #[arg(long = "deny-file", value_delimiter = ',')]
     pub deny_file: Vec<PathBuf>,
@@ -30,7 +100,401 @@ pub struct Args {
     #[arg(long = "deny-file-write", value_delimiter = ',')]
     pub deny_file_write: Vec<PathBuf>,
 
+    /// Deny executing the specified programs (all other programs are allowed)
+    #[arg(long = "deny-exec", value_delimiter = ',')]
+    pub deny_exec: Vec<PathBuf>,
+
+    /// Allow executing only the specified programs (the command itself must be included)
+    #[arg(long = "allow-exec-only", value_delimiter = ',')]
+    pub allow_exec_only: Vec<PathBuf>,
+
+    /// Limit the number of processes and threads the command may have at
+    /// once (overrides `[process] max_pids`)
+    #[cfg(target_os = "linux")]
+    #[arg(long = "max-pids", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_pids: Option<u32>,
+
+    /// Deny creating or joining namespaces (unshare, clone with CLONE_NEW*
+    /// flags, setns)
+    #[cfg(target_os = "linux")]
+    #[arg(long = "deny-namespaces")]
+    pub deny_namespaces: bool,
+}
+
+/// Options of `mori run`
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub policy_args: PolicyArgs,
+
+    /// Report policy violations without blocking them
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[arg(long = "audit")]
+    pub audit: bool,
+
+    /// Exit with a nonzero status if any violation was reported (requires --audit)
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[arg(long = "fail-on-violation", requires = "audit")]
+    pub fail_on_violation: bool,
+
+    /// Write the audit report as JSON to the specified file (requires --audit)
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[arg(long = "audit-report", value_name = "PATH", requires = "audit")]
+    pub audit_report: Option<PathBuf>,
+
+    /// Format of the violation report printed to stderr
+    #[arg(long = "report-format", value_name = "FORMAT", default_value = "text")]
+    pub report_format: ReportFormat,
+
+    /// WebAssembly module deciding on IPv4 connections that match no allow rule,
+    /// IPv4-mapped IPv6 ones included; other IPv6 connections are denied without
+    /// consulting it, and file access is never sent to it (requires the
+    /// wasm-plugin feature)
+    #[cfg(target_os = "linux")]
+    #[arg(long = "plugin", value_name = "PATH")]
+    pub plugin: Option<PathBuf>,
+
+    /// Enforce the policy with the LD_PRELOAD shim (weaker) when the kernel
+    /// cannot run mori's eBPF programs
+    #[cfg(target_os = "linux")]
+    #[arg(long = "preload-fallback")]
+    pub preload_fallback: bool,
+
+    /// Record every connection attempt (allowed or denied) and write the log to
+    /// the specified file when the command exits
+    #[cfg(target_os = "linux")]
+    #[arg(long = "flow-log", value_name = "PATH")]
+    pub flow_log: Option<PathBuf>,
+
+    /// Format of the flow log
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long = "flow-log-format",
+        value_name = "FORMAT",
+        default_value = "jsonl",
+        requires = "flow_log"
+    )]
+    pub flow_log_format: FlowLogFormat,
+
+    /// Record every file the command opens, without denying anything, and
+    /// write the inventory (one JSON line per path) to the specified file
+    /// when the command exits
+    #[cfg(target_os = "linux")]
+    #[arg(long = "file-inventory", value_name = "PATH")]
+    pub file_inventory: Option<PathBuf>,
+
+    /// Count how many opens each file deny rule allowed and denied, and
+    /// print the counts when the command exits
+    #[cfg(target_os = "linux")]
+    #[arg(long = "file-rule-stats")]
+    pub file_rule_stats: bool,
+
+    /// Resolve allowed domains again this long before their DNS records expire
+    /// (percentage of the TTL such as `10%`, or seconds such as `5s`)
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long = "dns-refresh-margin",
+        value_name = "MARGIN",
+        default_value = "10%"
+    )]
+    pub dns_refresh_margin: RefreshMargin,
+
+    /// Reuse the unexpired DNS records of earlier runs saved in this file
+    /// (e.g. `~/.cache/mori/dns.json`), and save this run's records to it
+    #[cfg(target_os = "linux")]
+    #[arg(long = "dns-cache", value_name = "PATH")]
+    pub dns_cache: Option<PathBuf>,
+
+    /// Look up the PTR name of each denied destination and show it in the
+    /// logs, explanations and flow log
+    #[cfg(target_os = "linux")]
+    #[arg(long = "reverse-dns")]
+    pub reverse_dns: bool,
+
+    /// Error denied connections fail with
+    #[cfg(target_os = "linux")]
+    #[arg(long = "deny-errno", value_name = "ERRNO", default_value = "eperm")]
+    pub deny_errno: DenyErrno,
+
+    /// Mount an empty tmpfs on /tmp and /var/tmp for the command, in a mount
+    /// namespace of its own; its files are gone when it exits
+    #[cfg(target_os = "linux")]
+    #[arg(long = "private-tmp")]
+    pub private_tmp: bool,
+
+    /// Mount a copy-on-write overlay on the working directory, staging the
+    /// command's writes in DIR/upper instead; the changes are listed at exit
+    #[cfg(target_os = "linux")]
+    #[arg(long = "overlay", value_name = "DIR")]
+    pub overlay: Option<PathBuf>,
+
+    /// Kill the command, and everything it started, after this long (such as
+    /// `300s`, `10m` or `1h`) and exit with status 124
+    #[cfg(target_os = "linux")]
+    #[arg(long = "timeout", value_name = "DURATION", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Run the command as this user (name or uid, optionally followed by
+    /// `:group`) instead of the user behind sudo; a user from the passwd
+    /// database keeps its supplementary groups
+    #[cfg(target_os = "linux")]
+    #[arg(long = "user", value_name = "USER[:GROUP]")]
+    pub user: Option<String>,
+
+    /// Supplementary group (name or gid) for the `--user` (can be specified
+    /// multiple times)
+    #[cfg(target_os = "linux")]
+    #[arg(long = "group", value_name = "GROUP", requires = "user")]
+    pub groups: Vec<String>,
+
+    /// Start the command in this directory instead of the current one
+    #[cfg(target_os = "linux")]
+    #[arg(long = "chdir", value_name = "DIR")]
+    pub chdir: Option<PathBuf>,
+
+    /// Pass the command only PATH, HOME, USER, LOGNAME, SHELL, TERM, TZ and
+    /// the locale variables of mori's environment
+    #[cfg(target_os = "linux")]
+    #[arg(long = "clean-env")]
+    pub clean_env: bool,
+
+    /// Write the command's stdout to FILE instead of the terminal, apart
+    /// from mori's own messages
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[arg(long = "log-stdout", value_name = "FILE")]
+    pub log_stdout: Option<PathBuf>,
+
+    /// Write the command's stderr to FILE instead of the terminal
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[arg(long = "log-stderr", value_name = "FILE")]
+    pub log_stderr: Option<PathBuf>,
+
+    /// Prefix each line the command writes to the terminal with `[stdout] `
+    /// or `[stderr] `
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[arg(long = "prefix-output")]
+    pub prefix_output: bool,
+
+    /// How to enforce the policy: eBPF (needs root), or Landlock, seccomp and
+    /// user namespaces without root; auto picks eBPF when running as root
+    #[cfg(target_os = "linux")]
+    #[arg(long = "backend", value_name = "BACKEND", default_value = "auto")]
+    pub backend: Backend,
+
+    /// Run the command unrestricted when the platform cannot enforce the policy,
+    /// logging every rule that is not enforced
+    #[arg(long = "best-effort")]
+    pub best_effort: bool,
+
+    /// Report the run to a CI system (annotations for violations and a step summary)
+    #[arg(long = "ci", value_name = "PROVIDER")]
+    pub ci: Option<CiProvider>,
+
+    /// Command to execute
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+/// Subcommands; without one, mori runs the command given after `--`
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Run a command in the sandbox (the same as `mori [OPTIONS] -- COMMAND`)
+    Run(Box<RunArgs>),
+    /// Check how this system can enforce mori's policies
+    Check,
+    /// Run a command with all access allowed, recording the connections it
+    /// makes and the files it opens
+    #[cfg(target_os = "linux")]
+    Trace(TraceArgs),
+    /// Inspect the policy mori would enforce
+    #[command(subcommand)]
+    Policy(PolicyCommand),
+    /// Translate another sandbox definition into mori configuration (printed as TOML)
+    Convert(ConvertArgs),
+    /// Generate an equivalent policy for another security module (printed to stdout)
+    Export(ExportArgs),
+    /// Manage git hooks that run under the repository's mori.toml policy
+    #[command(subcommand)]
+    GitHook(GitHookCommand),
+    /// Print a shell completion script (e.g. `mori completions bash > /etc/bash_completion.d/mori`)
+    Completions(CompletionsArgs),
+    /// Write man pages for mori and its configuration file (mori.toml(5))
+    Mangen(MangenArgs),
+    /// Build the smallest configuration allowing what traced or audited runs
+    /// did: the connections in flow logs and the files in file inventories
+    #[command(alias = "generate")]
+    PolicyFrom(PolicyFromArgs),
+}
+
+#[cfg(target_os = "linux")]
+#[derive(clap::Args, Debug)]
+pub struct TraceArgs {
+    /// File the connections are written to (JSON lines, as with --flow-log)
+    #[arg(
+        long = "flow-log",
+        value_name = "PATH",
+        default_value = DEFAULT_FLOW_LOG
+    )]
+    pub flow_log: PathBuf,
+
+    /// File the opened files are written to (JSON lines, as with --file-inventory)
+    #[arg(
+        long = "file-inventory",
+        value_name = "PATH",
+        default_value = DEFAULT_FILE_INVENTORY
+    )]
+    pub file_inventory: PathBuf,
+
     /// Command to execute
     #[arg(last = true, required = true)]
     pub command: Vec<String>,
 }
+
+#[derive(Subcommand, Debug)]
+pub enum PolicyCommand {
+    /// Print the policy built from the preset, policy file, config file and
+    /// flags, as `mori run` would enforce it
+    Show(PolicyShowArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PolicyShowArgs {
+    #[command(flatten)]
+    pub policy_args: PolicyArgs,
+
+    /// Output format
+    #[arg(long = "format", value_name = "FORMAT", default_value = "text")]
+    pub format: PolicyFormat,
+}
+
+/// How `mori policy show` prints the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PolicyFormat {
+    /// One restriction per line
+    Text,
+    /// mori's JSON schema, as accepted by --policy
+    Json,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    /// Format of the input
+    #[arg(long = "from", value_name = "FORMAT")]
+    pub from: SourceFormat,
+
+    /// Path to the file to convert
+    #[arg(value_name = "PATH", required_unless_present = "invocation")]
+    pub input: Option<PathBuf>,
+
+    /// Command line to convert, for formats describing an invocation (e.g. bwrap)
+    #[arg(last = true, value_name = "ARGS")]
+    pub invocation: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Output format
+    #[arg(long = "format", value_name = "FORMAT")]
+    pub format: ExportFormat,
+
+    /// Path to the mori configuration file to export (default: deny all network access)
+    #[arg(long = "config", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Name of the generated profile
+    #[arg(long = "name", default_value = "mori")]
+    pub name: String,
+
+    /// Write the generated files into this directory instead of printing them
+    #[arg(long = "output-dir", value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to generate completions for
+    #[arg(value_name = "SHELL")]
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PolicyFromArgs {
+    /// JSONL flow logs recorded with --flow-log or `mori trace`, one per run
+    /// [default: mori-flows.jsonl when no file is given]
+    #[arg(value_name = "FLOW_LOG")]
+    pub flow_logs: Vec<PathBuf>,
+
+    /// JSONL file inventory recorded with --file-inventory or `mori trace`,
+    /// one per run [default: mori-files.jsonl when no file is given]
+    #[arg(long = "file-inventory", value_name = "PATH")]
+    pub file_inventories: Vec<PathBuf>,
+
+    /// Write the configuration to this file instead of printing it
+    #[arg(long = "out", value_name = "PATH")]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MangenArgs {
+    /// Directory the man pages are written to
+    #[arg(long = "output-dir", value_name = "DIR", default_value = ".")]
+    pub output_dir: PathBuf,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GitHookCommand {
+    /// Wrap the repository's hooks so they run under mori
+    Install(GitHookInstallArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GitHookInstallArgs {
+    /// Repository whose hooks are wrapped
+    #[arg(long = "repo", value_name = "DIR", default_value = ".")]
+    pub repo: PathBuf,
+
+    /// Hooks to wrap
+    #[arg(
+        long = "hook",
+        value_name = "NAME",
+        value_delimiter = ',',
+        default_values = ["pre-commit", "post-checkout"]
+    )]
+    pub hooks: Vec<String>,
+}
+
+/// Parse a duration in seconds, minutes or hours (`300`, `300s`, `10m`, `1h`)
+#[cfg(target_os = "linux")]
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], 1),
+        Some((index, 'm')) => (&value[..index], 60),
+        Some((index, 'h')) => (&value[..index], 3600),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(count) if count > 0 => Ok(Duration::from_secs(count * unit)),
+        _ => Err(format!(
+            "invalid duration {} (expected e.g. 300s, 10m or 1h)",
+            value
+        )),
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::bare("90", Some(90))]
+    #[case::seconds("300s", Some(300))]
+    #[case::minutes("10m", Some(600))]
+    #[case::hours("1h", Some(3600))]
+    #[case::zero("0s", None)]
+    #[case::fraction("1.5h", None)]
+    #[case::unit_only("m", None)]
+    fn parse_duration_accepts_units(#[case] value: &str, #[case] secs: Option<u64>) {
+        assert_eq!(parse_duration(value).ok(), secs.map(Duration::from_secs));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    error::MoriError,
+    policy::{FilePolicy, NetworkPolicy, Policy, ProcessPolicy},
+};
+
+/// Presets covering the hosts cargo talks to for crates and git dependencies
+const CARGO_PRESETS: &[&str] = &["crates", "github"];
+
+/// Workspace entries cargo itself writes to; everything else in the workspace is read-only
+const WRITABLE_ENTRIES: &[&str] = &["target", "Cargo.lock"];
+
+/// Secrets under the home directory that builds never need to read
+const HOME_SECRETS: &[&str] = &[
+    ".ssh",
+    ".gnupg",
+    ".cargo/credentials.toml",
+    ".cargo/credentials",
+];
+
+/// Files under the home directory that build scripts could use to persist code
+const HOME_PROTECTED: &[&str] = &[
+    ".bashrc",
+    ".zshrc",
+    ".profile",
+    ".gitconfig",
+    ".cargo/config.toml",
+    ".cargo/bin",
+];
+
+/// Policy for running cargo inside a workspace
+///
+/// Network access is limited to crates.io and GitHub, writes inside the
+/// workspace are limited to the target directory and Cargo.lock, and
+/// credentials and shell startup files in `home` are protected. A `mori.toml`
+/// in the workspace root (or the explicitly given config) is merged on top,
+/// which is where registry mirrors and other extra hosts go.
+pub fn cargo_policy(
+    workspace_root: &Path,
+    home: Option<&Path>,
+    config: Option<&Path>,
+) -> Result<Policy, MoriError> {
+    let presets: Vec<String> = CARGO_PRESETS.iter().map(|name| name.to_string()).collect();
+    let mut network = NetworkPolicy::from_presets(&presets)?;
+
+    let mut file = FilePolicy::new();
+    let entries = fs::read_dir(workspace_root).map_err(|source| MoriError::CargoWorkspace {
+        reason: format!("failed to read {}: {}", workspace_root.display(), source),
+    })?;
+    let mut entries: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            !WRITABLE_ENTRIES
+                .iter()
+                .any(|name| entry.file_name() == *name)
+        })
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        file.deny_write(path);
+    }
+
+    if let Some(home) = home {
+        for name in HOME_SECRETS {
+            file.deny_read_write(home.join(name));
+        }
+        for name in HOME_PROTECTED {
+            file.deny_write(home.join(name));
+        }
+    }
+
+    let mut process = ProcessPolicy::new();
+
+    let workspace_config = workspace_root.join("mori.toml");
+    let config = config.or_else(|| workspace_config.is_file().then_some(&*workspace_config));
+    if let Some(path) = config {
+        let extra = super::PolicyLoader::load_config(path)?;
+        network.merge(extra.network);
+        file.merge(extra.file);
+        process.merge(extra.process);
+    }
+
+    Ok(Policy {
+        network,
+        file,
+        process,
+        ..Policy::new()
+    })
+}
+
+/// Find the root directory of the workspace cargo would build from the current directory
+pub fn locate_workspace(cargo: &OsStr) -> Result<PathBuf, MoriError> {
+    let output = Command::new(cargo)
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .output()
+        .map_err(|source| MoriError::CommandSpawn {
+            command: cargo.to_string_lossy().into_owned(),
+            source,
+        })?;
+    if !output.status.success() {
+        return Err(MoriError::CargoWorkspace {
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    let manifest = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    manifest
+        .parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| MoriError::CargoWorkspace {
+            reason: format!("unexpected manifest path {}", manifest.display()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{AccessMode, AllowPolicy};
+
+    fn workspace() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["src", "target"] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        for name in ["Cargo.toml", "Cargo.lock"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn cargo_policy_confines_writes_to_target() {
+        let dir = workspace();
+        let policy = cargo_policy(dir.path(), Some(Path::new("/home/dev")), None).unwrap();
+
+        let denied = &policy.file.denied_paths;
+        assert!(denied.contains(&(dir.path().join("src"), AccessMode::Write)));
+        assert!(denied.contains(&(dir.path().join("Cargo.toml"), AccessMode::Write)));
+        assert!(!denied.iter().any(|(path, _)| path.ends_with("target")));
+        assert!(!denied.iter().any(|(path, _)| path.ends_with("Cargo.lock")));
+        assert!(denied.contains(&(PathBuf::from("/home/dev/.ssh"), AccessMode::ReadWrite)));
+        assert!(denied.contains(&(PathBuf::from("/home/dev/.bashrc"), AccessMode::Write)));
+
+        match policy.network.policy {
+            AllowPolicy::Entries {
+                allowed_domains, ..
+            } => {
+                assert!(allowed_domains.contains(&"static.crates.io".to_string()));
+                assert!(allowed_domains.contains(&"github.com".to_string()));
+            }
+            AllowPolicy::All => panic!("expected allow list"),
+        }
+    }
+
+    #[test]
+    fn cargo_policy_merges_workspace_config() {
+        let dir = workspace();
+        fs::write(
+            dir.path().join("mori.toml"),
+            "[network]\nallow = [\"mirror.example.com\"]\n",
+        )
+        .unwrap();
+
+        let policy = cargo_policy(dir.path(), None, None).unwrap();
+        match policy.network.policy {
+            AllowPolicy::Entries {
+                allowed_domains, ..
+            } => {
+                assert!(allowed_domains.contains(&"mirror.example.com".to_string()));
+                assert!(allowed_domains.contains(&"crates.io".to_string()));
+            }
+            AllowPolicy::All => panic!("expected allow list"),
+        }
+    }
+}
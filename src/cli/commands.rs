@@ -0,0 +1,299 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use clap::CommandFactory;
+
+use crate::{audit::Observations, convert, error::MoriError, export, policy::Policy};
+
+#[cfg(target_os = "linux")]
+use crate::runtime::{Diagnostic, DiagnosticStatus};
+
+use super::{
+    args::{
+        Args, CompletionsArgs, ConvertArgs, DEFAULT_FILE_INVENTORY, DEFAULT_FLOW_LOG, ExportArgs,
+        GitHookCommand, MangenArgs, PolicyCommand, PolicyFormat, PolicyFromArgs,
+    },
+    git_hook::{self, HookStatus},
+    loader::PolicyLoader,
+    mangen,
+};
+
+/// Run `mori check`: report how this system enforces policies
+///
+/// On Linux, every probe of `runtime::preflight` is printed with how to fix
+/// it; returns false when one failed.
+pub fn check() -> Result<bool, MoriError> {
+    #[cfg(target_os = "linux")]
+    let diagnostics = crate::runtime::preflight();
+    #[cfg(target_os = "linux")]
+    print!("{}", render_diagnostics(&diagnostics));
+    #[cfg(target_os = "linux")]
+    let backend = if unsafe { libc::geteuid() } == 0 {
+        "eBPF (cgroup and LSM programs)"
+    } else {
+        "unprivileged (Landlock, seccomp and user namespaces)"
+    };
+    #[cfg(target_os = "macos")]
+    let backend = "sandbox-exec";
+    #[cfg(target_os = "windows")]
+    let backend = "job objects and WFP filters (network rules only)";
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    let backend = "none; policies are not enforced on this platform";
+    println!("backend: {}", backend);
+    #[cfg(target_os = "linux")]
+    return Ok(diagnostics
+        .iter()
+        .all(|diagnostic| diagnostic.status != DiagnosticStatus::Fail));
+    #[cfg(not(target_os = "linux"))]
+    Ok(true)
+}
+
+/// One line per probe, followed by the remedy for problems
+#[cfg(target_os = "linux")]
+fn render_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        let status = match diagnostic.status {
+            DiagnosticStatus::Ok => "ok",
+            DiagnosticStatus::Warn => "WARN",
+            DiagnosticStatus::Fail => "FAIL",
+        };
+        out.push_str(&format!(
+            "{:<5} {}: {}\n",
+            status, diagnostic.name, diagnostic.detail
+        ));
+        if let Some(remedy) = &diagnostic.remedy {
+            out.push_str(&format!("      fix: {}\n", remedy));
+        }
+    }
+    out
+}
+
+/// Run `mori policy`
+pub fn policy(command: &PolicyCommand) -> Result<(), MoriError> {
+    match command {
+        PolicyCommand::Show(args) => {
+            let policy = PolicyLoader::load(&args.policy_args)?;
+            match args.format {
+                PolicyFormat::Text => print!("{}", describe_policy(&policy)),
+                PolicyFormat::Json => println!("{}", policy.to_json()),
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Run `mori convert`: print the converted configuration to stdout
+///
+/// Rules that could not be expressed are reported on stderr so the TOML output
+/// can be redirected straight into a config file.
+pub fn convert(args: &ConvertArgs) -> Result<(), MoriError> {
+    let conversion = match &args.input {
+        Some(_) if args.from.is_invocation() => {
+            return Err(MoriError::ConvertInput {
+                reason: "pass the command line after -- instead of a file".to_string(),
+            });
+        }
+        Some(input) => convert::convert_file(args.from, input)?,
+        None => convert::convert_invocation(args.from, &args.invocation)?,
+    };
+
+    for skipped in &conversion.skipped {
+        eprintln!("mori: skipped {}", skipped);
+    }
+
+    print!("{}", conversion.config.to_toml()?);
+    Ok(())
+}
+
+/// Run `mori export`: render the policy for another security module
+///
+/// Generated files are printed to stdout unless an output directory is given.
+/// Rules that could only be approximated are reported on stderr.
+pub fn export(args: &ExportArgs) -> Result<(), MoriError> {
+    let policy = match &args.config {
+        Some(path) => PolicyLoader::load_config(path)?,
+        None => Policy::new(),
+    };
+
+    let export = export::export(&policy, args.format, &args.name);
+    for note in &export.notes {
+        eprintln!("mori: approximated {}", note);
+    }
+
+    for file in &export.files {
+        match &args.output_dir {
+            Some(dir) => {
+                let path = dir.join(&file.file_name);
+                fs::write(&path, &file.content)?;
+                eprintln!("mori: wrote {}", path.display());
+            }
+            None => print!("{}", file.content),
+        }
+    }
+    Ok(())
+}
+
+/// Run `mori git-hook`
+pub fn git_hook(command: &GitHookCommand) -> Result<(), MoriError> {
+    match command {
+        GitHookCommand::Install(args) => {
+            let hooks_dir = git_hook::hooks_dir(&args.repo)?;
+            let mori = std::env::current_exe()?;
+            for (hook, status) in git_hook::install(&hooks_dir, &mori, &args.hooks)? {
+                match status {
+                    HookStatus::Wrapped => eprintln!("mori: wrapped {} hook", hook),
+                    HookStatus::Updated => eprintln!("mori: updated {} hook", hook),
+                    HookStatus::Missing => eprintln!("mori: no {} hook, skipped", hook),
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Run `mori completions`: print the completion script for the shell
+pub fn completions(args: &CompletionsArgs) -> Result<(), MoriError> {
+    write_completions(args.shell, &mut std::io::stdout())?;
+    Ok(())
+}
+
+/// Run `mori mangen`: write the man pages into the output directory
+pub fn mangen(args: &MangenArgs) -> Result<(), MoriError> {
+    for page in mangen::generate(&args.output_dir)? {
+        eprintln!("mori: wrote {}", page.display());
+    }
+    Ok(())
+}
+
+/// Run `mori policy-from` (or its alias `mori generate`): aggregate the
+/// flow logs and file inventories recorded by `mori trace`, or by runs with
+/// `--flow-log` and `--file-inventory`, into a configuration
+///
+/// Without any file given, the files `mori trace` writes by default are read.
+/// The configuration is printed to stdout unless an output file is given.
+pub fn policy_from(args: &PolicyFromArgs) -> Result<(), MoriError> {
+    let (flow_logs, file_inventories) = recorded_files(args);
+    let mut observations = Observations::default();
+    for flow_log in &flow_logs {
+        observations.add_flow_log(flow_log)?;
+    }
+    for file_inventory in &file_inventories {
+        observations.add_file_inventory(file_inventory)?;
+    }
+
+    let toml = observations.to_config().to_toml()?;
+    match &args.out {
+        Some(path) => {
+            fs::write(path, toml)?;
+            eprintln!("mori: wrote {}", path.display());
+        }
+        None => print!("{}", toml),
+    }
+    Ok(())
+}
+
+/// Flow logs and file inventories `mori policy-from` reads
+fn recorded_files(args: &PolicyFromArgs) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    if args.flow_logs.is_empty() && args.file_inventories.is_empty() {
+        (
+            vec![PathBuf::from(DEFAULT_FLOW_LOG)],
+            vec![PathBuf::from(DEFAULT_FILE_INVENTORY)],
+        )
+    } else {
+        (args.flow_logs.clone(), args.file_inventories.clone())
+    }
+}
+
+/// The policy's restrictions, one per line
+fn describe_policy(policy: &Policy) -> String {
+    let restrictions = policy.restrictions();
+    if restrictions.is_empty() {
+        return "no restrictions\n".to_string();
+    }
+    restrictions
+        .iter()
+        .map(|restriction| format!("{}\n", restriction))
+        .collect()
+}
+
+fn write_completions(shell: clap_complete::Shell, out: &mut impl Write) -> std::io::Result<()> {
+    let mut command = Args::command();
+    clap_complete::generate(shell, &mut command, "mori", out);
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::NetworkPolicy;
+    use clap_complete::Shell;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::bash(Shell::Bash)]
+    #[case::zsh(Shell::Zsh)]
+    #[case::fish(Shell::Fish)]
+    fn completions_cover_flags_and_subcommands(#[case] shell: Shell) {
+        let mut out = Vec::new();
+        write_completions(shell, &mut out).unwrap();
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("allow-network-all"));
+        assert!(script.contains("git-hook"));
+        assert!(script.contains("policy"));
+    }
+
+    #[test]
+    fn recorded_files_default_to_trace_output() {
+        let mut args = PolicyFromArgs {
+            flow_logs: Vec::new(),
+            file_inventories: Vec::new(),
+            out: None,
+        };
+        assert_eq!(
+            recorded_files(&args),
+            (
+                vec![PathBuf::from("mori-flows.jsonl")],
+                vec![PathBuf::from("mori-files.jsonl")]
+            )
+        );
+
+        args.flow_logs.push(PathBuf::from("run1.jsonl"));
+        assert_eq!(
+            recorded_files(&args),
+            (vec![PathBuf::from("run1.jsonl")], Vec::new())
+        );
+    }
+
+    #[test]
+    fn describe_policy_lists_restrictions() {
+        let mut policy = Policy::with_network(NetworkPolicy::from_allow_all(true));
+        assert_eq!(describe_policy(&policy), "no restrictions\n");
+
+        policy.file.deny_read("/etc/shadow");
+        assert!(describe_policy(&policy).contains("file: deny read /etc/shadow\n"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn render_diagnostics_adds_remedies() {
+        let diagnostics = [
+            Diagnostic {
+                name: "btf",
+                status: DiagnosticStatus::Ok,
+                detail: "/sys/kernel/btf/vmlinux is present".to_string(),
+                remedy: None,
+            },
+            Diagnostic {
+                name: "cgroup v2",
+                status: DiagnosticStatus::Fail,
+                detail: "no cgroup2 file system is mounted".to_string(),
+                remedy: Some("mount it".to_string()),
+            },
+        ];
+        assert_eq!(
+            render_diagnostics(&diagnostics),
+            "ok    btf: /sys/kernel/btf/vmlinux is present\n\
+             FAIL  cgroup v2: no cgroup2 file system is mounted\n      fix: mount it\n"
+        );
+    }
+}
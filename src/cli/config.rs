@@ -1,18 +1,29 @@
 use std::{
+    collections::BTreeMap,
     fs,
+    net::{IpAddr, Ipv4Addr},
     path::{Path, PathBuf},
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::{error::MoriError, policy::NetworkPolicy};
+use crate::{
+    convert::docker_seccomp,
+    error::MoriError,
+    policy::{
+        DEFAULT_SECCOMP_PROFILE, DeniedDevice, DenyList, DnsPolicy, FilePolicy, InboundPolicy,
+        NetworkPolicy, ProcessPolicy, SeccompAction, SeccompPolicy, SocketFamily, UnixPolicy,
+    },
+};
 
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct ConfigFile {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "NetworkConfig::is_default")]
     pub network: NetworkConfig,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "FileConfig::is_empty")]
     pub file: FileConfig,
+    #[serde(default, skip_serializing_if = "ProcessConfig::is_empty")]
+    pub process: ProcessConfig,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -20,16 +31,193 @@ pub struct NetworkConfig {
     /// Allowed network destinations (bool for allow-all/deny-all, or Vec<String> for specific destinations)
     #[serde(default)]
     pub allow: AllowConfig,
+    /// Curated destination presets to allow (e.g. "crates", "pypi")
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_presets: Vec<String>,
+    /// Destinations denied even when `allow` covers them (IPs, CIDR ranges and domains)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// Socket families the command may not create (Linux only); defaults to
+    /// netlink and packet while outbound connections are restricted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deny_families: Option<Vec<SocketFamily>>,
+    /// Whether the command may ping (ICMP echo) any host; defaults to allowed
+    /// only when all outbound connections are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_icmp: Option<bool>,
+    /// Domains allowed through fixed IPv4 addresses instead of DNS resolution
+    /// (for split-horizon DNS, where mori's view differs from the command's)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pins: BTreeMap<String, Vec<Ipv4Addr>>,
+    /// Static hostname to address mappings, like /etc/hosts: the addresses
+    /// are allowed and named after the host without any DNS lookup (for
+    /// air-gapped environments and hosts missing from public DNS)
+    #[serde(
+        default,
+        deserialize_with = "deserialize_hosts",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub hosts: BTreeMap<String, Vec<IpAddr>>,
+    /// Peers allowed to connect to sockets the command listens on
+    /// (inbound connections are not filtered without this table)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbound: Option<InboundConfig>,
+    /// Ports the command may listen on (listening is only restricted by
+    /// this key or by outbound network rules)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_listen: Option<Vec<u16>>,
+    /// Connections to abstract unix sockets (Linux only; unrestricted
+    /// without this table)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix: Option<UnixConfig>,
+    /// Nameservers mori resolves through, and whether the command is held
+    /// to them (Linux only; the system resolver configuration without this
+    /// table)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsConfig>,
+    /// Deny TLS connections whose server name (SNI) is not an allowed
+    /// domain, so a domain does not allow every site sharing its addresses
+    /// (Linux only)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enforce_sni: bool,
+    /// Whether localhost (127.0.0.1 and ::1) is reachable without being
+    /// listed in `allow`; defaults to true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_localhost: Option<bool>,
+    /// Abort instead of running when an allowed or denied domain has no
+    /// address at startup, after retrying its lookup for a while
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict: bool,
+    /// Seconds between lookups of allowed domains that have no DNS records
+    /// cached (Linux only; 30 by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_interval: Option<u64>,
+    /// Lower bound in seconds on the TTL of DNS records, so tiny TTLs do not
+    /// cause constant lookups (Linux only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_ttl: Option<u64>,
+    /// Upper bound in seconds on the TTL of DNS records, so address changes
+    /// are picked up sooner (Linux only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_ttl: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct InboundConfig {
+    /// IPv4 and IPv6 addresses and CIDR ranges of the allowed peers
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct UnixConfig {
+    /// Deny abstract socket names missing from `allow`
+    #[serde(default)]
+    pub deny_abstract: bool,
+    /// Abstract socket names (`@name`) allowed while `deny_abstract` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Abstract socket names (`@name`) that are always denied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DnsConfig {
+    /// Only allow port 53 to the nameservers and block known
+    /// DNS-over-HTTPS/TLS resolvers that are not explicitly allowed
+    #[serde(default)]
+    pub enforce: bool,
+    /// Nameservers used instead of the ones in the system configuration
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nameservers: Vec<IpAddr>,
 }
 
 impl Default for NetworkConfig {
     fn default() -> Self {
         Self {
             allow: AllowConfig::Boolean(false),
+            allow_presets: Vec::new(),
+            deny: Vec::new(),
+            deny_families: None,
+            allow_icmp: None,
+            pins: BTreeMap::new(),
+            hosts: BTreeMap::new(),
+            inbound: None,
+            allow_listen: None,
+            unix: None,
+            dns: None,
+            enforce_sni: false,
+            allow_localhost: None,
+            strict: false,
+            refresh_interval: None,
+            min_ttl: None,
+            max_ttl: None,
         }
     }
 }
 
+impl NetworkConfig {
+    /// Check if this is the default deny-all configuration
+    pub fn is_default(&self) -> bool {
+        matches!(self.allow, AllowConfig::Boolean(false))
+            && self.allow_presets.is_empty()
+            && self.deny.is_empty()
+            && self.deny_families.is_none()
+            && self.allow_icmp.is_none()
+            && self.pins.is_empty()
+            && self.hosts.is_empty()
+            && self.inbound.is_none()
+            && self.allow_listen.is_none()
+            && self.unix.is_none()
+            && self.dns.is_none()
+            && !self.enforce_sni
+            && self.allow_localhost.is_none()
+            && !self.strict
+            && self.refresh_interval.is_none()
+            && self.min_ttl.is_none()
+            && self.max_ttl.is_none()
+    }
+}
+
+/// A `[network.hosts]` value: one address, several, or a table of names
+/// (`internal.db = "10.1.2.3"` is a dotted key, so TOML makes it the
+/// table `internal` with the key `db`)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HostEntry {
+    Addr(IpAddr),
+    Addrs(Vec<IpAddr>),
+    Names(BTreeMap<String, HostEntry>),
+}
+
+/// Read `[network.hosts]`, joining dotted keys back into hostnames
+fn deserialize_hosts<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Vec<IpAddr>>, D::Error> {
+    fn flatten(
+        prefix: Option<&str>,
+        entries: BTreeMap<String, HostEntry>,
+        hosts: &mut BTreeMap<String, Vec<IpAddr>>,
+    ) {
+        for (name, entry) in entries {
+            let name = match prefix {
+                Some(prefix) => format!("{}.{}", prefix, name),
+                None => name,
+            };
+            match entry {
+                HostEntry::Addr(addr) => hosts.entry(name).or_default().push(addr),
+                HostEntry::Addrs(addrs) => hosts.entry(name).or_default().extend(addrs),
+                HostEntry::Names(names) => flatten(Some(&name), names, hosts),
+            }
+        }
+    }
+
+    let mut hosts = BTreeMap::new();
+    flatten(None, BTreeMap::deserialize(deserializer)?, &mut hosts);
+    Ok(hosts)
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum AllowConfig {
@@ -46,14 +234,151 @@ impl Default for AllowConfig {
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct FileConfig {
     /// Deny file read/write access to the specified paths
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub deny: Vec<PathBuf>,
     /// Deny file read access to the specified paths
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub deny_read: Vec<PathBuf>,
     /// Deny file write access to the specified paths
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub deny_write: Vec<PathBuf>,
+    /// Deny executing, or mapping as executable code, the specified paths
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_execute: Vec<PathBuf>,
+    /// Make the paths denied for reading look missing (Linux)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hide_denied: bool,
+    /// Deny opening device nodes: "block", "char" or device paths like "/dev/sd*"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_devices: Vec<DeniedDevice>,
+    /// Deny opening /proc/<pid> of processes outside the sandbox (Linux)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private_proc: bool,
+    /// Also deny and allow the targets of symlinked paths (Linux)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resolve_symlinks: bool,
+    /// Allow access only to the specified paths and the system libraries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<PathBuf>,
+}
+
+impl FileConfig {
+    /// Check if no file rule is configured
+    pub fn is_empty(&self) -> bool {
+        self.deny.is_empty()
+            && self.deny_read.is_empty()
+            && self.deny_write.is_empty()
+            && self.deny_execute.is_empty()
+            && !self.hide_denied
+            && self.deny_devices.is_empty()
+            && !self.private_proc
+            && self.allow.is_empty()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct ProcessConfig {
+    /// Deny executing the specified programs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_exec: Vec<PathBuf>,
+    /// Allow executing only the specified programs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_exec: Vec<PathBuf>,
+    /// System call filter: `"default"`, the path of a Docker seccomp profile,
+    /// or a `[process.seccomp]` table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seccomp: Option<SeccompSetting>,
+    /// Most processes and threads the command may have at once (Linux)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pids: Option<u32>,
+    /// Deny creating or joining namespaces (Linux)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny_namespaces: bool,
+    /// Pass only these environment variables to the command (`NAME` or `PREFIX*`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_allow: Vec<String>,
+    /// Remove these environment variables before the command starts (`NAME` or `PREFIX*`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_deny: Vec<String>,
+}
+
+impl ProcessConfig {
+    /// Check if no process rule is configured
+    pub fn is_empty(&self) -> bool {
+        self.deny_exec.is_empty()
+            && self.allow_exec.is_empty()
+            && self.seccomp.is_none()
+            && self.max_pids.is_none()
+            && !self.deny_namespaces
+            && self.env_allow.is_empty()
+            && self.env_deny.is_empty()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum SeccompSetting {
+    /// `"default"` for the built-in profile, or the path of a Docker seccomp profile (JSON)
+    Profile(String),
+    /// Filter written out in the configuration
+    Rules(SeccompConfig),
+}
+
+impl SeccompSetting {
+    /// Build the filter, reading the Docker profile if one is named
+    ///
+    /// Rules of the profile mori cannot express are skipped with a warning.
+    fn to_seccomp_policy(&self) -> Result<SeccompPolicy, MoriError> {
+        match self {
+            SeccompSetting::Profile(name) if name == DEFAULT_SECCOMP_PROFILE => {
+                Ok(SeccompPolicy::default_profile())
+            }
+            SeccompSetting::Profile(path) => {
+                let path = PathBuf::from(path);
+                let content =
+                    fs::read_to_string(&path).map_err(|source| MoriError::ProfileRead {
+                        path: path.clone(),
+                        source,
+                    })?;
+                let (rules, skipped) = match docker_seccomp::parse(&content) {
+                    Ok(parsed) => parsed,
+                    Err(reason) => return Err(MoriError::ProfileParse { path, reason }),
+                };
+                for rule in skipped {
+                    eprintln!("mori: {}: skipped {}", path.display(), rule);
+                }
+                Ok(rules.to_seccomp_policy())
+            }
+            SeccompSetting::Rules(rules) => Ok(rules.to_seccomp_policy()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq)]
+pub struct SeccompConfig {
+    /// Action for system calls not listed in `allow` or `deny`
+    #[serde(default)]
+    pub default_action: SeccompAction,
+    /// System calls that are always allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// System calls that are always denied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// System calls that always fail with the given errno instead of EPERM
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub errno: BTreeMap<String, i32>,
+}
+
+impl SeccompConfig {
+    fn to_seccomp_policy(&self) -> SeccompPolicy {
+        SeccompPolicy {
+            default_action: self.default_action,
+            allowed_syscalls: self.allow.clone(),
+            denied_syscalls: self.deny.clone(),
+            errno_syscalls: self.errno.clone(),
+        }
+    }
 }
 
 impl ConfigFile {
@@ -71,10 +396,104 @@ impl ConfigFile {
 
     /// Build network policy from configuration file
     pub fn to_policy(&self) -> Result<NetworkPolicy, MoriError> {
-        match &self.network.allow {
-            AllowConfig::Boolean(allow_all) => Ok(NetworkPolicy::from_allow_all(*allow_all)),
-            AllowConfig::Entries(entries) => NetworkPolicy::from_entries(entries),
+        let mut policy = match &self.network.allow {
+            AllowConfig::Boolean(allow_all) => NetworkPolicy::from_allow_all(*allow_all),
+            AllowConfig::Entries(entries) => NetworkPolicy::from_entries(entries)?,
+        };
+        if !self.network.allow_presets.is_empty() {
+            policy.merge(NetworkPolicy::from_presets(&self.network.allow_presets)?);
+        }
+        for (domain, addrs) in &self.network.pins {
+            let addrs: Vec<IpAddr> = addrs.iter().copied().map(IpAddr::V4).collect();
+            policy.pin_domain(domain, &addrs);
+        }
+        for (host, addrs) in &self.network.hosts {
+            policy.add_host(host, addrs);
+        }
+        policy.denied = DenyList::from_entries(&self.network.deny)?;
+        policy.denied_families = self.network.deny_families.clone();
+        policy.allow_icmp = self.network.allow_icmp;
+        policy.enforce_sni = self.network.enforce_sni;
+        policy.deny_localhost = self.network.allow_localhost == Some(false);
+        if let Some(unix) = &self.network.unix {
+            policy.unix = UnixPolicy::from_entries(unix.deny_abstract, &unix.allow, &unix.deny)?;
+        }
+        if let Some(dns) = &self.network.dns {
+            policy.dns = DnsPolicy {
+                enforce: dns.enforce,
+                nameservers: dns.nameservers.clone(),
+                ..DnsPolicy::default()
+            };
+        }
+        policy.dns.strict = self.network.strict;
+        policy.dns.refresh_interval = self.network.refresh_interval;
+        policy.dns.min_ttl = self.network.min_ttl;
+        policy.dns.max_ttl = self.network.max_ttl;
+        Ok(policy)
+    }
+
+    /// Build inbound connection policy from configuration file
+    pub fn to_inbound_policy(&self) -> Result<InboundPolicy, MoriError> {
+        let mut policy = match &self.network.inbound {
+            Some(inbound) => InboundPolicy::from_entries(&inbound.allow)?,
+            None => InboundPolicy::default(),
+        };
+        if let Some(ports) = &self.network.allow_listen {
+            policy.merge(InboundPolicy::from_listen_ports(ports));
+        }
+        Ok(policy)
+    }
+
+    /// Build file policy from configuration file
+    pub fn to_file_policy(&self) -> FilePolicy {
+        let mut policy = FilePolicy::new();
+        for path in &self.file.deny {
+            policy.deny_read_write(path);
+        }
+        for path in &self.file.deny_read {
+            policy.deny_read(path);
+        }
+        for path in &self.file.deny_write {
+            policy.deny_write(path);
+        }
+        for path in &self.file.deny_execute {
+            policy.deny_execute(path);
+        }
+        for path in &self.file.allow {
+            policy.allow(path);
+        }
+        policy.hide_denied = self.file.hide_denied;
+        policy.denied_devices = self.file.deny_devices.clone();
+        policy.private_proc = self.file.private_proc;
+        policy.resolve_symlinks = self.file.resolve_symlinks;
+        policy
+    }
+
+    /// Build process policy from configuration file
+    pub fn to_process_policy(&self) -> Result<ProcessPolicy, MoriError> {
+        let mut policy = ProcessPolicy::new();
+        for path in &self.process.deny_exec {
+            policy.deny_exec(path);
+        }
+        for path in &self.process.allow_exec {
+            policy.allow_exec(path);
         }
+        policy.seccomp = self
+            .process
+            .seccomp
+            .as_ref()
+            .map(SeccompSetting::to_seccomp_policy)
+            .transpose()?;
+        policy.max_pids = self.process.max_pids;
+        policy.deny_namespaces = self.process.deny_namespaces;
+        policy.env_allow = self.process.env_allow.clone();
+        policy.env_deny = self.process.env_deny.clone();
+        Ok(policy)
+    }
+
+    /// Render the configuration as TOML
+    pub fn to_toml(&self) -> Result<String, MoriError> {
+        toml::to_string(self).map_err(|source| MoriError::ConfigSerialize { source })
     }
 }
 
@@ -101,6 +520,7 @@ mod tests {
                 allowed_ipv4,
                 allowed_cidr,
                 allowed_domains,
+                ..
             } => {
                 assert_eq!(allowed_ipv4.len(), 1);
                 assert_eq!(allowed_cidr.len(), 0);
@@ -110,6 +530,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn load_merges_allow_presets() {
+        use crate::policy::AllowPolicy;
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network]\nallow = [\"example.com\"]\nallow_presets = [\"pypi\"]\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        match config.to_policy().unwrap().policy {
+            AllowPolicy::Entries {
+                allowed_domains, ..
+            } => {
+                assert_eq!(allowed_domains.len(), 3);
+                for domain in ["example.com", "pypi.org", "files.pythonhosted.org"] {
+                    assert!(allowed_domains.contains(&domain.to_string()));
+                }
+            }
+            _ => panic!("Expected Entries variant"),
+        }
+    }
+
+    #[test]
+    fn load_pins_domains_to_addresses() {
+        use crate::policy::AllowPolicy;
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network]\nallow = [\"internal.corp\", \"example.com\"]\n\n[network.pins]\n\"internal.corp\" = [\"10.1.2.3\", \"10.1.2.4\"]\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        match config.to_policy().unwrap().policy {
+            AllowPolicy::Entries {
+                allowed_ipv4,
+                allowed_domains,
+                ..
+            } => {
+                assert_eq!(allowed_domains, vec!["example.com".to_string()]);
+                assert!(allowed_ipv4.contains(&Ipv4Addr::new(10, 1, 2, 3)));
+                assert!(allowed_ipv4.contains(&Ipv4Addr::new(10, 1, 2, 4)));
+            }
+            _ => panic!("Expected Entries variant"),
+        }
+        assert!(config.to_toml().unwrap().contains("[network.pins]"));
+    }
+
     #[test]
     fn load_boolean_allow_true() {
         let mut tmp = tempfile::NamedTempFile::new().unwrap();
@@ -130,6 +602,211 @@ mod tests {
         assert!(!policy.is_allow_all());
     }
 
+    #[test]
+    fn load_network_deny_list() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network]\nallow = true\ndeny = [\"10.0.0.0/8\", \"metadata.internal\"]\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_policy().unwrap();
+        assert!(policy.is_allow_all());
+        assert_eq!(
+            policy.denied.denied_cidr,
+            vec![(Ipv4Addr::new(10, 0, 0, 0), 8)]
+        );
+        assert_eq!(policy.denied.denied_domains, vec!["metadata.internal"]);
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[network]\ndeny = [\"example.com:443\"]\n").unwrap();
+        assert!(matches!(
+            ConfigFile::load(tmp.path()).unwrap().to_policy(),
+            Err(MoriError::InvalidAllowNetworkEntry { .. })
+        ));
+    }
+
+    #[test]
+    fn load_network_deny_families() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network]\nallow = true\ndeny_families = [\"packet\", \"vsock\"]\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_policy().unwrap();
+        assert_eq!(
+            policy.denied_socket_families(),
+            vec![SocketFamily::Packet, SocketFamily::Vsock]
+        );
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[network]\ndeny_families = [\"unix\"]\n").unwrap();
+        assert!(ConfigFile::load(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn load_network_allow_icmp() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network]\nallow = [\"example.com\"]\nallow_icmp = true\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert_eq!(config.network.allow_icmp, Some(true));
+        assert!(config.to_policy().unwrap().icmp_allowed());
+    }
+
+    #[test]
+    fn load_network_lookup_settings() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network]\nallow = [\"example.com\"]\nstrict = true\nrefresh_interval = 60\nmin_ttl = 10\nmax_ttl = 300\n"
+        )
+        .unwrap();
+
+        let policy = ConfigFile::load(tmp.path()).unwrap().to_policy().unwrap();
+        assert!(policy.dns.strict);
+        assert_eq!(policy.dns.refresh_interval, Some(60));
+        assert_eq!(policy.dns.min_ttl, Some(10));
+        assert_eq!(policy.dns.max_ttl, Some(300));
+    }
+
+    #[test]
+    fn load_network_enforce_sni() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network]\nallow = [\"example.com\"]\nenforce_sni = true\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(config.network.enforce_sni);
+        assert!(config.to_policy().unwrap().enforce_sni);
+    }
+
+    #[test]
+    fn load_network_allow_localhost() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[network]\nallow_localhost = false\n").unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(!config.network.is_default());
+        assert!(config.to_policy().unwrap().deny_localhost);
+        assert!(!ConfigFile::default().to_policy().unwrap().deny_localhost);
+    }
+
+    #[test]
+    fn load_network_unix_config() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            r#"
+[network.unix]
+deny_abstract = true
+allow = ["@/tmp/.X11-unix/X0"]
+"#
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_policy().unwrap();
+        assert!(policy.unix.deny_abstract);
+        assert_eq!(policy.unix.allowed_abstract, vec!["/tmp/.X11-unix/X0"]);
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[network.unix]\ndeny = [\"/run/docker.sock\"]\n").unwrap();
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(matches!(
+            config.to_policy(),
+            Err(MoriError::InvalidUnixSocketEntry { .. })
+        ));
+    }
+
+    #[test]
+    fn load_network_hosts_with_dotted_names() {
+        use crate::policy::AllowPolicy;
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            r#"
+[network.hosts]
+internal.db = "10.1.2.3"
+"registry.corp" = ["10.1.2.4", "fd00::4"]
+"#
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert_eq!(
+            config.network.hosts.keys().collect::<Vec<_>>(),
+            vec!["internal.db", "registry.corp"]
+        );
+        let policy = config.to_policy().unwrap();
+        assert_eq!(
+            policy.hosts["internal.db"],
+            vec!["10.1.2.3".parse::<IpAddr>().unwrap()]
+        );
+        match policy.policy {
+            AllowPolicy::Entries {
+                allowed_ipv4,
+                allowed_ipv6,
+                ..
+            } => {
+                assert!(allowed_ipv4.contains(&Ipv4Addr::new(10, 1, 2, 3)));
+                assert!(allowed_ipv4.contains(&Ipv4Addr::new(10, 1, 2, 4)));
+                assert_eq!(
+                    allowed_ipv6,
+                    vec!["fd00::4".parse::<std::net::Ipv6Addr>().unwrap()]
+                );
+            }
+            _ => panic!("Expected Entries variant"),
+        }
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[network.hosts]
+\"internal.db\" = \"not an address\"\n"
+        )
+        .unwrap();
+        assert!(ConfigFile::load(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn load_network_dns_config() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            r#"
+[network.dns]
+enforce = true
+nameservers = ["10.0.0.2", "fd00::53"]
+"#
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_policy().unwrap();
+        assert!(policy.dns.enforce);
+        assert_eq!(
+            policy.dns.nameservers,
+            vec![
+                "10.0.0.2".parse::<IpAddr>().unwrap(),
+                "fd00::53".parse().unwrap()
+            ]
+        );
+    }
+
     #[test]
     fn load_file_config_deny_paths() {
         let mut tmp = tempfile::NamedTempFile::new().unwrap();
@@ -140,6 +817,7 @@ mod tests {
 deny = ["/tmp/secret", "/etc/passwd"]
 deny_read = ["/home/user/.ssh"]
 deny_write = ["/var/log"]
+deny_execute = ["/tmp"]
 "#
         )
         .unwrap();
@@ -148,6 +826,92 @@ deny_write = ["/var/log"]
         assert_eq!(config.file.deny.len(), 2);
         assert_eq!(config.file.deny_read.len(), 1);
         assert_eq!(config.file.deny_write.len(), 1);
+        assert_eq!(config.file.deny_execute.len(), 1);
+
+        let policy = config.to_file_policy();
+        assert_eq!(policy.denied_paths.len(), 5);
+        assert!(
+            policy
+                .denied_paths
+                .contains(&(PathBuf::from("/tmp"), crate::policy::AccessMode::Execute))
+        );
+    }
+
+    #[test]
+    fn load_file_config_hide_denied() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[file]\ndeny_read = [\"/home/user/.ssh\"]\nhide_denied = true\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(config.to_file_policy().hide_denied);
+    }
+
+    #[test]
+    fn load_file_config_resolve_symlinks() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[file]\ndeny = [\"/var/run/secrets\"]\nresolve_symlinks = true\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_file_policy();
+        assert!(policy.resolve_symlinks);
+    }
+
+    #[test]
+    fn load_file_config_private_proc() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[file]\nprivate_proc = true\n").unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(!config.file.is_empty());
+        let policy = config.to_file_policy();
+        assert!(policy.private_proc);
+        assert!(!policy.is_empty());
+    }
+
+    #[test]
+    fn load_file_config_deny_devices() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[file]\ndeny_devices = [\"block\", \"/dev/kmsg\"]\n").unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_file_policy();
+        assert_eq!(
+            policy.denied_devices,
+            vec![
+                DeniedDevice::Block,
+                DeniedDevice::Node(PathBuf::from("/dev/kmsg"))
+            ]
+        );
+        assert!(!policy.is_empty());
+
+        let mut relative = tempfile::NamedTempFile::new().unwrap();
+        writeln!(relative, "[file]\ndeny_devices = [\"kmsg\"]\n").unwrap();
+        assert!(ConfigFile::load(relative.path()).is_err());
+    }
+
+    #[test]
+    fn load_file_config_allow_paths() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[file]\nallow = [\"/work/project\", \"/tmp/../data\"]\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(!config.file.is_empty());
+        assert_eq!(
+            config.to_file_policy().allowed_paths,
+            vec![PathBuf::from("/work/project"), PathBuf::from("/data")]
+        );
     }
 
     #[test]
@@ -160,4 +924,184 @@ deny_write = ["/var/log"]
         assert_eq!(config.file.deny_read.len(), 0);
         assert_eq!(config.file.deny_write.len(), 0);
     }
+
+    #[test]
+    fn load_network_inbound_config() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            r#"
+[network.inbound]
+allow = ["10.0.0.0/8", "192.0.2.7", "2001:db8::/32"]
+"#
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_inbound_policy().unwrap();
+        assert!(policy.restricted);
+        assert_eq!(policy.allowed_peers.len(), 2);
+        assert_eq!(policy.allowed_peers_v6.len(), 1);
+
+        let unfiltered = ConfigFile::default().to_inbound_policy().unwrap();
+        assert!(!unfiltered.restricted);
+        assert!(!unfiltered.listen_restricted);
+    }
+
+    #[test]
+    fn load_network_allow_listen() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            r#"
+[network]
+allow = true
+allow_listen = [8080, 3000]
+"#
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_inbound_policy().unwrap();
+        assert!(policy.listen_restricted);
+        assert!(!policy.restricted);
+        assert_eq!(policy.allowed_listen_ports, vec![3000, 8080]);
+    }
+
+    #[test]
+    fn load_process_config_exec_rules() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            r#"
+[process]
+deny_exec = ["/usr/bin/curl", "/usr/bin/ssh"]
+allow_exec = ["/usr/bin/make"]
+"#
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let policy = config.to_process_policy().unwrap();
+        assert_eq!(policy.denied_exec.len(), 2);
+        assert_eq!(policy.allowed_exec, vec![PathBuf::from("/usr/bin/make")]);
+    }
+
+    #[test]
+    fn load_process_config_max_pids() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[process]\nmax_pids = 128\n").unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(!config.process.is_empty());
+        assert_eq!(config.to_process_policy().unwrap().max_pids, Some(128));
+    }
+
+    #[test]
+    fn load_process_config_deny_namespaces() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[process]\ndeny_namespaces = true\n").unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(!config.process.is_empty());
+        assert!(config.to_process_policy().unwrap().deny_namespaces);
+    }
+
+    #[test]
+    fn load_process_config_env_rules() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "[process]\nenv_allow = [\"PATH\", \"HOME\"]\nenv_deny = [\"AWS_*\"]\n"
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert!(!config.process.is_empty());
+        let policy = config.to_process_policy().unwrap();
+        assert_eq!(policy.env_allow, vec!["PATH", "HOME"]);
+        assert_eq!(policy.env_deny, vec!["AWS_*"]);
+    }
+
+    #[test]
+    fn load_process_config_seccomp() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            r#"
+[process.seccomp]
+default_action = "deny"
+allow = ["read", "write"]
+"#
+        )
+        .unwrap();
+
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        let seccomp = config.to_process_policy().unwrap().seccomp.unwrap();
+        assert_eq!(seccomp.default_action, SeccompAction::Deny);
+        assert_eq!(seccomp.allowed_syscalls, vec!["read", "write"]);
+        assert!(seccomp.denied_syscalls.is_empty());
+    }
+
+    #[test]
+    fn load_process_config_seccomp_profiles() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[process]\nseccomp = \"default\"\n").unwrap();
+        let config = ConfigFile::load(tmp.path()).unwrap();
+        assert_eq!(
+            config.to_process_policy().unwrap().seccomp,
+            Some(SeccompPolicy::default_profile())
+        );
+
+        let mut profile = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            profile,
+            r#"{{"defaultAction": "SCMP_ACT_ALLOW", "syscalls": [{{"names": ["bpf"], "action": "SCMP_ACT_ERRNO"}}]}}"#
+        )
+        .unwrap();
+        let config = ConfigFile {
+            process: ProcessConfig {
+                seccomp: Some(SeccompSetting::Profile(
+                    profile.path().display().to_string(),
+                )),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let seccomp = config.to_process_policy().unwrap().seccomp.unwrap();
+        assert_eq!(seccomp.denied_syscalls, vec!["bpf"]);
+
+        let config = ConfigFile {
+            process: ProcessConfig {
+                seccomp: Some(SeccompSetting::Profile("/nonexistent/seccomp.json".into())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.to_process_policy(),
+            Err(MoriError::ProfileRead { .. })
+        ));
+    }
+
+    #[test]
+    fn to_toml_omits_default_sections() {
+        let config = ConfigFile {
+            process: ProcessConfig {
+                seccomp: Some(SeccompSetting::Rules(SeccompConfig {
+                    default_action: SeccompAction::Allow,
+                    allow: vec![],
+                    deny: vec!["ptrace".to_string()],
+                    errno: BTreeMap::from([("clone3".to_string(), 38)]),
+                })),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.to_toml().unwrap(),
+            "[process.seccomp]\ndefault_action = \"allow\"\ndeny = [\"ptrace\"]\n\n[process.seccomp.errno]\nclone3 = 38\n"
+        );
+    }
 }
@@ -0,0 +1,192 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::error::MoriError;
+
+/// Marker identifying hook scripts written by `mori git-hook install`
+const WRAPPER_MARKER: &str = "# Installed by `mori git-hook install`";
+
+/// Suffix of the original hook a wrapper runs
+const ORIGINAL_SUFFIX: &str = "mori-orig";
+
+/// What happened to a hook during installation
+#[derive(Debug, PartialEq, Eq)]
+pub enum HookStatus {
+    /// The existing hook was moved aside and replaced with a wrapper
+    Wrapped,
+    /// The hook already was a wrapper; it was rewritten in place
+    Updated,
+    /// There was no hook to wrap
+    Missing,
+}
+
+/// Find the hooks directory git uses for the repository at `repo` (honours `core.hooksPath`)
+pub fn hooks_dir(repo: &Path) -> Result<PathBuf, MoriError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["rev-parse", "--path-format=absolute", "--git-path", "hooks"])
+        .output()
+        .map_err(|source| MoriError::CommandSpawn {
+            command: "git".to_string(),
+            source,
+        })?;
+    if !output.status.success() {
+        return Err(MoriError::GitHooks {
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim(),
+    ))
+}
+
+/// Wrap each of `hooks` in `hooks_dir` so it runs under `mori`
+///
+/// The original hook is renamed to `<hook>.mori-orig` and replaced with a
+/// script that runs it through `mori` with the `mori.toml` at the top of the
+/// working tree, or with mori's default policy when there is none. Running
+/// this again only refreshes the wrappers.
+pub fn install(
+    hooks_dir: &Path,
+    mori: &Path,
+    hooks: &[String],
+) -> Result<Vec<(String, HookStatus)>, MoriError> {
+    let mut results = Vec::with_capacity(hooks.len());
+    for hook in hooks {
+        let path = hooks_dir.join(hook);
+        let original = hooks_dir.join(format!("{}.{}", hook, ORIGINAL_SUFFIX));
+
+        let status = match fs::read(&path) {
+            Ok(content) if is_wrapper(&content) => HookStatus::Updated,
+            Ok(_) => {
+                fs::rename(&path, &original)?;
+                HookStatus::Wrapped
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                results.push((hook.clone(), HookStatus::Missing));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        fs::write(&path, wrapper_script(mori))?;
+        set_executable(&path)?;
+        results.push((hook.clone(), status));
+    }
+    Ok(results)
+}
+
+fn is_wrapper(content: &[u8]) -> bool {
+    String::from_utf8_lossy(content)
+        .lines()
+        .nth(1)
+        .is_some_and(|line| line == WRAPPER_MARKER)
+}
+
+/// Shell script running the hook's original next to it through `mori`
+fn wrapper_script(mori: &Path) -> String {
+    let mori = shell_quote(&mori.to_string_lossy());
+    format!(
+        r#"#!/bin/sh
+{marker}
+# Runs the original hook under the project's mori.toml policy
+hook="$0.{suffix}"
+[ -x "$hook" ] || exit 0
+config="$(git rev-parse --show-toplevel)/mori.toml"
+if [ -f "$config" ]; then
+    exec {mori} --config "$config" -- "$hook" "$@"
+fi
+exec {mori} -- "$hook" "$@"
+"#,
+        marker = WRAPPER_MARKER,
+        suffix = ORIGINAL_SUFFIX
+    )
+}
+
+/// Quote a string for POSIX sh
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn hooks(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn install_wraps_existing_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("pre-commit"), "#!/bin/sh\nmake lint\n").unwrap();
+
+        let results = install(
+            dir.path(),
+            Path::new("/usr/local/bin/mori"),
+            &hooks(&["pre-commit", "post-checkout"]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                ("pre-commit".to_string(), HookStatus::Wrapped),
+                ("post-checkout".to_string(), HookStatus::Missing),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("pre-commit.mori-orig")).unwrap(),
+            "#!/bin/sh\nmake lint\n"
+        );
+        let wrapper = fs::read_to_string(dir.path().join("pre-commit")).unwrap();
+        assert!(wrapper.contains("exec '/usr/local/bin/mori' --config \"$config\""));
+        assert!(!dir.path().join("post-checkout").exists());
+    }
+
+    #[test]
+    fn install_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("pre-commit"), "#!/bin/sh\nmake lint\n").unwrap();
+        let names = hooks(&["pre-commit"]);
+
+        install(dir.path(), Path::new("/old/mori"), &names).unwrap();
+        let results = install(dir.path(), Path::new("/new/mori"), &names).unwrap();
+
+        assert_eq!(
+            results,
+            vec![("pre-commit".to_string(), HookStatus::Updated)]
+        );
+        // The original hook is not overwritten by the first wrapper
+        assert_eq!(
+            fs::read_to_string(dir.path().join("pre-commit.mori-orig")).unwrap(),
+            "#!/bin/sh\nmake lint\n"
+        );
+        let wrapper = fs::read_to_string(dir.path().join("pre-commit")).unwrap();
+        assert!(wrapper.contains("'/new/mori'"));
+        assert!(!wrapper.contains("'/old/mori'"));
+    }
+
+    #[rstest]
+    #[case("/usr/bin/mori", "'/usr/bin/mori'")]
+    #[case("/opt/it's/mori", r"'/opt/it'\''s/mori'")]
+    fn shell_quote_escapes_single_quotes(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(shell_quote(value), expected);
+    }
+}
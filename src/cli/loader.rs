@@ -1,25 +1,54 @@
+use std::{collections::BTreeMap, net::IpAddr, path::Path};
+
 use crate::error::MoriError;
-use crate::policy::{FilePolicy, NetworkPolicy, Policy};
+use crate::policy::{FilePolicy, InboundPolicy, NetworkPolicy, Policy, ProcessPolicy};
 
-use super::args::Args;
+use super::args::PolicyArgs;
 use super::config::ConfigFile;
+use super::preset::preset_policy;
 
 /// Load and merge policies from command line arguments and config file
 pub struct PolicyLoader;
 
 impl PolicyLoader {
     /// Load complete policy from CLI arguments
-    pub fn load(args: &Args) -> Result<Policy, MoriError> {
+    pub fn load(args: &PolicyArgs) -> Result<Policy, MoriError> {
         let mut network_policy = NetworkPolicy::from_allow_all(args.allow_network_all);
 
         let mut file_policy = FilePolicy::new();
 
+        let mut process_policy = ProcessPolicy::new();
+
+        let mut inbound_policy = InboundPolicy::default();
+
+        if let Some(preset) = args.preset {
+            let preset = preset_policy(preset)?;
+            network_policy.merge(preset.network);
+            file_policy.merge(preset.file);
+            process_policy.merge(preset.process);
+            inbound_policy.merge(preset.inbound);
+        }
+
+        if let Some(path) = args.policy.as_ref() {
+            let policy = Self::load_policy(path)?;
+            network_policy.merge(policy.network);
+            file_policy.merge(policy.file);
+            process_policy.merge(policy.process);
+            inbound_policy.merge(policy.inbound);
+        }
+
         // Load configuration file if specified
+        let mut pins = BTreeMap::new();
+        let mut hosts = BTreeMap::new();
         if let Some(config_path) = args.config.as_ref() {
             let config = ConfigFile::load(config_path)?;
-            let config_network_policy = config.to_policy()?;
-            network_policy.merge(config_network_policy);
-            // TODO: Load file policy from config file
+            pins = config.network.pins.clone();
+            hosts = config.network.hosts.clone();
+            let config = Self::config_policy(&config)?;
+            network_policy.merge(config.network);
+            file_policy.merge(config.file);
+            process_policy.merge(config.process);
+            inbound_policy.merge(config.inbound);
         }
 
         // Load policies from CLI arguments
@@ -29,6 +58,30 @@ impl PolicyLoader {
             let cli_network_policy = NetworkPolicy::from_entries(&args.allow_network)?;
             network_policy.merge(cli_network_policy);
         }
+        #[cfg(not(target_os = "macos"))]
+        network_policy
+            .denied
+            .merge(crate::policy::DenyList::from_entries(&args.deny_network)?);
+        #[cfg(not(target_os = "macos"))]
+        if args.strict_dns {
+            network_policy.dns.strict = true;
+        }
+        #[cfg(not(target_os = "macos"))]
+        if args.no_allow_localhost {
+            network_policy.deny_localhost = true;
+        }
+        // Pinned domains and static hosts are never resolved, wherever they were allowed
+        for (domain, addrs) in &pins {
+            let addrs: Vec<IpAddr> = addrs.iter().copied().map(IpAddr::V4).collect();
+            network_policy.pin_domain(domain, &addrs);
+        }
+        for (host, addrs) in &hosts {
+            network_policy.add_host(host, addrs);
+        }
+
+        if !args.allow_listen.is_empty() {
+            inbound_policy.merge(InboundPolicy::from_listen_ports(&args.allow_listen));
+        }
 
         // File policy (deny-list mode) - available on all platforms
         for path in &args.deny_file {
@@ -41,10 +94,53 @@ impl PolicyLoader {
             file_policy.deny_write(path);
         }
 
+        // Process policy - available on all platforms
+        for path in &args.deny_exec {
+            process_policy.deny_exec(path);
+        }
+        for path in &args.allow_exec_only {
+            process_policy.allow_exec(path);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(max) = args.max_pids {
+            process_policy.max_pids = Some(max);
+        }
+        #[cfg(target_os = "linux")]
+        if args.deny_namespaces {
+            process_policy.deny_namespaces = true;
+        }
+
         Ok(Policy {
             network: network_policy,
             file: file_policy,
-            ..Default::default()
+            process: process_policy,
+            inbound: inbound_policy,
+        })
+    }
+
+    /// Load a policy written in mori's JSON schema
+    pub fn load_policy(path: &Path) -> Result<Policy, MoriError> {
+        let content = std::fs::read_to_string(path).map_err(|source| MoriError::ConfigRead {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Policy::from_json(&content).map_err(|source| MoriError::PolicyParse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Load the policy defined by a configuration file alone
+    pub fn load_config(path: &Path) -> Result<Policy, MoriError> {
+        Self::config_policy(&ConfigFile::load(path)?)
+    }
+
+    fn config_policy(config: &ConfigFile) -> Result<Policy, MoriError> {
+        Ok(Policy {
+            network: config.to_policy()?,
+            file: config.to_file_policy(),
+            process: config.to_process_policy()?,
+            inbound: config.to_inbound_policy()?,
         })
     }
 }
@@ -55,15 +151,29 @@ mod tests {
 
     #[test]
     fn load_creates_allow_all_policy() {
-        let args = Args {
+        let args = PolicyArgs {
             config: None,
+            policy: None,
+            preset: None,
             #[cfg(not(target_os = "macos"))]
             allow_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            deny_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            strict_dns: false,
+            #[cfg(not(target_os = "macos"))]
+            no_allow_localhost: false,
+            allow_listen: vec![],
             allow_network_all: true,
             deny_file: vec![],
             deny_file_read: vec![],
             deny_file_write: vec![],
-            command: vec!["echo".to_string(), "test".to_string()],
+            deny_exec: vec![],
+            allow_exec_only: vec![],
+            #[cfg(target_os = "linux")]
+            max_pids: None,
+            #[cfg(target_os = "linux")]
+            deny_namespaces: false,
         };
 
         let policy = PolicyLoader::load(&args).unwrap();
@@ -72,18 +182,131 @@ mod tests {
 
     #[test]
     fn load_creates_deny_all_policy() {
-        let args = Args {
+        let args = PolicyArgs {
             config: None,
+            policy: None,
+            preset: None,
             #[cfg(not(target_os = "macos"))]
             allow_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            deny_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            strict_dns: false,
+            #[cfg(not(target_os = "macos"))]
+            no_allow_localhost: false,
+            allow_listen: vec![],
             allow_network_all: false,
             deny_file: vec![],
             deny_file_read: vec![],
             deny_file_write: vec![],
-            command: vec!["echo".to_string(), "test".to_string()],
+            deny_exec: vec![],
+            allow_exec_only: vec![],
+            #[cfg(target_os = "linux")]
+            max_pids: None,
+            #[cfg(target_os = "linux")]
+            deny_namespaces: false,
         };
 
         let policy = PolicyLoader::load(&args).unwrap();
         assert!(!policy.network.is_allow_all());
     }
+
+    #[test]
+    fn load_collects_exec_rules() {
+        let args = PolicyArgs {
+            config: None,
+            policy: None,
+            preset: None,
+            #[cfg(not(target_os = "macos"))]
+            allow_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            deny_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            strict_dns: false,
+            #[cfg(not(target_os = "macos"))]
+            no_allow_localhost: false,
+            allow_listen: vec![],
+            allow_network_all: false,
+            deny_file: vec![],
+            deny_file_read: vec![],
+            deny_file_write: vec![],
+            deny_exec: vec!["/usr/bin/curl".into()],
+            allow_exec_only: vec!["/usr/bin/make".into(), "/usr/bin/curl".into()],
+            #[cfg(target_os = "linux")]
+            max_pids: None,
+            #[cfg(target_os = "linux")]
+            deny_namespaces: false,
+        };
+
+        let policy = PolicyLoader::load(&args).unwrap();
+        assert_eq!(policy.process.denied_exec.len(), 1);
+        assert_eq!(policy.process.allowed_exec.len(), 2);
+    }
+
+    #[test]
+    fn load_merges_file_rules_from_config() {
+        use std::io::Write;
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "[file]\ndeny_read = [\"/etc/shadow\"]\n").unwrap();
+
+        let args = PolicyArgs {
+            config: Some(tmp.path().to_path_buf()),
+            policy: None,
+            preset: None,
+            #[cfg(not(target_os = "macos"))]
+            allow_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            deny_network: vec![],
+            #[cfg(not(target_os = "macos"))]
+            strict_dns: false,
+            #[cfg(not(target_os = "macos"))]
+            no_allow_localhost: false,
+            allow_listen: vec![],
+            allow_network_all: false,
+            deny_file: vec![],
+            deny_file_read: vec![],
+            deny_file_write: vec!["/var/log".into()],
+            deny_exec: vec![],
+            allow_exec_only: vec![],
+            #[cfg(target_os = "linux")]
+            max_pids: None,
+            #[cfg(target_os = "linux")]
+            deny_namespaces: false,
+        };
+
+        let policy = PolicyLoader::load(&args).unwrap();
+        assert_eq!(
+            policy.file.denied_paths,
+            vec![
+                ("/etc/shadow".into(), crate::policy::AccessMode::Read),
+                ("/var/log".into(), crate::policy::AccessMode::Write),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_policy_reads_json_schema() {
+        use std::io::Write;
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            tmp,
+            r#"{{"network": {{"mode": "all"}}, "file": {{"denied_paths": [{{"path": "/etc/shadow", "access": "read"}}]}}}}"#
+        )
+        .unwrap();
+
+        let policy = PolicyLoader::load_policy(tmp.path()).unwrap();
+        assert!(policy.network.is_allow_all());
+        assert_eq!(
+            policy.file.denied_paths,
+            vec![("/etc/shadow".into(), crate::policy::AccessMode::Read)]
+        );
+
+        writeln!(tmp, "not json").unwrap();
+        assert!(matches!(
+            PolicyLoader::load_policy(tmp.path()),
+            Err(MoriError::PolicyParse { .. })
+        ));
+    }
 }
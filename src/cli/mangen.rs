@@ -0,0 +1,217 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use clap::CommandFactory;
+
+use super::args::Args;
+
+/// File name of the configuration file manual page
+const CONFIG_PAGE_NAME: &str = "mori.toml.5";
+
+/// mori.toml(5), written by hand since the format has no clap definition
+const CONFIG_PAGE: &str = r#".TH mori.toml 5 "" "mori" "File Formats Manual"
+.SH NAME
+mori.toml \- configuration file for mori
+.SH SYNOPSIS
+.B mori \-\-config
+.I mori.toml
+.B \-\-
+.I command
+.SH DESCRIPTION
+A TOML file describing the policy a command runs under. Every table is
+optional; an empty file denies all network access and nothing else.
+Rules from the file are merged with the command line options.
+.SH [network]
+.TP
+.B allow
+\fBtrue\fR to allow all connections, \fBfalse\fR (the default) to deny them,
+or an array of destinations: domain names, IPv4 and IPv6 addresses and CIDR ranges.
+Domain and IP filtering is not available on macOS.
+.TP
+.B allow_presets
+Array of curated destination sets to allow, such as \fB"crates"\fR,
+\fB"npm"\fR, \fB"pypi"\fR, \fB"github"\fR or \fB"model-apis"\fR.
+.TP
+.B deny
+Array of destinations denied even when \fBallow\fR covers them: domain
+names, IPv4 and IPv6 addresses and CIDR ranges. Linux only.
+.TP
+.B enforce_sni
+\fBtrue\fR to deny TLS connections whose server name (SNI) is not an
+allowed domain, so allowing a domain does not allow every site that shares
+its CDN addresses. Destinations allowed by address are not checked. Linux only.
+.TP
+.B allow_localhost
+\fBfalse\fR to stop allowing localhost (127.0.0.1 and ::1) implicitly, so
+local services are only reachable through entries in \fBallow\fR. Not
+available on macOS.
+.TP
+.B strict
+\fBtrue\fR to refuse to run when an allowed or denied domain has no address
+at startup, after retrying its lookup for up to 10 seconds. Not available on
+macOS.
+.TP
+.B refresh_interval
+Seconds between lookups of allowed domains that have no DNS records (30 by
+default). Linux only.
+.TP
+.BR min_ttl ", " max_ttl
+Bounds in seconds on the TTL of the DNS records of allowed and denied
+domains, which decides when they are resolved again; \fBmax_ttl\fR wins if
+they conflict. Linux only.
+.TP
+.B [network.pins]
+Table mapping domain names to arrays of IPv4 addresses. A pinned domain is
+allowed through those addresses and never resolved by mori.
+.TP
+.B [network.hosts]
+Static host mappings, like /etc/hosts: each key is a hostname (dotted keys
+are joined back into one name) and each value an IPv4 or IPv6 address or an
+array of them. The addresses are allowed and the host is never resolved.
+.TP
+.B [network.inbound]
+Table with an \fBallow\fR array of IPv4 and IPv6 addresses and CIDR ranges. When
+present, only those peers and localhost can connect to sockets the command
+listens on. Linux only.
+.TP
+.B [network.unix]
+Connections to abstract unix sockets, named \fB@name\fR. \fBdeny_abstract\fR
+denies every name missing from the \fBallow\fR array; names in the \fBdeny\fR
+array are always denied. Linux only.
+.TP
+.B [network.dns]
+Nameservers mori resolves through, as the \fBnameservers\fR array (the
+system configuration when empty). With \fBenforce = true\fR, port 53 only
+reaches the nameservers and known DNS-over-HTTPS/TLS resolvers are blocked
+unless explicitly allowed. Linux only.
+.SH [file]
+.TP
+.B deny
+Array of paths that can be neither read nor written.
+.TP
+.B deny_read
+Array of paths that cannot be read.
+.TP
+.B deny_write
+Array of paths that cannot be written.
+.TP
+.B allow
+Array of the only paths that can be opened, with everything below them;
+system libraries and configuration stay readable. Empty allows all paths.
+Not enforced on Windows.
+.SH [process]
+.TP
+.B deny_exec
+Array of programs that cannot be executed.
+.TP
+.B allow_exec
+Array of the only programs that can be executed (empty allows all).
+.TP
+.B max_pids
+Most processes and threads the command may have at once, containing fork
+bombs. Linux only.
+.TP
+.B deny_namespaces
+Deny creating or joining namespaces (unshare, clone with CLONE_NEW* flags,
+setns), so the command cannot remount paths out of reach of the file rules.
+Linux only.
+.TP
+.B env_allow
+Array of the only environment variables passed to the command (empty passes
+all); \fBPREFIX*\fR matches every variable starting with PREFIX.
+.TP
+.B env_deny
+Array of environment variables removed before the command starts, taking
+precedence over \fBenv_allow\fR; \fBPREFIX*\fR works as above.
+.TP
+.B seccomp
+System call filter installed before the command starts: \fB"default"\fR
+denies ptrace, keyctl, bpf, mount, module loading and similar calls; any
+other string is the path of a Docker seccomp profile. Linux only.
+.TP
+.B [process.seccomp]
+System call filter with \fBdefault_action\fR (\fB"allow"\fR or
+\fB"deny"\fR) and arrays of system call names in \fBallow\fR and
+\fBdeny\fR. The \fB[process.seccomp.errno]\fR table maps system call
+names to the errno they fail with instead of EPERM.
+.SH EXAMPLE
+.nf
+[network]
+allow = ["github.com", "10.0.0.0/24"]
+allow_presets = ["crates"]
+
+[network.pins]
+"internal.corp" = ["10.1.2.3"]
+
+[file]
+deny_read = ["/home/user/.ssh"]
+deny_write = ["/etc"]
+
+[process]
+deny_exec = ["/usr/bin/curl"]
+.fi
+.SH SEE ALSO
+.BR mori (1)
+"#;
+
+/// Write the manual pages of mori and its subcommands (section 1) and of the
+/// configuration file (section 5) into `dir`
+///
+/// Returns the paths of the written pages.
+pub fn generate(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+
+    let mut command = Args::command().disable_help_subcommand(true);
+    command.build();
+    let mut pages = Vec::new();
+    generate_command(command, dir, &mut pages)?;
+
+    let config_page = dir.join(CONFIG_PAGE_NAME);
+    fs::write(&config_page, CONFIG_PAGE)?;
+    pages.push(config_page);
+    Ok(pages)
+}
+
+fn generate_command(
+    command: clap::Command,
+    dir: &Path,
+    pages: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    pages.push(clap_mangen::Man::new(command.clone()).generate_to(dir)?);
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        generate_command(subcommand.clone(), dir, pages)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_writes_command_and_config_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = generate(dir.path()).unwrap();
+
+        let names: Vec<String> = pages
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        for name in [
+            "mori.1",
+            "mori-convert.1",
+            "mori-git-hook-install.1",
+            "mori.toml.5",
+        ] {
+            assert!(
+                names.contains(&name.to_string()),
+                "missing {name} in {names:?}"
+            );
+        }
+
+        let main_page = fs::read_to_string(dir.path().join("mori.1")).unwrap();
+        assert!(main_page.contains("allow\\-network\\-all"));
+    }
+}
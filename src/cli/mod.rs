@@ -1,7 +1,17 @@
 pub mod args;
+pub mod cargo;
+pub mod commands;
 pub mod config;
+pub mod git_hook;
 pub mod loader;
+pub mod mangen;
+pub mod preset;
 
-pub use args::Args;
-pub use config::{ConfigFile, NetworkConfig};
+#[cfg(target_os = "linux")]
+pub use args::TraceArgs;
+pub use args::{
+    Args, Commands, ConvertArgs, ExportArgs, GitHookCommand, GitHookInstallArgs, PolicyArgs,
+    PolicyCommand, RunArgs,
+};
+pub use config::{ConfigFile, NetworkConfig, ProcessConfig, SeccompConfig, SeccompSetting};
 pub use loader::PolicyLoader;
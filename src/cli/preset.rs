@@ -0,0 +1,173 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::MoriError,
+    policy::{FilePolicy, NetworkPolicy, Policy, ProcessPolicy},
+};
+
+/// Built-in policies selected with `--preset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PolicyPreset {
+    /// Coding agents: model APIs and package registries, credentials protected,
+    /// writes confined to the working directory
+    Agent,
+}
+
+/// Presets covering model APIs and the registries agents install packages from
+const AGENT_PRESETS: &[&str] = &["model-apis", "crates", "npm", "pypi", "github"];
+
+/// Credentials under the home directory an agent never needs
+const HOME_SECRETS: &[&str] = &[
+    ".ssh",
+    ".aws",
+    ".gnupg",
+    ".netrc",
+    ".docker/config.json",
+    ".config/gcloud",
+];
+
+/// Package manager caches and toolchains under the home directory that the
+/// registries of the preset fill
+const HOME_CACHES: &[&str] = &[".cargo", ".rustup", ".npm", ".cache"];
+
+/// Temporary directories besides the one of the environment
+const TEMP_DIRS: &[&str] = &["/tmp", "/var/tmp"];
+
+/// Credentials in the workspace itself
+const WORKSPACE_SECRETS: &[&str] = &[".env", ".env.local"];
+
+/// Programs used to pipe downloaded scripts into a shell (`curl ... | bash`)
+const DOWNLOADERS: &[&str] = &["curl", "wget"];
+
+/// Build the policy for `preset` from the current environment
+pub fn preset_policy(preset: PolicyPreset) -> Result<Policy, MoriError> {
+    match preset {
+        PolicyPreset::Agent => {
+            let workspace = std::env::current_dir()?;
+            let home = std::env::var_os("HOME").map(PathBuf::from);
+            agent_policy(
+                &workspace,
+                home.as_deref(),
+                &std::env::temp_dir(),
+                std::env::var_os("PATH").as_deref(),
+            )
+        }
+    }
+}
+
+/// Policy for running a coding agent in `workspace`
+///
+/// Network access is limited to model APIs and package registries, and files
+/// are in allow-list mode: only the workspace, `temp_dir`, the other temporary
+/// directories and the package manager caches in `home` can be opened besides
+/// the system paths. Credentials in `home` and `.env` files in the workspace
+/// stay denied even when the workspace covers them, and curl/wget found on
+/// `path_var` cannot be executed.
+pub fn agent_policy(
+    workspace: &Path,
+    home: Option<&Path>,
+    temp_dir: &Path,
+    path_var: Option<&OsStr>,
+) -> Result<Policy, MoriError> {
+    let presets: Vec<String> = AGENT_PRESETS.iter().map(|name| name.to_string()).collect();
+    let network = NetworkPolicy::from_presets(&presets)?;
+
+    let mut file = FilePolicy::new();
+    file.allow(workspace);
+    file.allow(temp_dir);
+    for dir in TEMP_DIRS {
+        file.allow(dir);
+    }
+    for name in WORKSPACE_SECRETS {
+        file.deny_read_write(workspace.join(name));
+    }
+    if let Some(home) = home {
+        for name in HOME_CACHES {
+            file.allow(home.join(name));
+        }
+        for name in HOME_SECRETS {
+            file.deny_read_write(home.join(name));
+        }
+    }
+
+    let mut process = ProcessPolicy::new();
+    for program in find_programs(DOWNLOADERS, path_var) {
+        process.deny_exec(program);
+    }
+
+    Ok(Policy {
+        network,
+        file,
+        process,
+        ..Policy::new()
+    })
+}
+
+/// Every file named like one of `names` in the directories of `path_var`
+fn find_programs(names: &[&str], path_var: Option<&OsStr>) -> Vec<PathBuf> {
+    let mut programs = Vec::new();
+    for dir in path_var.map(std::env::split_paths).into_iter().flatten() {
+        for name in names {
+            let program = dir.join(name);
+            if program.is_file() && !programs.contains(&program) {
+                programs.push(program);
+            }
+        }
+    }
+    programs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::policy::{AccessMode, AllowPolicy};
+
+    #[test]
+    fn agent_policy_allows_only_workspace_temp_and_caches() {
+        let home = Path::new("/home/user");
+        let workspace = home.join("projects/app");
+
+        let policy = agent_policy(&workspace, Some(home), Path::new("/run/tmp"), None).unwrap();
+
+        let file = &policy.file;
+        assert!(file.allows(&workspace.join("src/main.rs"), AccessMode::ReadWrite));
+        assert!(file.allows(Path::new("/run/tmp/build.log"), AccessMode::ReadWrite));
+        assert!(file.allows(Path::new("/tmp/scratch"), AccessMode::ReadWrite));
+        assert!(file.allows(&home.join(".cargo/registry"), AccessMode::ReadWrite));
+        assert!(!file.allows(&home.join(".bashrc"), AccessMode::Read));
+        assert!(!file.allows(&home.join("Documents/notes.txt"), AccessMode::Read));
+        assert!(!file.allows(&home.join("projects/other"), AccessMode::Read));
+
+        let denied = &file.denied_paths;
+        assert!(denied.contains(&(home.join(".ssh"), AccessMode::ReadWrite)));
+        assert!(denied.contains(&(home.join(".aws"), AccessMode::ReadWrite)));
+        assert!(denied.contains(&(workspace.join(".env"), AccessMode::ReadWrite)));
+
+        match policy.network.policy {
+            AllowPolicy::Entries {
+                allowed_domains, ..
+            } => {
+                assert!(allowed_domains.contains(&"api.anthropic.com".to_string()));
+                assert!(allowed_domains.contains(&"registry.npmjs.org".to_string()));
+            }
+            AllowPolicy::All => panic!("expected allow list"),
+        }
+    }
+
+    #[test]
+    fn agent_policy_denies_downloaders_on_path() {
+        let bin = tempfile::tempdir().unwrap();
+        fs::write(bin.path().join("curl"), "").unwrap();
+        fs::write(bin.path().join("git"), "").unwrap();
+        let path_var = std::env::join_paths([bin.path(), bin.path()]).unwrap();
+
+        let policy =
+            agent_policy(Path::new("/work"), None, Path::new("/tmp"), Some(&path_var)).unwrap();
+        assert_eq!(policy.process.denied_exec, vec![bin.path().join("curl")]);
+    }
+}
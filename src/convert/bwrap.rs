@@ -0,0 +1,206 @@
+use std::path::PathBuf;
+
+use super::Conversion;
+use crate::cli::{ConfigFile, config::AllowConfig};
+
+/// Options that do not change what the sandboxed command can access
+const NEUTRAL_OPTIONS: &[&str] = &[
+    "--die-with-parent",
+    "--new-session",
+    "--as-pid-1",
+    "--clearenv",
+    "--chdir",
+    "--setenv",
+    "--unsetenv",
+    "--argv0",
+    "--proc",
+    "--dev",
+    "--dir",
+    "--unshare-user",
+    "--unshare-user-try",
+    "--unshare-ipc",
+    "--unshare-pid",
+    "--unshare-uts",
+    "--unshare-cgroup",
+    "--unshare-cgroup-try",
+];
+
+/// Number of values taken by each bwrap option
+fn option_arity(option: &str) -> Option<usize> {
+    let arity = match option {
+        "--unshare-all"
+        | "--share-net"
+        | "--unshare-user"
+        | "--unshare-user-try"
+        | "--unshare-ipc"
+        | "--unshare-pid"
+        | "--unshare-net"
+        | "--unshare-uts"
+        | "--unshare-cgroup"
+        | "--unshare-cgroup-try"
+        | "--clearenv"
+        | "--die-with-parent"
+        | "--as-pid-1"
+        | "--new-session"
+        | "--disable-userns"
+        | "--assert-userns-disabled" => 0,
+        "--args" | "--argv0" | "--userns" | "--userns2" | "--pidns" | "--uid" | "--gid"
+        | "--hostname" | "--chdir" | "--unsetenv" | "--lock-file" | "--sync-fd"
+        | "--remount-ro" | "--proc" | "--dev" | "--tmpfs" | "--mqueue" | "--dir" | "--seccomp"
+        | "--add-seccomp-fd" | "--block-fd" | "--userns-block-fd" | "--info-fd"
+        | "--json-status-fd" | "--cap-add" | "--cap-drop" | "--perms" | "--size"
+        | "--exec-label" | "--file-label" | "--overlay-src" | "--tmp-overlay" | "--ro-overlay"
+        | "--level-prefix" => 1,
+        "--setenv" | "--bind" | "--bind-try" | "--dev-bind" | "--dev-bind-try" | "--ro-bind"
+        | "--ro-bind-try" | "--file" | "--bind-data" | "--ro-bind-data" | "--symlink"
+        | "--chmod" => 2,
+        "--overlay" => 3,
+        _ => return None,
+    };
+    Some(arity)
+}
+
+/// Translate a bubblewrap command line into an approximate mori configuration
+///
+/// `args` are the arguments of `bwrap` (optionally starting with `bwrap`
+/// itself); parsing stops at the sandboxed command. The network is denied when it is unshared, read-only binds and
+/// `--remount-ro` become write denials, and writable binds need no rule. bwrap
+/// hides everything that is not bound, which mori's deny lists cannot express,
+/// so that and other unsupported options are reported as skipped.
+pub fn convert(args: &[String]) -> Result<Conversion, String> {
+    let mut config = ConfigFile::default();
+    let mut skipped = Vec::new();
+    let mut unshare_net = false;
+    let mut share_net = false;
+    let mut binds = false;
+
+    let mut args = args.iter().peekable();
+    args.next_if(|arg| *arg == "bwrap" || arg.ends_with("/bwrap"));
+    while let Some(option) = args.next() {
+        if option == "--" || !option.starts_with("--") {
+            break;
+        }
+        let arity = option_arity(option).ok_or_else(|| format!("unknown option {}", option))?;
+        let values: Vec<&String> = args.by_ref().take(arity).collect();
+        if values.len() < arity {
+            return Err(format!("{} takes {} argument(s)", option, arity));
+        }
+
+        match option.as_str() {
+            "--unshare-net" | "--unshare-all" => unshare_net = true,
+            "--share-net" => share_net = true,
+            "--ro-bind" | "--ro-bind-try" => {
+                binds = true;
+                let (source, dest) = (values[0], values[1]);
+                if source == dest {
+                    config.file.deny_write.push(PathBuf::from(dest));
+                } else {
+                    skipped.push(format!(
+                        "{} {} {}: binding to a different path cannot be expressed",
+                        option, source, dest
+                    ));
+                }
+            }
+            "--remount-ro" => config.file.deny_write.push(PathBuf::from(values[0])),
+            "--bind" | "--bind-try" | "--dev-bind" | "--dev-bind-try" => {
+                binds = true;
+                if values[0] != values[1] {
+                    skipped.push(format!(
+                        "{} {} {}: binding to a different path cannot be expressed",
+                        option, values[0], values[1]
+                    ));
+                }
+            }
+            option if NEUTRAL_OPTIONS.contains(&option) => {}
+            option => {
+                let mut rule = vec![option];
+                rule.extend(values.iter().map(|value| value.as_str()));
+                skipped.push(format!("{}: not supported", rule.join(" ")));
+            }
+        }
+    }
+
+    if binds {
+        skipped.push(
+            "paths that are not bound are hidden by bwrap; mori only denies listed paths"
+                .to_string(),
+        );
+    }
+    config.network.allow = AllowConfig::Boolean(!unshare_net || share_net);
+
+    Ok(Conversion { config, skipped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn convert_maps_binds_and_network() {
+        let conversion = convert(&args(
+            "--ro-bind /usr /usr --bind /home/user/project /home/user/project \
+             --proc /proc --dev /dev --unshare-all --die-with-parent -- bash -c true",
+        ))
+        .unwrap();
+
+        let config = conversion.config;
+        assert_eq!(config.file.deny_write, vec![PathBuf::from("/usr")]);
+        assert!(matches!(config.network.allow, AllowConfig::Boolean(false)));
+        assert_eq!(
+            conversion.skipped,
+            vec!["paths that are not bound are hidden by bwrap; mori only denies listed paths"]
+        );
+    }
+
+    #[test]
+    fn convert_keeps_shared_network() {
+        let conversion = convert(&args(
+            "/usr/bin/bwrap --unshare-all --share-net --remount-ro /etc",
+        ))
+        .unwrap();
+        assert!(matches!(
+            conversion.config.network.allow,
+            AllowConfig::Boolean(true)
+        ));
+        assert_eq!(
+            conversion.config.file.deny_write,
+            vec![PathBuf::from("/etc")]
+        );
+        assert!(conversion.skipped.is_empty());
+    }
+
+    #[test]
+    fn convert_reports_unsupported_options() {
+        let conversion = convert(&args(
+            "--ro-bind /nix/store /usr --tmpfs /tmp --cap-drop ALL sh",
+        ))
+        .unwrap();
+        assert_eq!(
+            conversion.skipped,
+            vec![
+                "--ro-bind /nix/store /usr: binding to a different path cannot be expressed",
+                "--tmpfs /tmp: not supported",
+                "--cap-drop ALL: not supported",
+                "paths that are not bound are hidden by bwrap; mori only denies listed paths",
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_rejects_invalid_arguments() {
+        assert!(
+            convert(&args("--frobnicate"))
+                .unwrap_err()
+                .contains("--frobnicate")
+        );
+        assert!(
+            convert(&args("--ro-bind /usr"))
+                .unwrap_err()
+                .contains("2 argument(s)")
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use serde::Deserialize;
+
+use super::Conversion;
+use crate::{
+    cli::{ConfigFile, ProcessConfig, SeccompConfig, SeccompSetting},
+    policy::SeccompAction,
+};
+
+/// Docker seccomp profile, as consumed by `docker run --security-opt seccomp=...`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerProfile {
+    default_action: String,
+    /// errno of `SCMP_ACT_ERRNO` as the default action
+    #[serde(default)]
+    default_errno_ret: Option<i32>,
+    #[serde(default)]
+    syscalls: Vec<DockerSyscallRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerSyscallRule {
+    #[serde(default)]
+    names: Vec<String>,
+    /// Single-name form used by older profiles
+    #[serde(default)]
+    name: Option<String>,
+    action: String,
+    /// errno of `SCMP_ACT_ERRNO`, EPERM when missing
+    #[serde(default)]
+    errno_ret: Option<i32>,
+    #[serde(default)]
+    args: Option<Vec<serde_json::Value>>,
+    #[serde(default)]
+    includes: DockerFilter,
+    #[serde(default)]
+    excludes: DockerFilter,
+}
+
+/// Conditions restricting a rule to certain capabilities or architectures
+#[derive(Debug, Default, Deserialize)]
+struct DockerFilter {
+    #[serde(default)]
+    caps: Vec<String>,
+    #[serde(default)]
+    arches: Vec<String>,
+}
+
+impl DockerSyscallRule {
+    /// Check if the rule applies on the architecture Docker calls `arch`
+    fn applies_to(&self, arch: &str) -> bool {
+        (self.includes.arches.is_empty() || self.includes.arches.iter().any(|a| a == arch))
+            && !self.excludes.arches.iter().any(|a| a == arch)
+    }
+}
+
+/// errno Docker's `SCMP_ACT_ERRNO` returns by default, and mori's denied calls
+const EPERM: i32 = 1;
+
+/// Docker's name for the architecture mori runs on
+fn docker_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64le",
+        "loongarch64" => "loong64",
+        arch => arch,
+    }
+}
+
+/// Map a libseccomp action to a mori action
+///
+/// Returns None for actions that hand the decision to another process (tracer, notifier),
+/// which mori cannot express.
+fn map_action(action: &str) -> Option<SeccompAction> {
+    match action {
+        "SCMP_ACT_ALLOW" | "SCMP_ACT_LOG" => Some(SeccompAction::Allow),
+        "SCMP_ACT_ERRNO"
+        | "SCMP_ACT_KILL"
+        | "SCMP_ACT_KILL_THREAD"
+        | "SCMP_ACT_KILL_PROCESS"
+        | "SCMP_ACT_TRAP" => Some(SeccompAction::Deny),
+        _ => None,
+    }
+}
+
+/// Translate a Docker seccomp profile into a `[process.seccomp]` configuration
+///
+/// See `parse` for the rules that cannot be expressed by mori.
+pub fn convert(content: &str) -> Result<Conversion, String> {
+    let (seccomp, skipped) = parse(content)?;
+    Ok(Conversion {
+        config: ConfigFile {
+            process: ProcessConfig {
+                seccomp: Some(SeccompSetting::Rules(seccomp)),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        skipped,
+    })
+}
+
+/// Parse a Docker seccomp profile into a filter and the rules it skipped
+///
+/// Used by `mori convert` and by `[process] seccomp = "<path>"`. Architecture
+/// conditions are evaluated for the architecture mori runs on, capability
+/// conditions as Docker does for a container without added capabilities:
+/// rules needing one are dropped, rules excluding one apply. mori cannot filter on
+/// arguments, so a conditional rule allowing calls the default action denies
+/// allows them unconditionally, since denying them would break every program
+/// making them the allowed way; other conditional rules are skipped.
+pub fn parse(content: &str) -> Result<(SeccompConfig, Vec<String>), String> {
+    parse_for(content, docker_arch())
+}
+
+/// `parse` for the architecture Docker calls `arch`
+fn parse_for(content: &str, arch: &str) -> Result<(SeccompConfig, Vec<String>), String> {
+    let profile: DockerProfile = serde_json::from_str(content).map_err(|e| e.to_string())?;
+
+    let default_action = map_action(&profile.default_action)
+        .ok_or_else(|| format!("unsupported default action {}", profile.default_action))?;
+
+    let mut seccomp = SeccompConfig {
+        default_action,
+        ..Default::default()
+    };
+    let mut skipped = Vec::new();
+    if let Some(errno) = profile.default_errno_ret
+        && default_action == SeccompAction::Deny
+        && errno != EPERM
+    {
+        skipped.push(format!(
+            "defaultErrnoRet {}: denied calls fail with EPERM",
+            errno
+        ));
+    }
+
+    for rule in profile.syscalls {
+        if !rule.applies_to(arch) {
+            continue;
+        }
+        let names: Vec<String> = rule
+            .names
+            .iter()
+            .cloned()
+            .chain(rule.name.clone())
+            .collect();
+        let Some(action) = map_action(&rule.action) else {
+            skipped.push(format!(
+                "{}: action {} is not supported",
+                names.join(", "),
+                rule.action
+            ));
+            continue;
+        };
+        if !rule.includes.caps.is_empty() {
+            continue;
+        }
+        let errno = rule
+            .errno_ret
+            .filter(|errno| action == SeccompAction::Deny && *errno != EPERM);
+        // Rules repeating the default action are redundant
+        if action == default_action && errno.is_none() {
+            continue;
+        }
+        if rule.args.as_ref().is_some_and(|args| !args.is_empty()) {
+            if action != SeccompAction::Allow {
+                skipped.push(format!(
+                    "{}: argument conditions are not supported",
+                    names.join(", ")
+                ));
+                continue;
+            }
+            let note = format!(
+                "argument conditions of {}, allowing it unconditionally (looser than the profile)",
+                names.join(", ")
+            );
+            // Profiles allow some calls with several alternative conditions
+            if !skipped.contains(&note) {
+                skipped.push(note);
+            }
+        }
+
+        match errno {
+            Some(errno) => {
+                for name in names {
+                    seccomp.errno.entry(name).or_insert(errno);
+                }
+            }
+            None => {
+                let list = match action {
+                    SeccompAction::Allow => &mut seccomp.allow,
+                    SeccompAction::Deny => &mut seccomp.deny,
+                };
+                for name in names {
+                    if !list.contains(&name) {
+                        list.push(name);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((seccomp, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_allow_list_profile() {
+        let profile = r#"{
+            "defaultAction": "SCMP_ACT_ERRNO",
+            "syscalls": [
+                {"names": ["read", "write"], "action": "SCMP_ACT_ALLOW", "args": []},
+                {"names": ["write", "openat"], "action": "SCMP_ACT_ALLOW"},
+                {"name": "close", "action": "SCMP_ACT_ALLOW"},
+                {"names": ["ptrace"], "action": "SCMP_ACT_ERRNO"}
+            ]
+        }"#;
+
+        let conversion = convert(profile).unwrap();
+        let Some(SeccompSetting::Rules(seccomp)) = conversion.config.process.seccomp else {
+            panic!("expected an inline filter");
+        };
+        assert_eq!(seccomp.default_action, SeccompAction::Deny);
+        assert_eq!(seccomp.allow, vec!["read", "write", "openat", "close"]);
+        assert!(seccomp.deny.is_empty());
+        assert!(conversion.skipped.is_empty());
+    }
+
+    #[test]
+    fn parse_keeps_docker_default_profile_runnable() {
+        let profile = include_str!("testdata/docker-default.json");
+
+        let (seccomp, skipped) = parse_for(profile, "amd64").unwrap();
+        assert_eq!(seccomp.default_action, SeccompAction::Deny);
+        for name in [
+            "read",
+            "execve",
+            "clone",
+            "arch_prctl",
+            "modify_ldt",
+            "socket",
+        ] {
+            assert!(seccomp.allow.contains(&name.to_string()), "{}", name);
+        }
+        // Allowed on other architectures or with capabilities only
+        for name in [
+            "cacheflush",
+            "riscv_flush_icache",
+            "mount",
+            "unshare",
+            "bpf",
+        ] {
+            assert!(!seccomp.allow.contains(&name.to_string()), "{}", name);
+        }
+        assert_eq!(seccomp.errno.get("clone3"), Some(&38));
+        assert!(seccomp.deny.is_empty());
+        assert!(
+            skipped
+                .iter()
+                .any(|note| note.starts_with("argument conditions of clone,"))
+        );
+
+        let (seccomp, _) = parse_for(profile, "arm64").unwrap();
+        assert!(seccomp.allow.contains(&"cacheflush".to_string()));
+        assert!(!seccomp.allow.contains(&"arch_prctl".to_string()));
+    }
+
+    #[test]
+    fn parse_honors_errno_ret() {
+        let profile = r#"{
+            "defaultAction": "SCMP_ACT_ALLOW",
+            "syscalls": [
+                {"names": ["clone3"], "action": "SCMP_ACT_ERRNO", "errnoRet": 38},
+                {"names": ["ptrace"], "action": "SCMP_ACT_ERRNO", "errnoRet": 1}
+            ]
+        }"#;
+
+        let (seccomp, skipped) = parse_for(profile, "amd64").unwrap();
+        assert_eq!(seccomp.errno.get("clone3"), Some(&38));
+        assert_eq!(seccomp.deny, vec!["ptrace"]);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn convert_skips_conditional_rules() {
+        let profile = r#"{
+            "defaultAction": "SCMP_ACT_ALLOW",
+            "syscalls": [
+                {"names": ["personality"], "action": "SCMP_ACT_ERRNO",
+                 "args": [{"index": 0, "value": 0, "op": "SCMP_CMP_EQ"}]},
+                {"names": ["mount"], "action": "SCMP_ACT_ERRNO",
+                 "excludes": {"caps": ["CAP_SYS_ADMIN"]}},
+                {"names": ["kexec_load"], "action": "SCMP_ACT_NOTIFY"},
+                {"names": ["bpf", "keyctl"], "action": "SCMP_ACT_KILL"}
+            ]
+        }"#;
+
+        let conversion = convert(profile).unwrap();
+        let Some(SeccompSetting::Rules(seccomp)) = conversion.config.process.seccomp else {
+            panic!("expected an inline filter");
+        };
+        assert_eq!(seccomp.default_action, SeccompAction::Allow);
+        // Applies to a container without CAP_SYS_ADMIN
+        assert_eq!(seccomp.deny, vec!["mount", "bpf", "keyctl"]);
+        assert_eq!(conversion.skipped.len(), 2);
+        assert!(conversion.skipped[0].starts_with("personality:"));
+    }
+
+    #[test]
+    fn convert_rejects_unsupported_default_action() {
+        let err = convert(r#"{"defaultAction": "SCMP_ACT_TRACE"}"#).unwrap_err();
+        assert!(err.contains("SCMP_ACT_TRACE"));
+    }
+
+    #[test]
+    fn convert_rejects_invalid_json() {
+        assert!(convert("not json").is_err());
+    }
+}
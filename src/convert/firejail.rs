@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+
+use super::Conversion;
+use crate::{
+    cli::{ConfigFile, SeccompConfig, SeccompSetting, config::AllowConfig},
+    policy::SeccompAction,
+};
+
+/// Directives that only affect how firejail itself behaves
+const IGNORED_DIRECTIVES: &[&str] = &["quiet"];
+
+/// Translate a firejail profile into mori configuration
+///
+/// `blacklist` and `read-only` map to file deny rules, `net none` and
+/// `protocol` lists without inet/inet6 deny all network access (anything else
+/// allows it, as firejail does by default), and `seccomp.drop`/`seccomp.keep`
+/// become a `[process.seccomp]` filter. `${HOME}` is expanded with `home`.
+/// mori only denies listed paths, so `whitelist` and the `private*` options
+/// cannot be expressed; they, `include` and other directives are reported as
+/// skipped.
+pub fn convert(content: &str, home: Option<&Path>) -> Result<Conversion, String> {
+    let mut config = ConfigFile::default();
+    let mut skipped = Vec::new();
+    let mut network = true;
+    let mut no_blacklist = Vec::new();
+    let mut blacklist = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || IGNORED_DIRECTIVES.contains(&line) {
+            continue;
+        }
+        let (directive, value) = line
+            .split_once(char::is_whitespace)
+            .map(|(directive, value)| (directive, value.trim()))
+            .unwrap_or((line, ""));
+
+        let mut path = |value: &str| match expand_path(value, home) {
+            Ok(path) => Some(path),
+            Err(reason) => {
+                skipped.push(format!("{}: {}", line, reason));
+                None
+            }
+        };
+        match directive {
+            "blacklist" => blacklist.extend(path(value)),
+            "noblacklist" => no_blacklist.extend(path(value)),
+            "read-only" => config.file.deny_write.extend(path(value)),
+            "net" if value == "none" => network = false,
+            // Any interface gives the sandbox network access
+            "net" => network = true,
+            "protocol" => {
+                if !value
+                    .split(',')
+                    .any(|protocol| matches!(protocol.trim(), "inet" | "inet6"))
+                {
+                    network = false;
+                }
+            }
+            "seccomp.drop" => {
+                let seccomp = seccomp(&mut config, SeccompAction::Allow, line)?;
+                seccomp.deny.extend(syscall_list(value));
+            }
+            "seccomp.keep" => {
+                let seccomp = seccomp(&mut config, SeccompAction::Deny, line)?;
+                seccomp.allow.extend(syscall_list(value));
+            }
+            "whitelist" | "mkdir" | "mkfile" => skipped.push(format!(
+                "{}: mori cannot restrict access to an allow list of paths",
+                line
+            )),
+            "include" => skipped.push(format!("{}: included profiles are not followed", line)),
+            _ => skipped.push(format!("{}: not supported", line)),
+        }
+    }
+
+    // noblacklist exempts paths from blacklist rules wherever they appear
+    blacklist.retain(|path| !no_blacklist.contains(path));
+    config.file.deny.extend(blacklist);
+    config.network.allow = AllowConfig::Boolean(network);
+
+    Ok(Conversion { config, skipped })
+}
+
+/// The seccomp filter, created with `default_action`
+///
+/// firejail profiles use either a drop list (default allow) or a keep list
+/// (default deny); mixing both cannot be expressed.
+fn seccomp<'a>(
+    config: &'a mut ConfigFile,
+    default_action: SeccompAction,
+    line: &str,
+) -> Result<&'a mut SeccompConfig, String> {
+    let setting = config.process.seccomp.get_or_insert_with(|| {
+        SeccompSetting::Rules(SeccompConfig {
+            default_action,
+            ..Default::default()
+        })
+    });
+    match setting {
+        SeccompSetting::Rules(seccomp) if seccomp.default_action == default_action => Ok(seccomp),
+        _ => Err(format!(
+            "{}: seccomp.drop and seccomp.keep cannot be combined",
+            line
+        )),
+    }
+}
+
+fn syscall_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+/// Expand `${HOME}` and `~`; other macros and wildcards cannot be expressed
+fn expand_path(value: &str, home: Option<&Path>) -> Result<PathBuf, String> {
+    if value.contains(['*', '?', '[']) {
+        return Err("wildcards are not supported".to_string());
+    }
+    let rest = value
+        .strip_prefix("${HOME}")
+        .or_else(|| value.strip_prefix('~'));
+    let path = match rest {
+        Some(rest) => {
+            let home = home.ok_or("HOME is not set")?;
+            home.join(rest.trim_start_matches('/'))
+        }
+        None => PathBuf::from(value),
+    };
+    if path.to_string_lossy().contains("${") {
+        return Err("firejail macros other than ${HOME} are not supported".to_string());
+    }
+    if !path.is_absolute() {
+        return Err("relative paths are not supported".to_string());
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: &str = "/home/user";
+
+    #[test]
+    fn convert_maps_file_and_network_rules() {
+        let profile = "\
+# Firejail profile for transmission
+include globals.local
+quiet
+noblacklist ${HOME}/.config/transmission
+blacklist ${HOME}/.config/transmission
+blacklist ${HOME}/.ssh
+read-only ~/.bashrc
+whitelist ${DOWNLOADS}
+net none
+";
+        let conversion = convert(profile, Some(Path::new(HOME))).unwrap();
+        let config = conversion.config;
+
+        assert_eq!(config.file.deny, vec![PathBuf::from("/home/user/.ssh")]);
+        assert_eq!(
+            config.file.deny_write,
+            vec![PathBuf::from("/home/user/.bashrc")]
+        );
+        assert!(matches!(config.network.allow, AllowConfig::Boolean(false)));
+        assert_eq!(conversion.skipped.len(), 2);
+        assert!(conversion.skipped[0].starts_with("include globals.local"));
+        assert!(conversion.skipped[1].starts_with("whitelist ${DOWNLOADS}"));
+    }
+
+    #[test]
+    fn convert_allows_network_by_default() {
+        let conversion = convert("caps.drop all\n", None).unwrap();
+        assert!(matches!(
+            conversion.config.network.allow,
+            AllowConfig::Boolean(true)
+        ));
+        assert_eq!(conversion.skipped, vec!["caps.drop all: not supported"]);
+    }
+
+    #[test]
+    fn convert_denies_network_without_inet_protocols() {
+        let conversion = convert("protocol unix,netlink\n", None).unwrap();
+        assert!(matches!(
+            conversion.config.network.allow,
+            AllowConfig::Boolean(false)
+        ));
+    }
+
+    #[test]
+    fn convert_builds_seccomp_filter() {
+        let conversion =
+            convert("seccomp.drop mount, umount2\nseccomp.drop ptrace\n", None).unwrap();
+        let Some(SeccompSetting::Rules(seccomp)) = conversion.config.process.seccomp else {
+            panic!("expected an inline filter");
+        };
+        assert_eq!(seccomp.default_action, SeccompAction::Allow);
+        assert_eq!(seccomp.deny, vec!["mount", "umount2", "ptrace"]);
+
+        let err = convert("seccomp.drop mount\nseccomp.keep read\n", None).unwrap_err();
+        assert!(err.contains("cannot be combined"));
+    }
+
+    #[test]
+    fn expand_path_rejects_unsupported_forms() {
+        assert!(expand_path("${HOME}/.ssh", None).is_err());
+        assert!(expand_path("/tmp/*.log", None).is_err());
+        assert!(expand_path("${RUNUSER}/bus", None).is_err());
+        assert!(expand_path("relative", None).is_err());
+        assert_eq!(
+            expand_path("/etc/shadow", None),
+            Ok(PathBuf::from("/etc/shadow"))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+pub mod bwrap;
+pub mod docker_seccomp;
+pub mod firejail;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+use crate::{cli::ConfigFile, error::MoriError};
+
+/// Sandbox definitions that can be translated into mori configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SourceFormat {
+    /// Docker/OCI seccomp profile (JSON)
+    DockerSeccomp,
+    /// Firejail profile
+    Firejail,
+    /// Bubblewrap command line (the arguments after `bwrap`, given after `--`)
+    Bwrap,
+}
+
+impl SourceFormat {
+    /// Check if the format is a command line rather than a file
+    pub fn is_invocation(self) -> bool {
+        matches!(self, SourceFormat::Bwrap)
+    }
+}
+
+/// Result of translating a foreign sandbox definition
+#[derive(Debug, Default)]
+pub struct Conversion {
+    /// Equivalent mori configuration
+    pub config: ConfigFile,
+    /// Parts of the source that could not be expressed and were skipped
+    pub skipped: Vec<String>,
+}
+
+/// Translate the sandbox definition stored at `path`
+pub fn convert_file(format: SourceFormat, path: &Path) -> Result<Conversion, MoriError> {
+    let content = fs::read_to_string(path).map_err(|source| MoriError::ProfileRead {
+        path: PathBuf::from(path),
+        source,
+    })?;
+
+    let result = match format {
+        SourceFormat::DockerSeccomp => docker_seccomp::convert(&content),
+        SourceFormat::Firejail => {
+            let home = std::env::var_os("HOME").map(PathBuf::from);
+            firejail::convert(&content, home.as_deref())
+        }
+        SourceFormat::Bwrap => Err("bwrap arguments are given after --".to_string()),
+    };
+    result.map_err(|reason| MoriError::ProfileParse {
+        path: PathBuf::from(path),
+        reason,
+    })
+}
+
+/// Translate a sandbox command line given as arguments
+pub fn convert_invocation(format: SourceFormat, args: &[String]) -> Result<Conversion, MoriError> {
+    let result = match format {
+        SourceFormat::Bwrap => bwrap::convert(args),
+        SourceFormat::DockerSeccomp | SourceFormat::Firejail => {
+            return Err(MoriError::ConvertInput {
+                reason: "this format is read from a file".to_string(),
+            });
+        }
+    };
+    result.map_err(|reason| MoriError::ConvertInput { reason })
+}
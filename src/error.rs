@@ -45,25 +45,44 @@ pub enum MoriError {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("failed to create output log {path}: {source}")]
+    OutputLog {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("eBPF map error: {0}")]
     Map(#[from] MapError),
 
     #[error("BTF error: {0}")]
     Btf(#[from] BtfError),
 
+    #[error("the BPF LSM is not enabled (bpf is missing from /sys/kernel/security/lsm)")]
+    BpfLsmInactive,
+
     #[error("invalid --allow-network entry '{entry}': {reason}")]
     InvalidAllowNetworkEntry { entry: String, reason: String },
 
     #[error("unsupported network protocol '{protocol}' in entry '{entry}'")]
     UnsupportedNetworkProtocol { entry: String, protocol: String },
 
+    #[error("invalid unix socket entry '{entry}': {reason}")]
+    InvalidUnixSocketEntry { entry: String, reason: String },
+
     #[error("invalid CIDR prefix length {prefix_len} for {addr} (must be 0-{max_allowed})")]
     InvalidCidrPrefix {
-        addr: std::net::Ipv4Addr,
+        addr: std::net::IpAddr,
         prefix_len: u8,
         max_allowed: u8,
     },
 
+    #[error("{addr}/{prefix_len} is too wide for a port rule (at most 256 addresses)")]
+    CidrTooWideForPorts {
+        addr: std::net::IpAddr,
+        prefix_len: u8,
+    },
+
     #[error("failed to perform cgroup operation '{operation}' on {path}: {source}")]
     CgroupOperation {
         operation: String,
@@ -91,9 +110,22 @@ pub enum MoriError {
         source: nix::Error,
     },
 
+    #[error("failed to spawn command '{command}': {source}")]
+    CommandSpawn {
+        command: String,
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("DNS refresh task panicked")]
     RefreshTaskPanic,
 
+    #[error("failed to load plugin {path}: {reason}")]
+    PluginLoad { path: PathBuf, reason: String },
+
+    #[error("--plugin requires mori to be built with the wasm-plugin feature")]
+    PluginUnsupported,
+
     #[error("failed to read config file {path}: {source}")]
     ConfigRead {
         path: PathBuf,
@@ -108,6 +140,93 @@ pub enum MoriError {
         source: toml::de::Error,
     },
 
+    #[error("failed to serialize config: {source}")]
+    ConfigSerialize {
+        #[source]
+        source: toml::ser::Error,
+    },
+
+    #[error("failed to parse policy {path}: {source}")]
+    PolicyParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("unknown network preset '{name}' (available: {available})")]
+    UnknownPreset { name: String, available: String },
+
+    #[error("failed to locate cargo workspace: {reason}")]
+    CargoWorkspace { reason: String },
+
+    #[error("failed to locate git hooks directory: {reason}")]
+    GitHooks { reason: String },
+
+    #[error("failed to read profile {path}: {source}")]
+    ProfileRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse profile {path}: {reason}")]
+    ProfileParse { path: PathBuf, reason: String },
+
+    #[error("invalid input for mori convert: {reason}")]
+    ConvertInput { reason: String },
+
+    #[error("failed to read flow log {path}: {source}")]
+    FlowLogRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse flow log {path} line {line}: {reason}")]
+    FlowLogParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    #[error("failed to read file inventory {path}: {source}")]
+    FileInventoryRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse file inventory {path} line {line}: {reason}")]
+    FileInventoryParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    #[error("cannot stage writes in {path}: {reason}")]
+    Overlay { path: PathBuf, reason: String },
+
+    #[error("cannot run the command as {user}: {reason}")]
+    InvalidUser { user: String, reason: String },
+
+    #[error("working directory {path} does not exist")]
+    WorkingDirectory { path: PathBuf },
+
+    #[error(
+        "the unprivileged backend cannot enforce the policy ({}); run mori as root, or use --best-effort to run without these rules",
+        .rules.join("; ")
+    )]
+    PolicyNotEnforceable { rules: Vec<String> },
+
+    #[error("--audit needs the eBPF backend; run mori as root")]
+    AuditNeedsEbpf,
+
+    #[error("failed to set up Landlock: {source}")]
+    Landlock {
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("file path too long (>= {max_len} bytes): {path}")]
     PathTooLong { path: String, max_len: usize },
 }
@@ -134,12 +253,22 @@ pub enum MoriError {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("failed to create output log {path}: {source}")]
+    OutputLog {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("invalid --allow-network entry '{entry}': {reason}")]
     InvalidAllowNetworkEntry { entry: String, reason: String },
 
     #[error("unsupported network protocol '{protocol}' in entry '{entry}'")]
     UnsupportedNetworkProtocol { entry: String, protocol: String },
 
+    #[error("invalid unix socket entry '{entry}': {reason}")]
+    InvalidUnixSocketEntry { entry: String, reason: String },
+
     #[error("failed to spawn command '{command}': {source}")]
     CommandSpawn {
         command: String,
@@ -167,8 +296,336 @@ pub enum MoriError {
         source: toml::de::Error,
     },
 
+    #[error("failed to serialize config: {source}")]
+    ConfigSerialize {
+        #[source]
+        source: toml::ser::Error,
+    },
+
+    #[error("failed to parse policy {path}: {source}")]
+    PolicyParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("unknown network preset '{name}' (available: {available})")]
+    UnknownPreset { name: String, available: String },
+
+    #[error("failed to locate cargo workspace: {reason}")]
+    CargoWorkspace { reason: String },
+
+    #[error("failed to locate git hooks directory: {reason}")]
+    GitHooks { reason: String },
+
+    #[error("failed to read profile {path}: {source}")]
+    ProfileRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse profile {path}: {reason}")]
+    ProfileParse { path: PathBuf, reason: String },
+
+    #[error("invalid input for mori convert: {reason}")]
+    ConvertInput { reason: String },
+
+    #[error("failed to read flow log {path}: {source}")]
+    FlowLogRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse flow log {path} line {line}: {reason}")]
+    FlowLogParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    #[error("failed to read file inventory {path}: {source}")]
+    FileInventoryRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse file inventory {path} line {line}: {reason}")]
+    FileInventoryParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
     #[error(
         "entry-based network policy is not supported on macOS. Use 'allow = true' or 'allow = false' instead"
     )]
     EntryBasedPolicyNotSupported,
+
+    #[error(
+        "sandbox-exec cannot enforce the policy ({}); use --best-effort to run without these rules",
+        .rules.join("; ")
+    )]
+    PolicyNotEnforceable { rules: Vec<String> },
+}
+
+#[cfg(target_os = "windows")]
+#[derive(Debug, Error)]
+pub enum MoriError {
+    #[error("operation not supported on this platform")]
+    Unsupported,
+
+    #[error(
+        "the Windows backend cannot enforce the policy ({}); use --best-effort to run without these rules",
+        .rules.join("; ")
+    )]
+    PolicyNotEnforceable { rules: Vec<String> },
+
+    #[error("failed to initialize DNS resolver: {source}")]
+    DnsResolverInit {
+        #[source]
+        source: ResolveError,
+    },
+
+    #[error("failed to resolve domain {domain}: {source}")]
+    DnsLookup {
+        domain: String,
+        #[source]
+        source: ResolveError,
+    },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid --allow-network entry '{entry}': {reason}")]
+    InvalidAllowNetworkEntry { entry: String, reason: String },
+
+    #[error("unsupported network protocol '{protocol}' in entry '{entry}'")]
+    UnsupportedNetworkProtocol { entry: String, protocol: String },
+
+    #[error("invalid unix socket entry '{entry}': {reason}")]
+    InvalidUnixSocketEntry { entry: String, reason: String },
+
+    #[error("failed to spawn command '{command}': {source}")]
+    CommandSpawn {
+        command: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to wait for command: {source}")]
+    CommandWait {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Windows API call {operation} failed with error code {code:#x}")]
+    WindowsApi { operation: String, code: u32 },
+
+    #[error("failed to read config file {path}: {source}")]
+    ConfigRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse config {path}: {source}")]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("failed to serialize config: {source}")]
+    ConfigSerialize {
+        #[source]
+        source: toml::ser::Error,
+    },
+
+    #[error("failed to parse policy {path}: {source}")]
+    PolicyParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("unknown network preset '{name}' (available: {available})")]
+    UnknownPreset { name: String, available: String },
+
+    #[error("failed to locate cargo workspace: {reason}")]
+    CargoWorkspace { reason: String },
+
+    #[error("failed to locate git hooks directory: {reason}")]
+    GitHooks { reason: String },
+
+    #[error("failed to read profile {path}: {source}")]
+    ProfileRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse profile {path}: {reason}")]
+    ProfileParse { path: PathBuf, reason: String },
+
+    #[error("invalid input for mori convert: {reason}")]
+    ConvertInput { reason: String },
+
+    #[error("failed to read flow log {path}: {source}")]
+    FlowLogRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse flow log {path} line {line}: {reason}")]
+    FlowLogParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    #[error("failed to read file inventory {path}: {source}")]
+    FileInventoryRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse file inventory {path} line {line}: {reason}")]
+    FileInventoryParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+#[derive(Debug, Error)]
+pub enum MoriError {
+    #[error("operation not supported on this platform")]
+    Unsupported,
+
+    #[error(
+        "this platform cannot enforce the policy ({}); use --best-effort to run the command unrestricted",
+        .rules.join("; ")
+    )]
+    PolicyNotEnforceable { rules: Vec<String> },
+
+    #[error("failed to initialize DNS resolver: {source}")]
+    DnsResolverInit {
+        #[source]
+        source: ResolveError,
+    },
+
+    #[error("failed to resolve domain {domain}: {source}")]
+    DnsLookup {
+        domain: String,
+        #[source]
+        source: ResolveError,
+    },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid --allow-network entry '{entry}': {reason}")]
+    InvalidAllowNetworkEntry { entry: String, reason: String },
+
+    #[error("unsupported network protocol '{protocol}' in entry '{entry}'")]
+    UnsupportedNetworkProtocol { entry: String, protocol: String },
+
+    #[error("invalid unix socket entry '{entry}': {reason}")]
+    InvalidUnixSocketEntry { entry: String, reason: String },
+
+    #[error("failed to spawn command '{command}': {source}")]
+    CommandSpawn {
+        command: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to wait for command: {source}")]
+    CommandWait {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to read config file {path}: {source}")]
+    ConfigRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse config {path}: {source}")]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("failed to serialize config: {source}")]
+    ConfigSerialize {
+        #[source]
+        source: toml::ser::Error,
+    },
+
+    #[error("failed to parse policy {path}: {source}")]
+    PolicyParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("unknown network preset '{name}' (available: {available})")]
+    UnknownPreset { name: String, available: String },
+
+    #[error("failed to locate cargo workspace: {reason}")]
+    CargoWorkspace { reason: String },
+
+    #[error("failed to locate git hooks directory: {reason}")]
+    GitHooks { reason: String },
+
+    #[error("failed to read profile {path}: {source}")]
+    ProfileRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse profile {path}: {reason}")]
+    ProfileParse { path: PathBuf, reason: String },
+
+    #[error("invalid input for mori convert: {reason}")]
+    ConvertInput { reason: String },
+
+    #[error("failed to read flow log {path}: {source}")]
+    FlowLogRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse flow log {path} line {line}: {reason}")]
+    FlowLogParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    #[error("failed to read file inventory {path}: {source}")]
+    FileInventoryRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse file inventory {path} line {line}: {reason}")]
+    FileInventoryParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
 }
@@ -0,0 +1,202 @@
+use std::{fmt::Write, path::Path};
+
+use super::{Export, ExportFile};
+use crate::policy::{AccessMode, Policy};
+
+/// Rules granting everything mori does not restrict, so the profile only
+/// narrows what the mori policy narrows
+const BASELINE_RULES: &[&str] = &[
+    "capability,",
+    "signal,",
+    "ptrace,",
+    "unix,",
+    "dbus,",
+    "mount,",
+    "umount,",
+    "pivot_root,",
+    "network,",
+];
+
+/// File rule of the baseline, replaced by the allowed paths in allow-list mode
+const BASELINE_FILE_RULE: &str = "/{,**} rwmlk,";
+
+/// Generate an AppArmor profile approximating the file, network and exec policy
+pub fn export(policy: &Policy, name: &str) -> Export {
+    let mut notes = Vec::new();
+    let mut rules = Vec::new();
+
+    // Files: everything, or in allow-list mode only the allowed paths and the
+    // system paths
+    let allowed = policy.file.allowed_entries();
+    if allowed.is_empty() {
+        rules.push(BASELINE_FILE_RULE.to_string());
+    }
+    for (path, mode) in &allowed {
+        let permissions = match mode {
+            AccessMode::Read => "rm",
+            AccessMode::Write => "wlk",
+            AccessMode::ReadWrite => "rwmlk",
+            AccessMode::Execute => "m",
+        };
+        rules.push(format!("{} {},", path_with_children(path), permissions));
+    }
+
+    // Network: AppArmor mediates address families, not destinations
+    if policy.network.is_deny_all() {
+        rules.push("deny network inet,".to_string());
+        rules.push("deny network inet6,".to_string());
+    } else if !policy.network.is_allow_all() || !policy.network.denied.is_empty() {
+        notes.push(
+            "network: AppArmor cannot restrict destinations; all IPv4/IPv6 connections are allowed"
+                .to_string(),
+        );
+    }
+
+    for (path, mode) in &policy.file.denied_paths {
+        let permissions = match mode {
+            AccessMode::Read => "r",
+            AccessMode::Write => "w",
+            AccessMode::ReadWrite => "rw",
+            AccessMode::Execute => "mx",
+        };
+        rules.push(format!(
+            "deny {} {},",
+            path_with_children(path),
+            permissions
+        ));
+    }
+
+    // Exec: inherit the profile for allowed programs, so children stay confined
+    if policy.process.allowed_exec.is_empty() {
+        rules.push("/{,**} ix,".to_string());
+    } else {
+        for path in &policy.process.allowed_exec {
+            rules.push(format!("{} ix,", path_with_children(path)));
+        }
+    }
+    for path in &policy.process.denied_exec {
+        rules.push(format!("deny {} x,", path_with_children(path)));
+    }
+
+    if policy.process.seccomp.is_some() {
+        notes.push(
+            "seccomp: not part of AppArmor profiles; load the seccomp filter separately"
+                .to_string(),
+        );
+    }
+
+    let mut content = String::new();
+    content.push_str("# AppArmor profile generated by mori\n");
+    content.push_str("abi <abi/3.0>,\n\n");
+    content.push_str("include <tunables/global>\n\n");
+    let _ = writeln!(content, "profile {} flags=(attach_disconnected) {{", name);
+    content.push_str("  include <abstractions/base>\n\n");
+    for rule in BASELINE_RULES {
+        let _ = writeln!(content, "  {}", rule);
+    }
+    content.push('\n');
+    for rule in &rules {
+        let _ = writeln!(content, "  {}", rule);
+    }
+    content.push_str("}\n");
+
+    Export {
+        files: vec![ExportFile {
+            file_name: name.to_string(),
+            content,
+        }],
+        notes,
+    }
+}
+
+/// Render a path pattern matching the path itself and everything below it
+fn path_with_children(path: &Path) -> String {
+    let path = path.display().to_string();
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.trim_end_matches('/').chars() {
+        // Escape AppArmor glob characters so paths are matched literally
+        if matches!(
+            c,
+            '*' | '?' | '[' | ']' | '{' | '}' | '^' | ',' | '"' | '\\'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    let pattern = format!("{}{{,/**}}", escaped);
+    if pattern.contains(char::is_whitespace) {
+        format!("\"{}\"", pattern)
+    } else {
+        pattern
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::NetworkPolicy;
+    use rstest::rstest;
+
+    #[test]
+    fn export_deny_all_network_and_files() {
+        let mut policy = Policy::new();
+        policy.file.deny_read("/etc/shadow");
+        policy.file.deny_read_write("/root/.ssh");
+        policy.process.deny_exec("/usr/bin/curl");
+
+        let export = export(&policy, "build");
+        assert_eq!(export.files.len(), 1);
+        assert_eq!(export.files[0].file_name, "build");
+        let content = &export.files[0].content;
+        assert!(content.contains("profile build flags=(attach_disconnected) {"));
+        assert!(content.contains("  deny network inet,\n"));
+        assert!(content.contains("  deny network inet6,\n"));
+        assert!(content.contains("  deny /etc/shadow{,/**} r,\n"));
+        assert!(content.contains("  deny /root/.ssh{,/**} rw,\n"));
+        assert!(content.contains("  /{,**} ix,\n"));
+        assert!(content.contains("  deny /usr/bin/curl{,/**} x,\n"));
+        assert!(content.contains("  /{,**} rwmlk,\n"));
+        assert!(export.notes.is_empty());
+    }
+
+    #[test]
+    fn export_file_allow_list() {
+        let mut policy = Policy::new();
+        policy.file.allow("/work/project");
+        policy.file.deny_read("/work/project/.env");
+
+        let export = export(&policy, "build");
+        let content = &export.files[0].content;
+        assert!(!content.contains("/{,**} rwmlk,"));
+        assert!(content.contains("  /work/project{,/**} rwmlk,\n"));
+        assert!(content.contains("  /etc{,/**} rm,\n"));
+        assert!(content.contains("  /dev/null{,/**} rwmlk,\n"));
+        assert!(content.contains("  deny /work/project/.env{,/**} r,\n"));
+        assert!(export.notes.is_empty());
+    }
+
+    #[test]
+    fn export_allow_list_exec_and_network_entries() {
+        let mut policy = Policy::with_network(
+            NetworkPolicy::from_entries(&["example.com".to_string()]).unwrap(),
+        );
+        policy.process.allow_exec("/usr/bin/make");
+
+        let export = export(&policy, "mori");
+        let content = &export.files[0].content;
+        assert!(!content.contains("deny network"));
+        assert!(!content.contains("/{,**} ix,"));
+        assert!(content.contains("  /usr/bin/make{,/**} ix,\n"));
+        assert_eq!(export.notes.len(), 1);
+    }
+
+    #[rstest]
+    #[case("/var/log", "/var/log{,/**}")]
+    #[case("/var/log/", "/var/log{,/**}")]
+    #[case("/tmp/a*b", "/tmp/a\\*b{,/**}")]
+    #[case("/tmp/my dir", "\"/tmp/my dir{,/**}\"")]
+    fn path_with_children_escapes_globs(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(path_with_children(Path::new(path)), expected);
+    }
+}
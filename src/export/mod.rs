@@ -0,0 +1,40 @@
+pub mod apparmor;
+pub mod selinux;
+
+use clap::ValueEnum;
+
+use crate::policy::Policy;
+
+/// Policy formats of other security modules that mori can generate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// AppArmor profile
+    Apparmor,
+    /// SELinux reference policy module (.te and .fc)
+    Selinux,
+}
+
+/// A generated policy source file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportFile {
+    /// Conventional file name for the generated content
+    pub file_name: String,
+    pub content: String,
+}
+
+/// A policy rendered for another security module
+#[derive(Debug, Default)]
+pub struct Export {
+    /// Generated policy sources
+    pub files: Vec<ExportFile>,
+    /// Rules that could only be approximated or not expressed at all
+    pub notes: Vec<String>,
+}
+
+/// Render `policy` in the given format under the profile/module name `name`
+pub fn export(policy: &Policy, format: ExportFormat, name: &str) -> Export {
+    match format {
+        ExportFormat::Apparmor => apparmor::export(policy, name),
+        ExportFormat::Selinux => selinux::export(policy, name),
+    }
+}
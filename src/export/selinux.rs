@@ -0,0 +1,378 @@
+use std::{fmt::Write, path::Path};
+
+use super::{Export, ExportFile};
+use crate::policy::{AccessMode, AllowPolicy, Policy};
+
+/// Reference policy interfaces granting the system paths mori keeps
+/// available in allow-list mode (`/usr`, `/etc`, `/proc`, `/sys`, the
+/// standard device nodes, terminals and `/dev/shm`)
+const SYSTEM_PATH_INTERFACES: &[&str] = &[
+    "files_list_root",
+    "files_read_usr_files",
+    "files_read_etc_files",
+    "kernel_read_system_state",
+    "dev_read_sysfs",
+    "dev_rw_null",
+    "dev_rw_zero",
+    "dev_read_rand",
+    "dev_read_urand",
+    "term_use_controlling_term",
+    "term_use_ptmx",
+    "term_use_generic_ptys",
+    "fs_manage_tmpfs_files",
+];
+
+/// Generate a reference policy module (type enforcement and file contexts)
+/// approximating the file, network and exec policy
+///
+/// SELinux has no deny rules, so denied paths are relabeled with types the
+/// generated domain is simply not granted. They are declared as security file
+/// types, which keeps them out of the broad "non security files" access given
+/// to the domain while unconfined users keep their access. In allow-list mode
+/// that broad access is replaced by a type for the allowed paths.
+pub fn export(policy: &Policy, name: &str) -> Export {
+    let module = module_name(name);
+    let domain = format!("{}_t", module);
+    let mut notes = Vec::new();
+
+    // (type, description, rules granting the domain the still-allowed access)
+    let mut file_types: Vec<(String, String, Vec<String>)> = Vec::new();
+    // (path, type) pairs for the file contexts
+    let mut contexts: Vec<(&Path, String)> = Vec::new();
+
+    for (mode, suffix, rules) in [
+        (AccessMode::Read, "deny_read", vec!["write_files_pattern"]),
+        (
+            AccessMode::Write,
+            "deny_write",
+            vec!["list_dirs_pattern", "read_files_pattern"],
+        ),
+        (AccessMode::ReadWrite, "deny_rw", vec![]),
+    ] {
+        let paths: Vec<&Path> = policy
+            .file
+            .denied_paths
+            .iter()
+            .filter(|(_, m)| *m == mode)
+            .map(|(path, _)| path.as_path())
+            .collect();
+        if paths.is_empty() {
+            continue;
+        }
+        let file_type = format!("{}_{}_t", module, suffix);
+        let rules = rules
+            .iter()
+            .map(|pattern| format!("{}({}, {}, {})", pattern, domain, file_type, file_type))
+            .collect();
+        file_types.push((file_type.clone(), format!("{} paths", suffix), rules));
+        contexts.extend(paths.into_iter().map(|path| (path, file_type.clone())));
+    }
+
+    let mut te = String::new();
+    te.push_str("# SELinux policy module generated by mori\n");
+    let _ = writeln!(te, "policy_module({}, 1.0.0)\n", module);
+    te.push_str("gen_require(`\n    type unconfined_t;\n    role unconfined_r;\n')\n\n");
+
+    let _ = writeln!(te, "type {};", domain);
+    let _ = writeln!(te, "domain_type({})", domain);
+    let _ = writeln!(te, "role unconfined_r types {};", domain);
+    let _ = writeln!(te, "allow unconfined_t {}:process transition;\n", domain);
+
+    // Baseline: everything mori does not restrict
+    te.push_str("# Baseline access\n");
+    for rule in [
+        "allow DOMAIN self:process { fork signal_perms getsched setsched };",
+        "allow DOMAIN self:fifo_file rw_fifo_file_perms;",
+        "allow DOMAIN self:unix_stream_socket create_stream_socket_perms;",
+        "domain_use_interactive_fds(DOMAIN)",
+        "userdom_use_user_terminals(DOMAIN)",
+        "libs_use_ld_so(DOMAIN)",
+        "libs_use_shared_libs(DOMAIN)",
+        "miscfiles_read_localization(DOMAIN)",
+    ] {
+        let _ = writeln!(te, "{}", rule.replace("DOMAIN", &domain));
+    }
+    te.push('\n');
+
+    // Files: everything, or in allow-list mode only the allowed paths and the
+    // system paths
+    te.push_str("# File access\n");
+    if policy.file.allowed_paths.is_empty() {
+        let _ = writeln!(te, "files_manage_non_security_dirs({})", domain);
+        let _ = writeln!(te, "files_manage_non_security_files({})", domain);
+    } else {
+        let allowed_type = format!("{}_allowed_t", module);
+        let _ = writeln!(te, "type {};", allowed_type);
+        let _ = writeln!(te, "files_type({})", allowed_type);
+        for pattern in [
+            "manage_dirs_pattern",
+            "manage_files_pattern",
+            "manage_lnk_files_pattern",
+        ] {
+            let _ = writeln!(
+                te,
+                "{}({}, {}, {})",
+                pattern, domain, allowed_type, allowed_type
+            );
+        }
+        for interface in SYSTEM_PATH_INTERFACES {
+            let _ = writeln!(te, "{}({})", interface, domain);
+        }
+        contexts.extend(
+            policy
+                .file
+                .allowed_paths
+                .iter()
+                .map(|path| (path.as_path(), allowed_type.clone())),
+        );
+        notes.push(
+            "file: allowed paths are relabeled, which also affects other confined domains"
+                .to_string(),
+        );
+    }
+    te.push('\n');
+
+    // Network: SELinux mediates ports and sockets, not destination hosts
+    te.push_str("# Network\n");
+    let denied = &policy.network.denied;
+    match &policy.network.policy {
+        _ if policy.network.is_deny_all() => {
+            te.push_str("# All outbound network access is denied\n");
+        }
+        policy => {
+            if !matches!(policy, AllowPolicy::All) || !denied.is_empty() {
+                notes.push(
+                    "network: SELinux cannot restrict destinations; all outbound connections are allowed"
+                        .to_string(),
+                );
+            }
+            for rule in [
+                "allow DOMAIN self:tcp_socket create_stream_socket_perms;",
+                "allow DOMAIN self:udp_socket create_socket_perms;",
+                "corenet_tcp_connect_all_ports(DOMAIN)",
+                "corenet_tcp_sendrecv_generic_node(DOMAIN)",
+                "corenet_udp_sendrecv_generic_node(DOMAIN)",
+                "sysnet_dns_name_resolve(DOMAIN)",
+            ] {
+                let _ = writeln!(te, "{}", rule.replace("DOMAIN", &domain));
+            }
+        }
+    }
+    te.push('\n');
+
+    // Exec: with an allow-list only the relabeled programs may be executed
+    te.push_str("# Program execution\n");
+    if policy.process.allowed_exec.is_empty() {
+        let _ = writeln!(te, "corecmd_bin_entry_type({})", domain);
+        let _ = writeln!(te, "corecmd_exec_bin({})", domain);
+        let _ = writeln!(te, "corecmd_exec_shell({})", domain);
+    } else {
+        let exec_type = format!("{}_exec_allowed_t", module);
+        let _ = writeln!(te, "type {};", exec_type);
+        let _ = writeln!(te, "corecmd_executable_file({})", exec_type);
+        let _ = writeln!(te, "domain_entry_file({}, {})", domain, exec_type);
+        let _ = writeln!(te, "can_exec({}, {})", domain, exec_type);
+        contexts.extend(
+            policy
+                .process
+                .allowed_exec
+                .iter()
+                .map(|path| (path.as_path(), exec_type.clone())),
+        );
+    }
+    // Files denied execution by [file] deny_execute share the type
+    let denied_exec: Vec<&Path> = policy
+        .process
+        .denied_exec
+        .iter()
+        .map(|path| path.as_path())
+        .chain(
+            policy
+                .file
+                .denied_paths
+                .iter()
+                .filter(|(_, mode)| *mode == AccessMode::Execute)
+                .map(|(path, _)| path.as_path()),
+        )
+        .collect();
+    let relabels_exec = policy.process.has_exec_rules() || !denied_exec.is_empty();
+    if !denied_exec.is_empty() {
+        // Still executable by other domains, but not granted to this one
+        let exec_type = format!("{}_exec_denied_t", module);
+        let _ = writeln!(te, "type {};", exec_type);
+        let _ = writeln!(te, "corecmd_executable_file({})", exec_type);
+        contexts.extend(
+            denied_exec
+                .into_iter()
+                .map(|path| (path, exec_type.clone())),
+        );
+    }
+    if relabels_exec {
+        notes.push(
+            "exec: listed programs are relabeled, which also affects other confined domains"
+                .to_string(),
+        );
+    }
+
+    for (file_type, description, rules) in &file_types {
+        let _ = writeln!(te, "\n# Denied file access: {}", description);
+        let _ = writeln!(te, "type {};", file_type);
+        let _ = writeln!(te, "files_security_file({})", file_type);
+        for rule in rules {
+            let _ = writeln!(te, "{}", rule);
+        }
+    }
+
+    if policy.process.seccomp.is_some() {
+        notes.push(
+            "seccomp: not part of SELinux policy; load the seccomp filter separately".to_string(),
+        );
+    }
+
+    let mut fc = String::new();
+    for (path, file_type) in &contexts {
+        let _ = writeln!(
+            fc,
+            "{}(/.*)?\tgen_context(system_u:object_r:{},s0)",
+            escape_regex(path),
+            file_type
+        );
+    }
+
+    Export {
+        files: vec![
+            ExportFile {
+                file_name: format!("{}.te", module),
+                content: te,
+            },
+            ExportFile {
+                file_name: format!("{}.fc", module),
+                content: fc,
+            },
+        ],
+        notes,
+    }
+}
+
+/// Turn a profile name into a valid SELinux module/type prefix
+fn module_name(name: &str) -> String {
+    let module: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if module.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        module
+    } else {
+        format!("mori_{}", module)
+    }
+}
+
+/// Escape a path for use as a file context regular expression
+fn escape_regex(path: &Path) -> String {
+    let path = path.display().to_string();
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.trim_end_matches('/').chars() {
+        if "\\.+*?()[]{}|^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::NetworkPolicy;
+    use rstest::rstest;
+
+    #[test]
+    fn export_denied_paths_and_network() {
+        let mut policy = Policy::new();
+        policy.file.deny_read("/etc/shadow");
+        policy.file.deny_write("/var/log");
+
+        let export = export(&policy, "build");
+        assert_eq!(export.files[0].file_name, "build.te");
+        assert_eq!(export.files[1].file_name, "build.fc");
+
+        let te = &export.files[0].content;
+        assert!(te.contains("policy_module(build, 1.0.0)"));
+        assert!(te.contains("# All outbound network access is denied"));
+        assert!(!te.contains("corenet_tcp_connect_all_ports"));
+        assert!(te.contains("files_security_file(build_deny_read_t)"));
+        assert!(te.contains("write_files_pattern(build_t, build_deny_read_t, build_deny_read_t)"));
+        assert!(te.contains("read_files_pattern(build_t, build_deny_write_t, build_deny_write_t)"));
+        assert!(te.contains("corecmd_bin_entry_type(build_t)"));
+        assert!(te.contains("files_manage_non_security_files(build_t)"));
+
+        let fc = &export.files[1].content;
+        assert!(
+            fc.contains("/etc/shadow(/.*)?\tgen_context(system_u:object_r:build_deny_read_t,s0)")
+        );
+        assert!(
+            fc.contains("/var/log(/.*)?\tgen_context(system_u:object_r:build_deny_write_t,s0)")
+        );
+        assert!(export.notes.is_empty());
+    }
+
+    #[test]
+    fn export_file_allow_list() {
+        let mut policy = Policy::new();
+        policy.file.allow("/work/project");
+        policy.file.deny_read("/work/project/.env");
+
+        let export = export(&policy, "build");
+        let te = &export.files[0].content;
+        assert!(!te.contains("files_manage_non_security_files"));
+        assert!(te.contains("files_type(build_allowed_t)"));
+        assert!(te.contains("manage_files_pattern(build_t, build_allowed_t, build_allowed_t)"));
+        assert!(te.contains("files_read_etc_files(build_t)"));
+        assert!(te.contains("dev_rw_null(build_t)"));
+
+        let fc = &export.files[1].content;
+        assert!(
+            fc.contains("/work/project(/.*)?\tgen_context(system_u:object_r:build_allowed_t,s0)")
+        );
+        assert!(fc.contains(
+            "/work/project/\\.env(/.*)?\tgen_context(system_u:object_r:build_deny_read_t,s0)"
+        ));
+        assert_eq!(export.notes.len(), 1);
+    }
+
+    #[test]
+    fn export_exec_allow_list_and_network_entries() {
+        let mut policy =
+            Policy::with_network(NetworkPolicy::from_entries(&["192.0.2.1".to_string()]).unwrap());
+        policy.process.allow_exec("/usr/bin/make");
+
+        let export = export(&policy, "mori");
+        let te = &export.files[0].content;
+        assert!(te.contains("corenet_tcp_connect_all_ports(mori_t)"));
+        assert!(te.contains("domain_entry_file(mori_t, mori_exec_allowed_t)"));
+        assert!(!te.contains("corecmd_bin_entry_type"));
+        assert!(export.files[1].content.contains(
+            "/usr/bin/make(/.*)?\tgen_context(system_u:object_r:mori_exec_allowed_t,s0)"
+        ));
+        assert_eq!(export.notes.len(), 2);
+    }
+
+    #[rstest]
+    #[case("build", "build")]
+    #[case("My-App", "my_app")]
+    #[case("1st", "mori_1st")]
+    fn module_name_sanitizes(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(module_name(name), expected);
+    }
+
+    #[test]
+    fn escape_regex_escapes_metacharacters() {
+        assert_eq!(escape_regex(Path::new("/opt/app+1.0/")), "/opt/app\\+1\\.0");
+    }
+}
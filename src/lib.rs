@@ -1,5 +1,9 @@
+pub mod audit;
 pub mod cli;
+pub mod convert;
 pub mod error;
+pub mod export;
 pub mod net;
 pub mod policy;
 pub mod runtime;
+pub mod testing;
@@ -1,8 +1,24 @@
 use clap::Parser;
+#[cfg(target_os = "linux")]
+use mori::audit::{
+    flow::render_flow_log,
+    inventory::{render_file_inventory, render_file_rule_hits},
+};
+#[cfg(target_os = "linux")]
+use mori::{
+    audit::FlowLogFormat,
+    cli::TraceArgs,
+    policy::{NetworkPolicy, Policy},
+    runtime::{Backend, Credentials},
+};
 use mori::{
-    cli::{Args, PolicyLoader},
+    audit::{
+        ReportFormat,
+        ci::{self, RunSummary},
+    },
+    cli::{Args, Commands, PolicyLoader, RunArgs, commands},
     error::MoriError,
-    runtime::execute_with_policy,
+    runtime::{RunOptions, execute_with_policy},
 };
 
 #[tokio::main]
@@ -11,11 +27,177 @@ async fn main() -> Result<(), MoriError> {
 
     let args = Args::parse();
 
+    // Without a subcommand, the flat `mori [OPTIONS] -- COMMAND` form runs the command
+    let run_args = match args.subcommand {
+        None => args.run,
+        Some(Commands::Run(run_args)) => *run_args,
+        Some(Commands::Check) => std::process::exit(if commands::check()? { 0 } else { 1 }),
+        #[cfg(target_os = "linux")]
+        Some(Commands::Trace(trace_args)) => return trace(trace_args).await,
+        Some(Commands::Policy(policy)) => return commands::policy(&policy),
+        Some(Commands::Convert(convert)) => return commands::convert(&convert),
+        Some(Commands::Export(export)) => return commands::export(&export),
+        Some(Commands::GitHook(git_hook)) => return commands::git_hook(&git_hook),
+        Some(Commands::Completions(completions)) => return commands::completions(&completions),
+        Some(Commands::Mangen(mangen)) => return commands::mangen(&mangen),
+        Some(Commands::PolicyFrom(policy_from)) => return commands::policy_from(&policy_from),
+    };
+    run(run_args).await
+}
+
+/// Run the command in the sandbox and exit with its exit code
+async fn run(args: RunArgs) -> Result<(), MoriError> {
     let command = &args.command[0];
     let command_args: Vec<&str> = args.command[1..].iter().map(String::as_str).collect();
 
-    let policy = PolicyLoader::load(&args)?;
+    let policy = PolicyLoader::load(&args.policy_args)?;
+    // sandbox-exec cannot filter by domain, so the preset's allow list would deny everything
+    #[cfg(target_os = "macos")]
+    let policy = if args.policy_args.preset.is_some() && !policy.network.is_allow_all() {
+        let rule = "network access limited to the hosts of --preset".to_string();
+        if !args.best_effort {
+            return Err(MoriError::PolicyNotEnforceable { rules: vec![rule] });
+        }
+        eprintln!("mori: warning: not enforced: {}", rule);
+        mori::policy::Policy {
+            network: mori::policy::NetworkPolicy::from_allow_all(true),
+            ..policy
+        }
+    } else {
+        policy
+    };
+    let options = run_options(&args)?;
+
+    let outcome = execute_with_policy(command, &command_args, &policy, &options).await?;
+
+    if let Some(report) = &outcome.report {
+        match args.report_format {
+            ReportFormat::Text => eprint!("{}", report),
+            ReportFormat::Json => eprintln!("{}", report.to_json()),
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Some(path) = &args.audit_report {
+            std::fs::write(path, report.to_json())?;
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(path) = &args.flow_log {
+        std::fs::write(path, render_flow_log(&outcome.flows, args.flow_log_format))?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(path) = &args.file_inventory {
+        std::fs::write(path, render_file_inventory(&outcome.files))?;
+    }
+    #[cfg(target_os = "linux")]
+    if args.file_rule_stats {
+        eprint!("{}", render_file_rule_hits(&outcome.file_rules));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let fail_on_violation = args.fail_on_violation;
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let fail_on_violation = false;
+    // Keep the command's own failure; otherwise fail because of the violations
+    let exit_code = if fail_on_violation && outcome.has_violations() && outcome.exit_code == 0 {
+        eprintln!("mori: failing because of policy violations (--fail-on-violation)");
+        1
+    } else {
+        outcome.exit_code
+    };
+
+    if let Some(provider) = args.ci {
+        let summary = RunSummary {
+            command: &args.command,
+            exit_code,
+            audit: options.audit,
+            restrictions: &policy.restrictions(),
+            report: outcome.report.as_ref(),
+        };
+        if let Err(e) = ci::publish(provider, &summary) {
+            eprintln!("mori: failed to publish CI report: {}", e);
+        }
+    }
 
-    let exit_code = execute_with_policy(command, &command_args, &policy).await?;
     std::process::exit(exit_code);
 }
+
+/// Options for running the command; those this platform lacks keep their defaults
+fn run_options(args: &RunArgs) -> Result<RunOptions, MoriError> {
+    #[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(unused_mut))]
+    let mut options = RunOptions {
+        best_effort: args.best_effort,
+        ..Default::default()
+    };
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        options.audit = args.audit;
+        options.stdout_log = args.log_stdout.clone();
+        options.stderr_log = args.log_stderr.clone();
+        options.prefix_output = args.prefix_output;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        options.plugin = args.plugin.clone();
+        options.preload_fallback = args.preload_fallback;
+        options.flow_log = args.flow_log.is_some();
+        options.file_inventory = args.file_inventory.is_some();
+        options.file_rule_stats = args.file_rule_stats;
+        options.dns_refresh_margin = args.dns_refresh_margin;
+        options.dns_cache = args.dns_cache.clone();
+        options.reverse_dns = args.reverse_dns;
+        options.deny_errno = args.deny_errno;
+        options.private_tmp = args.private_tmp;
+        options.overlay = args.overlay.clone();
+        options.timeout = args.timeout;
+        options.user = args
+            .user
+            .as_deref()
+            .map(|user| Credentials::resolve(user, &args.groups))
+            .transpose()?;
+        options.chdir = args.chdir.clone();
+        options.clean_env = args.clean_env;
+        options.backend = args.backend;
+    }
+    Ok(options)
+}
+
+/// Run the command with all access allowed, recording the connections it
+/// makes and the files it opens
+///
+/// Recording needs the eBPF programs, so the unprivileged backend is not
+/// used.
+#[cfg(target_os = "linux")]
+async fn trace(args: TraceArgs) -> Result<(), MoriError> {
+    let command = &args.command[0];
+    let command_args: Vec<&str> = args.command[1..].iter().map(String::as_str).collect();
+
+    let policy = Policy::with_network(NetworkPolicy::from_allow_all(true));
+    let options = RunOptions {
+        flow_log: true,
+        file_inventory: true,
+        backend: Backend::Ebpf,
+        ..Default::default()
+    };
+    let outcome = execute_with_policy(command, &command_args, &policy, &options).await?;
+
+    std::fs::write(
+        &args.flow_log,
+        render_flow_log(&outcome.flows, FlowLogFormat::Jsonl),
+    )?;
+    std::fs::write(&args.file_inventory, render_file_inventory(&outcome.files))?;
+    eprintln!(
+        "mori: recorded {} connections in {} and {} files in {}",
+        outcome.flows.len(),
+        args.flow_log.display(),
+        outcome.files.len(),
+        args.file_inventory.display()
+    );
+    eprintln!(
+        "mori: run `mori policy-from {} --file-inventory {}` for a configuration allowing them",
+        args.flow_log.display(),
+        args.file_inventory.display()
+    );
+
+    std::process::exit(outcome.exit_code);
+}
@@ -1,39 +1,175 @@
 use std::{
-    collections::HashMap,
-    net::Ipv4Addr,
-    time::{Duration, Instant},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, fs, io,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use serde::{Deserialize, Serialize};
+
+use crate::error::MoriError;
+
+use super::parser::PortRule;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Entry {
-    pub ip: Ipv4Addr,
+    pub ip: IpAddr,
     pub expires_at: Instant,
 }
 
+/// How long before a DNS record expires it is resolved again
+///
+/// Refreshing early installs the new addresses while the old ones are still
+/// allowed, so a reconnect racing the refresh never finds the domain blocked.
+/// The margin never exceeds half of the record's TTL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshMargin {
+    /// Percentage of the TTL (`10%`)
+    Percent(u8),
+    /// Fixed duration (`5s`)
+    Fixed(Duration),
+}
+
+impl Default for RefreshMargin {
+    fn default() -> Self {
+        RefreshMargin::Percent(10)
+    }
+}
+
+impl RefreshMargin {
+    /// Margin applied to a record with the given TTL
+    pub fn for_ttl(self, ttl: Duration) -> Duration {
+        let margin = match self {
+            RefreshMargin::Percent(percent) => ttl * u32::from(percent) / 100,
+            RefreshMargin::Fixed(margin) => margin,
+        };
+        margin.min(ttl / 2)
+    }
+}
+
+impl FromStr for RefreshMargin {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(percent) = value.strip_suffix('%') {
+            return match percent.parse::<u8>() {
+                Ok(percent) if percent <= 50 => Ok(RefreshMargin::Percent(percent)),
+                _ => Err(format!("invalid percentage {} (0-50%)", value)),
+            };
+        }
+        value
+            .strip_suffix('s')
+            .unwrap_or(value)
+            .parse::<u64>()
+            .map(|secs| RefreshMargin::Fixed(Duration::from_secs(secs)))
+            .map_err(|_| format!("invalid margin {} (expected e.g. 10% or 5s)", value))
+    }
+}
+
+impl fmt::Display for RefreshMargin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefreshMargin::Percent(percent) => write!(f, "{}%", percent),
+            RefreshMargin::Fixed(margin) => write!(f, "{}s", margin.as_secs()),
+        }
+    }
+}
+
+/// DNS records saved between runs (see `DnsCache::save`)
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedRecords {
+    #[serde(default)]
+    domains: BTreeMap<String, Vec<SavedEntry>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedEntry {
+    ip: IpAddr,
+    /// Expiry in seconds since the Unix epoch
+    expires_at: u64,
+}
+
+impl SavedRecords {
+    fn read(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                log::warn!("Ignoring unreadable DNS cache {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(err) => {
+                log::warn!("Failed to read DNS cache {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+}
+
+/// Seconds since the Unix epoch of the wall-clock time `at` stands for
+fn unix_secs(at: Instant, now: Instant, wall_now: SystemTime) -> u64 {
+    let wall = wall_now + at.saturating_duration_since(now);
+    wall.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 #[derive(Default, Debug)]
 pub struct UpdateDiff {
-    pub added: Vec<Ipv4Addr>,
-    pub removed: Vec<Ipv4Addr>,
+    pub added: Vec<IpAddr>,
+    pub removed: Vec<IpAddr>,
 }
 
 #[derive(Default, Debug)]
 pub struct DnsCache {
-    per_domain: HashMap<String, HashMap<Ipv4Addr, Instant>>,
+    per_domain: HashMap<String, HashMap<IpAddr, Instant>>,
+    /// When each domain was last resolved, to recover the TTL of its records
+    resolved_at: HashMap<String, Instant>,
+    /// When each address learned from DNS entered the allow list
+    added_at: HashMap<IpAddr, Instant>,
+    /// Addresses also allowed by a static rule; DNS updates never add or remove them
+    pinned: HashSet<IpAddr>,
+    /// Number of allow list entries that do not come from DNS
+    static_entries: usize,
+    /// Destination ports of the domains only allowed on some ports
+    ports: HashMap<String, BTreeSet<PortRule>>,
+    /// Static host mappings; never refreshed or expired
+    hosts: HashMap<String, Vec<IpAddr>>,
+    /// Bounds applied to the TTL of every record (see `with_ttl_bounds`)
+    min_ttl: Option<Duration>,
+    max_ttl: Option<Duration>,
 }
 
 impl DnsCache {
+    /// Create a cache that clamps the TTL of the records it is given
+    ///
+    /// A record expires no sooner than `min_ttl` and no later than `max_ttl`
+    /// after it is applied; `max_ttl` wins if the bounds conflict.
+    pub fn with_ttl_bounds(min_ttl: Option<Duration>, max_ttl: Option<Duration>) -> Self {
+        Self {
+            min_ttl,
+            max_ttl,
+            ..Self::default()
+        }
+    }
+
     /// Apply new DNS resolution results and calculate the diff from previous state
     ///
     /// Updates the cache for a given domain with new DNS entries and returns
     /// which IP addresses were added or removed since the last update.
     ///
     /// # Behavior
-    /// 1. Filters out already-expired entries (where `expires_at <= now`)
+    /// 1. Clamps each entry's TTL to the cache's bounds, then filters out
+    ///    already-expired entries (where `expires_at <= now`)
     /// 2. For duplicate IPs in new entries, keeps the one with latest expiration
     /// 3. Compares new state with previous state to detect changes
     /// 4. Returns `UpdateDiff` containing:
     ///    - `added`: IPs present in new state but not in previous state
     ///    - `removed`: IPs present in previous state but not in new state
+    ///
+    ///    IPs that another domain still resolves to, or that are pinned by a
+    ///    static rule, are left out of both lists.
     /// 5. Replaces the domain's cached state with the new state
     ///
     /// # Arguments
@@ -44,30 +180,32 @@ impl DnsCache {
     /// # Returns
     /// `UpdateDiff` containing added and removed IP addresses
     pub fn apply(&mut self, domain: &str, now: Instant, new_entries: Vec<Entry>) -> UpdateDiff {
-        let state = self.per_domain.entry(domain.to_string()).or_default();
-
-        let mut new_state: HashMap<Ipv4Addr, Instant> = HashMap::new();
+        self.resolved_at.insert(domain.to_string(), now);
+        let mut new_state: HashMap<IpAddr, Instant> = HashMap::new();
         for entry in new_entries {
-            if entry.expires_at <= now {
+            let expires_at = self.clamp_expiry(now, entry.expires_at);
+            if expires_at <= now {
                 continue;
             }
             new_state
                 .entry(entry.ip)
                 .and_modify(|expires| {
-                    if *expires < entry.expires_at {
-                        *expires = entry.expires_at;
+                    if *expires < expires_at {
+                        *expires = expires_at;
                     }
                 })
-                .or_insert(entry.expires_at);
+                .or_insert(expires_at);
         }
 
-        let mut removed: Vec<Ipv4Addr> = state
+        let state = self.per_domain.entry(domain.to_string()).or_default();
+
+        let mut removed: Vec<IpAddr> = state
             .keys()
             .filter(|ip| !new_state.contains_key(ip))
             .copied()
             .collect();
 
-        let mut added: Vec<Ipv4Addr> = new_state
+        let mut added: Vec<IpAddr> = new_state
             .keys()
             .filter(|ip| !state.contains_key(ip))
             .copied()
@@ -75,6 +213,19 @@ impl DnsCache {
 
         *state = new_state;
 
+        // The allow list holds each address once, whichever domains resolve to it
+        added.retain(|ip| !self.pinned.contains(ip) && !self.added_at.contains_key(ip));
+        for &ip in &added {
+            self.added_at.insert(ip, now);
+        }
+        removed.retain(|ip| {
+            let still_resolved = self.per_domain.values().any(|ips| ips.contains_key(ip));
+            if !still_resolved {
+                self.added_at.remove(ip);
+            }
+            !still_resolved && !self.pinned.contains(ip)
+        });
+
         removed.sort();
         removed.dedup();
         added.sort();
@@ -83,24 +234,266 @@ impl DnsCache {
         UpdateDiff { added, removed }
     }
 
+    /// `expires_at` with the time left until then clamped to the TTL bounds
+    fn clamp_expiry(&self, now: Instant, expires_at: Instant) -> Instant {
+        if self.min_ttl.is_none() && self.max_ttl.is_none() {
+            return expires_at;
+        }
+        let mut ttl = expires_at.saturating_duration_since(now);
+        if let Some(min_ttl) = self.min_ttl {
+            ttl = ttl.max(min_ttl);
+        }
+        if let Some(max_ttl) = self.max_ttl {
+            ttl = ttl.min(max_ttl);
+        }
+        now + ttl
+    }
+
     /// Calculate the duration until the next DNS refresh is needed
     ///
-    /// Returns the time until the earliest expiring entry across all cached domains.
-    /// This allows the refresh thread to sleep for the optimal duration before
-    /// re-resolving domain names.
+    /// Returns the time until the earliest expiring entry across all cached domains,
+    /// brought forward by `margin`. This allows the refresh thread to sleep for the
+    /// optimal duration before re-resolving domain names.
     ///
     /// # Behavior
     /// - Iterates through all domains and their IP entries
-    /// - Calculates time remaining until each entry expires (saturating to 0 if already expired)
-    /// - Returns the minimum duration (earliest expiration)
+    /// - Subtracts the margin for the entry's TTL (time from resolution to expiry)
+    /// - Calculates time remaining until then (saturating to 0 if already due)
+    /// - Returns the minimum duration (earliest refresh)
     /// - Returns `None` if cache is empty
-    pub fn next_refresh_in(&self, now: Instant) -> Option<Duration> {
+    pub fn next_refresh_in(&self, now: Instant, margin: RefreshMargin) -> Option<Duration> {
         self.per_domain
-            .values()
-            .flat_map(|ips| ips.values())
-            .map(|expires| expires.saturating_duration_since(now))
+            .iter()
+            .flat_map(|(domain, ips)| {
+                let resolved_at = self.resolved_at.get(domain).copied();
+                ips.values().map(move |&expires| {
+                    let ttl = resolved_at
+                        .map(|resolved_at| expires.saturating_duration_since(resolved_at))
+                        .unwrap_or_default();
+                    expires - margin.for_ttl(ttl)
+                })
+            })
+            .map(|refresh_at| refresh_at.saturating_duration_since(now))
             .min()
     }
+
+    /// Record an allow list entry that does not come from DNS
+    ///
+    /// Single addresses (`prefix_len` 32, or 128 for IPv6) are pinned: DNS updates and eviction
+    /// never remove them from the allow list.
+    pub fn pin(&mut self, ip: IpAddr, prefix_len: u8) {
+        self.static_entries += 1;
+        let single = match ip {
+            IpAddr::V4(_) => prefix_len == 32,
+            IpAddr::V6(_) => prefix_len == 128,
+        };
+        if single && self.pinned.insert(ip) {
+            self.added_at.remove(&ip);
+        }
+    }
+
+    /// Record the fixed addresses of a static host mapping
+    ///
+    /// The host is looked up by `domains_for` and `addresses` like a resolved
+    /// domain, but never needs a refresh. The addresses themselves are pinned
+    /// separately with `pin`.
+    pub fn seed(&mut self, host: &str, addrs: &[IpAddr]) {
+        self.hosts
+            .entry(host.to_string())
+            .or_default()
+            .extend_from_slice(addrs);
+    }
+
+    /// Number of allow list entries, static and learned from DNS
+    pub fn allow_list_len(&self) -> usize {
+        self.static_entries + self.added_at.len()
+    }
+
+    /// Forget up to `count` addresses learned from DNS, coldest first
+    ///
+    /// Addresses whose records have all expired go first, then the ones that
+    /// entered the allow list earliest. Returns the evicted addresses, which
+    /// the caller removes from the allow list.
+    pub fn evict(&mut self, now: Instant, count: usize) -> Vec<IpAddr> {
+        let expires_at = |ip: &IpAddr| {
+            self.per_domain
+                .values()
+                .filter_map(|ips| ips.get(ip))
+                .max()
+                .copied()
+        };
+        let mut candidates: Vec<(bool, Instant, IpAddr)> = self
+            .added_at
+            .iter()
+            .map(|(ip, added_at)| {
+                let live = expires_at(ip).is_some_and(|expires| expires > now);
+                (live, *added_at, *ip)
+            })
+            .collect();
+        candidates.sort_unstable();
+
+        let mut evicted: Vec<IpAddr> = candidates
+            .into_iter()
+            .take(count)
+            .map(|(_, _, ip)| ip)
+            .collect();
+        for ip in &evicted {
+            self.added_at.remove(ip);
+            for ips in self.per_domain.values_mut() {
+                ips.remove(ip);
+            }
+        }
+        evicted.sort();
+        evicted
+    }
+
+    /// Addresses learned from DNS with the latest expiry of their records, sorted
+    ///
+    /// Pinned addresses are left out, since static rules never expire.
+    pub fn learned_expirations(&self) -> Vec<(IpAddr, Instant)> {
+        let mut expirations: Vec<(IpAddr, Instant)> = self
+            .added_at
+            .keys()
+            .filter_map(|ip| {
+                let expires_at = self
+                    .per_domain
+                    .values()
+                    .filter_map(|ips| ips.get(ip))
+                    .max()?;
+                Some((*ip, *expires_at))
+            })
+            .collect();
+        expirations.sort_unstable();
+        expirations
+    }
+
+    /// Only allow the addresses of `domain` on the given destination ports
+    pub fn restrict_ports(&mut self, domain: &str, ports: BTreeSet<PortRule>) {
+        self.ports.insert(domain.to_string(), ports);
+    }
+
+    /// Ports the domains resolving to `ip` allow it on; `None` allows every port
+    pub fn ports_for(&self, ip: IpAddr) -> BTreeSet<Option<PortRule>> {
+        self.domains_for(ip)
+            .into_iter()
+            .flat_map(|domain| match self.ports.get(domain) {
+                Some(ports) => ports.iter().copied().map(Some).collect(),
+                None => vec![None],
+            })
+            .collect()
+    }
+
+    /// Every port some domain is restricted to
+    pub fn restricted_ports(&self) -> BTreeSet<PortRule> {
+        self.ports.values().flatten().copied().collect()
+    }
+
+    /// Domains currently resolving to `ip`, sorted by name
+    pub fn domains_for(&self, ip: IpAddr) -> Vec<&str> {
+        let mut domains: Vec<&str> = self
+            .per_domain
+            .iter()
+            .filter(|(_, ips)| ips.contains_key(&ip))
+            .map(|(domain, _)| domain.as_str())
+            .chain(
+                self.hosts
+                    .iter()
+                    .filter(|(_, addrs)| addrs.contains(&ip))
+                    .map(|(host, _)| host.as_str()),
+            )
+            .collect();
+        domains.sort_unstable();
+        domains.dedup();
+        domains
+    }
+
+    /// Addresses `domain` currently resolves to, sorted
+    pub fn addresses(&self, domain: &str) -> Vec<IpAddr> {
+        let mut addrs: Vec<IpAddr> = self
+            .per_domain
+            .get(domain)
+            .map(|ips| ips.keys().copied().collect())
+            .unwrap_or_default();
+        addrs.extend(self.hosts.get(domain).into_iter().flatten());
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Records saved at `path` by earlier runs that have not expired yet
+    ///
+    /// A missing or unreadable file yields no records, so the domains are
+    /// simply resolved again.
+    pub fn load_saved(path: &Path, now: Instant) -> HashMap<String, Vec<Entry>> {
+        Self::load_saved_at(path, now, SystemTime::now())
+    }
+
+    /// `load_saved` with `now` standing for the wall-clock time `wall_now`
+    fn load_saved_at(
+        path: &Path,
+        now: Instant,
+        wall_now: SystemTime,
+    ) -> HashMap<String, Vec<Entry>> {
+        let wall_now = unix_secs(now, now, wall_now);
+        SavedRecords::read(path)
+            .domains
+            .into_iter()
+            .filter_map(|(domain, entries)| {
+                let entries: Vec<Entry> = entries
+                    .into_iter()
+                    .filter(|entry| entry.expires_at > wall_now)
+                    .map(|entry| Entry {
+                        ip: entry.ip,
+                        expires_at: now + Duration::from_secs(entry.expires_at - wall_now),
+                    })
+                    .collect();
+                (!entries.is_empty()).then_some((domain, entries))
+            })
+            .collect()
+    }
+
+    /// Save the unexpired records of the resolved domains to `path`
+    ///
+    /// Records other runs saved for other domains are kept until they expire,
+    /// so runs with different allow lists can share the file. It is replaced
+    /// atomically, and its directory created if needed.
+    pub fn save(&self, path: &Path, now: Instant) -> Result<(), MoriError> {
+        let wall_now = SystemTime::now();
+        let now_secs = unix_secs(now, now, wall_now);
+        let mut saved = SavedRecords::read(path);
+        saved.domains.retain(|_, entries| {
+            entries.retain(|entry| entry.expires_at > now_secs);
+            !entries.is_empty()
+        });
+        for (domain, ips) in &self.per_domain {
+            let entries: Vec<SavedEntry> = ips
+                .iter()
+                .filter(|(_, expires_at)| **expires_at > now)
+                .map(|(&ip, &expires_at)| SavedEntry {
+                    ip,
+                    expires_at: unix_secs(expires_at, now, wall_now),
+                })
+                .collect();
+            if entries.is_empty() {
+                saved.domains.remove(domain);
+            } else {
+                saved.domains.insert(domain.clone(), entries);
+            }
+        }
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = serde_json::to_string(&saved).map_err(io::Error::other)?;
+        let tmp = path.with_file_name(format!(
+            ".{}.{}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            std::process::id()
+        ));
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -113,7 +506,7 @@ mod tests {
         let mut cache = DnsCache::default();
         let now = Instant::now();
         let entry = Entry {
-            ip: Ipv4Addr::new(192, 168, 0, 1),
+            ip: IpAddr::from([192, 168, 0, 1]),
             expires_at: now + Duration::from_secs(60),
         };
 
@@ -123,12 +516,93 @@ mod tests {
         assert!(diff.removed.is_empty());
     }
 
+    #[test]
+    fn ttl_bounds_clamp_record_expiry() {
+        let now = Instant::now();
+        let refresh_in = |mut cache: DnsCache, ttl: u64| {
+            let entry = Entry {
+                ip: IpAddr::from([192, 0, 2, 1]),
+                expires_at: now + Duration::from_secs(ttl),
+            };
+            cache.apply("example.com", now, vec![entry]);
+            cache.next_refresh_in(now, RefreshMargin::Fixed(Duration::ZERO))
+        };
+        let bounded = || {
+            DnsCache::with_ttl_bounds(
+                Some(Duration::from_secs(30)),
+                Some(Duration::from_secs(600)),
+            )
+        };
+
+        assert_eq!(refresh_in(bounded(), 5), Some(Duration::from_secs(30)));
+        // Records that already expired are kept for the minimum TTL
+        assert_eq!(refresh_in(bounded(), 0), Some(Duration::from_secs(30)));
+        assert_eq!(refresh_in(bounded(), 3600), Some(Duration::from_secs(600)));
+        assert_eq!(refresh_in(bounded(), 60), Some(Duration::from_secs(60)));
+        assert_eq!(
+            refresh_in(DnsCache::default(), 5),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn saved_records_survive_until_they_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mori").join("dns.json");
+        let now = Instant::now();
+        let record = |ip: [u8; 4], ttl| Entry {
+            ip: IpAddr::from(ip),
+            expires_at: now + Duration::from_secs(ttl),
+        };
+
+        let mut cache = DnsCache::default();
+        cache.apply("example.com", now, vec![record([192, 0, 2, 1], 300)]);
+        cache.save(&path, now).unwrap();
+        // Another run keeps the domains it did not resolve
+        let mut other = DnsCache::default();
+        other.apply("example.org", now, vec![record([192, 0, 2, 2], 300)]);
+        other.save(&path, now).unwrap();
+
+        let loaded = DnsCache::load_saved(&path, now);
+        assert_eq!(loaded.len(), 2);
+        let entries = &loaded["example.com"];
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].ip, IpAddr::from([192, 0, 2, 1]));
+        // Saved expiries are rounded to whole seconds
+        let ttl = entries[0].expires_at - now;
+        assert!(ttl > Duration::from_secs(298) && ttl <= Duration::from_secs(300));
+
+        let later = SystemTime::now() + Duration::from_secs(301);
+        assert!(DnsCache::load_saved_at(&path, now, later).is_empty());
+        assert!(DnsCache::load_saved(&dir.path().join("missing.json"), now).is_empty());
+    }
+
+    #[test]
+    fn domains_for_lists_domains_sharing_an_ip() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let entry = Entry {
+            ip: IpAddr::from([192, 0, 2, 1]),
+            expires_at: now + Duration::from_secs(60),
+        };
+        cache.apply("www.example.com", now, vec![entry.clone()]);
+        cache.apply("example.com", now, vec![entry.clone()]);
+
+        assert_eq!(
+            cache.domains_for(entry.ip),
+            vec!["example.com", "www.example.com"]
+        );
+        assert!(cache.domains_for(IpAddr::from([192, 0, 2, 2])).is_empty());
+        assert_eq!(cache.addresses("example.com"), vec![entry.ip]);
+        assert!(cache.addresses("example.org").is_empty());
+    }
+
     #[test]
     fn expires_old_ips() {
         let mut cache = DnsCache::default();
         let now = Instant::now();
         let entry = Entry {
-            ip: Ipv4Addr::new(10, 0, 0, 1),
+            ip: IpAddr::from([10, 0, 0, 1]),
             expires_at: now + Duration::from_secs(30),
         };
         cache.apply("example.com", now, vec![entry.clone()]);
@@ -140,6 +614,70 @@ mod tests {
         assert_eq!(diff.removed, vec![entry.ip]);
     }
 
+    #[test]
+    fn shared_ips_stay_until_no_domain_resolves_to_them() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let entry = Entry {
+            ip: IpAddr::from([192, 0, 2, 1]),
+            expires_at: now + Duration::from_secs(60),
+        };
+        cache.apply("example.com", now, vec![entry.clone()]);
+        let diff = cache.apply("www.example.com", now, vec![entry.clone()]);
+        assert!(diff.added.is_empty());
+
+        let diff = cache.apply("example.com", now, vec![]);
+        assert!(diff.removed.is_empty());
+        let diff = cache.apply("www.example.com", now, vec![]);
+        assert_eq!(diff.removed, vec![entry.ip]);
+    }
+
+    #[test]
+    fn pinned_ips_are_never_added_or_removed() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        cache.pin(ip, 32);
+        cache.pin(IpAddr::from([10, 0, 0, 0]), 8);
+
+        let diff = cache.apply(
+            "example.com",
+            now,
+            vec![Entry {
+                ip,
+                expires_at: now + Duration::from_secs(60),
+            }],
+        );
+        assert!(diff.added.is_empty());
+        assert_eq!(cache.allow_list_len(), 2);
+
+        let diff = cache.apply("example.com", now, vec![]);
+        assert!(diff.removed.is_empty());
+        assert!(cache.evict(now, 10).is_empty());
+    }
+
+    #[test]
+    fn evict_prefers_expired_then_oldest_entries() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let entry = |last: u8, ttl: u64| Entry {
+            ip: IpAddr::from([192, 0, 2, last]),
+            expires_at: now + Duration::from_secs(ttl),
+        };
+        cache.apply("old.example", now, vec![entry(1, 600)]);
+        cache.apply("short.example", now, vec![entry(2, 5)]);
+        let later = now + Duration::from_secs(10);
+        cache.apply("new.example", later, vec![entry(3, 600)]);
+        assert_eq!(cache.allow_list_len(), 3);
+
+        assert_eq!(
+            cache.evict(later, 2),
+            vec![IpAddr::from([192, 0, 2, 1]), IpAddr::from([192, 0, 2, 2])]
+        );
+        assert_eq!(cache.allow_list_len(), 1);
+        assert!(cache.domains_for(IpAddr::from([192, 0, 2, 1])).is_empty());
+    }
+
     #[test]
     fn next_refresh_tracks_soonest_expiry() {
         let mut cache = DnsCache::default();
@@ -148,7 +686,7 @@ mod tests {
             "example.com",
             now,
             vec![Entry {
-                ip: Ipv4Addr::new(1, 1, 1, 1),
+                ip: IpAddr::from([1, 1, 1, 1]),
                 expires_at: now + Duration::from_secs(5),
             }],
         );
@@ -156,12 +694,100 @@ mod tests {
             "example.net",
             now,
             vec![Entry {
-                ip: Ipv4Addr::new(2, 2, 2, 2),
+                ip: IpAddr::from([2, 2, 2, 2]),
                 expires_at: now + Duration::from_secs(10),
             }],
         );
 
-        let refresh = cache.next_refresh_in(now).expect("has entries");
+        let refresh = cache
+            .next_refresh_in(now, RefreshMargin::Fixed(Duration::ZERO))
+            .expect("has entries");
         assert_eq!(refresh, Duration::from_secs(5));
     }
+
+    #[test]
+    fn next_refresh_leaves_margin_before_expiry() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        cache.apply(
+            "example.com",
+            now,
+            vec![Entry {
+                ip: IpAddr::from([1, 1, 1, 1]),
+                expires_at: now + Duration::from_secs(300),
+            }],
+        );
+
+        let refresh = |margin| cache.next_refresh_in(now, margin).unwrap();
+        assert_eq!(
+            refresh(RefreshMargin::Percent(10)),
+            Duration::from_secs(270)
+        );
+        assert_eq!(
+            refresh(RefreshMargin::Fixed(Duration::from_secs(5))),
+            Duration::from_secs(295)
+        );
+        // Never more than half the TTL early
+        assert_eq!(
+            refresh(RefreshMargin::Fixed(Duration::from_secs(600))),
+            Duration::from_secs(150)
+        );
+    }
+
+    #[test]
+    fn refresh_margin_parses_percent_and_seconds() {
+        assert_eq!("10%".parse(), Ok(RefreshMargin::Percent(10)));
+        assert_eq!(
+            "5s".parse(),
+            Ok(RefreshMargin::Fixed(Duration::from_secs(5)))
+        );
+        assert_eq!(
+            "5".parse(),
+            Ok(RefreshMargin::Fixed(Duration::from_secs(5)))
+        );
+        assert!("75%".parse::<RefreshMargin>().is_err());
+        assert!("soon".parse::<RefreshMargin>().is_err());
+        assert_eq!(RefreshMargin::default().to_string(), "10%");
+    }
+
+    #[test]
+    fn ports_for_combines_the_domains_of_an_address() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let ip = IpAddr::from([93, 184, 216, 34]);
+        let entry = Entry {
+            ip,
+            expires_at: now + Duration::from_secs(60),
+        };
+        cache.restrict_ports("example.com", BTreeSet::from([443.into()]));
+        cache.restrict_ports("example.org", BTreeSet::from([80.into(), 443.into()]));
+        cache.apply("example.com", now, vec![entry.clone()]);
+        cache.apply("example.org", now, vec![entry.clone()]);
+        assert_eq!(
+            cache.ports_for(ip),
+            BTreeSet::from([Some(80.into()), Some(443.into())])
+        );
+        assert_eq!(
+            cache.restricted_ports(),
+            BTreeSet::from([80.into(), 443.into()])
+        );
+
+        cache.apply("example.net", now, vec![entry]);
+        assert!(cache.ports_for(ip).contains(&None));
+    }
+
+    #[test]
+    fn seeded_hosts_are_named_without_refresh() {
+        let mut cache = DnsCache::default();
+        let ip = IpAddr::from([10, 1, 2, 3]);
+        cache.pin(ip, 32);
+        cache.seed("internal.db", &[ip]);
+        assert_eq!(cache.domains_for(ip), vec!["internal.db"]);
+        assert_eq!(cache.addresses("internal.db"), vec![ip]);
+        assert_eq!(
+            cache.next_refresh_in(Instant::now(), RefreshMargin::default()),
+            None
+        );
+        assert!(cache.learned_expirations().is_empty());
+    }
 }
@@ -1,7 +1,8 @@
 pub mod cache;
 pub mod parser;
+pub mod psl;
 pub mod resolver;
 
 // Re-export main types and functions
-pub use parser::{NetworkRules, parse_allow_network};
-pub use resolver::{DnsResolver, ResolvedAddresses, SystemDnsResolver};
+pub use parser::{NetworkRules, PortRule, Protocol, parse_allow_network};
+pub use resolver::{DnsResolver, ResolvedAddresses, ReverseResolver, SystemDnsResolver};
@@ -0,0 +1,96 @@
+//! Public suffixes that a domain rule must never cover
+//!
+//! A compact subset of the Public Suffix List (<https://publicsuffix.org/>):
+//! every top-level domain is treated as public, plus the common multi-label
+//! suffixes under which anyone can register a name.
+
+/// Multi-label public suffixes (registry and well-known private sections)
+const MULTI_LABEL_SUFFIXES: &[&str] = &[
+    // Country-code second-level domains
+    "ac.uk",
+    "co.uk",
+    "gov.uk",
+    "ltd.uk",
+    "me.uk",
+    "net.uk",
+    "org.uk",
+    "plc.uk",
+    "com.au",
+    "edu.au",
+    "gov.au",
+    "net.au",
+    "org.au",
+    "co.jp",
+    "ac.jp",
+    "go.jp",
+    "ne.jp",
+    "or.jp",
+    "co.kr",
+    "or.kr",
+    "co.nz",
+    "net.nz",
+    "org.nz",
+    "co.za",
+    "org.za",
+    "co.in",
+    "net.in",
+    "org.in",
+    "com.br",
+    "net.br",
+    "org.br",
+    "com.cn",
+    "net.cn",
+    "org.cn",
+    "com.mx",
+    "com.tw",
+    "com.hk",
+    "com.sg",
+    "com.tr",
+    "com.ar",
+    // Hosting platforms that hand out subdomains to their users
+    "github.io",
+    "githubusercontent.com",
+    "gitlab.io",
+    "herokuapp.com",
+    "netlify.app",
+    "vercel.app",
+    "pages.dev",
+    "workers.dev",
+    "web.app",
+    "firebaseapp.com",
+    "appspot.com",
+    "azurewebsites.net",
+    "cloudfront.net",
+    "s3.amazonaws.com",
+    "blogspot.com",
+    "ngrok.io",
+    "ngrok-free.app",
+];
+
+/// Whether `domain` is a public suffix
+///
+/// Comparison ignores case and a trailing dot.
+pub fn is_public_suffix(domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    !domain.is_empty() && (!domain.contains('.') || MULTI_LABEL_SUFFIXES.contains(&domain.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::tld("com", true)]
+    #[case::tld_trailing_dot("org.", true)]
+    #[case::country_second_level("co.uk", true)]
+    #[case::uppercase("CO.UK", true)]
+    #[case::hosting_platform("github.io", true)]
+    #[case::registered_domain("example.com", false)]
+    #[case::registered_under_country("example.co.uk", false)]
+    #[case::user_site("user.github.io", false)]
+    #[case::empty("", false)]
+    fn test_is_public_suffix(#[case] domain: &str, #[case] expected: bool) {
+        assert_eq!(is_public_suffix(domain), expected);
+    }
+}
@@ -1,10 +1,26 @@
 use std::{
     collections::HashSet,
-    net::{IpAddr, Ipv4Addr},
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
-use hickory_resolver::{Resolver, config::ResolverConfig, system_conf};
+use hickory_resolver::{
+    Name, ResolveError, Resolver, TokioResolver,
+    config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    name_server::TokioConnectionProvider,
+    proto::rr::{
+        RData, RecordType,
+        rdata::{
+            HTTPS, SVCB,
+            svcb::{IpHint, SvcParamValue},
+        },
+    },
+    system_conf,
+};
+use tokio::{sync::Semaphore, task::JoinSet};
 
 #[cfg(test)]
 use mockall::automock;
@@ -12,18 +28,65 @@ use mockall::automock;
 use super::cache::Entry;
 use crate::error::MoriError;
 
+/// Addresses of the systemd-resolved stub listeners
+const RESOLVED_STUB_ADDRS: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(127, 0, 0, 53), Ipv4Addr::new(127, 0, 0, 54)];
+
+/// resolv.conf maintained by systemd-resolved with the upstream servers it forwards to
+const RESOLVED_UPLINK_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+/// Most domains looked up at the same time
+const MAX_CONCURRENT_LOOKUPS: usize = 16;
+
+/// How long `resolve_strict` retries the domains that do not resolve
+pub const STRICT_RESOLVE_DEADLINE: Duration = Duration::from_secs(10);
+/// Pause between the attempts of `resolve_strict`
+const STRICT_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How long `ReverseResolver::lookup` waits for a PTR record
+const REVERSE_LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[derive(Default, Debug, PartialEq, Clone)]
 pub struct DomainRecords {
     pub domain: String,
     pub records: Vec<Entry>,
 }
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug)]
 pub struct ResolvedAddresses {
-    /// Resolved IPv4 addresses per domain with TTL information
+    /// Resolved IPv4 and IPv6 addresses per domain with TTL information
     pub domains: Vec<DomainRecords>,
     /// IPv4 addresses of DNS servers used for resolution
     pub dns_v4: Vec<Ipv4Addr>,
+    /// IPv6 addresses of DNS servers used for resolution
+    pub dns_v6: Vec<Ipv6Addr>,
+    /// Domains that did not resolve, with the error of their last lookup
+    pub failed: Vec<(String, ResolveError)>,
+}
+
+impl ResolvedAddresses {
+    /// Fail with the first domain that did not resolve
+    ///
+    /// At startup every domain must resolve; refreshes keep the previous
+    /// records of the domains that failed instead.
+    pub fn ensure_resolved(&self) -> Result<(), MoriError> {
+        match self.failed.first() {
+            Some((domain, source)) => Err(MoriError::DnsLookup {
+                domain: domain.clone(),
+                source: source.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Addresses of every DNS server, IPv4 first
+    pub fn dns_servers(&self) -> Vec<IpAddr> {
+        self.dns_v4
+            .iter()
+            .copied()
+            .map(IpAddr::V4)
+            .chain(self.dns_v6.iter().copied().map(IpAddr::V6))
+            .collect()
+    }
 }
 
 /// DNS resolver abstraction for testing
@@ -33,8 +96,110 @@ pub trait DnsResolver: Send + Sync + 'static {
     async fn resolve_domains(&self, domains: &[String]) -> Result<ResolvedAddresses, MoriError>;
 }
 
+/// Resolve `domains`, retrying the ones that fail or have no addresses
+/// until `deadline` has passed
+///
+/// Unlike `resolve_domains`, a domain without any address is an error: the
+/// first domain still unresolved at the deadline fails the whole lookup.
+pub async fn resolve_strict<R: DnsResolver + ?Sized>(
+    resolver: &R,
+    domains: &[String],
+    deadline: Duration,
+) -> Result<ResolvedAddresses, MoriError> {
+    let give_up_at = Instant::now() + deadline;
+    let mut result = ResolvedAddresses::default();
+    let mut pending = domains.to_vec();
+    loop {
+        let attempt = resolver.resolve_domains(&pending).await?;
+        result.dns_v4 = attempt.dns_v4;
+        result.dns_v6 = attempt.dns_v6;
+        let mut failed = attempt.failed;
+        for domain in attempt.domains {
+            if domain.records.is_empty() {
+                failed.push((domain.domain, ResolveError::from("no addresses found")));
+            } else {
+                result.domains.push(domain);
+            }
+        }
+
+        let now = Instant::now();
+        if failed.is_empty() || now >= give_up_at {
+            result.failed = failed;
+            result.ensure_resolved()?;
+            return Ok(result);
+        }
+        for (domain, err) in &failed {
+            log::warn!("Failed to resolve {}: {}; retrying", domain, err);
+        }
+        pending = failed.into_iter().map(|(domain, _)| domain).collect();
+        tokio::time::sleep(STRICT_RETRY_DELAY.min(give_up_at - now)).await;
+    }
+}
+
 /// Production DNS resolver using the system resolver
-pub struct SystemDnsResolver;
+///
+/// Queries go to `nameservers` instead of the ones in the system
+/// configuration when it is not empty.
+#[derive(Debug, Default, Clone)]
+pub struct SystemDnsResolver {
+    nameservers: Vec<IpAddr>,
+}
+
+impl SystemDnsResolver {
+    /// Resolver querying the given nameservers, or the system ones if empty
+    pub fn with_nameservers(nameservers: Vec<IpAddr>) -> Self {
+        Self { nameservers }
+    }
+
+    /// The system resolver configuration, with `nameservers` in place of the
+    /// system ones if configured
+    fn config(&self) -> Result<(ResolverConfig, ResolverOpts), MoriError> {
+        if self.nameservers.is_empty() {
+            return system_conf::read_system_conf()
+                .map_err(|source| MoriError::DnsResolverInit { source });
+        }
+        // Configured nameservers only take the search list and options
+        // from the system, which may well have no resolv.conf
+        let (system, opts) = system_conf::read_system_conf().unwrap_or_default();
+        let config = ResolverConfig::from_parts(
+            system.domain().cloned(),
+            system.search().to_vec(),
+            NameServerConfigGroup::from_ips_clear(&self.nameservers, 53, true),
+        );
+        Ok((config, opts))
+    }
+
+    /// Resolver for the PTR names of addresses, using the same nameservers
+    pub fn reverse(&self) -> Result<ReverseResolver, MoriError> {
+        let (config, opts) = self.config()?;
+        let mut builder = Resolver::builder_with_config(config, TokioConnectionProvider::default());
+        *builder.options_mut() = opts;
+        Ok(ReverseResolver {
+            resolver: builder.build(),
+        })
+    }
+}
+
+/// Looks up the names of addresses in their PTR records
+#[derive(Clone)]
+pub struct ReverseResolver {
+    resolver: TokioResolver,
+}
+
+impl ReverseResolver {
+    /// The first PTR name of `ip`, without the trailing dot
+    ///
+    /// None if the lookup fails, finds no name, or takes longer than
+    /// REVERSE_LOOKUP_TIMEOUT.
+    pub async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let lookup = tokio::time::timeout(REVERSE_LOOKUP_TIMEOUT, self.resolver.reverse_lookup(ip))
+            .await
+            .ok()?
+            .ok()?;
+        let name = lookup.iter().next()?.0.to_string();
+        Some(name.trim_end_matches('.').to_string())
+    }
+}
 
 #[async_trait]
 impl DnsResolver for SystemDnsResolver {
@@ -48,108 +213,509 @@ impl DnsResolver for SystemDnsResolver {
     /// * `domains` - List of domain names to resolve
     ///
     /// # Returns
-    /// * `Ok(ResolvedAddresses)` - Contains resolved IPv4 addresses from domains and DNS server IPs
-    /// * `Err(MoriError)` - If DNS resolver initialization or lookup fails
+    /// * `Ok(ResolvedAddresses)` - Contains resolved IPv4 addresses from domains and DNS server
+    ///   IPs; domains that failed to resolve are listed in `failed`
+    /// * `Err(MoriError)` - If DNS resolver initialization fails
     ///
     /// # Examples
     /// ```no_run
     /// use mori::net::{SystemDnsResolver, DnsResolver};
     ///
     /// # async fn example() {
-    /// let resolver = SystemDnsResolver;
+    /// let resolver = SystemDnsResolver::default();
     /// let domains = vec!["example.com".to_string()];
     /// let resolved = resolver.resolve_domains(&domains).await.unwrap();
+    /// resolved.ensure_resolved().unwrap();
     /// # }
     /// ```
     async fn resolve_domains(&self, domains: &[String]) -> Result<ResolvedAddresses, MoriError> {
         // Always read system DNS configuration to get nameserver IPs
         // DNS servers must be allowed even when no domains are specified
-        let config = system_conf::read_system_conf()
-            .map_err(|source| MoriError::DnsResolverInit { source })?
-            .0;
-        let nameservers = collect_nameserver_ips(&config);
+        let (config, opts) = self.config()?;
+        let search = search_domains(&config);
+        let (mut nameservers, dns_v6) = collect_nameserver_ips(&config);
+        if self.nameservers.is_empty()
+            && nameservers
+                .iter()
+                .any(|ip| RESOLVED_STUB_ADDRS.contains(ip))
+        {
+            // Behind the systemd-resolved stub, the child may also query the
+            // upstream servers directly (or over DNS-over-TLS through resolved)
+            let uplinks = fs::read_to_string(RESOLVED_UPLINK_CONF).unwrap_or_default();
+            let upstreams = parse_nameservers(&uplinks);
+            log::info!(
+                "systemd-resolved stub detected; upstream servers: {:?}",
+                upstreams
+            );
+            merge_nameservers(&mut nameservers, upstreams);
+        }
 
         if domains.is_empty() {
             return Ok(ResolvedAddresses {
-                domains: Vec::new(),
                 dns_v4: nameservers,
+                dns_v6,
+                ..ResolvedAddresses::default()
             });
         }
 
-        let resolver = Resolver::builder_tokio().unwrap().build();
-
-        let mut domain_records = Vec::with_capacity(domains.len());
+        // AAAA records are needed too, since the command may connect over IPv6
+        let mut builder = Resolver::builder_with_config(config, TokioConnectionProvider::default());
+        *builder.options_mut() = opts.clone();
+        builder.options_mut().ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+        let resolver = builder.build();
 
-        for domain in domains {
-            let response = resolver
-                .lookup_ip(domain.as_str())
-                .await
-                .map_err(|source| MoriError::DnsLookup {
-                    domain: domain.clone(),
-                    source,
-                })?;
-
-            let valid_until = response.valid_until();
-            let mut records = Vec::new();
+        // Domains are looked up concurrently, a few at a time, so long allow
+        // lists do not hold up the start of the command
+        let search = Arc::new(search);
+        let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_LOOKUPS));
+        let mut lookups = JoinSet::new();
+        for (index, domain) in domains.iter().enumerate() {
+            let resolver = resolver.clone();
+            let search = Arc::clone(&search);
+            let permits = Arc::clone(&permits);
+            let domain = domain.clone();
+            let ndots = opts.ndots;
+            lookups.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let result = resolve_domain(&resolver, &domain, &search, ndots).await;
+                (index, result)
+            });
+        }
+        let mut results = Vec::with_capacity(domains.len());
+        while let Some(joined) = lookups.join_next().await {
+            results.push(joined.expect("DNS lookup task panicked"));
+        }
+        // Keep the order of `domains`
+        results.sort_unstable_by_key(|(index, _)| *index);
 
-            for ip in response.iter() {
-                if let IpAddr::V4(v4) = ip {
-                    records.push(Entry {
-                        ip: v4,
-                        expires_at: valid_until,
-                    });
+        let mut resolved = ResolvedAddresses {
+            dns_v4: nameservers,
+            dns_v6,
+            ..ResolvedAddresses::default()
+        };
+        for (index, result) in results {
+            let domain = domains[index].clone();
+            match result {
+                Ok(records) if records.is_empty() => {}
+                Ok(records) => resolved.domains.push(DomainRecords { domain, records }),
+                Err(err) => {
+                    log::warn!("Failed to resolve {}: {}", domain, err);
+                    resolved.failed.push((domain, err));
                 }
             }
+        }
+        Ok(resolved)
+    }
+}
 
-            if !records.is_empty() {
-                domain_records.push(DomainRecords {
-                    domain: domain.clone(),
-                    records,
-                });
+/// Addresses of `domain`, expanding short names the way the child's libc
+/// resolver does, so `registry` allows what `curl registry` will actually
+/// connect to
+async fn resolve_domain(
+    resolver: &TokioResolver,
+    domain: &str,
+    search: &[String],
+    ndots: usize,
+) -> Result<Vec<Entry>, ResolveError> {
+    let mut error = None;
+    for candidate in search_candidates(domain, search, ndots) {
+        let lookup = if is_service_name(domain) {
+            resolve_service(resolver, &candidate).await
+        } else {
+            resolve_host(resolver, &candidate).await
+        };
+        match lookup {
+            Ok(records) => {
+                log::debug!("Resolved {} as {}", domain, candidate);
+                return Ok(records);
             }
+            Err(err) => error = Some(err),
         }
+    }
+    Err(error.expect("search_candidates is never empty"))
+}
 
-        Ok(ResolvedAddresses {
-            domains: domain_records,
-            dns_v4: nameservers,
-        })
+/// Whether `domain` names a service (`_imap._tcp.example.com`) rather than a host
+fn is_service_name(domain: &str) -> bool {
+    domain.starts_with('_')
+}
+
+/// Addresses of a host: its A and AAAA records and the endpoints its HTTPS records advertise
+async fn resolve_host(resolver: &TokioResolver, name: &str) -> Result<Vec<Entry>, ResolveError> {
+    let response = resolver.lookup_ip(name).await?;
+    let mut records = ip_entries(response.iter(), response.valid_until());
+    records.extend(service_binding_records(resolver, name, RecordType::HTTPS).await);
+    Ok(records)
+}
+
+/// Addresses of a service: the targets of its SRV records and the
+/// endpoints its SVCB records advertise
+async fn resolve_service(resolver: &TokioResolver, name: &str) -> Result<Vec<Entry>, ResolveError> {
+    let mut records = service_binding_records(resolver, name, RecordType::SVCB).await;
+    let srv = match resolver.srv_lookup(name).await {
+        Ok(srv) => srv,
+        Err(_) if !records.is_empty() => return Ok(records),
+        Err(err) => return Err(err),
+    };
+    let valid_until = srv.as_lookup().valid_until();
+    for target in srv.iter().map(|srv| srv.target()) {
+        // A target of "." means the service is not available
+        if target.is_root() {
+            continue;
+        }
+        records.extend(resolve_target(resolver, target, valid_until).await);
     }
+    Ok(records)
 }
 
-/// Extract IPv4 addresses of DNS nameservers from resolver configuration
+/// Endpoints advertised by the HTTPS or SVCB records of `name`
+///
+/// Lookup failures are ignored, since most names have no such records.
+async fn service_binding_records(
+    resolver: &TokioResolver,
+    name: &str,
+    record_type: RecordType,
+) -> Vec<Entry> {
+    let lookup = match resolver.lookup(name, record_type).await {
+        Ok(lookup) => lookup,
+        Err(err) => {
+            log::debug!("No {} records for {}: {}", record_type, name, err);
+            return Vec::new();
+        }
+    };
+    let owner = lookup.query().name().clone();
+    let valid_until = lookup.valid_until();
+
+    let mut records = Vec::new();
+    for rdata in lookup.iter() {
+        let svcb = match rdata {
+            RData::HTTPS(HTTPS(svcb)) | RData::SVCB(svcb) => svcb,
+            _ => continue,
+        };
+        let (hints, target) = svcb_endpoints(&owner, svcb);
+        records.extend(hints.into_iter().map(|ip| Entry {
+            ip,
+            expires_at: valid_until,
+        }));
+        if let Some(target) = target {
+            records.extend(resolve_target(resolver, &target, valid_until).await);
+        }
+    }
+    records
+}
+
+/// Address hints of an SVCB record, and the name it points to if that is not `owner`
+fn svcb_endpoints(owner: &Name, svcb: &SVCB) -> (Vec<IpAddr>, Option<Name>) {
+    let mut hints = Vec::new();
+    for (_, value) in svcb.svc_params() {
+        match value {
+            SvcParamValue::Ipv4Hint(IpHint(addrs)) => {
+                hints.extend(addrs.iter().map(|addr| IpAddr::V4(addr.0)))
+            }
+            SvcParamValue::Ipv6Hint(IpHint(addrs)) => {
+                hints.extend(addrs.iter().map(|addr| IpAddr::V6(addr.0)))
+            }
+            _ => {}
+        }
+    }
+    // In service mode, a target of "." stands for the owner name itself
+    let target = svcb.target_name();
+    let target = (!target.is_root() && target != owner).then(|| target.clone());
+    (hints, target)
+}
+
+/// Addresses of a name an SRV or SVCB record points to
+///
+/// The entries expire with the record pointing to `target` if that comes
+/// first, so the pointer is looked up again too. Failures are logged and
+/// yield no addresses.
+async fn resolve_target(
+    resolver: &TokioResolver,
+    target: &Name,
+    pointer_valid_until: Instant,
+) -> Vec<Entry> {
+    match resolver.lookup_ip(target.clone()).await {
+        Ok(response) => ip_entries(
+            response.iter(),
+            response.valid_until().min(pointer_valid_until),
+        ),
+        Err(err) => {
+            log::warn!("Failed to resolve service target {}: {}", target, err);
+            Vec::new()
+        }
+    }
+}
+
+fn ip_entries(ips: impl Iterator<Item = IpAddr>, expires_at: Instant) -> Vec<Entry> {
+    ips.map(|ip| Entry { ip, expires_at }).collect()
+}
+
+/// Search list of the system configuration (`search`, or else `domain`)
+fn search_domains(config: &ResolverConfig) -> Vec<String> {
+    let names = if config.search().is_empty() {
+        config.domain().into_iter().collect()
+    } else {
+        config.search().iter().collect::<Vec<_>>()
+    };
+    names
+        .into_iter()
+        .map(|name| name.to_utf8().trim_end_matches('.').to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Fully qualified names to try for `name`, in resolv.conf(5) order
+///
+/// A name ending with a dot is used as is. A name with at least `ndots` dots is
+/// tried as an absolute name before the search domains; a shorter one is tried
+/// with each search domain first and as an absolute name last.
+fn search_candidates(name: &str, search: &[String], ndots: usize) -> Vec<String> {
+    if name.ends_with('.') {
+        return vec![name.to_string()];
+    }
+    let absolute = format!("{}.", name);
+    let mut candidates: Vec<String> = search
+        .iter()
+        .map(|domain| format!("{}.{}.", name, domain))
+        .collect();
+    if name.matches('.').count() >= ndots {
+        candidates.insert(0, absolute);
+    } else {
+        candidates.push(absolute);
+    }
+    candidates
+}
+
+/// Extract IPv4 and IPv6 addresses of DNS nameservers from resolver configuration
 ///
 /// This is necessary because the controlled process needs to be able to
 /// connect to DNS servers to perform name resolution.
-fn collect_nameserver_ips(config: &ResolverConfig) -> Vec<Ipv4Addr> {
+fn collect_nameserver_ips(config: &ResolverConfig) -> (Vec<Ipv4Addr>, Vec<Ipv6Addr>) {
     let mut v4_set: HashSet<Ipv4Addr> = HashSet::new();
+    let mut v6_set: HashSet<Ipv6Addr> = HashSet::new();
 
     for ns in config.name_servers() {
-        if let IpAddr::V4(ip) = ns.socket_addr.ip() {
-            v4_set.insert(ip);
+        match ns.socket_addr.ip() {
+            IpAddr::V4(ip) => {
+                v4_set.insert(ip);
+            }
+            IpAddr::V6(ip) => {
+                v6_set.insert(ip);
+            }
         }
     }
 
-    v4_set.into_iter().collect()
+    (v4_set.into_iter().collect(), v6_set.into_iter().collect())
+}
+
+/// IPv4 `nameserver` entries of a resolv.conf(5) file
+fn parse_nameservers(content: &str) -> Vec<Ipv4Addr> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some("nameserver"), Some(addr)) => addr.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Add the addresses of `extra` missing from `nameservers`
+fn merge_nameservers(nameservers: &mut Vec<Ipv4Addr>, extra: Vec<Ipv4Addr>) {
+    for ip in extra {
+        if !nameservers.contains(&ip) {
+            nameservers.push(ip);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Instant;
+    use hickory_resolver::proto::rr::rdata::{
+        A, AAAA,
+        svcb::{Alpn, SvcParamKey},
+    };
+    use rstest::rstest;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_svcb_endpoints_reads_hints_and_alias_target() {
+        let owner = Name::from_str("example.com.").unwrap();
+        let service = SVCB::new(
+            1,
+            Name::root(),
+            vec![
+                (
+                    SvcParamKey::Alpn,
+                    SvcParamValue::Alpn(Alpn(vec!["h2".to_string()])),
+                ),
+                (SvcParamKey::Port, SvcParamValue::Port(8443)),
+                (
+                    SvcParamKey::Ipv4Hint,
+                    SvcParamValue::Ipv4Hint(IpHint(vec![
+                        A(Ipv4Addr::new(192, 0, 2, 1)),
+                        A(Ipv4Addr::new(192, 0, 2, 2)),
+                    ])),
+                ),
+                (
+                    SvcParamKey::Ipv6Hint,
+                    SvcParamValue::Ipv6Hint(IpHint(vec![AAAA("2001:db8::1".parse().unwrap())])),
+                ),
+            ],
+        );
+        assert_eq!(
+            svcb_endpoints(&owner, &service),
+            (
+                vec![
+                    IpAddr::from([192, 0, 2, 1]),
+                    IpAddr::from([192, 0, 2, 2]),
+                    "2001:db8::1".parse().unwrap(),
+                ],
+                None
+            )
+        );
+
+        let alias = SVCB::new(0, Name::from_str("cdn.example.net.").unwrap(), vec![]);
+        assert_eq!(
+            svcb_endpoints(&owner, &alias),
+            (vec![], Some(Name::from_str("cdn.example.net.").unwrap()))
+        );
+    }
+
+    #[rstest]
+    #[case::srv("_imaps._tcp.example.com", true)]
+    #[case::svcb("_8443._foo.api.example.com", true)]
+    #[case::host("mail.example.com", false)]
+    fn test_is_service_name(#[case] domain: &str, #[case] expected: bool) {
+        assert_eq!(is_service_name(domain), expected);
+    }
+
+    #[test]
+    fn test_parse_nameservers_reads_ipv4_entries() {
+        let uplinks = "# This is /run/systemd/resolve/resolv.conf managed by man:systemd-resolved(8).\n\
+                       nameserver 192.168.1.1\n\
+                       nameserver 2001:db8::1\n\
+                       nameserver 1.1.1.1\n\
+                       search corp.example\n";
+        let mut nameservers = vec![Ipv4Addr::new(127, 0, 0, 53), Ipv4Addr::new(1, 1, 1, 1)];
+        merge_nameservers(&mut nameservers, parse_nameservers(uplinks));
+        assert_eq!(
+            nameservers,
+            vec![
+                Ipv4Addr::new(127, 0, 0, 53),
+                Ipv4Addr::new(1, 1, 1, 1),
+                Ipv4Addr::new(192, 168, 1, 1),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case::short_name_searches_first("registry", 1, &["registry.corp.example.", "registry.example.", "registry."])]
+    #[case::dotted_name_absolute_first("api.github.com", 1, &["api.github.com.", "api.github.com.corp.example.", "api.github.com.example."])]
+    #[case::ndots_raised("api.github.com", 5, &["api.github.com.corp.example.", "api.github.com.example.", "api.github.com."])]
+    #[case::trailing_dot_is_absolute("registry.", 1, &["registry."])]
+    fn test_search_candidates(#[case] name: &str, #[case] ndots: usize, #[case] expected: &[&str]) {
+        let search = vec!["corp.example".to_string(), "example".to_string()];
+        assert_eq!(search_candidates(name, &search, ndots), expected);
+    }
+
+    #[test]
+    fn test_search_candidates_without_search_list() {
+        assert_eq!(search_candidates("registry", &[], 1), vec!["registry."]);
+    }
+
+    #[test]
+    fn test_ensure_resolved_reports_first_failure() {
+        let mut resolved = ResolvedAddresses::default();
+        assert!(resolved.ensure_resolved().is_ok());
+        resolved.failed.push((
+            "missing.invalid".to_string(),
+            ResolveError::from("no records"),
+        ));
+        assert!(matches!(
+            resolved.ensure_resolved(),
+            Err(MoriError::DnsLookup { domain, .. }) if domain == "missing.invalid"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_strict_retries_until_resolved() {
+        let mut resolver = MockDnsResolver::new();
+        let mut attempts = 0;
+        resolver
+            .expect_resolve_domains()
+            .times(2)
+            .returning(move |domains| {
+                attempts += 1;
+                let mut resolved = ResolvedAddresses::default();
+                for domain in domains {
+                    if attempts == 1 && domain == "flaky.example" {
+                        resolved
+                            .failed
+                            .push((domain.clone(), ResolveError::from("timed out")));
+                        continue;
+                    }
+                    resolved.domains.push(DomainRecords {
+                        domain: domain.clone(),
+                        records: vec![Entry {
+                            ip: IpAddr::from([192, 0, 2, 1]),
+                            expires_at: Instant::now() + Duration::from_secs(60),
+                        }],
+                    });
+                }
+                Ok(resolved)
+            });
+
+        let domains = vec!["example.com".to_string(), "flaky.example".to_string()];
+        let resolved = resolve_strict(&resolver, &domains, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(resolved.domains.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_strict_fails_on_domain_without_addresses() {
+        let mut resolver = MockDnsResolver::new();
+        resolver.expect_resolve_domains().returning(|domains| {
+            Ok(ResolvedAddresses {
+                domains: domains
+                    .iter()
+                    .map(|domain| DomainRecords {
+                        domain: domain.clone(),
+                        records: Vec::new(),
+                    })
+                    .collect(),
+                ..ResolvedAddresses::default()
+            })
+        });
+
+        let domains = vec!["_svc._tcp.example.com".to_string()];
+        let result = resolve_strict(&resolver, &domains, Duration::from_millis(10)).await;
+        assert!(matches!(
+            result,
+            Err(MoriError::DnsLookup { domain, .. }) if domain == "_svc._tcp.example.com"
+        ));
+    }
 
     #[tokio::test]
     async fn test_resolve_domain_success() {
         let domains = vec!["localhost".to_string()];
-        let resolver = SystemDnsResolver;
+        let resolver = SystemDnsResolver::default();
         let resolved = resolver.resolve_domains(&domains).await.unwrap();
         let record = resolved
             .domains
             .iter()
             .find(|entry| entry.domain == "localhost")
             .expect("localhost record present");
-        assert_eq!(record.records.len(), 1);
-        let entry = &record.records[0];
-        assert_eq!(entry.ip, "127.0.0.1".parse::<Ipv4Addr>().unwrap());
+        let entry = record
+            .records
+            .iter()
+            .find(|entry| entry.ip.is_ipv4())
+            .expect("IPv4 record present");
+        assert_eq!(entry.ip, "127.0.0.1".parse::<IpAddr>().unwrap());
         assert!(entry.expires_at > Instant::now());
     }
 }
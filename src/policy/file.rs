@@ -1,18 +1,189 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
 
 /// Access mode for file operations
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum AccessMode {
     Read = 1,
     Write = 2,
     ReadWrite = 3,
+    /// Executing the file, or mapping it into memory as executable code
+    Execute = 4,
+}
+
+/// Device nodes the command may not open, whatever path they are reached by
+///
+/// Written as `"block"`, `"char"` or the absolute path of a device node; a
+/// trailing `*` covers every node in the directory whose name starts with
+/// the part before it (`/dev/sd*`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DeniedDevice {
+    /// Every block device
+    Block,
+    /// Every character device
+    Char,
+    /// The device behind this node, or behind the nodes the pattern matches
+    Node(PathBuf),
+}
+
+impl DeniedDevice {
+    /// Directory and name prefix of a `/dev/sd*` pattern; None for a single node
+    pub fn node_prefix(&self) -> Option<(&Path, &str)> {
+        let DeniedDevice::Node(path) = self else {
+            return None;
+        };
+        let name = path.file_name()?.to_str()?.strip_suffix('*')?;
+        Some((path.parent()?, name))
+    }
+}
+
+impl TryFrom<String> for DeniedDevice {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "block" => Ok(DeniedDevice::Block),
+            "char" => Ok(DeniedDevice::Char),
+            path if Path::new(path).is_absolute() => Ok(DeniedDevice::Node(PathBuf::from(path))),
+            _ => Err(format!(
+                "invalid device {:?}: expected \"block\", \"char\" or an absolute path",
+                value
+            )),
+        }
+    }
+}
+
+impl From<DeniedDevice> for String {
+    fn from(device: DeniedDevice) -> Self {
+        device.to_string()
+    }
+}
+
+impl std::fmt::Display for DeniedDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeniedDevice::Block => f.write_str("block"),
+            DeniedDevice::Char => f.write_str("char"),
+            DeniedDevice::Node(path) => write!(f, "{}", path.display()),
+        }
+    }
 }
 
-/// File access policy (deny-list mode: all paths allowed except those in the deny list)
-#[derive(Debug, Clone, PartialEq, Default)]
+/// Paths every command needs, with the access they get in allow-list mode:
+/// programs, shared libraries, system configuration and device nodes
+#[cfg(not(target_os = "macos"))]
+pub const SYSTEM_PATHS: &[(&str, AccessMode)] = &[
+    ("/usr", AccessMode::Read),
+    ("/bin", AccessMode::Read),
+    ("/sbin", AccessMode::Read),
+    ("/lib", AccessMode::Read),
+    ("/lib32", AccessMode::Read),
+    ("/lib64", AccessMode::Read),
+    ("/etc", AccessMode::Read),
+    ("/proc", AccessMode::Read),
+    ("/sys", AccessMode::Read),
+    ("/dev/null", AccessMode::ReadWrite),
+    ("/dev/zero", AccessMode::Read),
+    ("/dev/full", AccessMode::ReadWrite),
+    ("/dev/random", AccessMode::Read),
+    ("/dev/urandom", AccessMode::Read),
+    ("/dev/tty", AccessMode::ReadWrite),
+    ("/dev/ptmx", AccessMode::ReadWrite),
+    ("/dev/pts", AccessMode::ReadWrite),
+    ("/dev/shm", AccessMode::ReadWrite),
+];
+
+/// Paths every command needs, with the access they get in allow-list mode:
+/// programs, shared libraries, system configuration and device nodes
+#[cfg(target_os = "macos")]
+pub const SYSTEM_PATHS: &[(&str, AccessMode)] = &[
+    ("/System", AccessMode::Read),
+    ("/Library", AccessMode::Read),
+    ("/usr", AccessMode::Read),
+    ("/bin", AccessMode::Read),
+    ("/sbin", AccessMode::Read),
+    ("/etc", AccessMode::Read),
+    ("/var/db", AccessMode::Read),
+    ("/dev/null", AccessMode::ReadWrite),
+    ("/dev/zero", AccessMode::Read),
+    ("/dev/random", AccessMode::Read),
+    ("/dev/urandom", AccessMode::Read),
+    ("/dev/tty", AccessMode::ReadWrite),
+    ("/dev/ptmx", AccessMode::ReadWrite),
+    ("/dev/dtracehelper", AccessMode::ReadWrite),
+];
+
+/// File access policy
+///
+/// All paths are allowed except those in the deny list. When `allowed_paths`
+/// is non-empty the policy switches to allow-list mode: only the listed paths,
+/// everything below them and [`SYSTEM_PATHS`] may be opened, minus anything
+/// in `denied_paths`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FilePolicy {
     /// List of denied file paths with their access modes
+    #[serde(with = "denied_path_list")]
     pub denied_paths: Vec<(PathBuf, AccessMode)>,
+    /// Paths that may be read and written, with everything below them;
+    /// empty means every path is allowed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_paths: Vec<PathBuf>,
+    /// Make paths denied for reading look missing: opening or stat'ing them
+    /// fails with ENOENT instead of EPERM (Linux)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub hide_denied: bool,
+    /// Device nodes that may not be opened, matched by device type and number
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub denied_devices: Vec<DeniedDevice>,
+    /// Deny opening the /proc/<pid> entries of processes outside the
+    /// sandbox (Linux)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub private_proc: bool,
+    /// Also deny and allow the paths the entries' symlinks point to, as
+    /// resolved when the sandbox starts (Linux; macOS always does)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub resolve_symlinks: bool,
+}
+
+/// Denied paths as `{"path": ..., "access": ...}` objects
+mod denied_path_list {
+    use std::path::PathBuf;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::AccessMode;
+
+    #[derive(Serialize, Deserialize)]
+    struct DeniedPath {
+        path: PathBuf,
+        access: AccessMode,
+    }
+
+    pub fn serialize<S: Serializer>(
+        paths: &[(PathBuf, AccessMode)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(paths.iter().map(|(path, access)| DeniedPath {
+            path: path.clone(),
+            access: *access,
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(PathBuf, AccessMode)>, D::Error> {
+        Ok(Vec::<DeniedPath>::deserialize(deserializer)?
+            .into_iter()
+            .map(|denied| (denied.path, denied.access))
+            .collect())
+    }
 }
 
 impl FilePolicy {
@@ -20,9 +191,61 @@ impl FilePolicy {
     pub fn new() -> Self {
         Self {
             denied_paths: Vec::new(),
+            allowed_paths: Vec::new(),
+            hide_denied: false,
+            denied_devices: Vec::new(),
+            private_proc: false,
+            resolve_symlinks: false,
         }
     }
 
+    /// Allow reading and writing the given path and switch to allow-list mode
+    pub fn allow<P: AsRef<Path>>(&mut self, path: P) {
+        let path = self.normalize_path(path.as_ref());
+        if !self.allowed_paths.contains(&path) {
+            self.allowed_paths.push(path);
+        }
+    }
+
+    /// Check if no file rule is configured
+    pub fn is_empty(&self) -> bool {
+        self.denied_paths.is_empty()
+            && self.allowed_paths.is_empty()
+            && self.denied_devices.is_empty()
+            && !self.private_proc
+    }
+
+    /// Paths that may be opened in allow-list mode with their access modes:
+    /// the allowed paths followed by [`SYSTEM_PATHS`]; empty outside of it
+    pub fn allowed_entries(&self) -> Vec<(PathBuf, AccessMode)> {
+        if self.allowed_paths.is_empty() {
+            return Vec::new();
+        }
+        self.allowed_paths
+            .iter()
+            .map(|path| (path.clone(), AccessMode::ReadWrite))
+            .chain(
+                SYSTEM_PATHS
+                    .iter()
+                    .map(|&(path, mode)| (PathBuf::from(path), mode)),
+            )
+            .collect()
+    }
+
+    /// Check if opening `path` (absolute, normalized) with `access` is
+    /// allowed by the allow list, if there is one
+    pub fn allows(&self, path: &Path, access: AccessMode) -> bool {
+        if self.allowed_paths.is_empty() {
+            return true;
+        }
+        let allowed = self
+            .allowed_entries()
+            .iter()
+            .filter(|(allowed, _)| path.starts_with(allowed))
+            .fold(0, |mode, (_, allowed_mode)| mode | *allowed_mode as u8);
+        (access as u8) & !allowed == 0
+    }
+
     /// Add a path to deny read access
     pub fn deny_read<P: AsRef<Path>>(&mut self, path: P) {
         let path = self.normalize_path(path.as_ref());
@@ -41,39 +264,119 @@ impl FilePolicy {
         self.denied_paths.push((path, AccessMode::ReadWrite));
     }
 
+    /// Add a path to deny executing, including mapping it as executable code
+    pub fn deny_execute<P: AsRef<Path>>(&mut self, path: P) {
+        let path = self.normalize_path(path.as_ref());
+        self.denied_paths.push((path, AccessMode::Execute));
+    }
+
+    /// Merge another policy into this one
+    pub fn merge(&mut self, other: Self) {
+        self.denied_paths.extend(other.denied_paths);
+        self.hide_denied |= other.hide_denied;
+        self.private_proc |= other.private_proc;
+        self.resolve_symlinks |= other.resolve_symlinks;
+        for device in other.denied_devices {
+            if !self.denied_devices.contains(&device) {
+                self.denied_devices.push(device);
+            }
+        }
+        for path in other.allowed_paths {
+            if !self.allowed_paths.contains(&path) {
+                self.allowed_paths.push(path);
+            }
+        }
+    }
+
+    /// This policy with the resolved spelling of every denied and allowed
+    /// path reached through a symlink added as an entry of its own, when
+    /// `resolve_symlinks` is set
+    ///
+    /// The kernel reports the target of a symlink, so `/var/run` (a link to
+    /// `/run`) is only matched by path once `/run` is listed. Symlinks are
+    /// resolved now; a link retargeted later is not followed.
+    pub fn with_symlink_targets(&self) -> Self {
+        let mut resolved = self.clone();
+        if !self.resolve_symlinks {
+            return resolved;
+        }
+        for (path, mode) in &self.denied_paths {
+            let target = canonicalize_lenient(path);
+            if !resolved.denied_paths.contains(&(target.clone(), *mode)) {
+                resolved.denied_paths.push((target, *mode));
+            }
+        }
+        for path in &self.allowed_paths {
+            let target = canonicalize_lenient(path);
+            if !resolved.allowed_paths.contains(&target) {
+                resolved.allowed_paths.push(target);
+            }
+        }
+        resolved
+    }
+
     /// Normalize a path to absolute form, resolving `.` and `..` components
     fn normalize_path(&self, path: &Path) -> PathBuf {
-        // Convert to absolute path first
-        let absolute = std::path::absolute(path).unwrap_or_else(|_| {
-            if path.is_absolute() {
-                path.to_path_buf()
-            } else {
-                std::env::current_dir()
-                    .unwrap_or_else(|_| PathBuf::from("/"))
-                    .join(path)
-            }
-        });
-
-        // Manually resolve . and .. components since std::path::absolute doesn't do this
-        let mut normalized = PathBuf::new();
-        for component in absolute.components() {
-            match component {
-                std::path::Component::CurDir => {
-                    // Skip "." components
-                }
-                std::path::Component::ParentDir => {
-                    // ".." - pop the last component
-                    normalized.pop();
-                }
-                comp => {
-                    // Normal component (RootDir, Prefix, Normal)
-                    normalized.push(comp);
-                }
+        normalize_path(path)
+    }
+}
+
+/// Canonicalize a path that may not exist yet
+///
+/// Resolves symlinks in the longest existing ancestor and appends the remaining components,
+/// so deny rules for files that will be created later are still canonical.
+pub(crate) fn canonicalize_lenient(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut remainder = Vec::new();
+    loop {
+        if let Ok(canonical) = fs::canonicalize(existing) {
+            return remainder
+                .iter()
+                .rev()
+                .fold(canonical, |acc: PathBuf, component| acc.join(component));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                remainder.push(name.to_os_string());
+                existing = parent;
             }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Normalize a path to absolute form, resolving `.` and `..` components
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    // Convert to absolute path first
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir()
+                .unwrap_or_else(|_| PathBuf::from("/"))
+                .join(path)
         }
+    });
 
-        normalized
+    // Manually resolve . and .. components since std::path::absolute doesn't do this
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            std::path::Component::CurDir => {
+                // Skip "." components
+            }
+            std::path::Component::ParentDir => {
+                // ".." - pop the last component
+                normalized.pop();
+            }
+            comp => {
+                // Normal component (RootDir, Prefix, Normal)
+                normalized.push(comp);
+            }
+        }
     }
+
+    normalized
 }
 
 #[cfg(test)]
@@ -81,6 +384,8 @@ mod tests {
     use super::*;
     use rstest::rstest;
     use std::env;
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink;
 
     #[rstest]
     #[case("/tmp/test.txt", "/tmp/test.txt")]
@@ -124,6 +429,104 @@ mod tests {
         assert_eq!(normalized, expected);
     }
 
+    #[test]
+    fn allows_follows_allow_list_and_system_paths() {
+        let mut policy = FilePolicy::new();
+        assert!(policy.allows(Path::new("/home/user/.ssh/id_ed25519"), AccessMode::Read));
+
+        policy.allow("/work/project");
+        assert!(policy.allows(
+            Path::new("/work/project/src/main.rs"),
+            AccessMode::ReadWrite
+        ));
+        assert!(policy.allows(Path::new("/usr/lib/libc.so.6"), AccessMode::Read));
+        assert!(!policy.allows(Path::new("/usr/lib/libc.so.6"), AccessMode::Write));
+        assert!(policy.allows(Path::new("/dev/null"), AccessMode::Write));
+        assert!(!policy.allows(Path::new("/work/project2"), AccessMode::Read));
+        assert!(!policy.allows(Path::new("/home/user/.ssh/id_ed25519"), AccessMode::Read));
+    }
+
+    #[rstest]
+    #[case("block", DeniedDevice::Block, None)]
+    #[case("char", DeniedDevice::Char, None)]
+    #[case("/dev/kmsg", DeniedDevice::Node(PathBuf::from("/dev/kmsg")), None)]
+    #[case("/dev/sd*", DeniedDevice::Node(PathBuf::from("/dev/sd*")), Some(("/dev", "sd")))]
+    fn denied_device_parses_types_and_nodes(
+        #[case] input: &str,
+        #[case] expected: DeniedDevice,
+        #[case] prefix: Option<(&str, &str)>,
+    ) {
+        let device = DeniedDevice::try_from(input.to_string()).unwrap();
+        assert_eq!(device, expected);
+        assert_eq!(device.to_string(), input);
+        assert_eq!(
+            device.node_prefix(),
+            prefix.map(|(dir, name)| (Path::new(dir), name))
+        );
+    }
+
+    #[test]
+    fn denied_device_rejects_relative_paths() {
+        assert!(DeniedDevice::try_from("kmsg".to_string()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn canonicalize_resolves_symlinked_parent_of_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        symlink(&real, &link).unwrap();
+
+        let canonical = canonicalize_lenient(&link.join("missing/file.txt"));
+        assert_eq!(
+            canonical,
+            fs::canonicalize(&real).unwrap().join("missing/file.txt")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn with_symlink_targets_adds_resolved_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        symlink(&real, &link).unwrap();
+        let real = fs::canonicalize(&real).unwrap();
+
+        let mut policy = FilePolicy::new();
+        policy.deny_read(link.join("secret"));
+        policy.allow(&link);
+        assert_eq!(policy.with_symlink_targets(), policy);
+
+        policy.resolve_symlinks = true;
+        let resolved = policy.with_symlink_targets();
+        assert_eq!(
+            resolved.denied_paths,
+            vec![
+                (link.join("secret"), AccessMode::Read),
+                (real.join("secret"), AccessMode::Read),
+            ]
+        );
+        assert_eq!(resolved.allowed_paths, vec![link.clone(), real]);
+    }
+
+    #[test]
+    fn merge_combines_allowed_paths() {
+        let mut policy = FilePolicy::new();
+        policy.allow("/work");
+        let mut other = FilePolicy::new();
+        other.allow("/work");
+        other.allow("/data");
+        policy.merge(other);
+        assert_eq!(
+            policy.allowed_paths,
+            vec![PathBuf::from("/work"), PathBuf::from("/data")]
+        );
+    }
+
     #[test]
     fn normalize_path_with_mixed_components() {
         let policy = FilePolicy::new();
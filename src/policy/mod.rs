@@ -1,9 +1,14 @@
 pub mod file;
 pub mod model;
 pub mod net;
+pub mod presets;
 pub mod process;
 
 // Re-export main types for backward compatibility and convenience
-pub use file::{AccessMode, FilePolicy};
+pub use file::{AccessMode, DeniedDevice, FilePolicy};
 pub use model::Policy;
-pub use net::{AllowPolicy, NetworkPolicy};
+pub use net::{
+    AllowPolicy, DenyList, DnsPolicy, InboundPolicy, NetworkPolicy, SocketFamily, UnixPolicy,
+};
+pub use presets::{PRESETS, Preset, find_preset};
+pub use process::{DEFAULT_SECCOMP_PROFILE, ProcessPolicy, SeccompAction, SeccompPolicy};
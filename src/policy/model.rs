@@ -1,14 +1,22 @@
 // Common model definitions shared across all policy types
-use super::file::FilePolicy;
-use super::net::NetworkPolicy;
-use super::process::ProcessPolicy;
+use serde::{Deserialize, Serialize};
+
+use super::file::{AccessMode, DeniedDevice, FilePolicy};
+use super::net::{AllowPolicy, InboundPolicy, NetworkPolicy, SocketFamily};
+use super::process::{ProcessPolicy, SeccompAction};
 
 /// Unified policy model that combines all policy types
-#[derive(Debug, Clone, PartialEq, Default)]
+///
+/// The serde representation is a stable JSON schema for tools generating or
+/// consuming mori policies (see `--policy`); missing sections default to an
+/// empty policy, which denies all network access. Paths are used as given.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Policy {
     pub network: NetworkPolicy,
     pub file: FilePolicy,
     pub process: ProcessPolicy,
+    pub inbound: InboundPolicy,
 }
 
 impl Policy {
@@ -24,4 +32,430 @@ impl Policy {
             ..Default::default()
         }
     }
+
+    /// Render the policy as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("policy serializes to JSON")
+    }
+
+    /// Parse a policy from JSON
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Check if listening sockets are limited to the allowed listen ports
+    ///
+    /// Listening is restricted when asked for explicitly, and whenever
+    /// outbound connections are restricted in any way.
+    pub fn restricts_listen(&self) -> bool {
+        self.inbound.listen_restricted
+            || !self.network.is_allow_all()
+            || !self.network.denied.is_empty()
+    }
+
+    /// Describe every restriction in the policy, one human-readable rule per entry
+    ///
+    /// An empty list means the policy does not restrict the command at all.
+    pub fn restrictions(&self) -> Vec<String> {
+        let mut rules = Vec::new();
+
+        if let AllowPolicy::Entries {
+            allowed_ipv4,
+            allowed_cidr,
+            allowed_ipv6,
+            allowed_cidr_v6,
+            allowed_domains,
+            allowed_domain_suffixes,
+            allowed_ports,
+        } = &self.network.policy
+        {
+            // Hosts restricted to some ports are listed once per port
+            let with_ports = |host: String| -> Vec<String> {
+                match allowed_ports.get(&host) {
+                    None => vec![host],
+                    Some(ports) => ports.iter().map(|port| port.entry(&host)).collect(),
+                }
+            };
+            let allowed: Vec<String> = allowed_ipv4
+                .iter()
+                .map(ToString::to_string)
+                .flat_map(with_ports)
+                .chain(
+                    allowed_cidr
+                        .iter()
+                        .map(|(addr, prefix_len)| format!("{}/{}", addr, prefix_len))
+                        .flat_map(with_ports),
+                )
+                .chain(
+                    allowed_ipv6
+                        .iter()
+                        .map(ToString::to_string)
+                        .flat_map(with_ports),
+                )
+                .chain(
+                    allowed_cidr_v6
+                        .iter()
+                        .map(|(addr, prefix_len)| format!("{}/{}", addr, prefix_len))
+                        .flat_map(with_ports),
+                )
+                .chain(allowed_domains.iter().cloned().flat_map(with_ports))
+                .chain(allowed_domain_suffixes.iter().cloned())
+                .collect();
+            if allowed.is_empty() {
+                rules.push("network: deny all outbound connections".to_string());
+            } else {
+                rules.push(format!(
+                    "network: allow outbound connections only to {}",
+                    allowed.join(", ")
+                ));
+            }
+            if self.network.deny_localhost {
+                rules.push("network: do not allow localhost unless listed".to_string());
+            }
+        }
+
+        let denied = &self.network.denied;
+        if !denied.is_empty() {
+            let destinations: Vec<String> = denied
+                .denied_cidr
+                .iter()
+                .map(|(addr, prefix_len)| format!("{}/{}", addr, prefix_len))
+                .chain(
+                    denied
+                        .denied_cidr_v6
+                        .iter()
+                        .map(|(addr, prefix_len)| format!("{}/{}", addr, prefix_len)),
+                )
+                .chain(denied.denied_domains.iter().cloned())
+                .collect();
+            rules.push(format!(
+                "network: deny outbound connections to {}",
+                destinations.join(", ")
+            ));
+        }
+
+        if self.inbound.restricted {
+            let peers: Vec<String> = self
+                .inbound
+                .allowed_peers
+                .iter()
+                .map(|(addr, prefix_len)| format!("{}/{}", addr, prefix_len))
+                .chain(
+                    self.inbound
+                        .allowed_peers_v6
+                        .iter()
+                        .map(|(addr, prefix_len)| format!("{}/{}", addr, prefix_len)),
+                )
+                .collect();
+            if peers.is_empty() {
+                rules.push("network: accept inbound connections only from localhost".to_string());
+            } else {
+                rules.push(format!(
+                    "network: accept inbound connections only from {}",
+                    peers.join(", ")
+                ));
+            }
+        }
+
+        if self.restricts_listen() {
+            let ports: Vec<String> = self
+                .inbound
+                .allowed_listen_ports
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            if ports.is_empty() {
+                rules.push("network: deny listening sockets".to_string());
+            } else {
+                rules.push(format!(
+                    "network: listen only on port(s) {}",
+                    ports.join(", ")
+                ));
+            }
+        }
+
+        let families: Vec<&str> = self
+            .network
+            .denied_socket_families()
+            .into_iter()
+            .map(SocketFamily::name)
+            .collect();
+        if !families.is_empty() {
+            rules.push(format!("network: deny {} sockets", families.join(", ")));
+        }
+        if !self.network.icmp_allowed() {
+            rules.push("network: deny ICMP".to_string());
+        }
+        let names = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| format!("@{}", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let unix = &self.network.unix;
+        if unix.deny_abstract {
+            if unix.allowed_abstract.is_empty() {
+                rules.push("network: deny abstract unix sockets".to_string());
+            } else {
+                rules.push(format!(
+                    "network: allow abstract unix sockets only to {}",
+                    names(&unix.allowed_abstract)
+                ));
+            }
+        }
+        if !unix.denied_abstract.is_empty() {
+            rules.push(format!(
+                "network: deny abstract unix sockets {}",
+                names(&unix.denied_abstract)
+            ));
+        }
+
+        for (path, mode) in &self.file.denied_paths {
+            let access = match mode {
+                AccessMode::Read => "read",
+                AccessMode::Write => "write",
+                AccessMode::ReadWrite => "read/write",
+                AccessMode::Execute => "execute",
+            };
+            rules.push(format!("file: deny {} {}", access, path.display()));
+        }
+        if !self.file.allowed_paths.is_empty() {
+            let allowed: Vec<String> = self
+                .file
+                .allowed_paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            rules.push(format!(
+                "file: allow only {} and the system paths",
+                allowed.join(", ")
+            ));
+        }
+        for device in &self.file.denied_devices {
+            let device = match device {
+                DeniedDevice::Block => "block devices".to_string(),
+                DeniedDevice::Char => "character devices".to_string(),
+                DeniedDevice::Node(path) => path.display().to_string(),
+            };
+            rules.push(format!("file: deny opening {}", device));
+        }
+        if self.file.private_proc {
+            rules.push("file: deny /proc entries of processes outside the sandbox".to_string());
+        }
+        if self.file.resolve_symlinks {
+            rules.push("file: apply the path rules to symlink targets too".to_string());
+        }
+        if self.file.hide_denied {
+            rules.push("file: hide the paths denied for reading".to_string());
+        }
+
+        for path in &self.process.denied_exec {
+            rules.push(format!("exec: deny {}", path.display()));
+        }
+        if !self.process.allowed_exec.is_empty() {
+            let allowed: Vec<String> = self
+                .process
+                .allowed_exec
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            rules.push(format!("exec: allow only {}", allowed.join(", ")));
+        }
+        if let Some(max) = self.process.max_pids {
+            rules.push(format!("process: at most {} processes", max));
+        }
+        if self.process.deny_namespaces {
+            rules.push("process: no new namespaces".to_string());
+        }
+
+        if let Some(seccomp) = &self.process.seccomp {
+            let default = match seccomp.default_action {
+                SeccompAction::Allow => "allow",
+                SeccompAction::Deny => "deny",
+            };
+            rules.push(format!(
+                "seccomp: {} by default, {} allowed and {} denied system call(s)",
+                default,
+                seccomp.allowed_syscalls.len(),
+                seccomp.denied_syscalls.len() + seccomp.errno_syscalls.len()
+            ));
+        }
+
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{DenyList, UnixPolicy};
+
+    #[test]
+    fn restrictions_empty_for_unrestricted_policy() {
+        let policy = Policy::with_network(NetworkPolicy::from_allow_all(true));
+        assert!(policy.restrictions().is_empty());
+    }
+
+    #[test]
+    fn restrictions_describe_each_rule() {
+        let mut policy = Policy::with_network(
+            NetworkPolicy::from_entries(&["192.0.2.1".to_string(), "example.com:443".to_string()])
+                .unwrap(),
+        );
+        policy.inbound = InboundPolicy::from_entries(&["10.0.0.0/8".to_string()]).unwrap();
+        policy.file.deny_read("/etc/shadow");
+        policy.process.deny_exec("/usr/bin/curl");
+
+        assert_eq!(
+            policy.restrictions(),
+            vec![
+                "network: allow outbound connections only to 192.0.2.1, example.com:443",
+                "network: accept inbound connections only from 10.0.0.0/8",
+                "network: deny listening sockets",
+                "network: deny netlink, packet sockets",
+                "network: deny ICMP",
+                "file: deny read /etc/shadow",
+                "exec: deny /usr/bin/curl",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_round_trips_with_stable_schema() {
+        let mut policy = Policy::with_network(
+            NetworkPolicy::from_entries(&["10.0.0.0/8".to_string(), "example.com".to_string()])
+                .unwrap(),
+        );
+        policy.file.deny_read_write("/home/user/.ssh");
+        policy.process.deny_exec("/usr/bin/curl");
+
+        let json: serde_json::Value = serde_json::from_str(&policy.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "network": {
+                    "mode": "entries",
+                    "allowed_ipv4": [],
+                    "allowed_cidr": ["10.0.0.0/8"],
+                    "allowed_ipv6": [],
+                    "allowed_cidr_v6": [],
+                    "allowed_domains": ["example.com"],
+                    "allowed_ports": {},
+                },
+                "file": {
+                    "denied_paths": [{"path": "/home/user/.ssh", "access": "read-write"}],
+                },
+                "process": {
+                    "denied_exec": ["/usr/bin/curl"],
+                    "allowed_exec": [],
+                    "seccomp": null,
+                },
+                "inbound": {
+                    "restricted": false,
+                    "allowed_peers": [],
+                    "allowed_peers_v6": [],
+                    "listen_restricted": false,
+                    "allowed_listen_ports": [],
+                },
+            })
+        );
+        assert_eq!(Policy::from_json(&policy.to_json()).unwrap(), policy);
+    }
+
+    #[test]
+    fn deny_list_round_trips_next_to_mode() {
+        let mut network = NetworkPolicy::from_allow_all(true);
+        network.denied = DenyList::from_entries(&[
+            "10.0.0.0/8".to_string(),
+            "169.254.169.254".to_string(),
+            "metadata.internal".to_string(),
+        ])
+        .unwrap();
+        let policy = Policy::with_network(network);
+
+        let json: serde_json::Value = serde_json::from_str(&policy.to_json()).unwrap();
+        assert_eq!(
+            json["network"],
+            serde_json::json!({
+                "mode": "all",
+                "denied": {
+                    "denied_cidr": ["169.254.169.254/32", "10.0.0.0/8"],
+                    "denied_cidr_v6": [],
+                    "denied_domains": ["metadata.internal"],
+                },
+            })
+        );
+        assert_eq!(Policy::from_json(&policy.to_json()).unwrap(), policy);
+        assert_eq!(
+            policy.restrictions(),
+            vec![
+                "network: deny outbound connections to 169.254.169.254/32, 10.0.0.0/8, metadata.internal",
+                "network: deny listening sockets",
+                "network: deny netlink, packet sockets",
+                "network: deny ICMP",
+            ]
+        );
+    }
+
+    #[test]
+    fn from_json_defaults_missing_sections() {
+        let policy = Policy::from_json(r#"{"network": {"mode": "all"}}"#).unwrap();
+        assert_eq!(
+            policy,
+            Policy::with_network(NetworkPolicy::from_allow_all(true))
+        );
+        assert_eq!(Policy::from_json("{}").unwrap(), Policy::new());
+        assert!(
+            Policy::from_json(
+                r#"{"network": {"mode": "entries", "allowed_cidr": ["10.0.0.0/33"]}}"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn restrictions_report_default_network_deny() {
+        assert_eq!(
+            Policy::new().restrictions(),
+            vec![
+                "network: deny all outbound connections",
+                "network: deny listening sockets",
+                "network: deny netlink, packet sockets",
+                "network: deny ICMP"
+            ]
+        );
+    }
+
+    #[test]
+    fn restricts_listen_with_network_rules_or_listen_ports() {
+        assert!(Policy::new().restricts_listen());
+        let mut policy = Policy::with_network(NetworkPolicy::from_allow_all(true));
+        assert!(!policy.restricts_listen());
+
+        policy.inbound = InboundPolicy::from_listen_ports(&[8080, 3000]);
+        assert!(policy.restricts_listen());
+        assert_eq!(
+            policy.restrictions(),
+            vec!["network: listen only on port(s) 3000, 8080"]
+        );
+    }
+
+    #[test]
+    fn restrictions_describe_abstract_unix_sockets() {
+        let mut network = NetworkPolicy::from_allow_all(true);
+        network.unix = UnixPolicy::from_entries(
+            true,
+            &["@/tmp/.X11-unix/X0".to_string()],
+            &["@dbus".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            Policy::with_network(network).restrictions(),
+            vec![
+                "network: allow abstract unix sockets only to @/tmp/.X11-unix/X0",
+                "network: deny abstract unix sockets @dbus",
+            ]
+        );
+    }
 }
@@ -1,24 +1,442 @@
-use std::net::Ipv4Addr;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
 
-use crate::{error::MoriError, net::parse_allow_network};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::MoriError,
+    net::{PortRule, parse_allow_network},
+};
+
+use super::presets::{find_preset, preset_names};
 
 /// Network access policy variants
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Serialized with a `mode` tag, e.g. `{"mode": "all"}` or
+/// `{"mode": "entries", "allowed_cidr": ["10.0.0.0/8"], ...}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
 pub enum AllowPolicy {
     /// Allow all network connections
     All,
     /// Allow specific entries (IPs, CIDR ranges, and domains)
     Entries {
+        #[serde(default)]
         allowed_ipv4: Vec<Ipv4Addr>,
+        #[serde(default, with = "cidr_list")]
         allowed_cidr: Vec<(Ipv4Addr, u8)>,
+        #[serde(default)]
+        allowed_ipv6: Vec<Ipv6Addr>,
+        #[serde(default, with = "cidr_list")]
+        allowed_cidr_v6: Vec<(Ipv6Addr, u8)>,
+        #[serde(default)]
         allowed_domains: Vec<String>,
+        /// Suffixes like `.corp.internal`, allowing the domain and every name
+        /// under it as the command looks them up
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        allowed_domain_suffixes: Vec<String>,
+        /// Destination ports (and protocols) of the addresses and domains
+        /// above that are only allowed on some ports, keyed by address or domain
+        #[serde(default)]
+        allowed_ports: BTreeMap<String, BTreeSet<PortRule>>,
     },
 }
 
+/// CIDR ranges as "addr/prefix_len" strings
+mod cidr_list {
+    use std::{
+        fmt::Display,
+        net::{Ipv4Addr, Ipv6Addr},
+        str::FromStr,
+    };
+
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    /// Address types CIDR ranges are written for
+    pub trait Prefixed: Display + FromStr<Err: Display> {
+        const MAX_PREFIX_LEN: u8;
+    }
+
+    impl Prefixed for Ipv4Addr {
+        const MAX_PREFIX_LEN: u8 = 32;
+    }
+
+    impl Prefixed for Ipv6Addr {
+        const MAX_PREFIX_LEN: u8 = 128;
+    }
+
+    pub fn serialize<A: Prefixed, S: Serializer>(
+        cidrs: &[(A, u8)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            cidrs
+                .iter()
+                .map(|(addr, prefix_len)| format!("{}/{}", addr, prefix_len)),
+        )
+    }
+
+    pub fn deserialize<'de, A: Prefixed, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(A, u8)>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|cidr| {
+                let (addr, prefix_len) = cidr
+                    .split_once('/')
+                    .ok_or_else(|| D::Error::custom(format!("{} is not a CIDR range", cidr)))?;
+                let addr: A = addr.parse().map_err(D::Error::custom)?;
+                match prefix_len.parse::<u8>() {
+                    Ok(prefix_len) if prefix_len <= A::MAX_PREFIX_LEN => Ok((addr, prefix_len)),
+                    _ => Err(D::Error::custom(format!(
+                        "invalid prefix length in {}",
+                        cidr
+                    ))),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Destinations denied even when the allow list (or allow-all) covers them
+///
+/// Addresses are stored as full-length CIDR ranges.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DenyList {
+    #[serde(with = "cidr_list")]
+    pub denied_cidr: Vec<(Ipv4Addr, u8)>,
+    #[serde(with = "cidr_list")]
+    pub denied_cidr_v6: Vec<(Ipv6Addr, u8)>,
+    pub denied_domains: Vec<String>,
+}
+
+impl DenyList {
+    /// Build a deny list from IP addresses, CIDR ranges and domains
+    pub fn from_entries(entries: &[String]) -> Result<Self, MoriError> {
+        let rules = parse_allow_network(entries)?;
+        if let Some(host) = rules.ports.keys().next() {
+            return Err(MoriError::InvalidAllowNetworkEntry {
+                entry: host.clone(),
+                reason: "denied destinations cannot be restricted to ports".to_string(),
+            });
+        }
+        if let Some(suffix) = rules.domain_suffixes.first() {
+            return Err(MoriError::InvalidAllowNetworkEntry {
+                entry: suffix.clone(),
+                reason: "domain suffixes cannot be denied".to_string(),
+            });
+        }
+        let mut denied_cidr: Vec<(Ipv4Addr, u8)> =
+            rules.direct_v4.into_iter().map(|ip| (ip, 32)).collect();
+        denied_cidr.extend(rules.cidr_v4);
+        let mut denied_cidr_v6: Vec<(Ipv6Addr, u8)> =
+            rules.direct_v6.into_iter().map(|ip| (ip, 128)).collect();
+        denied_cidr_v6.extend(rules.cidr_v6);
+        Ok(Self {
+            denied_cidr,
+            denied_cidr_v6,
+            denied_domains: rules.domains,
+        })
+    }
+
+    /// Check if nothing is denied
+    pub fn is_empty(&self) -> bool {
+        self.denied_cidr.is_empty()
+            && self.denied_cidr_v6.is_empty()
+            && self.denied_domains.is_empty()
+    }
+
+    /// Merge another deny list
+    pub fn merge(&mut self, other: Self) {
+        for cidr in other.denied_cidr {
+            if !self.denied_cidr.contains(&cidr) {
+                self.denied_cidr.push(cidr);
+            }
+        }
+        for cidr in other.denied_cidr_v6 {
+            if !self.denied_cidr_v6.contains(&cidr) {
+                self.denied_cidr_v6.push(cidr);
+            }
+        }
+        for domain in other.denied_domains {
+            if !self.denied_domains.contains(&domain) {
+                self.denied_domains.push(domain);
+            }
+        }
+    }
+}
+
+/// Socket address families that can bypass address-based filtering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketFamily {
+    /// AF_NETLINK: kernel interfaces (routing, firewall and socket diagnostics)
+    Netlink,
+    /// AF_PACKET: raw frames sent below the IP layer
+    Packet,
+    /// AF_BLUETOOTH
+    Bluetooth,
+    /// AF_VSOCK: virtual machine sockets
+    Vsock,
+    /// AF_XDP: raw frames through XDP
+    Xdp,
+}
+
+impl SocketFamily {
+    /// Families denied when outbound connections are restricted and no
+    /// families were configured
+    pub const DEFAULT_DENIED: [SocketFamily; 2] = [SocketFamily::Netlink, SocketFamily::Packet];
+
+    /// Every family that can be denied
+    pub const ALL: [SocketFamily; 5] = [
+        SocketFamily::Netlink,
+        SocketFamily::Packet,
+        SocketFamily::Bluetooth,
+        SocketFamily::Vsock,
+        SocketFamily::Xdp,
+    ];
+
+    /// Address family number passed to socket(2)
+    pub fn number(self) -> u32 {
+        match self {
+            SocketFamily::Netlink => 16,
+            SocketFamily::Packet => 17,
+            SocketFamily::Bluetooth => 31,
+            SocketFamily::Vsock => 40,
+            SocketFamily::Xdp => 44,
+        }
+    }
+
+    /// Lowercase name as written in the configuration file
+    pub fn name(self) -> &'static str {
+        match self {
+            SocketFamily::Netlink => "netlink",
+            SocketFamily::Packet => "packet",
+            SocketFamily::Bluetooth => "bluetooth",
+            SocketFamily::Vsock => "vsock",
+            SocketFamily::Xdp => "xdp",
+        }
+    }
+}
+
+/// Longest abstract unix socket name: sun_path minus the leading NUL byte
+pub const UNIX_NAME_MAX: usize = 107;
+
+/// Connections to abstract unix sockets
+///
+/// Abstract sockets live in a namespace of names starting with a NUL byte
+/// rather than in the filesystem, so file rules never apply to them. Names
+/// are stored without the NUL byte and written as `@name`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnixPolicy {
+    /// Deny every abstract name missing from `allowed_abstract`
+    pub deny_abstract: bool,
+    pub allowed_abstract: Vec<String>,
+    /// Names denied even when `deny_abstract` is not set
+    pub denied_abstract: Vec<String>,
+}
+
+impl UnixPolicy {
+    /// Build a policy from `@name` entries
+    pub fn from_entries(
+        deny_abstract: bool,
+        allow: &[String],
+        deny: &[String],
+    ) -> Result<Self, MoriError> {
+        let names = |entries: &[String]| -> Result<Vec<String>, MoriError> {
+            let mut names = entries
+                .iter()
+                .map(|entry| parse_abstract_name(entry))
+                .collect::<Result<Vec<_>, _>>()?;
+            names.sort();
+            names.dedup();
+            Ok(names)
+        };
+        Ok(Self {
+            deny_abstract,
+            allowed_abstract: names(allow)?,
+            denied_abstract: names(deny)?,
+        })
+    }
+
+    /// Check if the policy leaves unix sockets unrestricted
+    pub fn is_empty(&self) -> bool {
+        !self.deny_abstract && self.denied_abstract.is_empty()
+    }
+
+    /// Merge another policy; abstract names are denied by default if either
+    /// policy denies them, and the name lists are combined
+    pub fn merge(&mut self, other: Self) {
+        self.deny_abstract |= other.deny_abstract;
+        for (names, others) in [
+            (&mut self.allowed_abstract, other.allowed_abstract),
+            (&mut self.denied_abstract, other.denied_abstract),
+        ] {
+            names.extend(others);
+            names.sort();
+            names.dedup();
+        }
+    }
+}
+
+/// Parse an `@name` entry into the abstract socket name
+fn parse_abstract_name(entry: &str) -> Result<String, MoriError> {
+    let invalid = |reason: &str| MoriError::InvalidUnixSocketEntry {
+        entry: entry.to_string(),
+        reason: reason.to_string(),
+    };
+    let name = entry
+        .strip_prefix('@')
+        .ok_or_else(|| invalid("abstract socket names must start with '@'"))?;
+    if name.is_empty() {
+        return Err(invalid("empty socket name"));
+    }
+    if name.len() > UNIX_NAME_MAX {
+        return Err(invalid("socket names are at most 107 bytes"));
+    }
+    Ok(name.to_string())
+}
+
+/// Well-known public resolvers reachable over DNS-over-HTTPS (443) and
+/// DNS-over-TLS (853), by hostname
+pub const DOH_ENDPOINTS: &[(&str, &[IpAddr])] = &[
+    (
+        "dns.google",
+        &[
+            IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+            IpAddr::V4(Ipv4Addr::new(8, 8, 4, 4)),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888)),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8844)),
+        ],
+    ),
+    (
+        "cloudflare-dns.com",
+        &[
+            IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(1, 0, 0, 1)),
+            IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111)),
+            IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1001)),
+        ],
+    ),
+    (
+        "dns.quad9.net",
+        &[
+            IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9)),
+            IpAddr::V4(Ipv4Addr::new(149, 112, 112, 112)),
+            IpAddr::V6(Ipv6Addr::new(0x2620, 0xfe, 0, 0, 0, 0, 0, 0xfe)),
+            IpAddr::V6(Ipv6Addr::new(0x2620, 0xfe, 0, 0, 0, 0, 0, 0x9)),
+        ],
+    ),
+    (
+        "doh.opendns.com",
+        &[
+            IpAddr::V4(Ipv4Addr::new(208, 67, 222, 222)),
+            IpAddr::V4(Ipv4Addr::new(208, 67, 220, 220)),
+        ],
+    ),
+    (
+        "dns.adguard-dns.com",
+        &[
+            IpAddr::V4(Ipv4Addr::new(94, 140, 14, 14)),
+            IpAddr::V4(Ipv4Addr::new(94, 140, 15, 15)),
+        ],
+    ),
+];
+
+/// Where the command may send DNS queries
+///
+/// With `enforce`, port 53 only reaches the nameservers and the known
+/// DNS-over-HTTPS/TLS resolvers are blocked, so every lookup goes through
+/// a resolver mori trusts. `nameservers` replaces the system configuration
+/// when not empty. With `strict`, every allowed or denied domain must resolve
+/// to at least one address at startup (lookups are retried for a while).
+///
+/// The remaining fields tune how often allowed domains are resolved again, in
+/// seconds: `refresh_interval` while a domain has no cached records, and
+/// `min_ttl`/`max_ttl` clamp the TTL of the records (`max_ttl` wins if they
+/// conflict).
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsPolicy {
+    pub enforce: bool,
+    pub nameservers: Vec<IpAddr>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub strict: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_ttl: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ttl: Option<u64>,
+}
+
+impl DnsPolicy {
+    /// Check if the policy keeps the system resolver configuration and the
+    /// default refresh schedule as is
+    pub fn is_empty(&self) -> bool {
+        !self.enforce
+            && self.nameservers.is_empty()
+            && !self.strict
+            && self.refresh_interval.is_none()
+            && self.min_ttl.is_none()
+            && self.max_ttl.is_none()
+    }
+
+    /// Merge another policy; DNS is enforced (and strict) if either policy
+    /// says so, the nameservers are combined, and the other policy's refresh
+    /// settings take precedence
+    pub fn merge(&mut self, other: Self) {
+        self.enforce |= other.enforce;
+        self.strict |= other.strict;
+        for nameserver in other.nameservers {
+            if !self.nameservers.contains(&nameserver) {
+                self.nameservers.push(nameserver);
+            }
+        }
+        self.refresh_interval = other.refresh_interval.or(self.refresh_interval);
+        self.min_ttl = other.min_ttl.or(self.min_ttl);
+        self.max_ttl = other.max_ttl.or(self.max_ttl);
+    }
+}
+
 /// Unified representation of network access policy
-#[derive(Debug, Clone, PartialEq)]
+///
+/// The deny list is checked before the allow list; it is serialized next to
+/// the `mode` tag as a `denied` table, omitted when empty. `denied_families`
+/// and `allow_icmp` are omitted when unset, which means
+/// `SocketFamily::DEFAULT_DENIED` and no ICMP while outbound connections are
+/// restricted. `unix` and `dns` are omitted when they restrict nothing,
+/// `hosts` when no static host mappings are configured, and `enforce_sni`
+/// and `deny_localhost` when unset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NetworkPolicy {
+    #[serde(flatten)]
     pub policy: AllowPolicy,
+    #[serde(default, skip_serializing_if = "DenyList::is_empty")]
+    pub denied: DenyList,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denied_families: Option<Vec<SocketFamily>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_icmp: Option<bool>,
+    #[serde(default, skip_serializing_if = "UnixPolicy::is_empty")]
+    pub unix: UnixPolicy,
+    #[serde(default, skip_serializing_if = "DnsPolicy::is_empty")]
+    pub dns: DnsPolicy,
+    /// Static host mappings, by lowercase name without the trailing dot
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, Vec<IpAddr>>,
+    /// Deny TLS connections whose server name (SNI) is not an allowed
+    /// domain, unless the destination is allowed by address
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enforce_sni: bool,
+    /// Drop the implicit allow of localhost (127.0.0.1 and ::1), so local
+    /// services are only reachable through explicit entries
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny_localhost: bool,
 }
 
 impl Default for NetworkPolicy {
@@ -27,8 +445,20 @@ impl Default for NetworkPolicy {
             policy: AllowPolicy::Entries {
                 allowed_ipv4: Vec::new(),
                 allowed_cidr: Vec::new(),
+                allowed_ipv6: Vec::new(),
+                allowed_cidr_v6: Vec::new(),
                 allowed_domains: Vec::new(),
+                allowed_domain_suffixes: Vec::new(),
+                allowed_ports: BTreeMap::new(),
             },
+            denied: DenyList::default(),
+            denied_families: None,
+            allow_icmp: None,
+            unix: UnixPolicy::default(),
+            dns: DnsPolicy::default(),
+            hosts: BTreeMap::new(),
+            enforce_sni: false,
+            deny_localhost: false,
         }
     }
 }
@@ -44,6 +474,14 @@ impl NetworkPolicy {
         if allow_all {
             Self {
                 policy: AllowPolicy::All,
+                denied: DenyList::default(),
+                denied_families: None,
+                allow_icmp: None,
+                unix: UnixPolicy::default(),
+                dns: DnsPolicy::default(),
+                hosts: BTreeMap::new(),
+                enforce_sni: false,
+                deny_localhost: false,
             }
         } else {
             Self::default()
@@ -57,18 +495,219 @@ impl NetworkPolicy {
             policy: AllowPolicy::Entries {
                 allowed_ipv4: network_rules.direct_v4,
                 allowed_cidr: network_rules.cidr_v4,
+                allowed_ipv6: network_rules.direct_v6,
+                allowed_cidr_v6: network_rules.cidr_v6,
                 allowed_domains: network_rules.domains,
+                allowed_domain_suffixes: network_rules.domain_suffixes,
+                allowed_ports: network_rules.ports,
             },
+            denied: DenyList::default(),
+            denied_families: None,
+            allow_icmp: None,
+            unix: UnixPolicy::default(),
+            dns: DnsPolicy::default(),
+            hosts: BTreeMap::new(),
+            enforce_sni: false,
+            deny_localhost: false,
         })
     }
 
+    /// Build policy allowing the hosts of the named presets
+    pub fn from_presets(names: &[String]) -> Result<Self, MoriError> {
+        let mut hosts = Vec::new();
+        for name in names {
+            let preset = find_preset(name).ok_or_else(|| MoriError::UnknownPreset {
+                name: name.clone(),
+                available: preset_names(),
+            })?;
+            hosts.extend(preset.hosts.iter().map(|host| host.to_string()));
+        }
+        Self::from_entries(&hosts)
+    }
+
     /// Check if all network is allowed
     pub fn is_allow_all(&self) -> bool {
         matches!(self.policy, AllowPolicy::All)
     }
 
+    /// Socket families the command may not create
+    ///
+    /// Unless configured, AF_NETLINK and AF_PACKET are denied whenever
+    /// outbound connections are restricted, since raw sockets bypass the
+    /// connect hooks.
+    pub fn denied_socket_families(&self) -> Vec<SocketFamily> {
+        match &self.denied_families {
+            Some(families) => families.clone(),
+            None if self.is_allow_all() && self.denied.is_empty() => Vec::new(),
+            None => SocketFamily::DEFAULT_DENIED.to_vec(),
+        }
+    }
+
+    /// Check if the command may create ICMP sockets and ping any host
+    ///
+    /// Unless configured, ICMP follows the outbound rules: it is allowed when
+    /// every destination is, and denied otherwise.
+    pub fn icmp_allowed(&self) -> bool {
+        self.allow_icmp
+            .unwrap_or_else(|| self.is_allow_all() && self.denied.is_empty())
+    }
+
+    /// Addresses of the known DNS-over-HTTPS/TLS resolvers to block
+    ///
+    /// Empty unless DNS is enforced. Resolvers allowed by address or by name,
+    /// and configured nameservers, stay reachable.
+    pub fn dns_bypass_endpoints(&self) -> Vec<IpAddr> {
+        if !self.dns.enforce {
+            return Vec::new();
+        }
+        let (allowed_ipv4, allowed_ipv6, allowed_domains): (&[_], &[_], &[_]) = match &self.policy {
+            AllowPolicy::Entries {
+                allowed_ipv4,
+                allowed_ipv6,
+                allowed_domains,
+                ..
+            } => (allowed_ipv4, allowed_ipv6, allowed_domains),
+            AllowPolicy::All => (&[], &[], &[]),
+        };
+        let explicitly_allowed = |ip: &IpAddr| {
+            self.dns.nameservers.contains(ip)
+                || match ip {
+                    IpAddr::V4(ip) => allowed_ipv4.contains(ip),
+                    IpAddr::V6(ip) => allowed_ipv6.contains(ip),
+                }
+        };
+
+        DOH_ENDPOINTS
+            .iter()
+            .filter(|(name, _)| {
+                !allowed_domains
+                    .iter()
+                    .any(|domain| domain.trim_end_matches('.').eq_ignore_ascii_case(name))
+            })
+            .flat_map(|(_, addrs)| addrs.iter().copied())
+            .filter(|ip| !explicitly_allowed(ip))
+            .collect()
+    }
+
+    /// Check if no network access is allowed at all
+    pub fn is_deny_all(&self) -> bool {
+        matches!(
+            &self.policy,
+            AllowPolicy::Entries {
+                allowed_ipv4,
+                allowed_cidr,
+                allowed_ipv6,
+                allowed_cidr_v6,
+                allowed_domains,
+                allowed_domain_suffixes,
+                ..
+            } if allowed_ipv4.is_empty()
+                && allowed_cidr.is_empty()
+                && allowed_ipv6.is_empty()
+                && allowed_cidr_v6.is_empty()
+                && allowed_domains.is_empty()
+                && allowed_domain_suffixes.is_empty()
+        )
+    }
+
+    /// Allow `domain` through the given addresses instead of resolving it
+    ///
+    /// The domain is dropped from the allowed domains, so it is never looked up.
+    /// Has no effect when all network access is allowed.
+    pub fn pin_domain(&mut self, domain: &str, addrs: &[IpAddr]) {
+        let AllowPolicy::Entries {
+            allowed_ipv4,
+            allowed_ipv6,
+            allowed_domains,
+            allowed_ports,
+            ..
+        } = &mut self.policy
+        else {
+            return;
+        };
+        let domain = domain.trim_end_matches('.');
+        let pinned = |allowed: &str| allowed.trim_end_matches('.').eq_ignore_ascii_case(domain);
+        allowed_domains.retain(|allowed| !pinned(allowed));
+        // The addresses take over the ports the domain was restricted to
+        let mut ports: Option<BTreeSet<PortRule>> = None;
+        allowed_ports.retain(|allowed, allowed_ports| {
+            if pinned(allowed) {
+                ports.get_or_insert_default().extend(allowed_ports.iter());
+            }
+            !pinned(allowed)
+        });
+        for addr in addrs {
+            let key = addr.to_string();
+            let already_allowed = match addr {
+                IpAddr::V4(addr) => allowed_ipv4.contains(addr),
+                IpAddr::V6(addr) => allowed_ipv6.contains(addr),
+            };
+            let every_port = already_allowed && !allowed_ports.contains_key(&key);
+            match &ports {
+                Some(ports) if !every_port => {
+                    allowed_ports.entry(key).or_default().extend(ports);
+                }
+                Some(_) => {}
+                None => {
+                    allowed_ports.remove(&key);
+                }
+            }
+            if !already_allowed {
+                match *addr {
+                    IpAddr::V4(addr) => allowed_ipv4.push(addr),
+                    IpAddr::V6(addr) => allowed_ipv6.push(addr),
+                }
+            }
+        }
+    }
+
+    /// Map `host` to fixed addresses, like an /etc/hosts entry
+    ///
+    /// The host is allowed through the addresses and never resolved (see
+    /// `pin_domain`); the mapping is kept in `hosts` so the runtime knows
+    /// the name of those addresses without a lookup.
+    pub fn add_host(&mut self, host: &str, addrs: &[IpAddr]) {
+        self.pin_domain(host, addrs);
+        let known = self
+            .hosts
+            .entry(host.trim_end_matches('.').to_ascii_lowercase())
+            .or_default();
+        for addr in addrs {
+            if !known.contains(addr) {
+                known.push(*addr);
+            }
+        }
+    }
+
     /// Merge another policy
+    ///
+    /// Allow lists are combined and so are deny lists; a destination denied
+    /// by either policy stays denied.
     pub fn merge(&mut self, other: Self) {
+        self.denied.merge(other.denied);
+        if let Some(families) = other.denied_families {
+            let merged = self.denied_families.get_or_insert_with(Vec::new);
+            merged.extend(families);
+            merged.sort_unstable();
+            merged.dedup();
+        }
+        self.unix.merge(other.unix);
+        self.dns.merge(other.dns);
+        self.enforce_sni |= other.enforce_sni;
+        self.deny_localhost |= other.deny_localhost;
+        for (host, addrs) in other.hosts {
+            let known = self.hosts.entry(host).or_default();
+            for addr in addrs {
+                if !known.contains(&addr) {
+                    known.push(addr);
+                }
+            }
+        }
+        // Allowing ICMP in either policy allows it
+        self.allow_icmp = match (self.allow_icmp, other.allow_icmp) {
+            (Some(base), Some(other)) => Some(base || other),
+            (base, other) => base.or(other),
+        };
         match (&mut self.policy, other.policy) {
             // If either is allow-all, result is allow-all
             (_, AllowPolicy::All) => {
@@ -82,14 +721,59 @@ impl NetworkPolicy {
                 AllowPolicy::Entries {
                     allowed_ipv4: base_ips,
                     allowed_cidr: base_cidrs,
+                    allowed_ipv6: base_ips_v6,
+                    allowed_cidr_v6: base_cidrs_v6,
                     allowed_domains: base_domains,
+                    allowed_domain_suffixes: base_suffixes,
+                    allowed_ports: base_ports,
                 },
                 AllowPolicy::Entries {
                     allowed_ipv4: other_ips,
                     allowed_cidr: other_cidrs,
+                    allowed_ipv6: other_ips_v6,
+                    allowed_cidr_v6: other_cidrs_v6,
                     allowed_domains: other_domains,
+                    allowed_domain_suffixes: other_suffixes,
+                    allowed_ports: mut other_ports,
                 },
             ) => {
+                // A host allowed on every port by either policy stays allowed on every port
+                let hosts = |ips: &[Ipv4Addr],
+                             cidrs: &[(Ipv4Addr, u8)],
+                             ips_v6: &[Ipv6Addr],
+                             cidrs_v6: &[(Ipv6Addr, u8)],
+                             domains: &[String]| {
+                    ips.iter()
+                        .map(ToString::to_string)
+                        .chain(cidrs.iter().map(|(ip, len)| format!("{}/{}", ip, len)))
+                        .chain(ips_v6.iter().map(ToString::to_string))
+                        .chain(cidrs_v6.iter().map(|(ip, len)| format!("{}/{}", ip, len)))
+                        .chain(domains.iter().cloned())
+                        .collect::<BTreeSet<String>>()
+                };
+                let base_hosts = hosts(
+                    base_ips,
+                    base_cidrs,
+                    base_ips_v6,
+                    base_cidrs_v6,
+                    base_domains,
+                );
+                let other_hosts = hosts(
+                    &other_ips,
+                    &other_cidrs,
+                    &other_ips_v6,
+                    &other_cidrs_v6,
+                    &other_domains,
+                );
+                base_ports.retain(|host, _| {
+                    !other_hosts.contains(host) || other_ports.contains_key(host)
+                });
+                other_ports
+                    .retain(|host, _| !base_hosts.contains(host) || base_ports.contains_key(host));
+                for (host, ports) in other_ports {
+                    base_ports.entry(host).or_default().extend(ports);
+                }
+
                 for ip in other_ips {
                     if !base_ips.contains(&ip) {
                         base_ips.push(ip);
@@ -100,11 +784,105 @@ impl NetworkPolicy {
                         base_cidrs.push(cidr);
                     }
                 }
+                for ip in other_ips_v6 {
+                    if !base_ips_v6.contains(&ip) {
+                        base_ips_v6.push(ip);
+                    }
+                }
+                for cidr in other_cidrs_v6 {
+                    if !base_cidrs_v6.contains(&cidr) {
+                        base_cidrs_v6.push(cidr);
+                    }
+                }
                 for domain in other_domains {
                     if !base_domains.contains(&domain) {
                         base_domains.push(domain);
                     }
                 }
+                for suffix in other_suffixes {
+                    if !base_suffixes.contains(&suffix) {
+                        base_suffixes.push(suffix);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Peers allowed to connect to sockets the command listens on, and the
+/// ports it may listen on
+///
+/// Inbound connections are not filtered unless `restricted` is set; loopback
+/// peers are always allowed. Listening sockets are limited to
+/// `allowed_listen_ports` when `listen_restricted` is set, and whenever
+/// outbound connections are restricted (see `Policy::restricts_listen`).
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InboundPolicy {
+    pub restricted: bool,
+    #[serde(with = "cidr_list")]
+    pub allowed_peers: Vec<(Ipv4Addr, u8)>,
+    #[serde(with = "cidr_list")]
+    pub allowed_peers_v6: Vec<(Ipv6Addr, u8)>,
+    pub listen_restricted: bool,
+    pub allowed_listen_ports: Vec<u16>,
+}
+
+impl InboundPolicy {
+    /// Build a policy allowing listening sockets only on the given ports
+    pub fn from_listen_ports(ports: &[u16]) -> Self {
+        let mut allowed_listen_ports = ports.to_vec();
+        allowed_listen_ports.sort_unstable();
+        allowed_listen_ports.dedup();
+        Self {
+            listen_restricted: true,
+            allowed_listen_ports,
+            ..Self::default()
+        }
+    }
+
+    /// Build a restricted policy allowing the given IP addresses and CIDR ranges
+    pub fn from_entries(entries: &[String]) -> Result<Self, MoriError> {
+        let rules = parse_allow_network(entries)?;
+        if let Some(domain) = rules.domains.first() {
+            return Err(MoriError::InvalidAllowNetworkEntry {
+                entry: domain.clone(),
+                reason: "inbound peers must be IP addresses or CIDR ranges".to_string(),
+            });
+        }
+        let mut allowed_peers: Vec<(Ipv4Addr, u8)> =
+            rules.direct_v4.into_iter().map(|ip| (ip, 32)).collect();
+        allowed_peers.extend(rules.cidr_v4);
+        allowed_peers.sort_unstable();
+        let mut allowed_peers_v6: Vec<(Ipv6Addr, u8)> =
+            rules.direct_v6.into_iter().map(|ip| (ip, 128)).collect();
+        allowed_peers_v6.extend(rules.cidr_v6);
+        allowed_peers_v6.sort_unstable();
+        Ok(Self {
+            restricted: true,
+            allowed_peers,
+            allowed_peers_v6,
+            ..Self::default()
+        })
+    }
+
+    /// Merge another policy; the result is restricted if either one is
+    pub fn merge(&mut self, other: Self) {
+        self.restricted |= other.restricted;
+        for peer in other.allowed_peers {
+            if !self.allowed_peers.contains(&peer) {
+                self.allowed_peers.push(peer);
+            }
+        }
+        for peer in other.allowed_peers_v6 {
+            if !self.allowed_peers_v6.contains(&peer) {
+                self.allowed_peers_v6.push(peer);
+            }
+        }
+        self.listen_restricted |= other.listen_restricted;
+        for port in other.allowed_listen_ports {
+            if !self.allowed_listen_ports.contains(&port) {
+                self.allowed_listen_ports.push(port);
             }
         }
     }
@@ -114,6 +892,202 @@ impl NetworkPolicy {
 mod tests {
     use super::*;
 
+    #[test]
+    fn inbound_from_entries_accepts_addresses_and_ranges() {
+        let policy = InboundPolicy::from_entries(&[
+            "10.0.0.0/8".to_string(),
+            "192.0.2.7".to_string(),
+            "2001:db8::/32".to_string(),
+            "fd00::7".to_string(),
+        ])
+        .unwrap();
+        assert!(policy.restricted);
+        assert_eq!(
+            policy.allowed_peers,
+            vec![
+                (Ipv4Addr::new(10, 0, 0, 0), 8),
+                (Ipv4Addr::new(192, 0, 2, 7), 32)
+            ]
+        );
+        assert_eq!(
+            policy.allowed_peers_v6,
+            vec![
+                ("2001:db8::".parse().unwrap(), 32),
+                ("fd00::7".parse().unwrap(), 128)
+            ]
+        );
+
+        let err = InboundPolicy::from_entries(&["example.com".to_string()]).unwrap_err();
+        assert!(matches!(err, MoriError::InvalidAllowNetworkEntry { .. }));
+    }
+
+    #[test]
+    fn inbound_merge_keeps_restriction() {
+        let mut policy = InboundPolicy::default();
+        policy.merge(InboundPolicy::from_entries(&["192.0.2.0/24".to_string()]).unwrap());
+        assert!(policy.restricted);
+        assert_eq!(
+            policy.allowed_peers,
+            vec![(Ipv4Addr::new(192, 0, 2, 0), 24)]
+        );
+    }
+
+    #[test]
+    fn denied_socket_families_default_to_raw_sockets_when_restricted() {
+        assert_eq!(
+            NetworkPolicy::new().denied_socket_families(),
+            vec![SocketFamily::Netlink, SocketFamily::Packet]
+        );
+        assert!(
+            NetworkPolicy::from_allow_all(true)
+                .denied_socket_families()
+                .is_empty()
+        );
+
+        let mut policy = NetworkPolicy::from_allow_all(true);
+        policy.merge(NetworkPolicy {
+            denied_families: Some(vec![SocketFamily::Packet]),
+            ..NetworkPolicy::new()
+        });
+        assert_eq!(policy.denied_socket_families(), vec![SocketFamily::Packet]);
+
+        policy.denied_families = Some(Vec::new());
+        policy.policy = NetworkPolicy::new().policy;
+        assert!(policy.denied_socket_families().is_empty());
+    }
+
+    #[test]
+    fn icmp_follows_outbound_rules_unless_configured() {
+        assert!(!NetworkPolicy::new().icmp_allowed());
+        assert!(NetworkPolicy::from_allow_all(true).icmp_allowed());
+
+        let mut policy = NetworkPolicy::new();
+        policy.allow_icmp = Some(true);
+        assert!(policy.icmp_allowed());
+
+        let mut policy = NetworkPolicy::from_allow_all(true);
+        policy.allow_icmp = Some(false);
+        assert!(!policy.icmp_allowed());
+        policy.merge(NetworkPolicy {
+            allow_icmp: Some(true),
+            ..NetworkPolicy::new()
+        });
+        assert!(policy.icmp_allowed());
+    }
+
+    #[test]
+    fn dns_bypass_endpoints_skip_explicitly_allowed_resolvers() {
+        assert!(NetworkPolicy::new().dns_bypass_endpoints().is_empty());
+
+        let mut policy =
+            NetworkPolicy::from_entries(&["dns.google".to_string(), "1.1.1.1".to_string()])
+                .unwrap();
+        policy.dns = DnsPolicy {
+            enforce: true,
+            nameservers: vec!["9.9.9.9".parse().unwrap()],
+            ..DnsPolicy::default()
+        };
+        let endpoints = policy.dns_bypass_endpoints();
+        for allowed in ["8.8.8.8", "2001:4860:4860::8844", "1.1.1.1", "9.9.9.9"] {
+            assert!(
+                !endpoints.contains(&allowed.parse().unwrap()),
+                "{}",
+                allowed
+            );
+        }
+        for blocked in ["1.0.0.1", "149.112.112.112", "94.140.14.14"] {
+            assert!(endpoints.contains(&blocked.parse().unwrap()), "{}", blocked);
+        }
+    }
+
+    #[test]
+    fn unix_policy_parses_abstract_names() {
+        let entries = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        let mut policy = UnixPolicy::from_entries(
+            true,
+            &entries(&["@/tmp/.X11-unix/X0", "@/tmp/.X11-unix/X0"]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(policy.allowed_abstract, vec!["/tmp/.X11-unix/X0"]);
+        assert!(!policy.is_empty());
+
+        policy.merge(UnixPolicy::from_entries(false, &[], &entries(&["@dbus"])).unwrap());
+        assert!(policy.deny_abstract);
+        assert_eq!(policy.denied_abstract, vec!["dbus"]);
+
+        for entry in ["/run/docker.sock", "@", &format!("@{}", "a".repeat(108))] {
+            assert!(matches!(
+                UnixPolicy::from_entries(false, &entries(&[entry]), &[]),
+                Err(MoriError::InvalidUnixSocketEntry { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn inbound_merge_combines_listen_ports() {
+        let mut policy = InboundPolicy::default();
+        policy.merge(InboundPolicy::from_listen_ports(&[8080, 3000, 8080]));
+        policy.merge(InboundPolicy::from_listen_ports(&[3000, 9000]));
+        assert!(policy.listen_restricted);
+        assert!(!policy.restricted);
+        assert_eq!(policy.allowed_listen_ports, vec![3000, 8080, 9000]);
+    }
+
+    #[test]
+    fn pin_domain_replaces_domain_with_addresses() {
+        let mut policy =
+            NetworkPolicy::from_entries(&["Internal.Corp.".to_string(), "example.com".to_string()])
+                .unwrap();
+        policy.pin_domain("internal.corp", &[Ipv4Addr::new(10, 1, 2, 3).into()]);
+        match policy.policy {
+            AllowPolicy::Entries {
+                allowed_ipv4,
+                allowed_domains,
+                ..
+            } => {
+                assert_eq!(allowed_ipv4, vec![Ipv4Addr::new(10, 1, 2, 3)]);
+                assert_eq!(allowed_domains, vec!["example.com".to_string()]);
+            }
+            AllowPolicy::All => panic!("expected allow list"),
+        }
+
+        let mut all = NetworkPolicy::from_allow_all(true);
+        all.pin_domain("internal.corp", &[Ipv4Addr::new(10, 1, 2, 3).into()]);
+        assert!(all.is_allow_all());
+    }
+
+    #[test]
+    fn add_host_allows_and_records_addresses() {
+        let mut policy = NetworkPolicy::from_entries(&["internal.db".to_string()]).unwrap();
+        let addrs: Vec<IpAddr> = vec!["10.1.2.3".parse().unwrap(), "fd00::3".parse().unwrap()];
+        policy.add_host("Internal.DB.", &addrs);
+        match &policy.policy {
+            AllowPolicy::Entries {
+                allowed_ipv4,
+                allowed_ipv6,
+                allowed_domains,
+                ..
+            } => {
+                assert_eq!(allowed_ipv4, &vec![Ipv4Addr::new(10, 1, 2, 3)]);
+                assert_eq!(allowed_ipv6, &vec!["fd00::3".parse::<Ipv6Addr>().unwrap()]);
+                assert!(allowed_domains.is_empty());
+            }
+            AllowPolicy::All => panic!("expected allow list"),
+        }
+        assert_eq!(policy.hosts.get("internal.db"), Some(&addrs));
+
+        let mut other = NetworkPolicy::new();
+        other.add_host("internal.db", &["10.1.2.4".parse().unwrap()]);
+        policy.merge(other);
+        assert_eq!(policy.hosts["internal.db"].len(), 3);
+    }
+
     #[test]
     fn from_allow_all_true_creates_all_policy() {
         let policy = NetworkPolicy::from_allow_all(true);
@@ -129,10 +1103,18 @@ mod tests {
             AllowPolicy::Entries {
                 allowed_ipv4,
                 allowed_cidr,
+                allowed_ipv6,
+                allowed_cidr_v6,
                 allowed_domains,
+                allowed_domain_suffixes,
+                allowed_ports,
             } => {
+                assert!(allowed_ports.is_empty());
+                assert!(allowed_domain_suffixes.is_empty());
                 assert!(allowed_ipv4.is_empty());
                 assert!(allowed_cidr.is_empty());
+                assert!(allowed_ipv6.is_empty());
+                assert!(allowed_cidr_v6.is_empty());
                 assert!(allowed_domains.is_empty());
             }
             _ => panic!("Expected Entries variant"),
@@ -149,6 +1131,7 @@ mod tests {
                 allowed_ipv4,
                 allowed_cidr,
                 allowed_domains,
+                ..
             } => {
                 assert_eq!(allowed_ipv4.len(), 1);
                 assert_eq!(allowed_cidr.len(), 0);
@@ -158,6 +1141,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn domain_suffixes_merge_and_allow_network() {
+        let mut base = NetworkPolicy::from_entries(&[".corp.internal".to_string()]).unwrap();
+        assert!(!base.is_deny_all());
+        base.merge(
+            NetworkPolicy::from_entries(&[".corp.internal".to_string(), ".lab.test".to_string()])
+                .unwrap(),
+        );
+        match base.policy {
+            AllowPolicy::Entries {
+                allowed_domains,
+                allowed_domain_suffixes,
+                ..
+            } => {
+                assert!(allowed_domains.is_empty());
+                assert_eq!(allowed_domain_suffixes, vec![".corp.internal", ".lab.test"]);
+            }
+            AllowPolicy::All => panic!("expected allow list"),
+        }
+        assert!(DenyList::from_entries(&[".corp.internal".to_string()]).is_err());
+    }
+
     #[test]
     fn merge_entries_with_all_becomes_all() {
         let mut base = NetworkPolicy::from_entries(&["192.0.2.1".to_string()]).unwrap();
@@ -184,6 +1189,7 @@ mod tests {
                 allowed_ipv4,
                 allowed_cidr,
                 allowed_domains,
+                ..
             } => {
                 assert_eq!(allowed_ipv4.len(), 1);
                 assert_eq!(allowed_cidr.len(), 0);
@@ -193,6 +1199,79 @@ mod tests {
         }
     }
 
+    #[test]
+    fn merge_combines_ipv6_entries() {
+        let mut base = NetworkPolicy::from_entries(&["2001:db8::1".to_string()]).unwrap();
+        let other = NetworkPolicy::from_entries(&[
+            "2001:db8::1".to_string(),
+            "2001:db8:1::/48".to_string(),
+        ])
+        .unwrap();
+        base.merge(other);
+        match base.policy {
+            AllowPolicy::Entries {
+                allowed_ipv6,
+                allowed_cidr_v6,
+                ..
+            } => {
+                assert_eq!(
+                    allowed_ipv6,
+                    vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]
+                );
+                assert_eq!(
+                    allowed_cidr_v6,
+                    vec![("2001:db8:1::".parse::<Ipv6Addr>().unwrap(), 48)]
+                );
+            }
+            _ => panic!("Expected Entries variant"),
+        }
+    }
+
+    #[test]
+    fn merge_keeps_every_port_of_unrestricted_hosts() {
+        let mut base = NetworkPolicy::from_entries(&[
+            "example.com:443".to_string(),
+            "github.com".to_string(),
+            "192.0.2.1:80".to_string(),
+        ])
+        .unwrap();
+        base.merge(
+            NetworkPolicy::from_entries(&[
+                "example.com".to_string(),
+                "github.com:22".to_string(),
+                "192.0.2.1:8080".to_string(),
+                "crates.io:443".to_string(),
+            ])
+            .unwrap(),
+        );
+        match base.policy {
+            AllowPolicy::Entries { allowed_ports, .. } => assert_eq!(
+                allowed_ports,
+                BTreeMap::from([
+                    (
+                        "192.0.2.1".to_string(),
+                        BTreeSet::from([80.into(), 8080.into()])
+                    ),
+                    ("crates.io".to_string(), BTreeSet::from([443.into()])),
+                ])
+            ),
+            _ => panic!("Expected Entries variant"),
+        }
+    }
+
+    #[test]
+    fn pin_domain_moves_ports_to_addresses() {
+        let mut policy = NetworkPolicy::from_entries(&["internal.corp:443".to_string()]).unwrap();
+        policy.pin_domain("internal.corp", &[Ipv4Addr::new(10, 1, 2, 3).into()]);
+        match policy.policy {
+            AllowPolicy::Entries { allowed_ports, .. } => assert_eq!(
+                allowed_ports,
+                BTreeMap::from([("10.1.2.3".to_string(), BTreeSet::from([443.into()]))])
+            ),
+            _ => panic!("Expected Entries variant"),
+        }
+    }
+
     #[test]
     fn merge_avoids_duplicates() {
         let mut base =
@@ -207,6 +1286,7 @@ mod tests {
                 allowed_ipv4,
                 allowed_cidr,
                 allowed_domains,
+                ..
             } => {
                 assert_eq!(allowed_ipv4.len(), 1);
                 assert_eq!(allowed_cidr.len(), 0);
@@ -215,4 +1295,25 @@ mod tests {
             _ => panic!("Expected Entries variant"),
         }
     }
+
+    #[test]
+    fn from_presets_allows_preset_hosts() {
+        let policy =
+            NetworkPolicy::from_presets(&["crates".to_string(), "pypi".to_string()]).unwrap();
+        match policy.policy {
+            AllowPolicy::Entries {
+                allowed_domains, ..
+            } => {
+                assert!(allowed_domains.contains(&"static.crates.io".to_string()));
+                assert!(allowed_domains.contains(&"files.pythonhosted.org".to_string()));
+            }
+            _ => panic!("Expected Entries variant"),
+        }
+    }
+
+    #[test]
+    fn from_presets_rejects_unknown_preset() {
+        let result = NetworkPolicy::from_presets(&["cpan".to_string()]);
+        assert!(matches!(result, Err(MoriError::UnknownPreset { name, .. }) if name == "cpan"));
+    }
 }
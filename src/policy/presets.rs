@@ -0,0 +1,115 @@
+/// Named set of hosts a package manager or service needs
+#[derive(Debug, PartialEq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub hosts: &'static [&'static str],
+}
+
+/// Curated presets for package registries and hosted services
+///
+/// Each registry preset lists the download and CDN hosts next to the registry API, as
+/// allowing only the well-known registry name usually breaks downloads.
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "crates",
+        description: "crates.io (Rust)",
+        hosts: &["crates.io", "index.crates.io", "static.crates.io"],
+    },
+    Preset {
+        name: "npm",
+        description: "npm registry (Node.js)",
+        hosts: &["registry.npmjs.org", "registry.yarnpkg.com"],
+    },
+    Preset {
+        name: "pypi",
+        description: "Python Package Index",
+        hosts: &["pypi.org", "files.pythonhosted.org"],
+    },
+    Preset {
+        name: "rubygems",
+        description: "RubyGems",
+        hosts: &[
+            "rubygems.org",
+            "index.rubygems.org",
+            "rubygems.global.ssl.fastly.net",
+        ],
+    },
+    Preset {
+        name: "go",
+        description: "Go module proxy and checksum database",
+        hosts: &[
+            "proxy.golang.org",
+            "sum.golang.org",
+            "storage.googleapis.com",
+        ],
+    },
+    Preset {
+        name: "maven",
+        description: "Maven Central (Java)",
+        hosts: &["repo.maven.apache.org", "repo1.maven.org"],
+    },
+    Preset {
+        name: "github",
+        description: "GitHub repositories, archives and release assets",
+        hosts: &[
+            "github.com",
+            "codeload.github.com",
+            "objects.githubusercontent.com",
+            "raw.githubusercontent.com",
+        ],
+    },
+    Preset {
+        name: "model-apis",
+        description: "Hosted model APIs (Anthropic, OpenAI, Google Gemini)",
+        hosts: &[
+            "api.anthropic.com",
+            "api.openai.com",
+            "generativelanguage.googleapis.com",
+        ],
+    },
+];
+
+/// Look up a preset by name
+pub fn find_preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// Comma-separated names of all presets, for error messages
+pub fn preset_names() -> String {
+    PRESETS
+        .iter()
+        .map(|preset| preset.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::parse_allow_network;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("crates", "static.crates.io")]
+    #[case("pypi", "files.pythonhosted.org")]
+    #[case("github", "objects.githubusercontent.com")]
+    #[case("model-apis", "api.anthropic.com")]
+    fn find_preset_includes_cdn_hosts(#[case] name: &str, #[case] host: &str) {
+        assert!(find_preset(name).unwrap().hosts.contains(&host));
+    }
+
+    #[test]
+    fn find_preset_returns_none_for_unknown_name() {
+        assert_eq!(find_preset("cpan"), None);
+    }
+
+    #[test]
+    fn preset_hosts_are_valid_domains() {
+        for preset in PRESETS {
+            let hosts: Vec<String> = preset.hosts.iter().map(|h| h.to_string()).collect();
+            let rules = parse_allow_network(&hosts).unwrap();
+            assert_eq!(rules.domains.len(), hosts.len(), "preset {}", preset.name);
+        }
+    }
+}
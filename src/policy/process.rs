@@ -1,9 +1,289 @@
-// Process policy structures and validation logic
-// Currently empty as the project doesn't have process policies implemented yet
-// This file is a placeholder for future process control features
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq, Default)]
+use serde::{Deserialize, Serialize};
+
+use super::file::normalize_path;
+
+/// Action applied to system calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeccompAction {
+    /// Let the system call proceed
+    #[default]
+    Allow,
+    /// Fail the system call with EPERM
+    Deny,
+}
+
+/// System call filter applied to the sandboxed command
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeccompPolicy {
+    /// Action for system calls not listed below
+    pub default_action: SeccompAction,
+    /// System calls that are always allowed
+    pub allowed_syscalls: Vec<String>,
+    /// System calls that are always denied
+    pub denied_syscalls: Vec<String>,
+    /// System calls that always fail with the given errno instead of EPERM
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errno_syscalls: BTreeMap<String, i32>,
+}
+
+/// Name of the built-in seccomp profile (`[process] seccomp = "default"`)
+pub const DEFAULT_SECCOMP_PROFILE: &str = "default";
+
+/// System calls denied by the built-in seccomp profile
+///
+/// Debugging other processes, the kernel keyring, loading BPF programs or
+/// kernel modules, mounting, and other calls a build or a script has no use
+/// for but an escape from the sandbox would.
+const DEFAULT_DENIED_SYSCALLS: &[&str] = &[
+    "ptrace",
+    "process_vm_readv",
+    "process_vm_writev",
+    "keyctl",
+    "add_key",
+    "request_key",
+    "bpf",
+    "perf_event_open",
+    "userfaultfd",
+    "kexec_load",
+    "kexec_file_load",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "mount",
+    "umount2",
+    "pivot_root",
+    "fsopen",
+    "fsmount",
+    "move_mount",
+    "open_tree",
+    "open_by_handle_at",
+    "swapon",
+    "swapoff",
+    "reboot",
+    "acct",
+    "settimeofday",
+    "clock_settime",
+    "clock_adjtime",
+    "adjtimex",
+    "iopl",
+    "ioperm",
+];
+
+impl SeccompPolicy {
+    /// The built-in profile: allow everything but `DEFAULT_DENIED_SYSCALLS`
+    pub fn default_profile() -> Self {
+        Self {
+            default_action: SeccompAction::Allow,
+            allowed_syscalls: vec![],
+            denied_syscalls: DEFAULT_DENIED_SYSCALLS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            errno_syscalls: BTreeMap::new(),
+        }
+    }
+}
+
+/// Process execution policy
+///
+/// Executables listed in `denied_exec` can never be started. When `allowed_exec` is
+/// non-empty the policy switches to allow-list mode and only the listed executables
+/// (minus anything in `denied_exec`) may be started.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ProcessPolicy {
-    // Future: allowed processes, resource limits, etc.
+    /// Executables (or directories containing executables) that must not be started
+    pub denied_exec: Vec<PathBuf>,
+    /// Executables that may be started; empty means every executable is allowed
+    pub allowed_exec: Vec<PathBuf>,
+    /// Optional system call filter
+    pub seccomp: Option<SeccompPolicy>,
+    /// Most processes and threads the command may have at once (Linux)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pids: Option<u32>,
+    /// Deny creating or joining namespaces (Linux)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub deny_namespaces: bool,
+    /// Environment variables passed to the command; empty passes every
+    /// variable not denied. A trailing `*` matches any suffix
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env_allow: Vec<String>,
+    /// Environment variables removed before the command starts, taking
+    /// precedence over `env_allow`. A trailing `*` matches any suffix
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env_deny: Vec<String>,
+}
+
+impl ProcessPolicy {
+    /// Create a new empty process policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deny executing the given path
+    pub fn deny_exec<P: AsRef<Path>>(&mut self, path: P) {
+        let path = normalize_path(path.as_ref());
+        if !self.denied_exec.contains(&path) {
+            self.denied_exec.push(path);
+        }
+    }
+
+    /// Allow executing the given path and switch to allow-list mode
+    pub fn allow_exec<P: AsRef<Path>>(&mut self, path: P) {
+        let path = normalize_path(path.as_ref());
+        if !self.allowed_exec.contains(&path) {
+            self.allowed_exec.push(path);
+        }
+    }
+
+    /// Check if any executable restriction is configured
+    pub fn has_exec_rules(&self) -> bool {
+        !self.denied_exec.is_empty() || !self.allowed_exec.is_empty()
+    }
+
+    /// Check if the environment passed to the command is filtered
+    pub fn has_env_rules(&self) -> bool {
+        !self.env_allow.is_empty() || !self.env_deny.is_empty()
+    }
+
+    /// Check if environment variable `name` may be passed to the command
+    pub fn env_allowed(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+        !self.env_deny.iter().any(matches)
+            && (self.env_allow.is_empty() || self.env_allow.iter().any(matches))
+    }
+
+    /// The environment of mori filtered for the command, or None when it is
+    /// passed on unchanged
+    pub fn filtered_env(&self) -> Option<Vec<(OsString, OsString)>> {
+        if !self.has_env_rules() {
+            return None;
+        }
+        Some(
+            std::env::vars_os()
+                .filter(|(name, _)| self.env_allowed(&name.to_string_lossy()))
+                .collect(),
+        )
+    }
+
+    /// Merge another policy into this one
+    pub fn merge(&mut self, other: Self) {
+        for path in other.denied_exec {
+            self.deny_exec(path);
+        }
+        for path in other.allowed_exec {
+            self.allow_exec(path);
+        }
+        // A seccomp filter is a single profile, so a later one replaces the earlier one
+        if other.seccomp.is_some() {
+            self.seccomp = other.seccomp;
+        }
+        // The stricter limit wins
+        if let Some(max) = other.max_pids {
+            self.max_pids = Some(self.max_pids.map_or(max, |current| current.min(max)));
+        }
+        self.deny_namespaces |= other.deny_namespaces;
+        for name in other.env_allow {
+            if !self.env_allow.contains(&name) {
+                self.env_allow.push(name);
+            }
+        }
+        for name in other.env_deny {
+            if !self.env_deny.contains(&name) {
+                self.env_deny.push(name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn new_policy_has_no_exec_rules() {
+        assert!(!ProcessPolicy::new().has_exec_rules());
+    }
+
+    #[test]
+    fn deny_exec_normalizes_and_dedups() {
+        let mut policy = ProcessPolicy::new();
+        policy.deny_exec("/usr/bin/../bin/curl");
+        policy.deny_exec("/usr/bin/curl");
+        assert_eq!(policy.denied_exec, vec![PathBuf::from("/usr/bin/curl")]);
+        assert!(policy.has_exec_rules());
+    }
+
+    #[test]
+    fn merge_combines_allow_and_deny_lists() {
+        let mut base = ProcessPolicy::new();
+        base.allow_exec("/usr/bin/make");
+        let mut other = ProcessPolicy::new();
+        other.allow_exec("/usr/bin/make");
+        other.allow_exec("/usr/bin/cc");
+        other.deny_exec("/usr/bin/ssh");
+
+        base.merge(other);
+
+        assert_eq!(
+            base.allowed_exec,
+            vec![PathBuf::from("/usr/bin/make"), PathBuf::from("/usr/bin/cc")]
+        );
+        assert_eq!(base.denied_exec, vec![PathBuf::from("/usr/bin/ssh")]);
+    }
+
+    #[test]
+    fn default_seccomp_profile_denies_ptrace() {
+        let profile = SeccompPolicy::default_profile();
+        assert_eq!(profile.default_action, SeccompAction::Allow);
+        assert!(profile.denied_syscalls.contains(&"ptrace".to_string()));
+        assert!(profile.allowed_syscalls.is_empty());
+    }
+
+    #[rstest]
+    #[case::no_rules(&[], &[], "AWS_SECRET_ACCESS_KEY", true)]
+    #[case::allowed(&["PATH", "HOME"], &[], "PATH", true)]
+    #[case::not_allowed(&["PATH", "HOME"], &[], "GITHUB_TOKEN", false)]
+    #[case::allowed_prefix(&["LC_*"], &[], "LC_ALL", true)]
+    #[case::denied_prefix(&[], &["AWS_*"], "AWS_SECRET_ACCESS_KEY", false)]
+    #[case::prefix_is_not_substring(&[], &["AWS_*"], "MY_AWS_REGION", true)]
+    #[case::deny_wins(&["AWS_*"], &["AWS_SECRET_ACCESS_KEY"], "AWS_SECRET_ACCESS_KEY", false)]
+    fn env_allowed_applies_allow_and_deny_lists(
+        #[case] allow: &[&str],
+        #[case] deny: &[&str],
+        #[case] name: &str,
+        #[case] expected: bool,
+    ) {
+        let policy = ProcessPolicy {
+            env_allow: allow.iter().map(|name| name.to_string()).collect(),
+            env_deny: deny.iter().map(|name| name.to_string()).collect(),
+            ..ProcessPolicy::new()
+        };
+        assert_eq!(policy.env_allowed(name), expected);
+    }
+
+    #[test]
+    fn merge_keeps_the_lower_pid_limit() {
+        let mut base = ProcessPolicy::new();
+        base.max_pids = Some(256);
+        let mut other = ProcessPolicy::new();
+        other.max_pids = Some(64);
+        base.merge(other);
+        assert_eq!(base.max_pids, Some(64));
+
+        base.merge(ProcessPolicy::new());
+        assert_eq!(base.max_pids, Some(64));
+    }
 }
@@ -1,12 +1,20 @@
 use std::{
     fs::{self, File},
     os::fd::{AsRawFd, BorrowedFd},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process,
+    time::Duration,
 };
 
 use crate::error::MoriError;
 
+/// Default cgroup v2 mount point
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// How often, and how far apart, `kill_all` signals the processes of a
+/// cgroup on kernels without `cgroup.kill`
+const KILL_ATTEMPTS: usize = 10;
+const KILL_INTERVAL: Duration = Duration::from_millis(50);
+
 /// Cgroup manager that creates and manages a cgroup for process isolation
 pub struct CgroupManager {
     pub path: PathBuf,
@@ -15,12 +23,25 @@ pub struct CgroupManager {
 
 impl CgroupManager {
     /// Create a new cgroup and return a manager for it
+    ///
+    /// The cgroup is created at the root of the cgroup v2 hierarchy when it is
+    /// writable. Inside containers such as VS Code dev containers only the
+    /// container's own cgroup may be delegated, so mori falls back to creating
+    /// it below the cgroup it is running in.
     pub fn create() -> Result<Self, MoriError> {
-        // Create a unique cgroup directory under /sys/fs/cgroup/
         let cgroup_name = format!("mori-{}", process::id());
-        let cgroup_path = PathBuf::from("/sys/fs/cgroup").join(cgroup_name);
 
-        fs::create_dir_all(&cgroup_path)?;
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+        let own_cgroup = fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+        let candidates = candidate_parents(
+            parse_cgroup2_mount(&mountinfo),
+            parse_own_cgroup(&own_cgroup).as_deref(),
+        );
+
+        let cgroup_path = create_in_first(&candidates, &cgroup_name)?;
+        if cgroup_path.parent() != Some(candidates[0].as_path()) {
+            log::info!("Using delegated cgroup {}", cgroup_path.display());
+        }
 
         // Change ownership to SUDO_UID/SUDO_GID if running under sudo
         // This allows the child process to write to cgroup.procs after dropping privileges
@@ -39,6 +60,32 @@ impl CgroupManager {
         })
     }
 
+    /// Limit the number of processes and threads in the cgroup
+    ///
+    /// The pids controller is enabled for the parent's children first when
+    /// it is not yet; the kernel refuses that while the parent itself holds
+    /// processes, as the cgroup mori runs in does.
+    pub fn limit_pids(&self, max: u32) -> Result<(), MoriError> {
+        let pids_max = self.path.join("pids.max");
+        if !pids_max.exists()
+            && let Some(parent) = self.path.parent()
+        {
+            let control = parent.join("cgroup.subtree_control");
+            fs::write(&control, "+pids").map_err(|source| MoriError::CgroupOperation {
+                operation: "enable the pids controller".to_string(),
+                path: control.clone(),
+                source,
+            })?;
+        }
+        fs::write(&pids_max, max.to_string()).map_err(|source| MoriError::CgroupOperation {
+            operation: "limit processes".to_string(),
+            path: pids_max.clone(),
+            source,
+        })?;
+        log::info!("Limited the command to {} processes", max);
+        Ok(())
+    }
+
     /// Get a borrowed file descriptor for the cgroup
     pub fn fd(&self) -> BorrowedFd<'_> {
         unsafe { BorrowedFd::borrow_raw(self.file.as_raw_fd()) }
@@ -47,7 +94,256 @@ impl CgroupManager {
 
 impl Drop for CgroupManager {
     fn drop(&mut self) {
-        // Clean up the cgroup directory when dropped
-        let _ = fs::remove_dir(&self.path);
+        kill_remaining(&self.path);
+        // The killed processes keep the directory busy until they are gone
+        for _ in 0..KILL_ATTEMPTS {
+            match fs::remove_dir(&self.path) {
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    std::thread::sleep(KILL_INTERVAL)
+                }
+                _ => return,
+            }
+        }
+        log::warn!(
+            "Failed to remove cgroup {}: processes are still running in it",
+            self.path.display()
+        );
+    }
+}
+
+/// Kill the processes the command left in the cgroup at `path`, such as
+/// daemons and orphaned grandchildren
+pub fn kill_remaining(path: &Path) {
+    let remaining = fs::read_to_string(path.join("cgroup.procs"))
+        .map(|procs| procs.lines().count())
+        .unwrap_or(0);
+    if remaining == 0 {
+        return;
+    }
+    log::info!("Killing {} processes left in {}", remaining, path.display());
+    if let Err(e) = kill_all(path) {
+        log::warn!("Failed to kill the processes in {}: {}", path.display(), e);
+    }
+}
+
+/// Kill every process in the cgroup at `path` with SIGKILL
+///
+/// Writes `cgroup.kill`, which also catches processes forked meanwhile;
+/// kernels before 5.14 lack it, so there the processes listed in
+/// `cgroup.procs` are signalled until none is left.
+pub fn kill_all(path: &Path) -> std::io::Result<()> {
+    match fs::write(path.join("cgroup.kill"), "1") {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        result => return result,
+    }
+    for _ in 0..KILL_ATTEMPTS {
+        let procs = fs::read_to_string(path.join("cgroup.procs"))?;
+        let pids: Vec<libc::pid_t> = procs.lines().filter_map(|pid| pid.parse().ok()).collect();
+        if pids.is_empty() {
+            return Ok(());
+        }
+        for pid in pids {
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+        std::thread::sleep(KILL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Create directory `name` in the first of `parents` that allows it
+fn create_in_first(parents: &[PathBuf], name: &str) -> std::io::Result<PathBuf> {
+    let mut last_error = std::io::Error::from(std::io::ErrorKind::NotFound);
+    for parent in parents {
+        let path = parent.join(name);
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(path),
+            Err(e) => {
+                log::debug!("Cannot create cgroup under {}: {}", parent.display(), e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// cgroup v2 mount from /proc/self/mountinfo
+#[derive(Debug, PartialEq)]
+struct CgroupMount {
+    /// Path within the cgroup hierarchy that is mounted (not "/" for bind mounts into containers)
+    root: PathBuf,
+    mount_point: PathBuf,
+}
+
+/// Where the cgroup v2 hierarchy is mounted, or None without one
+pub(super) fn cgroup2_mount_point() -> Option<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    parse_cgroup2_mount(&mountinfo).map(|mount| mount.mount_point)
+}
+
+/// Find the cgroup v2 mount, preferring /sys/fs/cgroup when there are several
+fn parse_cgroup2_mount(mountinfo: &str) -> Option<CgroupMount> {
+    let mounts: Vec<CgroupMount> = mountinfo
+        .lines()
+        .filter_map(|line| {
+            // "<id> <parent> <major:minor> <root> <mount point> <options> [optional...] - <fstype> ..."
+            let (fields, fs_fields) = line.split_once(" - ")?;
+            if fs_fields.split_whitespace().next()? != "cgroup2" {
+                return None;
+            }
+            let mut fields = fields.split_whitespace().skip(3);
+            Some(CgroupMount {
+                root: PathBuf::from(unescape_mount_path(fields.next()?)),
+                mount_point: PathBuf::from(unescape_mount_path(fields.next()?)),
+            })
+        })
+        .collect();
+
+    let preferred = mounts
+        .iter()
+        .position(|mount| mount.mount_point == Path::new(CGROUP_ROOT))
+        .unwrap_or(0);
+    mounts.into_iter().nth(preferred)
+}
+
+/// cgroup v2 path of the current process from /proc/self/cgroup ("0::/path")
+fn parse_own_cgroup(proc_cgroup: &str) -> Option<PathBuf> {
+    proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from)
+}
+
+/// Directories to try creating mori's cgroup in, most preferred first
+fn candidate_parents(mount: Option<CgroupMount>, own_cgroup: Option<&Path>) -> Vec<PathBuf> {
+    let mount = mount.unwrap_or(CgroupMount {
+        root: PathBuf::from("/"),
+        mount_point: PathBuf::from(CGROUP_ROOT),
+    });
+    let mut candidates = vec![mount.mount_point.clone()];
+
+    // Map the process' own cgroup into the mount; paths outside the mounted
+    // subtree (e.g. "/.." from another cgroup namespace) are not reachable
+    if let Some(relative) = own_cgroup.and_then(|own| own.strip_prefix(&mount.root).ok())
+        && !relative.as_os_str().is_empty()
+        && !relative.starts_with("..")
+    {
+        candidates.push(mount.mount_point.join(relative));
+    }
+    candidates
+}
+
+/// Decode the octal escapes (e.g. "\040" for a space) used in mountinfo paths
+fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(byte) = bytes
+                .get(i + 1..i + 4)
+                .and_then(|digits| std::str::from_utf8(digits).ok())
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        {
+            out.push(byte);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const HOST_MOUNTINFO: &str = "\
+24 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 24 0:30 / /sys/fs/cgroup rw,nosuid,nodev,noexec,relatime shared:9 - cgroup2 cgroup2 rw,nsdelegate
+";
+
+    const CONTAINER_MOUNTINFO: &str = "\
+512 480 0:30 /docker/0123abcd /sys/fs/cgroup ro,nosuid,nodev,noexec,relatime - cgroup2 cgroup rw
+";
+
+    #[test]
+    fn parse_cgroup2_mount_finds_host_mount() {
+        assert_eq!(
+            parse_cgroup2_mount(HOST_MOUNTINFO),
+            Some(CgroupMount {
+                root: PathBuf::from("/"),
+                mount_point: PathBuf::from("/sys/fs/cgroup"),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_cgroup2_mount_returns_none_without_cgroup2() {
+        assert_eq!(
+            parse_cgroup2_mount("24 1 8:1 / / rw - ext4 /dev/sda1 rw\n"),
+            None
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "0::/user.slice/session-1.scope\n",
+        Some("/user.slice/session-1.scope")
+    )]
+    #[case("12:cpu:/\n0::/\n", Some("/"))]
+    #[case("12:cpu:/\n", None)]
+    fn parse_own_cgroup_reads_unified_entry(#[case] input: &str, #[case] expected: Option<&str>) {
+        assert_eq!(parse_own_cgroup(input), expected.map(PathBuf::from));
+    }
+
+    #[test]
+    fn candidate_parents_adds_delegated_subtree() {
+        let candidates = candidate_parents(
+            parse_cgroup2_mount(HOST_MOUNTINFO),
+            Some(Path::new("/user.slice/user@1000.service/app.slice")),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/sys/fs/cgroup"),
+                PathBuf::from("/sys/fs/cgroup/user.slice/user@1000.service/app.slice"),
+            ]
+        );
+    }
+
+    #[test]
+    fn candidate_parents_maps_container_bind_mount() {
+        // Without a cgroup namespace the process sees its full path, but only
+        // the container's subtree is mounted
+        let candidates = candidate_parents(
+            parse_cgroup2_mount(CONTAINER_MOUNTINFO),
+            Some(Path::new("/docker/0123abcd/vscode")),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/sys/fs/cgroup"),
+                PathBuf::from("/sys/fs/cgroup/vscode"),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(Some("/"))]
+    #[case(Some("/.."))]
+    #[case(None)]
+    fn candidate_parents_uses_root_only(#[case] own: Option<&str>) {
+        assert_eq!(
+            candidate_parents(parse_cgroup2_mount(HOST_MOUNTINFO), own.map(Path::new)),
+            vec![PathBuf::from("/sys/fs/cgroup")]
+        );
+    }
+
+    #[test]
+    fn unescape_mount_path_decodes_octal_escapes() {
+        assert_eq!(unescape_mount_path("/mnt/my\\040dir"), "/mnt/my dir");
     }
 }
@@ -1,6 +1,6 @@
 use std::{
-    collections::HashSet,
-    net::Ipv4Addr,
+    collections::{BTreeSet, HashSet},
+    net::IpAddr,
     sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
@@ -8,69 +8,250 @@ use std::{
 use crate::{
     error::MoriError,
     net::{
-        cache::DnsCache,
+        PortRule,
+        cache::{DnsCache, RefreshMargin},
         resolver::{DnsResolver, DomainRecords},
     },
 };
 
-use super::{ebpf::EbpfController, sync::ShutdownSignal};
+use super::{
+    ebpf::{ALLOW_V4_CAPACITY, EbpfController, max_prefix_len},
+    sync::ShutdownSignal,
+};
 
+/// How often domains without cached records are resolved again, unless configured
 const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 
+/// When the refresh tasks resolve their domains again
+#[derive(Debug, Clone, Copy)]
+pub struct RefreshSchedule {
+    /// How long before their records expire domains are resolved again
+    pub margin: RefreshMargin,
+    /// How often domains are resolved while no records are cached
+    pub interval: Duration,
+}
+
+impl RefreshSchedule {
+    /// Schedule with `margin`, and `interval_secs` (at least one second)
+    /// or the default interval
+    pub fn new(margin: RefreshMargin, interval_secs: Option<u64>) -> Self {
+        Self {
+            margin,
+            interval: interval_secs
+                .map(|secs| Duration::from_secs(secs.max(1)))
+                .unwrap_or(DEFAULT_REFRESH_INTERVAL),
+        }
+    }
+}
+
+/// How long after its DNS records expire the BPF program still allows an
+/// address, giving the refresh task time to resolve the domain again
+const KERNEL_EXPIRY_GRACE: Duration = Duration::from_secs(30);
+
+/// Allow list size above which DNS-learned entries are evicted
+const EVICTION_HIGH_WATERMARK: usize = ALLOW_V4_CAPACITY / 16 * 15;
+/// Allow list size eviction brings the map back down to
+const EVICTION_LOW_WATERMARK: usize = ALLOW_V4_CAPACITY / 8 * 7;
+
 pub fn apply_domain_records<E: EbpfController>(
     dns_cache: &Arc<Mutex<DnsCache>>,
     ebpf: &Arc<Mutex<E>>,
     now: Instant,
     new_domains: Vec<DomainRecords>,
 ) -> Result<(), MoriError> {
-    let diffs = {
+    let (diffs, evicted, len, expirations, restricted_ports) = {
         let mut cache = dns_cache.lock().unwrap();
-        new_domains
+        let diffs = new_domains
             .into_iter()
             .map(|domain| cache.apply(&domain.domain, now, domain.records))
-            .collect::<Vec<_>>()
+            .collect::<Vec<_>>();
+        let len = cache.allow_list_len();
+        let evicted = if len > EVICTION_HIGH_WATERMARK {
+            cache.evict(now, len - EVICTION_LOW_WATERMARK)
+        } else {
+            Vec::new()
+        };
+        let expirations: Vec<_> = cache
+            .learned_expirations()
+            .into_iter()
+            .map(|(ip, expires_at)| (ip, expires_at, cache.ports_for(ip)))
+            .collect();
+        (diffs, evicted, len, expirations, cache.restricted_ports())
     };
 
+    if !evicted.is_empty() {
+        eprintln!(
+            "mori: network allow list is nearly full ({} of {} entries); evicted {} addresses learned from DNS",
+            len,
+            ALLOW_V4_CAPACITY,
+            evicted.len()
+        );
+    }
+
     let mut ebpf_guard = ebpf.lock().unwrap();
-    for diff in diffs {
-        for ip in diff.removed {
-            ebpf_guard.remove_network(ip, 32)?; // DNS resolved IPs are single IPs (/32)
-            log::info!("Resolved domain IPv4 {} removed from allow list", ip);
+    let added: HashSet<&IpAddr> = diffs.iter().flat_map(|diff| &diff.added).collect();
+    let never_inserted: HashSet<&IpAddr> = added
+        .iter()
+        .copied()
+        .filter(|ip| evicted.contains(ip))
+        .collect();
+    // Install the new addresses before removing the old ones, so a domain is
+    // never left without an allowed address while it changes. Addresses that
+    // were already allowed are written again to move their kernel-side deadline.
+    // Entries for ports an address is no longer allowed on are not restamped,
+    // so the BPF programs expire them.
+    for (ip, expires_at, ip_ports) in expirations {
+        for port in ip_ports {
+            // DNS resolved IPs are single IPs (/32 or /128)
+            ebpf_guard.allow_network_until(
+                ip,
+                max_prefix_len(ip),
+                port,
+                expires_at + KERNEL_EXPIRY_GRACE,
+            )?;
         }
-        for ip in diff.added {
-            ebpf_guard.allow_network(ip, 32)?; // DNS resolved IPs are single IPs (/32)
-            log::info!("Resolved domain IPv4 {} added to allow list", ip);
+        if added.contains(&ip) {
+            log::info!("Resolved domain IP {} added to allow list", ip);
+        }
+    }
+    for ip in diffs.iter().flat_map(|diff| &diff.removed) {
+        // Moved from one domain to another within this update
+        if added.contains(ip) {
+            continue;
         }
+        remove_learned(&mut *ebpf_guard, *ip, &restricted_ports)?;
+        log::info!("Resolved domain IP {} removed from allow list", ip);
     }
+    for ip in evicted {
+        if !never_inserted.contains(&ip) {
+            remove_learned(&mut *ebpf_guard, ip, &restricted_ports)?;
+            log::warn!("Resolved domain IP {} evicted from allow list", ip);
+        }
+    }
+
+    Ok(())
+}
 
+/// Remove the entries of a learned address for every port a domain may allow it on
+fn remove_learned<E: EbpfController>(
+    ebpf: &mut E,
+    ip: IpAddr,
+    restricted_ports: &BTreeSet<PortRule>,
+) -> Result<(), MoriError> {
+    ebpf.remove_network(ip, max_prefix_len(ip), None)?;
+    for &port in restricted_ports {
+        ebpf.remove_network(ip, max_prefix_len(ip), Some(port))?;
+    }
     Ok(())
 }
 
 pub fn apply_dns_servers<E: EbpfController>(
+    dns_cache: &Arc<Mutex<DnsCache>>,
     ebpf: &Arc<Mutex<E>>,
-    allowed_dns_ips: &Arc<Mutex<HashSet<Ipv4Addr>>>,
-    ips: Vec<Ipv4Addr>,
+    allowed_dns_ips: &Arc<Mutex<HashSet<IpAddr>>>,
+    ips: Vec<IpAddr>,
 ) -> Result<(), MoriError> {
+    let mut cache = dns_cache.lock().unwrap();
     let mut set = allowed_dns_ips.lock().unwrap();
     let mut ebpf_guard = ebpf.lock().unwrap();
 
     for ip in ips {
         if set.insert(ip) {
-            ebpf_guard.allow_network(ip, 32)?; // DNS server IPs are single IPs (/32)
-            log::info!("Nameserver IPv4 {} added to allow list", ip);
+            // DNS server IPs are single IPs (/32 or /128)
+            cache.pin(ip, max_prefix_len(ip));
+            ebpf_guard.allow_nameserver(ip)?;
+            log::info!("Nameserver IP {} added to allow list", ip);
         }
     }
 
     Ok(())
 }
 
+/// Deny the addresses the denied domains resolved to
+///
+/// Addresses stay denied for the rest of the run, even once a domain stops
+/// resolving to them, so a stale record never reopens a denied host.
+pub fn apply_denied_records<E: EbpfController>(
+    deny_cache: &Arc<Mutex<DnsCache>>,
+    ebpf: &Arc<Mutex<E>>,
+    now: Instant,
+    new_domains: Vec<DomainRecords>,
+) -> Result<(), MoriError> {
+    let added: Vec<IpAddr> = {
+        let mut cache = deny_cache.lock().unwrap();
+        new_domains
+            .into_iter()
+            .flat_map(|domain| cache.apply(&domain.domain, now, domain.records).added)
+            .collect()
+    };
+
+    let mut ebpf_guard = ebpf.lock().unwrap();
+    for ip in added {
+        // DNS resolved IPs are single IPs (/32 or /128)
+        ebpf_guard.deny_network(ip, max_prefix_len(ip))?;
+        log::info!("Resolved domain IP {} added to deny list", ip);
+    }
+    Ok(())
+}
+
+/// Resolve the denied domains again as their records expire, denying new addresses
+///
+/// While no records are cached the domains are resolved every `schedule.interval`.
+pub fn spawn_deny_refresh<R: DnsResolver, E: EbpfController>(
+    domains: Vec<String>,
+    deny_cache: Arc<Mutex<DnsCache>>,
+    ebpf: Arc<Mutex<E>>,
+    shutdown_signal: Arc<ShutdownSignal>,
+    resolver: R,
+    schedule: RefreshSchedule,
+) -> Option<tokio::task::JoinHandle<Result<(), MoriError>>> {
+    if domains.is_empty() {
+        return None;
+    }
+
+    Some(tokio::spawn(async move {
+        loop {
+            let sleep_duration = deny_cache
+                .lock()
+                .unwrap()
+                .next_refresh_in(Instant::now(), schedule.margin)
+                .unwrap_or(schedule.interval);
+
+            if shutdown_signal
+                .wait_timeout_or_shutdown(sleep_duration)
+                .await
+            {
+                return Ok(());
+            }
+
+            match resolver.resolve_domains(&domains).await {
+                Ok(resolved) => {
+                    let _ =
+                        apply_denied_records(&deny_cache, &ebpf, Instant::now(), resolved.domains)
+                            .inspect_err(|err| {
+                                log::error!("Failed to apply denied domain records: {err}");
+                            });
+                }
+                Err(err) => {
+                    log::error!("Failed to refresh denied domain records: {err}");
+                }
+            }
+        }
+    }))
+}
+
+/// Resolve the allowed domains again as their records expire, allowing new
+/// addresses and removing stale ones
+///
+/// While no records are cached the domains are resolved every `schedule.interval`.
 pub fn spawn_refresh<R: DnsResolver, E: EbpfController>(
     domains: Vec<String>,
     dns_cache: Arc<Mutex<DnsCache>>,
     ebpf: Arc<Mutex<E>>,
-    allowed_dns_ips: Arc<Mutex<HashSet<Ipv4Addr>>>,
+    allowed_dns_ips: Arc<Mutex<HashSet<IpAddr>>>,
     shutdown_signal: Arc<ShutdownSignal>,
     resolver: R,
+    schedule: RefreshSchedule,
 ) -> Option<tokio::task::JoinHandle<Result<(), MoriError>>> {
     if domains.is_empty() {
         return None;
@@ -82,8 +263,8 @@ pub fn spawn_refresh<R: DnsResolver, E: EbpfController>(
             let sleep_duration = {
                 let cache = dns_cache.lock().unwrap();
                 cache
-                    .next_refresh_in(now)
-                    .unwrap_or(DEFAULT_REFRESH_INTERVAL)
+                    .next_refresh_in(now, schedule.margin)
+                    .unwrap_or(schedule.interval)
             };
 
             // Wait for timeout or shutdown signal
@@ -94,14 +275,16 @@ pub fn spawn_refresh<R: DnsResolver, E: EbpfController>(
                 return Ok(());
             }
 
+            // Domains that fail to resolve keep their previous records
             match resolver.resolve_domains(&domains).await {
                 Ok(resolved) => {
                     let now = Instant::now();
+                    let dns_servers = resolved.dns_servers();
                     let _ = apply_domain_records(&dns_cache, &ebpf, now, resolved.domains)
                         .inspect_err(|err| {
                             log::error!("Failed to apply domain records: {err}");
                         });
-                    let _ = apply_dns_servers(&ebpf, &allowed_dns_ips, resolved.dns_v4)
+                    let _ = apply_dns_servers(&dns_cache, &ebpf, &allowed_dns_ips, dns_servers)
                         .inspect_err(|err| {
                             log::error!("Failed to apply DNS servers: {err}");
                         });
@@ -110,6 +293,13 @@ pub fn spawn_refresh<R: DnsResolver, E: EbpfController>(
                     log::error!("Failed to refresh DNS records: {err}");
                 }
             }
+
+            // Addresses the BPF program learned from DNS answers expire on their own
+            match ebpf.lock().unwrap().reap_expired() {
+                Ok(0) => {}
+                Ok(reaped) => log::info!("Removed {} expired addresses from allow list", reaped),
+                Err(err) => log::error!("Failed to remove expired addresses: {err}"),
+            }
         }
     }))
 }
@@ -121,6 +311,186 @@ mod tests {
 
     use super::super::ebpf::MockEbpfController;
 
+    #[test]
+    fn test_apply_domain_records_evicts_under_map_pressure() {
+        use crate::net::cache::Entry;
+
+        let dns_cache = Arc::new(Mutex::new(DnsCache::default()));
+        let now = Instant::now();
+        let static_ip: IpAddr = "192.0.2.1".parse().unwrap();
+        dns_cache.lock().unwrap().pin(static_ip, 32);
+
+        let mut mock_ebpf = MockEbpfController::new();
+        mock_ebpf
+            .expect_allow_network_until()
+            .returning(|_, _, _, _| Ok(()))
+            .times(..);
+        mock_ebpf
+            .expect_remove_network()
+            .withf(move |ip, _, _| *ip != static_ip)
+            .returning(|_, _, _| Ok(()))
+            .times(..);
+        let ebpf = Arc::new(Mutex::new(mock_ebpf));
+
+        let records = (0..EVICTION_HIGH_WATERMARK as u32)
+            .map(|n| Entry {
+                ip: IpAddr::from((0x0a00_0000 + n).to_be_bytes()),
+                expires_at: now + Duration::from_secs(60),
+            })
+            .chain(std::iter::once(Entry {
+                ip: static_ip,
+                expires_at: now + Duration::from_secs(60),
+            }))
+            .collect();
+        apply_domain_records(
+            &dns_cache,
+            &ebpf,
+            now,
+            vec![DomainRecords {
+                domain: "cdn.example.com".to_string(),
+                records,
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(
+            dns_cache.lock().unwrap().allow_list_len(),
+            EVICTION_LOW_WATERMARK
+        );
+    }
+
+    #[test]
+    fn test_apply_domain_records_extends_kernel_deadlines() {
+        use crate::net::cache::Entry;
+
+        let dns_cache = Arc::new(Mutex::new(DnsCache::default()));
+        let now = Instant::now();
+        let ip: IpAddr = "93.184.216.34".parse().unwrap();
+        let first_expiry = now + Duration::from_secs(60);
+        let second_expiry = now + Duration::from_secs(120);
+
+        let mut mock_ebpf = MockEbpfController::new();
+        let mut seq = mockall::Sequence::new();
+        for expires_at in [first_expiry, second_expiry] {
+            mock_ebpf
+                .expect_allow_network_until()
+                .withf(move |addr, prefix_len, port, deadline| {
+                    *addr == ip
+                        && *prefix_len == 32
+                        && port.is_none()
+                        && *deadline == expires_at + KERNEL_EXPIRY_GRACE
+                })
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_, _, _, _| Ok(()));
+        }
+        mock_ebpf.expect_remove_network().times(0);
+        let ebpf = Arc::new(Mutex::new(mock_ebpf));
+
+        // The address does not change, but its new records expire later
+        for (resolved_at, expires_at) in [
+            (now, first_expiry),
+            (now + Duration::from_secs(50), second_expiry),
+        ] {
+            apply_domain_records(
+                &dns_cache,
+                &ebpf,
+                resolved_at,
+                vec![DomainRecords {
+                    domain: "example.com".to_string(),
+                    records: vec![Entry { ip, expires_at }],
+                }],
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_apply_domain_records_restricts_ports() {
+        use crate::net::cache::Entry;
+
+        let dns_cache = Arc::new(Mutex::new(DnsCache::default()));
+        dns_cache
+            .lock()
+            .unwrap()
+            .restrict_ports("example.com", BTreeSet::from([443.into()]));
+        let now = Instant::now();
+        let old_ip: IpAddr = "93.184.216.34".parse().unwrap();
+        let new_ip: IpAddr = "93.184.216.35".parse().unwrap();
+
+        let mut mock_ebpf = MockEbpfController::new();
+        mock_ebpf
+            .expect_allow_network_until()
+            .withf(|_, prefix_len, port, _| *prefix_len == 32 && *port == Some(443.into()))
+            .times(2)
+            .returning(|_, _, _, _| Ok(()));
+        for port in [None, Some(443.into())] {
+            mock_ebpf
+                .expect_remove_network()
+                .withf(move |ip, prefix_len, removed| {
+                    *ip == old_ip && *prefix_len == 32 && *removed == port
+                })
+                .times(1)
+                .returning(|_, _, _| Ok(()));
+        }
+        let ebpf = Arc::new(Mutex::new(mock_ebpf));
+
+        for ip in [old_ip, new_ip] {
+            apply_domain_records(
+                &dns_cache,
+                &ebpf,
+                now,
+                vec![DomainRecords {
+                    domain: "example.com".to_string(),
+                    records: vec![Entry {
+                        ip,
+                        expires_at: now + Duration::from_secs(60),
+                    }],
+                }],
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_apply_denied_records_keeps_old_addresses() {
+        use crate::net::cache::Entry;
+
+        let deny_cache = Arc::new(Mutex::new(DnsCache::default()));
+        let mut mock_ebpf = MockEbpfController::new();
+        mock_ebpf
+            .expect_deny_network()
+            .withf(|ip, prefix_len| {
+                [
+                    IpAddr::from([169, 254, 169, 254]),
+                    IpAddr::from([169, 254, 0, 1]),
+                ]
+                .contains(ip)
+                    && *prefix_len == 32
+            })
+            .times(2)
+            .returning(|_, _| Ok(()));
+        mock_ebpf.expect_remove_network().times(0);
+        let ebpf = Arc::new(Mutex::new(mock_ebpf));
+
+        let now = Instant::now();
+        for ip in [[169, 254, 169, 254], [169, 254, 0, 1]] {
+            apply_denied_records(
+                &deny_cache,
+                &ebpf,
+                now,
+                vec![DomainRecords {
+                    domain: "metadata.internal".to_string(),
+                    records: vec![Entry {
+                        ip: IpAddr::from(ip),
+                        expires_at: now + Duration::from_secs(60),
+                    }],
+                }],
+            )
+            .unwrap();
+        }
+    }
+
     #[tokio::test]
     async fn test_empty_domains_returns_none() {
         let domains = vec![];
@@ -137,6 +507,7 @@ mod tests {
             allowed_dns_ips,
             shutdown_signal,
             resolver,
+            RefreshSchedule::new(RefreshMargin::default(), None),
         );
 
         assert!(result.is_none());
@@ -164,8 +535,10 @@ mod tests {
 
         let mut mock_ebpf = MockEbpfController::new();
         // eBPF operations should not be called since we terminate early
-        mock_ebpf.expect_allow_network().times(0);
+        mock_ebpf.expect_allow_nameserver().times(0);
+        mock_ebpf.expect_allow_network_until().times(0);
         mock_ebpf.expect_remove_network().times(0);
+        mock_ebpf.expect_reap_expired().times(0);
         let ebpf = Arc::new(Mutex::new(mock_ebpf));
 
         let allowed_dns_ips = Arc::new(Mutex::new(HashSet::new()));
@@ -182,6 +555,7 @@ mod tests {
             allowed_dns_ips,
             Arc::clone(&shutdown_signal),
             mock_resolver,
+            RefreshSchedule::new(RefreshMargin::default(), None),
         )
         .unwrap();
 
@@ -217,13 +591,18 @@ mod tests {
         let mut mock_ebpf = MockEbpfController::new();
         // Allow eBPF operations to succeed
         mock_ebpf
-            .expect_allow_network()
-            .returning(|_, _| Ok(()))
+            .expect_allow_network_until()
+            .returning(|_, _, _, _| Ok(()))
             .times(..);
         mock_ebpf
             .expect_remove_network()
-            .returning(|_, _| Ok(()))
+            .returning(|_, _, _| Ok(()))
             .times(..);
+        // Expired entries are reaped after every refresh
+        mock_ebpf
+            .expect_reap_expired()
+            .returning(|| Ok(0))
+            .times(1..);
         let ebpf = Arc::new(Mutex::new(mock_ebpf));
 
         let allowed_dns_ips = Arc::new(Mutex::new(HashSet::new()));
@@ -243,6 +622,7 @@ mod tests {
             allowed_dns_ips,
             Arc::clone(&shutdown_signal),
             mock_resolver,
+            RefreshSchedule::new(RefreshMargin::default(), None),
         )
         .unwrap();
 
@@ -276,7 +656,12 @@ mod tests {
             );
         }
 
-        let mock_ebpf = MockEbpfController::new();
+        let mut mock_ebpf = MockEbpfController::new();
+        // Reaping goes on when resolution fails
+        mock_ebpf
+            .expect_reap_expired()
+            .returning(|| Ok(0))
+            .times(1..);
         let ebpf = Arc::new(Mutex::new(mock_ebpf));
 
         let allowed_dns_ips = Arc::new(Mutex::new(HashSet::new()));
@@ -296,6 +681,7 @@ mod tests {
             allowed_dns_ips,
             Arc::clone(&shutdown_signal),
             mock_resolver,
+            RefreshSchedule::new(RefreshMargin::default(), None),
         )
         .unwrap();
 
@@ -1,34 +1,108 @@
-use std::{convert::TryInto, net::Ipv4Addr, os::fd::BorrowedFd};
+use std::{
+    convert::TryInto,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::BorrowedFd,
+    time::{Duration, Instant},
+};
 
 use aya::{
     Ebpf, include_bytes_aligned,
-    maps::lpm_trie::{Key, LpmTrie},
-    programs::{cgroup_sock_addr::CgroupSockAddr, links::CgroupAttachMode},
+    maps::{
+        Array, HashMap, MapData, MapError, RingBuf,
+        lpm_trie::{Key, LpmTrie},
+    },
+    programs::{
+        cgroup_skb::{CgroupSkb, CgroupSkbAttachType},
+        cgroup_sock_addr::CgroupSockAddr,
+        links::CgroupAttachMode,
+    },
+    sys::SyscallError,
 };
-
 #[cfg(test)]
 use mockall::automock;
 
-use crate::error::MoriError;
+use crate::{error::MoriError, net::PortRule, runtime::DenyErrno};
+
+#[cfg(feature = "wasm-plugin")]
+use super::plugin::{VERDICT_ALLOW, VERDICT_DENY, connection_key};
 
 pub const EBPF_ELF: &[u8] = include_bytes_aligned!(env!("MORI_BPF_ELF"));
-const PROGRAM_NAMES: &[&str] = &["mori_connect4"];
+/// connect and UDP sendmsg programs denying with the default EPERM
+const EGRESS_PROGRAMS: [&str; 4] = [
+    "mori_connect4",
+    "mori_connect6",
+    "mori_sendmsg4",
+    "mori_sendmsg6",
+];
+/// connect and UDP sendmsg programs denying with the errno in DENY_ERRNO; they
+/// call bpf_set_retval, so they only load on Linux 5.18 and later
+const EGRESS_ERRNO_PROGRAMS: [&str; 4] = [
+    "mori_connect4_errno",
+    "mori_connect6_errno",
+    "mori_sendmsg4_errno",
+    "mori_sendmsg6_errno",
+];
+
+/// Maximum number of entries in ALLOW_V4_LPM (matches mori-bpf)
+pub const ALLOW_V4_CAPACITY: usize = 1024;
+/// ALLOW_V4_LPM and ALLOW_V6_LPM value of entries the BPF programs never expire
+const NEVER_EXPIRES: u64 = 0;
+/// Program reading DNS answers into the allow lists
+const DNS_SNOOP_PROGRAM: &str = "mori_dns_snoop";
+/// Size of the SNOOP_DOMAINS keys (matches mori-bpf); longer names are not snooped
+const DNS_NAME_MAX: usize = 128;
+/// Program checking the server name of TLS ClientHellos
+const SNI_PROGRAM: &str = "mori_sni";
+/// Size of the SNI_DOMAINS keys (matches mori-bpf); longer names are never allowed
+const SNI_NAME_MAX: usize = 128;
+/// Length of the protocol that prefixes the keys of ALLOW_V4_PORT_LPM and
+/// ALLOW_V6_PORT_LPM
+const PROTOCOL_BITS: u32 = 8;
+/// Most host bits of a CIDR range given with ports; port entries are added
+/// once per address of the range
+const MAX_PORT_HOST_BITS: u8 = 8;
+/// DNS_ENDPOINTS_V4 and DNS_ENDPOINTS_V6 values (match mori-bpf)
+const DNS_NAMESERVER: u8 = 1;
+const DNS_BYPASS: u8 = 2;
+/// Port plain DNS queries go to
+const DNS_PORT: u16 = 53;
 
 /// eBPF controller abstraction for testing
 #[cfg_attr(test, automock)]
 pub trait EbpfController: Send + Sync + 'static {
-    fn allow_network(&mut self, addr: Ipv4Addr, prefix_len: u8) -> Result<(), MoriError>;
-    fn remove_network(&mut self, addr: Ipv4Addr, prefix_len: u8) -> Result<(), MoriError>;
+    fn allow_network_until(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+        expires_at: Instant,
+    ) -> Result<(), MoriError>;
+    fn remove_network(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+    ) -> Result<(), MoriError>;
+    fn deny_network(&mut self, addr: IpAddr, prefix_len: u8) -> Result<(), MoriError>;
+    fn allow_nameserver(&mut self, addr: IpAddr) -> Result<(), MoriError>;
+    fn reap_expired(&mut self) -> Result<usize, MoriError>;
 }
 
 /// Holds the loaded eBPF object. Dropping this struct detaches the programs automatically.
 pub struct NetworkEbpf {
     bpf: Ebpf,
+    /// Nameservers are only allowed on port 53 (see `enforce_dns`)
+    dns_enforced: bool,
 }
 
 impl NetworkEbpf {
-    /// Load the mori eBPF program and attach the connect4 hook to the provided cgroup fd.
-    pub fn load_and_attach(cgroup_fd: BorrowedFd<'_>) -> Result<Self, MoriError> {
+    /// Load the mori eBPF program and attach the connect and UDP sendmsg hooks to the provided cgroup fd.
+    ///
+    /// Denied connections fail with `deny_errno`.
+    pub fn load_and_attach(
+        cgroup_fd: BorrowedFd<'_>,
+        deny_errno: DenyErrno,
+    ) -> Result<Self, MoriError> {
         let mut bpf = Ebpf::load(EBPF_ELF)?;
 
         // Initialize aya-log for eBPF logging
@@ -36,7 +110,17 @@ impl NetworkEbpf {
             log::warn!("Failed to initialize eBPF logger for NetworkEbpf: {}", e);
         }
 
-        for name in PROGRAM_NAMES {
+        let names = if deny_errno == DenyErrno::Eperm {
+            EGRESS_PROGRAMS
+        } else {
+            let mut errno: Array<_, u32> = Array::try_from(bpf.map_mut("DENY_ERRNO").unwrap())?;
+            errno
+                .set(0, deny_errno.code() as u32, 0)
+                .map_err(MoriError::Map)?;
+            EGRESS_ERRNO_PROGRAMS
+        };
+
+        for name in names {
             let program = bpf
                 .program_mut(name)
                 .ok_or_else(|| MoriError::ProgramNotFound {
@@ -64,77 +148,773 @@ impl NetworkEbpf {
                 })?;
         }
 
-        Ok(Self { bpf })
+        Ok(Self {
+            bpf,
+            dns_enforced: false,
+        })
     }
 
-    /// Add a single IPv4 address or CIDR range to the allow list
+    /// Add a single IP address or CIDR range to the allow list
     ///
     /// # Arguments
-    /// - addr: Network address (e.g., 192.168.1.1 or 10.0.0.0)
-    /// - prefix_len: Prefix length (32=single IP, 24=/24, 13=/13, etc.)
+    /// - addr: Network address (e.g., 192.168.1.1, 10.0.0.0 or 2001:db8::)
+    /// - prefix_len: Prefix length (32=single IPv4, 128=single IPv6, 24=/24, etc.)
+    /// - port: Only allow these destination ports (and protocol), or every port if `None`
     ///
     /// # Behavior
-    /// - prefix_len=32: Registered as a single IP address
-    /// - prefix_len<32: Registered as a CIDR range
-    /// - Registered as 1 entry in LPM Trie (no expansion like HashMap)
-    pub fn allow_network(&mut self, addr: Ipv4Addr, prefix_len: u8) -> Result<(), MoriError> {
-        if prefix_len > 32 {
+    /// - IPv4 entries go to ALLOW_V4_LPM, IPv6 entries to ALLOW_V6_LPM, and
+    ///   entries with ports to ALLOW_V4_PORT_LPM or ALLOW_V6_PORT_LPM
+    /// - Registered as 1 entry in LPM Trie (no expansion like HashMap); port
+    ///   entries are registered once per protocol, address and aligned block
+    ///   of the port range (see `port_keys`)
+    pub fn allow_network(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+    ) -> Result<(), MoriError> {
+        self.insert_network(addr, prefix_len, port, NEVER_EXPIRES)
+    }
+
+    /// Add an IP address or CIDR range the BPF programs stop allowing at `expires_at`
+    ///
+    /// Once the deadline has passed, the connect programs treat the entry as
+    /// missing and delete it, so addresses learned from DNS do not stay allowed
+    /// if the refresh task stops updating them. Adding an entry again moves its
+    /// deadline.
+    pub fn allow_network_until(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+        expires_at: Instant,
+    ) -> Result<(), MoriError> {
+        let deadline = match boot_clock() {
+            Some(now) => {
+                let remaining = expires_at.saturating_duration_since(Instant::now());
+                (now + remaining).as_nanos() as u64
+            }
+            None => {
+                log::warn!("Failed to read CLOCK_BOOTTIME; {} will not expire", addr);
+                NEVER_EXPIRES
+            }
+        };
+        self.insert_network(addr, prefix_len, port, deadline)
+    }
+
+    /// Insert an allow list entry whose value is its CLOCK_BOOTTIME deadline in nanoseconds
+    fn insert_network(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+        deadline: u64,
+    ) -> Result<(), MoriError> {
+        let max_allowed = max_prefix_len(addr);
+        if prefix_len > max_allowed {
             return Err(MoriError::InvalidCidrPrefix {
                 addr,
                 prefix_len,
-                max_allowed: 32,
+                max_allowed,
             });
         }
 
-        let mut map: LpmTrie<_, [u8; 4], u8> =
-            LpmTrie::try_from(self.bpf.map_mut("ALLOW_V4_LPM").unwrap())?;
+        // Insert into LPM Trie
+        // flags=0 (BPF_ANY) overwrites existing entry if present (same behavior as HashMap)
+        let bpf = &mut self.bpf;
+        let network = network_address(addr, prefix_len);
+        match (network, port) {
+            (IpAddr::V4(network), None) => lpm_insert(
+                bpf,
+                "ALLOW_V4_LPM",
+                u32::from(prefix_len),
+                network.octets(),
+                deadline,
+            ),
+            (IpAddr::V6(network), None) => lpm_insert(
+                bpf,
+                "ALLOW_V6_LPM",
+                u32::from(prefix_len),
+                network.octets(),
+                deadline,
+            ),
+            (network, Some(port)) => {
+                for (prefix_len, key) in port_keys(network, prefix_len, port)? {
+                    match key {
+                        PortKey::V4(key) => {
+                            lpm_insert(bpf, "ALLOW_V4_PORT_LPM", prefix_len, key, deadline)?
+                        }
+                        PortKey::V6(key) => {
+                            lpm_insert(bpf, "ALLOW_V6_PORT_LPM", prefix_len, key, deadline)?
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
 
-        // Normalize network address (apply mask based on prefix_len)
-        let network_bits = addr.to_bits();
-        let mask = if prefix_len == 0 {
-            0
-        } else {
-            !0u32 << (32 - prefix_len)
+    /// Remove an IP address or CIDR range from the allow list
+    ///
+    /// Entries the BPF programs already deleted because they expired are ignored.
+    pub fn remove_network(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+    ) -> Result<(), MoriError> {
+        let bpf = &mut self.bpf;
+        let network = network_address(addr, prefix_len);
+        let result = match (network, port) {
+            (IpAddr::V4(network), None) => {
+                lpm_remove(bpf, "ALLOW_V4_LPM", u32::from(prefix_len), network.octets())
+            }
+            (IpAddr::V6(network), None) => {
+                lpm_remove(bpf, "ALLOW_V6_LPM", u32::from(prefix_len), network.octets())
+            }
+            (network, Some(port)) => {
+                for (prefix_len, key) in port_keys(network, prefix_len, port)? {
+                    let result = match key {
+                        PortKey::V4(key) => lpm_remove(bpf, "ALLOW_V4_PORT_LPM", prefix_len, key),
+                        PortKey::V6(key) => lpm_remove(bpf, "ALLOW_V6_PORT_LPM", prefix_len, key),
+                    };
+                    ignore_missing(result)?;
+                }
+                Ok(())
+            }
         };
-        let network_addr = network_bits & mask;
+        ignore_missing(result)
+    }
 
-        // Convert to network byte order (big-endian) byte array
-        let be_bytes = network_addr.to_be_bytes();
-        let key = Key::new(prefix_len as u32, be_bytes);
+    /// Add an IP address or CIDR range to the deny list
+    ///
+    /// Denied entries are checked before the allow lists, in DENY_V4_LPM or
+    /// DENY_V6_LPM, and stay for the whole run.
+    pub fn deny_network(&mut self, addr: IpAddr, prefix_len: u8) -> Result<(), MoriError> {
+        let max_allowed = max_prefix_len(addr);
+        if prefix_len > max_allowed {
+            return Err(MoriError::InvalidCidrPrefix {
+                addr,
+                prefix_len,
+                max_allowed,
+            });
+        }
 
-        // Insert into LPM Trie
-        // flags=0 (BPF_ANY) overwrites existing entry if present (same behavior as HashMap)
-        map.insert(&key, 1, 0).map_err(MoriError::Map)?;
+        let network = network_address(addr, prefix_len);
+        let prefix_len = u32::from(prefix_len);
+        match network {
+            IpAddr::V4(network) => {
+                let mut map: LpmTrie<_, [u8; 4], u8> =
+                    LpmTrie::try_from(self.bpf.map_mut("DENY_V4_LPM").unwrap())?;
+                map.insert(&Key::new(prefix_len, network.octets()), 1, 0)
+            }
+            IpAddr::V6(network) => {
+                let mut map: LpmTrie<_, [u8; 16], u8> =
+                    LpmTrie::try_from(self.bpf.map_mut("DENY_V6_LPM").unwrap())?;
+                map.insert(&Key::new(prefix_len, network.octets()), 1, 0)
+            }
+        }
+        .map_err(MoriError::Map)
+    }
+
+    /// Allow the addresses in DNS answers for `domains` as the command receives them
+    ///
+    /// Attaches a cgroup skb ingress program that parses UDP responses from
+    /// `servers` and adds their A and AAAA records to ALLOW_V4_LPM and
+    /// ALLOW_V6_LPM with the record's TTL, so addresses the command looks up
+    /// itself are allowed before the refresh task learns them. Answers for
+    /// any name under one of `suffixes` (`.corp.internal`) are used too; the
+    /// addresses of those names are only ever learned this way. Domains whose
+    /// wire-format name exceeds DNS_NAME_MAX bytes are left to the refresh task.
+    pub fn enable_dns_snooping(
+        &mut self,
+        cgroup_fd: BorrowedFd<'_>,
+        domains: &[String],
+        suffixes: &[String],
+        servers: &[Ipv4Addr],
+    ) -> Result<(), MoriError> {
+        {
+            let mut names: HashMap<_, [u8; DNS_NAME_MAX], u8> =
+                HashMap::try_from(self.bpf.map_mut("SNOOP_DOMAINS").unwrap())?;
+            for domain in domains {
+                match dns_wire_name(domain) {
+                    Some(name) => names.insert(name, 1, 0).map_err(MoriError::Map)?,
+                    None => log::warn!("{} is too long to read from DNS answers", domain),
+                }
+            }
+        }
+        self.insert_suffixes(suffixes)?;
+        {
+            let mut trusted: HashMap<_, [u8; 4], u8> =
+                HashMap::try_from(self.bpf.map_mut("SNOOP_SERVERS").unwrap())?;
+            for server in servers {
+                trusted
+                    .insert(server.octets(), 1, 0)
+                    .map_err(MoriError::Map)?;
+            }
+        }
 
+        // mori_inbound may be attached to the same hook
+        self.attach_skb(DNS_SNOOP_PROGRAM, cgroup_fd, CgroupSkbAttachType::Ingress)
+    }
+
+    /// Deny TLS connections whose server name is not one of `domains` or
+    /// under one of `suffixes`
+    ///
+    /// Attaches a cgroup skb egress program that drops ClientHellos naming
+    /// any other server, or none. Destinations in `exempt` (address and
+    /// prefix length) are allowed by address and not checked. Domains longer
+    /// than SNI_NAME_MAX bytes can never match.
+    pub fn enforce_sni(
+        &mut self,
+        cgroup_fd: BorrowedFd<'_>,
+        domains: &[String],
+        suffixes: &[String],
+        exempt: &[(IpAddr, u8)],
+    ) -> Result<(), MoriError> {
+        self.insert_suffixes(suffixes)?;
+        {
+            let mut names: HashMap<_, [u8; SNI_NAME_MAX], u8> =
+                HashMap::try_from(self.bpf.map_mut("SNI_DOMAINS").unwrap())?;
+            for domain in domains {
+                match sni_name(domain) {
+                    Some(name) => names.insert(name, 1, 0).map_err(MoriError::Map)?,
+                    None => log::warn!("{} is too long to match a TLS server name", domain),
+                }
+            }
+        }
+        for &(addr, prefix_len) in exempt {
+            let network = network_address(addr, prefix_len);
+            let prefix_len = u32::from(prefix_len);
+            match network {
+                IpAddr::V4(network) => {
+                    let mut map: LpmTrie<_, [u8; 4], u8> =
+                        LpmTrie::try_from(self.bpf.map_mut("SNI_EXEMPT_V4").unwrap())?;
+                    map.insert(&Key::new(prefix_len, network.octets()), 1, 0)
+                }
+                IpAddr::V6(network) => {
+                    let mut map: LpmTrie<_, [u8; 16], u8> =
+                        LpmTrie::try_from(self.bpf.map_mut("SNI_EXEMPT_V6").unwrap())?;
+                    map.insert(&Key::new(prefix_len, network.octets()), 1, 0)
+                }
+            }
+            .map_err(MoriError::Map)?;
+        }
+        self.attach_skb(SNI_PROGRAM, cgroup_fd, CgroupSkbAttachType::Egress)
+    }
+
+    /// Add domain suffixes to DOMAIN_SUFFIXES, shared by DNS snooping and
+    /// the TLS server name check
+    fn insert_suffixes(&mut self, suffixes: &[String]) -> Result<(), MoriError> {
+        let mut map: LpmTrie<_, [u8; DNS_NAME_MAX], u8> =
+            LpmTrie::try_from(self.bpf.map_mut("DOMAIN_SUFFIXES").unwrap())?;
+        for suffix in suffixes {
+            match suffix_key(suffix) {
+                Some((prefix_len, key)) => map
+                    .insert(&Key::new(prefix_len, key), 1, 0)
+                    .map_err(MoriError::Map)?,
+                None => log::warn!("{} is too long to match any name", suffix),
+            }
+        }
         Ok(())
     }
 
-    /// Remove an IPv4 address from the allow list
-    pub fn remove_network(&mut self, addr: Ipv4Addr, prefix_len: u8) -> Result<(), MoriError> {
-        let mut map: LpmTrie<_, [u8; 4], u8> =
-            LpmTrie::try_from(self.bpf.map_mut("ALLOW_V4_LPM").unwrap())?;
+    /// Load a cgroup skb program and attach it next to any others on the hook
+    fn attach_skb(
+        &mut self,
+        name: &str,
+        cgroup_fd: BorrowedFd<'_>,
+        attach_type: CgroupSkbAttachType,
+    ) -> Result<(), MoriError> {
+        let program = self
+            .bpf
+            .program_mut(name)
+            .ok_or_else(|| MoriError::ProgramNotFound {
+                name: name.to_string(),
+            })?;
+        let program: &mut CgroupSkb =
+            program
+                .try_into()
+                .map_err(|source| MoriError::ProgramPrepare {
+                    name: name.to_string(),
+                    source,
+                })?;
+        program.load().map_err(|source| MoriError::ProgramPrepare {
+            name: name.to_string(),
+            source,
+        })?;
+        program
+            .attach(cgroup_fd, attach_type, CgroupAttachMode::AllowMultiple)
+            .map_err(|source| MoriError::ProgramAttach {
+                name: name.to_string(),
+                source,
+            })?;
+        Ok(())
+    }
 
-        let network_bits = addr.to_bits();
-        let mask = if prefix_len == 0 {
-            0
-        } else {
-            !0u32 << (32 - prefix_len)
+    /// Delete the allow list entries whose deadline has passed
+    ///
+    /// The connect programs only delete expired entries they look up, so
+    /// addresses added from DNS answers that are never connected to would
+    /// otherwise stay in the maps. Returns the number of deleted entries.
+    pub fn reap_expired(&mut self) -> Result<usize, MoriError> {
+        let Some(now) = boot_clock() else {
+            return Ok(0);
         };
-        let network_addr = network_bits & mask;
-        let be_bytes = network_addr.to_be_bytes();
-        let key = Key::new(prefix_len as u32, be_bytes);
+        let now = now.as_nanos() as u64;
+        Ok(reap_lpm::<4>(&mut self.bpf, "ALLOW_V4_LPM", now)?
+            + reap_lpm::<16>(&mut self.bpf, "ALLOW_V6_LPM", now)?)
+    }
+
+    /// Let connected ICMP (ping) sockets reach any destination the deny lists do not cover
+    pub fn allow_icmp(&mut self) -> Result<(), MoriError> {
+        let mut allowed: Array<_, u8> = Array::try_from(self.bpf.map_mut("ICMP_ALLOWED").unwrap())?;
+        allowed.set(0, 1, 0).map_err(MoriError::Map)
+    }
+
+    /// Hold DNS traffic to the nameservers and block `bypass`, the known
+    /// DNS-over-HTTPS/TLS resolvers, on ports 443 and 853
+    ///
+    /// Port 53 is denied to every address but the nameservers added with
+    /// `allow_nameserver` afterwards, even where the allow lists cover it.
+    pub fn enforce_dns(&mut self, bypass: &[IpAddr]) -> Result<(), MoriError> {
+        for &addr in bypass {
+            self.insert_dns_endpoint(addr, DNS_BYPASS)?;
+        }
+        let mut enforced: Array<_, u8> =
+            Array::try_from(self.bpf.map_mut("DNS_ENFORCED").unwrap())?;
+        enforced.set(0, 1, 0).map_err(MoriError::Map)?;
+        self.dns_enforced = true;
+        Ok(())
+    }
+
+    /// Allow queries to a nameserver: on every port, or only on port 53
+    /// while DNS is enforced
+    pub fn allow_nameserver(&mut self, addr: IpAddr) -> Result<(), MoriError> {
+        // DNS servers are single IPs (/32 or /128)
+        if !self.dns_enforced {
+            return self.allow_network(addr, max_prefix_len(addr), None);
+        }
+        self.insert_dns_endpoint(addr, DNS_NAMESERVER)?;
+        self.allow_network(addr, max_prefix_len(addr), Some(PortRule::from(DNS_PORT)))
+    }
+
+    /// Record `addr` in DNS_ENDPOINTS_V4 or DNS_ENDPOINTS_V6
+    fn insert_dns_endpoint(&mut self, addr: IpAddr, value: u8) -> Result<(), MoriError> {
+        match addr {
+            IpAddr::V4(addr) => {
+                let mut map: HashMap<_, [u8; 4], u8> =
+                    HashMap::try_from(self.bpf.map_mut("DNS_ENDPOINTS_V4").unwrap())?;
+                map.insert(addr.octets(), value, 0)
+            }
+            IpAddr::V6(addr) => {
+                let mut map: HashMap<_, [u8; 16], u8> =
+                    HashMap::try_from(self.bpf.map_mut("DNS_ENDPOINTS_V6").unwrap())?;
+                map.insert(addr.octets(), value, 0)
+            }
+        }
+        .map_err(MoriError::Map)
+    }
+
+    /// Publish an event for every connection attempt
+    ///
+    /// Returns the ring buffer the BPF program writes the events to.
+    pub fn enable_connect_events(&mut self) -> Result<RingBuf<MapData>, MoriError> {
+        let mut enabled: Array<_, u8> =
+            Array::try_from(self.bpf.map_mut("FLOW_LOG_ENABLED").unwrap())?;
+        enabled.set(0, 1, 0).map_err(MoriError::Map)?;
+
+        let events = RingBuf::try_from(self.bpf.take_map("FLOW_EVENTS").unwrap())?;
+        Ok(events)
+    }
+
+    /// Let through the connections the programs would deny (see `enable_audit`)
+    pub fn enable_audit(&mut self) -> Result<(), MoriError> {
+        enable_audit(&mut self.bpf)
+    }
+
+    /// Publish an event for every TLS connection the SNI program drops
+    pub fn enable_denial_events(&mut self) -> Result<RingBuf<MapData>, MoriError> {
+        enable_denial_events(&mut self.bpf)
+    }
+}
 
-        map.remove(&key).map_err(MoriError::Map)?;
+/// Publish an event for every access the programs of `bpf` deny
+///
+/// Returns the ring buffer the BPF programs write the events to.
+pub(super) fn enable_denial_events(bpf: &mut Ebpf) -> Result<RingBuf<MapData>, MoriError> {
+    let mut enabled: Array<_, u8> = Array::try_from(bpf.map_mut("DENIAL_EVENTS_ENABLED").unwrap())?;
+    enabled.set(0, 1, 0).map_err(MoriError::Map)?;
+
+    let events = RingBuf::try_from(bpf.take_map("DENIAL_EVENTS").unwrap())?;
+    Ok(events)
+}
+
+/// Make the programs of `bpf` let through what they would deny, still
+/// reporting it, for `--audit`
+pub(super) fn enable_audit(bpf: &mut Ebpf) -> Result<(), MoriError> {
+    let mut audit: Array<_, u8> = Array::try_from(bpf.map_mut("AUDIT").unwrap())?;
+    audit.set(0, 1, 0).map_err(MoriError::Map)
+}
+
+#[cfg(feature = "wasm-plugin")]
+impl NetworkEbpf {
+    /// Route connections matching no allow rule to the policy plugin
+    ///
+    /// Returns the ring buffer on which the BPF program publishes decision requests.
+    pub fn enable_plugin(&mut self) -> Result<RingBuf<MapData>, MoriError> {
+        let mut enabled: Array<_, u8> =
+            Array::try_from(self.bpf.map_mut("PLUGIN_ENABLED").unwrap())?;
+        enabled.set(0, 1, 0).map_err(MoriError::Map)?;
+
+        let requests = RingBuf::try_from(self.bpf.take_map("PLUGIN_REQUESTS").unwrap())?;
+        Ok(requests)
+    }
+
+    /// Record the plugin verdict for connections to addr:port
+    pub fn set_plugin_verdict(
+        &mut self,
+        addr: Ipv4Addr,
+        port: u16,
+        allow: bool,
+    ) -> Result<(), MoriError> {
+        let mut map: HashMap<_, [u8; 6], u8> =
+            HashMap::try_from(self.bpf.map_mut("PLUGIN_VERDICTS").unwrap())?;
+
+        let verdict = if allow { VERDICT_ALLOW } else { VERDICT_DENY };
+        map.insert(connection_key(addr, port), verdict, 0)
+            .map_err(MoriError::Map)?;
         Ok(())
     }
 }
 
 impl EbpfController for NetworkEbpf {
-    fn allow_network(&mut self, addr: Ipv4Addr, prefix_len: u8) -> Result<(), MoriError> {
-        self.allow_network(addr, prefix_len)
+    fn allow_network_until(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+        expires_at: Instant,
+    ) -> Result<(), MoriError> {
+        self.allow_network_until(addr, prefix_len, port, expires_at)
+    }
+
+    fn remove_network(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        port: Option<PortRule>,
+    ) -> Result<(), MoriError> {
+        self.remove_network(addr, prefix_len, port)
+    }
+
+    fn deny_network(&mut self, addr: IpAddr, prefix_len: u8) -> Result<(), MoriError> {
+        self.deny_network(addr, prefix_len)
+    }
+
+    fn allow_nameserver(&mut self, addr: IpAddr) -> Result<(), MoriError> {
+        self.allow_nameserver(addr)
+    }
+
+    fn reap_expired(&mut self) -> Result<usize, MoriError> {
+        self.reap_expired()
+    }
+}
+
+/// Time since boot, including suspend (CLOCK_BOOTTIME, the BPF programs' clock)
+pub fn boot_clock() -> Option<Duration> {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut now) } != 0 {
+        return None;
+    }
+    Some(Duration::new(now.tv_sec as u64, now.tv_nsec as u32))
+}
+
+/// Longest prefix length for the address family of `addr`, which matches a single address
+pub fn max_prefix_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Insert `deadline` under the key `data`/`prefix_len` of the LPM trie `name`
+fn lpm_insert<const N: usize>(
+    bpf: &mut Ebpf,
+    name: &str,
+    prefix_len: u32,
+    data: [u8; N],
+    deadline: u64,
+) -> Result<(), MoriError> {
+    let mut map: LpmTrie<_, [u8; N], u64> = LpmTrie::try_from(bpf.map_mut(name).unwrap())?;
+    map.insert(&Key::new(prefix_len, data), deadline, 0)
+        .map_err(MoriError::Map)
+}
+
+/// Remove the key `data`/`prefix_len` from the LPM trie `name`
+fn lpm_remove<const N: usize>(
+    bpf: &mut Ebpf,
+    name: &str,
+    prefix_len: u32,
+    data: [u8; N],
+) -> Result<(), MoriError> {
+    let mut map: LpmTrie<_, [u8; N], u64> = LpmTrie::try_from(bpf.map_mut(name).unwrap())?;
+    map.remove(&Key::new(prefix_len, data))
+        .map_err(MoriError::Map)
+}
+
+/// Remove the entries of the LPM trie `name` whose deadline is before `now`
+fn reap_lpm<const N: usize>(bpf: &mut Ebpf, name: &str, now: u64) -> Result<usize, MoriError> {
+    let mut map: LpmTrie<_, [u8; N], u64> = LpmTrie::try_from(bpf.map_mut(name).unwrap())?;
+    // Collect first: deleting while iterating restarts the walk
+    let expired: Vec<Key<[u8; N]>> = map
+        .iter()
+        .filter_map(Result::ok)
+        .filter(|(_, deadline)| *deadline != NEVER_EXPIRES && *deadline < now)
+        .map(|(key, _)| key)
+        .collect();
+    for key in &expired {
+        // The BPF programs may have deleted it in the meantime
+        let _ = map.remove(key);
+    }
+    Ok(expired.len())
+}
+
+/// `domain` in DNS wire format (length-prefixed lowercase labels and a
+/// terminating zero), zero-padded to a SNOOP_DOMAINS key
+fn dns_wire_name(domain: &str) -> Option<[u8; DNS_NAME_MAX]> {
+    let mut name = [0u8; DNS_NAME_MAX];
+    let mut len = 0;
+    for label in domain.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 || len + 1 + label.len() >= DNS_NAME_MAX {
+            return None;
+        }
+        name[len] = label.len() as u8;
+        name[len + 1..len + 1 + label.len()].copy_from_slice(label.to_ascii_lowercase().as_bytes());
+        len += 1 + label.len();
     }
+    Some(name)
+}
+
+/// `domain` as a TLS server name (lowercase, without the trailing dot),
+/// zero-padded to an SNI_DOMAINS key
+fn sni_name(domain: &str) -> Option<[u8; SNI_NAME_MAX]> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    if domain.is_empty() || domain.len() >= SNI_NAME_MAX {
+        return None;
+    }
+    let mut name = [0u8; SNI_NAME_MAX];
+    name[..domain.len()].copy_from_slice(domain.as_bytes());
+    Some(name)
+}
+
+/// `suffix` as a DOMAIN_SUFFIXES key and its length in bits
+///
+/// The BPF programs look names up as lowercase text with a leading dot,
+/// reversed: "git.corp.internal" becomes "lanretni.proc.tig.". Reversed the
+/// same way, the suffix ".corp.internal" is a prefix of the key of every name
+/// under it, and of "corp.internal" itself, but not of "notcorp.internal".
+fn suffix_key(suffix: &str) -> Option<(u32, [u8; DNS_NAME_MAX])> {
+    let suffix = format!(
+        ".{}",
+        suffix
+            .trim_start_matches('.')
+            .trim_end_matches('.')
+            .to_ascii_lowercase()
+    );
+    if suffix.len() < 2 || suffix.len() >= DNS_NAME_MAX {
+        return None;
+    }
+    let mut key = [0u8; DNS_NAME_MAX];
+    for (byte, reversed) in suffix.bytes().rev().zip(key.iter_mut()) {
+        *reversed = byte;
+    }
+    Some((suffix.len() as u32 * 8, key))
+}
+
+/// Key data of a port-restricted entry in ALLOW_V4_PORT_LPM or ALLOW_V6_PORT_LPM
+enum PortKey {
+    V4([u8; 7]),
+    V6([u8; 19]),
+}
+
+/// Keys and prefix lengths covering `port` on every address of the network
+///
+/// The IP protocol number comes first and the address matches exactly, so
+/// only the port after them matches by prefix: each aligned block of the
+/// range is one entry. Ranges are expanded to single addresses, which is why
+/// they are limited to MAX_PORT_HOST_BITS host bits.
+fn port_keys(
+    network: IpAddr,
+    prefix_len: u8,
+    port: PortRule,
+) -> Result<Vec<(u32, PortKey)>, MoriError> {
+    let host_bits = max_prefix_len(network) - prefix_len;
+    if host_bits > MAX_PORT_HOST_BITS {
+        return Err(MoriError::CidrTooWideForPorts {
+            addr: network,
+            prefix_len,
+        });
+    }
+    let mut keys = Vec::new();
+    for protocol in port.protocols() {
+        for host in 0..1u32 << host_bits {
+            for (start, block_bits) in port.blocks() {
+                let key = match network {
+                    IpAddr::V4(network) => PortKey::V4(port_key(
+                        protocol.number(),
+                        &Ipv4Addr::from_bits(network.to_bits() + host).octets(),
+                        start,
+                    )),
+                    IpAddr::V6(network) => PortKey::V6(port_key(
+                        protocol.number(),
+                        &Ipv6Addr::from_bits(network.to_bits() + u128::from(host)).octets(),
+                        start,
+                    )),
+                };
+                let addr_bits = u32::from(max_prefix_len(network));
+                keys.push((PROTOCOL_BITS + addr_bits + u32::from(block_bits), key));
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Key data of a port-restricted entry: the IP protocol number, the address
+/// and the port in network byte order
+fn port_key<const N: usize>(protocol: u8, addr: &[u8], port: u16) -> [u8; N] {
+    let mut key = [0; N];
+    key[0] = protocol;
+    key[1..N - 2].copy_from_slice(addr);
+    key[N - 2..].copy_from_slice(&port.to_be_bytes());
+    key
+}
+
+/// Treat removing an entry the BPF programs already deleted as success
+fn ignore_missing(result: Result<(), MoriError>) -> Result<(), MoriError> {
+    match result {
+        Err(MoriError::Map(MapError::SyscallError(SyscallError { io_error, .. })))
+            if io_error.raw_os_error() == Some(libc::ENOENT) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Clear the host bits of `addr` beyond `prefix_len`, as LPM trie keys require
+pub(super) fn network_address(addr: IpAddr, prefix_len: u8) -> IpAddr {
+    let host_bits = u32::from(max_prefix_len(addr).saturating_sub(prefix_len));
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(host_bits).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from_bits(addr.to_bits() & mask))
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from_bits(addr.to_bits() & mask))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_address_clears_host_bits() {
+        let network = |addr: &str, prefix_len| network_address(addr.parse().unwrap(), prefix_len);
+        assert_eq!(
+            network("10.1.2.3", 8),
+            "10.0.0.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            network("192.0.2.7", 32),
+            "192.0.2.7".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            network("192.0.2.7", 0),
+            "0.0.0.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            network("2001:db8:1:2::3", 32),
+            "2001:db8::".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(network("2001:db8::1", 0), "::".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn dns_wire_name_encodes_lowercase_labels() {
+        let name = dns_wire_name("Example.COM.").unwrap();
+        assert_eq!(&name[..13], b"\x07example\x03com\x00");
+        assert!(name[13..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(dns_wire_name("a..b"), None);
+        assert_eq!(dns_wire_name(&format!("{}.com", "a".repeat(64))), None);
+        assert_eq!(dns_wire_name(&["label"; 24].join(".")), None);
+    }
+
+    #[test]
+    fn suffix_key_reverses_name_with_leading_dot() {
+        let (prefix_len, key) = suffix_key(".Corp.Internal.").unwrap();
+        assert_eq!(prefix_len, 14 * 8);
+        assert_eq!(&key[..14], b"lanretni.proc.");
+        assert!(key[14..].iter().all(|&byte| byte == 0));
+        assert_eq!(suffix_key("corp.internal"), suffix_key(".corp.internal"));
+
+        assert_eq!(suffix_key("."), None);
+        assert_eq!(suffix_key(&format!(".{}", "a".repeat(DNS_NAME_MAX))), None);
+    }
+
+    #[test]
+    fn sni_name_pads_lowercase_name() {
+        let name = sni_name("Example.COM.").unwrap();
+        assert_eq!(&name[..11], b"example.com");
+        assert!(name[11..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(sni_name("."), None);
+        assert_eq!(sni_name(&"a".repeat(SNI_NAME_MAX)), None);
+    }
+
+    #[test]
+    fn port_key_puts_port_after_protocol_and_address() {
+        assert_eq!(
+            port_key::<7>(6, &[192, 0, 2, 1], 443),
+            [6, 192, 0, 2, 1, 0x01, 0xbb]
+        );
+    }
+
+    #[test]
+    fn port_keys_cover_each_address_and_port_block() {
+        let rule = PortRule {
+            start: 8000,
+            end: 8009,
+            protocol: Some(crate::net::Protocol::Tcp),
+        };
+        let keys = port_keys("192.0.2.0".parse().unwrap(), 31, rule).unwrap();
+        let keys: Vec<(u32, [u8; 7])> = keys
+            .into_iter()
+            .map(|(prefix_len, key)| match key {
+                PortKey::V4(key) => (prefix_len, key),
+                PortKey::V6(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (53, [6, 192, 0, 2, 0, 0x1f, 0x40]),
+                (55, [6, 192, 0, 2, 0, 0x1f, 0x48]),
+                (53, [6, 192, 0, 2, 1, 0x1f, 0x40]),
+                (55, [6, 192, 0, 2, 1, 0x1f, 0x48]),
+            ]
+        );
 
-    fn remove_network(&mut self, addr: Ipv4Addr, prefix_len: u8) -> Result<(), MoriError> {
-        self.remove_network(addr, prefix_len)
+        assert!(matches!(
+            port_keys("10.0.0.0".parse().unwrap(), 16, 443.into()),
+            Err(MoriError::CidrTooWideForPorts { .. })
+        ));
     }
 }
@@ -0,0 +1,80 @@
+use std::{os::fd::BorrowedFd, os::unix::fs::MetadataExt, path::PathBuf};
+
+use aya::{
+    Btf, Ebpf,
+    maps::{Array, HashMap},
+};
+
+use super::file::{attach_program, kernel_dev, register_target_cgroup};
+use crate::{error::MoriError, policy::ProcessPolicy};
+
+/// LSM program denying executing programs outside the exec rules
+const EXEC_PROGRAM: &str = "mori_exec_check";
+/// LSM program denying mapping denied programs as executable code
+const MMAP_PROGRAM: &str = "mori_exec_mmap";
+
+/// Executable access control using eBPF LSM
+pub struct ExecEbpf {}
+
+impl ExecEbpf {
+    /// Load the exec LSM eBPF programs and attach them
+    ///
+    /// Programs are matched by inode, so the listed paths must exist when
+    /// mori starts; a listed directory covers the programs below it, even
+    /// those created later.
+    pub fn load_and_attach(
+        bpf: &mut Ebpf,
+        policy: &ProcessPolicy,
+        cgroup_fd: BorrowedFd<'_>,
+    ) -> Result<(), MoriError> {
+        let btf = Btf::from_sys_fs()?;
+        register_target_cgroup(bpf, cgroup_fd)?;
+
+        insert_programs(bpf, "EXEC_DENY_INODES", &policy.denied_exec, "deny")?;
+        if !policy.allowed_exec.is_empty() {
+            insert_programs(bpf, "EXEC_ALLOW_INODES", &policy.allowed_exec, "allow")?;
+            let mut allow_only: Array<_, u8> =
+                Array::try_from(bpf.map_mut("EXEC_ALLOW_ONLY").unwrap())?;
+            allow_only.set(0, 1, 0).map_err(MoriError::Map)?;
+            log::info!(
+                "Allowed executing only {} programs",
+                policy.allowed_exec.len()
+            );
+        }
+
+        attach_program(bpf, EXEC_PROGRAM, "bprm_check_security", &btf)?;
+        if !policy.denied_exec.is_empty() {
+            attach_program(bpf, MMAP_PROGRAM, "mmap_file", &btf)?;
+        }
+        Ok(())
+    }
+}
+
+/// Populate EXEC_DENY_INODES or EXEC_ALLOW_INODES with the inodes of `paths`
+///
+/// A missing denied program cannot be denied, and a missing allowed one
+/// cannot be run, so both are reported.
+fn insert_programs(
+    bpf: &mut Ebpf,
+    map: &str,
+    paths: &[PathBuf],
+    verb: &str,
+) -> Result<(), MoriError> {
+    let mut inodes: HashMap<_, [u64; 2], u8> = HashMap::try_from(bpf.map_mut(map).unwrap())?;
+    for path in paths {
+        match std::fs::metadata(path) {
+            Ok(metadata) => {
+                let key = [kernel_dev(metadata.dev()), metadata.ino()];
+                inodes.insert(key, 1, 0).map_err(MoriError::Map)?;
+                log::info!("Exec rule: {} {}", verb, path.display());
+            }
+            Err(e) => eprintln!(
+                "mori: cannot {} executing {} ({}); ignoring it",
+                verb,
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(())
+}
@@ -1,14 +1,86 @@
-use std::{convert::TryFrom, os::fd::BorrowedFd};
+use std::{
+    convert::TryFrom,
+    fs,
+    os::fd::BorrowedFd,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+};
 
-use aya::{Btf, Ebpf, maps::HashMap, programs::lsm::Lsm};
+use aya::{
+    Btf, Ebpf,
+    maps::{
+        Array, HashMap, MapData, PerCpuArray, RingBuf,
+        lpm_trie::{Key, LpmTrie},
+    },
+    programs::{TracePoint, lsm::Lsm},
+};
 
 use crate::{
+    audit::FileRuleHits,
     error::MoriError,
-    policy::{AccessMode, FilePolicy},
+    policy::{AccessMode, DeniedDevice, FilePolicy},
 };
 
-const PATH_MAX: usize = 512;
-const PROGRAM_NAMES: &[&str] = &["mori_path_open"];
+/// Longest path bpf_d_path resolves, null terminator included (the kernel's PATH_MAX)
+const PATH_MAX: usize = 4096;
+/// Longest path, trailing slash or null terminator included, matched as a
+/// prefix of the opened path
+const SUBTREE_PATH_MAX: usize = 256;
+/// LSM program matching denied files by path with bpf_d_path
+const PATH_PROGRAM: &str = "mori_path_open";
+/// LSM program matching denied files by inode, for kernels that reject bpf_d_path
+const INODE_PROGRAM: &str = "mori_inode_open";
+/// LSM program denying truncate(2) of files that may not be written, by path
+const TRUNCATE_PROGRAM: &str = "mori_path_truncate";
+/// LSM program denying mode, owner and size changes of files that may not be
+/// written, by inode
+const SETATTR_PROGRAM: &str = "mori_inode_setattr";
+/// The same for kernels before 6.9, whose hook has no idmap argument
+const SETATTR_LEGACY_PROGRAM: &str = "mori_inode_setattr_legacy";
+/// LSM program making hidden files fail stat(2) with ENOENT
+const GETATTR_PROGRAM: &str = "mori_path_getattr";
+/// LSM program recording the directory a getdents64 call lists
+const LISTING_PROGRAM: &str = "mori_file_permission";
+/// Tracepoints dropping hidden files from directory listings, in attach
+/// order: the exit program first, so no listing is recorded without it
+const GETDENTS_PROGRAMS: &[(&str, &str)] = &[
+    ("mori_getdents_exit", "sys_exit_getdents64"),
+    ("mori_getdents_enter", "sys_enter_getdents64"),
+];
+/// LSM programs denying extended attribute changes of files that may not be
+/// written, by inode, with the hooks they attach to
+const XATTR_PROGRAMS: &[(&str, &str)] = &[
+    ("mori_inode_setxattr", "inode_setxattr"),
+    ("mori_inode_removexattr", "inode_removexattr"),
+];
+/// Likewise for POSIX ACLs, which have hooks of their own since Linux 6.2
+const ACL_PROGRAMS: &[(&str, &str)] = &[
+    ("mori_inode_set_acl", "inode_set_acl"),
+    ("mori_inode_remove_acl", "inode_remove_acl"),
+];
+/// LSM programs denying creating, renaming and removing names in
+/// directories that may not be written, and renaming and removing files
+/// that may not be written, by inode, with the hooks they attach to
+const CREATE_PROGRAMS: &[(&str, &str)] = &[
+    ("mori_path_mknod", "path_mknod"),
+    ("mori_path_mkdir", "path_mkdir"),
+    ("mori_path_symlink", "path_symlink"),
+    ("mori_path_link", "path_link"),
+    ("mori_path_rename", "path_rename"),
+    ("mori_path_unlink", "path_unlink"),
+    ("mori_path_rmdir", "path_rmdir"),
+];
+/// LSM program publishing every open for the file inventory
+const OBSERVE_PROGRAM: &str = "mori_file_observe";
+/// LSM programs denying execution and executable mappings of files, by inode
+const BPRM_PROGRAM: &str = "mori_bprm_check";
+const MMAP_PROGRAM: &str = "mori_mmap_file";
+/// Maps of the inode programs for denied and allowed paths
+const DENY_INODES: &str = "DENY_INODES";
+const ALLOW_INODES: &str = "ALLOW_INODES";
+/// Device kinds in DENY_DEVICES keys and DENY_DEVICE_TYPES (bit flags)
+const DEVICE_BLOCK: u8 = 1;
+const DEVICE_CHAR: u8 = 2;
 
 /// File access control using eBPF LSM
 pub struct FileEbpf {}
@@ -22,21 +94,17 @@ impl FileEbpf {
     ) -> Result<(), MoriError> {
         let btf = Btf::from_sys_fs()?;
 
-        // Get cgroup ID and register it in TARGET_CGROUP map
         // Note: We use system-wide LSM attach + cgroup ID filtering because:
         // - file_open is a sleepable LSM hook
         // - BPF_LSM_CGROUP attach type only supports non-sleepable hooks
-        let cgroup_id = get_cgroup_id(cgroup_fd)?;
-        let mut target_cgroup: HashMap<_, u64, u8> =
-            HashMap::try_from(bpf.map_mut("TARGET_CGROUP").unwrap())?;
-        target_cgroup.insert(cgroup_id, 1, 0)?;
-        log::info!("Target cgroup ID: {}", cgroup_id);
+        register_target_cgroup(bpf, cgroup_fd)?;
 
         // Populate DENY_PATHS map (deny-list mode)
-        let mut deny_paths: HashMap<_, [u8; PATH_MAX], u8> =
+        let mut deny_paths: HashMap<_, u64, u8> =
             HashMap::try_from(bpf.map_mut("DENY_PATHS").unwrap())?;
 
-        for (path, mode) in &policy.denied_paths {
+        let denied = with_parent_modes(&policy.denied_paths);
+        for (path, mode) in &denied {
             let path_str = path.to_string_lossy();
             let path_bytes = path_str.as_bytes();
 
@@ -47,73 +115,524 @@ impl FileEbpf {
                 });
             }
 
-            let mut key = [0u8; PATH_MAX];
-            // Copy path bytes including null terminator to match bpf_d_path output
-            key[..path_bytes.len()].copy_from_slice(path_bytes);
-            // bpf_d_path includes null terminator, so we explicitly set it
-            if path_bytes.len() < PATH_MAX {
-                key[path_bytes.len()] = 0;
-            }
-
-            let mode_value = *mode as u8;
             deny_paths
-                .insert(key, mode_value, 0)
+                .insert(path_hash(path_bytes), *mode, 0)
                 .map_err(MoriError::Map)?;
 
             log::info!(
                 "Denied file access: {} (mode: {})",
                 path_str,
-                match mode {
-                    AccessMode::Read => "READ",
-                    AccessMode::Write => "WRITE",
-                    AccessMode::ReadWrite => "READ|WRITE",
-                },
+                mode_names(*mode),
+            );
+        }
+
+        // A denied directory covers everything below it
+        let mut deny_subtrees: LpmTrie<_, [u8; SUBTREE_PATH_MAX], u8> =
+            LpmTrie::try_from(bpf.map_mut("DENY_SUBTREES").unwrap())?;
+        for (path, mode) in &denied {
+            match subtree_key(path) {
+                Some((prefix_len, data)) => deny_subtrees
+                    .insert(&Key::new(prefix_len, data), *mode, 0)
+                    .map_err(MoriError::Map)?,
+                None => log::warn!(
+                    "{} is too long to deny the files below it; only the path itself is denied",
+                    path.display()
+                ),
+            }
+        }
+
+        // In allow-list mode everything else is denied; allowed paths are
+        // keyed as files and as directories
+        let allowed = with_parent_modes(&policy.allowed_entries());
+        if !allowed.is_empty() {
+            let mut allow_subtrees: LpmTrie<_, [u8; SUBTREE_PATH_MAX], u8> =
+                LpmTrie::try_from(bpf.map_mut("ALLOW_SUBTREES").unwrap())?;
+            for (path, mode) in &allowed {
+                let (Some(exact), Some(subtree)) = (exact_key(path), subtree_key(path)) else {
+                    log::warn!("{} is too long to allow; it stays denied", path.display());
+                    continue;
+                };
+                for (prefix_len, data) in [exact, subtree] {
+                    allow_subtrees
+                        .insert(&Key::new(prefix_len, data), *mode, 0)
+                        .map_err(MoriError::Map)?;
+                }
+            }
+            let mut allow_only: Array<_, u8> =
+                Array::try_from(bpf.map_mut("FILE_ALLOW_ONLY").unwrap())?;
+            allow_only.set(0, 1, 0).map_err(MoriError::Map)?;
+            log::info!(
+                "Allowed file access only below {} paths",
+                policy.allowed_paths.len()
+            );
+        }
+
+        insert_devices(bpf, &policy.denied_devices)?;
+        if policy.private_proc {
+            let mut private_proc: Array<_, u8> =
+                Array::try_from(bpf.map_mut("FILE_PRIVATE_PROC").unwrap())?;
+            private_proc.set(0, 1, 0).map_err(MoriError::Map)?;
+            log::info!("Denied the /proc entries of processes outside the sandbox");
+        }
+
+        // bpf_d_path is only allowed from some hooks and missing on older
+        // kernels; when the verifier rejects the path program, match by inode
+        let by_path = match attach_program(bpf, PATH_PROGRAM, "file_open", &btf) {
+            Ok(()) => true,
+            Err(MoriError::ProgramPrepare { name, source }) => {
+                log::warn!(
+                    "Failed to load {} ({}); matching files by inode instead",
+                    name,
+                    source
+                );
+                false
+            }
+            Err(e) => return Err(e),
+        };
+
+        // The inode maps also serve the hooks without bpf_d_path, and let the
+        // path program deny hard links to a denied file under other names
+        insert_inodes(bpf, DENY_INODES, &denied, !by_path)?;
+        insert_inodes(bpf, ALLOW_INODES, &allowed, false)?;
+        if by_path {
+            attach_program(bpf, TRUNCATE_PROGRAM, "path_truncate", &btf)?;
+        } else {
+            attach_program(bpf, INODE_PROGRAM, "file_open", &btf)?;
+        }
+
+        if policy.hide_denied {
+            if by_path {
+                let mut hide: Array<_, u8> =
+                    Array::try_from(bpf.map_mut("FILE_HIDE_DENIED").unwrap())?;
+                hide.set(0, 1, 0).map_err(MoriError::Map)?;
+                attach_program(bpf, GETATTR_PROGRAM, "inode_getattr", &btf)?;
+                // Listings are rewritten with bpf_probe_write_user, which
+                // kernel lockdown forbids
+                let listing = attach_program(bpf, LISTING_PROGRAM, "file_permission", &btf)
+                    .and_then(|_| {
+                        GETDENTS_PROGRAMS
+                            .iter()
+                            .try_for_each(|(name, event)| attach_tracepoint(bpf, name, event))
+                    });
+                if let Err(e) = listing {
+                    log::warn!("{}; hidden paths still show up in directory listings", e);
+                }
+            } else {
+                log::warn!("Hiding denied paths needs bpf_d_path; they are denied but visible");
+            }
+        }
+
+        // Linux 6.9 added the idmap argument to the hook; the verifier rejects
+        // the program written for the other argument list
+        let setattr = match attach_program(bpf, SETATTR_PROGRAM, "inode_setattr", &btf) {
+            Err(MoriError::ProgramPrepare { .. }) => {
+                attach_program(bpf, SETATTR_LEGACY_PROGRAM, "inode_setattr", &btf)
+            }
+            result => result,
+        };
+        if let Err(e) = setattr {
+            log::warn!(
+                "{}; chmod, chown and truncate of write-protected files are not denied",
+                e
             );
         }
 
-        // Attach LSM programs using standard LSM attach (not cgroup-based)
-        let mut links = Vec::new();
-        for name in PROGRAM_NAMES {
-            let program = bpf
-                .program_mut(name)
-                .ok_or_else(|| MoriError::ProgramNotFound {
-                    name: name.to_string(),
-                })?;
-
-            let program: &mut Lsm =
-                program
-                    .try_into()
-                    .map_err(|source| MoriError::ProgramPrepare {
-                        name: name.to_string(),
-                        source,
-                    })?;
-
-            program
-                .load("file_open", &btf)
-                .map_err(|source| MoriError::ProgramPrepare {
-                    name: name.to_string(),
-                    source,
-                })?;
-
-            let link = program
-                .attach()
-                .map_err(|source| MoriError::ProgramAttach {
-                    name: name.to_string(),
-                    source,
-                })?;
-
-            links.push(link);
-            log::info!("Attached LSM program: {}", name);
+        // The xattr hooks take the mount's idmap (a user namespace before
+        // Linux 6.3) first since Linux 5.12
+        for (name, hook) in XATTR_PROGRAMS {
+            if let Err(e) = attach_program(bpf, name, hook, &btf) {
+                log::warn!(
+                    "{}; extended attributes of write-protected files may be changed",
+                    e
+                );
+                break;
+            }
+        }
+        // Older kernels change ACLs through the xattr hooks
+        for (name, hook) in ACL_PROGRAMS {
+            if let Err(e) = attach_program(bpf, name, hook, &btf) {
+                log::info!("{}; ACLs are covered by the xattr programs", e);
+                break;
+            }
+        }
+
+        // The path hooks need a kernel built with CONFIG_SECURITY_PATH
+        for (name, hook) in CREATE_PROGRAMS {
+            if let Err(e) = attach_program(bpf, name, hook, &btf) {
+                log::warn!(
+                    "{}; files may be created, renamed and deleted in write-protected directories",
+                    e
+                );
+                break;
+            }
         }
 
+        let execute = AccessMode::Execute as u8;
+        if denied.iter().any(|(_, mode)| mode & execute != 0) {
+            attach_program(bpf, BPRM_PROGRAM, "bprm_check_security", &btf)?;
+            attach_program(bpf, MMAP_PROGRAM, "mmap_file", &btf)?;
+        }
         Ok(())
     }
 }
 
+/// Count the opens of the paths each deny rule covers
+///
+/// The rules are the entries `load_and_attach` denies, numbered in the same
+/// order; the path program counts an open against the path itself when it is
+/// listed, otherwise against the closest listed directory above it. The
+/// inode program counts nothing.
+pub(super) fn count_rule_hits(
+    bpf: &mut Ebpf,
+    policy: &FilePolicy,
+) -> Result<RuleCounters, MoriError> {
+    let denied = with_parent_modes(&policy.denied_paths);
+
+    let mut path_rules: HashMap<_, u64, u32> =
+        HashMap::try_from(bpf.map_mut("DENY_PATH_RULES").unwrap())?;
+    for (index, (path, _)) in denied.iter().enumerate() {
+        let hash = path_hash(path.to_string_lossy().as_bytes());
+        path_rules
+            .insert(hash, index as u32, 0)
+            .map_err(MoriError::Map)?;
+    }
+    let mut subtree_rules: LpmTrie<_, [u8; SUBTREE_PATH_MAX], u32> =
+        LpmTrie::try_from(bpf.map_mut("DENY_SUBTREE_RULES").unwrap())?;
+    for (index, (path, _)) in denied.iter().enumerate() {
+        if let Some((prefix_len, data)) = subtree_key(path) {
+            subtree_rules
+                .insert(&Key::new(prefix_len, data), index as u32, 0)
+                .map_err(MoriError::Map)?;
+        }
+    }
+
+    let mut enabled: Array<_, u8> = Array::try_from(bpf.map_mut("FILE_RULE_STATS").unwrap())?;
+    enabled.set(0, 1, 0).map_err(MoriError::Map)?;
+
+    Ok(RuleCounters {
+        hits: PerCpuArray::try_from(bpf.take_map("FILE_RULE_HITS").unwrap())?,
+        rules: denied.into_iter().map(|(path, _)| path).collect(),
+    })
+}
+
+/// FILE_RULE_HITS with the deny rules it counts the opens of
+pub(super) struct RuleCounters {
+    hits: PerCpuArray<MapData, [u64; 2]>,
+    rules: Vec<PathBuf>,
+}
+
+impl RuleCounters {
+    /// Opens counted so far per rule, summed over the CPUs
+    pub(super) fn read(&self) -> Vec<FileRuleHits> {
+        self.rules
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let (allowed, denied) = self
+                    .hits
+                    .get(&(index as u32), 0)
+                    .map(|values| {
+                        values.iter().fold((0, 0), |(allowed, denied), [a, d]| {
+                            (allowed + a, denied + d)
+                        })
+                    })
+                    .unwrap_or_default();
+                FileRuleHits {
+                    path: path.clone(),
+                    allowed,
+                    denied,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Publish an event for every file the command opens, denying nothing
+///
+/// Returns the ring buffer the BPF program writes the events to. Paths are
+/// resolved with bpf_d_path, so kernels rejecting it cannot record them.
+pub(super) fn observe_opens(
+    bpf: &mut Ebpf,
+    cgroup_fd: BorrowedFd<'_>,
+) -> Result<RingBuf<MapData>, MoriError> {
+    let btf = Btf::from_sys_fs()?;
+    register_target_cgroup(bpf, cgroup_fd)?;
+    attach_program(bpf, OBSERVE_PROGRAM, "file_open", &btf)?;
+
+    let events = RingBuf::try_from(bpf.take_map("OPEN_EVENTS").unwrap())?;
+    Ok(events)
+}
+
+/// Names of the access modes set in `mode`, such as "READ|WRITE"
+fn mode_names(mode: u8) -> String {
+    [
+        (AccessMode::Read, "READ"),
+        (AccessMode::Write, "WRITE"),
+        (AccessMode::Execute, "EXECUTE"),
+    ]
+    .iter()
+    .filter(|(access, _)| mode & *access as u8 != 0)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>()
+    .join("|")
+}
+
+/// Check whether the BPF LSM is active; None when securityfs does not say
+///
+/// Without `bpf` in the kernel's LSM list, LSM programs load and attach but
+/// never run.
+pub(super) fn bpf_lsm_active() -> Option<bool> {
+    fs::read_to_string("/sys/kernel/security/lsm")
+        .ok()
+        .map(|lsms| lists_bpf(&lsms))
+}
+
+fn lists_bpf(lsms: &str) -> bool {
+    lsms.trim().split(',').any(|lsm| lsm == "bpf")
+}
+
+/// Register the cgroup in TARGET_CGROUP, which the LSM programs filter events by
+pub(super) fn register_target_cgroup(
+    bpf: &mut Ebpf,
+    cgroup_fd: BorrowedFd<'_>,
+) -> Result<(), MoriError> {
+    let cgroup_id = get_cgroup_id(cgroup_fd)?;
+    let mut target_cgroup: HashMap<_, u64, u8> =
+        HashMap::try_from(bpf.map_mut("TARGET_CGROUP").unwrap())?;
+    target_cgroup.insert(cgroup_id, 1, 0)?;
+    log::info!("Target cgroup ID: {}", cgroup_id);
+    Ok(())
+}
+
+/// Load an LSM program on `hook` and attach it (standard LSM attach, not cgroup-based)
+pub(super) fn attach_program(
+    bpf: &mut Ebpf,
+    name: &str,
+    hook: &str,
+    btf: &Btf,
+) -> Result<(), MoriError> {
+    let program = bpf
+        .program_mut(name)
+        .ok_or_else(|| MoriError::ProgramNotFound {
+            name: name.to_string(),
+        })?;
+
+    let program: &mut Lsm = program
+        .try_into()
+        .map_err(|source| MoriError::ProgramPrepare {
+            name: name.to_string(),
+            source,
+        })?;
+
+    program
+        .load(hook, btf)
+        .map_err(|source| MoriError::ProgramPrepare {
+            name: name.to_string(),
+            source,
+        })?;
+
+    program
+        .attach()
+        .map_err(|source| MoriError::ProgramAttach {
+            name: name.to_string(),
+            source,
+        })?;
+
+    log::info!("Attached LSM program: {}", name);
+    Ok(())
+}
+
+/// Load the tracepoint program `name` and attach it to the syscall event `event`
+fn attach_tracepoint(bpf: &mut Ebpf, name: &str, event: &str) -> Result<(), MoriError> {
+    let program: &mut TracePoint = bpf
+        .program_mut(name)
+        .ok_or_else(|| MoriError::ProgramNotFound {
+            name: name.to_string(),
+        })?
+        .try_into()
+        .map_err(|source| MoriError::ProgramPrepare {
+            name: name.to_string(),
+            source,
+        })?;
+    program.load().map_err(|source| MoriError::ProgramPrepare {
+        name: name.to_string(),
+        source,
+    })?;
+    program
+        .attach("syscalls", event)
+        .map_err(|source| MoriError::ProgramAttach {
+            name: name.to_string(),
+            source,
+        })?;
+
+    log::info!("Attached tracepoint program: {}", name);
+    Ok(())
+}
+
+/// Paths with their access modes, including the modes of the listed
+/// directories above them
+///
+/// The eBPF programs only look up the closest listed directory of a file, so
+/// it has to carry the modes of the ones above it. A path listed twice is
+/// merged into one entry.
+fn with_parent_modes(paths: &[(PathBuf, AccessMode)]) -> Vec<(PathBuf, u8)> {
+    let mut merged: Vec<(PathBuf, u8)> = Vec::new();
+    for (path, _) in paths {
+        if merged.iter().any(|(known, _)| known == path) {
+            continue;
+        }
+        let mode = paths
+            .iter()
+            .filter(|(above, _)| path.starts_with(above))
+            .fold(0, |mode, (_, above_mode)| mode | *above_mode as u8);
+        merged.push((path.clone(), mode));
+    }
+    merged
+}
+
+/// Build the LPM trie key matching everything below a directory: its path
+/// with a trailing slash, and the prefix length in bits covering it
+fn subtree_key(path: &Path) -> Option<(u32, [u8; SUBTREE_PATH_MAX])> {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    if bytes.last() == Some(&b'/') {
+        prefix_key(bytes, &[])
+    } else {
+        prefix_key(bytes, b"/")
+    }
+}
+
+/// Build the LPM trie key matching exactly a path, null terminator included
+/// like bpf_d_path output
+fn exact_key(path: &Path) -> Option<(u32, [u8; SUBTREE_PATH_MAX])> {
+    prefix_key(path.as_os_str().as_encoded_bytes(), b"\0")
+}
+
+/// Build an LPM trie key from `bytes` followed by `end`
+fn prefix_key(bytes: &[u8], end: &[u8]) -> Option<(u32, [u8; SUBTREE_PATH_MAX])> {
+    let bytes = [bytes, end].concat();
+    if bytes.len() > SUBTREE_PATH_MAX {
+        return None;
+    }
+    let mut data = [0u8; SUBTREE_PATH_MAX];
+    data[..bytes.len()].copy_from_slice(&bytes);
+    Some(((bytes.len() * 8) as u32, data))
+}
+
+/// Populate DENY_INODES or ALLOW_INODES with the inodes of the listed paths
+///
+/// Only files existing now can be matched this way; a file created or
+/// replaced later gets a new inode. Files below a listed directory are
+/// matched through their parent directories by the eBPF programs. Paths that
+/// cannot be found are reported when `report_missing` is set, or when they
+/// are denied execution, which is only ever matched by inode; missing
+/// allowed paths, such as /lib32, simply stay denied.
+fn insert_inodes(
+    bpf: &mut Ebpf,
+    map: &str,
+    paths: &[(PathBuf, u8)],
+    report_missing: bool,
+) -> Result<(), MoriError> {
+    let mut inodes: HashMap<_, [u64; 2], u8> = HashMap::try_from(bpf.map_mut(map).unwrap())?;
+
+    for (path, mode) in paths {
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) if !report_missing && mode & AccessMode::Execute as u8 == 0 => continue,
+            Err(e) => {
+                eprintln!(
+                    "mori: cannot deny {} by inode ({}); access to it is not restricted",
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        let key = [kernel_dev(metadata.dev()), metadata.ino()];
+        inodes.insert(key, *mode, 0).map_err(MoriError::Map)?;
+    }
+    Ok(())
+}
+
+/// Populate DENY_DEVICE_TYPES and DENY_DEVICES with the denied devices
+///
+/// Nodes are keyed by device kind and number, so that another node for the
+/// same device (made with mknod, or bind-mounted elsewhere) is denied too.
+fn insert_devices(bpf: &mut Ebpf, devices: &[DeniedDevice]) -> Result<(), MoriError> {
+    let types = devices.iter().fold(0, |types, device| match device {
+        DeniedDevice::Block => types | DEVICE_BLOCK,
+        DeniedDevice::Char => types | DEVICE_CHAR,
+        DeniedDevice::Node(_) => types,
+    });
+    if types != 0 {
+        let mut deny_types: Array<_, u8> =
+            Array::try_from(bpf.map_mut("DENY_DEVICE_TYPES").unwrap())?;
+        deny_types.set(0, types, 0).map_err(MoriError::Map)?;
+    }
+
+    let mut deny_devices: HashMap<_, [u64; 2], u8> =
+        HashMap::try_from(bpf.map_mut("DENY_DEVICES").unwrap())?;
+    for device in devices {
+        for path in device_nodes(device) {
+            let (kind, rdev) = match std::fs::metadata(&path) {
+                Ok(metadata) if metadata.file_type().is_block_device() => {
+                    (DEVICE_BLOCK, metadata.rdev())
+                }
+                Ok(metadata) if metadata.file_type().is_char_device() => {
+                    (DEVICE_CHAR, metadata.rdev())
+                }
+                Ok(_) => {
+                    log::warn!("{} is not a device node; ignoring it", path.display());
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Cannot deny device {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let key = [u64::from(kind), kernel_dev(rdev)];
+            deny_devices.insert(key, 1, 0).map_err(MoriError::Map)?;
+            log::info!("Denied opening device {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Device nodes a denied device names: the node itself, or the nodes in
+/// the pattern's directory whose names start with its prefix
+fn device_nodes(device: &DeniedDevice) -> Vec<PathBuf> {
+    let DeniedDevice::Node(path) = device else {
+        return Vec::new();
+    };
+    let Some((dir, prefix)) = device.node_prefix() else {
+        return vec![path.clone()];
+    };
+    let mut nodes: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+        .map(|entry| entry.path())
+        .collect();
+    nodes.sort();
+    nodes
+}
+
+/// Key of a path in DENY_PATHS: the 64-bit FNV-1a hash of its bytes, which
+/// the eBPF program computes the same way from the output of bpf_d_path
+fn path_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Convert a device number from stat(2) to the kernel's internal encoding
+/// (`super_block.s_dev`: 12-bit major, 20-bit minor)
+pub(super) fn kernel_dev(dev: u64) -> u64 {
+    (u64::from(libc::major(dev)) << 20) | u64::from(libc::minor(dev))
+}
+
 /// Get cgroup ID from cgroup file descriptor using fstat
 fn get_cgroup_id(cgroup_fd: BorrowedFd<'_>) -> Result<u64, MoriError> {
-    use std::os::unix::fs::MetadataExt;
-
     // Use fstat to get file metadata directly from fd
     // The inode number of the cgroup directory is the cgroup ID
     let metadata = std::fs::File::from(cgroup_fd.try_clone_to_owned()?).metadata()?;
@@ -121,3 +640,99 @@ fn get_cgroup_id(cgroup_fd: BorrowedFd<'_>) -> Result<u64, MoriError> {
 
     Ok(cgroup_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_dev_uses_internal_encoding() {
+        assert_eq!(kernel_dev(libc::makedev(8, 1)), (8 << 20) | 1);
+        assert_eq!(kernel_dev(libc::makedev(259, 300)), (259 << 20) | 300);
+        assert_eq!(kernel_dev(libc::makedev(0, 45)), 45);
+    }
+
+    #[test]
+    fn device_nodes_expands_trailing_star() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["sda", "sda1", "sdb", "nvme0n1"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let pattern = DeniedDevice::Node(dir.path().join("sd*"));
+        assert_eq!(
+            device_nodes(&pattern),
+            ["sda", "sda1", "sdb"].map(|name| dir.path().join(name))
+        );
+        let node = DeniedDevice::Node(PathBuf::from("/dev/kmsg"));
+        assert_eq!(device_nodes(&node), vec![PathBuf::from("/dev/kmsg")]);
+        assert!(device_nodes(&DeniedDevice::Block).is_empty());
+    }
+
+    #[test]
+    fn parent_modes_carry_over_to_paths_below() {
+        let mut policy = FilePolicy::new();
+        policy.deny_read("/home/user");
+        policy.deny_write("/home/user/.ssh");
+        policy.deny_write("/home/user");
+        policy.deny_write("/home/username");
+
+        assert_eq!(
+            with_parent_modes(&policy.denied_paths),
+            vec![
+                (PathBuf::from("/home/user"), AccessMode::ReadWrite as u8),
+                (
+                    PathBuf::from("/home/user/.ssh"),
+                    AccessMode::ReadWrite as u8
+                ),
+                (PathBuf::from("/home/username"), AccessMode::Write as u8),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_bpf_finds_bpf_among_the_lsms() {
+        assert!(lists_bpf(
+            "lockdown,capability,landlock,yama,apparmor,bpf\n"
+        ));
+        assert!(!lists_bpf("lockdown,capability,landlock,yama,apparmor\n"));
+    }
+
+    #[test]
+    fn path_hash_is_fnv1a() {
+        assert_eq!(path_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(path_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(path_hash(b"/etc/shadow"), path_hash(b"/etc/shadow2"));
+    }
+
+    #[test]
+    fn mode_names_lists_every_mode() {
+        assert_eq!(mode_names(AccessMode::Read as u8), "READ");
+        assert_eq!(mode_names(AccessMode::ReadWrite as u8), "READ|WRITE");
+        assert_eq!(
+            mode_names(AccessMode::Write as u8 | AccessMode::Execute as u8),
+            "WRITE|EXECUTE"
+        );
+    }
+
+    #[test]
+    fn subtree_key_ends_with_slash() {
+        let (prefix_len, data) = subtree_key(Path::new("/home/user/.ssh")).unwrap();
+        assert_eq!(prefix_len, 16 * 8);
+        assert_eq!(&data[..17], b"/home/user/.ssh/\0");
+
+        let (prefix_len, data) = subtree_key(Path::new("/")).unwrap();
+        assert_eq!(prefix_len, 8);
+        assert_eq!(&data[..2], b"/\0");
+
+        let long = format!("/{}", "a".repeat(SUBTREE_PATH_MAX - 1));
+        assert!(subtree_key(Path::new(&long)).is_none());
+    }
+
+    #[test]
+    fn exact_key_ends_with_null() {
+        let (prefix_len, data) = exact_key(Path::new("/etc/hosts")).unwrap();
+        assert_eq!(prefix_len, 11 * 8);
+        assert_eq!(&data[..12], b"/etc/hosts\0\0");
+    }
+}
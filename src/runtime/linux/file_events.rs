@@ -0,0 +1,374 @@
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use aya::maps::{MapData, RingBuf};
+use tokio::io::unix::AsyncFd;
+
+use crate::{
+    audit::{FileAccess, FileInventory, Operation, ViolationEvent, ViolationSender},
+    policy::{AccessMode, SocketFamily, net::UNIX_NAME_MAX},
+};
+
+use super::{flow::parse_comm, sync::ShutdownSignal};
+
+/// Access mode bits of a record (ACCESS_MODE_* in mori-bpf)
+const ACCESS_MODE_READ: u8 = 1;
+const ACCESS_MODE_WRITE: u8 = 2;
+const ACCESS_MODE_EXECUTE: u8 = 4;
+
+/// What a DENIAL_EVENTS record reports (DENIAL_* in mori-bpf)
+const DENIAL_PATH: u8 = 0;
+const DENIAL_NAME: u8 = 1;
+const DENIAL_BIND: u8 = 2;
+const DENIAL_SOCKET: u8 = 3;
+const DENIAL_SNI: u8 = 4;
+const DENIAL_INBOUND: u8 = 5;
+const DENIAL_UNIX: u8 = 6;
+
+/// Socket families and ICMP protocols of DENIAL_SOCKET records
+const AF_INET: i32 = 2;
+const AF_INET6: i32 = 10;
+const IPPROTO_ICMP: i32 = 1;
+const IPPROTO_ICMPV6: i32 = 58;
+
+/// Size of the fields of a DENIAL_EVENTS or OPEN_EVENTS record before the
+/// path or other detail
+const HEADER_LEN: usize = 24;
+
+/// A file event as published by the BPF programs
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RawFileEvent {
+    pid: u32,
+    /// Denied (DENIAL_EVENTS) or requested (OPEN_EVENTS) ACCESS_MODE_* bits
+    mode: u8,
+    /// DENIAL_* kind of a DENIAL_EVENTS record
+    kind: u8,
+    comm: String,
+    /// The detail as a NUL-terminated string: the path or file name of file
+    /// records, the server name of DENIAL_SNI records; None when empty
+    path: Option<String>,
+}
+
+impl RawFileEvent {
+    /// The violation a file record of DENIAL_EVENTS reports; a denied read
+    /// is reported as such even when writing was denied too
+    fn file_operation(&self) -> Operation {
+        if self.mode & ACCESS_MODE_READ != 0 {
+            Operation::FileRead
+        } else if self.mode & ACCESS_MODE_EXECUTE != 0 {
+            Operation::Exec
+        } else {
+            Operation::FileWrite
+        }
+    }
+
+    /// Access requested by an OPEN_EVENTS record
+    fn access(&self) -> AccessMode {
+        match self.mode & (ACCESS_MODE_READ | ACCESS_MODE_WRITE) {
+            ACCESS_MODE_WRITE => AccessMode::Write,
+            ACCESS_MODE_READ => AccessMode::Read,
+            0 => AccessMode::Read,
+            _ => AccessMode::ReadWrite,
+        }
+    }
+}
+
+fn parse_record(record: &[u8]) -> Option<RawFileEvent> {
+    let header: &[u8; HEADER_LEN] = record.get(..HEADER_LEN)?.try_into().ok()?;
+    let path = &record[HEADER_LEN..];
+    let path_len = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+    Some(RawFileEvent {
+        pid: u32::from_ne_bytes(header[0..4].try_into().ok()?),
+        mode: header[4],
+        kind: header[5],
+        comm: parse_comm(&header[8..24]),
+        path: (path_len > 0).then(|| String::from_utf8_lossy(&path[..path_len]).into_owned()),
+    })
+}
+
+/// The violation a DENIAL_EVENTS record reports (detail layouts documented
+/// at DENIAL_EVENTS in mori-bpf)
+fn parse_denial(record: &[u8]) -> Option<ViolationEvent> {
+    let event = parse_record(record)?;
+    let detail = &record[HEADER_LEN..];
+    let (operation, target) = match event.kind {
+        DENIAL_PATH => (
+            event.file_operation(),
+            event
+                .path
+                .unwrap_or_else(|| "(unresolved path)".to_string()),
+        ),
+        DENIAL_NAME => (
+            event.file_operation(),
+            event
+                .path
+                .unwrap_or_else(|| "(unresolved name)".to_string()),
+        ),
+        DENIAL_BIND => {
+            let port = u16::from_be_bytes(detail.get(..2)?.try_into().ok()?);
+            (Operation::Bind, port.to_string())
+        }
+        DENIAL_SOCKET => {
+            let family = i32::from_ne_bytes(detail.get(..4)?.try_into().ok()?);
+            let protocol = i32::from_ne_bytes(detail.get(4..8)?.try_into().ok()?);
+            (Operation::Socket, socket_name(family, protocol))
+        }
+        DENIAL_SNI => (
+            Operation::Tls,
+            event.path.unwrap_or_else(|| "(no server name)".to_string()),
+        ),
+        DENIAL_INBOUND => {
+            let peer: [u8; 16] = detail.get(..16)?.try_into().ok()?;
+            let peer = IpAddr::V6(Ipv6Addr::from(peer)).to_canonical();
+            let port = u16::from_be_bytes(detail.get(16..18)?.try_into().ok()?);
+            (Operation::Accept, format!("{} to port {}", peer, port))
+        }
+        DENIAL_UNIX => {
+            // Names are zero-padded, but may hold NUL bytes themselves, so
+            // only the padding is dropped
+            let name = detail.get(..UNIX_NAME_MAX)?;
+            let len = name
+                .iter()
+                .rposition(|&b| b != 0)
+                .map_or(0, |last| last + 1);
+            (
+                Operation::Unix,
+                format!("@{}", String::from_utf8_lossy(&name[..len])),
+            )
+        }
+        _ => return None,
+    };
+    Some(ViolationEvent {
+        operation,
+        target,
+        pid: event.pid,
+        comm: event.comm,
+    })
+}
+
+/// Configuration name of a socket family, `icmp` for ICMP sockets
+fn socket_name(family: i32, protocol: i32) -> String {
+    if (family == AF_INET && protocol == IPPROTO_ICMP)
+        || (family == AF_INET6 && protocol == IPPROTO_ICMPV6)
+    {
+        return "icmp".to_string();
+    }
+    SocketFamily::ALL
+        .into_iter()
+        .find(|known| known.number() as i32 == family)
+        .map_or_else(
+            || format!("family {}", family),
+            |known| known.name().to_string(),
+        )
+}
+
+/// Forward the accesses the eBPF programs denied to `sender` until shutdown
+/// is signaled
+pub fn spawn_denial_reader(
+    events: RingBuf<MapData>,
+    sender: ViolationSender,
+    shutdown_signal: Arc<ShutdownSignal>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        read_events(events, &shutdown_signal, "denial", parse_denial, |event| {
+            let _ = sender.send(event);
+        })
+        .await;
+    })
+}
+
+/// Aggregate every file the command opens into an inventory until shutdown
+/// is signaled
+pub fn spawn_open_recorder(
+    events: RingBuf<MapData>,
+    shutdown_signal: Arc<ShutdownSignal>,
+) -> tokio::task::JoinHandle<Vec<FileAccess>> {
+    tokio::spawn(async move {
+        let mut inventory = FileInventory::default();
+        read_events(
+            events,
+            &shutdown_signal,
+            "file open",
+            parse_record,
+            |event| {
+                if let Some(path) = &event.path {
+                    inventory.record(Path::new(path), event.access(), &event.comm);
+                }
+            },
+        )
+        .await;
+        inventory.into_entries()
+    })
+}
+
+/// Hand every record of `events`, as read by `parse`, to `handle` until
+/// shutdown is signaled
+///
+/// Events still in the ring buffer at shutdown are read before returning.
+async fn read_events<T>(
+    events: RingBuf<MapData>,
+    shutdown_signal: &ShutdownSignal,
+    kind: &str,
+    parse: fn(&[u8]) -> Option<T>,
+    mut handle: impl FnMut(T),
+) {
+    let mut forward = |record: &[u8]| match parse(record) {
+        Some(event) => handle(event),
+        None => log::warn!("Ignoring malformed {} event ({} bytes)", kind, record.len()),
+    };
+
+    let mut events = match AsyncFd::new(events) {
+        Ok(events) => events,
+        Err(err) => {
+            log::error!("Failed to watch {kind} events: {err}");
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            guard = events.readable_mut() => {
+                let mut guard = match guard {
+                    Ok(guard) => guard,
+                    Err(err) => {
+                        log::error!("Failed to wait for {kind} events: {err}");
+                        break;
+                    }
+                };
+                while let Some(record) = guard.get_inner_mut().next() {
+                    forward(&record);
+                }
+                guard.clear_ready();
+            }
+            shutdown = shutdown_signal.wait_timeout_or_shutdown(Duration::from_secs(60)) => {
+                if shutdown {
+                    break;
+                }
+            }
+        }
+    }
+
+    while let Some(record) = events.get_mut().next() {
+        forward(&record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn raw(mode: u8, path: &[u8]) -> Vec<u8> {
+        let mut record = raw_kind(DENIAL_PATH, mode, path);
+        record.resize(HEADER_LEN + 4096, 0xff);
+        record
+    }
+
+    fn raw_kind(kind: u8, mode: u8, detail: &[u8]) -> Vec<u8> {
+        let mut record = Vec::new();
+        record.extend_from_slice(&4242u32.to_ne_bytes());
+        record.extend_from_slice(&[mode, kind, 0, 0]);
+        record.extend_from_slice(b"cat\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        record.extend_from_slice(detail);
+        record.resize(HEADER_LEN + 128, 0);
+        record
+    }
+
+    #[test]
+    fn parse_record_reads_all_fields() {
+        assert_eq!(
+            parse_record(&raw(3, b"/etc/shadow\0")).unwrap(),
+            RawFileEvent {
+                pid: 4242,
+                mode: 3,
+                kind: DENIAL_PATH,
+                comm: "cat".to_string(),
+                path: Some("/etc/shadow".to_string()),
+            }
+        );
+        assert_eq!(parse_record(&raw(1, b"\0")).unwrap().path, None);
+        assert_eq!(parse_record(&[0; 12]), None);
+    }
+
+    #[test]
+    fn denial_records_become_violations() {
+        let violation = |mode, path| parse_denial(&raw(mode, path)).unwrap();
+        assert_eq!(
+            violation(3, b"/etc/shadow\0"),
+            ViolationEvent {
+                operation: Operation::FileRead,
+                target: "/etc/shadow".to_string(),
+                pid: 4242,
+                comm: "cat".to_string(),
+            }
+        );
+        assert_eq!(
+            violation(2, b"/etc/passwd\0").operation,
+            Operation::FileWrite
+        );
+        assert_eq!(violation(1, b"\0").target, "(unresolved path)");
+    }
+
+    #[test]
+    fn denial_records_of_other_kinds_become_violations() {
+        let violation = |kind, mode, detail: &[u8]| {
+            let event = parse_denial(&raw_kind(kind, mode, detail)).unwrap();
+            (event.operation, event.target)
+        };
+        assert_eq!(
+            violation(DENIAL_NAME, ACCESS_MODE_WRITE, b"config.toml\0"),
+            (Operation::FileWrite, "config.toml".to_string())
+        );
+        assert_eq!(
+            violation(DENIAL_NAME, ACCESS_MODE_EXECUTE, b"curl\0"),
+            (Operation::Exec, "curl".to_string())
+        );
+        assert_eq!(
+            violation(DENIAL_BIND, 0, &8080u16.to_be_bytes()),
+            (Operation::Bind, "8080".to_string())
+        );
+
+        let socket = |family: i32, protocol: i32| {
+            let mut detail = family.to_ne_bytes().to_vec();
+            detail.extend_from_slice(&protocol.to_ne_bytes());
+            violation(DENIAL_SOCKET, 0, &detail).1
+        };
+        assert_eq!(socket(17, 0), "packet");
+        assert_eq!(socket(AF_INET6, IPPROTO_ICMPV6), "icmp");
+        assert_eq!(socket(38, 0), "family 38");
+
+        assert_eq!(
+            violation(DENIAL_SNI, 0, b"evil.example\0"),
+            (Operation::Tls, "evil.example".to_string())
+        );
+        assert_eq!(violation(DENIAL_SNI, 0, b"").1, "(no server name)");
+
+        let mut inbound = Ipv4Addr::new(198, 51, 100, 7)
+            .to_ipv6_mapped()
+            .octets()
+            .to_vec();
+        inbound.extend_from_slice(&22u16.to_be_bytes());
+        assert_eq!(
+            violation(DENIAL_INBOUND, 0, &inbound),
+            (Operation::Accept, "198.51.100.7 to port 22".to_string())
+        );
+
+        assert_eq!(
+            violation(DENIAL_UNIX, 0, b"/tmp/.X11-unix/X0"),
+            (Operation::Unix, "@/tmp/.X11-unix/X0".to_string())
+        );
+        assert_eq!(parse_denial(&raw_kind(42, 0, b"")), None);
+    }
+
+    #[test]
+    fn open_records_name_the_requested_access() {
+        let access = |mode| parse_record(&raw(mode, b"/tmp/x\0")).unwrap().access();
+        assert_eq!(access(1), AccessMode::Read);
+        assert_eq!(access(2), AccessMode::Write);
+        assert_eq!(access(3), AccessMode::ReadWrite);
+    }
+}
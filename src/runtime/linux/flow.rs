@@ -0,0 +1,556 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use aya::maps::{MapData, RingBuf};
+use tokio::io::unix::AsyncFd;
+
+use crate::{
+    audit::{
+        Explainer, Explanation, FlowRecord, FlowVerdict, Operation, ViolationEvent,
+        ViolationSender, explain,
+    },
+    net::{ReverseResolver, cache::DnsCache},
+};
+
+use super::{ebpf::boot_clock, sync::ShutdownSignal};
+
+// Why a connection got its verdict (matching constants in mori-bpf)
+const REASON_ALLOW_LIST: u8 = 0;
+const REASON_PLUGIN: u8 = 1;
+const REASON_PLUGIN_PENDING: u8 = 2;
+const REASON_NO_RULE: u8 = 3;
+const REASON_DENY_LIST: u8 = 4;
+const REASON_DNS_ENFORCED: u8 = 5;
+
+/// Size of a record published on FLOW_EVENTS
+const RECORD_LEN: usize = 68;
+
+/// A flow record as published by the BPF program
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RawFlow {
+    /// CLOCK_BOOTTIME of the attempt in nanoseconds
+    boot_ns: u64,
+    pid: u32,
+    /// Command name of the connecting task
+    comm: String,
+    protocol: u8,
+    source: SocketAddr,
+    destination: SocketAddr,
+    allowed: bool,
+    reason: u8,
+}
+
+fn parse_record(record: &[u8]) -> Option<RawFlow> {
+    let record: &[u8; RECORD_LEN] = record.get(..RECORD_LEN)?.try_into().ok()?;
+    // IPv4 addresses come IPv4-mapped, whether the command connected to them
+    // through an IPv4 or a dual-stack socket
+    let addr = |at: usize| -> Option<IpAddr> {
+        let octets: [u8; 16] = record[at..at + 16].try_into().ok()?;
+        Some(IpAddr::V6(Ipv6Addr::from(octets)).to_canonical())
+    };
+    let port = |at: usize| u16::from_be_bytes([record[at], record[at + 1]]);
+    Some(RawFlow {
+        boot_ns: u64::from_ne_bytes(record[0..8].try_into().ok()?),
+        pid: u32::from_ne_bytes(record[8..12].try_into().ok()?),
+        comm: parse_comm(&record[52..68]),
+        destination: SocketAddr::new(addr(12)?, port(44)),
+        source: SocketAddr::new(addr(28)?, port(46)),
+        protocol: record[48],
+        allowed: record[49] == 1,
+        reason: record[50],
+    })
+}
+
+/// Read a NUL-padded task comm
+pub(super) fn parse_comm(comm: &[u8]) -> String {
+    let len = comm.iter().position(|&b| b == 0).unwrap_or(comm.len());
+    String::from_utf8_lossy(&comm[..len]).into_owned()
+}
+
+/// Policy entries used to name the rule that allowed a connection
+pub struct RuleIndex {
+    pub allow_all: bool,
+    /// Addresses allowed on their own, IPv4 and IPv6
+    pub allowed_ips: Vec<IpAddr>,
+    /// CIDR ranges allowed, IPv4 and IPv6
+    pub allowed_networks: Vec<(IpAddr, u8)>,
+    pub dns_cache: Arc<Mutex<DnsCache>>,
+    pub dns_servers: Arc<Mutex<HashSet<IpAddr>>>,
+    pub explainer: Explainer,
+}
+
+impl RuleIndex {
+    fn rule_for(&self, flow: &RawFlow) -> String {
+        let ip = flow.destination.ip();
+        match flow.reason {
+            REASON_PLUGIN => return "plugin".to_string(),
+            REASON_PLUGIN_PENDING => return "plugin (awaiting verdict)".to_string(),
+            REASON_NO_RULE => return "no matching rule".to_string(),
+            REASON_DENY_LIST => return "deny list".to_string(),
+            REASON_DNS_ENFORCED => return "DNS enforcement".to_string(),
+            REASON_ALLOW_LIST => {}
+            other => return format!("unknown reason {}", other),
+        }
+
+        if ip.is_loopback() {
+            return "localhost".to_string();
+        }
+        if self.allowed_ips.contains(&ip) {
+            return format!("ip {}", ip);
+        }
+        if let Some((network, prefix_len)) = self
+            .allowed_networks
+            .iter()
+            .find(|(network, prefix_len)| in_network(ip, *network, *prefix_len))
+        {
+            return format!("cidr {}/{}", network, prefix_len);
+        }
+        let domains = self.dns_cache.lock().unwrap().domains_for(ip).join(",");
+        if !domains.is_empty() {
+            return format!("domain {}", domains);
+        }
+        if self.dns_servers.lock().unwrap().contains(&ip) {
+            return format!("dns server {}", ip);
+        }
+        if self.allow_all {
+            return "allow all".to_string();
+        }
+        // A DNS record that expired after the connection was allowed
+        "allow list".to_string()
+    }
+
+    /// Explain a denied connection against the current DNS records
+    fn explain(&self, flow: &RawFlow) -> Explanation {
+        let mut explainer = self.explainer.clone();
+        let dns_cache = self.dns_cache.lock().unwrap();
+        // Nearest domains are only looked for among IPv4 addresses
+        explainer.resolve_with(|domain| {
+            dns_cache
+                .addresses(domain)
+                .into_iter()
+                .filter_map(|addr| match addr {
+                    IpAddr::V4(addr) => Some(addr),
+                    IpAddr::V6(_) => None,
+                })
+                .collect()
+        });
+        explainer.explain(&ViolationEvent {
+            operation: Operation::Connect,
+            target: flow.destination.to_string(),
+            pid: flow.pid,
+            comm: flow.comm.clone(),
+        })
+    }
+
+    fn to_record(&self, flow: &RawFlow, boot_time: SystemTime) -> FlowRecord {
+        FlowRecord {
+            timestamp: boot_time + Duration::from_nanos(flow.boot_ns),
+            pid: flow.pid,
+            comm: flow.comm.clone(),
+            protocol: flow.protocol,
+            source: flow.source,
+            destination: flow.destination,
+            verdict: if flow.allowed {
+                FlowVerdict::Allowed
+            } else {
+                FlowVerdict::Denied
+            },
+            rule: self.rule_for(flow),
+            ptr: None,
+        }
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    let (addr, network, bits) = match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            (addr.to_bits().into(), network.to_bits().into(), 32)
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => (addr.to_bits(), network.to_bits(), 128),
+        _ => return false,
+    };
+    let mask = u128::MAX
+        .checked_shl(bits - u32::from(prefix_len).min(bits))
+        .unwrap_or(0)
+        & (u128::MAX >> (128 - bits));
+    addr & mask == network & mask
+}
+
+/// Wall-clock time at which CLOCK_BOOTTIME was zero
+fn boot_time() -> SystemTime {
+    let Some(since_boot) = boot_clock() else {
+        log::warn!("Failed to read CLOCK_BOOTTIME; flow log timestamps will be wrong");
+        return SystemTime::UNIX_EPOCH;
+    };
+    SystemTime::now() - since_boot
+}
+
+/// Log connection events until shutdown is signaled
+///
+/// Every event is logged at info level. With `flow_log`, the records are also
+/// collected and returned, and the first connection to each destination denied
+/// for matching no rule is explained on stderr. With `reverse`, the PTR name
+/// of each denied destination is looked up once and added to the log line,
+/// the explanation and the record. With `violations` (in audit mode), every
+/// denied connection is published there instead of being explained here.
+/// Events still in the ring buffer at shutdown are read before returning.
+pub fn spawn_flow_collector(
+    events: RingBuf<MapData>,
+    index: RuleIndex,
+    flow_log: bool,
+    violations: Option<ViolationSender>,
+    reverse: Option<ReverseResolver>,
+    shutdown_signal: Arc<ShutdownSignal>,
+) -> tokio::task::JoinHandle<Vec<FlowRecord>> {
+    tokio::spawn(async move {
+        let mut collector = FlowCollector {
+            index,
+            flow_log,
+            violations,
+            reverse,
+            boot_time: boot_time(),
+            color: explain::stderr_color(),
+            records: Vec::new(),
+            explained: HashSet::new(),
+            ptr_names: HashMap::new(),
+        };
+
+        let mut events = match AsyncFd::new(events) {
+            Ok(events) => events,
+            Err(err) => {
+                log::error!("Failed to watch connection events: {err}");
+                return Vec::new();
+            }
+        };
+
+        loop {
+            tokio::select! {
+                guard = events.readable_mut() => {
+                    let mut guard = match guard {
+                        Ok(guard) => guard,
+                        Err(err) => {
+                            log::error!("Failed to wait for connection events: {err}");
+                            break;
+                        }
+                    };
+                    // Copied out so the ring buffer is not held across lookups
+                    let mut batch = Vec::new();
+                    while let Some(record) = guard.get_inner_mut().next() {
+                        batch.push(record.to_vec());
+                    }
+                    guard.clear_ready();
+                    for record in batch {
+                        collector.collect(&record).await;
+                    }
+                }
+                shutdown = shutdown_signal.wait_timeout_or_shutdown(Duration::from_secs(60)) => {
+                    if shutdown {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut batch = Vec::new();
+        while let Some(record) = events.get_mut().next() {
+            batch.push(record.to_vec());
+        }
+        for record in batch {
+            collector.collect(&record).await;
+        }
+        collector.records
+    })
+}
+
+/// State of the task spawned by `spawn_flow_collector`
+struct FlowCollector {
+    index: RuleIndex,
+    flow_log: bool,
+    violations: Option<ViolationSender>,
+    reverse: Option<ReverseResolver>,
+    boot_time: SystemTime,
+    color: bool,
+    records: Vec<FlowRecord>,
+    /// Destinations whose denial was already explained
+    explained: HashSet<SocketAddr>,
+    /// PTR names of the denied destinations looked up so far
+    ptr_names: HashMap<IpAddr, Option<String>>,
+}
+
+impl FlowCollector {
+    async fn collect(&mut self, record: &[u8]) {
+        let Some(flow) = parse_record(record) else {
+            log::warn!(
+                "Ignoring malformed connection event ({} bytes)",
+                record.len()
+            );
+            return;
+        };
+        let mut record = self.index.to_record(&flow, self.boot_time);
+        if !flow.allowed {
+            record.ptr = self.ptr_name(flow.destination.ip()).await;
+        }
+        let ptr = record
+            .ptr
+            .as_ref()
+            .map(|ptr| format!(" ({})", ptr))
+            .unwrap_or_default();
+        log::info!(
+            "connect: pid {} ({}) -> {}{} {} by {}",
+            record.pid,
+            record.comm,
+            record.destination,
+            ptr,
+            if flow.allowed { "allowed" } else { "denied" },
+            record.rule
+        );
+        if !flow.allowed
+            && let Some(violations) = &self.violations
+        {
+            let _ = violations.send(ViolationEvent {
+                operation: Operation::Connect,
+                target: flow.destination.to_string(),
+                pid: flow.pid,
+                comm: flow.comm.clone(),
+            });
+        }
+        if !self.flow_log {
+            return;
+        }
+        if !flow.allowed
+            && self.violations.is_none()
+            && matches!(flow.reason, REASON_NO_RULE | REASON_DENY_LIST)
+            && self.explained.insert(flow.destination)
+        {
+            let mut explanation = self.index.explain(&flow);
+            explanation.access.push_str(&ptr);
+            eprintln!("{}", explanation.render(self.color));
+        }
+        self.records.push(record);
+    }
+
+    /// PTR name of `ip`, looked up on first use when reverse lookups are enabled
+    async fn ptr_name(&mut self, ip: IpAddr) -> Option<String> {
+        let reverse = self.reverse.as_ref()?;
+        if let Some(name) = self.ptr_names.get(&ip) {
+            return name.clone();
+        }
+        let name = reverse.lookup(ip).await;
+        self.ptr_names.insert(ip, name.clone());
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::Ipv4Addr, time::Instant};
+
+    use crate::{
+        net::{SystemDnsResolver, cache::Entry},
+        policy::{NetworkPolicy, Policy},
+    };
+
+    fn raw(addr: [u8; 4], allowed: bool, reason: u8) -> Vec<u8> {
+        raw_v6(
+            Ipv4Addr::from(addr).to_ipv6_mapped().octets(),
+            allowed,
+            reason,
+        )
+    }
+
+    fn raw_v6(addr: [u8; 16], allowed: bool, reason: u8) -> Vec<u8> {
+        let mut record = Vec::new();
+        record.extend_from_slice(&1_500_000_000u64.to_ne_bytes());
+        record.extend_from_slice(&4242u32.to_ne_bytes());
+        record.extend_from_slice(&addr);
+        record.extend_from_slice(&Ipv4Addr::new(10, 0, 0, 2).to_ipv6_mapped().octets());
+        record.extend_from_slice(&443u16.to_be_bytes());
+        record.extend_from_slice(&50000u16.to_be_bytes());
+        record.extend_from_slice(&[6, allowed as u8, reason, 0]);
+        record.extend_from_slice(b"curl\0\0\0\0\0\0\0\0\0\0\0\0");
+        record
+    }
+
+    fn index() -> RuleIndex {
+        let dns_cache = Arc::new(Mutex::new(DnsCache::default()));
+        let now = Instant::now();
+        dns_cache.lock().unwrap().apply(
+            "example.com",
+            now,
+            vec![Entry {
+                ip: IpAddr::from([93, 184, 216, 34]),
+                expires_at: now + Duration::from_secs(60),
+            }],
+        );
+        RuleIndex {
+            allow_all: false,
+            allowed_ips: vec![IpAddr::from([192, 0, 2, 1])],
+            allowed_networks: vec![
+                (IpAddr::from([10, 1, 0, 0]), 16),
+                ("2001:db8:1::".parse().unwrap(), 48),
+            ],
+            dns_cache,
+            dns_servers: Arc::new(Mutex::new(HashSet::from([IpAddr::from([10, 0, 0, 53])]))),
+            explainer: Explainer::new(&Policy::with_network(
+                NetworkPolicy::from_entries(&["example.com".to_string()]).unwrap(),
+            )),
+        }
+    }
+
+    #[test]
+    fn parse_record_reads_all_fields() {
+        let flow = parse_record(&raw([93, 184, 216, 34], true, REASON_ALLOW_LIST)).unwrap();
+        assert_eq!(
+            flow,
+            RawFlow {
+                boot_ns: 1_500_000_000,
+                pid: 4242,
+                comm: "curl".to_string(),
+                protocol: 6,
+                source: "10.0.0.2:50000".parse().unwrap(),
+                destination: "93.184.216.34:443".parse().unwrap(),
+                allowed: true,
+                reason: REASON_ALLOW_LIST,
+            }
+        );
+        assert_eq!(parse_record(&[0; 12]), None);
+    }
+
+    #[test]
+    fn parse_record_reads_ipv6_destination() {
+        let ip: Ipv6Addr = "2001:db8:1::7".parse().unwrap();
+        let flow = parse_record(&raw_v6(ip.octets(), true, REASON_ALLOW_LIST)).unwrap();
+        assert_eq!(flow.destination, "[2001:db8:1::7]:443".parse().unwrap());
+        assert_eq!(index().rule_for(&flow), "cidr 2001:db8:1::/48");
+    }
+
+    #[test]
+    fn rule_for_names_matching_rule() {
+        let index = index();
+        let rule = |addr, allowed, reason| {
+            index.rule_for(&parse_record(&raw(addr, allowed, reason)).unwrap())
+        };
+
+        assert_eq!(
+            rule([93, 184, 216, 34], true, REASON_ALLOW_LIST),
+            "domain example.com"
+        );
+        assert_eq!(
+            rule([192, 0, 2, 1], true, REASON_ALLOW_LIST),
+            "ip 192.0.2.1"
+        );
+        assert_eq!(
+            rule([10, 1, 2, 3], true, REASON_ALLOW_LIST),
+            "cidr 10.1.0.0/16"
+        );
+        assert_eq!(
+            rule([10, 0, 0, 53], true, REASON_ALLOW_LIST),
+            "dns server 10.0.0.53"
+        );
+        assert_eq!(rule([127, 0, 0, 1], true, REASON_ALLOW_LIST), "localhost");
+        assert_eq!(
+            rule([198, 51, 100, 1], false, REASON_NO_RULE),
+            "no matching rule"
+        );
+        assert_eq!(rule([10, 1, 2, 3], false, REASON_DENY_LIST), "deny list");
+        assert_eq!(
+            rule([198, 51, 100, 1], false, REASON_PLUGIN_PENDING),
+            "plugin (awaiting verdict)"
+        );
+    }
+
+    #[test]
+    fn explain_uses_current_dns_records() {
+        let index = index();
+        let flow = parse_record(&raw([93, 184, 1, 1], false, REASON_NO_RULE)).unwrap();
+        assert_eq!(
+            index.explain(&flow).reason,
+            "no rule matches; nearest domain rule: example.com resolved to different IPs (93.184.216.34)"
+        );
+    }
+
+    #[tokio::test]
+    async fn ptr_name_is_looked_up_once_per_destination() {
+        let ip = Ipv4Addr::new(198, 51, 100, 1);
+        let mut collector = FlowCollector {
+            index: index(),
+            flow_log: true,
+            violations: None,
+            reverse: None,
+            boot_time: SystemTime::UNIX_EPOCH,
+            color: false,
+            records: Vec::new(),
+            explained: HashSet::new(),
+            ptr_names: HashMap::from([(ip.into(), Some("host.example.net".to_string()))]),
+        };
+        // Without reverse lookups no name is given, even a known one
+        assert_eq!(collector.ptr_name(ip.into()).await, None);
+
+        collector.reverse = Some(
+            SystemDnsResolver::with_nameservers(vec![IpAddr::from([192, 0, 2, 53])])
+                .reverse()
+                .unwrap(),
+        );
+        collector
+            .collect(&raw(ip.octets(), false, REASON_NO_RULE))
+            .await;
+        collector
+            .collect(&raw([93, 184, 216, 34], true, REASON_ALLOW_LIST))
+            .await;
+        assert_eq!(
+            collector.records[0].ptr.as_deref(),
+            Some("host.example.net")
+        );
+        assert_eq!(collector.records[1].ptr, None);
+    }
+
+    #[tokio::test]
+    async fn collect_publishes_denied_connections_in_audit_mode() {
+        let violations = crate::audit::ViolationCollector::spawn();
+        let mut collector = FlowCollector {
+            index: index(),
+            flow_log: false,
+            violations: Some(violations.sender()),
+            reverse: None,
+            boot_time: SystemTime::UNIX_EPOCH,
+            color: false,
+            records: Vec::new(),
+            explained: HashSet::new(),
+            ptr_names: HashMap::new(),
+        };
+        collector
+            .collect(&raw([198, 51, 100, 1], false, REASON_NO_RULE))
+            .await;
+        collector
+            .collect(&raw([93, 184, 216, 34], true, REASON_ALLOW_LIST))
+            .await;
+        drop(collector);
+
+        let report = violations.finish().await;
+        assert_eq!(report.total(), 1);
+        let (operation, target, _) = report.iter().next().unwrap();
+        assert_eq!(
+            (operation, target),
+            (Operation::Connect, "198.51.100.1:443")
+        );
+    }
+
+    #[test]
+    fn to_record_converts_boot_time() {
+        let index = index();
+        let flow = parse_record(&raw([198, 51, 100, 1], false, REASON_NO_RULE)).unwrap();
+        let record = index.to_record(&flow, SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            record.timestamp,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1_500)
+        );
+        assert_eq!(record.verdict, FlowVerdict::Denied);
+        assert_eq!(record.pid, 4242);
+        assert_eq!(record.comm, "curl");
+    }
+}
@@ -0,0 +1,146 @@
+use std::{convert::TryFrom, net::IpAddr, os::fd::BorrowedFd};
+
+use aya::{
+    Ebpf,
+    maps::{
+        HashMap,
+        lpm_trie::{Key, LpmTrie},
+    },
+    programs::{
+        cgroup_skb::{CgroupSkb, CgroupSkbAttachType},
+        cgroup_sock_addr::CgroupSockAddr,
+        links::CgroupAttachMode,
+    },
+};
+
+use crate::{error::MoriError, policy::InboundPolicy};
+
+use super::ebpf::network_address;
+
+const PROGRAM_NAME: &str = "mori_inbound";
+const BIND_PROGRAMS: [&str; 2] = ["mori_bind4", "mori_bind6"];
+
+/// Inbound connection filtering using a cgroup skb ingress program
+pub struct InboundEbpf {}
+
+impl InboundEbpf {
+    /// Fill INBOUND_V4_LPM and INBOUND_V6_LPM with the allowed peers and
+    /// attach the ingress program
+    pub fn load_and_attach(
+        bpf: &mut Ebpf,
+        policy: &InboundPolicy,
+        cgroup_fd: BorrowedFd<'_>,
+    ) -> Result<(), MoriError> {
+        let peers = policy
+            .allowed_peers
+            .iter()
+            .map(|&(network, prefix_len)| (IpAddr::V4(network), prefix_len))
+            .chain(
+                policy
+                    .allowed_peers_v6
+                    .iter()
+                    .map(|&(network, prefix_len)| (IpAddr::V6(network), prefix_len)),
+            );
+        for (network, prefix_len) in peers {
+            let key_len = u32::from(prefix_len);
+            match network_address(network, prefix_len) {
+                IpAddr::V4(address) => {
+                    let mut map: LpmTrie<_, [u8; 4], u8> =
+                        LpmTrie::try_from(bpf.map_mut("INBOUND_V4_LPM").unwrap())?;
+                    map.insert(&Key::new(key_len, address.octets()), 1, 0)
+                        .map_err(MoriError::Map)?;
+                }
+                IpAddr::V6(address) => {
+                    let mut map: LpmTrie<_, [u8; 16], u8> =
+                        LpmTrie::try_from(bpf.map_mut("INBOUND_V6_LPM").unwrap())?;
+                    map.insert(&Key::new(key_len, address.octets()), 1, 0)
+                        .map_err(MoriError::Map)?;
+                }
+            }
+            log::info!(
+                "Accepting inbound connections from {}/{}",
+                network,
+                prefix_len
+            );
+        }
+
+        let program = bpf
+            .program_mut(PROGRAM_NAME)
+            .ok_or_else(|| MoriError::ProgramNotFound {
+                name: PROGRAM_NAME.to_string(),
+            })?;
+
+        let program: &mut CgroupSkb =
+            program
+                .try_into()
+                .map_err(|source| MoriError::ProgramPrepare {
+                    name: PROGRAM_NAME.to_string(),
+                    source,
+                })?;
+
+        program.load().map_err(|source| MoriError::ProgramPrepare {
+            name: PROGRAM_NAME.to_string(),
+            source,
+        })?;
+
+        program
+            .attach(
+                cgroup_fd,
+                CgroupSkbAttachType::Ingress,
+                // mori_dns_snoop may be attached to the same hook
+                CgroupAttachMode::AllowMultiple,
+            )
+            .map_err(|source| MoriError::ProgramAttach {
+                name: PROGRAM_NAME.to_string(),
+                source,
+            })?;
+
+        log::info!("Attached inbound connection filter: {}", PROGRAM_NAME);
+        Ok(())
+    }
+
+    /// Fill LISTEN_PORTS with the allowed listen ports and attach the bind programs
+    pub fn restrict_listen(
+        bpf: &mut Ebpf,
+        policy: &InboundPolicy,
+        cgroup_fd: BorrowedFd<'_>,
+    ) -> Result<(), MoriError> {
+        let mut ports: HashMap<_, u16, u8> =
+            HashMap::try_from(bpf.map_mut("LISTEN_PORTS").unwrap())?;
+        for &port in &policy.allowed_listen_ports {
+            ports.insert(port, 1, 0).map_err(MoriError::Map)?;
+            log::info!("Allowing listening sockets on port {}", port);
+        }
+
+        for name in BIND_PROGRAMS {
+            let program = bpf
+                .program_mut(name)
+                .ok_or_else(|| MoriError::ProgramNotFound {
+                    name: name.to_string(),
+                })?;
+
+            let program: &mut CgroupSockAddr =
+                program
+                    .try_into()
+                    .map_err(|source| MoriError::ProgramPrepare {
+                        name: name.to_string(),
+                        source,
+                    })?;
+
+            program.load().map_err(|source| MoriError::ProgramPrepare {
+                name: name.to_string(),
+                source,
+            })?;
+
+            program
+                .attach(cgroup_fd, CgroupAttachMode::Single)
+                .map_err(|source| MoriError::ProgramAttach {
+                    name: name.to_string(),
+                    source,
+                })?;
+        }
+
+        log::info!("Attached listen port filter");
+        Ok(())
+    }
+}
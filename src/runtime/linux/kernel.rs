@@ -0,0 +1,31 @@
+use std::os::fd::BorrowedFd;
+
+use aya::{Btf, Ebpf};
+
+use super::file::{attach_program, register_target_cgroup};
+use crate::error::MoriError;
+
+/// LSM programs denying processes in TARGET_CGROUP loading kernel modules
+/// and using bpf(), with the hooks they attach to
+const PROGRAMS: &[(&str, &str)] = &[
+    ("mori_kernel_module_request", "kernel_module_request"),
+    ("mori_kernel_read_file", "kernel_read_file"),
+    ("mori_kernel_load_data", "kernel_load_data"),
+    ("mori_bpf", "bpf"),
+];
+
+/// Keep the command from changing the kernel: loading modules, directly or
+/// by making the kernel request one, and loading BPF programs or changing
+/// BPF maps, which would let it switch off mori's own programs
+pub(super) fn deny_kernel_changes(
+    bpf: &mut Ebpf,
+    cgroup_fd: BorrowedFd<'_>,
+) -> Result<(), MoriError> {
+    let btf = Btf::from_sys_fs()?;
+    register_target_cgroup(bpf, cgroup_fd)?;
+    for (name, hook) in PROGRAMS {
+        attach_program(bpf, name, hook, &btf)?;
+    }
+    log::info!("Denied loading kernel modules and using bpf() in the cgroup");
+    Ok(())
+}
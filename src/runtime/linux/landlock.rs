@@ -0,0 +1,367 @@
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs, io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::MoriError,
+    policy::{AccessMode, FilePolicy, ProcessPolicy, file::canonicalize_lenient},
+};
+
+/// `flags` of landlock_create_ruleset asking for the ABI version
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+/// Since ABI 2
+const ACCESS_FS_REFER: u64 = 1 << 13;
+/// Since ABI 3
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+const READ_ACCESS: u64 = ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+const WRITE_ACCESS: u64 = ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_REMOVE_DIR
+    | ACCESS_FS_REMOVE_FILE
+    | ACCESS_FS_MAKE_CHAR
+    | ACCESS_FS_MAKE_DIR
+    | ACCESS_FS_MAKE_REG
+    | ACCESS_FS_MAKE_SOCK
+    | ACCESS_FS_MAKE_FIFO
+    | ACCESS_FS_MAKE_BLOCK
+    | ACCESS_FS_MAKE_SYM
+    | ACCESS_FS_REFER
+    | ACCESS_FS_TRUNCATE;
+/// Rights a rule on a file, rather than a directory, may grant
+const FILE_ACCESS: u64 =
+    ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Landlock ABI version of the running kernel, or None without Landlock
+pub(super) fn abi_version() -> Option<i32> {
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    (version > 0).then_some(version as i32)
+}
+
+/// Access rights of `abi` Landlock handles
+fn supported_access(abi: i32) -> u64 {
+    let mut access = ACCESS_FS_EXECUTE | READ_ACCESS | WRITE_ACCESS;
+    if abi < 2 {
+        access &= !ACCESS_FS_REFER;
+    }
+    if abi < 3 {
+        access &= !ACCESS_FS_TRUNCATE;
+    }
+    access
+}
+
+/// Landlock ruleset enforcing the path rules of a policy
+///
+/// Landlock only grants access, beneath the directories rules name, so a
+/// denied path is carved out by granting access to its siblings, and to the
+/// siblings of each directory on the way to it, one by one. Those
+/// directories themselves get no rule: they cannot be listed (for a path
+/// denied for reading) and no file can be created or removed directly in
+/// them (for a path denied for writing).
+pub(super) struct Ruleset {
+    fd: OwnedFd,
+}
+
+impl Ruleset {
+    /// Build the ruleset for the file rules and the denied executables of a
+    /// policy, or None when there are none
+    ///
+    /// Paths the rules cannot open are skipped, so they stay inaccessible.
+    pub(super) fn for_policy(
+        file: &FilePolicy,
+        process: &ProcessPolicy,
+        abi: i32,
+    ) -> io::Result<Option<Self>> {
+        let rules = path_rules(file, process);
+        let handled = rules.handled & supported_access(abi);
+        if handled == 0 {
+            return Ok(None);
+        }
+
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd as i32) },
+        };
+        for (path, access) in rules.granted {
+            ruleset.add_rule(&path, access & handled)?;
+        }
+        Ok(Some(ruleset))
+    }
+
+    /// Grant `access` beneath `path`
+    fn add_rule(&self, path: &Path, access: u64) -> io::Result<()> {
+        let Ok(name) = CString::new(path.as_os_str().as_bytes()) else {
+            return Ok(());
+        };
+        let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            log::debug!(
+                "Skipping Landlock rule for {}: {}",
+                path.display(),
+                io::Error::last_os_error()
+            );
+            return Ok(());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let access = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => access,
+            _ => access & FILE_ACCESS,
+        };
+        if access == 0 {
+            return Ok(());
+        }
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: fd.as_raw_fd(),
+        };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                self.fd.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Restrict the calling process, and every process it starts, to the ruleset
+    ///
+    /// Called in the forked child before exec; sets no_new_privs, as Landlock
+    /// requires without CAP_SYS_ADMIN.
+    pub(super) fn restrict_self(&self) -> io::Result<()> {
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ret =
+            unsafe { libc::syscall(libc::SYS_landlock_restrict_self, self.fd.as_raw_fd(), 0) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// File access control using Landlock, for kernels without the BPF LSM
+///
+/// Unlike `FileEbpf`, nothing is attached to the cgroup: the forked child
+/// applies the ruleset to itself before exec.
+pub(super) struct FileLandlock {}
+
+impl FileLandlock {
+    /// Build the Landlock ruleset for the file policy
+    ///
+    /// `reason` is why the eBPF programs cannot be used; it is returned when
+    /// Landlock cannot enforce the policy either, because the kernel lacks
+    /// it or the policy has rules beyond paths.
+    pub(super) fn load(policy: &FilePolicy, reason: MoriError) -> Result<Ruleset, MoriError> {
+        let Some(abi) = abi_version() else {
+            return Err(reason);
+        };
+        if !policy.denied_devices.is_empty() || policy.private_proc || policy.hide_denied {
+            return Err(reason);
+        }
+        let Some(ruleset) = Ruleset::for_policy(policy, &ProcessPolicy::new(), abi)
+            .map_err(|source| MoriError::Landlock { source })?
+        else {
+            return Err(reason);
+        };
+        eprintln!(
+            "mori: file rules cannot be enforced with eBPF ({}); enforcing them with Landlock",
+            reason
+        );
+        Ok(ruleset)
+    }
+}
+
+/// Access the rules handle, and the access they grant by path
+#[derive(Debug, Default)]
+struct PathRules {
+    handled: u64,
+    granted: BTreeMap<PathBuf, u64>,
+}
+
+/// Translate the path rules of a policy into Landlock rules
+///
+/// Reading and writing are carved out of the allowed paths (`/` outside of
+/// allow-list mode), executing out of `/`.
+fn path_rules(file: &FilePolicy, process: &ProcessPolicy) -> PathRules {
+    let denied = |modes: &[AccessMode]| -> Vec<PathBuf> {
+        file.denied_paths
+            .iter()
+            .filter(|(_, mode)| modes.contains(mode))
+            .map(|(path, _)| canonicalize_lenient(path))
+            .collect()
+    };
+    let read_denied = denied(&[AccessMode::Read, AccessMode::ReadWrite]);
+    let write_denied = denied(&[AccessMode::Write, AccessMode::ReadWrite]);
+    let mut exec_denied = denied(&[AccessMode::Execute]);
+    exec_denied.extend(
+        process
+            .denied_exec
+            .iter()
+            .map(|path| canonicalize_lenient(path)),
+    );
+
+    let allowed = file.allowed_entries();
+    let roots = |access: AccessMode| -> Vec<PathBuf> {
+        if allowed.is_empty() {
+            return vec![PathBuf::from("/")];
+        }
+        allowed
+            .iter()
+            .filter(|(_, mode)| *mode as u8 & access as u8 != 0)
+            .map(|(path, _)| canonicalize_lenient(path))
+            .collect()
+    };
+
+    let mut rules = PathRules::default();
+    for (access, roots, denied) in [
+        (READ_ACCESS, roots(AccessMode::Read), read_denied),
+        (WRITE_ACCESS, roots(AccessMode::Write), write_denied),
+        (ACCESS_FS_EXECUTE, vec![PathBuf::from("/")], exec_denied),
+    ] {
+        if denied.is_empty() && (access == ACCESS_FS_EXECUTE || allowed.is_empty()) {
+            continue;
+        }
+        rules.handled |= access;
+        let denied: Vec<&Path> = denied.iter().map(PathBuf::as_path).collect();
+        for root in &roots {
+            grant_beneath(root, &denied, access, &mut rules.granted);
+        }
+    }
+    rules
+}
+
+/// Grant `access` beneath `path`, except beneath the `denied` paths
+fn grant_beneath(path: &Path, denied: &[&Path], access: u64, granted: &mut BTreeMap<PathBuf, u64>) {
+    if denied.iter().any(|denied| path.starts_with(denied)) {
+        return;
+    }
+    let below: Vec<&Path> = denied
+        .iter()
+        .copied()
+        .filter(|denied| denied.starts_with(path))
+        .collect();
+    if below.is_empty() {
+        *granted.entry(path.to_path_buf()).or_default() |= access;
+        return;
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        // A symlink leads to a path the walk covers on its own
+        if entry.file_type().is_ok_and(|kind| kind.is_symlink()) {
+            continue;
+        }
+        grant_beneath(&entry.path(), &below, access, granted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grant_beneath_carves_out_denied_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        for path in ["home/user/.ssh", "home/user/src", "home/other", "usr"] {
+            fs::create_dir_all(root.join(path)).unwrap();
+        }
+        std::os::unix::fs::symlink(root.join("home"), root.join("link")).unwrap();
+
+        let denied = root.join("home/user/.ssh");
+        let mut granted = BTreeMap::new();
+        grant_beneath(&root, &[&denied], READ_ACCESS, &mut granted);
+
+        let paths: Vec<&Path> = granted.keys().map(PathBuf::as_path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                root.join("home/other").as_path(),
+                root.join("home/user/src").as_path(),
+                root.join("usr").as_path(),
+            ]
+        );
+    }
+
+    #[test]
+    fn path_rules_handle_only_the_restricted_access() {
+        let mut file = FilePolicy::new();
+        file.deny_write("/etc/hosts");
+        let rules = path_rules(&file, &ProcessPolicy::new());
+        assert_eq!(rules.handled, WRITE_ACCESS);
+        assert!(rules.granted.contains_key(Path::new("/usr")));
+        assert!(!rules.granted.contains_key(Path::new("/etc")));
+
+        assert_eq!(
+            path_rules(&FilePolicy::new(), &ProcessPolicy::new()).handled,
+            0
+        );
+    }
+
+    #[test]
+    fn supported_access_drops_rights_of_newer_abis() {
+        assert_eq!(supported_access(1) & ACCESS_FS_REFER, 0);
+        assert_eq!(supported_access(2) & ACCESS_FS_TRUNCATE, 0);
+        assert_ne!(supported_access(3) & ACCESS_FS_TRUNCATE, 0);
+    }
+}
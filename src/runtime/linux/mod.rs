@@ -1,44 +1,105 @@
 mod cgroup;
 mod dns;
 mod ebpf;
+mod exec;
 mod file;
+mod file_events;
+mod flow;
+mod inbound;
+mod kernel;
+mod landlock;
+mod mount;
+#[cfg(feature = "wasm-plugin")]
+mod plugin;
+pub(super) mod preflight;
+mod preload;
+mod ptrace;
+mod seccomp;
+mod socket;
 mod sync;
+mod unprivileged;
+mod user;
 
 use std::{
-    collections::HashSet,
-    net::Ipv4Addr,
-    sync::{Arc, Mutex},
-    time::Instant,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsString,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
 use aya::Ebpf;
 
 use crate::{
+    audit::{Explainer, FileAccess, FlowRecord, ViolationCollector},
     error::MoriError,
     net::{
-        cache::DnsCache,
-        resolver::{DnsResolver, SystemDnsResolver},
+        ResolvedAddresses,
+        cache::{DnsCache, Entry},
+        resolver::{
+            DnsResolver, DomainRecords, STRICT_RESOLVE_DEADLINE, SystemDnsResolver, resolve_strict,
+        },
     },
-    policy::{AllowPolicy, Policy},
+    policy::{AllowPolicy, Policy, ProcessPolicy},
+};
+
+use super::{
+    Backend, Credentials, RunOptions, RunOutcome, TIMEOUT_EXIT_CODE,
+    output::{OutputCapture, OutputPumps},
+    process::{SignalRelay, exit_code, sudo_credentials},
 };
 
 use cgroup::CgroupManager;
-use dns::{apply_dns_servers, apply_domain_records, spawn_refresh};
-use ebpf::NetworkEbpf;
+use dns::{
+    RefreshSchedule, apply_denied_records, apply_dns_servers, apply_domain_records,
+    spawn_deny_refresh, spawn_refresh,
+};
+use ebpf::{NetworkEbpf, max_prefix_len};
+use file_events::{spawn_denial_reader, spawn_open_recorder};
+use flow::{RuleIndex, spawn_flow_collector};
+use landlock::{FileLandlock, Ruleset};
+use seccomp::SeccompFilter;
 use sync::ShutdownSignal;
+use unprivileged::Confinement;
 
-/// Spawn a command and add it to a cgroup before execution
+/// Where the command is confined
+enum Sandbox<'a> {
+    /// In the cgroup the policy's eBPF programs are attached to, with the
+    /// Landlock ruleset standing in for the file programs without the BPF LSM
+    Cgroup(&'a Path, Option<&'a Ruleset>),
+    /// In a process group of its own, confining itself (`--backend unprivileged`)
+    Unprivileged(&'a Confinement),
+}
+
+/// Spawn a command and add it to its sandbox before execution
 ///
 /// Uses fork() to get the PID before exec, allowing us to add the process
-/// to the cgroup before it starts executing the command.
+/// to the cgroup before it starts executing the command. The child sets up
+/// its namespaces (`--private-tmp`, `--overlay`, and those of the
+/// unprivileged backend) before exec as well, and installs Landlock and
+/// `seccomp` last, after dropping privileges. The command gets mori's
+/// environment (or only `CLEAN_ENV_VARS` of it with `--clean-env`)
+/// filtered by `process`, plus `env`.
 /// Returns a ChildProcess that can be waited on.
 fn spawn_command(
     command: &str,
     args: &[&str],
-    cgroup_path: &std::path::Path,
+    sandbox: &Sandbox,
+    process: &ProcessPolicy,
+    env: &[(String, String)],
+    options: &RunOptions,
+    seccomp: Option<&SeccompFilter>,
 ) -> Result<ChildProcess, MoriError> {
     use nix::unistd::{ForkResult, fork};
 
+    let credentials = command_credentials(options);
+    let inherited = command_environment(process, options);
+    let output = OutputCapture::new(options)?;
+
     // Create a pipe for synchronization using libc
     let mut pipe_fds = [0i32; 2];
     if unsafe { libc::pipe(pipe_fds.as_mut_ptr()) } != 0 {
@@ -55,22 +116,34 @@ fn spawn_command(
             // Parent process: close read end
             unsafe { libc::close(read_fd) };
 
-            // Add child to cgroup
-            let pid = child.as_raw() as u32;
-            let procs_path = cgroup_path.join("cgroup.procs");
-            std::fs::write(&procs_path, pid.to_string()).map_err(|source| {
-                MoriError::CgroupOperation {
-                    operation: "write_pid".to_string(),
-                    path: procs_path.clone(),
-                    source,
+            match sandbox {
+                Sandbox::Cgroup(cgroup_path, _) => {
+                    // Add child to cgroup
+                    let pid = child.as_raw() as u32;
+                    let procs_path = cgroup_path.join("cgroup.procs");
+                    std::fs::write(&procs_path, pid.to_string()).map_err(|source| {
+                        MoriError::CgroupOperation {
+                            operation: "write_pid".to_string(),
+                            path: procs_path.clone(),
+                            source,
+                        }
+                    })?;
+                    log::info!("Added process {} to cgroup", pid);
                 }
-            })?;
-            log::info!("Added process {} to cgroup", pid);
+                // The child does the same; whichever runs first, the group
+                // exists before the child execs
+                Sandbox::Unprivileged(_) => unsafe {
+                    libc::setpgid(child.as_raw(), child.as_raw());
+                },
+            }
 
             // Signal child to continue by closing write end
             unsafe { libc::close(write_fd) };
 
-            Ok(ChildProcess { pid: child })
+            Ok(ChildProcess {
+                pid: child,
+                output: output.map(OutputCapture::start),
+            })
         }
         Ok(ForkResult::Child) => {
             use std::os::unix::process::CommandExt;
@@ -86,16 +159,70 @@ fn spawn_command(
             // Close read end
             unsafe { libc::close(read_fd) };
 
+            if let Some(output) = &output
+                && let Err(e) = output.redirect()
+            {
+                panic!("cannot redirect the output: {}", e);
+            }
+
+            if let Sandbox::Unprivileged(confinement) = sandbox {
+                unsafe { libc::setpgid(0, 0) };
+                if let Err(e) = confinement.enter_namespaces() {
+                    panic!("cannot create the namespaces: {}", e);
+                }
+            }
+
+            // Mounts need root, so they happen before dropping privileges
+            if (options.private_tmp || options.overlay.is_some())
+                && let Err(e) = mount::unshare_mounts()
+            {
+                panic!("cannot create a mount namespace: {}", e);
+            }
+            if options.private_tmp
+                && let Err(e) = mount::private_tmp()
+            {
+                panic!("cannot set up a private /tmp: {}", e);
+            }
+            if let Some(scratch) = &options.overlay
+                && let Err(e) =
+                    std::env::current_dir().and_then(|dir| mount::overlay(scratch, &dir))
+            {
+                panic!("cannot mount the overlay: {}", e);
+            }
+
+            // Drop privileges to `--user` or the user behind sudo
+            if let Some(credentials) = &credentials
+                && let Err(e) = user::switch_to(credentials)
+            {
+                panic!("cannot switch to uid {}: {}", credentials.uid, e);
+            }
+            // After the overlay is entered, so a relative directory is found in it
+            if let Some(dir) = &options.chdir
+                && let Err(e) = std::env::set_current_dir(dir)
+            {
+                panic!("cannot change to {}: {}", dir.display(), e);
+            }
+            let ruleset = match sandbox {
+                Sandbox::Cgroup(_, ruleset) => *ruleset,
+                Sandbox::Unprivileged(confinement) => confinement.ruleset(),
+            };
+            if let Some(ruleset) = ruleset
+                && let Err(e) = ruleset.restrict_self()
+            {
+                panic!("cannot restrict file access with Landlock: {}", e);
+            }
+
             // Build command
             let mut cmd = Command::new(command);
             cmd.args(args);
+            if let Some(vars) = inherited {
+                cmd.env_clear().envs(vars);
+            }
+            cmd.envs(env.iter().map(|(key, value)| (key, value)));
 
-            // Drop privileges if running under sudo
-            if let (Ok(uid_str), Ok(gid_str)) =
-                (std::env::var("SUDO_UID"), std::env::var("SUDO_GID"))
-                && let (Ok(uid), Ok(gid)) = (uid_str.parse::<u32>(), gid_str.parse::<u32>())
-            {
-                cmd.uid(uid).gid(gid);
+            // pre_exec hooks run last, so the filter does not apply to the setup
+            if let Some(filter) = seccomp.cloned() {
+                unsafe { cmd.pre_exec(move || filter.install()) };
             }
 
             // exec the command (this replaces the current process image and never returns)
@@ -108,9 +235,134 @@ fn spawn_command(
     }
 }
 
+/// Variables `--clean-env` keeps, besides the `LC_*` locale variables
+const CLEAN_ENV_VARS: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "TZ", "LANG", "LANGUAGE",
+];
+
+/// Environment the command starts with, or None to pass on mori's
+fn command_environment(
+    process: &ProcessPolicy,
+    options: &RunOptions,
+) -> Option<Vec<(OsString, OsString)>> {
+    if !options.clean_env {
+        return process.filtered_env();
+    }
+    Some(
+        std::env::vars_os()
+            .filter(|(name, _)| {
+                let name = name.to_string_lossy();
+                (CLEAN_ENV_VARS.contains(&name.as_ref()) || name.starts_with("LC_"))
+                    && process.env_allowed(&name)
+            })
+            .collect(),
+    )
+}
+
+/// User the command runs as: `--user`, or else the user behind sudo when
+/// running as root
+fn command_credentials(options: &RunOptions) -> Option<Credentials> {
+    options.user.clone().or_else(|| {
+        if unsafe { libc::geteuid() } != 0 {
+            return None;
+        }
+        sudo_credentials().map(|(uid, gid)| Credentials {
+            uid,
+            gid,
+            groups: vec![],
+        })
+    })
+}
+
+/// Processes the command and everything it starts belong to
+#[derive(Debug, Clone)]
+enum ProcessTree {
+    /// The cgroup at this path
+    Cgroup(PathBuf),
+    /// This process group, for the unprivileged backend; processes that
+    /// start a session of their own leave it
+    ProcessGroup(libc::pid_t),
+}
+
+impl ProcessTree {
+    /// Kill every process in the tree with SIGKILL
+    fn kill(&self) {
+        match self {
+            ProcessTree::Cgroup(path) => {
+                if let Err(e) = cgroup::kill_all(path) {
+                    log::warn!("Failed to kill the processes in {}: {}", path.display(), e);
+                }
+            }
+            ProcessTree::ProcessGroup(pgid) => {
+                unsafe { libc::killpg(*pgid, libc::SIGKILL) };
+            }
+        }
+    }
+
+    /// Kill the processes left in the tree once the command has exited
+    fn kill_remaining(&self) {
+        match self {
+            ProcessTree::Cgroup(path) => cgroup::kill_remaining(path),
+            ProcessTree::ProcessGroup(pgid) => {
+                if unsafe { libc::killpg(*pgid, libc::SIGKILL) } == 0 {
+                    log::info!("Killed the processes left in process group {}", pgid);
+                }
+            }
+        }
+    }
+}
+
+/// Wait for the command and return the exit code mori should return
+///
+/// Signals mori receives meanwhile are forwarded to the command, and a
+/// second one kills everything in `tree`. Once `options.timeout` passes,
+/// everything in `tree` is killed as well and the exit code is
+/// `TIMEOUT_EXIT_CODE`.
+fn wait_command(
+    child: &mut ChildProcess,
+    tree: &ProcessTree,
+    options: &RunOptions,
+    signals: &SignalRelay,
+) -> Result<i32, MoriError> {
+    let relayed = tree.clone();
+    signals.forward_to(child.id(), Some(Box::new(move || relayed.kill())));
+    let timer = options.timeout.map(|timeout| {
+        let tree = tree.clone();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let expired = Arc::clone(&timed_out);
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            expired.store(true, Ordering::SeqCst);
+            log::info!("Timed out after {:?}; killing the command", timeout);
+            tree.kill();
+        });
+        (handle, timed_out)
+    });
+
+    let status = child.wait()?;
+    // Nothing the command started outlives it, even after the eBPF programs go
+    tree.kill_remaining();
+    if let Some(output) = child.output.take() {
+        output.finish();
+    }
+    if let Some((handle, timed_out)) = timer {
+        handle.abort();
+        if timed_out.load(Ordering::SeqCst) {
+            eprintln!(
+                "mori: the command timed out after {}s",
+                options.timeout.unwrap_or_default().as_secs()
+            );
+            return Ok(TIMEOUT_EXIT_CODE);
+        }
+    }
+    Ok(exit_code(status))
+}
+
 /// Wrapper for a child process that provides wait() functionality
 struct ChildProcess {
     pid: nix::unistd::Pid,
+    /// Threads copying the captured output (`--log-stdout`, `--prefix-output`)
+    output: Option<OutputPumps>,
 }
 
 impl ChildProcess {
@@ -136,38 +388,372 @@ impl ChildProcess {
     }
 }
 
+/// DNS state shared between the network eBPF program and the refresh tasks:
+/// the allowed domains' records, the nameservers and the denied domains' records
+type NetworkState = (
+    Arc<Mutex<NetworkEbpf>>,
+    Arc<Mutex<DnsCache>>,
+    Arc<Mutex<HashSet<IpAddr>>>,
+    Arc<Mutex<DnsCache>>,
+);
+
+/// eBPF programs enforcing the policy; they stay attached while this is alive
+struct EbpfEnforcement {
+    _bpf: Ebpf,
+    network: Option<NetworkState>,
+    plugin_handle: Option<tokio::task::JoinHandle<()>>,
+    flow_collector: Option<(
+        tokio::task::JoinHandle<Vec<FlowRecord>>,
+        Arc<ShutdownSignal>,
+    )>,
+    /// Readers of the denials of the file, process and socket programs and of
+    /// the SNI program
+    denials: Vec<(tokio::task::JoinHandle<()>, Arc<ShutdownSignal>)>,
+    /// Denied accesses and, in audit mode, connections
+    violations: ViolationCollector,
+    open_recorder: Option<(
+        tokio::task::JoinHandle<Vec<FileAccess>>,
+        Arc<ShutdownSignal>,
+    )>,
+    rule_counters: Option<file::RuleCounters>,
+    /// Enforces the file rules in place of the file programs
+    landlock: Option<Ruleset>,
+}
+
 /// Execute a command in a controlled cgroup with network and file access restrictions
 pub async fn execute_with_policy(
     command: &str,
     args: &[&str],
     policy: &Policy,
-) -> Result<i32, MoriError> {
+    options: &RunOptions,
+) -> Result<RunOutcome, MoriError> {
+    if let Some(dir) = &options.chdir
+        && !dir.is_dir()
+    {
+        return Err(MoriError::WorkingDirectory { path: dir.clone() });
+    }
+    // Before anything is set up that a signal would leave behind
+    let signals = SignalRelay::install();
+    let Some(scratch) = &options.overlay else {
+        return run_sandboxed(command, args, policy, options, &signals).await;
+    };
+    let target = std::env::current_dir()
+        .and_then(|dir| dir.canonicalize())
+        .map_err(|e| MoriError::Overlay {
+            path: scratch.clone(),
+            reason: format!("cannot resolve the working directory: {}", e),
+        })?;
+    let owner = command_credentials(options).map(|credentials| (credentials.uid, credentials.gid));
+    mount::prepare_overlay(scratch, &target, owner)?;
+
+    let outcome = run_sandboxed(command, args, policy, options, &signals).await?;
+    report_staged_changes(scratch, &target);
+    Ok(outcome)
+}
+
+/// Run the command with the backend `options.backend` selects
+async fn run_sandboxed(
+    command: &str,
+    args: &[&str],
+    policy: &Policy,
+    options: &RunOptions,
+    signals: &SignalRelay,
+) -> Result<RunOutcome, MoriError> {
+    let unprivileged = match options.backend {
+        Backend::Auto => (unsafe { libc::geteuid() }) != 0,
+        Backend::Ebpf => false,
+        Backend::Unprivileged => true,
+    };
+    if !unprivileged {
+        return run_in_cgroup(command, args, policy, options, signals).await;
+    }
+    if options.backend == Backend::Auto {
+        log::info!("Not running as root; using the unprivileged backend");
+    }
+    unprivileged::run(command, args, policy, options, signals)
+}
+
+/// Print the files the command changed through `--overlay`
+fn report_staged_changes(scratch: &std::path::Path, target: &std::path::Path) {
+    let upper = scratch.join(mount::OVERLAY_UPPER);
+    match mount::staged_changes(scratch, target) {
+        Ok(changes) if changes.is_empty() => {
+            eprintln!("mori: no changes staged in {}", upper.display());
+        }
+        Ok(changes) => {
+            eprintln!(
+                "mori: {} changes staged in {}:",
+                changes.len(),
+                upper.display()
+            );
+            for (change, path) in changes {
+                eprintln!("  {} {}", change, path.display());
+            }
+        }
+        Err(e) => log::warn!(
+            "Failed to list the changes staged in {}: {}",
+            upper.display(),
+            e
+        ),
+    }
+}
+
+/// Run the command in a new cgroup with the policy's eBPF programs attached
+async fn run_in_cgroup(
+    command: &str,
+    args: &[&str],
+    policy: &Policy,
+    options: &RunOptions,
+    signals: &SignalRelay,
+) -> Result<RunOutcome, MoriError> {
     let cgroup = CgroupManager::create()?;
+    let tree = ProcessTree::Cgroup(cgroup.path.clone());
+    if let Some(max) = policy.process.max_pids {
+        if options.audit {
+            eprintln!("mori: the process limit is not applied in audit mode");
+        } else {
+            cgroup.limit_pids(max)?;
+        }
+    }
 
-    // If network policy is allow-all and no file deny policy, run without restrictions
+    let seccomp = SeccompFilter::compile(&policy.process, options.audit);
+    if options.plugin.is_some() && matches!(policy.network.policy, AllowPolicy::All) {
+        log::warn!("All network access is allowed; the policy plugin will not be consulted");
+    }
+    if policy.network.enforce_sni && matches!(policy.network.policy, AllowPolicy::All) {
+        log::warn!("All network access is allowed; TLS server names will not be checked");
+    }
+
+    // If network policy is allow-all and no file, inbound or listen policy, run without restrictions
     // Still create a cgroup for consistency (no performance impact)
-    if matches!(policy.network.policy, AllowPolicy::All) && policy.file.denied_paths.is_empty() {
-        let mut child = spawn_command(command, args, &cgroup.path)?;
-        let status = child.wait()?;
-        return Ok(status.code().unwrap_or(-1));
+    if matches!(policy.network.policy, AllowPolicy::All)
+        && policy.network.denied.is_empty()
+        && policy.file.is_empty()
+        && !policy.process.has_exec_rules()
+        && !policy.inbound.restricted
+        && !policy.restricts_listen()
+        && policy.network.denied_socket_families().is_empty()
+        && policy.network.icmp_allowed()
+        && policy.network.unix.is_empty()
+        && !policy.network.dns.enforce
+        && !options.flow_log
+        && !options.file_inventory
+    {
+        let mut child = spawn_command(
+            command,
+            args,
+            &Sandbox::Cgroup(&cgroup.path, None),
+            &policy.process,
+            &[],
+            options,
+            seccomp.as_ref(),
+        )?;
+        let exit_code = wait_command(&mut child, &tree, options, signals)?;
+        return Ok(RunOutcome::exited(exit_code));
+    }
+
+    let domain_names = match &policy.network.policy {
+        AllowPolicy::Entries {
+            allowed_domains, ..
+        } => allowed_domains.clone(),
+        AllowPolicy::All => vec![],
+    };
+
+    let enforcement = attach_ebpf(policy, options, &cgroup).await;
+    if let Some(outcome) = signals.interrupted() {
+        return Ok(outcome);
     }
+    let enforcement = match enforcement {
+        Ok(enforcement) => enforcement,
+        // The shim enforces, so it cannot stand in for the programs in audit mode
+        Err(e) if options.preload_fallback && !options.audit && is_ebpf_unavailable(&e) => {
+            if options.flow_log {
+                eprintln!("mori: the flow log is not recorded with the LD_PRELOAD shim");
+            }
+            if options.file_inventory {
+                eprintln!("mori: the file inventory is not recorded with the LD_PRELOAD shim");
+            }
+            if options.file_rule_stats {
+                eprintln!("mori: file rule hits are not counted with the LD_PRELOAD shim");
+            }
+            let mut child =
+                spawn_with_preload(command, args, policy, options, &cgroup, seccomp.as_ref(), e)?;
+            let exit_code = wait_command(&mut child, &tree, options, signals)?;
+            return Ok(RunOutcome::exited(exit_code));
+        }
+        Err(e) => {
+            if is_ebpf_unavailable(&e) {
+                eprintln!("mori: run `mori check` to see what the kernel lacks for eBPF");
+            }
+            return Err(e);
+        }
+    };
 
+    // Spawn the command as a child process with privilege dropping if needed
+    // The process is added to the cgroup before exec via pre_exec hook
+    let mut child = spawn_command(
+        command,
+        args,
+        &Sandbox::Cgroup(&cgroup.path, enforcement.landlock.as_ref()),
+        &policy.process,
+        &[],
+        options,
+        seccomp.as_ref(),
+    )?;
+
+    log::info!(
+        "Spawned child process {} (added to cgroup via pre-exec)",
+        child.id()
+    );
+
+    // Spawn DNS refresh tasks if needed
+    let schedule = RefreshSchedule::new(
+        options.dns_refresh_margin,
+        policy.network.dns.refresh_interval,
+    );
+    let refresh_handle =
+        if let Some((ref ebpf, ref dns_cache, ref allowed_dns_ips, _)) = enforcement.network {
+            if !domain_names.is_empty() {
+                let shutdown_signal = ShutdownSignal::new();
+                let resolver =
+                    SystemDnsResolver::with_nameservers(policy.network.dns.nameservers.clone());
+                let handle = spawn_refresh(
+                    domain_names.clone(),
+                    Arc::clone(dns_cache),
+                    Arc::clone(ebpf),
+                    Arc::clone(allowed_dns_ips),
+                    Arc::clone(&shutdown_signal),
+                    resolver,
+                    schedule,
+                );
+                Some((handle, shutdown_signal))
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+    let deny_refresh_handle = match &enforcement.network {
+        Some((ebpf, _, _, deny_cache)) if !policy.network.denied.denied_domains.is_empty() => {
+            let shutdown_signal = ShutdownSignal::new();
+            let handle = spawn_deny_refresh(
+                policy.network.denied.denied_domains.clone(),
+                Arc::clone(deny_cache),
+                Arc::clone(ebpf),
+                Arc::clone(&shutdown_signal),
+                SystemDnsResolver::with_nameservers(policy.network.dns.nameservers.clone()),
+                schedule,
+            );
+            Some((handle, shutdown_signal))
+        }
+        _ => None,
+    };
+
+    // Wait for child process to finish
+    let exit_code = wait_command(&mut child, &tree, options, signals)?;
+    if let Some(handle) = &enforcement.plugin_handle {
+        handle.abort();
+    }
+
+    // Shutdown DNS refresh tasks if running
+    for (handle, shutdown_signal) in refresh_handle.into_iter().chain(deny_refresh_handle) {
+        shutdown_signal.shutdown();
+        if let Some(h) = handle {
+            h.await.map_err(|_| MoriError::RefreshTaskPanic)??;
+        }
+    }
+
+    if let (Some(path), Some((_, dns_cache, _, deny_cache))) =
+        (&options.dns_cache, &enforcement.network)
+    {
+        let now = Instant::now();
+        let saved = dns_cache
+            .lock()
+            .unwrap()
+            .save(path, now)
+            .and_then(|()| deny_cache.lock().unwrap().save(path, now));
+        if let Err(e) = saved {
+            log::warn!("Failed to save the DNS cache to {}: {}", path.display(), e);
+        }
+    }
+
+    let mut outcome = RunOutcome::exited(exit_code);
+    if let Some((handle, shutdown_signal)) = enforcement.flow_collector {
+        shutdown_signal.shutdown();
+        match handle.await {
+            Ok(flows) => outcome.flows = flows,
+            Err(e) => log::error!("Connection event collector failed: {}", e),
+        }
+    }
+    for (handle, shutdown_signal) in enforcement.denials {
+        shutdown_signal.shutdown();
+        if let Err(e) = handle.await {
+            log::error!("Denial event reader failed: {}", e);
+        }
+    }
+    let report = enforcement.violations.finish().await;
+    if !report.is_empty() {
+        outcome.report = Some(report);
+    }
+    if let Some((handle, shutdown_signal)) = enforcement.open_recorder {
+        shutdown_signal.shutdown();
+        match handle.await {
+            Ok(files) => outcome.files = files,
+            Err(e) => log::error!("File open event reader failed: {}", e),
+        }
+    }
+    if let Some(counters) = &enforcement.rule_counters {
+        outcome.file_rules = counters.read();
+    }
+    Ok(outcome)
+}
+
+/// Load the eBPF programs for the policy and attach them to the cgroup
+async fn attach_ebpf(
+    policy: &Policy,
+    options: &RunOptions,
+    cgroup: &CgroupManager,
+) -> Result<EbpfEnforcement, MoriError> {
     // Extract entries from network policy
-    let (allowed_ipv4, allowed_cidr, domain_names) = match &policy.network.policy {
+    let (allowed_ipv4, allowed_cidr, allowed_ipv6, allowed_cidr_v6, domain_names, ports) =
+        match &policy.network.policy {
+            AllowPolicy::Entries {
+                allowed_ipv4,
+                allowed_cidr,
+                allowed_ipv6,
+                allowed_cidr_v6,
+                allowed_domains,
+                allowed_ports,
+                ..
+            } => (
+                allowed_ipv4.clone(),
+                allowed_cidr.clone(),
+                allowed_ipv6.clone(),
+                allowed_cidr_v6.clone(),
+                allowed_domains.clone(),
+                allowed_ports.clone(),
+            ),
+            AllowPolicy::All => (vec![], vec![], vec![], vec![], vec![], BTreeMap::new()),
+        };
+    // Names under a suffix are only known once the command looks them up
+    let domain_suffixes: &[String] = match &policy.network.policy {
         AllowPolicy::Entries {
-            allowed_ipv4,
-            allowed_cidr,
-            allowed_domains,
-        } => (
-            allowed_ipv4.clone(),
-            allowed_cidr.clone(),
-            allowed_domains.clone(),
-        ),
-        AllowPolicy::All => (vec![], vec![], vec![]),
+            allowed_domain_suffixes,
+            ..
+        } => allowed_domain_suffixes,
+        AllowPolicy::All => &[],
     };
 
-    let resolver = SystemDnsResolver;
-    let resolved = resolver.resolve_domains(&domain_names).await?;
+    let resolver = SystemDnsResolver::with_nameservers(policy.network.dns.nameservers.clone());
+    let saved = match &options.dns_cache {
+        Some(path) => DnsCache::load_saved(path, Instant::now()),
+        None => HashMap::new(),
+    };
+    let strict = policy.network.dns.strict;
+    let resolved = resolve_unless_saved(&resolver, &domain_names, &saved, strict).await?;
+    let denied = &policy.network.denied;
+    let resolved_denied =
+        resolve_unless_saved(&resolver, &denied.denied_domains, &saved, strict).await?;
 
     // Load eBPF programs
     let mut bpf = Ebpf::load(ebpf::EBPF_ELF)?;
@@ -176,88 +762,493 @@ pub async fn execute_with_policy(
     if let Err(e) = aya_log::EbpfLogger::init(&mut bpf) {
         log::warn!("Failed to initialize eBPF logger: {}", e);
     }
+    if options.audit {
+        ebpf::enable_audit(&mut bpf)?;
+    }
 
-    // Attach network control eBPF programs if needed
-    let network_ebpf = if !matches!(policy.network.policy, AllowPolicy::All) {
-        let ebpf = Arc::new(Mutex::new(NetworkEbpf::load_and_attach(cgroup.fd())?));
+    // Attach network control eBPF programs if needed; the flow log and the deny
+    // list need them even when every connection is allowed
+    let allow_all = matches!(policy.network.policy, AllowPolicy::All);
+    let network = if !allow_all
+        || options.flow_log
+        || !denied.is_empty()
+        || policy.network.dns.enforce
+    {
+        let mut network_ebpf = NetworkEbpf::load_and_attach(cgroup.fd(), options.deny_errno)?;
+        if options.audit {
+            network_ebpf.enable_audit()?;
+        }
+        let ebpf = Arc::new(Mutex::new(network_ebpf));
 
-        let dns_cache = Arc::new(Mutex::new(DnsCache::default()));
+        let dns = &policy.network.dns;
+        let new_cache = || {
+            DnsCache::with_ttl_bounds(
+                dns.min_ttl.map(Duration::from_secs),
+                dns.max_ttl.map(Duration::from_secs),
+            )
+        };
+        let dns_cache = Arc::new(Mutex::new(new_cache()));
         let allowed_dns_ips = Arc::new(Mutex::new(HashSet::new()));
+        let deny_cache = Arc::new(Mutex::new(new_cache()));
         let now = Instant::now();
 
         // Add allowed IP addresses and CIDR ranges to the map
+        // They are pinned in the DNS cache so that DNS updates and eviction
+        // leave them alone
         {
             let mut ebpf_guard = ebpf.lock().unwrap();
+            let mut cache = dns_cache.lock().unwrap();
+
+            // Allow localhost (127.0.0.1 and ::1) by default
+            let mut entries: Vec<(IpAddr, u8)> = Vec::new();
+            if !policy.network.deny_localhost {
+                entries.push((Ipv4Addr::LOCALHOST.into(), 32));
+                entries.push((Ipv6Addr::LOCALHOST.into(), 128));
+            }
+            if allow_all {
+                entries.push((Ipv4Addr::UNSPECIFIED.into(), 0));
+                entries.push((Ipv6Addr::UNSPECIFIED.into(), 0));
+            }
+            // Single IPs are /32 (IPv4) or /128 (IPv6) entries
+            entries.extend(allowed_ipv4.iter().map(|&ip| (ip.into(), 32)));
+            entries.extend(allowed_cidr.iter().map(|&(ip, len)| (ip.into(), len)));
+            entries.extend(allowed_ipv6.iter().map(|&ip| (ip.into(), 128)));
+            entries.extend(allowed_cidr_v6.iter().map(|&(ip, len)| (ip.into(), len)));
 
-            // Always allow localhost (127.0.0.1) by default
-            let localhost: Ipv4Addr = "127.0.0.1".parse().unwrap();
-            ebpf_guard.allow_network(localhost, 32)?; // /32 = single IP
-            log::info!("Added {}/32 (localhost) to network allow list", localhost);
+            for (network, prefix_len) in entries {
+                // Single addresses are keyed without the prefix length
+                let host = if prefix_len == max_prefix_len(network) {
+                    network.to_string()
+                } else {
+                    format!("{}/{}", network, prefix_len)
+                };
+                match ports.get(&host) {
+                    Some(ports) => {
+                        for &port in ports {
+                            ebpf_guard.allow_network(network, prefix_len, Some(port))?;
+                            log::info!(
+                                "Added {}/{} port {} to network allow list",
+                                network,
+                                prefix_len,
+                                port
+                            );
+                        }
+                    }
+                    _ => {
+                        ebpf_guard.allow_network(network, prefix_len, None)?;
+                        log::info!("Added {}/{} to network allow list", network, prefix_len);
+                    }
+                }
+                cache.pin(network, prefix_len);
+            }
 
-            for &ip in &allowed_ipv4 {
-                ebpf_guard.allow_network(ip, 32)?; // /32 = single IP
-                log::info!("Added {}/32 to network allow list", ip);
+            for domain in &domain_names {
+                if let Some(ports) = ports.get(domain) {
+                    cache.restrict_ports(domain, ports.clone());
+                }
+            }
+            // Static hosts were allowed by address above; naming them lets
+            // the flow log and explanations refer to the host
+            for (host, addrs) in &policy.network.hosts {
+                cache.seed(host, addrs);
             }
-            for &(network, prefix_len) in &allowed_cidr {
-                ebpf_guard.allow_network(network, prefix_len)?;
-                log::info!("Added {}/{} to network allow list", network, prefix_len);
+
+            // Denied destinations take precedence over every allow list entry
+            let denied_entries = denied
+                .denied_cidr
+                .iter()
+                .map(|&(ip, len)| (IpAddr::from(ip), len))
+                .chain(
+                    denied
+                        .denied_cidr_v6
+                        .iter()
+                        .map(|&(ip, len)| (IpAddr::from(ip), len)),
+                );
+            for (network, prefix_len) in denied_entries {
+                ebpf_guard.deny_network(network, prefix_len)?;
+                log::info!("Added {}/{} to network deny list", network, prefix_len);
+            }
+
+            // Nameservers added below are then only allowed on port 53
+            if policy.network.dns.enforce {
+                let bypass = policy.network.dns_bypass_endpoints();
+                ebpf_guard.enforce_dns(&bypass)?;
+                log::info!(
+                    "Enforced DNS through the nameservers; blocked {} DNS-over-HTTPS/TLS addresses",
+                    bypass.len()
+                );
+            }
+
+            // Connected ping sockets skip the allow lists when ICMP is allowed
+            if !allow_all && policy.network.icmp_allowed() {
+                ebpf_guard.allow_icmp()?;
+                log::info!("Allowed ICMP to any host");
             }
         }
 
+        apply_denied_records(&deny_cache, &ebpf, now, resolved_denied.domains)?;
         apply_domain_records(&dns_cache, &ebpf, now, resolved.domains.to_vec())?;
-        apply_dns_servers(&ebpf, &allowed_dns_ips, resolved.dns_v4.clone())?;
+        apply_dns_servers(&dns_cache, &ebpf, &allowed_dns_ips, resolved.dns_servers())?;
+
+        // Allow the addresses of the command's own lookups as the answers arrive;
+        // domains restricted to some ports are only allowed through the refresh task
+        let snooped: Vec<String> = domain_names
+            .iter()
+            .filter(|domain| !ports.contains_key(*domain))
+            .cloned()
+            .collect();
+        if !snooped.is_empty() || !domain_suffixes.is_empty() {
+            let enabled = ebpf.lock().unwrap().enable_dns_snooping(
+                cgroup.fd(),
+                &snooped,
+                domain_suffixes,
+                &resolved.dns_v4,
+            );
+            if let Err(e) = enabled {
+                log::warn!("Failed to read DNS answers in the kernel: {}", e);
+            }
+        }
+
+        // Hold TLS connections to the allowed domains by name; destinations
+        // allowed by address, pins and hosts included, are not checked
+        if policy.network.enforce_sni && !allow_all {
+            let exempt: Vec<(IpAddr, u8)> = [
+                (IpAddr::V4(Ipv4Addr::LOCALHOST), 8),
+                (IpAddr::V6(Ipv6Addr::LOCALHOST), 128),
+            ]
+            .into_iter()
+            .chain(allowed_ipv4.iter().map(|&ip| (IpAddr::V4(ip), 32)))
+            .chain(allowed_cidr.iter().map(|&(ip, len)| (IpAddr::V4(ip), len)))
+            .chain(allowed_ipv6.iter().map(|&ip| (IpAddr::V6(ip), 128)))
+            .chain(
+                allowed_cidr_v6
+                    .iter()
+                    .map(|&(ip, len)| (IpAddr::V6(ip), len)),
+            )
+            .collect();
+            ebpf.lock().unwrap().enforce_sni(
+                cgroup.fd(),
+                &domain_names,
+                domain_suffixes,
+                &exempt,
+            )?;
+            log::info!(
+                "Checking TLS server names against {} allowed domains and {} suffixes",
+                domain_names.len(),
+                domain_suffixes.len()
+            );
+        }
+
+        Some((ebpf, dns_cache, allowed_dns_ips, deny_cache))
+    } else {
+        None
+    };
+
+    // Denied accesses are explained as they are reported; symlink targets
+    // are denied, and explained, like the listed paths
+    let mut resolved = policy.clone();
+    resolved.file = policy.file.with_symlink_targets();
+    let violations = ViolationCollector::spawn_explaining(Explainer::new(&resolved));
 
-        Some((ebpf, dns_cache, allowed_dns_ips))
+    // Attach file access control eBPF programs if needed; without the BPF
+    // LSM, Landlock enforces the path rules instead
+    let mut rule_counters = None;
+    let mut landlock = None;
+    if !policy.file.is_empty() {
+        let attached = match file::bpf_lsm_active() {
+            Some(false) => Err(MoriError::BpfLsmInactive),
+            _ => file::FileEbpf::load_and_attach(&mut bpf, &resolved.file, cgroup.fd()),
+        };
+        match attached {
+            Ok(()) => {
+                if options.file_rule_stats {
+                    rule_counters = Some(file::count_rule_hits(&mut bpf, &resolved.file)?);
+                }
+            }
+            // Landlock cannot report what it would deny
+            Err(e) if is_ebpf_unavailable(&e) && options.audit => {
+                eprintln!("mori: file rules are not audited: {}", e);
+            }
+            Err(e) if is_ebpf_unavailable(&e) => {
+                landlock = Some(FileLandlock::load(&resolved.file, e)?);
+                if options.file_rule_stats {
+                    eprintln!("mori: file rule hits are not counted with Landlock");
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Restrict which programs the command may run
+    if policy.process.has_exec_rules() {
+        exec::ExecEbpf::load_and_attach(&mut bpf, &policy.process, cgroup.fd())?;
+    }
+
+    // Keep the command from tracing processes outside the sandbox, which
+    // would reach their memory; the BPF LSM is often disabled, so this is
+    // only enforced when possible
+    if let Err(e) = ptrace::deny_outside_ptrace(&mut bpf, cgroup.fd()) {
+        log::warn!(
+            "Failed to restrict ptrace ({}); the command may read the memory of processes outside the sandbox",
+            e
+        );
+    }
+    // Likewise for kernel modules and BPF, which a command running as root
+    // could use to switch off the programs above
+    if let Err(e) = kernel::deny_kernel_changes(&mut bpf, cgroup.fd()) {
+        log::warn!(
+            "Failed to restrict kernel module loading and bpf() ({}); a command running as root may bypass mori",
+            e
+        );
+    }
+
+    // Record every open for the file inventory, on top of any file rules
+    let open_recorder = if options.file_inventory {
+        let events = file::observe_opens(&mut bpf, cgroup.fd())?;
+        let shutdown_signal = ShutdownSignal::new();
+        let handle = spawn_open_recorder(events, Arc::clone(&shutdown_signal));
+        Some((handle, shutdown_signal))
     } else {
         None
     };
 
-    // Attach file access control eBPF programs if needed (deny-list mode)
-    if !policy.file.denied_paths.is_empty() {
-        file::FileEbpf::load_and_attach(&mut bpf, &policy.file, cgroup.fd())?;
+    // Refuse raw and ICMP sockets that would bypass the connect hooks. The
+    // BPF LSM is often disabled, so the defaults are only enforced when
+    // possible; configured families and `allow_icmp = false` must be enforced.
+    let families = policy.network.denied_socket_families();
+    let deny_icmp = !policy.network.icmp_allowed();
+    if !families.is_empty() || deny_icmp {
+        let configured = policy
+            .network
+            .denied_families
+            .as_ref()
+            .is_some_and(|families| !families.is_empty())
+            || policy.network.allow_icmp == Some(false);
+        match socket::SocketEbpf::load_and_attach(&mut bpf, &families, deny_icmp, cgroup.fd()) {
+            Err(e) if !configured => log::warn!(
+                "Failed to restrict socket families ({}); raw and ICMP sockets can bypass the network rules",
+                e
+            ),
+            result => result?,
+        }
     }
 
-    // Spawn the command as a child process with privilege dropping if needed
-    // The process is added to the cgroup before exec via pre_exec hook
-    let mut child = spawn_command(command, args, &cgroup.path)?;
+    // Check connections to abstract unix sockets, which file rules never see
+    if !policy.network.unix.is_empty() {
+        socket::SocketEbpf::restrict_unix(&mut bpf, &policy.network.unix, cgroup.fd())?;
+    }
 
-    log::info!(
-        "Spawned child process {} (added to cgroup via pre-exec)",
-        child.id()
-    );
+    // Filter connections to the command's listening sockets by peer address
+    if policy.inbound.restricted {
+        inbound::InboundEbpf::load_and_attach(&mut bpf, &policy.inbound, cgroup.fd())?;
+    }
+
+    // Only let the command listen on the allowed ports
+    if policy.restricts_listen() {
+        inbound::InboundEbpf::restrict_listen(&mut bpf, &policy.inbound, cgroup.fd())?;
+    }
 
-    // Spawn DNS refresh task if needed
-    let refresh_handle = if let Some((ref ebpf, ref dns_cache, ref allowed_dns_ips)) = network_ebpf
+    // Report what the programs above and the SNI program deny, or would
+    // deny in audit mode; the connect programs report through the flow events
+    let mut denial_events = vec![ebpf::enable_denial_events(&mut bpf)?];
+    if let Some((ebpf, _, _, _)) = &network
+        && policy.network.enforce_sni
+        && !allow_all
     {
-        if !domain_names.is_empty() {
+        denial_events.push(ebpf.lock().unwrap().enable_denial_events()?);
+    }
+    let denials = denial_events
+        .into_iter()
+        .map(|events| {
             let shutdown_signal = ShutdownSignal::new();
-            let resolver = SystemDnsResolver;
-            let handle = spawn_refresh(
-                domain_names.clone(),
-                Arc::clone(dns_cache),
-                Arc::clone(ebpf),
-                Arc::clone(allowed_dns_ips),
+            let handle =
+                spawn_denial_reader(events, violations.sender(), Arc::clone(&shutdown_signal));
+            (handle, shutdown_signal)
+        })
+        .collect();
+
+    // Hand connections matching no allow rule to the policy plugin
+    let plugin_handle = match (&options.plugin, &network) {
+        (Some(path), Some((ebpf, _, _, _))) if !allow_all => Some(start_plugin(path, ebpf)?),
+        _ => None,
+    };
+
+    let flow_collector = match &network {
+        Some((ebpf, dns_cache, allowed_dns_ips, _)) => {
+            let events = ebpf.lock().unwrap().enable_connect_events()?;
+            let index = RuleIndex {
+                allow_all,
+                allowed_ips: allowed_ipv4
+                    .iter()
+                    .map(|&ip| IpAddr::V4(ip))
+                    .chain(allowed_ipv6.iter().map(|&ip| IpAddr::V6(ip)))
+                    .collect(),
+                allowed_networks: allowed_cidr
+                    .iter()
+                    .map(|&(ip, len)| (IpAddr::V4(ip), len))
+                    .chain(
+                        allowed_cidr_v6
+                            .iter()
+                            .map(|&(ip, len)| (IpAddr::V6(ip), len)),
+                    )
+                    .collect(),
+                dns_cache: Arc::clone(dns_cache),
+                dns_servers: Arc::clone(allowed_dns_ips),
+                explainer: Explainer::new(policy),
+            };
+            // Lookups go through mori's own sockets, outside the cgroup
+            let reverse = if options.reverse_dns {
+                match resolver.reverse() {
+                    Ok(reverse) => Some(reverse),
+                    Err(e) => {
+                        log::warn!("Failed to set up reverse DNS lookups: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            let shutdown_signal = ShutdownSignal::new();
+            let handle = spawn_flow_collector(
+                events,
+                index,
+                options.flow_log,
+                options.audit.then(|| violations.sender()),
+                reverse,
                 Arc::clone(&shutdown_signal),
-                resolver,
             );
             Some((handle, shutdown_signal))
-        } else {
-            None
         }
+        None => None,
+    };
+
+    Ok(EbpfEnforcement {
+        _bpf: bpf,
+        network,
+        plugin_handle,
+        flow_collector,
+        denials,
+        violations,
+        open_recorder,
+        rule_counters,
+        landlock,
+    })
+}
+
+/// Resolve every domain, taking the records of the domains in `saved` from
+/// there instead
+///
+/// With `strict`, lookups are retried and a domain without addresses is an
+/// error (see `resolve_strict`).
+async fn resolve_unless_saved(
+    resolver: &SystemDnsResolver,
+    domains: &[String],
+    saved: &HashMap<String, Vec<Entry>>,
+    strict: bool,
+) -> Result<ResolvedAddresses, MoriError> {
+    let (cached, uncached): (Vec<String>, Vec<String>) = domains
+        .iter()
+        .cloned()
+        .partition(|domain| saved.contains_key(domain));
+    let mut resolved = if strict {
+        resolve_strict(resolver, &uncached, STRICT_RESOLVE_DEADLINE).await?
     } else {
-        None
+        resolver.resolve_domains(&uncached).await?
     };
+    resolved.ensure_resolved()?;
+    if !cached.is_empty() {
+        log::info!("Reusing saved DNS records of {}", cached.join(", "));
+    }
+    resolved
+        .domains
+        .extend(cached.into_iter().map(|domain| DomainRecords {
+            records: saved[&domain].clone(),
+            domain,
+        }));
+    Ok(resolved)
+}
 
-    // Wait for child process to finish
-    let status = child.wait()?;
+/// Check if an error means the kernel cannot run mori's eBPF programs
+fn is_ebpf_unavailable(error: &MoriError) -> bool {
+    matches!(
+        error,
+        MoriError::BpfLoad(_)
+            | MoriError::ProgramNotFound { .. }
+            | MoriError::ProgramPrepare { .. }
+            | MoriError::ProgramAttach { .. }
+            | MoriError::Map(_)
+            | MoriError::Btf(_)
+            | MoriError::BpfLsmInactive
+    )
+}
 
-    // Shutdown DNS refresh task if running
-    if let Some((handle, shutdown_signal)) = refresh_handle {
-        shutdown_signal.shutdown();
-        if let Some(h) = handle {
-            h.await.map_err(|_| MoriError::RefreshTaskPanic)??;
-        }
+/// Start the command with the LD_PRELOAD shim enforcing the policy in userspace
+///
+/// `reason` is the error that made eBPF enforcement impossible; it is returned
+/// when the shim cannot be found.
+fn spawn_with_preload(
+    command: &str,
+    args: &[&str],
+    policy: &Policy,
+    options: &RunOptions,
+    cgroup: &CgroupManager,
+    seccomp: Option<&SeccompFilter>,
+    reason: MoriError,
+) -> Result<ChildProcess, MoriError> {
+    let Some(library) = preload::find_library() else {
+        eprintln!(
+            "mori: eBPF enforcement is unavailable and the LD_PRELOAD shim was not found ({})",
+            preload::search_hint()
+        );
+        return Err(reason);
+    };
+    eprintln!(
+        "mori: eBPF enforcement is unavailable ({}); falling back to the LD_PRELOAD shim {}",
+        reason,
+        library.display()
+    );
+    eprintln!(
+        "mori: warning: this is weaker enforcement; statically linked programs and direct system calls bypass it"
+    );
+
+    if policy.inbound.restricted {
+        eprintln!("mori: warning: inbound connections are not filtered with the LD_PRELOAD shim");
     }
 
-    Ok(status.code().unwrap_or(-1))
+    let current_preload = std::env::var("LD_PRELOAD").ok();
+    let env = preload::environment(
+        policy,
+        options.deny_errno,
+        &library,
+        current_preload.as_deref(),
+    );
+    spawn_command(
+        command,
+        args,
+        &Sandbox::Cgroup(&cgroup.path, None),
+        &policy.process,
+        &env,
+        options,
+        seccomp,
+    )
+}
+
+/// Load the WebAssembly policy plugin and start answering the BPF program's requests
+#[cfg(feature = "wasm-plugin")]
+fn start_plugin(
+    path: &Path,
+    ebpf: &Arc<Mutex<NetworkEbpf>>,
+) -> Result<tokio::task::JoinHandle<()>, MoriError> {
+    let wasm = plugin::WasmPlugin::load(path)?;
+    let requests = ebpf.lock().unwrap().enable_plugin()?;
+    log::info!("Loaded policy plugin {}", path.display());
+    Ok(plugin::spawn_plugin(wasm, requests, Arc::clone(ebpf)))
+}
+
+#[cfg(not(feature = "wasm-plugin"))]
+fn start_plugin(
+    _path: &Path,
+    _ebpf: &Arc<Mutex<NetworkEbpf>>,
+) -> Result<tokio::task::JoinHandle<()>, MoriError> {
+    Err(MoriError::PluginUnsupported)
 }
@@ -0,0 +1,231 @@
+use std::{
+    ffi::CString,
+    fmt, fs, io,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::error::MoriError;
+
+/// Directories `--private-tmp` replaces with an empty tmpfs
+const PRIVATE_TMP_DIRS: &[&str] = &["/tmp", "/var/tmp"];
+
+/// Subdirectories of the `--overlay` directory: the overlayfs upper layer,
+/// which receives the staged writes, and its work directory
+pub(super) const OVERLAY_UPPER: &str = "upper";
+const OVERLAY_WORK: &str = "work";
+
+/// Move the calling process into a new mount namespace whose mounts are not
+/// propagated back to the host
+///
+/// Called in the forked child before exec; the namespace, and everything
+/// mounted in it, goes away with the command.
+pub(super) fn unshare_mounts() -> io::Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // systemd makes / a shared mount, which would leak the new mounts to the host
+    mount(
+        None,
+        Path::new("/"),
+        None,
+        libc::MS_REC | libc::MS_PRIVATE,
+        None,
+    )
+}
+
+/// Mount an empty, world-writable tmpfs on /tmp and /var/tmp
+pub(super) fn private_tmp() -> io::Result<()> {
+    for dir in PRIVATE_TMP_DIRS.iter().map(Path::new) {
+        if !dir.is_dir() {
+            continue;
+        }
+        mount(
+            Some("tmpfs"),
+            dir,
+            Some("tmpfs"),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            Some("mode=1777"),
+        )?;
+        log::info!("Mounted a private tmpfs on {}", dir.display());
+    }
+    Ok(())
+}
+
+/// Create the upper and work directories of an overlay on `target` below `scratch`
+///
+/// overlayfs refuses layers inside each other, so `scratch` must be outside
+/// of `target`. `owner` (the user the command runs as) gets the directories,
+/// so that they can review and delete the staged files.
+pub(super) fn prepare_overlay(
+    scratch: &Path,
+    target: &Path,
+    owner: Option<(u32, u32)>,
+) -> Result<(), MoriError> {
+    let invalid = |reason: String| MoriError::Overlay {
+        path: scratch.to_path_buf(),
+        reason,
+    };
+    fs::create_dir_all(scratch).map_err(|e| invalid(e.to_string()))?;
+    let scratch = scratch.canonicalize().map_err(|e| invalid(e.to_string()))?;
+    if scratch.starts_with(target) || target.starts_with(&scratch) {
+        return Err(invalid(format!(
+            "it must be outside of the working directory {}",
+            target.display()
+        )));
+    }
+    let upper = scratch.join(OVERLAY_UPPER);
+    if upper
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(invalid(format!(
+            "{} holds the changes of an earlier run",
+            upper.display()
+        )));
+    }
+    for dir in [&scratch, &upper, &scratch.join(OVERLAY_WORK)] {
+        fs::create_dir_all(dir).map_err(|e| invalid(e.to_string()))?;
+        if let Some((uid, gid)) = owner {
+            std::os::unix::fs::chown(dir, Some(uid), Some(gid))
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Mount an overlay on `target` staging its writes below `scratch`, and make
+/// it the working directory
+///
+/// Called in the forked child after `unshare_mounts`; the working directory
+/// still refers to the directory below the overlay until it is entered again.
+pub(super) fn overlay(scratch: &Path, target: &Path) -> io::Result<()> {
+    let scratch = scratch.canonicalize()?;
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        target.display(),
+        scratch.join(OVERLAY_UPPER).display(),
+        scratch.join(OVERLAY_WORK).display()
+    );
+    mount(Some("overlay"), target, Some("overlay"), 0, Some(&options))?;
+    std::env::set_current_dir(target)
+}
+
+/// Kind of a change staged in an overlay's upper layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Change {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Change::Added => "A",
+            Change::Modified => "M",
+            Change::Deleted => "D",
+        })
+    }
+}
+
+/// Files changed through the overlay, relative to `target`, in path order
+///
+/// overlayfs records a deleted file as a 0/0 character device (a whiteout);
+/// other files are new unless `target` has them.
+pub(super) fn staged_changes(scratch: &Path, target: &Path) -> io::Result<Vec<(Change, PathBuf)>> {
+    let upper = scratch.join(OVERLAY_UPPER);
+    let mut changes = Vec::new();
+    let mut pending = vec![upper.clone()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            let relative = path.strip_prefix(&upper).unwrap_or(&path).to_path_buf();
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
+                changes.push((Change::Deleted, relative));
+            } else if target.join(&relative).symlink_metadata().is_ok() {
+                changes.push((Change::Modified, relative));
+            } else {
+                changes.push((Change::Added, relative));
+            }
+        }
+    }
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(changes)
+}
+
+/// mount(2)
+fn mount(
+    source: Option<&str>,
+    target: &Path,
+    fstype: Option<&str>,
+    flags: libc::c_ulong,
+    data: Option<&str>,
+) -> io::Result<()> {
+    let cstring = |value: &[u8]| {
+        CString::new(value).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+    };
+    let source = source.map(|value| cstring(value.as_bytes())).transpose()?;
+    let target = cstring(target.as_os_str().as_bytes())?;
+    let fstype = fstype.map(|value| cstring(value.as_bytes())).transpose()?;
+    let data = data.map(|value| cstring(value.as_bytes())).transpose()?;
+    let ptr = |value: &Option<CString>| value.as_ref().map_or(std::ptr::null(), |c| c.as_ptr());
+
+    let ret = unsafe {
+        libc::mount(
+            ptr(&source),
+            target.as_ptr(),
+            ptr(&fstype),
+            flags,
+            ptr(&data) as *const libc::c_void,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepare_overlay_rejects_scratch_inside_target() {
+        let target = tempfile::tempdir().unwrap();
+        let target = target.path().canonicalize().unwrap();
+        let err = prepare_overlay(&target.join("staged"), &target, None).unwrap_err();
+        assert!(matches!(err, MoriError::Overlay { .. }));
+
+        let scratch = tempfile::tempdir().unwrap();
+        prepare_overlay(scratch.path(), &target, None).unwrap();
+        assert!(scratch.path().join(OVERLAY_UPPER).is_dir());
+        assert!(scratch.path().join(OVERLAY_WORK).is_dir());
+    }
+
+    #[test]
+    fn staged_changes_lists_new_and_modified_files() {
+        let target = tempfile::tempdir().unwrap();
+        fs::write(target.path().join("Cargo.toml"), "").unwrap();
+        let scratch = tempfile::tempdir().unwrap();
+        let upper = scratch.path().join(OVERLAY_UPPER);
+        fs::create_dir_all(upper.join("src")).unwrap();
+        fs::write(upper.join("Cargo.toml"), "[package]").unwrap();
+        fs::write(upper.join("src/new.rs"), "").unwrap();
+
+        assert_eq!(
+            staged_changes(scratch.path(), target.path()).unwrap(),
+            vec![
+                (Change::Modified, PathBuf::from("Cargo.toml")),
+                (Change::Added, PathBuf::from("src/new.rs")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    net::Ipv4Addr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use aya::maps::{MapData, RingBuf};
+#[cfg(test)]
+use mockall::automock;
+use tokio::io::unix::AsyncFd;
+use wasmtime::{Config, Engine, Linker, Module, Store, TypedFunc};
+
+use crate::error::MoriError;
+
+use super::ebpf::NetworkEbpf;
+
+// Plugin verdicts (matching constants in mori-bpf)
+pub const VERDICT_ALLOW: u8 = 1;
+pub const VERDICT_DENY: u8 = 2;
+
+/// Function the plugin module must export: `(addr: i32, port: i32) -> i32`
+const DECIDE_CONNECT: &str = "decide_connect";
+/// Fuel given to every plugin call, bounding how long a single decision may run
+const FUEL_PER_CALL: u64 = 1_000_000;
+
+/// User-supplied WebAssembly module deciding on connections that match no allow rule
+///
+/// The module exports `decide_connect`, called with the IPv4 address (as a
+/// big-endian integer, e.g. 10.0.0.1 = 0x0a000001) and the destination port. A
+/// non-zero result allows the connection. No host functions are provided, so
+/// a decision can only depend on its arguments and the module's own state.
+pub struct WasmPlugin {
+    store: Store<()>,
+    decide_connect: TypedFunc<(i32, i32), i32>,
+}
+
+impl WasmPlugin {
+    /// Compile and instantiate a plugin from a `.wasm` (or `.wat`) file
+    pub fn load(path: &Path) -> Result<Self, MoriError> {
+        Self::instantiate(path).map_err(|e| MoriError::PluginLoad {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+    }
+
+    fn instantiate(path: &Path) -> wasmtime::Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, path)?;
+
+        let mut store = Store::new(&engine, ());
+        // The start function runs during instantiation and must be bounded too
+        store.set_fuel(FUEL_PER_CALL)?;
+        let instance = Linker::new(&engine).instantiate(&mut store, &module)?;
+        let decide_connect = instance.get_typed_func(&mut store, DECIDE_CONNECT)?;
+
+        Ok(Self {
+            store,
+            decide_connect,
+        })
+    }
+
+    /// Ask the plugin whether a connection to addr:port is allowed
+    pub fn allow_connect(&mut self, addr: Ipv4Addr, port: u16) -> wasmtime::Result<bool> {
+        self.store.set_fuel(FUEL_PER_CALL)?;
+        let verdict = self
+            .decide_connect
+            .call(&mut self.store, (addr.to_bits() as i32, i32::from(port)))?;
+        Ok(verdict != 0)
+    }
+}
+
+/// Destination of plugin verdicts, abstracted for testing
+#[cfg_attr(test, automock)]
+pub trait VerdictSink: Send + 'static {
+    fn set_plugin_verdict(
+        &mut self,
+        addr: Ipv4Addr,
+        port: u16,
+        allow: bool,
+    ) -> Result<(), MoriError>;
+}
+
+impl VerdictSink for NetworkEbpf {
+    fn set_plugin_verdict(
+        &mut self,
+        addr: Ipv4Addr,
+        port: u16,
+        allow: bool,
+    ) -> Result<(), MoriError> {
+        self.set_plugin_verdict(addr, port, allow)
+    }
+}
+
+/// Answer decision requests published by the BPF program until the task is aborted
+pub fn spawn_plugin<E: VerdictSink>(
+    mut plugin: WasmPlugin,
+    requests: RingBuf<MapData>,
+    ebpf: Arc<Mutex<E>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut requests = match AsyncFd::new(requests) {
+            Ok(requests) => requests,
+            Err(err) => {
+                log::error!("Failed to watch plugin requests: {err}");
+                return;
+            }
+        };
+
+        loop {
+            let mut guard = match requests.readable_mut().await {
+                Ok(guard) => guard,
+                Err(err) => {
+                    log::error!("Failed to wait for plugin requests: {err}");
+                    return;
+                }
+            };
+            while let Some(request) = guard.get_inner_mut().next() {
+                handle_request(&mut plugin, &ebpf, &request);
+            }
+            guard.clear_ready();
+        }
+    })
+}
+
+/// Evaluate a single request and write the verdict back to the BPF program
+fn handle_request<E: VerdictSink>(plugin: &mut WasmPlugin, ebpf: &Mutex<E>, request: &[u8]) {
+    let Some((addr, port)) = parse_request(request) else {
+        log::warn!(
+            "Ignoring malformed plugin request ({} bytes)",
+            request.len()
+        );
+        return;
+    };
+
+    let allow = plugin.allow_connect(addr, port).unwrap_or_else(|err| {
+        log::warn!("Plugin failed to decide on {addr}:{port}, denying: {err}");
+        false
+    });
+    log::info!(
+        "Plugin {} connection to {}:{}",
+        if allow { "allowed" } else { "denied" },
+        addr,
+        port
+    );
+
+    if let Err(err) = ebpf.lock().unwrap().set_plugin_verdict(addr, port, allow) {
+        log::error!("Failed to record plugin verdict for {addr}:{port}: {err}");
+    }
+}
+
+/// Key identifying a connection in the plugin maps (address and port in network byte order)
+pub fn connection_key(addr: Ipv4Addr, port: u16) -> [u8; 6] {
+    let [a, b, c, d] = addr.octets();
+    let [p0, p1] = port.to_be_bytes();
+    [a, b, c, d, p0, p1]
+}
+
+/// Decode a connection key (address and port in network byte order)
+fn parse_request(request: &[u8]) -> Option<(Ipv4Addr, u16)> {
+    match *request {
+        [a, b, c, d, p0, p1] => Some((Ipv4Addr::new(a, b, c, d), u16::from_be_bytes([p0, p1]))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockall::predicate::eq;
+
+    /// Allows HTTPS to 10.0.0.0/8 only
+    const HTTPS_INTERNAL: &str = r#"
+        (module
+          (func (export "decide_connect") (param $addr i32) (param $port i32) (result i32)
+            (i32.and
+              (i32.eq (i32.shr_u (local.get $addr) (i32.const 24)) (i32.const 10))
+              (i32.eq (local.get $port) (i32.const 443)))))
+    "#;
+
+    fn load_wat(wat: &str) -> Result<WasmPlugin, MoriError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plugin.wat");
+        std::fs::write(&path, wat).unwrap();
+        WasmPlugin::load(&path)
+    }
+
+    #[test]
+    fn allow_connect_returns_plugin_verdict() {
+        let mut plugin = load_wat(HTTPS_INTERNAL).unwrap();
+
+        assert!(
+            plugin
+                .allow_connect(Ipv4Addr::new(10, 1, 2, 3), 443)
+                .unwrap()
+        );
+        assert!(
+            !plugin
+                .allow_connect(Ipv4Addr::new(10, 1, 2, 3), 80)
+                .unwrap()
+        );
+        assert!(
+            !plugin
+                .allow_connect(Ipv4Addr::new(192, 0, 2, 1), 443)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn load_rejects_module_without_decide_connect() {
+        let result = load_wat(r#"(module (func (export "other")))"#);
+        assert!(matches!(result, Err(MoriError::PluginLoad { .. })));
+    }
+
+    #[test]
+    fn allow_connect_bounds_runaway_plugins() {
+        let mut plugin = load_wat(
+            r#"(module
+                 (func (export "decide_connect") (param i32 i32) (result i32)
+                   (loop $forever (br $forever))
+                   (i32.const 1)))"#,
+        )
+        .unwrap();
+
+        assert!(plugin.allow_connect(Ipv4Addr::LOCALHOST, 80).is_err());
+    }
+
+    #[test]
+    fn handle_request_records_verdict() {
+        let mut plugin = load_wat(HTTPS_INTERNAL).unwrap();
+        let addr = Ipv4Addr::new(10, 0, 0, 1);
+
+        let mut mock = MockVerdictSink::new();
+        mock.expect_set_plugin_verdict()
+            .with(eq(addr), eq(443), eq(true))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        mock.expect_set_plugin_verdict()
+            .with(eq(addr), eq(22), eq(false))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let ebpf = Mutex::new(mock);
+
+        handle_request(&mut plugin, &ebpf, &connection_key(addr, 443));
+        handle_request(&mut plugin, &ebpf, &connection_key(addr, 22));
+    }
+
+    #[test]
+    fn handle_request_denies_when_plugin_traps() {
+        let mut plugin = load_wat(
+            r#"(module
+                 (func (export "decide_connect") (param i32 i32) (result i32)
+                   unreachable))"#,
+        )
+        .unwrap();
+
+        let mut mock = MockVerdictSink::new();
+        mock.expect_set_plugin_verdict()
+            .with(eq(Ipv4Addr::LOCALHOST), eq(8080), eq(false))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let ebpf = Mutex::new(mock);
+
+        handle_request(
+            &mut plugin,
+            &ebpf,
+            &connection_key(Ipv4Addr::LOCALHOST, 8080),
+        );
+    }
+
+    #[test]
+    fn parse_request_rejects_malformed_records() {
+        assert_eq!(
+            parse_request(&connection_key(Ipv4Addr::new(192, 0, 2, 1), 8443)),
+            Some((Ipv4Addr::new(192, 0, 2, 1), 8443))
+        );
+        assert_eq!(parse_request(&[1, 2, 3]), None);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{ffi::CStr, fs, path::Path};
+
+use super::{cgroup, file, landlock};
+
+/// Oldest kernel the eBPF programs load on: the BPF LSM arrived in 5.7 and
+/// the ring buffers reporting connections and denials in 5.8
+const MIN_KERNEL: (u32, u32) = (5, 8);
+
+/// Capabilities the eBPF backend needs, with their numbers from
+/// linux/capability.h
+const REQUIRED_CAPABILITIES: &[(&str, u32)] = &[
+    ("CAP_NET_ADMIN", 12),
+    ("CAP_SYS_ADMIN", 21),
+    ("CAP_BPF", 39),
+];
+
+/// Result of one of the probes `mori check` runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// What was checked
+    pub name: &'static str,
+    pub status: DiagnosticStatus,
+    /// What was found
+    pub detail: String,
+    /// How to fix a warning or a failure
+    pub remedy: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticStatus {
+    Ok,
+    /// Works, but part of the policy cannot be enforced as usual
+    Warn,
+    /// The eBPF backend cannot run
+    Fail,
+}
+
+impl Diagnostic {
+    fn ok(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            status: DiagnosticStatus::Ok,
+            detail,
+            remedy: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: DiagnosticStatus,
+        detail: String,
+        remedy: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail,
+            remedy: Some(remedy.into()),
+        }
+    }
+}
+
+/// Check what the kernel and the current process provide for enforcing policies
+///
+/// These are the conditions that otherwise surface as load or attach errors
+/// when the command is run.
+pub fn preflight() -> Vec<Diagnostic> {
+    let root = unsafe { libc::geteuid() } == 0;
+    vec![
+        check_kernel(),
+        check_btf(),
+        check_bpf_lsm(),
+        check_cgroup(),
+        check_capabilities(root),
+        check_landlock(),
+        check_user_namespaces(root),
+    ]
+}
+
+fn check_kernel() -> Diagnostic {
+    let release = kernel_release();
+    match parse_kernel_version(&release) {
+        Some(version) if version < MIN_KERNEL => Diagnostic::problem(
+            "kernel",
+            DiagnosticStatus::Fail,
+            format!("Linux {}", release),
+            format!(
+                "mori's eBPF programs need Linux {}.{} or later; upgrade the kernel, or use --preload-fallback",
+                MIN_KERNEL.0, MIN_KERNEL.1
+            ),
+        ),
+        _ => Diagnostic::ok("kernel", format!("Linux {}", release)),
+    }
+}
+
+fn check_btf() -> Diagnostic {
+    let vmlinux = Path::new("/sys/kernel/btf/vmlinux");
+    if vmlinux.exists() {
+        return Diagnostic::ok("btf", format!("{} is present", vmlinux.display()));
+    }
+    Diagnostic::problem(
+        "btf",
+        DiagnosticStatus::Fail,
+        format!("{} is missing", vmlinux.display()),
+        "the LSM programs are resolved against the kernel's BTF; use a kernel built with CONFIG_DEBUG_INFO_BTF=y",
+    )
+}
+
+fn check_bpf_lsm() -> Diagnostic {
+    match file::bpf_lsm_active() {
+        Some(true) => Diagnostic::ok("bpf lsm", "bpf is in the active LSM list".to_string()),
+        Some(false) => {
+            let lsms = fs::read_to_string("/sys/kernel/security/lsm").unwrap_or_default();
+            Diagnostic::problem(
+                "bpf lsm",
+                DiagnosticStatus::Warn,
+                format!("the active LSMs are {}", lsms.trim()),
+                format!(
+                    "file rules fall back to Landlock and socket family, ptrace and kernel protections are off; \
+                     boot with lsm={},bpf (CONFIG_BPF_LSM=y is required)",
+                    lsms.trim()
+                ),
+            )
+        }
+        None => Diagnostic::problem(
+            "bpf lsm",
+            DiagnosticStatus::Warn,
+            "/sys/kernel/security/lsm cannot be read".to_string(),
+            "mount securityfs (mount -t securityfs securityfs /sys/kernel/security) to check",
+        ),
+    }
+}
+
+fn check_cgroup() -> Diagnostic {
+    match cgroup::cgroup2_mount_point() {
+        Some(mount_point) => {
+            Diagnostic::ok("cgroup v2", format!("mounted on {}", mount_point.display()))
+        }
+        None => Diagnostic::problem(
+            "cgroup v2",
+            DiagnosticStatus::Fail,
+            "no cgroup2 file system is mounted".to_string(),
+            "the network programs attach to a cgroup v2; boot with systemd.unified_cgroup_hierarchy=1, \
+             or mount -t cgroup2 none /sys/fs/cgroup",
+        ),
+    }
+}
+
+fn check_capabilities(root: bool) -> Diagnostic {
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let Some(effective) = parse_effective_capabilities(&status) else {
+        return Diagnostic::problem(
+            "capabilities",
+            DiagnosticStatus::Warn,
+            "/proc/self/status has no CapEff line".to_string(),
+            "run mori as root",
+        );
+    };
+    let missing = missing_capabilities(effective);
+    if missing.is_empty() {
+        return Diagnostic::ok(
+            "capabilities",
+            "CAP_BPF, CAP_SYS_ADMIN and CAP_NET_ADMIN are effective".to_string(),
+        );
+    }
+    let detail = format!("missing {}", missing.join(", "));
+    if root {
+        Diagnostic::problem(
+            "capabilities",
+            DiagnosticStatus::Fail,
+            detail,
+            "mori runs as root without them, as in a container; grant them (docker run --cap-add ...) \
+             or use --backend unprivileged",
+        )
+    } else {
+        Diagnostic::problem(
+            "capabilities",
+            DiagnosticStatus::Warn,
+            detail,
+            "without root mori uses the unprivileged backend, which enforces only part of a policy; \
+             run mori with sudo for eBPF",
+        )
+    }
+}
+
+fn check_landlock() -> Diagnostic {
+    match landlock::abi_version() {
+        Some(abi) => Diagnostic::ok("landlock", format!("ABI version {}", abi)),
+        None => Diagnostic::problem(
+            "landlock",
+            DiagnosticStatus::Warn,
+            "Landlock is not available".to_string(),
+            "the unprivileged backend and the file rule fallback need it; use Linux 5.13 or later \
+             and add landlock to the lsm= boot parameter",
+        ),
+    }
+}
+
+fn check_user_namespaces(root: bool) -> Diagnostic {
+    let read = |path: &str| {
+        fs::read_to_string(path)
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+    let blocker = if read("/proc/sys/user/max_user_namespaces").as_deref() == Some("0") {
+        Some("user.max_user_namespaces is 0")
+    } else if !root && read("/proc/sys/kernel/unprivileged_userns_clone").as_deref() == Some("0") {
+        Some("kernel.unprivileged_userns_clone is 0")
+    } else if !root
+        && read("/proc/sys/kernel/apparmor_restrict_unprivileged_userns").as_deref() == Some("1")
+    {
+        Some("AppArmor restricts unprivileged user namespaces")
+    } else {
+        None
+    };
+    match blocker {
+        None => Diagnostic::ok("user namespaces", "can be created".to_string()),
+        Some(reason) => Diagnostic::problem(
+            "user namespaces",
+            DiagnosticStatus::Warn,
+            reason.to_string(),
+            "the unprivileged backend needs them for deny-all network, --private-tmp and --overlay; \
+             allow them with sysctl, or run mori as root",
+        ),
+    }
+}
+
+/// Release of the running kernel, such as "6.8.0-45-generic"
+fn kernel_release() -> String {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return "unknown".to_string();
+    }
+    unsafe { CStr::from_ptr(uts.release.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Major and minor version of a kernel release
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Effective capability set from /proc/self/status
+fn parse_effective_capabilities(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+}
+
+fn missing_capabilities(effective: u64) -> Vec<&'static str> {
+    REQUIRED_CAPABILITIES
+        .iter()
+        .filter(|(_, bit)| effective & (1 << bit) == 0)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::distribution("6.8.0-45-generic", Some((6, 8)))]
+    #[case::plain("5.10", Some((5, 10)))]
+    #[case::suffix("6.18.44-fc-v130", Some((6, 18)))]
+    #[case::garbage("unknown", None)]
+    fn parse_kernel_version_reads_major_and_minor(
+        #[case] release: &str,
+        #[case] expected: Option<(u32, u32)>,
+    ) {
+        assert_eq!(parse_kernel_version(release), expected);
+    }
+
+    #[test]
+    fn missing_capabilities_reads_cap_eff() {
+        let status = "Name:\tmori\nCapInh:\t0000000000000000\nCapEff:\t0000000000201000\n";
+        let effective = parse_effective_capabilities(status).unwrap();
+        assert_eq!(missing_capabilities(effective), vec!["CAP_BPF"]);
+        assert!(missing_capabilities(u64::MAX).is_empty());
+    }
+}
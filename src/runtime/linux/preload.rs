@@ -0,0 +1,294 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    policy::{AccessMode, AllowPolicy, Policy},
+    runtime::DenyErrno,
+};
+
+/// Environment variable the shim reads its policy from (see mori-preload)
+const POLICY_ENV: &str = "MORI_PRELOAD_POLICY";
+
+/// Environment variable overriding the location of the shim
+const LIBRARY_ENV: &str = "MORI_PRELOAD_LIB";
+
+/// File name of the shim built by the mori-preload crate
+const LIBRARY_NAME: &str = "libmori_preload.so";
+
+/// Find the shim: `$MORI_PRELOAD_LIB`, or next to the mori executable
+pub fn find_library() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(LIBRARY_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let exe = std::env::current_exe().ok()?;
+    let path = exe.parent()?.join(LIBRARY_NAME);
+    path.is_file().then_some(path)
+}
+
+/// Describe where the shim was looked for, for error messages
+pub fn search_hint() -> String {
+    format!(
+        "{} next to the mori executable or ${}",
+        LIBRARY_NAME, LIBRARY_ENV
+    )
+}
+
+/// Environment variables loading the shim with `policy` into the command
+///
+/// `current_preload` is the caller's LD_PRELOAD, which is kept after the shim.
+pub fn environment(
+    policy: &Policy,
+    deny_errno: DenyErrno,
+    library: &Path,
+    current_preload: Option<&str>,
+) -> Vec<(String, String)> {
+    let preload = match current_preload.filter(|value| !value.is_empty()) {
+        Some(current) => format!("{} {}", library.display(), current),
+        None => library.display().to_string(),
+    };
+    vec![
+        ("LD_PRELOAD".to_string(), preload),
+        (POLICY_ENV.to_string(), encode_policy(policy, deny_errno)),
+    ]
+}
+
+/// Encode the policy in the shim's line format
+fn encode_policy(policy: &Policy, deny_errno: DenyErrno) -> String {
+    let mut out = String::new();
+    if !policy.network.denied.is_empty() {
+        log::warn!("The LD_PRELOAD shim does not enforce network deny rules; ignoring them");
+    }
+    if policy.network.dns.enforce {
+        log::warn!("The LD_PRELOAD shim does not enforce DNS nameservers; ignoring it");
+    }
+    if policy.file.hide_denied {
+        log::warn!("The LD_PRELOAD shim does not hide denied paths; they are denied but visible");
+    }
+    if policy.file.private_proc {
+        log::warn!("The LD_PRELOAD shim does not restrict /proc; ignoring private_proc");
+    }
+    if !policy.file.denied_devices.is_empty() {
+        log::warn!("The LD_PRELOAD shim does not deny device nodes; ignoring deny_devices");
+    }
+    if policy.process.has_exec_rules() {
+        log::warn!("The LD_PRELOAD shim does not restrict executing programs; ignoring exec rules");
+    }
+    if policy.network.enforce_sni {
+        log::warn!("The LD_PRELOAD shim does not inspect TLS server names; ignoring enforce_sni");
+    }
+    if let AllowPolicy::Entries {
+        allowed_ipv4,
+        allowed_cidr,
+        allowed_ipv6,
+        allowed_cidr_v6,
+        allowed_domains,
+        allowed_domain_suffixes,
+        allowed_ports,
+    } = &policy.network.policy
+    {
+        if !allowed_ports.is_empty() {
+            log::warn!(
+                "The LD_PRELOAD shim does not restrict destination ports; allowing every port"
+            );
+        }
+        out.push_str("restrict-network\n");
+        if deny_errno != DenyErrno::Eperm {
+            let _ = writeln!(out, "connect-errno {}", deny_errno.code());
+        }
+        if policy.network.deny_localhost {
+            out.push_str("deny-localhost\n");
+        }
+        for domain in allowed_domains {
+            let _ = writeln!(out, "host {}", domain);
+        }
+        for suffix in allowed_domain_suffixes {
+            let _ = writeln!(out, "host-suffix {}", suffix);
+        }
+        for ip in allowed_ipv4 {
+            let _ = writeln!(out, "net {}/32", ip);
+        }
+        for (network, prefix_len) in allowed_cidr {
+            let _ = writeln!(out, "net {}/{}", network, prefix_len);
+        }
+        for ip in allowed_ipv6 {
+            let _ = writeln!(out, "net {}/128", ip);
+        }
+        for (network, prefix_len) in allowed_cidr_v6 {
+            let _ = writeln!(out, "net {}/{}", network, prefix_len);
+        }
+    }
+
+    for family in policy.network.denied_socket_families() {
+        let _ = writeln!(out, "deny-family {}", family.number());
+    }
+    if !policy.network.icmp_allowed() {
+        out.push_str("deny-icmp\n");
+    }
+    let unix = &policy.network.unix;
+    if unix.deny_abstract {
+        out.push_str("deny-abstract\n");
+    }
+    for (keyword, names) in [
+        ("allow-abstract", &unix.allowed_abstract),
+        ("deny-abstract-name", &unix.denied_abstract),
+    ] {
+        for name in names {
+            if name.contains('\n') {
+                log::warn!(
+                    "Cannot pass @{:?} to the LD_PRELOAD shim; ignoring it",
+                    name
+                );
+                continue;
+            }
+            let _ = writeln!(out, "{} {}", keyword, name);
+        }
+    }
+
+    if policy.restricts_listen() {
+        out.push_str("restrict-listen\n");
+        for port in &policy.inbound.allowed_listen_ports {
+            let _ = writeln!(out, "listen {}", port);
+        }
+    }
+
+    // The shim sees the paths as the command spells them
+    let file = policy.file.with_symlink_targets();
+    for (path, mode) in &file.denied_paths {
+        let path = path.to_string_lossy();
+        if path.contains('\n') {
+            log::warn!("Cannot pass {:?} to the LD_PRELOAD shim; ignoring it", path);
+            continue;
+        }
+        let keyword = match mode {
+            AccessMode::Read => "deny-read",
+            AccessMode::Write => "deny-write",
+            AccessMode::ReadWrite => "deny-read-write",
+            AccessMode::Execute => {
+                log::warn!(
+                    "The LD_PRELOAD shim does not restrict executing {}; ignoring it",
+                    path
+                );
+                continue;
+            }
+        };
+        let _ = writeln!(out, "{} {}", keyword, path);
+    }
+    for (path, mode) in file.allowed_entries() {
+        let path = path.to_string_lossy();
+        if path.contains('\n') {
+            log::warn!("Cannot pass {:?} to the LD_PRELOAD shim; ignoring it", path);
+            continue;
+        }
+        let keyword = match mode {
+            AccessMode::Read => "allow-read",
+            AccessMode::Write => "allow-write",
+            AccessMode::ReadWrite => "allow-read-write",
+            // Allow-list entries only grant reading and writing
+            AccessMode::Execute => continue,
+        };
+        let _ = writeln!(out, "{} {}", keyword, path);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{FilePolicy, InboundPolicy, NetworkPolicy};
+
+    #[test]
+    fn encode_policy_lists_rules() {
+        let mut file = FilePolicy::new();
+        file.deny_read("/home/user/.ssh");
+        file.deny_read_write("/home/user/.aws");
+        file.deny_execute("/tmp");
+        let policy = Policy {
+            network: NetworkPolicy::from_entries(&[
+                "192.0.2.1".to_string(),
+                "2001:db8::/32".to_string(),
+                ".corp.internal".to_string(),
+            ])
+            .unwrap(),
+            file,
+            ..Policy::new()
+        };
+
+        assert_eq!(
+            encode_policy(&policy, DenyErrno::Eperm),
+            "restrict-network\nhost-suffix .corp.internal\nnet 192.0.2.1/32\nnet 2001:db8::/32\ndeny-family 16\ndeny-family 17\ndeny-icmp\nrestrict-listen\ndeny-read /home/user/.ssh\ndeny-read-write /home/user/.aws\n"
+        );
+    }
+
+    #[test]
+    fn encode_policy_passes_deny_localhost() {
+        let mut network = NetworkPolicy::from_entries(&["127.0.0.1:6379".to_string()]).unwrap();
+        network.deny_localhost = true;
+        let encoded = encode_policy(&Policy::with_network(network), DenyErrno::Eperm);
+        assert!(encoded.starts_with("restrict-network\ndeny-localhost\nnet 127.0.0.1/32\n"));
+    }
+
+    #[test]
+    fn encode_policy_lists_allowed_paths_and_system_paths() {
+        let mut policy = Policy::with_network(NetworkPolicy::from_allow_all(true));
+        policy.file.allow("/work/project");
+        let encoded = encode_policy(&policy, DenyErrno::Eperm);
+        assert!(encoded.starts_with("allow-read-write /work/project\nallow-read /usr\n"));
+        assert!(encoded.contains("allow-read-write /dev/null\n"));
+    }
+
+    #[test]
+    fn encode_policy_leaves_network_open_when_allowed() {
+        let policy = Policy::with_network(NetworkPolicy::from_allow_all(true));
+        assert_eq!(encode_policy(&policy, DenyErrno::Econnrefused), "");
+    }
+
+    #[test]
+    fn encode_policy_passes_deny_errno() {
+        assert_eq!(
+            encode_policy(&Policy::new(), DenyErrno::Econnrefused),
+            format!(
+                "restrict-network\nconnect-errno {}\ndeny-family 16\ndeny-family 17\ndeny-icmp\nrestrict-listen\n",
+                libc::ECONNREFUSED
+            )
+        );
+    }
+
+    #[test]
+    fn encode_policy_lists_listen_ports() {
+        let policy = Policy {
+            inbound: InboundPolicy::from_listen_ports(&[8080, 3000]),
+            ..Policy::with_network(NetworkPolicy::from_allow_all(true))
+        };
+        assert_eq!(
+            encode_policy(&policy, DenyErrno::Eperm),
+            "restrict-listen\nlisten 3000\nlisten 8080\n"
+        );
+    }
+
+    #[test]
+    fn environment_keeps_existing_preload() {
+        let env = environment(
+            &Policy::new(),
+            DenyErrno::Eperm,
+            Path::new("/opt/mori/libmori_preload.so"),
+            Some("/usr/lib/libfoo.so"),
+        );
+        assert_eq!(
+            env[0],
+            (
+                "LD_PRELOAD".to_string(),
+                "/opt/mori/libmori_preload.so /usr/lib/libfoo.so".to_string()
+            )
+        );
+        assert_eq!(
+            env[1],
+            (
+                POLICY_ENV.to_string(),
+                "restrict-network\ndeny-family 16\ndeny-family 17\ndeny-icmp\nrestrict-listen\n"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use std::os::fd::BorrowedFd;
+
+use aya::{Btf, Ebpf};
+
+use super::file::{attach_program, register_target_cgroup};
+use crate::error::MoriError;
+
+/// LSM program denying ptrace access to processes outside TARGET_CGROUP
+const PROGRAM_NAME: &str = "mori_ptrace_access_check";
+
+/// Keep the command from attaching to, or reading the memory of, processes
+/// outside its cgroup
+///
+/// Processes in the cgroup can still debug each other.
+pub(super) fn deny_outside_ptrace(
+    bpf: &mut Ebpf,
+    cgroup_fd: BorrowedFd<'_>,
+) -> Result<(), MoriError> {
+    let btf = Btf::from_sys_fs()?;
+    register_target_cgroup(bpf, cgroup_fd)?;
+    attach_program(bpf, PROGRAM_NAME, "ptrace_access_check", &btf)?;
+    log::info!("Denied ptrace access to processes outside the cgroup");
+    Ok(())
+}
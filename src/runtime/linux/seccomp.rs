@@ -0,0 +1,709 @@
+use std::{collections::HashSet, io, mem::offset_of};
+
+use crate::policy::{ProcessPolicy, SeccompAction, SeccompPolicy};
+
+/// `AUDIT_ARCH_*` value the kernel reports for system calls of this architecture
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_003E);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_00B7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// x32 system calls share the x86_64 architecture but set this bit in their number
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// CLONE_NEWTIME, which libc does not define
+const CLONE_NEWTIME: u32 = 0x80;
+/// Flags of clone and unshare creating namespaces
+const CLONE_NEW_FLAGS: u32 = (libc::CLONE_NEWNS
+    | libc::CLONE_NEWCGROUP
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET) as u32
+    | CLONE_NEWTIME;
+
+macro_rules! syscall_table {
+    ($($name:ident),* $(,)?) => {
+        &[$((stringify!($name), libc::$name)),*]
+    };
+}
+
+/// System call numbers by `SYS_` name, common to x86_64 and aarch64
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const SYSCALLS: &[(&str, libc::c_long)] = syscall_table![
+    SYS_accept,
+    SYS_accept4,
+    SYS_acct,
+    SYS_add_key,
+    SYS_adjtimex,
+    SYS_bind,
+    SYS_bpf,
+    SYS_brk,
+    SYS_capget,
+    SYS_capset,
+    SYS_chdir,
+    SYS_chroot,
+    SYS_clock_adjtime,
+    SYS_clock_getres,
+    SYS_clock_gettime,
+    SYS_clock_nanosleep,
+    SYS_clock_settime,
+    SYS_clone,
+    SYS_clone3,
+    SYS_close,
+    SYS_close_range,
+    SYS_connect,
+    SYS_copy_file_range,
+    SYS_delete_module,
+    SYS_dup,
+    SYS_dup3,
+    SYS_epoll_create1,
+    SYS_epoll_ctl,
+    SYS_epoll_pwait,
+    SYS_epoll_pwait2,
+    SYS_eventfd2,
+    SYS_execve,
+    SYS_execveat,
+    SYS_exit,
+    SYS_exit_group,
+    SYS_faccessat,
+    SYS_faccessat2,
+    SYS_fallocate,
+    SYS_fanotify_init,
+    SYS_fanotify_mark,
+    SYS_fchdir,
+    SYS_fchmod,
+    SYS_fchmodat,
+    SYS_fchown,
+    SYS_fchownat,
+    SYS_fcntl,
+    SYS_fdatasync,
+    SYS_fgetxattr,
+    SYS_finit_module,
+    SYS_flistxattr,
+    SYS_flock,
+    SYS_fremovexattr,
+    SYS_fsconfig,
+    SYS_fsetxattr,
+    SYS_fsmount,
+    SYS_fsopen,
+    SYS_fspick,
+    SYS_fstat,
+    SYS_fstatfs,
+    SYS_fsync,
+    SYS_ftruncate,
+    SYS_futex,
+    SYS_futex_waitv,
+    SYS_get_mempolicy,
+    SYS_get_robust_list,
+    SYS_getcpu,
+    SYS_getcwd,
+    SYS_getdents64,
+    SYS_getegid,
+    SYS_geteuid,
+    SYS_getgid,
+    SYS_getgroups,
+    SYS_getitimer,
+    SYS_getpeername,
+    SYS_getpgid,
+    SYS_getpid,
+    SYS_getppid,
+    SYS_getpriority,
+    SYS_getrandom,
+    SYS_getresgid,
+    SYS_getresuid,
+    SYS_getrusage,
+    SYS_getsid,
+    SYS_getsockname,
+    SYS_getsockopt,
+    SYS_gettid,
+    SYS_gettimeofday,
+    SYS_getuid,
+    SYS_getxattr,
+    SYS_init_module,
+    SYS_inotify_add_watch,
+    SYS_inotify_init1,
+    SYS_inotify_rm_watch,
+    SYS_io_cancel,
+    SYS_io_destroy,
+    SYS_io_getevents,
+    SYS_io_setup,
+    SYS_io_submit,
+    SYS_io_uring_enter,
+    SYS_io_uring_register,
+    SYS_io_uring_setup,
+    SYS_ioctl,
+    SYS_ioprio_get,
+    SYS_ioprio_set,
+    SYS_kcmp,
+    SYS_kexec_load,
+    SYS_keyctl,
+    SYS_kill,
+    SYS_landlock_add_rule,
+    SYS_landlock_create_ruleset,
+    SYS_landlock_restrict_self,
+    SYS_lgetxattr,
+    SYS_linkat,
+    SYS_listen,
+    SYS_listxattr,
+    SYS_llistxattr,
+    SYS_lookup_dcookie,
+    SYS_lremovexattr,
+    SYS_lseek,
+    SYS_lsetxattr,
+    SYS_madvise,
+    SYS_mbind,
+    SYS_membarrier,
+    SYS_memfd_create,
+    SYS_memfd_secret,
+    SYS_migrate_pages,
+    SYS_mincore,
+    SYS_mkdirat,
+    SYS_mknodat,
+    SYS_mlock,
+    SYS_mlock2,
+    SYS_mlockall,
+    SYS_mmap,
+    SYS_mount,
+    SYS_mount_setattr,
+    SYS_move_mount,
+    SYS_move_pages,
+    SYS_mprotect,
+    SYS_mq_getsetattr,
+    SYS_mq_notify,
+    SYS_mq_open,
+    SYS_mq_timedreceive,
+    SYS_mq_timedsend,
+    SYS_mq_unlink,
+    SYS_mremap,
+    SYS_mseal,
+    SYS_msgctl,
+    SYS_msgget,
+    SYS_msgrcv,
+    SYS_msgsnd,
+    SYS_msync,
+    SYS_munlock,
+    SYS_munlockall,
+    SYS_munmap,
+    SYS_name_to_handle_at,
+    SYS_nanosleep,
+    SYS_newfstatat,
+    SYS_nfsservctl,
+    SYS_open_by_handle_at,
+    SYS_open_tree,
+    SYS_openat,
+    SYS_openat2,
+    SYS_perf_event_open,
+    SYS_personality,
+    SYS_pidfd_getfd,
+    SYS_pidfd_open,
+    SYS_pidfd_send_signal,
+    SYS_pipe2,
+    SYS_pivot_root,
+    SYS_pkey_alloc,
+    SYS_pkey_free,
+    SYS_pkey_mprotect,
+    SYS_ppoll,
+    SYS_prctl,
+    SYS_pread64,
+    SYS_preadv,
+    SYS_preadv2,
+    SYS_prlimit64,
+    SYS_process_madvise,
+    SYS_process_mrelease,
+    SYS_process_vm_readv,
+    SYS_process_vm_writev,
+    SYS_pselect6,
+    SYS_ptrace,
+    SYS_pwrite64,
+    SYS_pwritev,
+    SYS_pwritev2,
+    SYS_quotactl,
+    SYS_quotactl_fd,
+    SYS_read,
+    SYS_readahead,
+    SYS_readlinkat,
+    SYS_readv,
+    SYS_reboot,
+    SYS_recvfrom,
+    SYS_recvmmsg,
+    SYS_recvmsg,
+    SYS_remap_file_pages,
+    SYS_removexattr,
+    SYS_renameat2,
+    SYS_request_key,
+    SYS_restart_syscall,
+    SYS_rseq,
+    SYS_rt_sigaction,
+    SYS_rt_sigpending,
+    SYS_rt_sigprocmask,
+    SYS_rt_sigqueueinfo,
+    SYS_rt_sigreturn,
+    SYS_rt_sigsuspend,
+    SYS_rt_sigtimedwait,
+    SYS_rt_tgsigqueueinfo,
+    SYS_sched_get_priority_max,
+    SYS_sched_get_priority_min,
+    SYS_sched_getaffinity,
+    SYS_sched_getattr,
+    SYS_sched_getparam,
+    SYS_sched_getscheduler,
+    SYS_sched_rr_get_interval,
+    SYS_sched_setaffinity,
+    SYS_sched_setattr,
+    SYS_sched_setparam,
+    SYS_sched_setscheduler,
+    SYS_sched_yield,
+    SYS_seccomp,
+    SYS_semctl,
+    SYS_semget,
+    SYS_semop,
+    SYS_semtimedop,
+    SYS_sendmmsg,
+    SYS_sendmsg,
+    SYS_sendto,
+    SYS_set_mempolicy,
+    SYS_set_mempolicy_home_node,
+    SYS_set_robust_list,
+    SYS_set_tid_address,
+    SYS_setdomainname,
+    SYS_setfsgid,
+    SYS_setfsuid,
+    SYS_setgid,
+    SYS_setgroups,
+    SYS_sethostname,
+    SYS_setitimer,
+    SYS_setns,
+    SYS_setpgid,
+    SYS_setpriority,
+    SYS_setregid,
+    SYS_setresgid,
+    SYS_setresuid,
+    SYS_setreuid,
+    SYS_setsid,
+    SYS_setsockopt,
+    SYS_settimeofday,
+    SYS_setuid,
+    SYS_setxattr,
+    SYS_shmat,
+    SYS_shmctl,
+    SYS_shmdt,
+    SYS_shmget,
+    SYS_shutdown,
+    SYS_sigaltstack,
+    SYS_signalfd4,
+    SYS_socket,
+    SYS_socketpair,
+    SYS_splice,
+    SYS_statfs,
+    SYS_statx,
+    SYS_swapoff,
+    SYS_swapon,
+    SYS_symlinkat,
+    SYS_sync,
+    SYS_syncfs,
+    SYS_sysinfo,
+    SYS_syslog,
+    SYS_tee,
+    SYS_tgkill,
+    SYS_timer_create,
+    SYS_timer_delete,
+    SYS_timer_getoverrun,
+    SYS_timer_gettime,
+    SYS_timer_settime,
+    SYS_timerfd_create,
+    SYS_timerfd_gettime,
+    SYS_timerfd_settime,
+    SYS_times,
+    SYS_tkill,
+    SYS_truncate,
+    SYS_umask,
+    SYS_umount2,
+    SYS_uname,
+    SYS_unlinkat,
+    SYS_unshare,
+    SYS_userfaultfd,
+    SYS_utimensat,
+    SYS_vhangup,
+    SYS_vmsplice,
+    SYS_wait4,
+    SYS_waitid,
+    SYS_write,
+    SYS_writev,
+];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const SYSCALLS: &[(&str, libc::c_long)] = &[];
+
+/// Legacy system calls aarch64 never had
+#[cfg(target_arch = "x86_64")]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = syscall_table![
+    SYS__sysctl,
+    SYS_access,
+    SYS_afs_syscall,
+    SYS_alarm,
+    SYS_arch_prctl,
+    SYS_chmod,
+    SYS_chown,
+    SYS_creat,
+    SYS_dup2,
+    SYS_epoll_create,
+    SYS_epoll_ctl_old,
+    SYS_epoll_wait,
+    SYS_epoll_wait_old,
+    SYS_eventfd,
+    SYS_fadvise64,
+    SYS_fchmodat2,
+    SYS_fork,
+    SYS_futimesat,
+    SYS_get_thread_area,
+    SYS_getdents,
+    SYS_getpgrp,
+    SYS_getpmsg,
+    SYS_getrlimit,
+    SYS_inotify_init,
+    SYS_ioperm,
+    SYS_iopl,
+    SYS_kexec_file_load,
+    SYS_lchown,
+    SYS_link,
+    SYS_lstat,
+    SYS_mkdir,
+    SYS_mknod,
+    SYS_modify_ldt,
+    SYS_open,
+    SYS_pause,
+    SYS_pipe,
+    SYS_poll,
+    SYS_putpmsg,
+    SYS_readlink,
+    SYS_rename,
+    SYS_renameat,
+    SYS_rmdir,
+    SYS_security,
+    SYS_select,
+    SYS_sendfile,
+    SYS_set_thread_area,
+    SYS_setrlimit,
+    SYS_signalfd,
+    SYS_stat,
+    SYS_symlink,
+    SYS_sync_file_range,
+    SYS_sysfs,
+    SYS_time,
+    SYS_tuxcall,
+    SYS_unlink,
+    SYS_uselib,
+    SYS_ustat,
+    SYS_utime,
+    SYS_utimes,
+    SYS_vfork,
+    SYS_vserver,
+];
+#[cfg(not(target_arch = "x86_64"))]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = &[];
+
+/// Number of the system call `name` on this architecture
+fn syscall_number(name: &str) -> Option<u32> {
+    SYSCALLS
+        .iter()
+        .chain(ARCH_SYSCALLS)
+        .find(|(sys, _)| sys.strip_prefix("SYS_") == Some(name))
+        .map(|&(_, nr)| nr as u32)
+}
+
+/// Seccomp filter compiled from a `SeccompPolicy`, installed in the forked
+/// child right before exec
+#[derive(Clone)]
+pub(super) struct SeccompFilter {
+    instructions: Vec<libc::sock_filter>,
+}
+
+impl SeccompFilter {
+    /// Compile the `[process]` seccomp filter and `deny_namespaces` into a
+    /// classic BPF program
+    ///
+    /// Denied system calls fail with EPERM, those of `errno_syscalls` with
+    /// their errno; a name listed several ways is denied, then failed with
+    /// its errno, then allowed.
+    /// Calls made through another architecture's (or the x32) entry point
+    /// fail with EPERM as well, since their numbers mean different calls.
+    /// Names this architecture does not have are skipped with a warning.
+    /// Returns None when there is nothing to filter, or where mori does not
+    /// know the system call numbers. With `audit`, denied calls, foreign
+    /// architectures included, are logged by the kernel (SECCOMP_RET_LOG)
+    /// and go through.
+    pub(super) fn compile(process: &ProcessPolicy, audit: bool) -> Option<Self> {
+        if process.seccomp.is_none() && !process.deny_namespaces {
+            return None;
+        }
+        let Some(arch) = AUDIT_ARCH else {
+            log::warn!(
+                "Seccomp filters are not supported on {}; ignoring seccomp rules and deny_namespaces",
+                std::env::consts::ARCH
+            );
+            return None;
+        };
+        let allow_all = SeccompPolicy::default();
+        let policy = process.seccomp.as_ref().unwrap_or(&allow_all);
+        let deny = if audit {
+            libc::SECCOMP_RET_LOG
+        } else {
+            libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA)
+        };
+        let action = |action: SeccompAction| match action {
+            SeccompAction::Allow => libc::SECCOMP_RET_ALLOW,
+            SeccompAction::Deny => deny,
+        };
+        let errno = |errno: i32| {
+            if audit {
+                libc::SECCOMP_RET_LOG
+            } else {
+                libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA)
+            }
+        };
+
+        let mut instructions = vec![
+            load(offset_of!(libc::seccomp_data, arch)),
+            jump(libc::BPF_JEQ, arch, 1, 0),
+            ret(deny),
+            load(offset_of!(libc::seccomp_data, nr)),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        instructions.extend([jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1), ret(deny)]);
+        // Checked first, so that allowing clone or unshare does not allow namespaces
+        if process.deny_namespaces {
+            instructions.extend(namespace_rules(deny));
+        }
+
+        let rules = policy
+            .denied_syscalls
+            .iter()
+            .map(|name| (name, action(SeccompAction::Deny)))
+            .chain(
+                policy
+                    .errno_syscalls
+                    .iter()
+                    .map(|(name, value)| (name, errno(*value))),
+            )
+            .chain(
+                policy
+                    .allowed_syscalls
+                    .iter()
+                    .map(|name| (name, action(SeccompAction::Allow))),
+            );
+        let default_action = action(policy.default_action);
+        let mut seen = HashSet::new();
+        for (name, rule) in rules {
+            let Some(nr) = syscall_number(name) else {
+                log::warn!(
+                    "Unknown system call {} in the seccomp filter; skipping it",
+                    name
+                );
+                continue;
+            };
+            // Rules repeating the default action are redundant
+            if !seen.insert(nr) || rule == default_action {
+                continue;
+            }
+            instructions.extend([jump(libc::BPF_JEQ, nr, 0, 1), ret(rule)]);
+        }
+        instructions.push(ret(default_action));
+
+        log::info!(
+            "Compiled a seccomp filter of {} instructions",
+            instructions.len()
+        );
+        Some(Self { instructions })
+    }
+
+    /// Install the filter in the calling process
+    ///
+    /// Sets no_new_privs first, which unprivileged processes need to install
+    /// a filter; setuid programs then run without gaining privileges.
+    pub(super) fn install(&self) -> io::Result<()> {
+        let program = libc::sock_fprog {
+            len: self.instructions.len() as libc::c_ushort,
+            filter: self.instructions.as_ptr() as *mut libc::sock_filter,
+        };
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Deny unshare and clone with CLONE_NEW* flags, and setns
+///
+/// clone3 passes its flags in memory a filter cannot read, so it fails with
+/// ENOSYS instead, which makes libc fall back to clone.
+fn namespace_rules(deny: u32) -> Vec<libc::sock_filter> {
+    // Low 32 bits of the first argument, the flags of both calls
+    let flags =
+        offset_of!(libc::seccomp_data, args) + if cfg!(target_endian = "big") { 4 } else { 0 };
+    let mut rules = Vec::new();
+    for nr in ["clone", "unshare"].into_iter().filter_map(syscall_number) {
+        rules.extend([
+            jump(libc::BPF_JEQ, nr, 0, 4),
+            load(flags),
+            jump(libc::BPF_JSET, CLONE_NEW_FLAGS, 0, 1),
+            ret(deny),
+            // The flags replaced the system call number
+            load(offset_of!(libc::seccomp_data, nr)),
+        ]);
+    }
+    if let Some(nr) = syscall_number("setns") {
+        rules.extend([jump(libc::BPF_JEQ, nr, 0, 1), ret(deny)]);
+    }
+    if let Some(nr) = syscall_number("clone3") {
+        let enosys = libc::SECCOMP_RET_ERRNO | (libc::ENOSYS as u32 & libc::SECCOMP_RET_DATA);
+        rules.extend([jump(libc::BPF_JEQ, nr, 0, 1), ret(enosys)]);
+    }
+    rules
+}
+
+/// `BPF_LD | BPF_W | BPF_ABS`: load the 32-bit word at `offset` of `seccomp_data`
+fn load(offset: usize) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16,
+        jt: 0,
+        jf: 0,
+        k: offset as u32,
+    }
+}
+
+/// `BPF_JMP | condition | BPF_K`: skip `jt` instructions if the loaded word
+/// compares true with `k`, `jf` otherwise
+fn jump(condition: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_JMP | condition | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// `BPF_RET | BPF_K`: return `action` to the kernel
+fn ret(action: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_RET | libc::BPF_K) as u16,
+        jt: 0,
+        jf: 0,
+        k: action,
+    }
+}
+
+#[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn process(seccomp: SeccompPolicy) -> ProcessPolicy {
+        ProcessPolicy {
+            seccomp: Some(seccomp),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn syscall_number_looks_up_names() {
+        assert_eq!(syscall_number("ptrace"), Some(libc::SYS_ptrace as u32));
+        assert_eq!(syscall_number("bpf"), Some(libc::SYS_bpf as u32));
+        assert_eq!(syscall_number("no_such_call"), None);
+    }
+
+    #[test]
+    fn compile_skips_redundant_and_unknown_rules() {
+        let policy = SeccompPolicy {
+            default_action: SeccompAction::Allow,
+            allowed_syscalls: vec!["read".to_string(), "ptrace".to_string()],
+            denied_syscalls: vec![
+                "ptrace".to_string(),
+                "no_such_call".to_string(),
+                "ptrace".to_string(),
+            ],
+            errno_syscalls: BTreeMap::from([("clone3".to_string(), libc::ENOSYS)]),
+        };
+        let filter = SeccompFilter::compile(&process(policy), false).unwrap();
+        let returns: Vec<u32> = filter
+            .instructions
+            .iter()
+            .filter(|insn| insn.code == (libc::BPF_RET | libc::BPF_K) as u16)
+            .map(|insn| insn.k)
+            .collect();
+        let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let mut expected = vec![deny];
+        if cfg!(target_arch = "x86_64") {
+            expected.push(deny);
+        }
+        // ptrace is denied once, read repeats the default action
+        let enosys = libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+        expected.extend([deny, enosys, libc::SECCOMP_RET_ALLOW]);
+        assert_eq!(returns, expected);
+
+        let ptrace = filter
+            .instructions
+            .iter()
+            .filter(|insn| insn.k == libc::SYS_ptrace as u32)
+            .count();
+        assert_eq!(ptrace, 1);
+    }
+
+    #[test]
+    fn compile_allow_list_denies_by_default() {
+        let policy = SeccompPolicy {
+            default_action: SeccompAction::Deny,
+            allowed_syscalls: vec!["read".to_string()],
+            ..Default::default()
+        };
+        let filter = SeccompFilter::compile(&process(policy.clone()), false).unwrap();
+        let last = filter.instructions.last().unwrap();
+        assert_eq!(last.k, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32);
+        let allow = &filter.instructions[filter.instructions.len() - 2];
+        assert_eq!(allow.k, libc::SECCOMP_RET_ALLOW);
+
+        let filter = SeccompFilter::compile(&process(policy), true).unwrap();
+        assert_eq!(filter.instructions.last().unwrap().k, libc::SECCOMP_RET_LOG);
+        // Calls through another architecture's entry point are only logged too
+        assert_eq!(filter.instructions[2].k, libc::SECCOMP_RET_LOG);
+    }
+
+    #[test]
+    fn compile_checks_namespace_flags_before_the_rules() {
+        assert!(SeccompFilter::compile(&ProcessPolicy::default(), false).is_none());
+
+        let policy = ProcessPolicy {
+            deny_namespaces: true,
+            ..Default::default()
+        };
+        let filter = SeccompFilter::compile(&policy, false).unwrap();
+        let flag_checks = filter
+            .instructions
+            .iter()
+            .filter(|insn| {
+                insn.code == (libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K) as u16
+                    && insn.k == CLONE_NEW_FLAGS
+            })
+            .count();
+        assert_eq!(flag_checks, 2);
+        let enosys = libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+        assert!(filter.instructions.iter().any(|insn| insn.k == enosys));
+        assert_eq!(
+            filter.instructions.last().unwrap().k,
+            libc::SECCOMP_RET_ALLOW
+        );
+    }
+}
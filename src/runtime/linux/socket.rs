@@ -0,0 +1,150 @@
+use std::{convert::TryFrom, os::fd::BorrowedFd};
+
+use aya::{
+    Btf, Ebpf,
+    maps::{Array, HashMap},
+    programs::lsm::Lsm,
+};
+
+use super::file::register_target_cgroup;
+use crate::{
+    error::MoriError,
+    policy::{SocketFamily, UnixPolicy, net::UNIX_NAME_MAX},
+};
+
+const PROGRAM_NAME: &str = "mori_socket_create";
+/// LSM programs checking abstract unix socket names, with their hooks
+const UNIX_PROGRAMS: [(&str, &str); 2] = [
+    ("mori_unix_connect", "socket_connect"),
+    ("mori_unix_sendmsg", "socket_sendmsg"),
+];
+/// UNIX_ABSTRACT values (match mori-bpf)
+const UNIX_ALLOW: u8 = 1;
+const UNIX_DENY: u8 = 2;
+
+/// Socket family, ICMP and unix socket restrictions using eBPF LSM programs
+pub struct SocketEbpf {}
+
+impl SocketEbpf {
+    /// Fill DENY_FAMILIES with the denied families, set DENY_ICMP when ICMP
+    /// sockets are denied and attach the LSM program
+    pub fn load_and_attach(
+        bpf: &mut Ebpf,
+        families: &[SocketFamily],
+        deny_icmp: bool,
+        cgroup_fd: BorrowedFd<'_>,
+    ) -> Result<(), MoriError> {
+        let btf = Btf::from_sys_fs()?;
+
+        // socket_create could use BPF_LSM_CGROUP, but sharing TARGET_CGROUP
+        // with the file programs keeps a single attach path for LSM hooks
+        register_target_cgroup(bpf, cgroup_fd)?;
+
+        let mut denied: HashMap<_, u32, u8> =
+            HashMap::try_from(bpf.map_mut("DENY_FAMILIES").unwrap())?;
+        for family in families {
+            denied
+                .insert(family.number(), 1, 0)
+                .map_err(MoriError::Map)?;
+            log::info!("Denied socket family: {}", family.name());
+        }
+        if deny_icmp {
+            let mut icmp: Array<_, u8> = Array::try_from(bpf.map_mut("DENY_ICMP").unwrap())?;
+            icmp.set(0, 1, 0).map_err(MoriError::Map)?;
+            log::info!("Denied ICMP sockets");
+        }
+
+        attach_lsm(bpf, PROGRAM_NAME, "socket_create", &btf)
+    }
+
+    /// Fill UNIX_ABSTRACT with the listed abstract socket names and attach
+    /// the connect and sendmsg LSM programs checking them
+    pub fn restrict_unix(
+        bpf: &mut Ebpf,
+        policy: &UnixPolicy,
+        cgroup_fd: BorrowedFd<'_>,
+    ) -> Result<(), MoriError> {
+        let btf = Btf::from_sys_fs()?;
+        register_target_cgroup(bpf, cgroup_fd)?;
+
+        {
+            let mut names: HashMap<_, [u8; UNIX_NAME_MAX], u8> =
+                HashMap::try_from(bpf.map_mut("UNIX_ABSTRACT").unwrap())?;
+            for name in &policy.allowed_abstract {
+                names
+                    .insert(abstract_key(name), UNIX_ALLOW, 0)
+                    .map_err(MoriError::Map)?;
+            }
+            // Denied names win over allowed ones
+            for name in &policy.denied_abstract {
+                names
+                    .insert(abstract_key(name), UNIX_DENY, 0)
+                    .map_err(MoriError::Map)?;
+                log::info!("Denied abstract unix socket: @{}", name);
+            }
+        }
+        if policy.deny_abstract {
+            let mut deny: Array<_, u8> =
+                Array::try_from(bpf.map_mut("UNIX_DENY_ABSTRACT").unwrap())?;
+            deny.set(0, 1, 0).map_err(MoriError::Map)?;
+            log::info!("Denied abstract unix sockets by default");
+        }
+
+        for (name, hook) in UNIX_PROGRAMS {
+            attach_lsm(bpf, name, hook, &btf)?;
+        }
+        Ok(())
+    }
+}
+
+/// Load the LSM program `name` for `hook` and attach it
+fn attach_lsm(bpf: &mut Ebpf, name: &str, hook: &str, btf: &Btf) -> Result<(), MoriError> {
+    let program = bpf
+        .program_mut(name)
+        .ok_or_else(|| MoriError::ProgramNotFound {
+            name: name.to_string(),
+        })?;
+
+    let program: &mut Lsm = program
+        .try_into()
+        .map_err(|source| MoriError::ProgramPrepare {
+            name: name.to_string(),
+            source,
+        })?;
+
+    program
+        .load(hook, btf)
+        .map_err(|source| MoriError::ProgramPrepare {
+            name: name.to_string(),
+            source,
+        })?;
+
+    program
+        .attach()
+        .map_err(|source| MoriError::ProgramAttach {
+            name: name.to_string(),
+            source,
+        })?;
+
+    log::info!("Attached LSM program: {}", name);
+    Ok(())
+}
+
+/// `name` zero-padded to a UNIX_ABSTRACT key
+fn abstract_key(name: &str) -> [u8; UNIX_NAME_MAX] {
+    let mut key = [0; UNIX_NAME_MAX];
+    key[..name.len()].copy_from_slice(name.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abstract_key_pads_name_with_zeros() {
+        let key = abstract_key("/tmp/.X11-unix/X0");
+        assert_eq!(&key[..17], b"/tmp/.X11-unix/X0");
+        assert!(key[17..].iter().all(|&byte| byte == 0));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    fs, io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use crate::{
+    error::MoriError,
+    policy::{InboundPolicy, NetworkPolicy, Policy},
+};
+
+use super::{
+    ProcessTree, RunOptions, RunOutcome, Sandbox, SignalRelay,
+    landlock::{self, Ruleset},
+    seccomp::SeccompFilter,
+    spawn_command, wait_command,
+};
+
+/// What the command sets up for itself in the forked child, in place of
+/// the cgroup and its eBPF programs
+pub(super) struct Confinement {
+    /// Create a user namespace first, so that the other namespaces and the
+    /// mounts need no privileges
+    user_namespace: bool,
+    /// Give the command a network namespace with only a loopback interface
+    network_namespace: bool,
+    /// Enforces the file rules and the denied executables
+    ruleset: Option<Ruleset>,
+}
+
+impl Confinement {
+    /// Enter the namespaces
+    ///
+    /// Called in the forked child before the mounts. The user namespace maps
+    /// the user to itself, so the files the command creates keep their owner.
+    pub(super) fn enter_namespaces(&self) -> io::Result<()> {
+        let mut flags = 0;
+        if self.user_namespace {
+            flags |= libc::CLONE_NEWUSER;
+        }
+        if self.network_namespace {
+            flags |= libc::CLONE_NEWNET;
+        }
+        if flags == 0 {
+            return Ok(());
+        }
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        if unsafe { libc::unshare(flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if self.user_namespace {
+            // Writing gid_map without privileges requires giving up setgroups
+            fs::write("/proc/self/setgroups", "deny")?;
+            fs::write("/proc/self/uid_map", format!("{} {} 1", uid, uid))?;
+            fs::write("/proc/self/gid_map", format!("{} {} 1", gid, gid))?;
+        }
+        if self.network_namespace {
+            loopback_up()?;
+        }
+        Ok(())
+    }
+
+    /// The Landlock ruleset the forked child applies to itself
+    pub(super) fn ruleset(&self) -> Option<&Ruleset> {
+        self.ruleset.as_ref()
+    }
+}
+
+/// Run the command confined by Landlock, seccomp and namespaces, without root
+///
+/// Rules these cannot enforce (see `unenforced_rules`) are refused unless
+/// `--best-effort` is given.
+pub(super) fn run(
+    command: &str,
+    args: &[&str],
+    policy: &Policy,
+    options: &RunOptions,
+    signals: &SignalRelay,
+) -> Result<RunOutcome, MoriError> {
+    if options.audit {
+        return Err(MoriError::AuditNeedsEbpf);
+    }
+    let root = unsafe { libc::geteuid() } == 0;
+    if let Some(credentials) = &options.user
+        && !root
+    {
+        return Err(MoriError::InvalidUser {
+            user: credentials.uid.to_string(),
+            reason: "switching users needs root".to_string(),
+        });
+    }
+
+    let abi = landlock::abi_version();
+    let rules = unenforced_rules(policy, abi.is_some());
+    if !rules.is_empty() {
+        if !options.best_effort {
+            return Err(MoriError::PolicyNotEnforceable { rules });
+        }
+        for rule in &rules {
+            eprintln!("mori: warning: not enforced without eBPF: {}", rule);
+        }
+    }
+    if options.plugin.is_some() {
+        eprintln!("mori: the policy plugin is not consulted by the unprivileged backend");
+    }
+    if options.flow_log {
+        eprintln!("mori: the flow log is not recorded by the unprivileged backend");
+    }
+    if options.file_inventory {
+        eprintln!("mori: the file inventory is not recorded by the unprivileged backend");
+    }
+    if options.file_rule_stats {
+        eprintln!("mori: file rule hits are not counted by the unprivileged backend");
+    }
+
+    let ruleset = match abi {
+        Some(abi) => Ruleset::for_policy(&policy.file, &policy.process, abi)
+            .map_err(|source| MoriError::Landlock { source })?,
+        None => None,
+    };
+    let network_namespace = policy.network.is_deny_all();
+    let confinement = Confinement {
+        user_namespace: !root
+            && (network_namespace || options.private_tmp || options.overlay.is_some()),
+        network_namespace,
+        ruleset,
+    };
+    let seccomp = SeccompFilter::compile(&policy.process, false);
+
+    let mut child = spawn_command(
+        command,
+        args,
+        &Sandbox::Unprivileged(&confinement),
+        &policy.process,
+        &[],
+        options,
+        seccomp.as_ref(),
+    )?;
+    log::info!(
+        "Spawned child process {} with the unprivileged backend",
+        child.id()
+    );
+    let tree = ProcessTree::ProcessGroup(child.pid.as_raw());
+    let exit_code = wait_command(&mut child, &tree, options, signals)?;
+    Ok(RunOutcome::exited(exit_code))
+}
+
+/// Rules of `policy` the unprivileged backend cannot enforce
+///
+/// A policy denying all network access is enforced by a network namespace
+/// of the command's own, which covers the socket, ICMP and inbound rules
+/// too; anything finer needs eBPF. Path and executable deny rules need
+/// Landlock.
+fn unenforced_rules(policy: &Policy, landlock: bool) -> Vec<String> {
+    let mut rest = policy.clone();
+    if policy.network.is_deny_all() {
+        rest.network = NetworkPolicy::from_allow_all(true);
+        rest.inbound = InboundPolicy::default();
+    }
+    if landlock {
+        rest.file.denied_paths.clear();
+        rest.file.allowed_paths.clear();
+        // Landlock follows symlinks to the inodes it guards
+        rest.file.resolve_symlinks = false;
+        rest.process.denied_exec.clear();
+    }
+    rest.process.seccomp = None;
+    rest.process.deny_namespaces = false;
+    rest.restrictions()
+}
+
+/// Bring up the loopback interface of a new network namespace, which
+/// starts out down
+fn loopback_up() -> io::Result<()> {
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in request.ifr_name.iter_mut().zip(b"lo") {
+        *dst = *src as libc::c_char;
+    }
+    if unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFFLAGS, &mut request) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+    if unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS, &request) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unenforced_rules_empty_for_deny_all_network() {
+        let mut policy = Policy::new();
+        policy.file.deny_read("/etc/shadow");
+        policy.process.deny_exec("/usr/bin/curl");
+        assert!(unenforced_rules(&policy, true).is_empty());
+    }
+
+    #[test]
+    fn unenforced_rules_list_what_needs_ebpf() {
+        let mut policy = Policy::with_network(
+            NetworkPolicy::from_entries(&["example.com:443".to_string()]).unwrap(),
+        );
+        policy.file.deny_read("/etc/shadow");
+        policy.process.max_pids = Some(10);
+
+        let rules = unenforced_rules(&policy, false);
+        assert!(
+            rules.contains(
+                &"network: allow outbound connections only to example.com:443".to_string()
+            )
+        );
+        assert!(rules.contains(&"file: deny read /etc/shadow".to_string()));
+        assert!(rules.contains(&"process: at most 10 processes".to_string()));
+        assert!(
+            !unenforced_rules(&policy, true).contains(&"file: deny read /etc/shadow".to_string())
+        );
+    }
+}
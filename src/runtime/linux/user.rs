@@ -0,0 +1,196 @@
+use std::{
+    ffi::{CStr, CString},
+    io,
+};
+
+use crate::{error::MoriError, runtime::Credentials};
+
+/// Size of the buffer the passwd and group lookups fill in
+const LOOKUP_BUFFER_SIZE: usize = 16 * 1024;
+
+/// User found in the passwd database
+struct PasswdEntry {
+    name: CString,
+    uid: u32,
+    gid: u32,
+}
+
+impl Credentials {
+    /// Resolve `--user` (`name`, `uid`, `name:group` or `uid:gid`) and the
+    /// supplementary `--group` entries
+    ///
+    /// The primary group defaults to the user's from the passwd database,
+    /// and a user found there keeps the groups it is a member of, as after a
+    /// login. A uid without a passwd entry needs its group spelled out.
+    pub fn resolve(user: &str, groups: &[String]) -> Result<Self, MoriError> {
+        let invalid = |reason: String| MoriError::InvalidUser {
+            user: user.to_string(),
+            reason,
+        };
+        let (name, group) = match user.split_once(':') {
+            Some((name, group)) => (name, Some(group)),
+            None => (user, None),
+        };
+
+        let entry = lookup_user(name);
+        let uid = match (&entry, name.parse::<u32>()) {
+            (Some(entry), _) => entry.uid,
+            (None, Ok(uid)) => uid,
+            (None, Err(_)) => return Err(invalid(format!("no user named {}", name))),
+        };
+        let gid = match (group, &entry) {
+            (Some(group), _) => {
+                lookup_group(group).ok_or_else(|| invalid(format!("no group named {}", group)))?
+            }
+            (None, Some(entry)) => entry.gid,
+            (None, None) => {
+                return Err(invalid(format!(
+                    "uid {} has no passwd entry; give its group as {}:GID",
+                    uid, uid
+                )));
+            }
+        };
+
+        let mut supplementary = match &entry {
+            Some(entry) => group_list(&entry.name, gid),
+            None => vec![],
+        };
+        for group in groups {
+            let gid =
+                lookup_group(group).ok_or_else(|| invalid(format!("no group named {}", group)))?;
+            if !supplementary.contains(&gid) {
+                supplementary.push(gid);
+            }
+        }
+        Ok(Self {
+            uid,
+            gid,
+            groups: supplementary,
+        })
+    }
+}
+
+/// Switch the calling process to `credentials`
+///
+/// Called in the forked child before exec. The supplementary groups are set
+/// first, while the process still has the privilege to.
+pub(super) fn switch_to(credentials: &Credentials) -> io::Result<()> {
+    let check = |ret: libc::c_int| match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    };
+    check(unsafe { libc::setgroups(credentials.groups.len(), credentials.groups.as_ptr()) })?;
+    check(unsafe { libc::setgid(credentials.gid) })?;
+    check(unsafe { libc::setuid(credentials.uid) })
+}
+
+/// Look up a user by name, or by uid when `user` is numeric
+fn lookup_user(user: &str) -> Option<PasswdEntry> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let ret = match user.parse::<u32>() {
+        Ok(uid) => unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        },
+        Err(_) => {
+            let name = CString::new(user).ok()?;
+            unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                )
+            }
+        }
+    };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+    Some(PasswdEntry {
+        name: unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned(),
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+    })
+}
+
+/// Look up a group by name; a numeric `group` is taken as the gid
+fn lookup_group(group: &str) -> Option<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Some(gid);
+    }
+    let name = CString::new(group).ok()?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let ret = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+    Some(entry.gr_gid)
+}
+
+/// Groups `name` is a member of, including `gid`
+fn group_list(name: &CStr, gid: u32) -> Vec<u32> {
+    let mut count: libc::c_int = 32;
+    loop {
+        let mut groups = vec![0 as libc::gid_t; count as usize];
+        let ret =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        // -1 means the list was too small; count now holds the size needed
+        if ret >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::name("root", 0, 0)]
+    #[case::uid("0", 0, 0)]
+    #[case::uid_and_gid("12345:12345", 12345, 12345)]
+    #[case::name_and_gid("root:12345", 0, 12345)]
+    fn resolve_finds_uid_and_gid(#[case] user: &str, #[case] uid: u32, #[case] gid: u32) {
+        let credentials = Credentials::resolve(user, &[]).unwrap();
+        assert_eq!((credentials.uid, credentials.gid), (uid, gid));
+    }
+
+    #[rstest]
+    #[case::unknown_name("no-such-user-for-mori")]
+    #[case::uid_without_entry("12345")]
+    #[case::unknown_group("root:no-such-group-for-mori")]
+    fn resolve_rejects_unknown_users(#[case] user: &str) {
+        assert!(matches!(
+            Credentials::resolve(user, &[]),
+            Err(MoriError::InvalidUser { .. })
+        ));
+    }
+
+    #[test]
+    fn resolve_adds_supplementary_groups() {
+        let credentials =
+            Credentials::resolve("12345:12345", &["root".to_string(), "0".to_string()]).unwrap();
+        assert_eq!(credentials.groups, vec![0]);
+    }
+}
@@ -0,0 +1,518 @@
+mod path;
+mod sandbox_log;
+
+use std::{path::Path, time::Duration};
+
+use crate::{
+    audit::{Explainer, ViolationCollector},
+    policy::{AccessMode, DeniedDevice, Policy},
+};
+use path::SandboxPathResolver;
+use sandbox_log::SandboxLogWatcher;
+use tokio::process::Command;
+
+use super::{
+    RunOptions, RunOutcome,
+    output::OutputCapture,
+    process::{SignalRelay, exit_code, sudo_credentials},
+};
+
+/// Time to keep reading the unified log after the child exits, so that late reports arrive
+const LOG_FLUSH_GRACE: Duration = Duration::from_millis(500);
+
+pub async fn execute_with_policy(
+    command: &str,
+    args: &[&str],
+    policy: &Policy,
+    options: &RunOptions,
+) -> Result<RunOutcome, crate::error::MoriError> {
+    use crate::policy::AllowPolicy;
+
+    let signals = SignalRelay::install();
+
+    // For macOS, we use sandbox-exec to control network and file access
+    // Note: macOS does not support domain-based network filtering via sandbox-exec,
+    // so we can only allow all or deny all network access.
+
+    if policy.process.seccomp.is_some() {
+        log::warn!("Seccomp is not available on macOS; ignoring seccomp rules");
+    }
+    if policy.process.max_pids.is_some() {
+        log::warn!("Process limits are not enforced on macOS; ignoring max_pids");
+    }
+    if policy.process.deny_namespaces {
+        log::warn!("macOS has no namespaces; ignoring deny_namespaces");
+    }
+    if policy.file.hide_denied {
+        log::warn!("Hiding denied paths is not available on macOS; they are denied but visible");
+    }
+    if policy.file.private_proc {
+        log::warn!("macOS has no /proc; ignoring private_proc");
+    }
+    if policy.inbound.restricted {
+        log::warn!(
+            "Inbound connection filtering is not available on macOS; ignoring inbound rules"
+        );
+    }
+
+    if policy
+        .network
+        .denied_families
+        .as_ref()
+        .is_some_and(|families| !families.is_empty())
+    {
+        log::warn!("Socket family restrictions are only enforced on Linux; ignoring deny_families");
+    }
+    if policy.network.allow_icmp.is_some() {
+        log::warn!("ICMP rules are only enforced on Linux; ignoring allow_icmp");
+    }
+    if !policy.network.unix.is_empty() {
+        log::warn!("Abstract unix sockets only exist on Linux; ignoring [network.unix]");
+    }
+    if !policy.network.dns.is_empty() {
+        log::warn!(
+            "DNS rules are only enforced on Linux; ignoring [network.dns], strict and the DNS refresh settings"
+        );
+    }
+    if policy.network.enforce_sni {
+        log::warn!("TLS server names are only checked on Linux; ignoring enforce_sni");
+    }
+    if !policy.network.denied.is_empty() {
+        log::warn!(
+            "sandbox-exec cannot filter by destination on macOS; ignoring network deny rules"
+        );
+    }
+
+    let needs_sandbox = !matches!(policy.network.policy, AllowPolicy::All)
+        || policy.inbound.listen_restricted
+        || !policy.file.is_empty()
+        || policy.process.has_exec_rules();
+
+    // In audit mode, sandbox reports are read back from the unified log
+    let mut audit = if options.audit && needs_sandbox {
+        let collector = ViolationCollector::spawn_explaining(Explainer::new(policy));
+        let watcher = SandboxLogWatcher::start(collector.sender()).await?;
+        Some((collector, watcher))
+    } else {
+        None
+    };
+
+    let mut cmd = if needs_sandbox {
+        // Use sandbox-exec with generated profile
+        let sandbox_profile = create_sandbox_profile(policy, options.audit);
+        let mut cmd = Command::new("sandbox-exec");
+        cmd.arg("-p").arg(sandbox_profile).arg(command).args(args);
+        cmd
+    } else {
+        // No restrictions: execute command directly
+        let mut cmd = Command::new(command);
+        cmd.args(args);
+        cmd
+    };
+
+    if let Some(vars) = policy.process.filtered_env() {
+        cmd.env_clear().envs(vars);
+    }
+
+    // Drop privileges if running under sudo
+    if let Some((uid, gid)) = sudo_credentials() {
+        cmd.uid(uid).gid(gid);
+    }
+
+    let output = OutputCapture::new(options)?;
+    if let Some(output) = &output {
+        let (stdout, stderr) = output.stdio()?;
+        if let Some(stdout) = stdout {
+            cmd.stdout(stdout);
+        }
+        if let Some(stderr) = stderr {
+            cmd.stderr(stderr);
+        }
+    }
+
+    let program = if needs_sandbox {
+        "sandbox-exec"
+    } else {
+        command
+    };
+    if let Some(outcome) = signals.interrupted() {
+        return Ok(outcome);
+    }
+    let mut child = cmd
+        .spawn()
+        .map_err(|source| crate::error::MoriError::CommandSpawn {
+            command: program.to_string(),
+            source,
+        })?;
+    // The command holds the write ends of the output pipes now
+    drop(cmd);
+    let output = output.map(OutputCapture::start);
+
+    if let Some(pid) = child.id() {
+        signals.forward_to(pid, None);
+    }
+
+    if let (Some((_, watcher)), Some(pid)) = (&mut audit, child.id()) {
+        watcher.watch_process_tree(pid);
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|source| crate::error::MoriError::CommandWait { source })?;
+
+    drop(signals);
+    if let Some(output) = output {
+        output.finish();
+    }
+
+    let report = match audit {
+        Some((collector, watcher)) => {
+            tokio::time::sleep(LOG_FLUSH_GRACE).await;
+            watcher.stop().await;
+            Some(collector.finish().await)
+        }
+        None => None,
+    };
+
+    Ok(RunOutcome {
+        exit_code: exit_code(status),
+        report,
+        flows: Vec::new(),
+        files: Vec::new(),
+        file_rules: Vec::new(),
+    })
+}
+
+/// Create a sandbox profile based on the policy
+///
+/// In audit mode every mori deny rule is emitted as an allow rule with reporting
+/// enabled, so accesses succeed but still show up in the unified log.
+fn create_sandbox_profile(policy: &Policy, audit: bool) -> String {
+    use crate::policy::AllowPolicy;
+
+    // Use (import "system.sb") + (deny default) approach like sbx
+    // This is required because (allow default) doesn't work with deny rules
+    let mut profile = String::from(
+        r#"(version 1)
+(import "system.sb")
+(deny default)
+(allow file-read*
+    (subpath "/opt/local/lib")
+    (subpath "/usr/lib")
+    (subpath "/usr/local/lib")
+)
+(allow file*)
+"#,
+    );
+
+    // Rules are written for every spelling of a path, since e.g. /tmp is a symlink
+    // to /private/tmp and the kernel only ever sees the canonical form
+    let resolver = SandboxPathResolver::load();
+
+    // In allow-list mode, deny every file and allow the listed paths back;
+    // metadata stays readable so that path lookups through parent
+    // directories keep working
+    let allowed = policy.file.allowed_entries();
+    if !allowed.is_empty() {
+        profile.push_str(&deny_rule("file*", None, audit));
+        profile.push_str("(allow file-read-metadata)\n");
+        for (path, mode) in &allowed {
+            let operation = match mode {
+                AccessMode::Read => "file-read*",
+                AccessMode::Write => "file-write*",
+                AccessMode::ReadWrite => "file*",
+                // Allow-list entries only grant reading and writing
+                AccessMode::Execute => continue,
+            };
+            for path in resolver.sandbox_paths(path) {
+                let filter = format!("(subpath \"{}\")", escape_path(&path.display().to_string()));
+                profile.push_str(&format!("(allow {} {})\n", operation, filter));
+            }
+        }
+    }
+
+    // Add file access denials using (deny file-*) rules
+    for (path, mode) in &policy.file.denied_paths {
+        let operation = match mode {
+            // Deny read operations only
+            AccessMode::Read => "file-read*",
+            // Deny write operations only
+            AccessMode::Write => "file-write*",
+            // Deny both read and write operations
+            AccessMode::ReadWrite => "file*",
+            // Deny mapping as executable code; exec is denied below
+            AccessMode::Execute => "file-map-executable",
+        };
+        for path in resolver.sandbox_paths(path) {
+            let filter = format!("(subpath \"{}\")", escape_path(&path.display().to_string()));
+            profile.push_str(&deny_rule(operation, Some(&filter), audit));
+        }
+    }
+
+    // Denied devices by node type, or by the path of their nodes; SBPL
+    // cannot match device numbers
+    for device in &policy.file.denied_devices {
+        let filters = match device {
+            DeniedDevice::Block => vec!["(vnode-type BLOCK-DEVICE)".to_string()],
+            DeniedDevice::Char => vec!["(vnode-type CHARACTER-DEVICE)".to_string()],
+            DeniedDevice::Node(path) => {
+                let (path, filter) = match device.node_prefix() {
+                    Some((dir, prefix)) => (dir.join(prefix), "prefix"),
+                    None => (path.clone(), "literal"),
+                };
+                resolver
+                    .sandbox_paths(&path)
+                    .iter()
+                    .map(|path| {
+                        format!(
+                            "({} \"{}\")",
+                            filter,
+                            escape_path(&path.display().to_string())
+                        )
+                    })
+                    .collect()
+            }
+        };
+        for filter in filters {
+            profile.push_str(&deny_rule(
+                "file-read-data file-write-data file-ioctl",
+                Some(&filter),
+                audit,
+            ));
+        }
+    }
+
+    // Add network denial if needed (at the end to override default allow)
+    if !matches!(policy.network.policy, AllowPolicy::All) {
+        profile.push_str(&deny_rule("network*", None, audit));
+    } else if policy.inbound.listen_restricted {
+        profile.push_str(&deny_rule("network-bind", None, audit));
+    }
+    // Allowed listen ports come after the denials so they override them
+    for port in &policy.inbound.allowed_listen_ports {
+        profile.push_str(&format!(
+            "(allow network-bind network-inbound (local ip \"*:{}\"))\n",
+            port
+        ));
+    }
+
+    // Allow process execution for all commands unless an allow-list is configured
+    profile.push_str("(allow process-exec*)\n");
+    if !policy.process.allowed_exec.is_empty() {
+        profile.push_str(&deny_rule("process-exec*", None, audit));
+        for path in policy
+            .process
+            .allowed_exec
+            .iter()
+            .flat_map(|path| resolver.sandbox_paths(path))
+        {
+            profile.push_str(&format!("(allow process-exec* {})\n", exec_filter(&path)));
+        }
+    }
+
+    // Denied executables come last so they override the allow-list
+    let execute_denied = policy
+        .file
+        .denied_paths
+        .iter()
+        .filter(|(_, mode)| *mode == AccessMode::Execute)
+        .map(|(path, _)| path);
+    for path in policy
+        .process
+        .denied_exec
+        .iter()
+        .chain(execute_denied)
+        .flat_map(|path| resolver.sandbox_paths(path))
+    {
+        profile.push_str(&deny_rule(
+            "process-exec*",
+            Some(&exec_filter(&path)),
+            audit,
+        ));
+    }
+
+    profile
+}
+
+/// Render a rule denying `operation`, or only reporting it when in audit mode
+fn deny_rule(operation: &str, filter: Option<&str>, audit: bool) -> String {
+    let action = if audit {
+        format!("(allow {} (with report)", operation)
+    } else {
+        format!("(deny {}", operation)
+    };
+    match filter {
+        Some(filter) => format!("{} {})\n", action, filter),
+        None => format!("{})\n", action),
+    }
+}
+
+/// Build the SBPL path filter for an exec rule
+///
+/// Directories match every executable beneath them; anything else is matched literally.
+fn exec_filter(path: &Path) -> String {
+    let path_str = escape_path(&path.display().to_string());
+    if path.is_dir() {
+        format!("(subpath \"{}\")", path_str)
+    } else {
+        format!("(literal \"{}\")", path_str)
+    }
+}
+
+/// Escape special characters in file paths for SBPL (Sandbox Profile Language)
+fn escape_path(path: &str) -> String {
+    // In SBPL, backslashes and quotes need to be escaped
+    path.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{InboundPolicy, NetworkPolicy};
+
+    fn allow_all_network_policy() -> Policy {
+        Policy::with_network(NetworkPolicy::from_allow_all(true))
+    }
+
+    #[test]
+    fn profile_without_exec_rules_allows_all_exec() {
+        let profile = create_sandbox_profile(&allow_all_network_policy(), false);
+        assert!(profile.contains("(allow process-exec*)\n"));
+        assert!(!profile.contains("(deny process-exec*"));
+    }
+
+    #[test]
+    fn profile_restricts_listen_ports() {
+        let mut policy = allow_all_network_policy();
+        policy.inbound = InboundPolicy::from_listen_ports(&[8080]);
+
+        let profile = create_sandbox_profile(&policy, false);
+        assert!(profile.contains(
+            "(deny network-bind)\n(allow network-bind network-inbound (local ip \"*:8080\"))\n"
+        ));
+        assert!(!profile.contains("(deny network*"));
+    }
+
+    #[test]
+    fn profile_denies_listed_executables() {
+        let mut policy = allow_all_network_policy();
+        policy.process.deny_exec("/usr/bin/curl");
+
+        let profile = create_sandbox_profile(&policy, false);
+        assert!(profile.ends_with("(deny process-exec* (literal \"/usr/bin/curl\"))\n"));
+    }
+
+    #[test]
+    fn profile_denies_opening_devices() {
+        let mut policy = allow_all_network_policy();
+        policy.file.denied_devices =
+            vec![DeniedDevice::Block, DeniedDevice::Node("/dev/disk*".into())];
+
+        let profile = create_sandbox_profile(&policy, false);
+        assert!(profile.contains(
+            "(deny file-read-data file-write-data file-ioctl (vnode-type BLOCK-DEVICE))\n"
+        ));
+        assert!(
+            profile.contains(
+                "(deny file-read-data file-write-data file-ioctl (prefix \"/dev/disk\"))\n"
+            )
+        );
+    }
+
+    #[test]
+    fn profile_denies_executing_and_mapping_execute_paths() {
+        let mut policy = allow_all_network_policy();
+        policy.file.deny_execute("/opt/untrusted");
+
+        let profile = create_sandbox_profile(&policy, false);
+        assert!(profile.contains("(deny file-map-executable (subpath \"/opt/untrusted\"))\n"));
+        assert!(profile.ends_with("(deny process-exec* (literal \"/opt/untrusted\"))\n"));
+    }
+
+    #[test]
+    fn profile_allow_list_denies_everything_else() {
+        let mut policy = allow_all_network_policy();
+        policy.process.allow_exec("/usr/bin/make");
+        policy.process.deny_exec("/usr/bin/ssh");
+
+        let profile = create_sandbox_profile(&policy, false);
+        let deny_all = profile.find("(deny process-exec*)\n").unwrap();
+        let allow_make = profile
+            .find("(allow process-exec* (literal \"/usr/bin/make\"))")
+            .unwrap();
+        let deny_ssh = profile
+            .find("(deny process-exec* (literal \"/usr/bin/ssh\"))")
+            .unwrap();
+        assert!(deny_all < allow_make);
+        assert!(allow_make < deny_ssh);
+    }
+
+    #[test]
+    fn profile_file_allow_list_denies_other_files() {
+        let mut policy = allow_all_network_policy();
+        policy.file.allow("/work/project");
+        policy.file.deny_read("/work/project/.env");
+
+        let profile = create_sandbox_profile(&policy, false);
+        let deny_all = profile.find("(deny file*)\n").unwrap();
+        let allow_project = profile
+            .find("(allow file* (subpath \"/work/project\"))")
+            .unwrap();
+        let allow_usr = profile
+            .find("(allow file-read* (subpath \"/usr\"))")
+            .unwrap();
+        let deny_env = profile
+            .find("(deny file-read* (subpath \"/work/project/.env\"))")
+            .unwrap();
+        assert!(deny_all < allow_project);
+        assert!(allow_project < allow_usr);
+        assert!(allow_usr < deny_env);
+    }
+
+    #[test]
+    fn exec_filter_uses_subpath_for_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let filter = exec_filter(dir.path());
+        assert_eq!(filter, format!("(subpath \"{}\")", dir.path().display()));
+    }
+
+    #[test]
+    fn audit_profile_reports_instead_of_denying() {
+        let mut policy = Policy::new();
+        policy.file.deny_read("/etc/passwd");
+        policy.process.deny_exec("/usr/bin/curl");
+
+        let profile = create_sandbox_profile(&policy, true);
+        assert!(profile.contains("(allow file-read* (with report) (subpath \"/etc/passwd\"))\n"));
+        assert!(profile.contains("(allow network* (with report))\n"));
+        assert!(
+            profile.contains("(allow process-exec* (with report) (literal \"/usr/bin/curl\"))\n")
+        );
+        assert!(!profile.contains("(deny file-read*"));
+        assert!(!profile.contains("(deny network*"));
+    }
+
+    #[test]
+    fn profile_covers_symlinked_and_canonical_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let mut policy = allow_all_network_policy();
+        policy.file.deny_write(link.join("out.txt"));
+
+        let profile = create_sandbox_profile(&policy, false);
+        let canonical = std::fs::canonicalize(&real).unwrap().join("out.txt");
+        assert!(profile.contains(&format!(
+            "(deny file-write* (subpath \"{}\"))",
+            canonical.display()
+        )));
+        assert!(profile.contains(&format!(
+            "(deny file-write* (subpath \"{}\"))",
+            link.join("out.txt").display()
+        )));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::policy::file::canonicalize_lenient;
+
+/// Firmlink table shipped with macOS; each line maps a root path to its location on the data volume
+const FIRMLINKS_FILE: &str = "/usr/share/firmlinks";
+
+/// Mount point of the data volume that firmlinks point into
+const DATA_VOLUME: &str = "/System/Volumes/Data";
+
+/// Maps user-supplied paths to the spellings the kernel uses when evaluating sandbox rules
+#[derive(Debug, Default)]
+pub struct SandboxPathResolver {
+    /// (root path, data volume path) pairs
+    firmlinks: Vec<(PathBuf, PathBuf)>,
+}
+
+impl SandboxPathResolver {
+    /// Create a resolver using the system firmlink table
+    pub fn load() -> Self {
+        let firmlinks = fs::read_to_string(FIRMLINKS_FILE)
+            .map(|content| parse_firmlinks(&content))
+            .unwrap_or_default();
+        Self { firmlinks }
+    }
+
+    /// Return every spelling of `path` that sandbox rules must cover
+    ///
+    /// The kernel evaluates sandbox rules against the canonical path, so a rule for
+    /// `/tmp/x` never matches because the kernel sees `/private/tmp/x`. The canonical
+    /// form comes first, followed by the path as written when it differs.
+    pub fn sandbox_paths(&self, path: &Path) -> Vec<PathBuf> {
+        let canonical = resolve_firmlink(&canonicalize_lenient(path), &self.firmlinks);
+
+        let mut paths = vec![canonical];
+        if !paths.contains(&path.to_path_buf()) {
+            paths.push(path.to_path_buf());
+        }
+        paths
+    }
+}
+
+/// Parse the firmlink table into (root path, data volume path) pairs
+fn parse_firmlinks(content: &str) -> Vec<(PathBuf, PathBuf)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (root, relative) = line.split_once('\t')?;
+            Some((PathBuf::from(root), Path::new(DATA_VOLUME).join(relative)))
+        })
+        .collect()
+}
+
+/// Map a path on the data volume back to the root path the kernel reports
+///
+/// e.g. `/System/Volumes/Data/Users/me` becomes `/Users/me`.
+fn resolve_firmlink(path: &Path, firmlinks: &[(PathBuf, PathBuf)]) -> PathBuf {
+    firmlinks
+        .iter()
+        .find_map(|(root, data)| {
+            path.strip_prefix(data).ok().map(|rest| {
+                if rest.as_os_str().is_empty() {
+                    root.clone()
+                } else {
+                    root.join(rest)
+                }
+            })
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn sandbox_paths_includes_original_spelling_for_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        symlink(&real, &link).unwrap();
+
+        let paths = SandboxPathResolver::default().sandbox_paths(&link);
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0], fs::canonicalize(&real).unwrap());
+        assert_eq!(paths[1], link);
+    }
+
+    #[test]
+    fn parse_firmlinks_maps_into_data_volume() {
+        let firmlinks = parse_firmlinks("/Users\tUsers\n/usr/local\tusr/local\n");
+        assert_eq!(
+            firmlinks,
+            vec![
+                (
+                    PathBuf::from("/Users"),
+                    PathBuf::from("/System/Volumes/Data/Users")
+                ),
+                (
+                    PathBuf::from("/usr/local"),
+                    PathBuf::from("/System/Volumes/Data/usr/local")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_firmlink_rewrites_data_volume_paths() {
+        let firmlinks = parse_firmlinks("/Users\tUsers\n");
+        assert_eq!(
+            resolve_firmlink(Path::new("/System/Volumes/Data/Users/me/.ssh"), &firmlinks),
+            PathBuf::from("/Users/me/.ssh")
+        );
+        assert_eq!(
+            resolve_firmlink(Path::new("/System/Volumes/Data/Users"), &firmlinks),
+            PathBuf::from("/Users")
+        );
+        assert_eq!(
+            resolve_firmlink(Path::new("/opt/homebrew"), &firmlinks),
+            PathBuf::from("/opt/homebrew")
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::HashSet,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+    task::JoinHandle,
+};
+
+use crate::{
+    audit::{Operation, ViolationEvent, ViolationSender},
+    error::MoriError,
+};
+
+/// Predicate selecting sandbox violation reports emitted by the kernel
+const LOG_PREDICATE: &str = r#"sender == "Sandbox" OR eventMessage CONTAINS "Sandbox: ""#;
+
+/// Upper bound on parent hops when checking process ancestry
+const MAX_ANCESTRY_DEPTH: usize = 64;
+
+/// Interval between scans of the sandboxed process tree for new descendants
+const TREE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Upper bound on the children listed per process in one scan
+const MAX_CHILDREN: usize = 1024;
+
+/// Watches the unified log for sandbox reports of the sandboxed process tree
+/// and forwards them as violation events
+pub struct SandboxLogWatcher {
+    child: Child,
+    handle: JoinHandle<()>,
+    tree: Arc<Mutex<ProcessTree>>,
+    poller: Option<JoinHandle<()>>,
+}
+
+impl SandboxLogWatcher {
+    /// Start `log stream` and wait until it is ready to deliver messages
+    pub async fn start(sender: ViolationSender) -> Result<Self, MoriError> {
+        let mut child = Command::new("log")
+            .args(["stream", "--style", "syslog", "--predicate", LOG_PREDICATE])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| MoriError::CommandSpawn {
+                command: "log".to_string(),
+                source,
+            })?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut lines = BufReader::new(stdout).lines();
+
+        // `log stream` prints a "Filtering the log data ..." header once the stream is live.
+        // Waiting for it ensures no report from the child is emitted before we listen.
+        lines.next_line().await?;
+
+        let tree = Arc::new(Mutex::new(ProcessTree::default()));
+        let task_tree = Arc::clone(&tree);
+        let handle = tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Some(event) = parse_sandbox_line(&line) else {
+                    continue;
+                };
+                if task_tree.lock().unwrap().contains(event.pid) {
+                    let _ = sender.send(event);
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            handle,
+            tree,
+            poller: None,
+        })
+    }
+
+    /// Only report events from `pid` and its descendants
+    ///
+    /// The descendants are recorded while the command runs, so reports that
+    /// arrive after a child exited are still attributed to the command.
+    pub fn watch_process_tree(&mut self, pid: u32) {
+        self.tree.lock().unwrap().root = Some(pid);
+        let tree = Arc::clone(&self.tree);
+        self.poller = Some(tokio::spawn(async move {
+            loop {
+                tree.lock().unwrap().record_descendants();
+                tokio::time::sleep(TREE_POLL_INTERVAL).await;
+            }
+        }));
+    }
+
+    /// Stop `log stream` and wait for buffered lines to be forwarded
+    pub async fn stop(mut self) {
+        if let Some(poller) = self.poller.take() {
+            poller.abort();
+        }
+        let _ = self.child.kill().await;
+        let _ = self.handle.await;
+    }
+}
+
+/// Membership test for the sandboxed process tree
+#[derive(Default)]
+struct ProcessTree {
+    root: Option<u32>,
+    /// Descendants of the root seen alive; kept after they exit
+    members: HashSet<u32>,
+}
+
+impl ProcessTree {
+    /// Add the running descendants of the root to the members
+    fn record_descendants(&mut self) {
+        let Some(root) = self.root else {
+            return;
+        };
+        let mut pending = vec![root];
+        while let Some(pid) = pending.pop() {
+            for child in child_pids(pid) {
+                if self.members.insert(child) {
+                    pending.push(child);
+                }
+            }
+        }
+    }
+
+    fn contains(&mut self, pid: u32) -> bool {
+        let Some(root) = self.root else {
+            return false;
+        };
+        if pid == root || self.members.contains(&pid) {
+            return true;
+        }
+
+        let mut current = pid;
+        for _ in 0..MAX_ANCESTRY_DEPTH {
+            match parent_pid(current) {
+                Some(ppid) if ppid == root || self.members.contains(&ppid) => {
+                    self.members.insert(pid);
+                    return true;
+                }
+                // Reached launchd without passing through the sandboxed tree
+                Some(0) | Some(1) => return false,
+                Some(ppid) => current = ppid,
+                // The process exited before a scan recorded it, so its ancestry
+                // is unknown and it may as well have been outside the tree
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+/// Look up the parent PID of a running process
+fn parent_pid(pid: u32) -> Option<u32> {
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    let ret = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut libc::proc_bsdinfo as *mut libc::c_void,
+            size,
+        )
+    };
+    if ret != size {
+        return None;
+    }
+    Some(info.pbi_ppid)
+}
+
+/// List the PIDs of the running children of a process
+fn child_pids(pid: u32) -> Vec<u32> {
+    let mut pids = vec![0 as libc::pid_t; MAX_CHILDREN];
+    let size = std::mem::size_of_val(pids.as_slice()) as libc::c_int;
+    let ret = unsafe {
+        libc::proc_listchildpids(
+            pid as libc::pid_t,
+            pids.as_mut_ptr() as *mut libc::c_void,
+            size,
+        )
+    };
+    if ret <= 0 {
+        return Vec::new();
+    }
+    pids.truncate((ret as usize).min(MAX_CHILDREN));
+    pids.into_iter()
+        .filter(|&pid| pid > 0)
+        .map(|pid| pid as u32)
+        .collect()
+}
+
+/// Parse a sandbox report such as
+/// `Sandbox: curl(1234) deny(1) network-outbound 192.0.2.1:443`
+fn parse_sandbox_line(line: &str) -> Option<ViolationEvent> {
+    let (_, report) = line.split_once("Sandbox: ")?;
+
+    // Command names may contain spaces and parentheses, so locate the "(<pid>) " group
+    let (comm, pid, rest) = report.match_indices('(').find_map(|(open, _)| {
+        let after = &report[open + 1..];
+        let close = after.find(')')?;
+        let pid = after[..close].parse::<u32>().ok()?;
+        let rest = after[close + 1..].strip_prefix(' ')?;
+        Some((&report[..open], pid, rest))
+    })?;
+
+    let mut parts = rest.splitn(3, ' ');
+    let _verdict = parts.next()?;
+    let operation = match parts.next()? {
+        op if op.starts_with("network") => Operation::Connect,
+        op if op.starts_with("file-read") => Operation::FileRead,
+        op if op.starts_with("file-write") => Operation::FileWrite,
+        op if op.starts_with("process-exec") => Operation::Exec,
+        _ => return None,
+    };
+    let target = parts.next().unwrap_or_default().trim().to_string();
+
+    Some(ViolationEvent {
+        operation,
+        target,
+        pid,
+        comm: comm.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::network(
+        "2025-01-01 10:00:00.000000+0900  localhost kernel[0]: (Sandbox) Sandbox: curl(1234) deny(1) network-outbound 192.0.2.1:443",
+        Operation::Connect,
+        "192.0.2.1:443",
+        1234,
+        "curl"
+    )]
+    #[case::file_read(
+        "kernel[0]: Sandbox: cat(42) allow file-read-data /etc/passwd",
+        Operation::FileRead,
+        "/etc/passwd",
+        42,
+        "cat"
+    )]
+    #[case::file_write_with_space(
+        "Sandbox: sh(7) deny(1) file-write-create /tmp/my dir/out.txt",
+        Operation::FileWrite,
+        "/tmp/my dir/out.txt",
+        7,
+        "sh"
+    )]
+    #[case::exec_with_parenthesized_comm(
+        "Sandbox: my (tool)(99) deny(2) process-exec* /usr/bin/curl",
+        Operation::Exec,
+        "/usr/bin/curl",
+        99,
+        "my (tool)"
+    )]
+    fn parse_sandbox_line_extracts_event(
+        #[case] line: &str,
+        #[case] operation: Operation,
+        #[case] target: &str,
+        #[case] pid: u32,
+        #[case] comm: &str,
+    ) {
+        let event = parse_sandbox_line(line).unwrap();
+        assert_eq!(event.operation, operation);
+        assert_eq!(event.target, target);
+        assert_eq!(event.pid, pid);
+        assert_eq!(event.comm, comm);
+    }
+
+    #[rstest]
+    #[case::not_a_sandbox_line("kernel[0]: something else")]
+    #[case::unsupported_operation("Sandbox: curl(1) deny(1) mach-lookup com.apple.foo")]
+    #[case::missing_pid("Sandbox: curl deny(1) file-read-data /etc/passwd")]
+    fn parse_sandbox_line_ignores_other_lines(#[case] line: &str) {
+        assert!(parse_sandbox_line(line).is_none());
+    }
+
+    #[test]
+    fn process_tree_ignores_events_before_root_is_known() {
+        let mut tree = ProcessTree::default();
+        assert!(!tree.contains(std::process::id()));
+    }
+
+    #[test]
+    fn process_tree_includes_root() {
+        let mut tree = ProcessTree {
+            root: Some(4242),
+            ..Default::default()
+        };
+        assert!(tree.contains(4242));
+    }
+
+    #[test]
+    fn process_tree_excludes_exited_processes() {
+        let mut tree = ProcessTree {
+            root: Some(4242),
+            ..Default::default()
+        };
+        // PIDs on macOS stay below 100000, so this one never exists
+        assert!(!tree.contains(i32::MAX as u32));
+    }
+
+    #[test]
+    fn process_tree_includes_recorded_members_after_they_exit() {
+        let mut tree = ProcessTree {
+            root: Some(4242),
+            members: HashSet::from([i32::MAX as u32]),
+        };
+        assert!(tree.contains(i32::MAX as u32));
+    }
+}
@@ -1,9 +1,172 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    audit::{AuditReport, FileAccess, FileRuleHits, FlowRecord},
+    net::cache::RefreshMargin,
+};
+
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
 pub use linux::execute_with_policy;
+#[cfg(target_os = "linux")]
+pub use linux::preflight::{Diagnostic, DiagnosticStatus, preflight};
 
 #[cfg(target_os = "macos")]
 mod macos;
 #[cfg(target_os = "macos")]
 pub use macos::execute_with_policy;
+
+#[cfg(target_os = "windows")]
+mod windows;
+#[cfg(target_os = "windows")]
+pub use windows::execute_with_policy;
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+mod passthrough;
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub use passthrough::execute_with_policy;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod output;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod process;
+
+/// Options controlling how the sandboxed command is run, independent of the policy itself
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Report violations instead of blocking them
+    pub audit: bool,
+    /// Run unrestricted with warnings instead of failing when the policy cannot be enforced
+    pub best_effort: bool,
+    /// WebAssembly module deciding on connections that match no allow rule
+    pub plugin: Option<PathBuf>,
+    /// Enforce the policy with the LD_PRELOAD shim when the kernel cannot run the eBPF programs
+    pub preload_fallback: bool,
+    /// Record every connection attempt of the command (see `RunOutcome::flows`)
+    pub flow_log: bool,
+    /// Record every file the command opens (see `RunOutcome::files`)
+    pub file_inventory: bool,
+    /// Count the opens each file deny rule allowed and denied (see
+    /// `RunOutcome::file_rules`)
+    pub file_rule_stats: bool,
+    /// How long before DNS records expire the allowed domains are resolved again
+    pub dns_refresh_margin: RefreshMargin,
+    /// File the DNS records of allowed and denied domains are reused from and saved to
+    pub dns_cache: Option<PathBuf>,
+    /// Look up the PTR names of denied destinations for the logs and the flow log
+    pub reverse_dns: bool,
+    /// Error denied connections fail with
+    pub deny_errno: DenyErrno,
+    /// Give the command an empty /tmp and /var/tmp of its own (Linux)
+    pub private_tmp: bool,
+    /// Stage the command's writes to the working directory in this directory (Linux)
+    pub overlay: Option<PathBuf>,
+    /// Kill the command, and every process it started, after this long (Linux)
+    pub timeout: Option<Duration>,
+    /// Run the command as this user instead of the one behind sudo (Linux)
+    pub user: Option<Credentials>,
+    /// Start the command in this directory (Linux)
+    pub chdir: Option<PathBuf>,
+    /// Pass the command only a minimal environment (Linux)
+    pub clean_env: bool,
+    /// Write the command's stdout to this file instead of mori's stdout (Linux, macOS)
+    pub stdout_log: Option<PathBuf>,
+    /// Write the command's stderr to this file instead of mori's stderr (Linux, macOS)
+    pub stderr_log: Option<PathBuf>,
+    /// Prefix each line of output the command writes to the terminal with
+    /// `[stdout] ` or `[stderr] ` (Linux, macOS)
+    pub prefix_output: bool,
+    /// How the policy is enforced (Linux)
+    pub backend: Backend,
+}
+
+/// How the policy is enforced on Linux
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// eBPF when running as root, the unprivileged backend otherwise
+    #[default]
+    Auto,
+    /// eBPF programs attached to a cgroup; needs root
+    Ebpf,
+    /// Landlock, seccomp and user namespaces; no root needed, but only part
+    /// of the policy can be enforced
+    Unprivileged,
+}
+
+/// User and groups the command runs as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups
+    pub groups: Vec<u32>,
+}
+
+/// Exit code of a command killed by `RunOptions::timeout`, as with timeout(1)
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Error a denied connect() fails with
+///
+/// Tools react very differently: most give up at once on EPERM, while
+/// ECONNREFUSED or ETIMEDOUT often make them retry or try the next address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DenyErrno {
+    /// Operation not permitted; fails fast and reads as a policy decision
+    #[default]
+    Eperm,
+    /// Connection refused, as if nothing listened at the destination
+    Econnrefused,
+    /// Network unreachable, as if there were no route to the destination
+    Enetunreach,
+    /// Connection timed out, as if the packets were dropped (without the wait)
+    Etimedout,
+}
+
+#[cfg(target_os = "linux")]
+impl DenyErrno {
+    /// The errno value
+    pub fn code(self) -> i32 {
+        match self {
+            DenyErrno::Eperm => libc::EPERM,
+            DenyErrno::Econnrefused => libc::ECONNREFUSED,
+            DenyErrno::Enetunreach => libc::ENETUNREACH,
+            DenyErrno::Etimedout => libc::ETIMEDOUT,
+        }
+    }
+}
+
+/// Result of running a sandboxed command
+#[derive(Debug)]
+pub struct RunOutcome {
+    /// Exit code of the command (128 + signal number if it was killed by a signal)
+    pub exit_code: i32,
+    /// Violations observed while running in audit mode
+    pub report: Option<AuditReport>,
+    /// Connection attempts, if `RunOptions::flow_log` was set
+    pub flows: Vec<FlowRecord>,
+    /// Files the command opened, if `RunOptions::file_inventory` was set
+    pub files: Vec<FileAccess>,
+    /// Opens per file deny rule, if `RunOptions::file_rule_stats` was set
+    pub file_rules: Vec<FileRuleHits>,
+}
+
+impl RunOutcome {
+    /// Outcome of a run without violation reporting
+    pub fn exited(exit_code: i32) -> Self {
+        Self {
+            exit_code,
+            report: None,
+            flows: Vec::new(),
+            files: Vec::new(),
+            file_rules: Vec::new(),
+        }
+    }
+
+    /// Check if any violation was observed
+    pub fn has_violations(&self) -> bool {
+        self.report
+            .as_ref()
+            .is_some_and(|report| !report.is_empty())
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, PipeReader, PipeWriter, Write},
+    path::Path,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::error::MoriError;
+
+use super::RunOptions;
+
+/// How long `OutputPumps::finish` waits for processes the command left
+/// behind to close its output
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// One of the command's output streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Where a captured stream goes
+enum Sink {
+    /// Written as is to a log file
+    File(File),
+    /// Passed on line by line to mori's own stream, prefixed with its name
+    Prefixed,
+}
+
+struct Pipe {
+    stream: Stream,
+    reader: PipeReader,
+    writer: PipeWriter,
+    sink: Sink,
+}
+
+/// Pipes the command's stdout and stderr are redirected to, for
+/// `--log-stdout`, `--log-stderr` and `--prefix-output`
+///
+/// Created before the command is spawned; `start` then copies the output to
+/// its destination in background threads until the last process holding
+/// the pipes exits.
+pub(crate) struct OutputCapture {
+    pipes: Vec<Pipe>,
+}
+
+impl OutputCapture {
+    /// Open the log files and create the pipes, or return None when the
+    /// output is not captured
+    pub(crate) fn new(options: &RunOptions) -> Result<Option<Self>, MoriError> {
+        let mut pipes = Vec::new();
+        for (stream, log) in [
+            (Stream::Stdout, &options.stdout_log),
+            (Stream::Stderr, &options.stderr_log),
+        ] {
+            let sink = match log {
+                Some(path) => Sink::File(create_log(path)?),
+                None if options.prefix_output => Sink::Prefixed,
+                None => continue,
+            };
+            let (reader, writer) = io::pipe()?;
+            pipes.push(Pipe {
+                stream,
+                reader,
+                writer,
+                sink,
+            });
+        }
+        Ok((!pipes.is_empty()).then_some(Self { pipes }))
+    }
+
+    /// Point the calling process's stdout and stderr at the pipes
+    ///
+    /// Called in the forked child before exec; the pipe descriptors
+    /// themselves are closed on exec.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn redirect(&self) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        for pipe in &self.pipes {
+            let target = match pipe.stream {
+                Stream::Stdout => libc::STDOUT_FILENO,
+                Stream::Stderr => libc::STDERR_FILENO,
+            };
+            if unsafe { libc::dup2(pipe.writer.as_raw_fd(), target) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Stdio for the command's stdout and stderr, None for a stream that is
+    /// not captured
+    #[cfg(target_os = "macos")]
+    pub(crate) fn stdio(
+        &self,
+    ) -> io::Result<(Option<std::process::Stdio>, Option<std::process::Stdio>)> {
+        let stdio = |stream: Stream| {
+            self.pipes
+                .iter()
+                .find(|pipe| pipe.stream == stream)
+                .map(|pipe| pipe.writer.try_clone().map(std::process::Stdio::from))
+                .transpose()
+        };
+        Ok((stdio(Stream::Stdout)?, stdio(Stream::Stderr)?))
+    }
+
+    /// Close mori's write ends and copy the output in background threads
+    ///
+    /// Called once the command is spawned.
+    pub(crate) fn start(self) -> OutputPumps {
+        let threads = self
+            .pipes
+            .into_iter()
+            .map(|pipe| {
+                drop(pipe.writer);
+                let (stream, reader, sink) = (pipe.stream, pipe.reader, pipe.sink);
+                std::thread::spawn(move || {
+                    if let Err(e) = pump(stream, reader, sink) {
+                        log::warn!("Failed to copy the command's {}: {}", stream.name(), e);
+                    }
+                })
+            })
+            .collect();
+        OutputPumps { threads }
+    }
+}
+
+/// Threads copying the command's output
+pub(crate) struct OutputPumps {
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl OutputPumps {
+    /// Wait until everything the command wrote has been copied
+    ///
+    /// The copies end once every process holding the pipes has exited.
+    /// Background processes still holding them after `DRAIN_TIMEOUT` are
+    /// not waited for; what they write later is lost when mori exits.
+    pub(crate) fn finish(self) {
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while !self.threads.iter().all(JoinHandle::is_finished) {
+            if Instant::now() >= deadline {
+                log::warn!("Processes left by the command still hold its output; not waiting");
+                return;
+            }
+            std::thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn create_log(path: &Path) -> Result<File, MoriError> {
+    File::create(path).map_err(|source| MoriError::OutputLog {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Copy `reader` to `sink` until the write ends are closed
+fn pump(stream: Stream, reader: PipeReader, sink: Sink) -> io::Result<()> {
+    match sink {
+        Sink::File(mut file) => {
+            io::copy(&mut BufReader::new(reader), &mut file)?;
+            file.flush()
+        }
+        Sink::Prefixed => {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(());
+                }
+                if !line.ends_with(b"\n") {
+                    line.push(b'\n');
+                }
+                match stream {
+                    Stream::Stdout => write_prefixed(&mut io::stdout().lock(), stream, &line)?,
+                    Stream::Stderr => write_prefixed(&mut io::stderr().lock(), stream, &line)?,
+                }
+            }
+        }
+    }
+}
+
+/// Write `line` prefixed with the stream name, for `--prefix-output`
+fn write_prefixed(out: &mut impl Write, stream: Stream, line: &[u8]) -> io::Result<()> {
+    write!(out, "[{}] ", stream.name())?;
+    out.write_all(line)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_captures_nothing_by_default() {
+        assert!(
+            OutputCapture::new(&RunOptions::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn start_copies_stdout_to_the_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stdout.log");
+        let options = RunOptions {
+            stdout_log: Some(path.clone()),
+            ..Default::default()
+        };
+        let capture = OutputCapture::new(&options).unwrap().unwrap();
+        let mut writer = capture.pipes[0].writer.try_clone().unwrap();
+
+        let pumps = capture.start();
+        writer.write_all(b"hello\nworld").unwrap();
+        drop(writer);
+        pumps.finish();
+
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "hello\nworld");
+    }
+
+    #[test]
+    fn write_prefixed_names_the_stream() {
+        let mut out = Vec::new();
+        write_prefixed(&mut out, Stream::Stderr, b"warning: unused\n").unwrap();
+        assert_eq!(out, b"[stderr] warning: unused\n");
+    }
+}
@@ -0,0 +1,47 @@
+use tokio::process::Command;
+
+use crate::{error::MoriError, policy::Policy};
+
+use super::{RunOptions, RunOutcome};
+
+/// Execute a command on a platform without a sandbox backend
+///
+/// Nothing can be enforced here, so a restrictive policy is rejected unless
+/// `--best-effort` is given, in which case the command runs unrestricted and
+/// every unenforced rule is logged.
+pub async fn execute_with_policy(
+    command: &str,
+    args: &[&str],
+    policy: &Policy,
+    options: &RunOptions,
+) -> Result<RunOutcome, MoriError> {
+    let rules = policy.restrictions();
+    if !rules.is_empty() {
+        if !options.best_effort {
+            return Err(MoriError::PolicyNotEnforceable { rules });
+        }
+        // Printed unconditionally rather than logged, so the warning is never filtered out
+        eprintln!(
+            "mori: warning: no sandbox backend for this platform; running '{}' WITHOUT restrictions",
+            command
+        );
+        for rule in &rules {
+            eprintln!("mori: warning: not enforced: {}", rule);
+        }
+    }
+
+    let mut cmd = Command::new(command);
+    cmd.args(args);
+    if let Some(vars) = policy.process.filtered_env() {
+        cmd.env_clear().envs(vars);
+    }
+    let status = cmd
+        .status()
+        .await
+        .map_err(|source| MoriError::CommandSpawn {
+            command: command.to_string(),
+            source,
+        })?;
+
+    Ok(RunOutcome::exited(status.code().unwrap_or(1)))
+}
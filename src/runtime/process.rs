@@ -0,0 +1,229 @@
+use std::{
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    task::JoinHandle,
+};
+
+use super::RunOutcome;
+
+/// Signals relayed from mori to the sandboxed command
+const FORWARDED_SIGNALS: [libc::c_int; 4] =
+    [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+/// Return the invoking user's uid and gid when mori runs under sudo
+///
+/// The sandboxed command is executed with these credentials, so only the
+/// sandbox setup itself runs with elevated privileges.
+pub(crate) fn sudo_credentials() -> Option<(u32, u32)> {
+    let uid = std::env::var("SUDO_UID").ok()?.parse::<u32>().ok()?;
+    let gid = std::env::var("SUDO_GID").ok()?.parse::<u32>().ok()?;
+    Some((uid, gid))
+}
+
+/// Convert a child exit status into the exit code mori should return
+///
+/// Follows the shell convention of 128 + signal number for processes killed by a signal.
+pub(crate) fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+/// Relays termination signals received by mori to the sandboxed command
+///
+/// Installed before the sandbox is set up: a signal arriving meanwhile is
+/// held instead of killing mori half-way, which would leave the cgroup and
+/// eBPF programs behind, and `interrupted` reports it. Once the command runs,
+/// signals are forwarded to it; a second one kills everything it started
+/// when `forward_to` was given a way to. The handlers also keep mori alive
+/// until the command exits, so cleanup and exit status propagation still
+/// happen.
+pub(crate) struct SignalRelay {
+    state: Arc<Mutex<RelayState>>,
+    task: JoinHandle<()>,
+}
+
+/// Callback killing the command and everything it started
+type KillAll = Box<dyn Fn() + Send + Sync>;
+
+#[derive(Default)]
+struct RelayState {
+    /// Signal received before the command started
+    pending: Option<libc::c_int>,
+    target: Option<RelayTarget>,
+}
+
+struct RelayTarget {
+    pid: u32,
+    kill_all: Option<KillAll>,
+    forwarded: bool,
+}
+
+/// What the relay did with a signal
+#[derive(Debug, PartialEq, Eq)]
+enum Relayed {
+    Held,
+    Forwarded(u32),
+    KilledAll,
+}
+
+impl RelayState {
+    fn receive(&mut self, signum: libc::c_int) -> Relayed {
+        match &mut self.target {
+            None => {
+                self.pending = Some(signum);
+                Relayed::Held
+            }
+            Some(RelayTarget {
+                kill_all: Some(kill_all),
+                forwarded: true,
+                ..
+            }) => {
+                kill_all();
+                Relayed::KilledAll
+            }
+            Some(target) => {
+                unsafe { libc::kill(target.pid as libc::pid_t, signum) };
+                target.forwarded = true;
+                Relayed::Forwarded(target.pid)
+            }
+        }
+    }
+}
+
+impl SignalRelay {
+    /// Install the handlers and start relaying
+    pub(crate) fn install() -> Self {
+        let mut streams = FORWARDED_SIGNALS
+            .iter()
+            .filter_map(|&signum| match signal(SignalKind::from_raw(signum)) {
+                Ok(stream) => Some((signum, stream)),
+                Err(e) => {
+                    log::warn!("Failed to install handler for signal {}: {}", signum, e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let state = Arc::new(Mutex::new(RelayState::default()));
+        let relay_state = Arc::clone(&state);
+        let task = tokio::spawn(async move {
+            loop {
+                let received = std::future::poll_fn(|cx| {
+                    for (signum, stream) in streams.iter_mut() {
+                        if let std::task::Poll::Ready(Some(())) = stream.poll_recv(cx) {
+                            return std::task::Poll::Ready(Some(*signum));
+                        }
+                    }
+                    if streams.is_empty() {
+                        std::task::Poll::Ready(None)
+                    } else {
+                        std::task::Poll::Pending
+                    }
+                })
+                .await;
+
+                let Some(signum) = received else {
+                    return;
+                };
+                match relay_state.lock().unwrap().receive(signum) {
+                    Relayed::Held => {
+                        log::info!("Received signal {} before the command started", signum)
+                    }
+                    Relayed::Forwarded(pid) => {
+                        log::info!("Forwarded signal {} to process {}", signum, pid)
+                    }
+                    Relayed::KilledAll => {
+                        log::info!("Received signal {} again; killed the command", signum)
+                    }
+                }
+            }
+        });
+        Self { state, task }
+    }
+
+    /// Outcome of a run stopped by a signal received before the command
+    /// started, if one was
+    ///
+    /// Returning it drops what was set up so far, as a normal exit does.
+    pub(crate) fn interrupted(&self) -> Option<RunOutcome> {
+        let signum = self.state.lock().unwrap().pending?;
+        eprintln!(
+            "mori: interrupted by signal {} before the command started",
+            signum
+        );
+        Some(RunOutcome::exited(128 + signum))
+    }
+
+    /// Forward signals to process `pid` from now on, and call `kill_all` on
+    /// a second signal
+    ///
+    /// A signal held since `interrupted` was last checked is forwarded at once.
+    pub(crate) fn forward_to(&self, pid: u32, kill_all: Option<KillAll>) {
+        let mut state = self.state.lock().unwrap();
+        state.target = Some(RelayTarget {
+            pid,
+            kill_all,
+            forwarded: false,
+        });
+        if let Some(signum) = state.pending.take() {
+            state.receive(signum);
+        }
+    }
+}
+
+impl Drop for SignalRelay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::success(0, 0)]
+    #[case::failure(3 << 8, 3)]
+    #[case::sigterm(libc::SIGTERM, 128 + libc::SIGTERM)]
+    #[case::sigkill(libc::SIGKILL, 128 + libc::SIGKILL)]
+    fn exit_code_follows_shell_convention(#[case] raw: i32, #[case] expected: i32) {
+        assert_eq!(exit_code(ExitStatus::from_raw(raw)), expected);
+    }
+
+    #[test]
+    fn relay_holds_signals_until_the_command_starts() {
+        let mut state = RelayState::default();
+        assert_eq!(state.receive(libc::SIGINT), Relayed::Held);
+        assert_eq!(state.pending, Some(libc::SIGINT));
+    }
+
+    #[test]
+    fn relay_kills_all_on_a_second_signal() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let killed = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&killed);
+        // Signal 0 only checks that the process exists
+        let mut state = RelayState {
+            pending: None,
+            target: Some(RelayTarget {
+                pid: std::process::id(),
+                kill_all: Some(Box::new(move || flag.store(true, Ordering::SeqCst))),
+                forwarded: false,
+            }),
+        };
+        assert_eq!(state.receive(0), Relayed::Forwarded(std::process::id()));
+        assert!(!killed.load(Ordering::SeqCst));
+        assert_eq!(state.receive(0), Relayed::KilledAll);
+        assert!(killed.load(Ordering::SeqCst));
+    }
+}
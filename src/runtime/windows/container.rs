@@ -0,0 +1,122 @@
+use std::ptr;
+
+use windows_sys::Win32::{
+    Foundation::ERROR_ALREADY_EXISTS,
+    Security::{
+        CreateWellKnownSid, FreeSid,
+        Isolation::{
+            CreateAppContainerProfile, DeleteAppContainerProfile,
+            DeriveAppContainerSidFromAppContainerName,
+        },
+        PSID, SECURITY_CAPABILITIES, SECURITY_MAX_SID_SIZE, SID_AND_ATTRIBUTES,
+        WELL_KNOWN_SID_TYPE, WinCapabilityInternetClientServerSid, WinCapabilityInternetClientSid,
+        WinCapabilityPrivateNetworkClientServerSid,
+    },
+};
+
+use crate::error::MoriError;
+
+use super::{last_error, wide};
+
+/// `SE_GROUP_ENABLED` attribute of a capability SID
+const SE_GROUP_ENABLED: u32 = 0x4;
+
+/// Capabilities granted to the container, so that the WFP filters decide
+/// which connections succeed rather than network isolation
+const CAPABILITIES: [WELL_KNOWN_SID_TYPE; 3] = [
+    WinCapabilityInternetClientSid,
+    WinCapabilityInternetClientServerSid,
+    WinCapabilityPrivateNetworkClientServerSid,
+];
+
+/// AppContainer profile the command runs in
+///
+/// Every process the command starts inherits the container's package SID,
+/// which the WFP filters match on, so the whole process tree is restricted
+/// whatever executables it runs. The profile is deleted when mori exits.
+pub struct AppContainer {
+    name: Vec<u16>,
+    sid: PSID,
+    capabilities: Vec<SID_AND_ATTRIBUTES>,
+    // Backing storage of the capability SIDs
+    _capability_sids: Vec<Box<[u8]>>,
+}
+
+impl AppContainer {
+    /// Create a profile named after mori's process ID
+    pub fn create() -> Result<Self, MoriError> {
+        let name = wide(format!("mori.{}", std::process::id()));
+        let mut sid: PSID = ptr::null_mut();
+        let mut code = unsafe {
+            CreateAppContainerProfile(
+                name.as_ptr(),
+                name.as_ptr(),
+                name.as_ptr(),
+                ptr::null(),
+                0,
+                &mut sid,
+            )
+        };
+        // Left behind by an earlier mori with the same process ID that was killed
+        if code == hresult_from_win32(ERROR_ALREADY_EXISTS) {
+            code = unsafe { DeriveAppContainerSidFromAppContainerName(name.as_ptr(), &mut sid) };
+        }
+        if code < 0 {
+            return Err(MoriError::WindowsApi {
+                operation: "CreateAppContainerProfile".to_string(),
+                code: code as u32,
+            });
+        }
+
+        let mut container = Self {
+            name,
+            sid,
+            capabilities: Vec::new(),
+            _capability_sids: Vec::new(),
+        };
+        for kind in CAPABILITIES {
+            let mut buffer = vec![0u8; SECURITY_MAX_SID_SIZE as usize].into_boxed_slice();
+            let mut size = SECURITY_MAX_SID_SIZE;
+            let ok = unsafe {
+                CreateWellKnownSid(kind, ptr::null_mut(), buffer.as_mut_ptr().cast(), &mut size)
+            };
+            if ok == 0 {
+                return Err(last_error("CreateWellKnownSid"));
+            }
+            container.capabilities.push(SID_AND_ATTRIBUTES {
+                Sid: buffer.as_mut_ptr().cast(),
+                Attributes: SE_GROUP_ENABLED,
+            });
+            container._capability_sids.push(buffer);
+        }
+        Ok(container)
+    }
+
+    /// Package SID of the container
+    pub fn sid(&self) -> PSID {
+        self.sid
+    }
+
+    /// Attribute value that starts a process inside the container
+    pub fn security_capabilities(&mut self) -> SECURITY_CAPABILITIES {
+        SECURITY_CAPABILITIES {
+            AppContainerSid: self.sid,
+            Capabilities: self.capabilities.as_mut_ptr(),
+            CapabilityCount: self.capabilities.len() as u32,
+            Reserved: 0,
+        }
+    }
+}
+
+impl Drop for AppContainer {
+    fn drop(&mut self) {
+        unsafe {
+            FreeSid(self.sid);
+            DeleteAppContainerProfile(self.name.as_ptr());
+        }
+    }
+}
+
+fn hresult_from_win32(code: u32) -> i32 {
+    (0x8007_0000 | (code & 0xffff)) as i32
+}
@@ -0,0 +1,63 @@
+use std::{os::windows::io::RawHandle, ptr};
+
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, HANDLE},
+    System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+        SetInformationJobObject,
+    },
+};
+
+use crate::error::MoriError;
+
+use super::last_error;
+
+/// Job object containing the sandboxed process tree
+///
+/// Processes created by a job member are members as well, and the whole tree is
+/// terminated when the job handle is closed.
+pub struct Job {
+    handle: HANDLE,
+}
+
+impl Job {
+    /// Create an anonymous job that kills its processes when mori exits
+    pub fn create() -> Result<Self, MoriError> {
+        let handle = unsafe { CreateJobObjectW(ptr::null(), ptr::null()) };
+        if handle.is_null() {
+            return Err(last_error("CreateJobObjectW"));
+        }
+        let job = Self { handle };
+
+        let mut info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let ok = unsafe {
+            SetInformationJobObject(
+                job.handle,
+                JobObjectExtendedLimitInformation,
+                &info as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const _,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        };
+        if ok == 0 {
+            return Err(last_error("SetInformationJobObject"));
+        }
+
+        Ok(job)
+    }
+
+    /// Add a process to the job
+    pub fn assign(&self, process: RawHandle) -> Result<(), MoriError> {
+        if unsafe { AssignProcessToJobObject(self.handle, process as HANDLE) } == 0 {
+            return Err(last_error("AssignProcessToJobObject"));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.handle) };
+    }
+}
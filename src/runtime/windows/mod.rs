@@ -0,0 +1,265 @@
+mod container;
+mod job;
+mod process;
+mod wfp;
+
+use std::{
+    ffi::OsStr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::windows::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::MoriError,
+    net::resolver::{DnsResolver, STRICT_RESOLVE_DEADLINE, SystemDnsResolver, resolve_strict},
+    policy::{AllowPolicy, NetworkPolicy, Policy},
+};
+
+use super::{RunOptions, RunOutcome};
+
+use container::AppContainer;
+use job::Job;
+use process::Process;
+use wfp::WfpSession;
+
+/// Execute a command in a job object with network access restricted by WFP filters
+///
+/// This backend is experimental: domains are resolved once at startup, and file
+/// and exec rules are not enforced yet, so they are rejected unless
+/// `--best-effort` is given.
+pub async fn execute_with_policy(
+    command: &str,
+    args: &[&str],
+    policy: &Policy,
+    options: &RunOptions,
+) -> Result<RunOutcome, MoriError> {
+    let rules = unenforced_rules(policy);
+    if !rules.is_empty() {
+        if !options.best_effort {
+            return Err(MoriError::PolicyNotEnforceable { rules });
+        }
+        for rule in &rules {
+            eprintln!("mori: warning: not enforced on Windows: {}", rule);
+        }
+    }
+    if policy.process.seccomp.is_some() {
+        log::warn!("Seccomp is not available on Windows; ignoring seccomp rules");
+    }
+    if policy.process.max_pids.is_some() {
+        log::warn!("Process limits are not enforced on Windows yet; ignoring max_pids");
+    }
+    if policy.process.deny_namespaces {
+        log::warn!("Windows has no namespaces; ignoring deny_namespaces");
+    }
+    if policy.inbound.restricted {
+        log::warn!(
+            "Inbound connection filtering is not enforced on Windows yet; ignoring inbound rules"
+        );
+    }
+    if policy
+        .network
+        .denied_families
+        .as_ref()
+        .is_some_and(|families| !families.is_empty())
+    {
+        log::warn!("Socket family restrictions are only enforced on Linux; ignoring deny_families");
+    }
+    if policy.network.allow_icmp.is_some() {
+        log::warn!("ICMP rules are only enforced on Linux; ignoring allow_icmp");
+    }
+    if !policy.network.unix.is_empty() {
+        log::warn!("Abstract unix sockets only exist on Linux; ignoring [network.unix]");
+    }
+    if policy.network.dns.enforce {
+        log::warn!("DNS enforcement is only supported on Linux; ignoring enforce");
+    }
+    let dns = &policy.network.dns;
+    if dns.refresh_interval.is_some() || dns.min_ttl.is_some() || dns.max_ttl.is_some() {
+        log::warn!("Domains are resolved once on Windows; ignoring the DNS refresh settings");
+    }
+    if policy.network.enforce_sni {
+        log::warn!("TLS server names are only checked on Linux; ignoring enforce_sni");
+    }
+    if !policy.network.denied.is_empty() {
+        log::warn!("Network deny rules are not enforced on Windows yet; ignoring them");
+    }
+    if policy.inbound.listen_restricted {
+        log::warn!("Listen port restrictions are not enforced on Windows yet; ignoring them");
+    }
+
+    let executable =
+        resolve_executable(command, std::env::var_os("PATH").as_deref()).ok_or_else(|| {
+            MoriError::CommandSpawn {
+                command: command.to_string(),
+                source: std::io::ErrorKind::NotFound.into(),
+            }
+        })?;
+
+    // WFP filters match the package SID of the AppContainer the command runs
+    // in, which every process it starts inherits; they must be in place before
+    // the child starts and stay alive until it exits
+    let (mut container, _wfp) = match &policy.network.policy {
+        AllowPolicy::All => (None, None),
+        AllowPolicy::Entries {
+            allowed_ipv4,
+            allowed_cidr,
+            allowed_ipv6,
+            allowed_cidr_v6,
+            allowed_domains,
+            allowed_domain_suffixes,
+            allowed_ports,
+        } => {
+            if !allowed_domain_suffixes.is_empty() {
+                log::warn!("Domain suffixes are only enforced on Linux; ignoring them");
+            }
+            if !allowed_ports.is_empty() {
+                log::warn!(
+                    "Destination ports are not enforced on Windows yet; allowing every port"
+                );
+            }
+            let resolver =
+                SystemDnsResolver::with_nameservers(policy.network.dns.nameservers.clone());
+            let resolved = if policy.network.dns.strict {
+                resolve_strict(&resolver, allowed_domains, STRICT_RESOLVE_DEADLINE).await?
+            } else {
+                resolver.resolve_domains(allowed_domains).await?
+            };
+            resolved.ensure_resolved()?;
+
+            let container = AppContainer::create()?;
+            let mut session = WfpSession::open(container.sid())?;
+            session.block_all()?;
+
+            // Allow localhost (127.0.0.1 and ::1) by default
+            let mut networks: Vec<(IpAddr, u8)> = Vec::new();
+            if !policy.network.deny_localhost {
+                networks.push((Ipv4Addr::LOCALHOST.into(), 32));
+                networks.push((Ipv6Addr::LOCALHOST.into(), 128));
+            }
+            networks.extend(
+                allowed_ipv4
+                    .iter()
+                    .chain(&resolved.dns_v4)
+                    .map(|&ip| (ip.into(), 32)),
+            );
+            networks.extend(
+                allowed_ipv6
+                    .iter()
+                    .chain(&resolved.dns_v6)
+                    .map(|&ip| (ip.into(), 128)),
+            );
+            networks.extend(allowed_cidr.iter().map(|&(ip, len)| (ip.into(), len)));
+            networks.extend(allowed_cidr_v6.iter().map(|&(ip, len)| (ip.into(), len)));
+            for record in resolved.domains.iter().flat_map(|d| &d.records) {
+                networks.push((record.ip, if record.ip.is_ipv4() { 32 } else { 128 }));
+            }
+            for (ip, prefix_len) in networks {
+                session.allow_network(ip, prefix_len)?;
+                log::info!("Added {}/{} to network allow list", ip, prefix_len);
+            }
+            (Some(container), Some(session))
+        }
+    };
+
+    let job = Job::create()?;
+
+    // Start suspended so the process is in the job before it can create children
+    let child = Process::spawn_suspended(
+        &executable,
+        command,
+        args,
+        policy.process.filtered_env(),
+        container.as_mut(),
+    )?;
+    let assigned = job.assign(child.raw_handle()).and_then(|_| child.resume());
+    if let Err(e) = assigned {
+        child.kill();
+        return Err(e);
+    }
+    log::info!("Spawned child process {} in job object", child.id());
+
+    let code = child.wait().await?;
+
+    Ok(RunOutcome::exited(code as i32))
+}
+
+/// Rules of `policy` this backend cannot enforce at all
+///
+/// Settings that only refine what is enforced (DNS refresh, ports, ...) are
+/// dropped with a warning instead.
+fn unenforced_rules(policy: &Policy) -> Vec<String> {
+    let mut rest = Policy::with_network(NetworkPolicy::from_allow_all(true));
+    rest.file = policy.file.clone();
+    rest.process.denied_exec = policy.process.denied_exec.clone();
+    rest.process.allowed_exec = policy.process.allowed_exec.clone();
+    rest.restrictions()
+}
+
+/// Encode `s` as a NUL-terminated UTF-16 string
+fn wide(s: impl AsRef<OsStr>) -> Vec<u16> {
+    s.as_ref().encode_wide().chain(std::iter::once(0)).collect()
+}
+
+fn last_error(operation: &str) -> MoriError {
+    MoriError::WindowsApi {
+        operation: operation.to_string(),
+        code: std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or_default() as u32,
+    }
+}
+
+/// Find the executable that `command` refers to, searching `path_var` like the shell does
+fn resolve_executable(command: &str, path_var: Option<&std::ffi::OsStr>) -> Option<PathBuf> {
+    let candidates = |base: PathBuf| {
+        let mut with_exe = base.clone().into_os_string();
+        with_exe.push(".exe");
+        [base, PathBuf::from(with_exe)]
+    };
+
+    let command_path = Path::new(command);
+    if command_path.components().count() > 1 || command_path.is_absolute() {
+        return candidates(command_path.to_path_buf())
+            .into_iter()
+            .find(|p| p.is_file())
+            .and_then(|p| std::path::absolute(p).ok());
+    }
+
+    std::env::split_paths(path_var?)
+        .flat_map(|dir| candidates(dir.join(command)))
+        .find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unenforced_rules_list_file_and_exec_rules() {
+        let mut policy = Policy::with_network(
+            NetworkPolicy::from_entries(&["example.com".to_string()]).unwrap(),
+        );
+        assert!(unenforced_rules(&policy).is_empty());
+
+        policy.file.deny_read(r"C:\secrets");
+        policy.process.deny_exec(r"C:\Windows\System32\curl.exe");
+        let rules = unenforced_rules(&policy);
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].starts_with("file: deny read "));
+        assert!(rules[1].starts_with("exec: deny "));
+    }
+
+    #[test]
+    fn resolve_executable_searches_path_with_exe_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("tool.exe"), b"").unwrap();
+        let path_var = std::env::join_paths([dir.path()]).unwrap();
+
+        assert_eq!(
+            resolve_executable("tool", Some(&path_var)),
+            Some(dir.path().join("tool.exe"))
+        );
+        assert_eq!(resolve_executable("missing", Some(&path_var)), None);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    ffi::OsString,
+    io,
+    iter::repeat_n,
+    os::windows::{ffi::OsStrExt, io::RawHandle},
+    path::Path,
+    ptr,
+};
+
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, HANDLE, HANDLE_FLAG_INHERIT, SetHandleInformation, WAIT_FAILED},
+    Security::SECURITY_CAPABILITIES,
+    System::{
+        Console::{GetStdHandle, STD_ERROR_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE},
+        Threading::{
+            CREATE_SUSPENDED, CREATE_UNICODE_ENVIRONMENT, CreateProcessW,
+            DeleteProcThreadAttributeList, EXTENDED_STARTUPINFO_PRESENT, GetExitCodeProcess,
+            INFINITE, InitializeProcThreadAttributeList, LPPROC_THREAD_ATTRIBUTE_LIST,
+            PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES, PROCESS_INFORMATION, ResumeThread,
+            STARTF_USESTDHANDLES, STARTUPINFOEXW, TerminateProcess, UpdateProcThreadAttribute,
+            WaitForSingleObject,
+        },
+    },
+};
+
+use crate::error::MoriError;
+
+use super::{container::AppContainer, last_error, wide};
+
+/// Process started suspended, so it can join the job before it runs
+pub struct Process {
+    process: HANDLE,
+    thread: HANDLE,
+    pid: u32,
+}
+
+impl Process {
+    /// Start `executable` suspended with mori's standard handles, inside
+    /// `container` when one is given
+    ///
+    /// `env` replaces the inherited environment when set.
+    pub fn spawn_suspended(
+        executable: &Path,
+        command: &str,
+        args: &[&str],
+        env: Option<Vec<(OsString, OsString)>>,
+        container: Option<&mut AppContainer>,
+    ) -> Result<Self, MoriError> {
+        let spawn_error = |source| MoriError::CommandSpawn {
+            command: command.to_string(),
+            source,
+        };
+
+        let application = wide(executable);
+        let mut command_line = command_line(command, args);
+        let environment = env.map(|vars| environment_block(&vars));
+
+        let mut startup = STARTUPINFOEXW::default();
+        startup.StartupInfo.cb = std::mem::size_of::<STARTUPINFOEXW>() as u32;
+        startup.StartupInfo.dwFlags = STARTF_USESTDHANDLES;
+        unsafe {
+            startup.StartupInfo.hStdInput = inheritable(GetStdHandle(STD_INPUT_HANDLE));
+            startup.StartupInfo.hStdOutput = inheritable(GetStdHandle(STD_OUTPUT_HANDLE));
+            startup.StartupInfo.hStdError = inheritable(GetStdHandle(STD_ERROR_HANDLE));
+        }
+
+        let mut flags = CREATE_SUSPENDED | CREATE_UNICODE_ENVIRONMENT;
+        // Kept alive until CreateProcessW has copied them
+        let mut attributes = Vec::new();
+        let capabilities: Option<SECURITY_CAPABILITIES> =
+            container.map(|container| container.security_capabilities());
+        if let Some(capabilities) = &capabilities {
+            let mut size = 0;
+            // Fails with the size the attribute list needs
+            unsafe { InitializeProcThreadAttributeList(ptr::null_mut(), 1, 0, &mut size) };
+            attributes = vec![0usize; size.div_ceil(std::mem::size_of::<usize>())];
+            let list = attributes.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST;
+            if unsafe { InitializeProcThreadAttributeList(list, 1, 0, &mut size) } == 0 {
+                return Err(last_error("InitializeProcThreadAttributeList"));
+            }
+            let ok = unsafe {
+                UpdateProcThreadAttribute(
+                    list,
+                    0,
+                    PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES as usize,
+                    (capabilities as *const SECURITY_CAPABILITIES).cast(),
+                    std::mem::size_of::<SECURITY_CAPABILITIES>(),
+                    ptr::null_mut(),
+                    ptr::null(),
+                )
+            };
+            if ok == 0 {
+                let error = last_error("UpdateProcThreadAttribute");
+                unsafe { DeleteProcThreadAttributeList(list) };
+                return Err(error);
+            }
+            startup.lpAttributeList = list;
+            flags |= EXTENDED_STARTUPINFO_PRESENT;
+        }
+
+        let mut info = PROCESS_INFORMATION::default();
+        let ok = unsafe {
+            CreateProcessW(
+                application.as_ptr(),
+                command_line.as_mut_ptr(),
+                ptr::null(),
+                ptr::null(),
+                1,
+                flags,
+                environment
+                    .as_ref()
+                    .map_or(ptr::null(), |block| block.as_ptr().cast()),
+                ptr::null(),
+                &startup.StartupInfo,
+                &mut info,
+            )
+        };
+        let spawned = if ok == 0 {
+            Err(spawn_error(io::Error::last_os_error()))
+        } else {
+            Ok(Self {
+                process: info.hProcess,
+                thread: info.hThread,
+                pid: info.dwProcessId,
+            })
+        };
+        if !attributes.is_empty() {
+            unsafe { DeleteProcThreadAttributeList(startup.lpAttributeList) };
+        }
+        spawned
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn raw_handle(&self) -> RawHandle {
+        self.process as RawHandle
+    }
+
+    /// Let the process run
+    pub fn resume(&self) -> Result<(), MoriError> {
+        if unsafe { ResumeThread(self.thread) } == u32::MAX {
+            return Err(last_error("ResumeThread"));
+        }
+        Ok(())
+    }
+
+    /// Terminate a process that could not be set up
+    pub fn kill(&self) {
+        unsafe { TerminateProcess(self.process, 1) };
+    }
+
+    /// Wait for the process to exit and return its exit code
+    pub async fn wait(&self) -> Result<u32, MoriError> {
+        // Handles are plain integers to the kernel; the blocking thread only
+        // borrows this one while `self` keeps it open
+        let process = self.process as usize;
+        tokio::task::spawn_blocking(move || {
+            let process = process as HANDLE;
+            if unsafe { WaitForSingleObject(process, INFINITE) } == WAIT_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let mut code = 0;
+            if unsafe { GetExitCodeProcess(process, &mut code) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(code)
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|result| result)
+        .map_err(|source| MoriError::CommandWait { source })
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.thread);
+            CloseHandle(self.process);
+        }
+    }
+}
+
+/// Mark a standard handle inheritable so the child can use it
+fn inheritable(handle: HANDLE) -> HANDLE {
+    if !handle.is_null() {
+        unsafe { SetHandleInformation(handle, HANDLE_FLAG_INHERIT, HANDLE_FLAG_INHERIT) };
+    }
+    handle
+}
+
+/// Build a NUL-terminated command line, quoting each argument the way the
+/// C runtime's argument parser expects
+fn command_line(command: &str, args: &[&str]) -> Vec<u16> {
+    let mut line = String::new();
+    for (i, arg) in std::iter::once(&command).chain(args).enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        quote_arg(arg, &mut line);
+    }
+    wide(line)
+}
+
+fn quote_arg(arg: &str, line: &mut String) {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\u{b}', '"']) {
+        line.push_str(arg);
+        return;
+    }
+    line.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quote are escaped, and so is the quote
+                line.extend(repeat_n('\\', backslashes * 2 + 1));
+                line.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                line.extend(repeat_n('\\', backslashes));
+                line.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    // Backslashes before the closing quote are escaped
+    line.extend(repeat_n('\\', backslashes * 2));
+    line.push('"');
+}
+
+/// Build a `NAME=value` environment block terminated by an empty entry
+fn environment_block(vars: &[(OsString, OsString)]) -> Vec<u16> {
+    let mut block = Vec::new();
+    for (name, value) in vars {
+        block.extend(name.encode_wide());
+        block.push(u16::from(b'='));
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    if block.is_empty() {
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::ffi::OsStr;
+
+    #[rstest]
+    #[case(&["tool"], "tool")]
+    #[case(&["tool", "a b", ""], r#"tool "a b" """#)]
+    #[case(&["tool", r#"say "hi""#], r#"tool "say \"hi\"""#)]
+    #[case(&["tool", r"C:\dir with space\"], r#"tool "C:\dir with space\\""#)]
+    #[case(&["tool", r"C:\plain\"], r"tool C:\plain\")]
+    fn command_line_quotes_arguments(#[case] argv: &[&str], #[case] expected: &str) {
+        let line = command_line(argv[0], &argv[1..]);
+        assert_eq!(line, wide(expected));
+    }
+
+    #[test]
+    fn environment_block_ends_with_empty_entry() {
+        let vars = [(OsString::from("A"), OsString::from("1"))];
+        assert_eq!(
+            environment_block(&vars),
+            OsStr::new("A=1\0\0").encode_wide().collect::<Vec<_>>()
+        );
+        assert_eq!(environment_block(&[]), vec![0, 0]);
+    }
+}
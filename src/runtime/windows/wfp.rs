@@ -0,0 +1,199 @@
+use std::{net::IpAddr, ptr};
+
+use windows_sys::{
+    Win32::{
+        Foundation::{ERROR_SUCCESS, HANDLE},
+        NetworkManagement::WindowsFilteringPlatform::{
+            FWP_ACTION_BLOCK, FWP_ACTION_PERMIT, FWP_ACTION_TYPE, FWP_MATCH_EQUAL, FWP_SID,
+            FWP_UINT8, FWP_V4_ADDR_AND_MASK, FWP_V4_ADDR_MASK, FWP_V6_ADDR_AND_MASK,
+            FWP_V6_ADDR_MASK, FWPM_CONDITION_ALE_PACKAGE_ID, FWPM_CONDITION_IP_REMOTE_ADDRESS,
+            FWPM_FILTER_CONDITION0, FWPM_FILTER0, FWPM_LAYER_ALE_AUTH_CONNECT_V4,
+            FWPM_LAYER_ALE_AUTH_CONNECT_V6, FWPM_SESSION_FLAG_DYNAMIC, FWPM_SESSION0,
+            FwpmEngineClose0, FwpmEngineOpen0, FwpmFilterAdd0,
+        },
+        Security::PSID,
+        System::Rpc::RPC_C_AUTHN_WINNT,
+    },
+    core::GUID,
+};
+
+use crate::error::MoriError;
+
+/// Filter weight for the catch-all block filter
+const BLOCK_WEIGHT: u8 = 0;
+/// Filter weight for permit filters, evaluated before the block filter
+const PERMIT_WEIGHT: u8 = 15;
+
+/// Windows Filtering Platform session holding mori's IPv4 and IPv6 connect filters
+///
+/// The session is dynamic, so every filter added through it is removed by the
+/// system as soon as the engine handle is closed, even if mori crashes.
+pub struct WfpSession {
+    engine: HANDLE,
+    package_sid: PSID,
+}
+
+impl WfpSession {
+    /// Open a dynamic session whose filters apply to connections made by the
+    /// processes of the AppContainer `package_sid`
+    ///
+    /// The SID must outlive the session.
+    pub fn open(package_sid: PSID) -> Result<Self, MoriError> {
+        let session = FWPM_SESSION0 {
+            flags: FWPM_SESSION_FLAG_DYNAMIC,
+            ..Default::default()
+        };
+        let mut engine: HANDLE = ptr::null_mut();
+        let code = unsafe {
+            FwpmEngineOpen0(
+                ptr::null(),
+                RPC_C_AUTHN_WINNT,
+                ptr::null(),
+                &session,
+                &mut engine,
+            )
+        };
+        check("FwpmEngineOpen0", code)?;
+
+        Ok(Self {
+            engine,
+            package_sid,
+        })
+    }
+
+    /// Block every outbound connection of the container, IPv4 or IPv6, not
+    /// permitted by another filter
+    pub fn block_all(&mut self) -> Result<(), MoriError> {
+        for layer in [
+            FWPM_LAYER_ALE_AUTH_CONNECT_V4,
+            FWPM_LAYER_ALE_AUTH_CONNECT_V6,
+        ] {
+            self.add_filter(layer, FWP_ACTION_BLOCK, BLOCK_WEIGHT, None)?;
+        }
+        Ok(())
+    }
+
+    /// Permit outbound connections of the container to the given network
+    ///
+    /// IPv4-mapped IPv6 destinations are classified at the IPv4 layer, so
+    /// they are covered by the IPv4 filters.
+    pub fn allow_network(&mut self, addr: IpAddr, prefix_len: u8) -> Result<(), MoriError> {
+        let (layer, mut remote) = match addr {
+            IpAddr::V4(addr) => (
+                FWPM_LAYER_ALE_AUTH_CONNECT_V4,
+                RemoteAddress::V4(FWP_V4_ADDR_AND_MASK {
+                    addr: u32::from(addr),
+                    mask: prefix_mask(prefix_len),
+                }),
+            ),
+            IpAddr::V6(addr) => (
+                FWPM_LAYER_ALE_AUTH_CONNECT_V6,
+                RemoteAddress::V6(FWP_V6_ADDR_AND_MASK {
+                    addr: addr.octets(),
+                    prefixLength: prefix_len.min(128),
+                }),
+            ),
+        };
+        self.add_filter(layer, FWP_ACTION_PERMIT, PERMIT_WEIGHT, Some(&mut remote))
+    }
+
+    fn add_filter(
+        &mut self,
+        layer: GUID,
+        action: FWP_ACTION_TYPE,
+        weight: u8,
+        remote: Option<&mut RemoteAddress>,
+    ) -> Result<(), MoriError> {
+        let mut conditions = vec![FWPM_FILTER_CONDITION0 {
+            fieldKey: FWPM_CONDITION_ALE_PACKAGE_ID,
+            matchType: FWP_MATCH_EQUAL,
+            ..Default::default()
+        }];
+        conditions[0].conditionValue.r#type = FWP_SID;
+        conditions[0].conditionValue.Anonymous.sid = self.package_sid.cast();
+
+        if let Some(remote) = remote {
+            let mut condition = FWPM_FILTER_CONDITION0 {
+                fieldKey: FWPM_CONDITION_IP_REMOTE_ADDRESS,
+                matchType: FWP_MATCH_EQUAL,
+                ..Default::default()
+            };
+            match remote {
+                RemoteAddress::V4(addr_mask) => {
+                    condition.conditionValue.r#type = FWP_V4_ADDR_MASK;
+                    condition.conditionValue.Anonymous.v4AddrMask = addr_mask;
+                }
+                RemoteAddress::V6(addr_mask) => {
+                    condition.conditionValue.r#type = FWP_V6_ADDR_MASK;
+                    condition.conditionValue.Anonymous.v6AddrMask = addr_mask;
+                }
+            }
+            conditions.push(condition);
+        }
+
+        let mut filter = FWPM_FILTER0 {
+            layerKey: layer,
+            numFilterConditions: conditions.len() as u32,
+            filterCondition: conditions.as_mut_ptr(),
+            ..Default::default()
+        };
+        filter.displayData.name = DISPLAY_NAME.as_ptr() as *mut u16;
+        filter.action.r#type = action;
+        filter.weight.r#type = FWP_UINT8;
+        filter.weight.Anonymous.uint8 = weight;
+
+        let code =
+            unsafe { FwpmFilterAdd0(self.engine, &filter, ptr::null_mut(), ptr::null_mut()) };
+        check("FwpmFilterAdd0", code)
+    }
+}
+
+impl Drop for WfpSession {
+    fn drop(&mut self) {
+        // Closing a dynamic session removes all of its filters
+        unsafe { FwpmEngineClose0(self.engine) };
+    }
+}
+
+/// Remote network a permit filter matches, in the layout of its layer
+enum RemoteAddress {
+    V4(FWP_V4_ADDR_AND_MASK),
+    V6(FWP_V6_ADDR_AND_MASK),
+}
+
+/// Filter name shown by `netsh wfp show filters` (UTF-16, NUL terminated)
+static DISPLAY_NAME: [u16; 5] = [b'm' as u16, b'o' as u16, b'r' as u16, b'i' as u16, 0];
+
+/// Convert a CIDR prefix length into a host-order IPv4 netmask
+fn prefix_mask(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        len => u32::MAX << (32 - u32::from(len.min(32))),
+    }
+}
+
+fn check(operation: &str, code: u32) -> Result<(), MoriError> {
+    if code == ERROR_SUCCESS {
+        Ok(())
+    } else {
+        Err(MoriError::WindowsApi {
+            operation: operation.to_string(),
+            code,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0, 0x0000_0000)]
+    #[case(8, 0xff00_0000)]
+    #[case(24, 0xffff_ff00)]
+    #[case(32, 0xffff_ffff)]
+    fn prefix_mask_converts_prefix_length(#[case] prefix_len: u8, #[case] expected: u32) {
+        assert_eq!(prefix_mask(prefix_len), expected);
+    }
+}
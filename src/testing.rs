@@ -0,0 +1,422 @@
+//! In-memory sandbox for downstream integration tests
+//!
+//! [`TestSandbox`] evaluates a [`Policy`] the way the enforcement backends do,
+//! without root, eBPF or sandbox-exec. Code under test reports the accesses it
+//! is about to make (typically through a trait the test swaps for the sandbox),
+//! and the test asserts on the verdicts and the recorded violations:
+//!
+//! ```
+//! use std::net::SocketAddrV4;
+//!
+//! use mori::{policy::Policy, testing::TestSandbox};
+//!
+//! let sandbox = TestSandbox::new(Policy::new());
+//! let addr: SocketAddrV4 = "203.0.113.1:443".parse().unwrap();
+//! assert!(sandbox.connect(addr).is_err());
+//! sandbox.assert_no_network_violations().unwrap_err();
+//! ```
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    sync::Mutex,
+};
+
+use crate::{
+    audit::{AuditReport, Operation, ViolationEvent},
+    policy::{AccessMode, AllowPolicy, Policy},
+};
+
+/// Command name recorded for accesses made through the sandbox
+const TEST_COMM: &str = "test";
+
+/// Fake enforcement layer applying a policy to accesses reported by tests
+///
+/// Connections are checked against the allow list (IPv4 addresses, CIDR
+/// ranges, and allowed domains and domain suffixes resolved through hosts
+/// registered with [`TestSandbox::with_host`]) after the deny list; 127.0.0.1 is reachable
+/// unless denied or `deny_localhost` is set, as with the eBPF backend. A denied path also denies everything below it,
+/// paths outside a `[file] allow` list are denied, and exec
+/// rules follow [`ProcessPolicy`](crate::policy::ProcessPolicy). In audit mode
+/// every access succeeds but violations are still recorded.
+#[derive(Debug)]
+pub struct TestSandbox {
+    policy: Policy,
+    audit: bool,
+    hosts: HashMap<String, Vec<Ipv4Addr>>,
+    violations: Mutex<Vec<ViolationEvent>>,
+}
+
+impl TestSandbox {
+    /// Create a sandbox enforcing `policy`
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            audit: false,
+            hosts: HashMap::new(),
+            violations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record violations without denying the accesses
+    pub fn audit(mut self) -> Self {
+        self.audit = true;
+        self
+    }
+
+    /// Register the addresses `host` resolves to
+    pub fn with_host(mut self, host: &str, addrs: impl IntoIterator<Item = Ipv4Addr>) -> Self {
+        self.hosts
+            .entry(normalize_host(host))
+            .or_default()
+            .extend(addrs);
+        self
+    }
+
+    /// The policy being enforced
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Check a connection to `addr`
+    pub fn connect(&self, addr: SocketAddrV4) -> Result<(), ViolationEvent> {
+        let allowed = self.allows_ip(*addr.ip());
+        self.verdict(allowed, Operation::Connect, addr.to_string())
+    }
+
+    /// Resolve `host` with the registered hosts and check a connection to each address
+    ///
+    /// Unknown hosts resolve to nothing, so the call succeeds without connecting.
+    pub fn connect_host(&self, host: &str, port: u16) -> Result<(), ViolationEvent> {
+        let addrs = self
+            .hosts
+            .get(&normalize_host(host))
+            .cloned()
+            .unwrap_or_default();
+        for addr in addrs {
+            self.connect(SocketAddrV4::new(addr, port))?;
+        }
+        Ok(())
+    }
+
+    /// Check opening `path` with `access`
+    pub fn open(&self, path: impl AsRef<Path>, access: AccessMode) -> Result<(), ViolationEvent> {
+        let path = path.as_ref();
+        let denied = self
+            .policy
+            .file
+            .denied_paths
+            .iter()
+            .any(|(denied, mode)| path.starts_with(denied) && overlaps(*mode, access))
+            || !self.policy.file.allows(path, access);
+        let operation = match access {
+            AccessMode::Read => Operation::FileRead,
+            AccessMode::Write | AccessMode::ReadWrite => Operation::FileWrite,
+            AccessMode::Execute => Operation::Exec,
+        };
+        self.verdict(!denied, operation, path.display().to_string())
+    }
+
+    /// Check executing `program`
+    pub fn exec(&self, program: impl AsRef<Path>) -> Result<(), ViolationEvent> {
+        let program = program.as_ref();
+        let process = &self.policy.process;
+        let denied =
+            process
+                .denied_exec
+                .iter()
+                .any(|denied| program.starts_with(denied))
+                || self.policy.file.denied_paths.iter().any(|(denied, mode)| {
+                    *mode == AccessMode::Execute && program.starts_with(denied)
+                });
+        let allowed = process.allowed_exec.is_empty()
+            || process
+                .allowed_exec
+                .iter()
+                .any(|allowed| program.starts_with(allowed));
+        self.verdict(
+            !denied && allowed,
+            Operation::Exec,
+            program.display().to_string(),
+        )
+    }
+
+    /// Violations recorded so far, in the order they happened
+    pub fn violations(&self) -> Vec<ViolationEvent> {
+        self.violations.lock().unwrap().clone()
+    }
+
+    /// Aggregated report of the recorded violations
+    pub fn report(&self) -> AuditReport {
+        let mut report = AuditReport::default();
+        for event in self.violations.lock().unwrap().iter() {
+            report.record(event);
+        }
+        report
+    }
+
+    /// Fail with the report if any violation was recorded
+    pub fn assert_no_violations(&self) -> Result<(), AuditReport> {
+        let report = self.report();
+        if report.is_empty() {
+            Ok(())
+        } else {
+            Err(report)
+        }
+    }
+
+    /// Fail with the report if a connection was denied
+    pub fn assert_no_network_violations(&self) -> Result<(), AuditReport> {
+        let mut report = AuditReport::default();
+        for event in self.violations.lock().unwrap().iter() {
+            if event.operation == Operation::Connect {
+                report.record(event);
+            }
+        }
+        if report.is_empty() {
+            Ok(())
+        } else {
+            Err(report)
+        }
+    }
+
+    fn allows_ip(&self, ip: Ipv4Addr) -> bool {
+        let denied = &self.policy.network.denied;
+        if denied
+            .denied_cidr
+            .iter()
+            .any(|&(network, prefix_len)| in_network(ip, network, prefix_len))
+            || denied.denied_domains.iter().any(|domain| {
+                self.hosts
+                    .get(&normalize_host(domain))
+                    .is_some_and(|addrs| addrs.contains(&ip))
+            })
+        {
+            return false;
+        }
+        let AllowPolicy::Entries {
+            allowed_ipv4,
+            allowed_cidr,
+            allowed_domains,
+            allowed_domain_suffixes,
+            ..
+        } = &self.policy.network.policy
+        else {
+            return true;
+        };
+        (ip == Ipv4Addr::LOCALHOST && !self.policy.network.deny_localhost)
+            || allowed_ipv4.contains(&ip)
+            || allowed_cidr
+                .iter()
+                .any(|&(network, prefix_len)| in_network(ip, network, prefix_len))
+            || allowed_domains.iter().any(|domain| {
+                self.hosts
+                    .get(&normalize_host(domain))
+                    .is_some_and(|addrs| addrs.contains(&ip))
+            })
+            || allowed_domain_suffixes.iter().any(|suffix| {
+                self.hosts.iter().any(|(host, addrs)| {
+                    (host.ends_with(suffix.as_str())
+                        || suffix.strip_prefix('.') == Some(host.as_str()))
+                        && addrs.contains(&ip)
+                })
+            })
+    }
+
+    fn verdict(
+        &self,
+        allowed: bool,
+        operation: Operation,
+        target: String,
+    ) -> Result<(), ViolationEvent> {
+        if allowed {
+            return Ok(());
+        }
+        let event = ViolationEvent {
+            operation,
+            target,
+            pid: std::process::id(),
+            comm: TEST_COMM.to_string(),
+        };
+        self.violations.lock().unwrap().push(event.clone());
+        if self.audit { Ok(()) } else { Err(event) }
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn overlaps(denied: AccessMode, access: AccessMode) -> bool {
+    (denied as u8) & (access as u8) != 0
+}
+
+fn in_network(addr: Ipv4Addr, network: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = u32::MAX
+        .checked_shl(32 - u32::from(prefix_len.min(32)))
+        .unwrap_or(0);
+    addr.to_bits() & mask == network.to_bits() & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{DenyList, NetworkPolicy};
+
+    fn policy() -> Policy {
+        let mut policy = Policy::with_network(
+            NetworkPolicy::from_entries(&["example.com".to_string(), "10.0.0.0/8".to_string()])
+                .unwrap(),
+        );
+        policy.file.deny_read("/home/user/.ssh");
+        policy.process.deny_exec("/usr/bin/curl");
+        policy
+    }
+
+    #[test]
+    fn deny_localhost_drops_implicit_loopback_allow() {
+        let mut policy = policy();
+        policy.network.deny_localhost = true;
+        let sandbox = TestSandbox::new(policy);
+        assert!(sandbox.connect("127.0.0.1:8080".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn connect_follows_allow_list() {
+        let sandbox =
+            TestSandbox::new(policy()).with_host("Example.com.", [Ipv4Addr::new(192, 0, 2, 1)]);
+
+        assert!(sandbox.connect("192.0.2.1:443".parse().unwrap()).is_ok());
+        assert!(sandbox.connect("10.1.2.3:80".parse().unwrap()).is_ok());
+        assert!(sandbox.connect("127.0.0.1:8080".parse().unwrap()).is_ok());
+        assert!(sandbox.connect_host("example.com", 443).is_ok());
+        assert!(sandbox.assert_no_violations().is_ok());
+
+        let event = sandbox
+            .connect("198.51.100.1:443".parse().unwrap())
+            .unwrap_err();
+        assert_eq!(event.target, "198.51.100.1:443");
+        assert_eq!(
+            sandbox.assert_no_network_violations().unwrap_err().total(),
+            1
+        );
+    }
+
+    #[test]
+    fn allow_all_network_allows_every_connection() {
+        let sandbox = TestSandbox::new(Policy::with_network(NetworkPolicy::from_allow_all(true)));
+        assert!(sandbox.connect("203.0.113.1:443".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn domain_suffix_allows_every_host_under_it() {
+        let network = NetworkPolicy::from_entries(&[".corp.internal".to_string()]).unwrap();
+        let sandbox = TestSandbox::new(Policy::with_network(network))
+            .with_host("git.corp.internal", [Ipv4Addr::new(10, 0, 0, 1)])
+            .with_host("corp.internal", [Ipv4Addr::new(10, 0, 0, 2)])
+            .with_host("notcorp.internal", [Ipv4Addr::new(10, 0, 0, 3)]);
+
+        assert!(sandbox.connect_host("git.corp.internal", 443).is_ok());
+        assert!(sandbox.connect_host("corp.internal", 443).is_ok());
+        assert!(sandbox.connect_host("notcorp.internal", 443).is_err());
+    }
+
+    #[test]
+    fn deny_list_overrides_allow_all() {
+        let mut network = NetworkPolicy::from_allow_all(true);
+        network.denied =
+            DenyList::from_entries(&["10.0.0.0/8".to_string(), "metadata.internal".to_string()])
+                .unwrap();
+        let sandbox = TestSandbox::new(Policy::with_network(network))
+            .with_host("metadata.internal", [Ipv4Addr::new(169, 254, 169, 254)]);
+
+        assert!(sandbox.connect("203.0.113.1:443".parse().unwrap()).is_ok());
+        assert!(sandbox.connect("10.1.2.3:80".parse().unwrap()).is_err());
+        assert!(sandbox.connect_host("metadata.internal", 80).is_err());
+    }
+
+    #[test]
+    fn open_follows_file_allow_list() {
+        let mut policy = policy();
+        policy.file.allow("/work");
+        let sandbox = TestSandbox::new(policy);
+
+        assert!(
+            sandbox
+                .open("/work/Cargo.toml", AccessMode::ReadWrite)
+                .is_ok()
+        );
+        assert!(sandbox.open("/usr/lib/libc.so.6", AccessMode::Read).is_ok());
+        assert!(
+            sandbox
+                .open("/home/user/.bashrc", AccessMode::Read)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn open_and_exec_follow_deny_rules() {
+        let sandbox = TestSandbox::new(policy());
+
+        assert!(
+            sandbox
+                .open("/home/user/.ssh/id_ed25519", AccessMode::Read)
+                .is_err()
+        );
+        assert!(
+            sandbox
+                .open("/home/user/.ssh/known_hosts", AccessMode::Write)
+                .is_ok()
+        );
+        assert!(
+            sandbox
+                .open("/home/user/.bashrc", AccessMode::ReadWrite)
+                .is_ok()
+        );
+        assert!(sandbox.exec("/usr/bin/curl").is_err());
+        assert!(sandbox.exec("/usr/bin/git").is_ok());
+
+        let operations: Vec<Operation> = sandbox
+            .violations()
+            .iter()
+            .map(|event| event.operation)
+            .collect();
+        assert_eq!(operations, vec![Operation::FileRead, Operation::Exec]);
+        assert!(sandbox.assert_no_network_violations().is_ok());
+    }
+
+    #[test]
+    fn exec_follows_file_execute_rules() {
+        let mut policy = Policy::new();
+        policy.file.deny_execute("/tmp");
+        let sandbox = TestSandbox::new(policy);
+
+        assert!(sandbox.exec("/tmp/build/a.out").is_err());
+        assert!(
+            sandbox
+                .open("/tmp/build/a.out", AccessMode::ReadWrite)
+                .is_ok()
+        );
+        assert!(sandbox.exec("/usr/bin/cc").is_ok());
+    }
+
+    #[test]
+    fn exec_allow_list_denies_unlisted_programs() {
+        let mut policy = Policy::new();
+        policy.process.allow_exec("/usr/bin/make");
+        let sandbox = TestSandbox::new(policy);
+
+        assert!(sandbox.exec("/usr/bin/make").is_ok());
+        assert!(sandbox.exec("/usr/bin/cc").is_err());
+    }
+
+    #[test]
+    fn audit_mode_records_without_denying() {
+        let sandbox = TestSandbox::new(policy()).audit();
+
+        assert!(sandbox.connect("198.51.100.1:443".parse().unwrap()).is_ok());
+        assert!(sandbox.exec("/usr/bin/curl").is_ok());
+        assert_eq!(sandbox.report().total(), 2);
+    }
+}
@@ -202,5 +202,104 @@ if ! echo "$output" | grep -q "test"; then
 fi
 echo "  [8-1] PASS"
 
+# Test 9: Write-protected files cannot be replaced, moved or deleted (Linux path hooks)
+if [[ "$(uname)" == "Linux" ]]; then
+    echo "[Test 9] Write-protected files cannot be replaced, moved or deleted"
+    PROTECTED_FILE="$TEMP_DIR/protected.txt"
+    PROTECTED_DIR="$TEMP_DIR/protected_dir"
+    echo "protected" > "$PROTECTED_FILE"
+    mkdir -p "$PROTECTED_DIR/subdir"
+    echo "inside" > "$PROTECTED_DIR/inside.txt"
+
+    echo "  [9-1] Testing: moving a file onto a write-denied file should fail"
+    if $BIN --deny-file-write "$PROTECTED_FILE" -- \
+        sh -c "echo evil > $TEMP_DIR/evil.txt && mv $TEMP_DIR/evil.txt $PROTECTED_FILE" > /dev/null 2>&1; then
+        echo "FAIL [9-1]: Replacing a write-denied file by rename should fail"
+        exit 1
+    fi
+    if ! grep -q "^protected$" "$PROTECTED_FILE"; then
+        echo "FAIL [9-1]: Content should remain unchanged, Got: $(cat $PROTECTED_FILE)"
+        exit 1
+    fi
+    echo "  [9-1] PASS"
+
+    echo "  [9-2] Testing: moving a file out of a write-denied directory should fail"
+    if $BIN --deny-file-write "$PROTECTED_DIR" -- \
+        mv "$PROTECTED_DIR/inside.txt" "$TEMP_DIR/outside.txt" > /dev/null 2>&1; then
+        echo "FAIL [9-2]: Moving a file out of a write-denied directory should fail"
+        exit 1
+    fi
+    echo "  [9-2] PASS"
+
+    echo "  [9-3] Testing: deleting a write-denied file should fail"
+    if $BIN --deny-file-write "$PROTECTED_FILE" -- rm -f "$PROTECTED_FILE" > /dev/null 2>&1 \
+        || [[ ! -f "$PROTECTED_FILE" ]]; then
+        echo "FAIL [9-3]: Deleting a write-denied file should fail"
+        exit 1
+    fi
+    echo "  [9-3] PASS"
+
+    echo "  [9-4] Testing: removing a directory in a write-denied directory should fail"
+    if $BIN --deny-file-write "$PROTECTED_DIR" -- rmdir "$PROTECTED_DIR/subdir" > /dev/null 2>&1 \
+        || [[ ! -d "$PROTECTED_DIR/subdir" ]]; then
+        echo "FAIL [9-4]: Removing a directory in a write-denied directory should fail"
+        exit 1
+    fi
+    echo "  [9-4] PASS"
+
+    echo "[Test 10] hide_denied leaves read-denied files out of directory listings"
+    HIDDEN_DIR="$TEMP_DIR/listing"
+    mkdir -p "$HIDDEN_DIR"
+    echo "secret" > "$HIDDEN_DIR/secret.txt"
+    echo "visible" > "$HIDDEN_DIR/visible.txt"
+    HIDE_CONFIG="$TEMP_DIR/hide.toml"
+    cat > "$HIDE_CONFIG" <<EOF
+[file]
+deny_read = ["$HIDDEN_DIR/secret.txt"]
+hide_denied = true
+EOF
+
+    echo "  [10-1] Testing: ls should not list a hidden file"
+    output=$($BIN --config "$HIDE_CONFIG" -- ls "$HIDDEN_DIR" 2>&1)
+    if echo "$output" | grep -q "secret.txt" || ! echo "$output" | grep -q "visible.txt"; then
+        echo "FAIL [10-1]: Only visible.txt should be listed"
+        echo "  Output: $output"
+        exit 1
+    fi
+    echo "  [10-1] PASS"
+
+    echo "  [10-2] Testing: stat of a hidden file should report it missing"
+    output=$($BIN --config "$HIDE_CONFIG" -- stat "$HIDDEN_DIR/secret.txt" 2>&1 || true)
+    if ! echo "$output" | grep -q "No such file or directory"; then
+        echo "FAIL [10-2]: stat should fail with ENOENT"
+        echo "  Output: $output"
+        exit 1
+    fi
+    echo "  [10-2] PASS"
+
+    # inode_setattr has no idmap argument before Linux 6.9, so on older
+    # kernels (Ubuntu 22.04's, for one) this covers mori_inode_setattr_legacy
+    echo "[Test 11] Write-protected files keep their mode and size (kernel $(uname -r))"
+    SETATTR_FILE="$TEMP_DIR/setattr.txt"
+    echo "protected" > "$SETATTR_FILE"
+    chmod 644 "$SETATTR_FILE"
+
+    echo "  [11-1] Testing: chmod of a write-denied file should fail"
+    if $BIN --deny-file-write "$SETATTR_FILE" -- chmod 777 "$SETATTR_FILE" > /dev/null 2>&1 \
+        || [[ "$(stat -c %a "$SETATTR_FILE")" != "644" ]]; then
+        echo "FAIL [11-1]: chmod of a write-denied file should fail"
+        exit 1
+    fi
+    echo "  [11-1] PASS"
+
+    echo "  [11-2] Testing: truncate of a write-denied file should fail"
+    if $BIN --deny-file-write "$SETATTR_FILE" -- truncate -s 0 "$SETATTR_FILE" > /dev/null 2>&1 \
+        || ! grep -q "^protected$" "$SETATTR_FILE"; then
+        echo "FAIL [11-2]: truncate of a write-denied file should fail"
+        exit 1
+    fi
+    echo "  [11-2] PASS"
+fi
+
 echo ""
 echo "All file access control tests passed!"